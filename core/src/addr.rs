@@ -0,0 +1,181 @@
+//! Target addresses as SOCKS5 encodes them.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use core::str::FromStr;
+
+use crate::constants::atyp;
+use crate::error::WireError;
+use crate::message::Decoded;
+
+/// Represents a target address in SOCKS5 protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetAddr {
+    /// IPv4 address and port
+    Ipv4(Ipv4Addr, u16),
+    /// IPv6 address and port
+    Ipv6(Ipv6Addr, u16),
+    /// Domain name and port
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    /// Returns the target port
+    pub fn port(&self) -> u16 {
+        match self {
+            TargetAddr::Ipv4(_, port) | TargetAddr::Ipv6(_, port) | TargetAddr::Domain(_, port) => *port,
+        }
+    }
+
+    /// Returns the target's IP address, unless it is a domain name
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            TargetAddr::Ipv4(ip, _) => Some(IpAddr::V4(*ip)),
+            TargetAddr::Ipv6(ip, _) => Some(IpAddr::V6(*ip)),
+            TargetAddr::Domain(..) => None,
+        }
+    }
+
+    /// Appends the wire encoding (ATYP, DST.ADDR, DST.PORT) of the address
+    ///
+    /// # Arguments
+    /// * `buf` - The buffer to append to
+    ///
+    /// # Returns
+    /// - Ok(()) if the address was encoded
+    /// - Err(WireError) if the domain name is too long to encode
+    pub fn write_to(&self, buf: &mut Vec<u8>) -> Result<(), WireError> {
+        match self {
+            TargetAddr::Ipv4(addr, port) => {
+                buf.push(atyp::IPV4);
+                buf.extend_from_slice(&addr.octets());
+                buf.extend_from_slice(&port.to_be_bytes());
+            }
+            TargetAddr::Ipv6(addr, port) => {
+                buf.push(atyp::IPV6);
+                buf.extend_from_slice(&addr.octets());
+                buf.extend_from_slice(&port.to_be_bytes());
+            }
+            TargetAddr::Domain(domain, port) => {
+                let len = u8::try_from(domain.len())
+                    .map_err(|_| WireError::Address(format!("Domain name too long: {}", domain)))?;
+                buf.push(atyp::DOMAIN);
+                buf.push(len);
+                buf.extend_from_slice(domain.as_bytes());
+                buf.extend_from_slice(&port.to_be_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes an address (ATYP, DST.ADDR, DST.PORT) from the start of `buf`
+    ///
+    /// # Returns
+    /// - Ok(Decoded::Complete) with the address and the bytes it took
+    /// - Ok(Decoded::Incomplete) with how many more bytes are needed at least
+    /// - Err(WireError) for unknown address types and names that are not UTF-8
+    pub fn decode(buf: &[u8]) -> Result<Decoded<TargetAddr>, WireError> {
+        let Some(&address_type) = buf.first() else {
+            return Ok(Decoded::Incomplete(1));
+        };
+        let (start, len) = match address_type {
+            atyp::IPV4 => (1, 4),
+            atyp::IPV6 => (1, 16),
+            atyp::DOMAIN => match buf.get(1) {
+                Some(&len) => (2, usize::from(len)),
+                None => return Ok(Decoded::Incomplete(1)),
+            },
+            other => return Err(WireError::AddressType(other)),
+        };
+        let total = start + len + 2;
+        if buf.len() < total {
+            return Ok(Decoded::Incomplete(total - buf.len()));
+        }
+        let addr = &buf[start..start + len];
+        let port = u16::from_be_bytes([buf[total - 2], buf[total - 1]]);
+        let target = match address_type {
+            atyp::IPV4 => TargetAddr::Ipv4(Ipv4Addr::from(<[u8; 4]>::try_from(addr).expect("4 bytes")), port),
+            atyp::IPV6 => TargetAddr::Ipv6(Ipv6Addr::from(<[u8; 16]>::try_from(addr).expect("16 bytes")), port),
+            _ => {
+                let domain = core::str::from_utf8(addr)
+                    .map_err(|e| WireError::Address(format!("Invalid domain name: {}", e)))?;
+                TargetAddr::Domain(domain.to_string(), port)
+            }
+        };
+        Ok(Decoded::Complete(target, total))
+    }
+}
+
+impl From<SocketAddr> for TargetAddr {
+    fn from(addr: SocketAddr) -> Self {
+        match addr.ip() {
+            IpAddr::V4(ip) => TargetAddr::Ipv4(ip, addr.port()),
+            IpAddr::V6(ip) => TargetAddr::Ipv6(ip, addr.port()),
+        }
+    }
+}
+
+impl From<(IpAddr, u16)> for TargetAddr {
+    fn from((ip, port): (IpAddr, u16)) -> Self {
+        TargetAddr::from(SocketAddr::new(ip, port))
+    }
+}
+
+impl From<(&str, u16)> for TargetAddr {
+    /// Treats IP literals as addresses and anything else as a domain name
+    fn from((host, port): (&str, u16)) -> Self {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => TargetAddr::from((ip, port)),
+            Err(_) => TargetAddr::Domain(host.to_string(), port),
+        }
+    }
+}
+
+impl From<(String, u16)> for TargetAddr {
+    fn from((host, port): (String, u16)) -> Self {
+        TargetAddr::from((host.as_str(), port))
+    }
+}
+
+impl fmt::Display for TargetAddr {
+    /// Formats the target address as `host:port`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetAddr::Ipv4(addr, port) => write!(f, "{}:{}", addr, port),
+            TargetAddr::Ipv6(addr, port) => write!(f, "[{}]:{}", addr, port),
+            TargetAddr::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
+
+impl FromStr for TargetAddr {
+    type Err = WireError;
+
+    /// Parses a `host:port` string, treating IPv4 literals and bracketed
+    /// IPv6 literals (`[::1]:443`) as addresses
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| WireError::Address(format!("Missing port in address: {}", s)))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| WireError::Address(format!("Invalid port in address: {}", s)))?;
+        if host.is_empty() {
+            return Err(WireError::Address(format!("Missing host in address: {}", s)));
+        }
+
+        if let Some(ipv6) = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')) {
+            return ipv6
+                .parse::<Ipv6Addr>()
+                .map(|ip| TargetAddr::Ipv6(ip, port))
+                .map_err(|_| WireError::Address(format!("Invalid IPv6 address: {}", s)));
+        }
+        match host.parse::<Ipv4Addr>() {
+            Ok(ip) => Ok(TargetAddr::Ipv4(ip, port)),
+            Err(_) => Ok(TargetAddr::Domain(host.to_string(), port)),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! The client side of a CONNECT exchange, without I/O.
+//!
+//! A [`ClientHandshake`] says what to do next: send some bytes, read an
+//! exact number of bytes and hand them back, or stop because the proxy
+//! connected or refused. Driving it over blocking sockets takes a loop:
+//!
+//! ```
+//! use rsocks5_core::client::{ClientHandshake, ClientStep};
+//! use rsocks5_core::{TargetAddr, WireError};
+//!
+//! fn connect(
+//!     mut stream: impl std::io::Read + std::io::Write,
+//!     target: TargetAddr,
+//! ) -> Result<TargetAddr, Box<dyn std::error::Error>> {
+//!     let mut handshake = ClientHandshake::new(target);
+//!     loop {
+//!         match handshake.poll()? {
+//!             ClientStep::Send(bytes) => stream.write_all(&bytes)?,
+//!             ClientStep::Receive(len) => {
+//!                 let mut buf = vec![0; len];
+//!                 stream.read_exact(&mut buf)?;
+//!                 handshake.receive(&buf);
+//!             }
+//!             ClientStep::Connected(bound) => return Ok(bound),
+//!             ClientStep::Refused(code) => return Err(format!("refused with {:#04x}", code).into()),
+//!         }
+//!     }
+//! }
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::addr::TargetAddr;
+use crate::constants::{auth, cmd};
+use crate::error::WireError;
+use crate::message::{Decoded, Greeting, MethodSelection, PasswordRequest, PasswordStatus, Reply, Request};
+
+/// What a client has to do next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientStep {
+    /// Write these bytes to the proxy
+    Send(Vec<u8>),
+    /// Read exactly this many bytes and pass them to [`ClientHandshake::receive`]
+    Receive(usize),
+    /// The proxy connected from this address; the stream now carries the tunnel
+    Connected(TargetAddr),
+    /// The proxy refused the request with this reply code
+    Refused(u8),
+}
+
+/// Where a handshake stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    /// The greeting is still to be sent
+    Start,
+    /// Waiting for the proxy to select a method
+    Selection,
+    /// Waiting for the verdict on the credentials
+    Authentication,
+    /// Waiting for the reply to the request
+    Reply,
+    /// The exchange is over
+    Done(ClientStep),
+}
+
+/// A client's CONNECT exchange with a SOCKS5 proxy
+#[derive(Debug, Clone)]
+pub struct ClientHandshake {
+    /// Where the proxy should connect to
+    target: TargetAddr,
+    /// The username and password to offer, if any
+    credentials: Option<(String, String)>,
+    /// Further methods to offer, e.g. private extensions
+    extra_methods: Vec<u8>,
+    /// Where the exchange stands
+    state: State,
+    /// Bytes of the message being received
+    received: Vec<u8>,
+}
+
+impl ClientHandshake {
+    /// Starts an exchange connecting to `target` without authentication
+    pub fn new(target: TargetAddr) -> Self {
+        Self {
+            target,
+            credentials: None,
+            extra_methods: Vec::new(),
+            state: State::Start,
+            received: Vec::new(),
+        }
+    }
+
+    /// Offers username/password authentication with these credentials
+    pub fn with_credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Offers a further method, which the proxy is not expected to select
+    pub fn with_method(mut self, method: u8) -> Self {
+        self.extra_methods.push(method);
+        self
+    }
+
+    /// Returns the methods the greeting offers
+    pub fn methods(&self) -> Vec<u8> {
+        let mut methods = Vec::from([auth::NO_AUTH]);
+        if self.credentials.is_some() {
+            methods.push(auth::USER_PASS);
+        }
+        methods.extend_from_slice(&self.extra_methods);
+        methods
+    }
+
+    /// Takes in bytes read as asked for by [`ClientStep::Receive`]
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.received.extend_from_slice(bytes);
+    }
+
+    /// Returns what to do next
+    ///
+    /// # Returns
+    /// - Ok(ClientStep) with the next step; once the exchange is over, its outcome
+    /// - Err(WireError) if the proxy broke the protocol or rejected the credentials
+    pub fn poll(&mut self) -> Result<ClientStep, WireError> {
+        match &self.state {
+            State::Start => {
+                self.state = State::Selection;
+                Ok(ClientStep::Send(Greeting { methods: self.methods() }.encode()))
+            }
+            State::Selection => {
+                let selection = match MethodSelection::decode(&self.received)? {
+                    Decoded::Complete(selection, _) => selection,
+                    Decoded::Incomplete(more) => return Ok(ClientStep::Receive(more)),
+                };
+                self.received.clear();
+                match (selection.method, &self.credentials) {
+                    (auth::NO_AUTH, _) => self.send_request(),
+                    (auth::USER_PASS, Some((username, password))) => {
+                        let request = PasswordRequest {
+                            username: username.as_bytes().to_vec(),
+                            password: password.as_bytes().to_vec(),
+                        };
+                        self.state = State::Authentication;
+                        Ok(ClientStep::Send(request.encode()?))
+                    }
+                    (method, _) => Err(WireError::Method(method)),
+                }
+            }
+            State::Authentication => {
+                let status = match PasswordStatus::decode(&self.received)? {
+                    Decoded::Complete(status, _) => status,
+                    Decoded::Incomplete(more) => return Ok(ClientStep::Receive(more)),
+                };
+                self.received.clear();
+                if !status.is_success() {
+                    return Err(WireError::AuthRejected);
+                }
+                self.send_request()
+            }
+            State::Reply => {
+                let reply = match Reply::decode(&self.received)? {
+                    Decoded::Complete(reply, _) => reply,
+                    Decoded::Incomplete(more) => return Ok(ClientStep::Receive(more)),
+                };
+                self.received.clear();
+                let outcome = if reply.code == crate::constants::reply::SUCCEEDED {
+                    ClientStep::Connected(reply.bound)
+                } else {
+                    ClientStep::Refused(reply.code)
+                };
+                self.state = State::Done(outcome.clone());
+                Ok(outcome)
+            }
+            State::Done(outcome) => Ok(outcome.clone()),
+        }
+    }
+
+    /// Moves on to sending the CONNECT request
+    fn send_request(&mut self) -> Result<ClientStep, WireError> {
+        let request = Request { command: cmd::CONNECT, target: self.target.clone() };
+        self.state = State::Reply;
+        Ok(ClientStep::Send(request.encode()?))
+    }
+}
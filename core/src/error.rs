@@ -0,0 +1,39 @@
+//! Errors in SOCKS5 messages.
+
+use alloc::string::String;
+use core::fmt;
+
+/// A message that breaks the SOCKS5 wire format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// A message carried a SOCKS version other than 5
+    Version(u8),
+    /// A username/password message carried a version other than 1
+    SubnegotiationVersion(u8),
+    /// The server selected a method the client cannot use
+    Method(u8),
+    /// The server rejected the username and password
+    AuthRejected,
+    /// A username or password does not fit its length byte
+    CredentialsTooLong,
+    /// An address carried an unknown address type
+    AddressType(u8),
+    /// An address could not be parsed or encoded
+    Address(String),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Version(version) => write!(f, "Unsupported SOCKS version: {}", version),
+            WireError::SubnegotiationVersion(version) => write!(f, "Unsupported subnegotiation version: {}", version),
+            WireError::Method(method) => write!(f, "Server selected unacceptable authentication method: {:#04x}", method),
+            WireError::AuthRejected => f.write_str("Server rejected username/password"),
+            WireError::CredentialsTooLong => f.write_str("Username or password longer than 255 bytes"),
+            WireError::AddressType(atyp) => write!(f, "Unknown address type: {}", atyp),
+            WireError::Address(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl core::error::Error for WireError {}
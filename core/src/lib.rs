@@ -0,0 +1,27 @@
+//! # rsocks5-core
+//!
+//! The SOCKS5 wire format of [RFC 1928](https://datatracker.ietf.org/doc/html/rfc1928)
+//! and [RFC 1929](https://datatracker.ietf.org/doc/html/rfc1929), without I/O.
+//!
+//! Messages encode into and decode from byte buffers, and a
+//! [`ClientHandshake`](client::ClientHandshake) walks a client through the
+//! CONNECT exchange one step at a time, leaving the reading and writing to
+//! its caller. Nothing here depends on an async runtime or on `std`, so the
+//! same wire logic that the `rsocks5` server is built on can run in
+//! embedded clients, with blocking sockets, or on any other runtime.
+//!
+//! The crate is `no_std` and needs `alloc`.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod addr;
+pub mod client;
+pub mod constants;
+pub mod error;
+pub mod message;
+
+pub use addr::TargetAddr;
+pub use error::WireError;
+pub use message::Decoded;
@@ -0,0 +1,225 @@
+//! SOCKS5 messages and their encodings.
+//!
+//! Every message encodes into a `Vec<u8>` and decodes from the start of a
+//! byte buffer. Decoding a partial message is not an error: it reports how
+//! many more bytes are needed at least, so callers can read exactly that
+//! many and never consume data past the message.
+
+use alloc::vec::Vec;
+
+use crate::addr::TargetAddr;
+use crate::constants::{RESERVED, SOCKS_VERSION, SUBNEGOTIATION_VERSION};
+use crate::error::WireError;
+
+/// The outcome of decoding a message from a buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded<T> {
+    /// The message and how many bytes of the buffer it took
+    Complete(T, usize),
+    /// The buffer ends early; at least this many more bytes are needed
+    Incomplete(usize),
+}
+
+/// Returns `Incomplete` from the enclosing decoder unless `buf` holds `len` bytes
+macro_rules! need {
+    ($buf:expr, $len:expr) => {
+        if $buf.len() < $len {
+            return Ok(Decoded::Incomplete($len - $buf.len()));
+        }
+    };
+}
+
+/// Checks the version byte leading a message
+fn check_version(version: u8) -> Result<(), WireError> {
+    if version == SOCKS_VERSION {
+        Ok(())
+    } else {
+        Err(WireError::Version(version))
+    }
+}
+
+/// The client's greeting, offering authentication methods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Greeting {
+    /// The offered methods, at most 255
+    pub methods: Vec<u8>,
+}
+
+impl Greeting {
+    /// Encodes the greeting: VER, NMETHODS, METHODS
+    pub fn encode(&self) -> Vec<u8> {
+        let methods = &self.methods[..self.methods.len().min(255)];
+        let mut buf = Vec::with_capacity(2 + methods.len());
+        buf.extend_from_slice(&[SOCKS_VERSION, methods.len() as u8]);
+        buf.extend_from_slice(methods);
+        buf
+    }
+
+    /// Decodes a greeting from the start of `buf`
+    pub fn decode(buf: &[u8]) -> Result<Decoded<Self>, WireError> {
+        need!(buf, 2);
+        check_version(buf[0])?;
+        let len = 2 + usize::from(buf[1]);
+        need!(buf, len);
+        Ok(Decoded::Complete(Greeting { methods: buf[2..len].to_vec() }, len))
+    }
+}
+
+/// The server's choice of authentication method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodSelection {
+    /// The selected method, or `NO_ACCEPTABLE_METHODS`
+    pub method: u8,
+}
+
+impl MethodSelection {
+    /// Encodes the selection: VER, METHOD
+    pub fn encode(&self) -> Vec<u8> {
+        Vec::from([SOCKS_VERSION, self.method])
+    }
+
+    /// Decodes a selection from the start of `buf`
+    pub fn decode(buf: &[u8]) -> Result<Decoded<Self>, WireError> {
+        need!(buf, 2);
+        check_version(buf[0])?;
+        Ok(Decoded::Complete(MethodSelection { method: buf[1] }, 2))
+    }
+}
+
+/// The client's username and password (RFC 1929)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordRequest {
+    /// The username, at most 255 bytes
+    pub username: Vec<u8>,
+    /// The password, at most 255 bytes
+    pub password: Vec<u8>,
+}
+
+impl PasswordRequest {
+    /// Encodes the request: VER, ULEN, UNAME, PLEN, PASSWD
+    ///
+    /// # Returns
+    /// - Ok(Vec<u8>) with the encoded request
+    /// - Err(WireError) if the username or password is longer than 255 bytes
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        let ulen = u8::try_from(self.username.len()).map_err(|_| WireError::CredentialsTooLong)?;
+        let plen = u8::try_from(self.password.len()).map_err(|_| WireError::CredentialsTooLong)?;
+        let mut buf = Vec::with_capacity(3 + self.username.len() + self.password.len());
+        buf.extend_from_slice(&[SUBNEGOTIATION_VERSION, ulen]);
+        buf.extend_from_slice(&self.username);
+        buf.push(plen);
+        buf.extend_from_slice(&self.password);
+        Ok(buf)
+    }
+
+    /// Decodes a request from the start of `buf`
+    pub fn decode(buf: &[u8]) -> Result<Decoded<Self>, WireError> {
+        need!(buf, 2);
+        if buf[0] != SUBNEGOTIATION_VERSION {
+            return Err(WireError::SubnegotiationVersion(buf[0]));
+        }
+        let username_end = 2 + usize::from(buf[1]);
+        need!(buf, username_end + 1);
+        let len = username_end + 1 + usize::from(buf[username_end]);
+        need!(buf, len);
+        let request = PasswordRequest {
+            username: buf[2..username_end].to_vec(),
+            password: buf[username_end + 1..len].to_vec(),
+        };
+        Ok(Decoded::Complete(request, len))
+    }
+}
+
+/// The server's verdict on a username and password (RFC 1929)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordStatus {
+    /// Zero for success, anything else for failure
+    pub status: u8,
+}
+
+impl PasswordStatus {
+    /// Returns whether the credentials were accepted
+    pub fn is_success(&self) -> bool {
+        self.status == 0x00
+    }
+
+    /// Encodes the status: VER, STATUS
+    pub fn encode(&self) -> Vec<u8> {
+        Vec::from([SUBNEGOTIATION_VERSION, self.status])
+    }
+
+    /// Decodes a status from the start of `buf`
+    pub fn decode(buf: &[u8]) -> Result<Decoded<Self>, WireError> {
+        need!(buf, 2);
+        if buf[0] != SUBNEGOTIATION_VERSION {
+            return Err(WireError::SubnegotiationVersion(buf[0]));
+        }
+        Ok(Decoded::Complete(PasswordStatus { status: buf[1] }, 2))
+    }
+}
+
+/// The client's request, e.g. to CONNECT to a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// The command code
+    pub command: u8,
+    /// The destination
+    pub target: TargetAddr,
+}
+
+impl Request {
+    /// Encodes the request: VER, CMD, RSV, ATYP, DST.ADDR, DST.PORT
+    ///
+    /// # Returns
+    /// - Ok(Vec<u8>) with the encoded request
+    /// - Err(WireError) if the target's domain name is too long to encode
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        let mut buf = Vec::from([SOCKS_VERSION, self.command, RESERVED]);
+        self.target.write_to(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decodes a request from the start of `buf`
+    ///
+    /// The reserved byte is not checked; servers decide how strict to be.
+    pub fn decode(buf: &[u8]) -> Result<Decoded<Self>, WireError> {
+        need!(buf, 4);
+        check_version(buf[0])?;
+        Ok(match TargetAddr::decode(&buf[3..])? {
+            Decoded::Complete(target, len) => Decoded::Complete(Request { command: buf[1], target }, 3 + len),
+            Decoded::Incomplete(more) => Decoded::Incomplete(more),
+        })
+    }
+}
+
+/// The server's reply to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// The reply code, `SUCCEEDED` or a failure
+    pub code: u8,
+    /// The address the server bound for the request
+    pub bound: TargetAddr,
+}
+
+impl Reply {
+    /// Encodes the reply: VER, REP, RSV, ATYP, BND.ADDR, BND.PORT
+    ///
+    /// # Returns
+    /// - Ok(Vec<u8>) with the encoded reply
+    /// - Err(WireError) if the bound domain name is too long to encode
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        let mut buf = Vec::from([SOCKS_VERSION, self.code, RESERVED]);
+        self.bound.write_to(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decodes a reply from the start of `buf`
+    pub fn decode(buf: &[u8]) -> Result<Decoded<Self>, WireError> {
+        need!(buf, 4);
+        check_version(buf[0])?;
+        Ok(match TargetAddr::decode(&buf[3..])? {
+            Decoded::Complete(bound, len) => Decoded::Complete(Reply { code: buf[1], bound }, 3 + len),
+            Decoded::Incomplete(more) => Decoded::Incomplete(more),
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use rsocks5_core::client::{ClientHandshake, ClientStep};
+use rsocks5_core::{TargetAddr, WireError};
+
+/// Answers each step from `responses` until the exchange ends
+///
+/// # Returns
+/// * The bytes the client sent, and its outcome
+fn drive(mut handshake: ClientHandshake, mut responses: &[u8]) -> (Vec<u8>, Result<ClientStep, WireError>) {
+    let mut sent = Vec::new();
+    loop {
+        match handshake.poll() {
+            Ok(ClientStep::Send(bytes)) => sent.extend_from_slice(&bytes),
+            Ok(ClientStep::Receive(len)) => {
+                let (chunk, rest) = responses.split_at(len);
+                handshake.receive(chunk);
+                responses = rest;
+            }
+            outcome => return (sent, outcome),
+        }
+    }
+}
+
+#[test]
+fn test_connect_without_authentication() {
+    let target = TargetAddr::from(("example.com", 80));
+    let (sent, outcome) = drive(
+        ClientHandshake::new(target),
+        &[0x05, 0x00, 0x05, 0x00, 0x00, 0x03, 4, b'p', b'r', b'o', b'x', 0x1f, 0x90],
+    );
+    let mut expected = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 11];
+    expected.extend_from_slice(b"example.com");
+    expected.extend_from_slice(&[0, 80]);
+    assert_eq!(sent, expected);
+    assert_eq!(outcome, Ok(ClientStep::Connected(TargetAddr::Domain("prox".to_string(), 8080))));
+}
+
+#[test]
+fn test_connect_with_password() {
+    let target = TargetAddr::from(("10.0.0.1", 443));
+    let handshake = ClientHandshake::new(target.clone()).with_credentials("alice", "pw").with_method(0xE5);
+    assert_eq!(handshake.methods(), [0x00, 0x02, 0xE5]);
+    let (sent, outcome) = drive(handshake, &[0x05, 0x02, 0x01, 0x00, 0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(&sent[..5], [0x05, 0x03, 0x00, 0x02, 0xE5]);
+    assert_eq!(&sent[5..15], [0x01, 5, b'a', b'l', b'i', b'c', b'e', 2, b'p', b'w']);
+    assert_eq!(outcome, Ok(ClientStep::Refused(0x02)));
+
+    let (_, outcome) = drive(ClientHandshake::new(target.clone()).with_credentials("alice", "pw"), &[0x05, 0x02, 0x01, 0x01]);
+    assert_eq!(outcome, Err(WireError::AuthRejected));
+    // Without credentials, the client cannot follow a USER_PASS selection
+    let (_, outcome) = drive(ClientHandshake::new(target), &[0x05, 0x02]);
+    assert_eq!(outcome, Err(WireError::Method(0x02)));
+}
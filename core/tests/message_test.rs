@@ -0,0 +1,65 @@
+use rsocks5_core::constants::{atyp, auth, cmd, reply};
+use rsocks5_core::message::{Greeting, MethodSelection, PasswordRequest, PasswordStatus, Reply, Request};
+use rsocks5_core::{Decoded, TargetAddr, WireError};
+
+#[test]
+fn test_target_addr_round_trips() {
+    for target in [
+        TargetAddr::from(("10.0.0.1", 80)),
+        TargetAddr::from(("2001:db8::1", 443)),
+        TargetAddr::from(("example.com", 8080)),
+    ] {
+        let mut buf = Vec::new();
+        target.write_to(&mut buf).unwrap();
+        assert_eq!(TargetAddr::decode(&buf), Ok(Decoded::Complete(target.clone(), buf.len())));
+        // Every prefix asks for exactly what is still missing, or a length byte
+        for len in 0..buf.len() {
+            match TargetAddr::decode(&buf[..len]).unwrap() {
+                Decoded::Incomplete(more) => assert!(len + more <= buf.len(), "{} + {}", len, more),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_target_addr_errors() {
+    assert_eq!(TargetAddr::decode(&[0x02, 0, 0]), Err(WireError::AddressType(0x02)));
+    assert!(matches!(TargetAddr::decode(&[atyp::DOMAIN, 2, 0xff, 0xfe, 0, 80]), Err(WireError::Address(_))));
+    let long = TargetAddr::Domain("a".repeat(256), 80);
+    assert!(matches!(long.write_to(&mut Vec::new()), Err(WireError::Address(_))));
+    assert!(matches!("no-port".parse::<TargetAddr>(), Err(WireError::Address(_))));
+    assert_eq!("[::1]:80".parse::<TargetAddr>(), Ok(TargetAddr::from(("::1", 80))));
+}
+
+#[test]
+fn test_messages_round_trip() {
+    let greeting = Greeting { methods: vec![auth::NO_AUTH, auth::USER_PASS] };
+    assert_eq!(greeting.encode(), [0x05, 0x02, 0x00, 0x02]);
+    assert_eq!(Greeting::decode(&greeting.encode()), Ok(Decoded::Complete(greeting, 4)));
+    assert_eq!(Greeting::decode(&[0x05, 0x02, 0x00]), Ok(Decoded::Incomplete(1)));
+    assert_eq!(Greeting::decode(&[0x04, 0x01]), Err(WireError::Version(0x04)));
+
+    let selection = MethodSelection { method: auth::USER_PASS };
+    assert_eq!(MethodSelection::decode(&selection.encode()), Ok(Decoded::Complete(selection, 2)));
+
+    let request = PasswordRequest { username: b"alice".to_vec(), password: b"secret".to_vec() };
+    let encoded = request.encode().unwrap();
+    assert_eq!(PasswordRequest::decode(&encoded), Ok(Decoded::Complete(request, encoded.len())));
+    let too_long = PasswordRequest { username: vec![b'a'; 256], password: Vec::new() };
+    assert_eq!(too_long.encode(), Err(WireError::CredentialsTooLong));
+    let status = PasswordStatus { status: 0x01 };
+    assert!(!status.is_success());
+    assert_eq!(PasswordStatus::decode(&status.encode()), Ok(Decoded::Complete(status, 2)));
+
+    let request = Request { command: cmd::CONNECT, target: TargetAddr::from(("example.com", 443)) };
+    let encoded = request.encode().unwrap();
+    assert_eq!(Request::decode(&encoded), Ok(Decoded::Complete(request, encoded.len())));
+
+    let reply = Reply { code: reply::SUCCEEDED, bound: TargetAddr::from(("0.0.0.0", 0)) };
+    assert_eq!(reply.encode().unwrap(), [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    // Trailing bytes belong to whatever follows the reply
+    let mut encoded = reply.encode().unwrap();
+    encoded.extend_from_slice(b"tunnel");
+    assert_eq!(Reply::decode(&encoded), Ok(Decoded::Complete(reply, 10)));
+}
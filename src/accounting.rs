@@ -0,0 +1,110 @@
+//! Per-user traffic accounting for the SOCKS5 proxy.
+//!
+//! Once a session has authenticated, [`TrafficAccounting`] counts it and,
+//! when it closes, the bytes it relayed under its username. The registry can
+//! be shared between servers and read at any time through the library API,
+//! as the basis for billing, quotas or usage reports. Sessions without a
+//! username are not accounted.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::relay::RelayStats;
+
+/// Traffic of one user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UserTraffic {
+    /// Sessions the user authenticated, including the active ones
+    pub sessions: u64,
+    /// Sessions of the user that have not closed yet
+    pub active_sessions: u64,
+    /// Bytes relayed from the user's clients to their targets
+    pub bytes_from_client: u64,
+    /// Bytes relayed from the targets to the user's clients
+    pub bytes_from_target: u64,
+}
+
+impl UserTraffic {
+    /// Returns the bytes relayed in both directions
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_from_client + self.bytes_from_target
+    }
+}
+
+/// Traffic counters of every user seen since the registry was created
+#[derive(Debug, Default)]
+pub struct TrafficAccounting {
+    /// The counters by username
+    users: Mutex<HashMap<String, UserTraffic>>,
+}
+
+impl TrafficAccounting {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a session that authenticated as `username`
+    pub fn open_session(&self, username: &str) {
+        let mut users = self.lock();
+        let traffic = users.entry(username.to_string()).or_default();
+        traffic.sessions += 1;
+        traffic.active_sessions += 1;
+    }
+
+    /// Adds the bytes a closed session of `username` relayed
+    ///
+    /// # Arguments
+    /// * `username` - The username the session authenticated as
+    /// * `relayed` - The bytes relayed in each direction
+    pub fn close_session(&self, username: &str, relayed: &RelayStats) {
+        let mut users = self.lock();
+        let traffic = users.entry(username.to_string()).or_default();
+        traffic.active_sessions = traffic.active_sessions.saturating_sub(1);
+        traffic.bytes_from_client += relayed.client_to_target;
+        traffic.bytes_from_target += relayed.target_to_client;
+    }
+
+    /// Returns the traffic of `username`, if it was seen
+    pub fn user(&self, username: &str) -> Option<UserTraffic> {
+        self.lock().get(username).copied()
+    }
+
+    /// Returns the traffic of every user seen, by username
+    pub fn users(&self) -> BTreeMap<String, UserTraffic> {
+        self.lock().iter().map(|(username, traffic)| (username.clone(), *traffic)).collect()
+    }
+
+    /// Returns the traffic of every user and clears the closed sessions'
+    /// counters, e.g. at the end of a billing period
+    ///
+    /// Users with active sessions keep their active session count.
+    pub fn take(&self) -> BTreeMap<String, UserTraffic> {
+        let mut users = self.lock();
+        let taken = users.iter().map(|(username, traffic)| (username.clone(), *traffic)).collect();
+        users.retain(|_, traffic| traffic.active_sessions > 0);
+        for traffic in users.values_mut() {
+            *traffic = UserTraffic {
+                active_sessions: traffic.active_sessions,
+                ..UserTraffic::default()
+            };
+        }
+        taken
+    }
+
+    /// Returns the number of users seen
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns whether no user was seen
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Locks the counters, ignoring poisoning
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, UserTraffic>> {
+        self.users.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,510 @@
+//! Destination access control for the SOCKS5 proxy.
+//!
+//! This module decides whether a client may connect to a requested target
+//! before any outbound connection is attempted.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::canonical::canonicalize;
+use crate::constants::reply;
+use crate::error::Socks5Error;
+use crate::policy::PolicyContext;
+use crate::protocol::TargetAddr;
+use crate::routing::{cidr_contains, HostPattern, TargetPattern};
+
+/// A strict list of the only `host:port` pairs clients may connect to
+///
+/// Hosts are compared case-insensitively and without a trailing dot; IPv4
+/// targets match their dotted-decimal form. Domains are not resolved, so a
+/// listed domain does not allow its IP address and vice versa.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetAllowList {
+    /// Normalized host and port pairs
+    entries: HashSet<(String, u16)>,
+    /// Whether denials are only logged and counted, not enforced
+    shadow: bool,
+}
+
+impl TargetAllowList {
+    /// Creates an empty allow-list, which allows nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a `host:port` pair to the list
+    ///
+    /// # Arguments
+    /// * `host` - The host name or IP address
+    /// * `port` - The port
+    pub fn allow(&mut self, host: &str, port: u16) {
+        self.entries.insert((canonicalize(host).into_owned(), port));
+    }
+
+    /// Parses and adds a `host:port` string to the list
+    ///
+    /// # Returns
+    /// - Ok(()) if the entry was added
+    /// - Err(Socks5Error) if the string is not a valid `host:port` pair
+    pub fn allow_str(&mut self, entry: &str) -> Result<(), Socks5Error> {
+        let target: TargetAddr = entry.parse()?;
+        let (host, port) = host_and_port(&target);
+        self.allow(&host, port);
+        Ok(())
+    }
+
+    /// Puts the list in shadow mode, where targets that are not on it are
+    /// logged and counted but still allowed
+    ///
+    /// Useful to validate a new list against live traffic before enforcing it.
+    pub fn with_shadow(mut self, shadow: bool) -> Self {
+        self.shadow = shadow;
+        self
+    }
+
+    /// Returns whether the list is in shadow mode
+    pub fn is_shadow(&self) -> bool {
+        self.shadow
+    }
+
+    /// Returns the number of entries in the list
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns whether the target is on the list
+    pub fn is_allowed(&self, target: &TargetAddr) -> bool {
+        self.entries.contains(&host_and_port(target))
+    }
+}
+
+/// What an [`AclRule`] does with matching targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclAction {
+    /// Matching targets may be connected to
+    Allow,
+    /// Matching targets are refused with NOT_ALLOWED
+    Deny,
+}
+
+impl AclAction {
+    /// Returns the keyword of the action, `allow` or `deny`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AclAction::Allow => "allow",
+            AclAction::Deny => "deny",
+        }
+    }
+}
+
+/// An allow or deny rule for targets matching a pattern
+///
+/// A rule can be scoped to one authenticated user and to clients from one
+/// network; it then only applies to requests known to come from them. A
+/// deny rule in shadow mode only logs and counts the requests it would
+/// refuse, and the rules after it decide them. A named rule is reported by
+/// its name, to logs and to clients told why they were refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    /// What the rule does with matching targets
+    pub action: AclAction,
+    /// The targets the rule applies to, e.g. `10.0.0.0/8`, `*.internal` or `*:25`
+    pub pattern: TargetPattern,
+    /// The name the rule is reported by, if any, e.g. `no-smtp`
+    pub name: Option<String>,
+    /// The only user the rule applies to, if scoped
+    pub user: Option<String>,
+    /// The only client network the rule applies to, as address and prefix length
+    pub source: Option<(IpAddr, u8)>,
+    /// Whether the rule's denials are only logged and counted, not enforced
+    pub shadow: bool,
+}
+
+impl AclRule {
+    /// Returns whether the rule applies to the client described in `context`
+    ///
+    /// Scoped rules never apply to anonymous clients or clients of unknown
+    /// address.
+    pub fn applies_to(&self, context: &PolicyContext<'_>) -> bool {
+        self.user.as_deref().is_none_or(|user| context.username == Some(user))
+            && self.source.is_none_or(|(network, len)| {
+                context
+                    .client
+                    .is_some_and(|client| cidr_contains(network, len, client.ip().to_canonical()))
+            })
+    }
+
+    /// Returns whether the rule decides a request for `target` by the client in `context`
+    pub fn matches(&self, context: &PolicyContext<'_>, target: &TargetAddr) -> bool {
+        self.applies_to(context) && self.pattern.matches(target)
+    }
+}
+
+impl FromStr for AclRule {
+    type Err = Socks5Error;
+
+    /// Parses `allow PATTERN` or `deny PATTERN`, where PATTERN is any
+    /// [`TargetPattern`], optionally followed by `name=NAME`, `user=NAME`
+    /// and `from=NETWORK`, e.g. `allow *:22 name=ssh user=admin from=10.0.0.0/8`.
+    /// Deny rules ending in `shadow` are in shadow mode.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Socks5Error::ConfigError(format!(
+                "Expected `allow PATTERN` or `deny PATTERN`, then `name=NAME`, `user=NAME`, `from=NETWORK` or `shadow`: {}",
+                s
+            ))
+        };
+        let mut words = s.split_whitespace();
+        let action = match words.next().map(str::to_ascii_lowercase).as_deref() {
+            Some("allow") => AclAction::Allow,
+            Some("deny") => AclAction::Deny,
+            _ => return Err(invalid()),
+        };
+        let mut rule = Self {
+            action,
+            pattern: words.next().ok_or_else(invalid)?.parse()?,
+            name: None,
+            user: None,
+            source: None,
+            shadow: false,
+        };
+        for word in words {
+            match word.split_once('=') {
+                Some(("name", name)) if !name.is_empty() => rule.name = Some(name.to_string()),
+                Some(("user", user)) if !user.is_empty() => rule.user = Some(user.to_string()),
+                Some(("from", network)) => rule.source = Some(parse_network(network)?),
+                None if word.eq_ignore_ascii_case("shadow") => rule.shadow = true,
+                _ => return Err(invalid()),
+            }
+        }
+        if rule.shadow && rule.action == AclAction::Allow {
+            return Err(Socks5Error::ConfigError(format!("Only deny rules can be in shadow mode: {}", s)));
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for AclRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.action.as_str(), self.pattern)?;
+        if let Some(name) = &self.name {
+            write!(f, " name={}", name)?;
+        }
+        if let Some(user) = &self.user {
+            write!(f, " user={}", user)?;
+        }
+        if let Some((network, len)) = self.source {
+            write!(f, " from={}/{}", network, len)?;
+        }
+        if self.shadow {
+            f.write_str(" shadow")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a client network such as `10.0.0.0/8`; a bare address is a network of one
+fn parse_network(s: &str) -> Result<(IpAddr, u8), Socks5Error> {
+    match s.parse::<HostPattern>()? {
+        HostPattern::Cidr(network, len) => Ok((network, len)),
+        HostPattern::Exact(host) => match host.parse::<IpAddr>() {
+            Ok(ip) => Ok((ip, if ip.is_ipv4() { 32 } else { 128 })),
+            Err(_) => Err(Socks5Error::ConfigError(format!("Invalid client network: {}", s))),
+        },
+        _ => Err(Socks5Error::ConfigError(format!("Invalid client network: {}", s))),
+    }
+}
+
+/// Ordered allow and deny rules for targets; the first matching rule wins
+///
+/// Targets matching no rule are allowed, so a list of deny rules blocks
+/// only what it names; end the list with `deny *` to allow only what the
+/// rules before it allow. Domain targets are not resolved, so they never
+/// match CIDR rules. Rules scoped to a user or client network are skipped
+/// for other clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AclRules {
+    /// The rules, in evaluation order
+    rules: Vec<AclRule>,
+}
+
+impl AclRules {
+    /// Creates a rule list without rules, which allows everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a rule
+    pub fn with_rule(mut self, rule: AclRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Parses and appends a rule such as `deny 10.0.0.0/8`
+    ///
+    /// # Returns
+    /// - Ok(()) if the rule was added
+    /// - Err(Socks5Error) if the rule is malformed
+    pub fn push_str(&mut self, rule: &str) -> Result<(), Socks5Error> {
+        self.rules.push(rule.parse()?);
+        Ok(())
+    }
+
+    /// Returns the rules in evaluation order
+    pub fn rules(&self) -> &[AclRule] {
+        &self.rules
+    }
+
+    /// Returns the number of rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns whether there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the index and rule deciding a request for `target` by the
+    /// client in `context`, if any rule matches
+    ///
+    /// Shadow rules never decide a request.
+    pub fn rule_for(&self, context: &PolicyContext<'_>, target: &TargetAddr) -> Option<(usize, &AclRule)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| !rule.shadow && rule.matches(context, target))
+    }
+
+    /// Returns whether the client in `context` may connect to the target
+    pub fn is_allowed(&self, context: &PolicyContext<'_>, target: &TargetAddr) -> bool {
+        self.rule_for(context, target).is_none_or(|(_, rule)| rule.action == AclAction::Allow)
+    }
+
+    /// Returns the indexes of the deny rules matching a request for `target`
+    /// by the client in `context`, in order
+    ///
+    /// Every matching shadow rule is listed, up to the rule deciding the
+    /// request; that one is listed last if it denies.
+    pub fn denials(&self, context: &PolicyContext<'_>, target: &TargetAddr) -> Vec<usize> {
+        let mut denials = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(context, target) {
+                continue;
+            }
+            if rule.action == AclAction::Deny {
+                denials.push(index);
+            }
+            if !rule.shadow {
+                break;
+            }
+        }
+        denials
+    }
+}
+
+/// A class of target ports refused by a [`PortPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortClass {
+    /// Port 0, which no service listens on
+    Zero,
+    /// Ports below 1024, reserved for system services
+    Privileged,
+    /// Ports on the deny list, e.g. 25 against spam relaying
+    Denied,
+    /// Ports missing from a configured allow list
+    Unlisted,
+}
+
+impl PortClass {
+    /// All port classes, in counter order
+    pub const ALL: [PortClass; 4] = [PortClass::Zero, PortClass::Privileged, PortClass::Denied, PortClass::Unlisted];
+
+    /// Returns a short, stable name for the class
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PortClass::Zero => "zero",
+            PortClass::Privileged => "privileged",
+            PortClass::Denied => "denied",
+            PortClass::Unlisted => "unlisted",
+        }
+    }
+
+    /// Returns the reply code sent to clients requesting a port of this class
+    ///
+    /// Port 0 is not a usable address at all; other classes are merely
+    /// not allowed by the ruleset.
+    pub fn reply(&self) -> u8 {
+        match self {
+            PortClass::Zero => reply::ADDRESS_TYPE_NOT_SUPPORTED,
+            PortClass::Privileged | PortClass::Denied | PortClass::Unlisted => reply::NOT_ALLOWED,
+        }
+    }
+
+    /// Returns the counter slot for the class
+    pub(crate) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// A single port or an inclusive range of ports, e.g. `443` or `8000-8100`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    /// The first port of the range
+    pub first: u16,
+    /// The last port of the range, equal to `first` for a single port
+    pub last: u16,
+}
+
+impl PortRange {
+    /// Creates a range of the ports from `first` to `last`, in either order
+    pub fn new(first: u16, last: u16) -> Self {
+        Self {
+            first: first.min(last),
+            last: first.max(last),
+        }
+    }
+
+    /// Returns whether the port is in the range
+    pub fn contains(&self, port: u16) -> bool {
+        (self.first..=self.last).contains(&port)
+    }
+}
+
+impl From<u16> for PortRange {
+    fn from(port: u16) -> Self {
+        Self::new(port, port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = Socks5Error;
+
+    /// Parses `PORT` or `FIRST-LAST`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|_| Socks5Error::ConfigError(format!("Invalid port or port range: {}", s)))
+        };
+        match s.split_once('-') {
+            Some((first, last)) => Ok(Self::new(parse(first)?, parse(last)?)),
+            None => parse(s).map(Self::from),
+        }
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// Which degenerate or sensitive target ports are refused
+///
+/// Port 0 is always refused instead of being handed to the OS, which fails
+/// with confusing errors. Operators can deny ports outright, e.g. 25 to
+/// keep the proxy from relaying spam, or list the only ports clients may
+/// use, e.g. 80 and 443. Privileged ports can be refused too, with
+/// exceptions for services such as HTTP and HTTPS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortPolicy {
+    /// Whether ports below 1024 are refused
+    deny_privileged: bool,
+    /// Privileged ports that stay allowed
+    exceptions: BTreeSet<u16>,
+    /// The only ports clients may use, unless empty
+    allowed: Vec<PortRange>,
+    /// Ports that are always refused
+    denied: Vec<PortRange>,
+}
+
+impl PortPolicy {
+    /// Creates a policy refusing port 0 only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuses ports below 1024 as well
+    pub fn with_privileged_denied(mut self, deny: bool) -> Self {
+        self.deny_privileged = deny;
+        self
+    }
+
+    /// Keeps a privileged port allowed, e.g. 443
+    pub fn with_exception(mut self, port: u16) -> Self {
+        self.exceptions.insert(port);
+        self
+    }
+
+    /// Only allows ports in this range and the other allowed ones
+    ///
+    /// Allowed ports below 1024 stay allowed when privileged ports are
+    /// refused.
+    pub fn with_allowed(mut self, ports: PortRange) -> Self {
+        self.allowed.push(ports);
+        self
+    }
+
+    /// Refuses the ports in this range, even if they are allowed
+    pub fn with_denied(mut self, ports: PortRange) -> Self {
+        self.denied.push(ports);
+        self
+    }
+
+    /// Returns the only ports clients may use; empty when unrestricted
+    pub fn allowed(&self) -> &[PortRange] {
+        &self.allowed
+    }
+
+    /// Returns the ports that are always refused
+    pub fn denied(&self) -> &[PortRange] {
+        &self.denied
+    }
+
+    /// Returns whether ports below 1024 are refused
+    pub fn denies_privileged(&self) -> bool {
+        self.deny_privileged
+    }
+
+    /// Returns the privileged ports that stay allowed
+    pub fn exceptions(&self) -> impl Iterator<Item = u16> + '_ {
+        self.exceptions.iter().copied()
+    }
+
+    /// Returns the class a refused port belongs to, or `None` if it is allowed
+    pub fn check(&self, port: u16) -> Option<PortClass> {
+        let listed = self.allowed.iter().any(|ports| ports.contains(port));
+        if port == 0 {
+            Some(PortClass::Zero)
+        } else if self.denied.iter().any(|ports| ports.contains(port)) {
+            Some(PortClass::Denied)
+        } else if !self.allowed.is_empty() && !listed {
+            Some(PortClass::Unlisted)
+        } else if self.deny_privileged && port < 1024 && !self.exceptions.contains(&port) && !listed {
+            Some(PortClass::Privileged)
+        } else {
+            None
+        }
+    }
+}
+
+/// Splits a target into its textual host and port
+fn host_and_port(target: &TargetAddr) -> (String, u16) {
+    match target {
+        TargetAddr::Ipv4(ip, port) => (ip.to_string(), *port),
+        TargetAddr::Ipv6(ip, port) => (ip.to_string(), *port),
+        TargetAddr::Domain(domain, port) => (canonicalize(domain).into_owned(), *port),
+    }
+}
@@ -0,0 +1,291 @@
+//! Live session registry for the SOCKS5 proxy.
+//!
+//! [`RecentSessions`](crate::recent::RecentSessions) only learns about a
+//! session once it has ended. [`ActiveSessions`] lists the sessions a server
+//! is handling right now: who connected, as which user, to which target, for
+//! how long so far and how many bytes went each way, counted as they are
+//! relayed. An operator can also terminate a single session, e.g. a runaway
+//! transfer, without restarting the proxy.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::listener::ClientStream;
+use crate::protocol::TargetAddr;
+
+/// One session that is being handled
+#[derive(Debug)]
+pub struct ActiveSession {
+    /// The connection ID used in log lines
+    conn_id: u32,
+    /// The client's address
+    client: SocketAddr,
+    /// The address of the listener that accepted the client
+    listener: String,
+    /// When the session was accepted
+    started: Instant,
+    /// Wall-clock time the session was accepted, in milliseconds since the Unix epoch
+    started_ms: u64,
+    /// The authenticated username, once the handshake is done
+    username: Mutex<Option<String>>,
+    /// The requested target, once the request is read
+    target: Mutex<Option<TargetAddr>>,
+    /// Bytes read from the client so far
+    bytes_from_client: AtomicU64,
+    /// Bytes written to the client so far
+    bytes_from_target: AtomicU64,
+    /// Woken when an operator terminates the session
+    terminate: Notify,
+}
+
+impl ActiveSession {
+    /// Returns the connection ID
+    pub fn conn_id(&self) -> u32 {
+        self.conn_id
+    }
+
+    /// Records the authenticated username
+    pub fn set_username(&self, username: Option<String>) {
+        *self.username.lock().unwrap_or_else(|e| e.into_inner()) = username;
+    }
+
+    /// Records the requested target
+    pub fn set_target(&self, target: TargetAddr) {
+        *self.target.lock().unwrap_or_else(|e| e.into_inner()) = Some(target);
+    }
+
+    /// Returns the bytes read from the client so far
+    pub fn bytes_from_client(&self) -> u64 {
+        self.bytes_from_client.load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes written to the client so far
+    pub fn bytes_from_target(&self) -> u64 {
+        self.bytes_from_target.load(Ordering::Relaxed)
+    }
+
+    /// Asks the task handling the session to close it
+    pub fn terminate(&self) {
+        self.terminate.notify_one();
+    }
+
+    /// Completes once the session was terminated
+    pub async fn terminated(&self) {
+        self.terminate.notified().await
+    }
+
+    /// Returns a copy of the session's state
+    ///
+    /// # Arguments
+    /// * `now` - The current time, to compute how long the session has lasted
+    pub fn snapshot(&self, now: Instant) -> SessionSnapshot {
+        SessionSnapshot {
+            conn_id: format!("{:08x}", self.conn_id),
+            client: self.client,
+            listener: self.listener.clone(),
+            username: self.username.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+            target: self.target.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(TargetAddr::to_string),
+            started_ms: self.started_ms,
+            duration_ms: now.saturating_duration_since(self.started).as_millis() as u64,
+            bytes_from_client: self.bytes_from_client(),
+            bytes_from_target: self.bytes_from_target(),
+        }
+    }
+}
+
+/// A point-in-time copy of an active session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionSnapshot {
+    /// The connection ID used in log lines, in hex
+    pub conn_id: String,
+    /// The client's address
+    pub client: SocketAddr,
+    /// The address of the listener that accepted the client
+    pub listener: String,
+    /// The authenticated username, if any
+    pub username: Option<String>,
+    /// The requested target, if the session got as far as asking for one
+    pub target: Option<String>,
+    /// Wall-clock time the session was accepted, in milliseconds since the Unix epoch
+    pub started_ms: u64,
+    /// How long the session has lasted so far
+    pub duration_ms: u64,
+    /// Bytes relayed from the client so far
+    pub bytes_from_client: u64,
+    /// Bytes relayed to the client so far
+    pub bytes_from_target: u64,
+}
+
+/// The sessions a server is handling right now
+#[derive(Debug, Default)]
+pub struct ActiveSessions {
+    /// The sessions by connection ID
+    sessions: Mutex<HashMap<u32, Arc<ActiveSession>>>,
+}
+
+impl ActiveSessions {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a newly accepted session
+    ///
+    /// The session stays listed until the returned handle is dropped.
+    ///
+    /// # Arguments
+    /// * `conn_id` - The connection ID used in log lines
+    /// * `client` - The client's address
+    /// * `listener` - The address of the listener that accepted the client
+    /// * `started` - When the session was accepted
+    /// * `started_ms` - The same time as milliseconds since the Unix epoch
+    pub fn register(
+        self: &Arc<Self>,
+        conn_id: u32,
+        client: SocketAddr,
+        listener: String,
+        started: Instant,
+        started_ms: u64,
+    ) -> SessionHandle {
+        let session = Arc::new(ActiveSession {
+            conn_id,
+            client,
+            listener,
+            started,
+            started_ms,
+            username: Mutex::new(None),
+            target: Mutex::new(None),
+            bytes_from_client: AtomicU64::new(0),
+            bytes_from_target: AtomicU64::new(0),
+            terminate: Notify::new(),
+        });
+        self.lock().insert(conn_id, Arc::clone(&session));
+        SessionHandle {
+            sessions: Arc::clone(self),
+            session,
+        }
+    }
+
+    /// Returns every active session, oldest first
+    ///
+    /// # Arguments
+    /// * `now` - The current time, to compute how long each session has lasted
+    pub fn list(&self, now: Instant) -> Vec<SessionSnapshot> {
+        let mut sessions: Vec<_> = self.lock().values().cloned().collect();
+        sessions.sort_by_key(|session| (session.started, session.conn_id));
+        sessions.iter().map(|session| session.snapshot(now)).collect()
+    }
+
+    /// Terminates the session with the given connection ID
+    ///
+    /// # Returns
+    /// * `true` if the session was active and is being closed
+    pub fn terminate(&self, conn_id: u32) -> bool {
+        match self.lock().get(&conn_id) {
+            Some(session) => {
+                session.terminate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of active sessions
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns whether no session is active
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Locks the sessions, ignoring poisoning
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u32, Arc<ActiveSession>>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Keeps a session listed until it is dropped
+#[derive(Debug)]
+pub struct SessionHandle {
+    /// The registry the session is listed in
+    sessions: Arc<ActiveSessions>,
+    /// The listed session
+    session: Arc<ActiveSession>,
+}
+
+impl SessionHandle {
+    /// Returns the listed session
+    pub fn session(&self) -> &Arc<ActiveSession> {
+        &self.session
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.sessions.lock().remove(&self.session.conn_id);
+    }
+}
+
+/// A client stream that counts the bytes read from and written to it into
+/// its [`ActiveSession`]
+#[derive(Debug)]
+pub struct Counted<S> {
+    /// The wrapped stream
+    inner: S,
+    /// The session the bytes are counted in
+    session: Arc<ActiveSession>,
+}
+
+impl<S> Counted<S> {
+    /// Wraps `inner`, counting its bytes into `session`
+    pub fn new(inner: S, session: Arc<ActiveSession>) -> Self {
+        Self { inner, session }
+    }
+}
+
+impl<S: ClientStream> ClientStream for Counted<S> {
+    fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<usize>> {
+        self.inner.poll_peek(cx, buf)
+    }
+
+    fn client_addr(&self) -> Option<SocketAddr> {
+        self.inner.client_addr()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - before) as u64;
+        self.session.bytes_from_client.fetch_add(read, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.session.bytes_from_target.fetch_add(written as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
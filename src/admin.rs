@@ -0,0 +1,448 @@
+//! Admin HTTP API for the SOCKS5 proxy.
+//!
+//! An optional second listener answers a handful of JSON endpoints so
+//! operators can look into a running proxy and act on it without restarting
+//! the process:
+//!
+//! * `GET /status` - every listener's [`ListenerStats`]
+//! * `GET /sessions` - the sessions being handled, from servers that list
+//!   them with [`Server::with_active_sessions`]
+//! * `DELETE /sessions/{conn_id}` - terminates one session, named by the hex
+//!   connection ID from the log lines
+//! * `GET /recent?last=N&target=PATTERN&since=MS` - the records of the last
+//!   sessions, newest first, from servers that keep them with
+//!   [`Server::with_recent_sessions`]; every parameter is optional
+//! * `GET /reputation?min=SCORE` - client IPs by penalty score, worst first
+//! * `GET /loglevel` and `PUT /loglevel?level=LEVEL` - the effective log
+//!   level, and changing it, given a [`LogLevelControl`]
+//!
+//! Each connection carries one request, which must arrive within
+//! [`READ_TIMEOUT`], and at most [`MAX_CONNECTIONS`] are served at once.
+//! Bind the listener to a loopback or management address; with a token set,
+//! every request must also carry it as `Authorization: Bearer TOKEN`. The
+//! API can also share a proxy's TLS port as its [`AlpnHandler`] for
+//! `http/1.1`, registered with
+//! [`TlsListener::with_alpn_handler`](crate::tls::TlsListener::with_alpn_handler).
+
+use log::LevelFilter;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio_rustls::server::TlsStream;
+
+use crate::active::SessionSnapshot;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::http::MAX_HEAD_SIZE;
+use crate::loglevel::LogLevelControl;
+use crate::metrics::ListenerStats;
+use crate::protocol::close_gracefully;
+use crate::recent::{RecentQuery, SessionRecord};
+use crate::routing::TargetPattern;
+use crate::server::Server;
+use crate::tls::{AlpnFuture, AlpnHandler};
+use crate::url::percent_decode;
+use crate::users::constant_time_eq;
+
+/// How long a client may take to send its request head
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many admin connections are served at once; further ones wait
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// A response to an admin request
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminResponse {
+    /// The status code, e.g. 200
+    pub status: u16,
+    /// The JSON body
+    pub body: serde_json::Value,
+}
+
+impl AdminResponse {
+    /// Creates a 200 response with `body`
+    fn ok(body: impl Serialize) -> Self {
+        Self {
+            status: 200,
+            body: serde_json::to_value(body).unwrap_or_default(),
+        }
+    }
+
+    /// Creates an error response with a message
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+
+    /// Returns the reason phrase of the status code
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Error",
+        }
+    }
+}
+
+/// Status of every listener
+#[derive(Debug, Serialize)]
+struct Status {
+    /// The proxy's version
+    version: &'static str,
+    /// Counters of each listener
+    listeners: Vec<ListenerStats>,
+}
+
+/// A finished session in the `/recent` listing
+#[derive(Debug, Serialize)]
+struct RecentSession {
+    /// The connection ID used in log lines, in hex
+    conn_id: String,
+    /// The client's address
+    client: SocketAddr,
+    /// The address of the listener that accepted the client
+    listener: String,
+    /// The authenticated username, if any
+    username: Option<String>,
+    /// The requested target, if the session got as far as asking for one
+    target: Option<String>,
+    /// Wall-clock time the session was accepted, in milliseconds since the Unix epoch
+    started_ms: u64,
+    /// How long the session lasted
+    duration_ms: u64,
+    /// Why the session ended, e.g. `completed`
+    reason: &'static str,
+    /// Bytes relayed from the client
+    bytes_from_client: u64,
+    /// Bytes relayed to the client
+    bytes_from_target: u64,
+}
+
+impl From<SessionRecord> for RecentSession {
+    fn from(record: SessionRecord) -> Self {
+        Self {
+            conn_id: format!("{:08x}", record.conn_id),
+            client: record.client,
+            listener: record.listener,
+            username: record.username,
+            target: record.target.map(|target| target.to_string()),
+            started_ms: record.started_ms,
+            duration_ms: record.duration.as_millis() as u64,
+            reason: record.reason,
+            bytes_from_client: record.bytes_from_client,
+            bytes_from_target: record.bytes_from_target,
+        }
+    }
+}
+
+/// A client IP in the `/reputation` listing
+#[derive(Debug, Serialize)]
+struct ClientScore {
+    /// The client's IP address
+    ip: IpAddr,
+    /// Its current penalty score
+    score: f64,
+}
+
+/// The effective and configured log level
+#[derive(Debug, Serialize)]
+struct LogLevel {
+    /// The level in effect now
+    level: String,
+    /// The level the proxy was started with
+    base: String,
+}
+
+/// Answers admin requests about a set of servers
+pub struct AdminApi {
+    /// The servers the API reports on and acts on
+    servers: Vec<Arc<Server>>,
+    /// Token every request must carry, if any
+    token: Option<String>,
+    /// Changes the effective log level, if exposed
+    log_level: Option<LogLevelControl>,
+}
+
+impl AdminApi {
+    /// Creates an API for `servers` that accepts unauthenticated requests
+    pub fn new(servers: Vec<Arc<Server>>) -> Self {
+        Self { servers, token: None, log_level: None }
+    }
+
+    /// Requires every request to carry `Authorization: Bearer TOKEN`
+    ///
+    /// # Arguments
+    /// * `token` - The bearer token requests must present
+    ///
+    /// # Returns
+    /// * The updated AdminApi instance
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Exposes the effective log level at `/loglevel`
+    ///
+    /// # Arguments
+    /// * `control` - The control the level is read and changed through
+    ///
+    /// # Returns
+    /// * The updated AdminApi instance
+    pub fn with_log_level(mut self, control: LogLevelControl) -> Self {
+        self.log_level = Some(control);
+        self
+    }
+
+    /// Answers one request
+    ///
+    /// # Arguments
+    /// * `method` - The request method, e.g. `GET`
+    /// * `path` - The request path and query, e.g. `/recent?last=10`
+    /// * `authorization` - The value of the `Authorization` header, if any
+    ///
+    /// # Returns
+    /// * The response to send
+    pub fn handle(&self, method: &str, path: &str, authorization: Option<&str>) -> AdminResponse {
+        if let Some(token) = &self.token {
+            let presented = authorization.and_then(|value| value.strip_prefix("Bearer ")).unwrap_or_default();
+            if !constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+                return AdminResponse::error(401, "missing or wrong bearer token");
+            }
+        }
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let Some(params) = query_params(query) else {
+            return AdminResponse::error(400, format!("invalid query: {}", query));
+        };
+        let result = match (method, path.trim_end_matches('/')) {
+            ("GET", "/status") => Ok(AdminResponse::ok(Status {
+                version: env!("CARGO_PKG_VERSION"),
+                listeners: self.servers.iter().map(|server| server.stats()).collect(),
+            })),
+            ("GET", "/sessions") => Ok(AdminResponse::ok(self.sessions())),
+            ("GET", "/recent") => self.recent(&params),
+            ("GET", "/reputation") => self.reputation(&params),
+            ("GET", "/loglevel") => self.log_level(None),
+            ("PUT", "/loglevel") => self.log_level(Some(&params)),
+            (_, "/status" | "/sessions" | "/recent" | "/reputation" | "/loglevel") => {
+                Err(AdminResponse::error(405, format!("{} is not allowed on {}", method, path)))
+            }
+            (method, path) => match path.strip_prefix("/sessions/") {
+                Some(id) if method == "DELETE" => Ok(self.terminate(id)),
+                Some(_) => Err(AdminResponse::error(405, format!("{} is not allowed on {}", method, path))),
+                None => Err(AdminResponse::error(404, format!("no such endpoint: {}", path))),
+            },
+        };
+        result.unwrap_or_else(|response| response)
+    }
+
+    /// Returns the sessions of every server that lists them, oldest first
+    fn sessions(&self) -> Vec<SessionSnapshot> {
+        let mut sessions: Vec<_> = self
+            .servers
+            .iter()
+            .filter_map(|server| server.active_sessions())
+            .flatten()
+            .collect();
+        sessions.sort_by_key(|session| session.started_ms);
+        sessions
+    }
+
+    /// Returns the records of the last sessions of every server that keeps
+    /// them, newest first
+    fn recent(&self, params: &[(String, String)]) -> Result<AdminResponse, AdminResponse> {
+        let mut query = RecentQuery::new();
+        if let Some(last) = param(params, "last")? {
+            query = query.with_last(last);
+        }
+        if let Some(target) = param::<TargetPattern>(params, "target")? {
+            query = query.with_target(target);
+        }
+        if let Some(since) = param(params, "since")? {
+            query = query.with_since(since);
+        }
+        let mut records: Vec<_> = self
+            .servers
+            .iter()
+            .filter_map(|server| server.recent_sessions())
+            .flat_map(|recent| recent.query(&query))
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.started_ms));
+        records.truncate(query.last.unwrap_or(usize::MAX));
+        Ok(AdminResponse::ok(records.into_iter().map(RecentSession::from).collect::<Vec<_>>()))
+    }
+
+    /// Returns the client IPs with a penalty score of at least `min`,
+    /// worst first, from every server that tracks them
+    fn reputation(&self, params: &[(String, String)]) -> Result<AdminResponse, AdminResponse> {
+        let min = param(params, "min")?.unwrap_or(0.0);
+        // Servers of one group may share a reputation; each IP is listed once
+        let mut worst: BTreeMap<IpAddr, f64> = BTreeMap::new();
+        for (ip, score) in self.servers.iter().filter_map(|server| server.reputation()).flat_map(|r| r.scores(min)) {
+            let entry = worst.entry(ip).or_insert(score);
+            *entry = entry.max(score);
+        }
+        let mut scores: Vec<_> = worst.into_iter().map(|(ip, score)| ClientScore { ip, score }).collect();
+        scores.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.ip.cmp(&b.ip)));
+        Ok(AdminResponse::ok(scores))
+    }
+
+    /// Returns the log level, first changing it to the `level` parameter if
+    /// `params` are given
+    fn log_level(&self, params: Option<&[(String, String)]>) -> Result<AdminResponse, AdminResponse> {
+        let control = self
+            .log_level
+            .ok_or_else(|| AdminResponse::error(404, "the log level is not exposed"))?;
+        if let Some(params) = params {
+            let level: LevelFilter =
+                param(params, "level")?.ok_or_else(|| AdminResponse::error(400, "missing level parameter"))?;
+            log::info!("Changing the log level on admin request");
+            control.set(level);
+        }
+        Ok(AdminResponse::ok(LogLevel {
+            level: control.current().to_string().to_lowercase(),
+            base: control.base().to_string().to_lowercase(),
+        }))
+    }
+
+    /// Terminates the session with the hex connection ID `id`
+    fn terminate(&self, id: &str) -> AdminResponse {
+        let Ok(conn_id) = u32::from_str_radix(id, 16) else {
+            return AdminResponse::error(400, format!("invalid connection ID: {}", id));
+        };
+        if self.servers.iter().any(|server| server.terminate_session(conn_id)) {
+            log::info!("Terminating conn {:08x} on admin request", conn_id);
+            AdminResponse::ok(json!({ "terminated": format!("{:08x}", conn_id) }))
+        } else {
+            AdminResponse::error(404, format!("no active session {:08x}", conn_id))
+        }
+    }
+
+    /// Serves admin requests on `listener` until it fails
+    ///
+    /// Once [`MAX_CONNECTIONS`] are being served, further connections wait
+    /// in the listen backlog.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Socks5Result<()> {
+        let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let slot = Arc::clone(&slots).acquire_owned().await.expect("the semaphore is never closed");
+            let (mut stream, peer_addr) = listener.accept().await?;
+            let api = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = api.serve_connection(&mut stream).await {
+                    log::debug!("Admin request from {} failed: {}", peer_addr, e);
+                }
+                drop(slot);
+            });
+        }
+    }
+
+    /// Reads one request from `stream` and sends the response
+    async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> Socks5Result<()> {
+        let head = tokio::time::timeout(READ_TIMEOUT, read_head(stream))
+            .await
+            .map_err(|_| Socks5Error::HandshakeError(format!("No admin request within {:?}", READ_TIMEOUT)))??;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+            return Err(Socks5Error::HandshakeError("Malformed admin request line".to_string()));
+        };
+        let authorization = lines
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.trim());
+        let response = self.handle(method, path, authorization);
+        let body = format!("{}\n", response.body);
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.reason(),
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        close_gracefully(stream).await;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for AdminApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminApi")
+            .field("servers", &self.servers.len())
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Answers admin requests on a TLS port negotiating [`ALPN_HTTP1`](crate::tls::ALPN_HTTP1)
+impl AlpnHandler for AdminApi {
+    fn serve(self: Arc<Self>, mut stream: TlsStream<TcpStream>, client: SocketAddr) -> AlpnFuture {
+        Box::pin(async move {
+            if let Err(e) = self.serve_connection(&mut stream).await {
+                log::debug!("Admin request from {} failed: {}", client, e);
+            }
+        })
+    }
+}
+
+/// Splits a query string into decoded `name=value` pairs
+///
+/// # Returns
+/// * `None` - If a name or value is not validly percent-encoded
+fn query_params(query: &str) -> Option<Vec<(String, String)>> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| percent_decode(&s.replace('+', " "));
+            Some((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// Parses the query parameter `name`, if given
+///
+/// # Returns
+/// * `Err(AdminResponse)` - A 400 response if the value does not parse
+fn param<T: std::str::FromStr>(params: &[(String, String)], name: &str) -> Result<Option<T>, AdminResponse> {
+    let Some((_, value)) = params.iter().find(|(key, _)| key == name) else {
+        return Ok(None);
+    };
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| AdminResponse::error(400, format!("invalid {} parameter: {}", name, value)))
+}
+
+/// Reads a request head up to and including the empty line
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> Socks5Result<String> {
+    let mut buf = Vec::with_capacity(1024);
+    loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            buf.truncate(end);
+            return String::from_utf8(buf)
+                .map_err(|_| Socks5Error::HandshakeError("Admin request head is not valid UTF-8".to_string()));
+        }
+        if buf.len() >= MAX_HEAD_SIZE {
+            return Err(Socks5Error::HandshakeError("Admin request head is too large".to_string()));
+        }
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(Socks5Error::HandshakeError("Admin request head is truncated".to_string()));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
@@ -0,0 +1,319 @@
+//! Audit files with built-in rotation for the SOCKS5 proxy.
+//!
+//! Every accepted request can be appended as a JSON line to an audit file.
+//! The file is rotated by size and/or age: `audit.log` becomes
+//! `audit.log.1` (or `audit.log.1.gz` when compression is on), older files
+//! shift up by one and only the configured number of rotated files is kept,
+//! so a long-running proxy cannot fill the disk.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::bandwidth::parse_bytes;
+use crate::clock::Clock;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::mirror::RequestEvent;
+
+/// How many records may wait for the writer thread before new ones are dropped
+const QUEUE_CAPACITY: usize = 4096;
+
+/// When audit files are rotated and how many rotated files are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Rotate before a record would grow the file beyond this many bytes
+    pub max_bytes: Option<u64>,
+    /// Rotate once the current file has been written to for this long
+    pub max_age: Option<Duration>,
+    /// How many rotated files to keep; older ones are deleted
+    pub keep: usize,
+    /// Whether rotated files are compressed with gzip
+    pub compress: bool,
+}
+
+impl RotationPolicy {
+    /// Creates a policy that never rotates and keeps seven rotated files
+    pub fn new() -> Self {
+        Self {
+            max_bytes: None,
+            max_age: None,
+            keep: 7,
+            compress: false,
+        }
+    }
+
+    /// Rotates before the file would grow beyond `max_bytes`
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes.max(1));
+        self
+    }
+
+    /// Rotates once the current file is `max_age` old
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Keeps `keep` rotated files
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Compresses rotated files with gzip
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses a file size with an optional binary suffix, e.g. `100M`
+pub fn parse_size(value: &str) -> Socks5Result<u64> {
+    parse_bytes(value).ok_or_else(|| Socks5Error::ConfigError(format!("Invalid size: {}", value)))
+}
+
+/// An append-only audit file that rotates itself
+///
+/// All methods do blocking file I/O; [`AuditWriter`] runs them on a
+/// dedicated thread.
+#[derive(Debug)]
+pub struct AuditFile {
+    /// Path of the current file
+    path: PathBuf,
+    /// When to rotate and what to keep
+    policy: RotationPolicy,
+    /// Time source for age-based rotation
+    clock: Arc<dyn Clock>,
+    /// The current file
+    file: BufWriter<File>,
+    /// Size of the current file in bytes
+    written: u64,
+    /// When the current file was opened
+    opened_at: Instant,
+}
+
+impl AuditFile {
+    /// Opens `path` for appending, creating it if needed
+    ///
+    /// # Arguments
+    /// * `path` - The audit file; rotated files are created next to it
+    /// * `policy` - When to rotate and what to keep
+    /// * `clock` - Time source for age-based rotation
+    pub fn open(path: impl Into<PathBuf>, policy: RotationPolicy, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        let path = path.into();
+        let (file, written) = open_append(&path)?;
+        let opened_at = clock.now();
+        Ok(Self { path, policy, clock, file, written, opened_at })
+    }
+
+    /// Returns the path of the current file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the `index`th rotated file (1 is the newest)
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let suffix = if self.policy.compress { ".gz" } else { "" };
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}{}", index, suffix));
+        PathBuf::from(name)
+    }
+
+    /// Appends one record, rotating first if the policy calls for it
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        let too_big = self.policy.max_bytes
+            .is_some_and(|max| self.written > 0 && self.written + record.len() as u64 > max);
+        let too_old = self.policy.max_age
+            .is_some_and(|max| self.clock.now().duration_since(self.opened_at) >= max);
+        if too_big || too_old {
+            self.rotate()?;
+        }
+        self.file.write_all(record)?;
+        self.written += record.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes buffered records to the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Moves the current file aside and starts a new one
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.policy.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            remove_if_exists(&self.rotated_path(self.policy.keep))?;
+            for index in (1..self.policy.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            if self.policy.compress {
+                compress(&self.path, &self.rotated_path(1))?;
+                fs::remove_file(&self.path)?;
+            } else {
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+        }
+        let (file, written) = open_append(&self.path)?;
+        self.file = file;
+        self.written = written;
+        self.opened_at = self.clock.now();
+        Ok(())
+    }
+}
+
+/// Opens a file for appending and returns it with its current size
+fn open_append(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    Ok((BufWriter::new(file), written))
+}
+
+/// Removes a file, ignoring that it does not exist
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Writes a gzip-compressed copy of `from` to `to`
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(to)?), Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()
+}
+
+/// Appends request records to a rotating audit file from a background thread
+///
+/// Publishing never blocks a connection: records wait in a bounded queue
+/// and are dropped and counted when the writer falls behind.
+#[derive(Debug)]
+pub struct AuditWriter {
+    /// Hands records to the writer thread
+    queue: Option<SyncSender<Vec<u8>>>,
+    /// The writer thread
+    thread: Option<JoinHandle<()>>,
+    /// The path of the audit file
+    destination: String,
+    /// Records that could not be queued or written
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditWriter {
+    /// Opens the audit file and starts the writer thread
+    ///
+    /// # Arguments
+    /// * `path` - The audit file
+    /// * `policy` - When to rotate and what to keep
+    /// * `clock` - Time source for age-based rotation
+    pub fn open(path: impl Into<PathBuf>, policy: RotationPolicy, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        let file = AuditFile::open(path, policy, clock)?;
+        let destination = file.path().display().to_string();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (queue, records) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let thread = thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn({
+                let dropped = Arc::clone(&dropped);
+                move || write_records(file, records, &dropped)
+            })?;
+        Ok(Self {
+            queue: Some(queue),
+            thread: Some(thread),
+            destination,
+            dropped,
+        })
+    }
+
+    /// Returns the path of the audit file
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Returns how many records could not be queued or written
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queues the record of a request without waiting
+    pub fn publish(&self, event: &RequestEvent) {
+        let mut record = match serde_json::to_vec(event) {
+            Ok(record) => record,
+            Err(e) => {
+                log::debug!("Cannot encode audit record: {}", e);
+                return;
+            }
+        };
+        record.push(b'\n');
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        if let Err(e) = queue.try_send(record) {
+            if matches!(e, TrySendError::Full(_)) {
+                log::debug!("Audit writer for {} is behind, dropping a record", self.destination);
+            }
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes all queued records and stops the writer thread
+    pub fn close(mut self) {
+        self.stop();
+    }
+
+    /// Closes the queue and waits for the writer thread to finish
+    fn stop(&mut self) {
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for AuditWriter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Writes queued records until the queue is closed
+fn write_records(mut file: AuditFile, records: Receiver<Vec<u8>>, dropped: &AtomicU64) {
+    while let Ok(record) = records.recv() {
+        write_record(&mut file, &record, dropped);
+        // Write whatever else is queued, then flush once the queue runs dry
+        while let Ok(record) = records.try_recv() {
+            write_record(&mut file, &record, dropped);
+        }
+        if let Err(e) = file.flush() {
+            log::warn!("Cannot flush audit file {}: {}", file.path().display(), e);
+        }
+    }
+}
+
+/// Writes one record, counting it as dropped if that fails
+fn write_record(file: &mut AuditFile, record: &[u8], dropped: &AtomicU64) {
+    if let Err(e) = file.write_record(record) {
+        log::warn!("Cannot write audit record to {}: {}", file.path().display(), e);
+        dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
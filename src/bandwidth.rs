@@ -0,0 +1,550 @@
+//! Bandwidth limits for relayed traffic.
+//!
+//! Limits are token buckets: a rate that caps sustained throughput plus a
+//! burst allowance. Credit accrues while a connection is idle, up to the
+//! burst, so interactive sessions get their short exchanges through at full
+//! speed while bulk transfers settle at the configured rate.
+//!
+//! Limits apply per class: one global limit shared by every connection,
+//! per-user limits shared by all of a user's connections, a per-connection
+//! cap that users can have overridden, and per-rule limits applied to each
+//! connection whose target matches the rule.
+//!
+//! The global limit is divided fairly: a [`FairQueue`] gives every active
+//! connection its own share of the rate, so one bulk transfer cannot build
+//! up a debt that every other connection has to wait behind. Connections
+//! are weighted by their [`Priority`] class when dividing it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::error::Socks5Error;
+use crate::protocol::TargetAddr;
+use crate::relay::Throttle;
+use crate::routing::TargetPattern;
+
+/// A sustained rate and a burst allowance, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Sustained throughput in bytes per second
+    pub rate: u64,
+    /// Bytes that may be sent at once after the connection was idle
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// Creates a limit of `rate` bytes per second with a `burst` allowance
+    ///
+    /// A zero rate is treated as one byte per second, and the burst is at
+    /// least one second's worth of the rate.
+    pub fn new(rate: u64, burst: u64) -> Self {
+        let rate = rate.max(1);
+        Self { rate, burst: burst.max(rate) }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = Socks5Error;
+
+    /// Parses `RATE` or `RATE/BURST`, each a byte count with an optional
+    /// `K`, `M` or `G` (binary) suffix, e.g. `1M/4M`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Socks5Error::ConfigError(format!("Invalid rate limit: {}", s));
+        let (rate, burst) = match s.split_once('/') {
+            Some((rate, burst)) => (parse_bytes(rate).ok_or_else(invalid)?, Some(parse_bytes(burst).ok_or_else(invalid)?)),
+            None => (parse_bytes(s).ok_or_else(invalid)?, None),
+        };
+        Ok(RateLimit::new(rate, burst.unwrap_or(rate)))
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.rate, self.burst)
+    }
+}
+
+/// Parses a byte count with an optional binary suffix (`K`, `M` or `G`)
+pub(crate) fn parse_bytes(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.as_bytes().last().map(u8::to_ascii_uppercase) {
+        Some(b'K') => (&value[..value.len() - 1], 1 << 10),
+        Some(b'M') => (&value[..value.len() - 1], 1 << 20),
+        Some(b'G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier))
+}
+
+/// A token bucket enforcing one [`RateLimit`]
+///
+/// The bucket starts full. Forwarding more than the available credit
+/// borrows against future refills and is delayed until they arrive.
+#[derive(Debug)]
+pub struct TokenBucket {
+    /// The rate and burst enforced
+    limit: RateLimit,
+    /// The time source for refills
+    clock: Arc<dyn Clock>,
+    /// Available credit in bytes (negative when borrowed) and when it was computed
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    pub fn new(limit: RateLimit, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        Self {
+            limit,
+            clock,
+            state: Mutex::new((limit.burst as f64, now)),
+        }
+    }
+
+    /// Returns the enforced limit
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Returns the credit currently available, in bytes
+    pub fn available(&self) -> f64 {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.refill(&mut state);
+        state.0
+    }
+
+    /// Takes `amount` of credit if that much is available, without borrowing
+    ///
+    /// # Returns
+    /// * `true` - If the credit was taken
+    /// * `false` - If less is available; nothing is taken
+    pub fn try_take(&self, amount: u64) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.refill(&mut state);
+        if state.0 < amount as f64 {
+            return false;
+        }
+        state.0 -= amount as f64;
+        true
+    }
+
+    /// Adds the credit accrued since the last update, up to the burst
+    fn refill(&self, state: &mut (f64, Instant)) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_duration_since(state.1).as_secs_f64();
+        state.0 = (state.0 + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        state.1 = now;
+    }
+}
+
+impl Throttle for TokenBucket {
+    fn delay_for(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.refill(&mut state);
+        state.0 -= bytes as f64;
+        if state.0 >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.0 / self.limit.rate as f64)
+        }
+    }
+}
+
+/// Class of service of a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// Interactive traffic, served ahead of everything else
+    High,
+    /// The default class
+    #[default]
+    Normal,
+    /// Background transfers that yield to the other classes
+    Bulk,
+}
+
+impl Priority {
+    /// All classes, in counter order
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Bulk];
+
+    /// Returns a short, stable name for the class
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Bulk => "bulk",
+        }
+    }
+
+    /// Returns the class's weight in a [`FairQueue`]
+    ///
+    /// Under contention a high-priority connection gets four times the
+    /// share of a normal one, and a bulk connection a quarter of it.
+    pub fn weight(&self) -> u32 {
+        match self {
+            Priority::High => 16,
+            Priority::Normal => 4,
+            Priority::Bulk => 1,
+        }
+    }
+
+    /// Returns the class's slot in counters
+    pub(crate) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Priority {
+    type Err = Socks5Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.as_str() == s)
+            .ok_or_else(|| Socks5Error::ConfigError(format!("Unknown priority class: {}", s)))
+    }
+}
+
+/// How recently a flow must have sent to count towards the fair division
+pub const ACTIVE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A rate limit divided fairly between the connections sharing it
+///
+/// Each connection is a flow with a weight. Whenever a flow sends, the
+/// rate is split between the flows active within [`ACTIVE_WINDOW`] in
+/// proportion to their weights, and the flow is delayed according to its
+/// own share only. Idle flows do not count, so a lone transfer still gets
+/// the whole rate, and credit accrues per flow as in a [`TokenBucket`].
+#[derive(Debug)]
+pub struct FairQueue {
+    /// The total rate and burst divided between the flows
+    limit: RateLimit,
+    /// The time source for refills and activity
+    clock: Arc<dyn Clock>,
+    /// The registered flows by ID, and the next ID to hand out
+    state: Mutex<(HashMap<u64, FlowState>, u64)>,
+}
+
+/// Per-flow accounting inside a [`FairQueue`]
+#[derive(Debug)]
+struct FlowState {
+    /// The flow's relative share of the rate
+    weight: u32,
+    /// Available credit in bytes (negative when borrowed)
+    tokens: f64,
+    /// When the credit was last updated
+    updated: Instant,
+    /// When the flow last sent, if it has
+    last_active: Option<Instant>,
+}
+
+impl FairQueue {
+    /// Creates a queue dividing `limit` between its flows
+    pub fn new(limit: RateLimit, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limit,
+            clock,
+            state: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    /// Returns the total limit
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Registers a flow with the given weight (at least 1)
+    ///
+    /// The flow leaves the queue when the returned handle is dropped.
+    pub fn flow(self: &Arc<Self>, weight: u32) -> FairFlow {
+        let weight = weight.max(1);
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let id = state.1;
+        state.1 += 1;
+        let share = self.share(&state.0, now, id, weight);
+        state.0.insert(id, FlowState {
+            weight,
+            tokens: self.limit.burst as f64 * share,
+            updated: now,
+            last_active: None,
+        });
+        FairFlow {
+            queue: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// Returns the number of registered flows
+    pub fn flows(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).0.len()
+    }
+
+    /// Returns the fraction of the limit that flow `id` with `weight` gets
+    /// while sending alongside the other active flows
+    fn share(&self, flows: &HashMap<u64, FlowState>, now: Instant, id: u64, weight: u32) -> f64 {
+        let others: u64 = flows
+            .iter()
+            .filter(|(other, _)| **other != id)
+            .filter(|(_, flow)| flow.last_active.is_some_and(|at| now.saturating_duration_since(at) < ACTIVE_WINDOW))
+            .map(|(_, flow)| u64::from(flow.weight))
+            .sum();
+        f64::from(weight) / (others + u64::from(weight)) as f64
+    }
+
+    /// Accounts for `bytes` sent by flow `id` and returns its delay
+    fn delay_for(&self, id: u64, bytes: usize) -> Duration {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let flows = &mut state.0;
+        let Some(weight) = flows.get(&id).map(|flow| flow.weight) else {
+            return Duration::ZERO;
+        };
+        let share = self.share(flows, now, id, weight);
+        let rate = self.limit.rate as f64 * share;
+        let burst = self.limit.burst as f64 * share;
+
+        let flow = flows.get_mut(&id).expect("flow looked up above");
+        let elapsed = now.saturating_duration_since(flow.updated).as_secs_f64();
+        flow.tokens = (flow.tokens + elapsed * rate).min(burst) - bytes as f64;
+        flow.updated = now;
+        flow.last_active = Some(now);
+        if flow.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-flow.tokens / rate)
+        }
+    }
+}
+
+/// One connection's handle on a [`FairQueue`]
+#[derive(Debug)]
+pub struct FairFlow {
+    /// The queue the flow belongs to
+    queue: Arc<FairQueue>,
+    /// The flow's ID inside the queue
+    id: u64,
+}
+
+impl Throttle for FairFlow {
+    fn delay_for(&self, bytes: usize) -> Duration {
+        self.queue.delay_for(self.id, bytes)
+    }
+}
+
+impl Drop for FairFlow {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.0.remove(&self.id);
+    }
+}
+
+/// Several throttles applied together; the slowest one decides
+#[derive(Debug)]
+pub struct ThrottleChain(Vec<Arc<dyn Throttle>>);
+
+impl Throttle for ThrottleChain {
+    fn delay_for(&self, bytes: usize) -> Duration {
+        self.0.iter().map(|throttle| throttle.delay_for(bytes)).max().unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Upload and download buckets for one limit
+#[derive(Debug, Clone)]
+struct BucketPair {
+    /// Bytes from the client to the target
+    upload: Arc<TokenBucket>,
+    /// Bytes from the target to the client
+    download: Arc<TokenBucket>,
+}
+
+impl BucketPair {
+    fn new(limit: RateLimit, clock: &Arc<dyn Clock>) -> Self {
+        Self {
+            upload: Arc::new(TokenBucket::new(limit, Arc::clone(clock))),
+            download: Arc::new(TokenBucket::new(limit, Arc::clone(clock))),
+        }
+    }
+}
+
+/// The throttles for one relayed connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionThrottles {
+    /// The connection's class of service
+    pub priority: Priority,
+    /// Throttle for bytes from the client to the target
+    pub upload: Option<Arc<dyn Throttle>>,
+    /// Throttle for bytes from the target to the client
+    pub download: Option<Arc<dyn Throttle>>,
+}
+
+/// Bandwidth limits per class: global, per user and per rule
+///
+/// Each limit applies separately to each direction.
+#[derive(Debug)]
+pub struct BandwidthPolicy {
+    /// The time source for all buckets
+    clock: Arc<dyn Clock>,
+    /// Upload and download queues dividing the global limit between connections
+    global: Option<(Arc<FairQueue>, Arc<FairQueue>)>,
+    /// Limits for the connections of each user
+    users: HashMap<String, RateLimit>,
+    /// Buckets shared by each user's connections, created on first use
+    user_buckets: Mutex<HashMap<String, BucketPair>>,
+    /// Limit for each connection on its own, unless its user has an override
+    connection: Option<RateLimit>,
+    /// Per-connection limits overriding `connection` for each user's connections
+    user_connections: HashMap<String, RateLimit>,
+    /// Limits for each connection to a matching target; first match wins
+    rules: Vec<(TargetPattern, RateLimit)>,
+    /// Class of service of each user's connections
+    user_priorities: HashMap<String, Priority>,
+    /// Class of service of connections to matching targets; first match wins
+    rule_priorities: Vec<(TargetPattern, Priority)>,
+}
+
+impl BandwidthPolicy {
+    /// Creates a policy without limits
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            global: None,
+            users: HashMap::new(),
+            user_buckets: Mutex::new(HashMap::new()),
+            connection: None,
+            user_connections: HashMap::new(),
+            rules: Vec::new(),
+            user_priorities: HashMap::new(),
+            rule_priorities: Vec::new(),
+        }
+    }
+
+    /// Limits the total throughput of all connections, shared fairly
+    pub fn with_global(mut self, limit: RateLimit) -> Self {
+        self.global = Some((
+            Arc::new(FairQueue::new(limit, Arc::clone(&self.clock))),
+            Arc::new(FairQueue::new(limit, Arc::clone(&self.clock))),
+        ));
+        self
+    }
+
+    /// Limits the total throughput of one user's connections
+    pub fn with_user(mut self, username: impl Into<String>, limit: RateLimit) -> Self {
+        self.users.insert(username.into(), limit);
+        self
+    }
+
+    /// Limits the throughput of each connection on its own
+    pub fn with_connection_limit(mut self, limit: RateLimit) -> Self {
+        self.connection = Some(limit);
+        self
+    }
+
+    /// Limits each of one user's connections on its own, instead of the
+    /// per-connection limit
+    pub fn with_user_connection_limit(mut self, username: impl Into<String>, limit: RateLimit) -> Self {
+        self.user_connections.insert(username.into(), limit);
+        self
+    }
+
+    /// Limits each connection to targets matching `pattern`
+    pub fn with_rule(mut self, pattern: TargetPattern, limit: RateLimit) -> Self {
+        self.rules.push((pattern, limit));
+        self
+    }
+
+    /// Assigns a class of service to one user's connections
+    pub fn with_user_priority(mut self, username: impl Into<String>, priority: Priority) -> Self {
+        self.user_priorities.insert(username.into(), priority);
+        self
+    }
+
+    /// Assigns a class of service to connections to targets matching `pattern`
+    pub fn with_rule_priority(mut self, pattern: TargetPattern, priority: Priority) -> Self {
+        self.rule_priorities.push((pattern, priority));
+        self
+    }
+
+    /// Returns whether any limit or class is configured
+    pub fn is_empty(&self) -> bool {
+        self.global.is_none()
+            && self.users.is_empty()
+            && self.connection.is_none()
+            && self.user_connections.is_empty()
+            && self.rules.is_empty()
+            && self.user_priorities.is_empty()
+            && self.rule_priorities.is_empty()
+    }
+
+    /// Returns the class of service of a connection
+    ///
+    /// A matching rule takes precedence over the user's class; connections
+    /// matching neither are [`Priority::Normal`].
+    pub fn priority_for(&self, username: Option<&str>, target: &TargetAddr) -> Priority {
+        self.rule_priorities
+            .iter()
+            .find(|(pattern, _)| pattern.matches(target))
+            .map(|(_, priority)| *priority)
+            .or_else(|| username.and_then(|name| self.user_priorities.get(name).copied()))
+            .unwrap_or_default()
+    }
+
+    /// Returns the throttles and class of service for a new connection
+    ///
+    /// # Arguments
+    /// * `username` - The authenticated user, if any
+    /// * `target` - The requested target
+    pub fn throttles_for(&self, username: Option<&str>, target: &TargetAddr) -> ConnectionThrottles {
+        let mut upload: Vec<Arc<dyn Throttle>> = Vec::new();
+        let mut download: Vec<Arc<dyn Throttle>> = Vec::new();
+        let mut pairs = Vec::new();
+        let priority = self.priority_for(username, target);
+        if let Some((global_upload, global_download)) = &self.global {
+            upload.push(Arc::new(global_upload.flow(priority.weight())));
+            download.push(Arc::new(global_download.flow(priority.weight())));
+        }
+        if let Some((username, limit)) = username.and_then(|name| self.users.get_key_value(name)) {
+            let mut user_buckets = self.user_buckets.lock().unwrap_or_else(|e| e.into_inner());
+            let pair = user_buckets
+                .entry(username.clone())
+                .or_insert_with(|| BucketPair::new(*limit, &self.clock));
+            pairs.push(pair.clone());
+        }
+        let connection_limit = username
+            .and_then(|name| self.user_connections.get(name))
+            .or(self.connection.as_ref());
+        if let Some(limit) = connection_limit {
+            pairs.push(BucketPair::new(*limit, &self.clock));
+        }
+        if let Some((_, limit)) = self.rules.iter().find(|(pattern, _)| pattern.matches(target)) {
+            pairs.push(BucketPair::new(*limit, &self.clock));
+        }
+
+        for pair in pairs {
+            upload.push(pair.upload);
+            download.push(pair.download);
+        }
+
+        let chain = |mut throttles: Vec<Arc<dyn Throttle>>| -> Option<Arc<dyn Throttle>> {
+            match throttles.len() {
+                0 => None,
+                1 => throttles.pop(),
+                _ => Some(Arc::new(ThrottleChain(throttles))),
+            }
+        };
+        ConnectionThrottles {
+            priority,
+            upload: chain(upload),
+            download: chain(download),
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! Domain blocklists for the SOCKS5 proxy.
+//!
+//! Running the proxy as a filtering proxy for a home network means blocking
+//! the same ad, tracker and malware domains DNS sinkholes block. Those lists
+//! are published as hosts files (`0.0.0.0 ads.example.com`) or as
+//! adblock-style filter lists (`||ads.example.com^`), and [`Blocklist`]
+//! imports both without any manual rule conversion. Hosts entries block
+//! exactly the named host; adblock rules block the domain and all its
+//! subdomains. Rules that only make sense in a browser, such as exceptions,
+//! cosmetic filters and path or resource-type filters, are skipped.
+//!
+//! [`Blocklists`] is the deny group a server checks: the merged lists of
+//! several files and URLs, refreshed on an interval. A list that cannot be
+//! fetched keeps its previous entries.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::canonical::canonicalize;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::protocol::TargetAddr;
+use crate::remote::{self, ConfigUrl};
+
+/// How often lists are refreshed by default
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The largest list accepted from a URL, head included
+const MAX_LIST_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Host names hosts files map to themselves rather than block
+const LOCAL_NAMES: [&str; 8] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-allnodes",
+];
+
+/// The syntax a blocklist is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlocklistFormat {
+    /// Hosts file lines such as `0.0.0.0 ads.example.com`, or one host per line
+    Hosts,
+    /// Adblock-style filter rules such as `||ads.example.com^`
+    Adblock,
+}
+
+impl BlocklistFormat {
+    /// Returns the name of the format, `hosts` or `adblock`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlocklistFormat::Hosts => "hosts",
+            BlocklistFormat::Adblock => "adblock",
+        }
+    }
+}
+
+impl FromStr for BlocklistFormat {
+    type Err = Socks5Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hosts" => Ok(BlocklistFormat::Hosts),
+            "adblock" => Ok(BlocklistFormat::Adblock),
+            _ => Err(Socks5Error::ConfigError(format!("Unknown blocklist format (expected hosts or adblock): {}", s))),
+        }
+    }
+}
+
+/// Blocked domains imported from lists
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    /// Hosts blocked exactly
+    hosts: HashSet<String>,
+    /// Domains blocked together with their subdomains
+    domains: HashSet<String>,
+    /// Rules that were not understood or cannot apply to a proxy
+    skipped: usize,
+}
+
+impl Blocklist {
+    /// Creates an empty list, which blocks nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Imports a list written in `format`
+    ///
+    /// Lines that cannot be imported are counted in [`Blocklist::skipped`]
+    /// instead of failing the whole list, as published lists routinely mix
+    /// in rules for other tools.
+    pub fn parse(text: &str, format: BlocklistFormat) -> Self {
+        let mut list = Self::new();
+        for line in text.lines() {
+            match format {
+                BlocklistFormat::Hosts => list.push_hosts_line(line),
+                BlocklistFormat::Adblock => list.push_adblock_line(line),
+            }
+        }
+        list
+    }
+
+    /// Blocks exactly `host`
+    pub fn block_host(&mut self, host: &str) {
+        self.hosts.insert(canonicalize(host).into_owned());
+    }
+
+    /// Blocks `domain` and all its subdomains
+    pub fn block_domain(&mut self, domain: &str) {
+        self.domains.insert(canonicalize(domain).into_owned());
+    }
+
+    /// Adds every entry of `other`
+    pub fn extend(&mut self, other: &Blocklist) {
+        self.hosts.extend(other.hosts.iter().cloned());
+        self.domains.extend(other.domains.iter().cloned());
+        self.skipped += other.skipped;
+    }
+
+    /// Returns the number of blocked hosts and domains
+    pub fn len(&self) -> usize {
+        self.hosts.len() + self.domains.len()
+    }
+
+    /// Returns whether nothing is blocked
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.domains.is_empty()
+    }
+
+    /// Returns how many rules were skipped while importing
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Returns whether the target is blocked
+    ///
+    /// Only domain targets can be blocked; the lists name hosts, which the
+    /// proxy does not resolve to compare with IP literal targets.
+    pub fn is_blocked(&self, target: &TargetAddr) -> bool {
+        let TargetAddr::Domain(domain, _) = target else {
+            return false;
+        };
+        let domain = canonicalize(domain);
+        if self.hosts.contains(domain.as_ref()) {
+            return true;
+        }
+        let mut suffix = domain.as_ref();
+        loop {
+            if self.domains.contains(suffix) {
+                return true;
+            }
+            match suffix.split_once('.') {
+                Some((_, parent)) => suffix = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Imports one hosts file line
+    fn push_hosts_line(&mut self, line: &str) {
+        let line = line.split_once('#').map_or(line, |(entry, _)| entry).trim();
+        let mut fields = line.split_whitespace();
+        let Some(first) = fields.next() else {
+            return;
+        };
+        if first.parse::<IpAddr>().is_err() {
+            // A bare list of hosts, one per line
+            match fields.next() {
+                None if is_host(first) => self.block_host(first),
+                _ => self.skipped += 1,
+            }
+            return;
+        }
+        for host in fields {
+            if LOCAL_NAMES.contains(&host.to_ascii_lowercase().as_str()) || host.parse::<IpAddr>().is_ok() {
+                continue;
+            }
+            if is_host(host) {
+                self.block_host(host);
+            } else {
+                self.skipped += 1;
+            }
+        }
+    }
+
+    /// Imports one adblock filter line
+    ///
+    /// Only `||domain^` rules are imported, optionally with the `important`
+    /// or `all` options, which do not narrow what they block.
+    fn push_adblock_line(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+            return;
+        }
+        let (rule, options) = line.split_once('$').unwrap_or((line, ""));
+        let unrestricted = options
+            .split(',')
+            .filter(|option| !option.is_empty())
+            .all(|option| matches!(option, "important" | "all"));
+        let domain = rule.strip_prefix("||").and_then(|rule| rule.strip_suffix('^'));
+        match domain {
+            Some(domain) if unrestricted && is_host(domain) => self.block_domain(domain),
+            _ => self.skipped += 1,
+        }
+    }
+}
+
+/// Where a list is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlocklistLocation {
+    /// A local file, re-read on every refresh
+    File(PathBuf),
+    /// An `http://` or `https://` URL
+    Url(ConfigUrl),
+}
+
+/// A list to import, e.g. `adblock:https://example.com/filters.txt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocklistSource {
+    /// The syntax the list is written in
+    pub format: BlocklistFormat,
+    /// Where the list is read from
+    pub location: BlocklistLocation,
+    /// The text the source was parsed from, for logs
+    spec: String,
+}
+
+impl FromStr for BlocklistSource {
+    type Err = Socks5Error;
+
+    /// Parses `FORMAT:PATH` or `FORMAT:URL`, e.g. `hosts:/etc/blocked.hosts`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, location) = s
+            .split_once(':')
+            .ok_or_else(|| Socks5Error::ConfigError(format!("Expected FORMAT:PATH or FORMAT:URL: {}", s)))?;
+        let location = if location.starts_with("http://") || location.starts_with("https://") {
+            BlocklistLocation::Url(ConfigUrl::parse(location)?)
+        } else {
+            BlocklistLocation::File(PathBuf::from(location))
+        };
+        Ok(Self {
+            format: format.parse()?,
+            location,
+            spec: s.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for BlocklistSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// What was last imported from one source
+#[derive(Debug, Default)]
+struct Imported {
+    /// The entries of the last copy read
+    list: Arc<Blocklist>,
+    /// The entity tag of the last copy fetched from a URL
+    etag: Option<String>,
+}
+
+/// A deny group of domains merged from several lists
+#[derive(Debug)]
+pub struct Blocklists {
+    /// The lists to import
+    sources: Vec<BlocklistSource>,
+    /// How often the lists are refreshed
+    refresh: Duration,
+    /// Trust anchors for `https://` URLs
+    tls: Arc<ClientConfig>,
+    /// What was last imported from each source, in source order
+    imported: Mutex<Vec<Imported>>,
+    /// The merged entries of all sources
+    current: RwLock<Arc<Blocklist>>,
+}
+
+impl Blocklists {
+    /// Creates a group of the given lists, refreshed daily; nothing is
+    /// blocked until the first [`Blocklists::refresh`]
+    pub fn new(sources: Vec<BlocklistSource>) -> Self {
+        let imported = sources.iter().map(|_| Imported::default()).collect();
+        Self {
+            sources,
+            refresh: DEFAULT_REFRESH,
+            tls: remote::web_pki_client_config(),
+            imported: Mutex::new(imported),
+            current: RwLock::new(Arc::new(Blocklist::new())),
+        }
+    }
+
+    /// Sets how often the lists are refreshed; at least once a minute
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh.max(Duration::from_secs(60));
+        self
+    }
+
+    /// Returns the lists that are imported
+    pub fn sources(&self) -> &[BlocklistSource] {
+        &self.sources
+    }
+
+    /// Returns how often the lists are refreshed
+    pub fn refresh_interval(&self) -> Duration {
+        self.refresh
+    }
+
+    /// Returns the merged entries of all lists
+    pub fn current(&self) -> Arc<Blocklist> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Returns whether the target is on any list
+    pub fn is_blocked(&self, target: &TargetAddr) -> bool {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).is_blocked(target)
+    }
+
+    /// Re-reads every list and replaces the merged entries
+    ///
+    /// A list that cannot be read keeps the entries of its last good copy.
+    ///
+    /// # Returns
+    /// * The failures, one per list that could not be read
+    pub async fn refresh(&self) -> Vec<Socks5Error> {
+        let mut failures = Vec::new();
+        for (index, source) in self.sources.iter().enumerate() {
+            let etag = self.imported.lock().unwrap_or_else(|e| e.into_inner())[index].etag.clone();
+            match self.read(source, etag.as_deref()).await {
+                Ok(Some((list, etag))) => {
+                    log::debug!("Imported {} entries from blocklist {} ({} rules skipped)", list.len(), source, list.skipped());
+                    self.imported.lock().unwrap_or_else(|e| e.into_inner())[index] = Imported { list: Arc::new(list), etag };
+                }
+                Ok(None) => log::debug!("Blocklist {} unchanged", source),
+                Err(e) => failures.push(Socks5Error::ConfigError(format!("Cannot read blocklist {}: {}", source, e))),
+            }
+        }
+        let mut merged = Blocklist::new();
+        for imported in self.imported.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            merged.extend(&imported.list);
+        }
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(merged);
+        failures
+    }
+
+    /// Spawns a task refreshing the lists every interval, logging failures
+    pub fn spawn_refresh(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let blocklists = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(blocklists.refresh).await;
+                for e in blocklists.refresh().await {
+                    log::warn!("Keeping the previous entries: {}", e);
+                }
+                log::info!("Blocking {} domains from {} blocklists", blocklists.current().len(), blocklists.sources.len());
+            }
+        })
+    }
+
+    /// Reads one list
+    ///
+    /// # Returns
+    /// * `Ok(Some((list, etag)))` - The list and, for URLs, its entity tag
+    /// * `Ok(None)` - If the URL answered that the list did not change
+    /// * `Err(Socks5Error)` - If the list could not be read
+    async fn read(&self, source: &BlocklistSource, etag: Option<&str>) -> Socks5Result<Option<(Blocklist, Option<String>)>> {
+        let (text, etag) = match &source.location {
+            BlocklistLocation::File(path) => (std::fs::read_to_string(path)?, None),
+            BlocklistLocation::Url(url) => {
+                let response = remote::get(&self.tls, url, etag, MAX_LIST_SIZE).await?;
+                match response.status {
+                    200 => {}
+                    304 => return Ok(None),
+                    _ => return Err(Socks5Error::ConfigError(format!("answered {}", response.status_line))),
+                }
+                let etag = remote::header(&response, "etag").map(str::to_string);
+                let text = String::from_utf8(response.body)
+                    .map_err(|_| Socks5Error::ConfigError("list is not UTF-8".to_string()))?;
+                (text, etag)
+            }
+        };
+        Ok(Some((Blocklist::parse(&text, source.format), etag)))
+    }
+}
+
+/// Returns whether `host` looks like a host name
+fn is_host(host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    !host.is_empty()
+        && host.len() <= 253
+        && host
+            .split('.')
+            .all(|label| !label.is_empty() && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'))
+}
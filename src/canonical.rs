@@ -0,0 +1,267 @@
+//! Host name canonicalization for the SOCKS5 proxy.
+//!
+//! Domain targets are compared and resolved in a canonical form: lowercase,
+//! without trailing dot, with internationalized labels converted to
+//! punycode (`xn--`). High-QPS clients ask for the same few domains over and
+//! over, so [`CanonicalCache`] remembers each domain's canonical form and
+//! the route it matched for a while. The policy engine and the connector
+//! share one cache, so a request is canonicalized and matched only once.
+
+use std::borrow::Cow;
+use std::hash::{BuildHasher, RandomState};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::lru::LruMap;
+use crate::protocol::TargetAddr;
+use crate::routing::RoutingTable;
+
+/// How many domains are cached by default
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// How long a cached domain stays valid by default
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// The canonical form of a domain target and the route it matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Canonical {
+    /// The canonical host name
+    pub host: Arc<str>,
+    /// Index of the first matching route in the routing table, if any
+    pub route: Option<usize>,
+}
+
+/// A cached canonical form
+#[derive(Debug)]
+struct Entry {
+    /// The domain as the client sent it
+    requested: Box<str>,
+    /// The requested port
+    port: u16,
+    /// The canonical form and matched route
+    canonical: Canonical,
+    /// When the entry must be computed again
+    expires: Instant,
+}
+
+/// A bounded cache of canonicalized domains and their matched routes
+///
+/// Entries are keyed by the domain as the client sent it and the port,
+/// since route patterns may name ports; lookups hash the two rather than
+/// copying the domain into a key. A cache must only be used with one
+/// routing table.
+#[derive(Debug)]
+pub struct CanonicalCache {
+    /// How long an entry stays valid
+    ttl: Duration,
+    /// Time source for expiry
+    clock: Arc<dyn Clock>,
+    /// Hashes requested domains and ports into keys, seeded per cache
+    hasher: RandomState,
+    /// Entries per hash of the requested domain and port
+    entries: Mutex<LruMap<u64, Entry>>,
+    /// Lookups answered from the cache
+    hits: AtomicU64,
+    /// Lookups that canonicalized and matched anew
+    misses: AtomicU64,
+}
+
+impl CanonicalCache {
+    /// Creates a cache
+    ///
+    /// # Arguments
+    /// * `capacity` - How many domains are kept at most
+    /// * `ttl` - How long an entry stays valid
+    /// * `clock` - Time source for expiry
+    pub fn new(capacity: usize, ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            hasher: RandomState::new(),
+            entries: Mutex::new(LruMap::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the canonical form of `host` and the route `host:port` matches
+    pub fn lookup(&self, host: &str, port: u16, routes: &RoutingTable) -> Canonical {
+        let now = self.clock.now();
+        let key = self.hasher.hash_one((host, port));
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        // A colliding entry for another domain counts as a miss and is replaced
+        let hit = entries
+            .get_mut(&key)
+            .filter(|entry| entry.expires > now && *entry.requested == *host && entry.port == port);
+        if let Some(entry) = hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return entry.canonical.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let canonical_host: Arc<str> = canonicalize(host).into();
+        let target = TargetAddr::Domain(canonical_host.to_string(), port);
+        let canonical = Canonical {
+            host: canonical_host,
+            route: routes.route_index(&target),
+        };
+        let entry = Entry {
+            requested: host.into(),
+            port,
+            canonical: canonical.clone(),
+            expires: now + self.ttl,
+        };
+        entries.insert(key, entry);
+        canonical
+    }
+
+    /// Returns the number of cached domains
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many lookups were answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many lookups canonicalized and matched anew
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the canonical form of a host name
+///
+/// The name is lowercased and stripped of a trailing dot, and labels with
+/// non-ASCII characters are converted to punycode (RFC 3492). Names already
+/// in canonical form are borrowed rather than copied.
+pub fn canonicalize(host: &str) -> Cow<'_, str> {
+    if host.bytes().all(|b| b.is_ascii() && !b.is_ascii_uppercase()) && !host.ends_with('.') {
+        return Cow::Borrowed(host);
+    }
+    let host = host.trim_end_matches('.');
+    if host.is_ascii() {
+        return Cow::Owned(host.to_ascii_lowercase());
+    }
+    let canonical = host
+        .split('.')
+        .map(|label| {
+            let label = label.to_lowercase();
+            if label.is_ascii() {
+                label
+            } else {
+                format!("xn--{}", punycode(&label))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    Cow::Owned(canonical)
+}
+
+/// Returns the address a host name spells, if it is an IP literal
+///
+/// Besides plain and bracketed addresses this accepts the shorthand IPv4
+/// forms `getaddrinfo` resolves, e.g. `10.1`, `0x0a000001` or `012.0.0.1`,
+/// so a domain target cannot smuggle an address past checks on addresses.
+pub fn ip_literal(host: &str) -> Option<IpAddr> {
+    let host = host.trim_end_matches('.');
+    let bare = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    // inet_aton: up to four parts, the last one filling the remaining bytes
+    let parts = host
+        .split('.')
+        .map(|part| {
+            if let Some(hex) = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+                u32::from_str_radix(hex, 16).ok()
+            } else if part.len() > 1 && part.starts_with('0') {
+                u32::from_str_radix(&part[1..], 8).ok()
+            } else {
+                part.parse::<u32>().ok()
+            }
+        })
+        .collect::<Option<Vec<u32>>>()?;
+    let (last, leading) = parts.split_last()?;
+    if leading.len() > 3 || leading.iter().any(|part| *part > 0xFF) {
+        return None;
+    }
+    let shift = 8 * (4 - leading.len() as u32);
+    if shift < 32 && *last >> shift != 0 {
+        return None;
+    }
+    let address = leading.iter().enumerate().fold(*last, |address, (i, part)| address | part << (24 - 8 * i));
+    Some(IpAddr::V4(Ipv4Addr::from(address)))
+}
+
+/// Encodes a label with the punycode bootstring parameters of RFC 3492
+fn punycode(label: &str) -> String {
+    const BASE: u32 = 36;
+    const T_MIN: u32 = 1;
+    const T_MAX: u32 = 26;
+    const SKEW: u32 = 38;
+    const DAMP: u32 = 700;
+
+    fn digit(d: u32) -> char {
+        char::from(if d < 26 { b'a' + d as u8 } else { b'0' + (d - 26) as u8 })
+    }
+
+    fn adapt(mut delta: u32, points: u32, first: bool) -> u32 {
+        delta /= if first { DAMP } else { 2 };
+        delta += delta / points;
+        let mut k = 0;
+        while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+            delta /= BASE - T_MIN;
+            k += BASE;
+        }
+        k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+    }
+
+    let code_points: Vec<u32> = label.chars().map(u32::from).collect();
+    let mut output: String = label.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias, mut handled) = (0x80u32, 0u32, 72u32, basic);
+    while (handled as usize) < code_points.len() {
+        let next = code_points.iter().copied().filter(|c| *c >= n).min().unwrap_or(n);
+        delta = delta.saturating_add((next - n).saturating_mul(handled + 1));
+        n = next;
+        for c in code_points.iter().copied() {
+            if c < n {
+                delta = delta.saturating_add(1);
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias { T_MIN } else if k >= bias + T_MAX { T_MAX } else { k - bias };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.saturating_add(1);
+        n += 1;
+    }
+    output
+}
@@ -0,0 +1,73 @@
+//! Feature advertisement for the SOCKS5 proxy.
+//!
+//! [`Capabilities`] describes what a configured server will accept: SOCKS
+//! versions, commands, target address types, authentication methods and
+//! enabled extensions. Embedders and tooling can check it instead of
+//! probing the server with handshakes.
+
+use serde::Serialize;
+use std::fmt;
+
+/// What a server accepts with its current settings
+///
+/// Every entry is a short, stable name so the struct serializes to readable
+/// JSON. Lists are in protocol order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// SOCKS protocol versions clients may speak
+    pub versions: Vec<u8>,
+    /// Request commands that are served
+    pub commands: Vec<&'static str>,
+    /// Target address types that are served
+    pub address_types: Vec<&'static str>,
+    /// Authentication methods the server selects
+    pub auth_methods: Vec<&'static str>,
+    /// Optional protocol behaviours that are enabled
+    pub extensions: Vec<&'static str>,
+    /// Tolerated client quirks
+    pub quirks: Vec<&'static str>,
+}
+
+impl Capabilities {
+    /// Returns whether the named command is served, e.g. `connect`
+    pub fn supports_command(&self, command: &str) -> bool {
+        self.commands.contains(&command)
+    }
+
+    /// Returns whether the named address type is served, e.g. `domain`
+    pub fn supports_address_type(&self, address_type: &str) -> bool {
+        self.address_types.contains(&address_type)
+    }
+
+    /// Returns whether the named extension is enabled, e.g. `denial-reasons`
+    pub fn has_extension(&self, extension: &str) -> bool {
+        self.extensions.contains(&extension)
+    }
+
+    /// Returns the capabilities as a JSON object
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Capabilities {
+    /// Formats the capabilities as one `key=value,value` line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let versions: Vec<String> = self.versions.iter().map(u8::to_string).collect();
+        write!(
+            f,
+            "versions={} commands={} address_types={} auth={}",
+            versions.join(","),
+            self.commands.join(","),
+            self.address_types.join(","),
+            self.auth_methods.join(","),
+        )?;
+        if !self.extensions.is_empty() {
+            write!(f, " extensions={}", self.extensions.join(","))?;
+        }
+        if !self.quirks.is_empty() {
+            write!(f, " quirks={}", self.quirks.join(","))?;
+        }
+        Ok(())
+    }
+}
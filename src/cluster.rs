@@ -0,0 +1,470 @@
+//! Fleet-wide per-user limits for the SOCKS5 proxy.
+//!
+//! A [`Cluster`] limits how many sessions each user may have open at once
+//! and how many bytes they may relay per quota period. Alone it enforces
+//! the limits on one node; given peers, it gossips its coarse per-user
+//! counters to them over UDP every interval and counts theirs in, so the
+//! limits hold approximately across the fleet. Counters of peers that fall
+//! silent expire after a few intervals, and a user may briefly overshoot
+//! by what the fleet admitted since the last exchange.
+//!
+//! Bytes are charged as they are relayed, and a session whose user runs out
+//! of quota is ended then rather than when it closes.
+//!
+//! Gossip can be authenticated with a shared key: every datagram then
+//! carries an HMAC-SHA256 tag, and untagged or forged ones are dropped.
+//! Each node numbers its datagrams, and one that is not newer than the last
+//! taken in from its node is dropped as a replay.
+
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::lru::LruMap;
+use crate::random::{RandomSource, StdRandom};
+use crate::relay::Meter;
+
+/// How long byte quotas last by default
+pub const DEFAULT_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often counters are sent to peers by default
+pub const DEFAULT_GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Intervals after which a silent peer's counters no longer count
+const STALE_AFTER_INTERVALS: u32 = 3;
+
+/// The least recently reported users are forgotten beyond this many
+const MAX_REMOTE_USERS: usize = 65536;
+
+/// The least recently heard nodes' sequence numbers are forgotten beyond this many
+const MAX_NODES: usize = 4096;
+
+/// Users per gossip datagram, keeping datagrams well below the UDP limit
+const USERS_PER_DATAGRAM: usize = 256;
+
+/// Length of the HMAC-SHA256 tag leading authenticated datagrams
+const TAG_LEN: usize = 32;
+
+/// What a user may use across the fleet; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserQuota {
+    /// Sessions the user may have open at once
+    pub max_connections: Option<u64>,
+    /// Bytes the user may relay per quota period, both directions together
+    pub max_bytes: Option<u64>,
+}
+
+impl UserQuota {
+    /// Creates an unlimited quota
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the sessions the user may have open at once
+    pub fn with_max_connections(mut self, max: u64) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Limits the bytes the user may relay per quota period
+    pub fn with_max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// Returns whether `usage` leaves room for another session
+    pub fn admits(&self, usage: Usage) -> bool {
+        self.max_connections.is_none_or(|max| usage.connections < max)
+            && self.max_bytes.is_none_or(|max| usage.bytes < max)
+    }
+}
+
+/// A user's open sessions and bytes relayed in the current period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Sessions open now
+    pub connections: u64,
+    /// Bytes relayed in the current quota period
+    pub bytes: u64,
+}
+
+impl Usage {
+    /// Returns the sum of two usages
+    fn plus(self, other: Usage) -> Usage {
+        Usage {
+            connections: self.connections + other.connections,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+/// This node's usage of a user
+#[derive(Debug, Clone, Copy)]
+struct LocalUsage {
+    /// The usage itself
+    usage: Usage,
+    /// The quota period `usage.bytes` counts in
+    period: u64,
+}
+
+/// A peer's last report of a user's usage
+#[derive(Debug, Clone, Copy)]
+struct RemoteUsage {
+    /// The reported usage
+    usage: Usage,
+    /// The quota period the report counts bytes in
+    period: u64,
+    /// When the report arrived
+    seen: Instant,
+}
+
+/// The counters one node sends its peers
+#[derive(Debug, Serialize, Deserialize)]
+struct Gossip {
+    /// The sending node
+    node: String,
+    /// The datagram's number, increasing with every datagram the node sends
+    seq: u64,
+    /// The quota period the byte counts are in
+    period: u64,
+    /// Open sessions and bytes per user
+    users: BTreeMap<String, (u64, u64)>,
+}
+
+/// Per-user limits shared between the nodes of a fleet
+#[derive(Debug)]
+pub struct Cluster {
+    /// This node's ID in gossip, random per process
+    node: String,
+    /// The time source for periods and expiry
+    clock: Arc<dyn Clock>,
+    /// The quota of users without one of their own
+    default_quota: UserQuota,
+    /// Quotas of individual users
+    quotas: HashMap<String, UserQuota>,
+    /// How long byte quotas last
+    period: Duration,
+    /// The nodes counters are sent to
+    peers: Vec<SocketAddr>,
+    /// The key gossip is authenticated with, if any
+    key: Option<hmac::Key>,
+    /// How often counters are sent to peers
+    interval: Duration,
+    /// This node's usage per user
+    local: Mutex<HashMap<String, LocalUsage>>,
+    /// The peers' usage per user and node
+    remote: Mutex<LruMap<String, HashMap<String, RemoteUsage>>>,
+    /// The number of the last datagram this node sent
+    sent: AtomicU64,
+    /// The number of the last datagram taken in per node
+    received: Mutex<LruMap<String, u64>>,
+}
+
+impl Cluster {
+    /// Creates a cluster of one with unlimited users
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            node: format!("{:016x}", StdRandom::new().next_u64()),
+            clock,
+            default_quota: UserQuota::new(),
+            quotas: HashMap::new(),
+            period: DEFAULT_PERIOD,
+            peers: Vec::new(),
+            key: None,
+            interval: DEFAULT_GOSSIP_INTERVAL,
+            local: Mutex::new(HashMap::new()),
+            remote: Mutex::new(LruMap::new(MAX_REMOTE_USERS)),
+            sent: AtomicU64::new(0),
+            received: Mutex::new(LruMap::new(MAX_NODES)),
+        }
+    }
+
+    /// Sets the quota of users without one of their own
+    pub fn with_default_quota(mut self, quota: UserQuota) -> Self {
+        self.default_quota = quota;
+        self
+    }
+
+    /// Sets the quota of one user
+    pub fn with_user_quota(mut self, username: impl Into<String>, quota: UserQuota) -> Self {
+        self.quotas.insert(username.into(), quota);
+        self
+    }
+
+    /// Sets how long byte quotas last; at least a second
+    ///
+    /// Periods are aligned to the Unix epoch, so nodes with the same period
+    /// and roughly synchronized clocks reset their quotas together.
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Sets the nodes counters are sent to
+    pub fn with_peers(mut self, peers: Vec<SocketAddr>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Authenticates gossip with a key every node shares
+    pub fn with_key(mut self, key: &[u8]) -> Self {
+        self.key = Some(hmac::Key::new(hmac::HMAC_SHA256, key));
+        self
+    }
+
+    /// Sets how often counters are sent to peers
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(Duration::from_millis(10));
+        self
+    }
+
+    /// Returns this node's ID in gossip
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Returns the nodes counters are sent to
+    pub fn peers(&self) -> &[SocketAddr] {
+        &self.peers
+    }
+
+    /// Returns the quota that applies to `username`
+    pub fn quota(&self, username: &str) -> UserQuota {
+        self.quotas.get(username).copied().unwrap_or(self.default_quota)
+    }
+
+    /// Returns this node's usage of `username`
+    pub fn local_usage(&self, username: &str) -> Usage {
+        let period = self.current_period();
+        let local = self.local.lock().unwrap_or_else(|e| e.into_inner());
+        local.get(username).map_or(Usage::default(), |local| Self::in_period(local.usage, local.period, period))
+    }
+
+    /// Returns the fleet's usage of `username` as far as this node knows
+    pub fn usage(&self, username: &str) -> Usage {
+        let period = self.current_period();
+        let now = self.clock.now();
+        let stale_after = self.interval * STALE_AFTER_INTERVALS;
+        let remote = self.remote.lock().unwrap_or_else(|e| e.into_inner());
+        remote
+            .peek(&username.to_string())
+            .into_iter()
+            .flat_map(|nodes| nodes.values())
+            .filter(|report| now.saturating_duration_since(report.seen) < stale_after)
+            .fold(self.local_usage(username), |usage, report| {
+                usage.plus(Self::in_period(report.usage, report.period, period))
+            })
+    }
+
+    /// Opens a session for `username` if the fleet's usage leaves room
+    ///
+    /// # Returns
+    /// * `Some(UserLease)` - The session, counted until the lease is dropped
+    /// * `None` - If the user is at their connection limit or out of quota
+    pub fn admit(self: &Arc<Self>, username: &str) -> Option<UserLease> {
+        if !self.quota(username).admits(self.usage(username)) {
+            return None;
+        }
+        Some(self.open(username))
+    }
+
+    /// Opens a session for `username` whatever the fleet's usage
+    ///
+    /// Keeps users whose limits are not enforced, e.g. in shadow mode, counted.
+    pub fn open(self: &Arc<Self>, username: &str) -> UserLease {
+        self.update(username, |usage| usage.connections += 1);
+        UserLease {
+            cluster: Arc::clone(self),
+            username: username.to_string(),
+        }
+    }
+
+    /// Spawns a task gossiping counters with the peers through `socket`
+    pub fn spawn_gossip(self: &Arc<Self>, socket: UdpSocket) -> JoinHandle<()> {
+        let cluster = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(cluster.interval);
+            let mut buf = vec![0; 65536];
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        for datagram in cluster.gossip() {
+                            for peer in &cluster.peers {
+                                if let Err(e) = socket.send_to(&datagram, peer).await {
+                                    log::debug!("Cannot send cluster counters to {}: {}", peer, e);
+                                }
+                            }
+                        }
+                    }
+                    received = socket.recv_from(&mut buf) => match received {
+                        Ok((len, from)) => {
+                            if !cluster.receive(&buf[..len]) {
+                                log::debug!("Dropped a malformed, unauthenticated or replayed cluster datagram from {}", from);
+                            }
+                        }
+                        Err(e) => log::debug!("Cannot receive cluster counters: {}", e),
+                    },
+                }
+            }
+        })
+    }
+
+    /// Encodes this node's counters into datagrams, forgetting idle users
+    fn gossip(&self) -> Vec<Vec<u8>> {
+        let period = self.current_period();
+        let users: Vec<(String, (u64, u64))> = {
+            let mut local = self.local.lock().unwrap_or_else(|e| e.into_inner());
+            let users = local
+                .iter()
+                .map(|(username, local)| {
+                    let usage = Self::in_period(local.usage, local.period, period);
+                    (username.clone(), (usage.connections, usage.bytes))
+                })
+                .collect();
+            // Idle users are reported as such once, then dropped
+            local.retain(|_, local| Self::in_period(local.usage, local.period, period) != Usage::default());
+            users
+        };
+        users
+            .chunks(USERS_PER_DATAGRAM)
+            .map(|chunk| {
+                let gossip = Gossip {
+                    node: self.node.clone(),
+                    seq: self.sent.fetch_add(1, Ordering::Relaxed) + 1,
+                    period,
+                    users: chunk.iter().cloned().collect(),
+                };
+                let json = serde_json::to_vec(&gossip).expect("gossip serializes");
+                match &self.key {
+                    Some(key) => [hmac::sign(key, &json).as_ref(), &json].concat(),
+                    None => json,
+                }
+            })
+            .collect()
+    }
+
+    /// Takes in a peer's datagram
+    ///
+    /// # Returns
+    /// * Whether the datagram was authentic, well-formed and newer than the
+    ///   last one taken in from its node
+    fn receive(&self, datagram: &[u8]) -> bool {
+        let json = match &self.key {
+            Some(key) if datagram.len() >= TAG_LEN => {
+                let (tag, json) = datagram.split_at(TAG_LEN);
+                if hmac::verify(key, json, tag).is_err() {
+                    return false;
+                }
+                json
+            }
+            Some(_) => return false,
+            None => datagram,
+        };
+        let Ok(gossip) = serde_json::from_slice::<Gossip>(json) else {
+            return false;
+        };
+        if gossip.node == self.node {
+            return true;
+        }
+        {
+            let mut received = self.received.lock().unwrap_or_else(|e| e.into_inner());
+            let last = received.get_or_insert_with(gossip.node.clone(), || 0);
+            if gossip.seq <= *last {
+                return false;
+            }
+            *last = gossip.seq;
+        }
+        let seen = self.clock.now();
+        let mut remote = self.remote.lock().unwrap_or_else(|e| e.into_inner());
+        for (username, (connections, bytes)) in gossip.users {
+            remote.get_or_insert_with(username, HashMap::new).insert(
+                gossip.node.clone(),
+                RemoteUsage {
+                    usage: Usage { connections, bytes },
+                    period: gossip.period,
+                    seen,
+                },
+            );
+        }
+        true
+    }
+
+    /// Changes this node's usage of `username`
+    fn update(&self, username: &str, change: impl FnOnce(&mut Usage)) {
+        let period = self.current_period();
+        let mut local = self.local.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = local
+            .entry(username.to_string())
+            .or_insert(LocalUsage { usage: Usage::default(), period });
+        entry.usage = Self::in_period(entry.usage, entry.period, period);
+        entry.period = period;
+        change(&mut entry.usage);
+    }
+
+    /// Returns the quota period the wall clock is in
+    fn current_period(&self) -> u64 {
+        let since_epoch = self.clock.wall_time().duration_since(UNIX_EPOCH).unwrap_or_default();
+        since_epoch.as_secs() / self.period.as_secs()
+    }
+
+    /// Returns `usage`, counted in `counted`, as it stands in `period`
+    ///
+    /// Open sessions carry over into a new period; bytes start over.
+    fn in_period(usage: Usage, counted: u64, period: u64) -> Usage {
+        if counted == period {
+            usage
+        } else {
+            Usage { connections: usage.connections, bytes: 0 }
+        }
+    }
+}
+
+/// A session counted against its user until dropped
+#[derive(Debug)]
+pub struct UserLease {
+    /// The cluster the session is counted in
+    cluster: Arc<Cluster>,
+    /// The user the session belongs to
+    username: String,
+}
+
+impl UserLease {
+    /// Returns the user the session belongs to
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Counts relayed bytes against the user's quota
+    pub fn add_bytes(&self, bytes: u64) {
+        self.cluster.update(&self.username, |usage| usage.bytes += bytes);
+    }
+
+    /// Returns whether the user has bytes left in the current quota period
+    pub fn has_bytes_left(&self) -> bool {
+        let max_bytes = self.cluster.quota(&self.username).max_bytes;
+        max_bytes.is_none_or(|max| self.cluster.usage(&self.username).bytes < max)
+    }
+}
+
+/// Charges relayed bytes as they go, running out with the user's quota
+impl Meter for UserLease {
+    fn charge(&self, bytes: u64) -> bool {
+        self.add_bytes(bytes);
+        self.has_bytes_left()
+    }
+}
+
+impl Drop for UserLease {
+    fn drop(&mut self) {
+        self.cluster
+            .update(&self.username, |usage| usage.connections = usage.connections.saturating_sub(1));
+    }
+}
@@ -0,0 +1,149 @@
+//! Compatibility shims for known-broken SOCKS clients.
+//!
+//! The default protocol path is strict. Each quirk here relaxes one specific
+//! rule so the proxy can interoperate with legacy clients and appliances; a
+//! quirk only takes effect when it is enabled, and every time it fires it is
+//! counted in the server's metrics.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::listener::{peek, ClientStream};
+
+pub use crate::socks4::{read_socks4_request, send_socks4_reply, SOCKS4_VERSION};
+
+/// How long a short greeting may take to deliver its remaining bytes
+pub const SHORT_READ_GRACE: Duration = Duration::from_millis(250);
+
+/// A known client misbehaviour the server can be told to tolerate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quirk {
+    /// Requests with a non-zero reserved (RSV) byte
+    ReservedByte,
+    /// Clients opening with version byte 4, served as SOCKS4 even when it is disabled
+    Socks4Greeting,
+    /// Greetings that deliver fewer methods than NMETHODS announced
+    ShortReads,
+    /// RFC 1929 credentials sent after NO_AUTH was selected
+    UnsolicitedCredentials,
+}
+
+impl Quirk {
+    /// All quirks, in counter order
+    pub const ALL: [Quirk; 4] = [
+        Quirk::ReservedByte,
+        Quirk::Socks4Greeting,
+        Quirk::ShortReads,
+        Quirk::UnsolicitedCredentials,
+    ];
+
+    /// Returns a short, stable name for the quirk
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quirk::ReservedByte => "reserved-byte",
+            Quirk::Socks4Greeting => "socks4-greeting",
+            Quirk::ShortReads => "short-reads",
+            Quirk::UnsolicitedCredentials => "unsolicited-credentials",
+        }
+    }
+
+    /// Returns the quirk's slot in counters and sets
+    pub(crate) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Quirk {
+    type Err = Socks5Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Quirk::ALL
+            .into_iter()
+            .find(|quirk| quirk.as_str() == s)
+            .ok_or_else(|| Socks5Error::ConfigError(format!("Unknown compatibility quirk: {}", s)))
+    }
+}
+
+/// A set of quirks: the ones enabled on a server, or the ones a client used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks(u8);
+
+impl Quirks {
+    /// Creates an empty set: the strict default
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    /// Returns the set with `quirk` added
+    pub fn with(mut self, quirk: Quirk) -> Self {
+        self.insert(quirk);
+        self
+    }
+
+    /// Adds `quirk` to the set
+    pub fn insert(&mut self, quirk: Quirk) {
+        self.0 |= 1 << quirk.index();
+    }
+
+    /// Removes `quirk` from the set
+    pub fn remove(&mut self, quirk: Quirk) {
+        self.0 &= !(1 << quirk.index());
+    }
+
+    /// Returns whether `quirk` is in the set
+    pub fn contains(&self, quirk: Quirk) -> bool {
+        self.0 & (1 << quirk.index()) != 0
+    }
+
+    /// Returns whether the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the quirks in the set, in counter order
+    pub fn iter(&self) -> impl Iterator<Item = Quirk> + '_ {
+        Quirk::ALL.into_iter().filter(|quirk| self.contains(*quirk))
+    }
+}
+
+impl FromIterator<Quirk> for Quirks {
+    fn from_iter<I: IntoIterator<Item = Quirk>>(iter: I) -> Self {
+        iter.into_iter().fold(Quirks::new(), Quirks::with)
+    }
+}
+
+/// Fills `buf` from the stream, giving up after `grace` without new bytes
+///
+/// # Returns
+/// * `Ok(n)` - The number of bytes read; less than `buf.len()` on a short read
+/// * `Err(Socks5Error)` - If the stream fails or closes before any byte arrives
+pub async fn read_tolerant<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut [u8], grace: Duration) -> Socks5Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match tokio::time::timeout(grace, stream.read(&mut buf[filled..])).await {
+            Ok(Ok(0)) if filled == 0 => {
+                return Err(Socks5Error::HandshakeError("Client closed during greeting".to_string()));
+            }
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => filled += n,
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Returns the client's first byte without consuming it, or `None` on EOF
+pub async fn peek_version<S: ClientStream>(stream: &mut S) -> Socks5Result<Option<u8>> {
+    let mut first = [0; 1];
+    let n = peek(stream, &mut first).await?;
+    Ok((n == 1).then_some(first[0]))
+}
@@ -0,0 +1,341 @@
+//! Configuration model for the SOCKS5 proxy.
+//!
+//! These types mirror the configuration file and can be serialized,
+//! deserialized or built in code, then turned into ready-to-run servers.
+//! A JSON Schema of the file is generated from the same types.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::acl::{AclRules, TargetAllowList};
+use crate::canonical::{self, CanonicalCache};
+use crate::clock::TokioClock;
+use crate::compat::Quirks;
+use crate::connection::Connector;
+use crate::constants::DEFAULT_PORT;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::hosts::Hosts;
+use crate::relay::{RelayEngine, RelayOptions};
+use crate::routing::{Route, RoutingTable};
+use crate::server::Server;
+use crate::timeouts;
+use crate::tls;
+use crate::upstream::{UpstreamMode, UpstreamProxy, Upstreams};
+use crate::users::UserTable;
+
+/// The complete proxy configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to accept SOCKS5 clients on
+    pub listeners: Vec<ListenerConfig>,
+    /// Username clients must authenticate with, if any
+    pub username: Option<String>,
+    /// Password clients must authenticate with, if any
+    pub password: Option<String>,
+    /// Further accounts clients may authenticate as, password per username
+    pub users: BTreeMap<String, String>,
+    /// A file of `username:password` lines with further accounts
+    pub users_file: Option<String>,
+    /// Upstream SOCKS5 proxies (`host:port` or `socks5://` / `socks5h://` URLs) tried in order
+    pub upstreams: Vec<String>,
+    /// Destination access control
+    pub acl: AclConfig,
+    /// Per-destination outbound settings, first match wins
+    pub routes: Vec<RouteConfig>,
+    /// Connection deadlines
+    pub timeouts: Timeouts,
+    /// Log and count policy denials without enforcing them
+    pub shadow: bool,
+    /// Known client misbehaviours to tolerate, e.g. `reserved-byte`,
+    /// `socks4-greeting`, `short-reads` or `unsolicited-credentials`
+    pub compat: Vec<String>,
+    /// An `/etc/hosts`-style file of host name overrides, reloaded when it changes
+    pub hosts_file: Option<String>,
+    /// Host name overrides consulted before DNS, e.g. `"db.test" = ["10.0.0.5"]`
+    pub hosts: BTreeMap<String, Vec<IpAddr>>,
+    /// The revision of a remote configuration document, raised with every
+    /// change; required in signed documents so older ones cannot be replayed
+    pub serial: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listeners: vec![ListenerConfig::default()],
+            username: None,
+            password: None,
+            users: BTreeMap::new(),
+            users_file: None,
+            upstreams: Vec::new(),
+            acl: AclConfig::default(),
+            routes: Vec::new(),
+            timeouts: Timeouts::default(),
+            shadow: false,
+            compat: Vec::new(),
+            hosts_file: None,
+            hosts: BTreeMap::new(),
+            serial: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parses a configuration from TOML
+    ///
+    /// # Returns
+    /// * `Ok(ServerConfig)` - The parsed configuration
+    /// * `Err(Socks5Error)` - If the document is not a valid configuration
+    pub fn from_toml(document: &str) -> Socks5Result<Self> {
+        toml::from_str(document).map_err(|e| Socks5Error::ConfigError(e.to_string()))
+    }
+
+    /// Returns the JSON Schema of the configuration file, pretty-printed
+    ///
+    /// Editors use it to validate and autocomplete configuration files.
+    pub fn json_schema() -> String {
+        let schema = schemars::schema_for!(ServerConfig);
+        serde_json::to_string_pretty(&schema).expect("JSON schemas always serialize")
+    }
+
+    /// Serializes the configuration to TOML
+    pub fn to_toml(&self) -> Socks5Result<String> {
+        toml::to_string(self).map_err(|e| Socks5Error::ConfigError(e.to_string()))
+    }
+
+    /// Builds one server per listener
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Server>)` - The configured, not yet bound servers
+    /// * `Err(Socks5Error)` - If a setting is invalid
+    pub fn build_servers(&self) -> Socks5Result<Vec<Server>> {
+        if self.username.is_some() != self.password.is_some() {
+            return Err(Socks5Error::ConfigError(
+                "Both username and password must be provided if either is provided".to_string(),
+            ));
+        }
+        
+        let mut users = match &self.users_file {
+            Some(path) => UserTable::load(path)?,
+            None => UserTable::new(),
+        };
+        for (username, password) in &self.users {
+            users.insert(username, password);
+        }
+        let acl = self.acl.rules()?;
+        let allowed_targets = self.acl.allow_list()?;
+        let compat = self.compat.iter().map(|quirk| quirk.parse()).collect::<Socks5Result<Quirks>>()?;
+        let routes = self.routes.iter().map(RouteConfig::to_route).collect::<Socks5Result<Vec<_>>>()?;
+        let mut connector = Connector::new()
+            .with_routes(RoutingTable::new(routes))
+            .with_canonical_cache(CanonicalCache::new(canonical::DEFAULT_CAPACITY, canonical::DEFAULT_TTL, Arc::new(TokioClock)));
+        if self.hosts_file.is_some() || !self.hosts.is_empty() {
+            let hosts = match &self.hosts_file {
+                Some(path) => Hosts::load(path)?,
+                None => Hosts::new(),
+            };
+            let hosts = self.hosts.iter().fold(hosts, |hosts, (name, ips)| {
+                ips.iter().fold(hosts, |hosts, ip| hosts.with_entry(name, *ip))
+            });
+            connector = connector.with_hosts(hosts);
+        }
+        let mut relay_options = RelayOptions::new();
+        if let Some(secs) = self.timeouts.idle {
+            relay_options = relay_options
+                .with_engine(RelayEngine::Full)
+                .with_idle_timeout(Duration::from_secs(secs));
+        }
+        let mut deadlines = timeouts::Timeouts::default();
+        if let Some(secs) = self.timeouts.handshake {
+            deadlines.handshake = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.timeouts.connect {
+            deadlines.connect = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        
+        self.listeners.iter().map(|listener| {
+            let mut server = Server::new(
+                listener.ip.clone(),
+                Some(listener.port),
+                self.username.clone(),
+                self.password.clone(),
+            )
+            .with_users(users.clone())
+            .with_connector(connector.clone())
+            .with_relay_options(relay_options.clone())
+            .with_acl(acl.clone())
+            .with_ip_literals_only(listener.ip_literals_only)
+            .with_shadow_mode(self.shadow)
+            .with_compat(compat)
+            .with_timeouts(deadlines);
+            if let Some(last_port) = listener.last_port {
+                server = server.with_port_fallback(last_port);
+            }
+            if let Some(secs) = self.timeouts.first_byte {
+                server = server.with_first_byte_timeout(Duration::from_secs(secs));
+            }
+            if let Some(secs) = self.timeouts.tls_handshake {
+                server = server.with_tls_handshake_timeout(Duration::from_secs(secs));
+            }
+            if let Some(allowed_targets) = &allowed_targets {
+                server = server.with_allowed_targets(allowed_targets.clone());
+            }
+            if !self.upstreams.is_empty() {
+                let proxies = self.upstreams.iter().map(|upstream| upstream.parse::<UpstreamProxy>()).collect::<Result<_, _>>()?;
+                server = server.with_upstreams(Upstreams::new(proxies, UpstreamMode::Failover));
+            }
+            match (&listener.tls_cert, &listener.tls_key) {
+                (Some(cert), Some(key)) => server = server.with_tls(tls::load_server_config(Path::new(cert), Path::new(key))?),
+                (None, None) => {}
+                _ => {
+                    return Err(Socks5Error::ConfigError(
+                        "Both tls_cert and tls_key must be provided if either is provided".to_string(),
+                    ))
+                }
+            }
+            Ok(server)
+        }).collect()
+    }
+}
+
+/// An address to accept SOCKS5 clients on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// IP address to bind to
+    pub ip: String,
+    /// Port to listen on
+    pub port: u16,
+    /// Try the following ports up to this one when `port` is taken
+    pub last_port: Option<u16>,
+    /// Refuse domain name targets on this listener
+    pub ip_literals_only: bool,
+    /// PEM certificate chain to terminate TLS with; requires `tls_key`
+    pub tls_cert: Option<String>,
+    /// PEM private key of `tls_cert`
+    pub tls_key: Option<String>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            ip: "0.0.0.0".to_string(),
+            port: DEFAULT_PORT,
+            last_port: None,
+            ip_literals_only: false,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+/// Destination access control settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AclConfig {
+    /// Ordered `allow PATTERN` and `deny PATTERN` rules such as
+    /// `deny 10.0.0.0/8`, optionally scoped with `user=NAME` or
+    /// `from=NETWORK`; the first match wins, unmatched targets are allowed.
+    /// Deny rules ending in `shadow` only log and count what they would
+    /// refuse; rules with `name=NAME` are reported by that name
+    pub rules: Vec<String>,
+    /// The only `host:port` targets clients may connect to; empty allows all
+    pub allow_targets: Vec<String>,
+    /// Log and count targets missing from `allow_targets` without refusing them
+    pub shadow: bool,
+}
+
+impl AclConfig {
+    /// Builds the ordered allow and deny rules
+    pub(crate) fn rules(&self) -> Socks5Result<AclRules> {
+        let mut rules = AclRules::new();
+        for rule in &self.rules {
+            rules.push_str(rule)?;
+        }
+        Ok(rules)
+    }
+
+    /// Builds the target allow-list, or `None` when every target is allowed
+    pub(crate) fn allow_list(&self) -> Socks5Result<Option<TargetAllowList>> {
+        if self.allow_targets.is_empty() {
+            return Ok(None);
+        }
+        let mut allow_list = TargetAllowList::new().with_shadow(self.shadow);
+        for entry in &self.allow_targets {
+            allow_list.allow_str(entry)?;
+        }
+        Ok(Some(allow_list))
+    }
+}
+
+/// Outbound settings for targets matching a pattern
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// Target pattern such as `*.backup.internal`, `10.0.0.0/8:443` or `*`
+    pub pattern: String,
+    /// DSCP code point (0-63) for outbound packets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dscp: Option<u8>,
+    /// Firewall mark (`SO_MARK`) for policy routing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mark: Option<u32>,
+    /// Host to connect to instead of the requested one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite_host: Option<String>,
+    /// Port to connect to instead of the requested one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite_port: Option<u16>,
+    /// SRV service prefix such as `_postgres._tcp`; domain targets are
+    /// reached on the hosts and ports of their SRV records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub srv: Option<String>,
+}
+
+impl RouteConfig {
+    /// Converts the entry into a routing rule
+    pub fn to_route(&self) -> Socks5Result<Route> {
+        if self.dscp.is_some_and(|dscp| dscp >= 64) {
+            return Err(Socks5Error::ConfigError(format!("DSCP out of range in route {}", self.pattern)));
+        }
+        Ok(Route {
+            pattern: self.pattern.parse()?,
+            dscp: self.dscp,
+            mark: self.mark,
+            rewrite_host: self.rewrite_host.clone(),
+            rewrite_port: self.rewrite_port,
+            srv: self.srv.as_ref().map(|srv| srv.trim_end_matches('.').to_string()),
+        })
+    }
+}
+
+/// Connection deadlines in seconds
+///
+/// Unset `first_byte`, `idle` and `tls_handshake` mean no deadline; unset
+/// `handshake` and `connect` keep the [`timeouts`] defaults, and 0 turns
+/// them off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// How long a client may stay silent after connecting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte: Option<u64>,
+    /// How long a client may take from connecting until its request is read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<u64>,
+    /// How long each connect attempt to a target address may take
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect: Option<u64>,
+    /// How long a relay may go without traffic in either direction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle: Option<u64>,
+    /// How long a client may take to complete the TLS handshake on
+    /// listeners that terminate TLS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_handshake: Option<u64>,
+}
@@ -3,59 +3,872 @@
 //! This module is responsible for establishing connections to target servers
 //! as requested by SOCKS5 clients.
 
-use tokio::net::TcpStream;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpSocket, TcpStream};
 
+use crate::canonical::CanonicalCache;
+use crate::dnscache::DnsCache;
+use crate::egress::EgressPool;
 use crate::error::{Socks5Error, Socks5Result};
-use crate::protocol::{TargetAddr, send_reply, send_success_reply};
+use crate::hosts::{self, Hosts};
+use crate::listener::ClientStream;
+use crate::protocol::{TargetAddr, send_failure, send_success_reply};
 use crate::constants::reply;
+use crate::nat64::Nat64Prefix;
+use crate::resolver::Resolver;
+use crate::routing::{Route, RoutingTable};
+use crate::srv::SrvResolver;
+use crate::upstream::Upstreams;
+use crate::warm::WarmPool;
 
 /// Establishes a connection to the target server.
 ///
+/// This is a convenience for [`Connector::connect`] with default settings.
+///
 /// # Arguments
-/// * `client_stream` - The client TCP stream for sending replies
+/// * `client_stream` - The client stream for sending replies
 /// * `target_addr` - The target address to connect to
 ///
 /// # Returns
 /// * `Ok(TcpStream)` - The established connection to the target server
 /// * `Err(Socks5Error)` - If connection fails
-pub async fn connect_to_target(
-    client_stream: &mut TcpStream,
+pub async fn connect_to_target<S: ClientStream>(
+    client_stream: &mut S,
     target_addr: &TargetAddr,
 ) -> Socks5Result<TcpStream> {
-    // Convert target address to string format for connection
-    let addr_string = target_addr.to_string();
-    
-    // Log connection attempt
-    log::info!("Connecting to target: {}", addr_string);
+    Connector::new().connect(client_stream, target_addr).await
+}
+
+/// Socket options applied to outbound connections before connecting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Local address to bind to before connecting (selects the egress IP)
+    pub bind_addr: Option<IpAddr>,
+    /// IP TOS byte (IPv4) or traffic class (IPv6), e.g. DSCP << 2
+    pub tos: Option<u32>,
+    /// Firewall mark (`SO_MARK`) for policy routing; Linux only
+    pub mark: Option<u32>,
+    /// Kernel send buffer size in bytes
+    pub send_buffer_size: Option<u32>,
+    /// Kernel receive buffer size in bytes
+    pub recv_buffer_size: Option<u32>,
+}
+
+impl SocketOptions {
+    /// Creates a socket for `addr` with these options applied
+    ///
+    /// # Arguments
+    /// * `addr` - The address the socket will connect to
+    ///
+    /// # Returns
+    /// * `Ok(TcpSocket)` - The configured, unconnected socket
+    /// * `Err(io::Error)` - If the socket cannot be created or configured
+    pub fn socket_for(&self, addr: &SocketAddr) -> io::Result<TcpSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(tos) = self.tos {
+            set_tos(&socket, addr, tos)?;
+        }
+        if let Some(mark) = self.mark {
+            set_mark(&socket, mark)?;
+        }
+        if let Some(ip) = self.bind_addr {
+            socket.bind(SocketAddr::new(ip, 0))?;
+        }
+        Ok(socket)
+    }
+}
+
+/// Sets the IPv4 TOS byte or the IPv6 traffic class
+fn set_tos(socket: &TcpSocket, addr: &SocketAddr, tos: u32) -> io::Result<()> {
+    match addr {
+        SocketAddr::V4(_) => socket.set_tos_v4(tos),
+        #[cfg(target_os = "linux")]
+        SocketAddr::V6(_) => socket.set_tclass_v6(tos),
+        #[cfg(not(target_os = "linux"))]
+        SocketAddr::V6(_) => {
+            log::debug!("Traffic class not supported on this platform, ignoring");
+            Ok(())
+        }
+    }
+}
+
+/// Sets the firewall mark used by policy routing
+#[cfg(target_os = "linux")]
+fn set_mark(socket: &TcpSocket, mark: u32) -> io::Result<()> {
+    socket2::SockRef::from(socket).set_mark(mark)
+}
+
+/// Sets the firewall mark used by policy routing
+#[cfg(not(target_os = "linux"))]
+fn set_mark(_socket: &TcpSocket, _mark: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_MARK is only supported on Linux"))
+}
+
+/// When the SUCCEEDED reply is sent relative to the target connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplyMode {
+    /// Reply as soon as the TCP handshake with the target completes
+    #[default]
+    Immediate,
+    /// Reply only once the target is writable and has not closed or reset
+    /// the connection within `settle`, for clients that treat SUCCEEDED as a
+    /// guarantee of reachability
+    Verified {
+        /// How long the fresh connection must stay up before replying
+        settle: Duration,
+    },
+    /// Reply SUCCEEDED before connecting, for latency-sensitive clients
+    ///
+    /// Client bytes sent in the meantime wait in the socket's receive buffer
+    /// until the relay starts. If the connect then fails, the client only
+    /// sees the connection close.
+    Optimistic,
+}
+
+/// Settings for opening outbound connections to targets
+#[derive(Debug, Clone, Default)]
+pub struct Connector {
+    /// NAT64 prefix used to reach IPv4 targets from an IPv6-only host
+    nat64: Option<Nat64Prefix>,
+    /// Socket options applied to every outbound connection
+    socket_options: SocketOptions,
+    /// Per-destination overrides of the socket options
+    routes: RoutingTable,
+    /// When the success reply is sent to the client
+    reply_mode: ReplyMode,
+    /// Whether a target that closes a verified connection right away is
+    /// retried once at another of its addresses
+    early_close_retry: bool,
+    /// How long each connect attempt may take, if bounded
+    connect_timeout: Option<Duration>,
+    /// Local addresses spread over instead of the fixed bind address
+    egress: Option<Arc<EgressPool>>,
+    /// Pre-resolved addresses and idle connections for hot destinations
+    warm: Option<Arc<WarmPool>>,
+    /// Host name overrides consulted before DNS
+    hosts: Option<Arc<Hosts>>,
+    /// Resolver for routes with SRV lookups; the system's nameservers when unset
+    srv_resolver: Option<Arc<SrvResolver>>,
+    /// Resolver for host names; the blocking system resolver when unset
+    resolver: Option<Arc<Resolver>>,
+    /// Recently resolved host names
+    dns_cache: Option<Arc<DnsCache>>,
+    /// Canonical forms and matched routes of hot domains
+    canonical: Option<Arc<CanonicalCache>>,
+}
+
+impl Connector {
+    /// Creates a connector with default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reaches IPv4 targets through the given NAT64 prefix
+    ///
+    /// # Arguments
+    /// * `prefix` - The /96 prefix of the NAT64 gateway
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_nat64(mut self, prefix: Nat64Prefix) -> Self {
+        self.nat64 = Some(prefix);
+        self
+    }
+
+    /// Applies socket options to every outbound connection
+    ///
+    /// # Arguments
+    /// * `options` - Bind address, TOS, mark and buffer sizes
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = options;
+        self
+    }
+
+    /// Applies per-destination routing rules (DSCP, firewall mark)
+    ///
+    /// # Arguments
+    /// * `routes` - The routes, first match wins
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_routes(mut self, routes: RoutingTable) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Sets when the success reply is sent to the client
+    ///
+    /// # Arguments
+    /// * `reply_mode` - The latency/accuracy trade-off for the SUCCEEDED reply
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_reply_mode(mut self, reply_mode: ReplyMode) -> Self {
+        self.reply_mode = reply_mode;
+        self
+    }
+
+    /// Retries once at another resolved address when a target closes or
+    /// resets a fresh connection during [`ReplyMode::Verified`]'s settle time
+    ///
+    /// Only verified replies can be retried: once SUCCEEDED is sent, the
+    /// client may already have sent data the next address would not see.
+    ///
+    /// # Arguments
+    /// * `retry` - Whether to retry
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_early_close_retry(mut self, retry: bool) -> Self {
+        self.early_close_retry = retry;
+        self
+    }
+
+    /// Returns whether targets that close right away are retried
+    pub fn early_close_retry(&self) -> bool {
+        self.early_close_retry
+    }
+
+    /// Gives up on a target address that does not accept the connection in time
+    ///
+    /// The deadline applies to each resolved address in turn, so a target
+    /// with one unreachable address is still reached at the next.
+    ///
+    /// # Arguments
+    /// * `timeout` - How long each connect attempt may take
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns how long each connect attempt may take, if bounded
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Spreads outbound connections over a pool of local addresses
+    ///
+    /// The pool's choice replaces the bind address of the socket options for
+    /// destinations of the same address family.
+    ///
+    /// # Arguments
+    /// * `pool` - The egress addresses and selection mode
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_egress(mut self, pool: EgressPool) -> Self {
+        self.egress = Some(Arc::new(pool));
+        self
+    }
+
+    /// Returns the egress pool, if any
+    pub fn egress(&self) -> Option<&EgressPool> {
+        self.egress.as_deref()
+    }
+
+    /// Keeps the pool's destinations resolved and pre-connected
+    ///
+    /// The pool is filled by [`Connector::refresh_warm`], usually through
+    /// [`Connector::spawn_warm_refresh`].
+    ///
+    /// # Arguments
+    /// * `pool` - The warm destinations and their cached state
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_warm(mut self, pool: WarmPool) -> Self {
+        self.warm = Some(Arc::new(pool));
+        self
+    }
+
+    /// Resolves host names through `hosts` before asking DNS
+    ///
+    /// A hosts file is checked for changes by
+    /// [`Connector::spawn_hosts_reload`].
+    ///
+    /// # Arguments
+    /// * `hosts` - The host name overrides
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_hosts(mut self, hosts: Hosts) -> Self {
+        self.hosts = Some(Arc::new(hosts));
+        self
+    }
+
+    /// Returns the host name overrides, if any
+    pub fn hosts(&self) -> Option<&Hosts> {
+        self.hosts.as_deref()
+    }
+
+    /// Caches the canonical form and matched route of domain targets
+    ///
+    /// The cache is shared with the server's policy engine, so requests for
+    /// hot domains are canonicalized and matched against the routes once.
+    ///
+    /// # Arguments
+    /// * `cache` - The cache, used only with this connector's routes
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_canonical_cache(mut self, cache: CanonicalCache) -> Self {
+        self.canonical = Some(Arc::new(cache));
+        self
+    }
+
+    /// Returns the canonicalization cache, if any
+    pub fn canonical_cache(&self) -> Option<&Arc<CanonicalCache>> {
+        self.canonical.as_ref()
+    }
+
+    /// Sends the SRV queries of routes with `srv` to a specific resolver
+    ///
+    /// # Arguments
+    /// * `resolver` - The resolver to use instead of the system's nameservers
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_srv_resolver(mut self, resolver: SrvResolver) -> Self {
+        self.srv_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Resolves host names by querying nameservers directly instead of
+    /// through the blocking system resolver
+    ///
+    /// Host name overrides are still checked first.
+    ///
+    /// # Arguments
+    /// * `resolver` - The nameservers, timeout and concurrency to resolve with
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Returns the asynchronous resolver, if any
+    pub fn resolver(&self) -> Option<&Resolver> {
+        self.resolver.as_deref()
+    }
+
+    /// Keeps resolved host names for their TTL instead of resolving them
+    /// on every connection
+    ///
+    /// # Arguments
+    /// * `cache` - The cache, with its capacity and TTL settings
+    ///
+    /// # Returns
+    /// * The updated Connector instance
+    pub fn with_dns_cache(mut self, cache: DnsCache) -> Self {
+        self.dns_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Returns the host name cache, if any
+    pub fn dns_cache(&self) -> Option<&DnsCache> {
+        self.dns_cache.as_deref()
+    }
+
+    /// Returns the warm destination pool, if any
+    pub fn warm(&self) -> Option<&WarmPool> {
+        self.warm.as_deref()
+    }
+
+    /// Returns when the success reply is sent to the client
+    pub fn reply_mode(&self) -> ReplyMode {
+        self.reply_mode
+    }
+
+    /// Returns the NAT64 prefix, if any
+    pub fn nat64(&self) -> Option<Nat64Prefix> {
+        self.nat64
+    }
+
+    /// Returns the socket options applied to outbound connections
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket_options
+    }
+
+    /// Returns the routing rules
+    pub fn routes(&self) -> &RoutingTable {
+        &self.routes
+    }
+
+    /// Returns the socket options for a target, with its route applied
+    ///
+    /// # Arguments
+    /// * `target_addr` - The target address being connected to
+    pub fn options_for(&self, target_addr: &TargetAddr) -> SocketOptions {
+        let mut options = self.socket_options;
+        if let Some(route) = self.route_for(target_addr) {
+            if let Some(dscp) = route.dscp {
+                // DSCP occupies the upper six bits of the TOS byte
+                options.tos = Some(u32::from(dscp) << 2);
+            }
+            if let Some(mark) = route.mark {
+                options.mark = Some(mark);
+            }
+        }
+        options
+    }
+
+    /// Resolves the target into the socket addresses to try, in order
+    ///
+    /// With NAT64 enabled, IPv4 literals are translated and domains prefer
+    /// their IPv6 addresses, falling back to translated IPv4 addresses when
+    /// the domain only has A records.
+    ///
+    /// # Arguments
+    /// * `target_addr` - The target address to resolve
+    ///
+    /// # Returns
+    /// * `Ok(Vec<SocketAddr>)` - The candidate addresses
+    /// * `Err(io::Error)` - If resolution fails
+    pub async fn resolve(&self, target_addr: &TargetAddr) -> io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.warm.as_ref().and_then(|warm| warm.cached_addrs(target_addr)) {
+            return Ok(addrs);
+        }
+        self.lookup(target_addr).await
+    }
+
+    /// Returns the first route matching the target, through the cache for domains
+    fn route_for(&self, target_addr: &TargetAddr) -> Option<&Route> {
+        match (target_addr, &self.canonical) {
+            (TargetAddr::Domain(domain, port), Some(cache)) => {
+                let index = cache.lookup(domain, *port, &self.routes).route?;
+                self.routes.routes().get(index)
+            }
+            _ => self.routes.route_for(target_addr),
+        }
+    }
+
+    /// Returns the address to connect to instead of the target, if its route rewrites it
+    fn rewrite(&self, target_addr: &TargetAddr) -> Option<TargetAddr> {
+        self.route_for(target_addr).and_then(|route| route.rewrite(target_addr))
+    }
+
+    /// Resolves the target without consulting the warm cache
+    async fn lookup(&self, target_addr: &TargetAddr) -> io::Result<Vec<SocketAddr>> {
+        let resolved: Vec<SocketAddr> = match target_addr {
+            TargetAddr::Ipv4(ip, port) => vec![SocketAddr::new(IpAddr::V4(*ip), *port)],
+            TargetAddr::Ipv6(ip, port) => vec![SocketAddr::new(IpAddr::V6(*ip), *port)],
+            TargetAddr::Domain(domain, port) => {
+                let host = match &self.canonical {
+                    Some(cache) => cache.lookup(domain, *port, &self.routes).host,
+                    None => Arc::from(domain.as_str()),
+                };
+                match self.hosts.as_ref().and_then(|hosts| hosts.lookup(&host)) {
+                    Some(ips) => ips.into_iter().map(|ip| SocketAddr::new(ip, *port)).collect(),
+                    None => self.resolve_host(&host, *port).await?,
+                }
+            }
+        };
+        Ok(self.synthesize(resolved))
+    }
+
+    /// Resolves a host name through the cache, then the configured resolver
+    async fn resolve_host(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let with_port = |ips: Vec<IpAddr>| ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect();
+        if let Some(ips) = self.dns_cache.as_ref().and_then(|cache| cache.get(host)) {
+            return Ok(with_port(ips));
+        }
+        let (ips, ttl) = match (&self.resolver, &self.dns_cache) {
+            (Some(resolver), _) => {
+                let resolution = resolver.lookup(host).await?;
+                (resolution.addrs, Some(resolution.ttl))
+            }
+            (None, Some(_)) => (self.lookup_host(host, port).await?.into_iter().map(|addr| addr.ip()).collect(), None),
+            (None, None) => return self.lookup_host(host, port).await,
+        };
+        if let Some(cache) = &self.dns_cache {
+            cache.insert(host, ips.clone(), ttl);
+        }
+        Ok(with_port(ips))
+    }
+
+    /// Resolves a host name with the system resolver within the connect deadline
+    ///
+    /// `getaddrinfo` runs on the blocking pool and keeps running when the
+    /// deadline passes, but the connection no longer waits for it.
+    async fn lookup_host(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let lookup = tokio::net::lookup_host((host, port));
+        let Some(deadline) = self.connect_timeout else {
+            return Ok(lookup.await?.collect());
+        };
+        match tokio::time::timeout(deadline, lookup).await {
+            Ok(resolved) => Ok(resolved?.collect()),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Resolving {} timed out after {:?}", host, deadline),
+            )),
+        }
+    }
+
+    /// Applies the NAT64 prefix, if any, to resolved addresses
+    fn synthesize(&self, resolved: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let Some(prefix) = self.nat64 else {
+            return resolved;
+        };
+        
+        if resolved.iter().any(SocketAddr::is_ipv6) {
+            return resolved.into_iter().filter(SocketAddr::is_ipv6).collect();
+        }
+        resolved
+            .into_iter()
+            .filter_map(|addr| match addr.ip() {
+                IpAddr::V4(v4) => Some(SocketAddr::new(IpAddr::V6(prefix.synthesize(v4)), addr.port())),
+                IpAddr::V6(_) => None,
+            })
+            .collect()
+    }
+
+    /// Opens a connection to the target without replying to any client
+    ///
+    /// Resolved addresses are tried in order until one connects. Routes that
+    /// rewrite the target's host or port are honoured.
+    ///
+    /// # Arguments
+    /// * `target_addr` - The target address to connect to
+    ///
+    /// # Returns
+    /// * `Ok(TcpStream)` - The established connection
+    /// * `Err(io::Error)` - If resolution or every connection attempt fails
+    pub async fn open(&self, target_addr: &TargetAddr) -> io::Result<TcpStream> {
+        self.open_for(None, target_addr).await
+    }
+
+    /// Opens a connection on behalf of a client, choosing its egress address
+    ///
+    /// A route that rewrites the target changes where the connection goes;
+    /// its socket options still come from the route matching the request.
+    pub async fn open_for(&self, client: Option<IpAddr>, target_addr: &TargetAddr) -> io::Result<TcpStream> {
+        let rewritten = self.rewrite(target_addr);
+        let destination = rewritten.as_ref().unwrap_or(target_addr);
+        if let Some(stream) = self.warm.as_ref().and_then(|warm| warm.take_connection(destination)) {
+            log::debug!("Using pre-connected socket to {}", destination);
+            return Ok(stream);
+        }
+        self.dial(client, target_addr, destination, None).await
+    }
+
+    /// Resolves `destination`, through SRV records if the route of
+    /// `target_addr` asks for them
+    ///
+    /// Falls back to resolving `destination` itself when the SRV lookup
+    /// fails or finds no usable records.
+    async fn resolve_routed(&self, target_addr: &TargetAddr, destination: &TargetAddr) -> io::Result<Vec<SocketAddr>> {
+        let service = self.route_for(target_addr).and_then(|route| route.srv.as_deref());
+        if let (Some(service), TargetAddr::Domain(domain, _)) = (service, destination) {
+            let name = format!("{}.{}", service, domain.trim_end_matches('.'));
+            let records = match &self.srv_resolver {
+                Some(resolver) => resolver.lookup(&name).await,
+                None => SrvResolver::system().lookup(&name).await,
+            };
+            match records {
+                Ok(records) => {
+                    let mut addrs = Vec::new();
+                    for record in records {
+                        match self.resolve(&TargetAddr::Domain(record.target.clone(), record.port)).await {
+                            Ok(resolved) => addrs.extend(resolved),
+                            Err(e) => log::debug!("Resolving SRV target {}:{} failed: {}", record.target, record.port, e),
+                        }
+                    }
+                    if !addrs.is_empty() {
+                        return Ok(addrs);
+                    }
+                    log::debug!("No usable SRV records for {}, resolving {} directly", name, destination);
+                }
+                Err(e) => log::debug!("SRV lookup of {} failed, resolving {} directly: {}", name, destination, e),
+            }
+        }
+        self.resolve(destination).await
+    }
+
+    /// Connects to `destination`, trying each resolved address in order
+    ///
+    /// Socket options come from the route matching `target_addr`, the target
+    /// the client asked for. The `exclude`d address, if any, is skipped.
+    async fn dial(
+        &self,
+        client: Option<IpAddr>,
+        target_addr: &TargetAddr,
+        destination: &TargetAddr,
+        exclude: Option<SocketAddr>,
+    ) -> io::Result<TcpStream> {
+        let addrs = self.resolve_routed(target_addr, destination).await?;
+        let base_options = self.options_for(target_addr);
+        
+        let mut last_error = None;
+        for addr in addrs.into_iter().filter(|addr| Some(*addr) != exclude) {
+            let mut options = base_options;
+            if let (Some(pool), Some(client)) = (&self.egress, client) {
+                if let Some(egress) = pool.select(client, target_addr, &addr) {
+                    options.bind_addr = Some(egress);
+                }
+            }
+            let attempt = match (options.socket_for(&addr), self.connect_timeout) {
+                (Ok(socket), Some(deadline)) => tokio::time::timeout(deadline, socket.connect(addr))
+                    .await
+                    .unwrap_or_else(|_| Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("Connecting to {} timed out after {:?}", addr, deadline),
+                    ))),
+                (Ok(socket), None) => socket.connect(addr).await,
+                (Err(e), _) => Err(e),
+            };
+            match attempt {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    log::debug!("Connecting to {} ({}) failed: {}", target_addr, addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} resolved to no addresses", target_addr))
+        }))
+    }
+
+    /// Re-resolves every warm destination and tops up its idle connections
+    pub async fn refresh_warm(&self) {
+        let Some(warm) = &self.warm else {
+            return;
+        };
+        for target in &warm.config().targets {
+            let destination = self.rewrite(target).unwrap_or_else(|| target.clone());
+            match self.lookup(&destination).await {
+                Ok(addrs) => warm.store_addrs(&destination, addrs),
+                Err(e) => log::warn!("Resolving warm destination {} failed: {}", destination, e),
+            }
+            while warm.idle_connections(&destination) < warm.config().idle_connections {
+                match self.dial(None, target, &destination, None).await {
+                    Ok(stream) => warm.store_connection(&destination, stream),
+                    Err(e) => {
+                        log::warn!("Pre-connecting to warm destination {} failed: {}", destination, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Spawns a task that refreshes the warm destinations periodically
+    ///
+    /// # Returns
+    /// * The task handle, or `None` when no warm destinations are configured
+    pub fn spawn_warm_refresh(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = self.warm.as_ref()?.config().refresh_interval;
+        let connector = self.clone();
+        Some(tokio::spawn(async move {
+            loop {
+                connector.refresh_warm().await;
+                tokio::time::sleep(interval).await;
+            }
+        }))
+    }
+
+    /// Spawns a task that reloads the hosts file whenever it changes
+    ///
+    /// # Returns
+    /// * The task handle, or `None` when no hosts file is configured
+    pub fn spawn_hosts_reload(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let hosts = Arc::clone(self.hosts.as_ref().filter(|hosts| hosts.path().is_some())?);
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(hosts::RELOAD_INTERVAL).await;
+                match hosts.reload() {
+                    Ok(true) => log::info!("Reloaded {} host overrides", hosts.len()),
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Keeping previous host overrides: {}", e),
+                }
+            }
+        }))
+    }
+
+    /// Establishes a connection to the target server and replies to the client
+    ///
+    /// # Arguments
+    /// * `client_stream` - The client stream for sending replies
+    /// * `target_addr` - The target address to connect to
+    ///
+    /// # Returns
+    /// * `Ok(TcpStream)` - The established connection to the target server
+    /// * `Err(Socks5Error)` - If connection fails
+    pub async fn connect<S: ClientStream>(
+        &self,
+        client_stream: &mut S,
+        target_addr: &TargetAddr,
+    ) -> Socks5Result<TcpStream> {
+        // Log connection attempt
+        log::info!("Connecting to target: {}", target_addr);
+        
+        let client = client_stream.client_addr().map(|addr| addr.ip());
+        if self.reply_mode == ReplyMode::Optimistic {
+            send_success_reply(client_stream).await?;
+            return self.open_for(client, target_addr).await.map_err(|e| {
+                Socks5Error::ConnectionError(format!(
+                    "Failed to connect to target {} after optimistic reply: {}", target_addr, e
+                ))
+            });
+        }
+        
+        // Attempt to connect to the target server
+        let opened = match self.open_for(client, target_addr).await {
+            Ok(stream) => match self.reply_mode {
+                ReplyMode::Verified { settle } => self.verify_or_retry(client, target_addr, stream, settle).await,
+                ReplyMode::Immediate | ReplyMode::Optimistic => Ok(stream),
+            },
+            Err(e) => Err(e),
+        };
+        
+        match opened {
+            Ok(stream) => {
+                // Connection successful, send success reply to client
+                send_success_reply(client_stream).await?;
+                log::info!("Successfully connected to target: {}", target_addr);
+                Ok(stream)
+            }
+            Err(e) => {
+                // Connection failed, determine appropriate error code
+                let reply_code = reply_code_for_io_error(&e);
+                
+                // Send error reply to client
+                send_failure(client_stream, reply_code).await?;
+                
+                // Return error
+                Err(Socks5Error::ConnectionError(format!(
+                    "Failed to connect to target {}: {}", target_addr, e
+                )))
+            }
+        }
+    }
+
+    /// Checks a fresh connection like [`verify_reachable`], retrying once at
+    /// another address of the target if it closed the connection right away
+    /// and retries are enabled
+    async fn verify_or_retry(
+        &self,
+        client: Option<IpAddr>,
+        target_addr: &TargetAddr,
+        stream: TcpStream,
+        settle: Duration,
+    ) -> io::Result<TcpStream> {
+        let peer = stream.peer_addr().ok();
+        let e = match verify_reachable(stream, settle).await {
+            Err(e) if self.early_close_retry && is_early_close(&e) => e,
+            result => return result,
+        };
+        log::warn!("Target {} ({:?}) closed the connection right after accepting it, trying another address: {}",
+                 target_addr, peer, e);
+        let rewritten = self.rewrite(target_addr);
+        let destination = rewritten.as_ref().unwrap_or(target_addr);
+        match self.dial(client, target_addr, destination, peer).await {
+            Ok(stream) => verify_reachable(stream, settle).await,
+            Err(retry_error) => {
+                log::debug!("No other address of {} could be reached: {}", target_addr, retry_error);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Establishes a connection to the target server through upstream proxies.
+///
+/// # Arguments
+/// * `client_stream` - The client stream for sending replies
+/// * `target_addr` - The target address to connect to
+/// * `upstreams` - The upstream proxies to tunnel through
+///
+/// # Returns
+/// * `Ok(TcpStream)` - A stream tunneled to the target server
+/// * `Err(Socks5Error)` - If no upstream could reach the target
+pub async fn connect_via_upstreams<S: AsyncRead + AsyncWrite + Unpin>(
+    client_stream: &mut S,
+    target_addr: &TargetAddr,
+    upstreams: &Upstreams,
+) -> Socks5Result<TcpStream> {
+    log::info!("Connecting to target {} through {} upstream chains",
+             target_addr, upstreams.chains().len());
     
-    // Attempt to connect to the target server
-    match TcpStream::connect(&addr_string).await {
+    match upstreams.connect(target_addr).await {
         Ok(stream) => {
-            // Connection successful, send success reply to client
             send_success_reply(client_stream).await?;
-            log::info!("Successfully connected to target: {}", addr_string);
+            log::info!("Successfully connected to target through upstream: {}", target_addr);
             Ok(stream)
         }
         Err(e) => {
-            // Connection failed, determine appropriate error code
-            let reply_code = match e.kind() {
-                std::io::ErrorKind::ConnectionRefused => reply::CONNECTION_REFUSED,
-                std::io::ErrorKind::TimedOut => reply::HOST_UNREACHABLE,
-                std::io::ErrorKind::AddrNotAvailable => reply::NETWORK_UNREACHABLE,
-                _ => reply::HOST_UNREACHABLE, // Default to host unreachable
+            // Pass the upstream's own reply code through where there is one
+            let reply_code = match &e {
+                Socks5Error::ReplyError(code) => *code,
+                Socks5Error::IoError(io_error) => reply_code_for_io_error(io_error),
+                _ => reply::GENERAL_FAILURE,
             };
+            send_failure(client_stream, reply_code).await?;
             
-            // Send error reply to client
-            send_reply(client_stream, reply_code).await?;
-            
-            // Return error
             Err(Socks5Error::ConnectionError(format!(
-                "Failed to connect to target {}: {}", addr_string, e
+                "Failed to connect to target {} through upstreams: {}", target_addr, e
             )))
         }
     }
 }
 
+/// Checks that a fresh target connection stays usable for `settle`
+///
+/// Data sent by the target is left in place for the relay; only an early
+/// close or reset fails the check.
+async fn verify_reachable(stream: TcpStream, settle: Duration) -> io::Result<TcpStream> {
+    stream.writable().await?;
+    
+    let mut probe = [0; 1];
+    match tokio::time::timeout(settle, stream.peek(&mut probe)).await {
+        // Nothing happened within the settle time, or the target spoke first
+        Err(_) | Ok(Ok(1..)) => Ok(stream),
+        Ok(Ok(_)) => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "target closed the connection right after accepting it",
+        )),
+        Ok(Err(e)) => Err(e),
+    }
+}
+
+/// Returns whether a failed [`verify_reachable`] check means the target
+/// closed or reset the connection rather than being unreachable
+fn is_early_close(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset)
+}
+
+/// Maps a connection error to the SOCKS5 reply code sent to the client
+fn reply_code_for_io_error(e: &io::Error) -> u8 {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => reply::CONNECTION_REFUSED,
+        io::ErrorKind::TimedOut => reply::HOST_UNREACHABLE,
+        io::ErrorKind::AddrNotAvailable => reply::NETWORK_UNREACHABLE,
+        _ => reply::HOST_UNREACHABLE, // Default to host unreachable
+    }
+}
+
 /// A struct representing a connection to a target server
 pub struct TargetConnection {
     /// The TCP stream connected to the target server
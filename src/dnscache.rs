@@ -0,0 +1,153 @@
+//! Host name resolution caching for the SOCKS5 proxy.
+//!
+//! Browsers open many connections to the same few domains, and each CONNECT
+//! would otherwise resolve its domain again. [`DnsCache`] keeps the
+//! addresses of recently resolved host names for as long as their records
+//! allow. Results of the system resolver carry no TTL, so they are kept for
+//! a fixed time; an override can replace every TTL, and all of them are
+//! capped.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::lru::LruMap;
+
+/// How many host names are cached by default
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// How long addresses without a TTL, from the system resolver, are kept
+pub const DEFAULT_TTL: Duration = Duration::from_secs(30);
+
+/// The longest any addresses are kept by default, whatever their TTL
+pub const DEFAULT_MAX_TTL: Duration = Duration::from_secs(3600);
+
+/// Cached addresses of a host name
+#[derive(Debug)]
+struct Entry {
+    /// The addresses in the order they were resolved
+    addrs: Vec<IpAddr>,
+    /// When the addresses must be resolved again
+    expires: Instant,
+}
+
+/// A bounded cache of resolved host names
+///
+/// Host names are compared case-insensitively and without trailing dot.
+#[derive(Debug)]
+pub struct DnsCache {
+    /// How long addresses are kept instead of their TTL, if overridden
+    ttl_override: Option<Duration>,
+    /// The longest addresses are kept
+    max_ttl: Duration,
+    /// Time source for expiry
+    clock: Arc<dyn Clock>,
+    /// Addresses per host name
+    entries: Mutex<LruMap<String, Entry>>,
+    /// Lookups answered from the cache
+    hits: AtomicU64,
+    /// Lookups that had to resolve
+    misses: AtomicU64,
+}
+
+impl DnsCache {
+    /// Creates a cache that keeps addresses for their TTL
+    ///
+    /// # Arguments
+    /// * `capacity` - How many host names are kept at most
+    /// * `clock` - Time source for expiry
+    pub fn new(capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl_override: None,
+            max_ttl: DEFAULT_MAX_TTL,
+            clock,
+            entries: Mutex::new(LruMap::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Keeps all addresses for `ttl` instead of their records' TTL
+    pub fn with_ttl_override(mut self, ttl: Duration) -> Self {
+        self.ttl_override = Some(ttl);
+        self
+    }
+
+    /// Keeps no addresses longer than `max_ttl`, overridden TTLs included
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Returns the TTL that replaces the records' TTL, if any
+    pub fn ttl_override(&self) -> Option<Duration> {
+        self.ttl_override
+    }
+
+    /// Returns the longest addresses are kept
+    pub fn max_ttl(&self) -> Duration {
+        self.max_ttl
+    }
+
+    /// Returns the cached addresses of `host`, unless they expired
+    pub fn get(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get_mut(&key(host)).filter(|entry| entry.expires > now) {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.addrs.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Stores freshly resolved addresses of `host`
+    ///
+    /// # Arguments
+    /// * `host` - The host name that was resolved
+    /// * `addrs` - Its addresses; nothing is stored when empty
+    /// * `ttl` - How long the records may be cached, if the resolver knows
+    pub fn insert(&self, host: &str, addrs: Vec<IpAddr>, ttl: Option<Duration>) {
+        let ttl = self.ttl_override.or(ttl).unwrap_or(DEFAULT_TTL).min(self.max_ttl);
+        let Some(expires) = self.clock.now().checked_add(ttl) else {
+            return;
+        };
+        if addrs.is_empty() || ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(key(host), Entry { addrs, expires });
+    }
+
+    /// Returns the number of cached host names, expired ones included
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many lookups were answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many lookups had to resolve
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the cache key of a host name
+fn key(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
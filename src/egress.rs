@@ -0,0 +1,156 @@
+//! Egress address selection for the SOCKS5 proxy.
+//!
+//! With several local addresses available for outbound connections, the pool
+//! spreads connections over them. Sticky mode keeps each (client, target host)
+//! pair on the same address so sites that dislike IP changes mid-login see a
+//! stable source, and rebalances a pair once it has been idle for the TTL.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::lru::LruMap;
+use crate::protocol::TargetAddr;
+
+/// Sticky assignments are pruned once the table grows beyond this many pairs
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// The least recently used sticky assignments are evicted beyond this many
+const MAX_SESSIONS: usize = 16384;
+
+/// Identifies a sticky session: client IP, target host and address family
+type SessionKey = (IpAddr, String, bool);
+
+/// The egress address a session is pinned to
+#[derive(Debug)]
+struct Assignment {
+    /// The pinned local address
+    egress: IpAddr,
+    /// When the session last opened a connection
+    last_used: Instant,
+}
+
+/// A set of local addresses outbound connections are spread over
+#[derive(Debug)]
+pub struct EgressPool {
+    /// The local addresses to bind outbound connections to
+    addrs: Vec<IpAddr>,
+    /// How long an idle sticky session keeps its address, if sticky
+    sticky_ttl: Option<Duration>,
+    /// Time source for session expiry
+    clock: Arc<dyn Clock>,
+    /// Round-robin position for non-sticky selection
+    next: AtomicUsize,
+    /// Sticky assignments per session, bounded against clients cycling hosts
+    sessions: Mutex<LruMap<SessionKey, Assignment>>,
+    /// Randomly keyed hasher spreading new sessions over tied addresses
+    hasher: RandomState,
+}
+
+impl EgressPool {
+    /// Creates a round-robin pool
+    ///
+    /// # Arguments
+    /// * `addrs` - The local addresses to bind outbound connections to
+    /// * `clock` - Time source for sticky session expiry
+    pub fn new(addrs: Vec<IpAddr>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            addrs,
+            sticky_ttl: None,
+            clock,
+            next: AtomicUsize::new(0),
+            sessions: Mutex::new(LruMap::new(MAX_SESSIONS)),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Pins each (client, target host) pair to one address until it has been
+    /// idle for `ttl`, after which it moves to the least used address
+    pub fn with_stickiness(mut self, ttl: Duration) -> Self {
+        self.sticky_ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the addresses in the pool
+    pub fn addrs(&self) -> &[IpAddr] {
+        &self.addrs
+    }
+
+    /// Returns the sticky session TTL, if sticky
+    pub fn sticky_ttl(&self) -> Option<Duration> {
+        self.sticky_ttl
+    }
+
+    /// Returns how many sticky sessions were evicted because the table was full
+    pub fn evictions(&self) -> u64 {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).evictions()
+    }
+
+    /// Chooses the local address for a connection
+    ///
+    /// # Arguments
+    /// * `client` - The IP of the client the connection is made for
+    /// * `target` - The requested target
+    /// * `dest` - The resolved address being connected to
+    ///
+    /// # Returns
+    /// * `Some(IpAddr)` - The local address to bind to
+    /// * `None` - If the pool has no address of the destination's family
+    pub fn select(&self, client: IpAddr, target: &TargetAddr, dest: &SocketAddr) -> Option<IpAddr> {
+        let candidates: Vec<IpAddr> = self.addrs.iter()
+            .copied()
+            .filter(|addr| addr.is_ipv4() == dest.is_ipv4())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        
+        let Some(ttl) = self.sticky_ttl else {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            return Some(candidates[index % candidates.len()]);
+        };
+        
+        let now = self.clock.now();
+        let key = (client, target_host(target), dest.is_ipv4());
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        if sessions.len() > PRUNE_THRESHOLD {
+            sessions.retain(|_, assignment| now.duration_since(assignment.last_used) < ttl);
+        }
+        
+        // Keep a live session on its address
+        if let Some(assignment) = sessions.get_mut(&key) {
+            if now.duration_since(assignment.last_used) < ttl && candidates.contains(&assignment.egress) {
+                assignment.last_used = now;
+                return Some(assignment.egress);
+            }
+        }
+        
+        // New or expired sessions go to the least used address; the hash of
+        // the pair breaks ties so equal loads still spread out
+        let mut load: HashMap<IpAddr, usize> = HashMap::new();
+        for assignment in sessions.values().filter(|assignment| now.duration_since(assignment.last_used) < ttl) {
+            *load.entry(assignment.egress).or_default() += 1;
+        }
+        let offset = self.hasher.hash_one(&key) as usize % candidates.len();
+        let egress = (0..candidates.len())
+            .map(|i| candidates[(offset + i) % candidates.len()])
+            .min_by_key(|addr| load.get(addr).copied().unwrap_or(0))?;
+        sessions.insert(key, Assignment { egress, last_used: now });
+        Some(egress)
+    }
+}
+
+/// Returns the target's host in a normalized textual form
+fn target_host(target: &TargetAddr) -> String {
+    match target {
+        TargetAddr::Ipv4(ip, _) => ip.to_string(),
+        TargetAddr::Ipv6(ip, _) => ip.to_string(),
+        TargetAddr::Domain(domain, _) => domain.trim_end_matches('.').to_ascii_lowercase(),
+    }
+}
@@ -5,6 +5,9 @@
 
 use std::fmt;
 use std::io;
+use std::time::Duration;
+
+use rsocks5_core::WireError;
 
 /// Custom error type for SOCKS5 protocol operations
 #[derive(Debug)]
@@ -24,6 +27,18 @@ pub enum Socks5Error {
     /// Error during data relay
     RelayError(String),
     
+    /// A remote SOCKS5 server answered with a failure reply code
+    ReplyError(u8),
+    
+    /// A remote SOCKS5 server denied the request and explained why
+    DeniedError(u8, String),
+    
+    /// Invalid or unreadable configuration
+    ConfigError(String),
+    
+    /// A user-provided hook did not finish within its time budget
+    HookTimeoutError(String, Duration),
+    
     /// Underlying IO error
     IoError(io::Error),
 }
@@ -36,6 +51,10 @@ impl fmt::Display for Socks5Error {
             Socks5Error::AddressError(msg) => write!(f, "SOCKS5 address error: {}", msg),
             Socks5Error::ConnectionError(msg) => write!(f, "SOCKS5 connection error: {}", msg),
             Socks5Error::RelayError(msg) => write!(f, "SOCKS5 relay error: {}", msg),
+            Socks5Error::ReplyError(code) => write!(f, "SOCKS5 server replied with error code: {:#04x}", code),
+            Socks5Error::DeniedError(code, reason) => write!(f, "SOCKS5 server denied the request ({:#04x}): {}", code, reason),
+            Socks5Error::ConfigError(msg) => write!(f, "SOCKS5 configuration error: {}", msg),
+            Socks5Error::HookTimeoutError(hook, budget) => write!(f, "SOCKS5 hook timeout: {} did not finish within {:?}", hook, budget),
             Socks5Error::IoError(e) => write!(f, "IO error: {}", e),
         }
     }
@@ -49,5 +68,14 @@ impl From<io::Error> for Socks5Error {
     }
 }
 
+impl From<WireError> for Socks5Error {
+    fn from(error: WireError) -> Self {
+        match error {
+            WireError::AddressType(_) | WireError::Address(_) => Socks5Error::AddressError(error.to_string()),
+            _ => Socks5Error::HandshakeError(error.to_string()),
+        }
+    }
+}
+
 /// Result type for SOCKS5 operations
 pub type Socks5Result<T> = Result<T, Socks5Error>;
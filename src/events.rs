@@ -0,0 +1,223 @@
+//! Session event stream for the SOCKS5 proxy.
+//!
+//! Each client session goes through a few milestones: it is accepted,
+//! authenticated, connected to its target and finally closed. With an
+//! [`EventWriter`], the server reports each of them as a [`SessionEvent`]
+//! JSON line to stdout, a file or a FIFO, so scripts can follow proxy
+//! activity without parsing logs or running an admin interface. Alerts of
+//! breached connect latency objectives go to the same stream.
+
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many events may wait for the writer thread before new ones are dropped
+const QUEUE_CAPACITY: usize = 4096;
+
+/// A milestone of one client session
+///
+/// Serialized with an `event` field naming the variant, e.g.
+/// `{"event":"accepted","conn_id":"0badc0de",...}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// A client connected to a listener
+    Accepted {
+        /// The connection ID used in log lines, as hex
+        conn_id: String,
+        /// Wall-clock time in milliseconds since the Unix epoch
+        timestamp_ms: u64,
+        /// The client's address
+        client: SocketAddr,
+        /// The address of the listener that accepted the client
+        listener: String,
+    },
+    /// The client completed the SOCKS5 handshake
+    Authenticated {
+        /// The connection ID used in log lines, as hex
+        conn_id: String,
+        /// Wall-clock time in milliseconds since the Unix epoch
+        timestamp_ms: u64,
+        /// The authenticated username, if any
+        username: Option<String>,
+        /// The authentication method selected in the handshake
+        method: u8,
+    },
+    /// The proxy connected to the client's target
+    Connected {
+        /// The connection ID used in log lines, as hex
+        conn_id: String,
+        /// Wall-clock time in milliseconds since the Unix epoch
+        timestamp_ms: u64,
+        /// The requested target as `host:port`
+        target: String,
+    },
+    /// The session ended
+    Closed {
+        /// The connection ID used in log lines, as hex
+        conn_id: String,
+        /// Wall-clock time in milliseconds since the Unix epoch
+        timestamp_ms: u64,
+        /// Why the session ended, as counted in the metrics, e.g. `completed`
+        reason: &'static str,
+        /// How long the session lasted
+        duration_ms: u64,
+        /// Bytes relayed from the client to the target
+        bytes_from_client: u64,
+        /// Bytes relayed from the target to the client
+        bytes_from_target: u64,
+    },
+    /// Connects to a destination breached its latency objective repeatedly
+    SloBreached {
+        /// The connection ID of the connect that raised the alert, as hex
+        conn_id: String,
+        /// Wall-clock time in milliseconds since the Unix epoch
+        timestamp_ms: u64,
+        /// The breached objective, e.g. `*.payments.internal>300ms`
+        slo: String,
+        /// The target of the connect that raised the alert, as `host:port`
+        target: String,
+        /// How long that connect took
+        latency_ms: u64,
+        /// How many breaches fell into the window
+        breaches: u32,
+        /// How far back breaches were counted
+        window_ms: u64,
+    },
+}
+
+/// Returns a wall-clock time in milliseconds since the Unix epoch
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+}
+
+/// Writes session events as JSON lines from a background thread
+///
+/// Publishing never blocks a connection: events wait in a bounded queue
+/// and are dropped and counted when the reader falls behind. Each line is
+/// flushed once the queue runs dry, so pipes see events as they happen.
+#[derive(Debug)]
+pub struct EventWriter {
+    /// Hands encoded events to the writer thread
+    queue: Option<SyncSender<Vec<u8>>>,
+    /// The writer thread
+    thread: Option<JoinHandle<()>>,
+    /// Where events are written, for logs
+    destination: String,
+    /// Events that could not be queued or written
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventWriter {
+    /// Writes events to any output, e.g. a socket or a buffer in tests
+    ///
+    /// # Arguments
+    /// * `output` - Where the JSON lines are written
+    /// * `destination` - A description of the output for logs
+    pub fn new(output: impl Write + Send + 'static, destination: impl Into<String>) -> io::Result<Self> {
+        let destination = destination.into();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (queue, events) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let thread = thread::Builder::new()
+            .name("event-writer".to_string())
+            .spawn({
+                let dropped = Arc::clone(&dropped);
+                let destination = destination.clone();
+                move || write_events(BufWriter::new(output), &destination, events, &dropped)
+            })?;
+        Ok(Self {
+            queue: Some(queue),
+            thread: Some(thread),
+            destination,
+            dropped,
+        })
+    }
+
+    /// Writes events to stdout
+    pub fn stdout() -> io::Result<Self> {
+        Self::new(io::stdout(), "stdout")
+    }
+
+    /// Appends events to a file, creating it if needed
+    ///
+    /// Opening a FIFO waits until a reader has opened it.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Self::new(file, path.display().to_string())
+    }
+
+    /// Returns where events are written
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Returns how many events could not be queued or written
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queues an event without waiting
+    pub fn publish(&self, event: &SessionEvent) {
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(e) => {
+                log::debug!("Cannot encode session event: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        if let Err(e) = queue.try_send(line) {
+            if matches!(e, TrySendError::Full(_)) {
+                log::debug!("Event reader on {} is behind, dropping an event", self.destination);
+            }
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes all queued events and stops the writer thread
+    pub fn close(mut self) {
+        self.stop();
+    }
+
+    /// Closes the queue and waits for the writer thread to finish
+    fn stop(&mut self) {
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for EventWriter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Writes queued events until the queue is closed
+fn write_events<W: Write>(mut output: W, destination: &str, events: Receiver<Vec<u8>>, dropped: &AtomicU64) {
+    while let Ok(line) = events.recv() {
+        let mut pending = Some(line);
+        // Write whatever else is queued, then flush once the queue runs dry
+        while let Some(line) = pending.take().or_else(|| events.try_recv().ok()) {
+            if let Err(e) = output.write_all(&line) {
+                log::warn!("Cannot write session event to {}: {}", destination, e);
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if let Err(e) = output.flush() {
+            log::warn!("Cannot flush session events to {}: {}", destination, e);
+        }
+    }
+}
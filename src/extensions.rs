@@ -0,0 +1,203 @@
+//! Negotiation of vendor wire extensions for the SOCKS5 proxy.
+//!
+//! RFC 1928 reserves methods X'80' to X'FE' for private use. A wire
+//! extension claims one of them as its marker: a client offers the marker
+//! alongside its real authentication methods, the server never selects it,
+//! and both sides then know the extension is in effect for the rest of the
+//! connection. Standard clients never offer a marker, so they never see
+//! extension bytes.
+//!
+//! [`ExtensionRegistry`] holds the extensions a server supports and
+//! negotiates them once per connection, right after the method greeting.
+//! Extensions act through the handlers of [`WireExtension`] rather than
+//! being special-cased in the request path. A changed wire format takes a
+//! new marker, so clients keep getting the version they asked for.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::constants::auth;
+use crate::error::{Socks5Error, Socks5Result};
+
+/// The methods RFC 1928 reserves for private use
+pub const PRIVATE_METHODS: RangeInclusive<u8> = 0x80..=0xFE;
+
+/// An optional extension of the SOCKS5 wire format
+///
+/// Handlers have default implementations that leave the wire format
+/// unchanged, so an extension only implements the ones it needs.
+pub trait WireExtension: Send + Sync + fmt::Debug {
+    /// Returns the extension's name, e.g. `denial-reasons`
+    fn name(&self) -> &'static str;
+
+    /// Returns the private method a client offers to ask for the extension
+    fn method(&self) -> u8;
+
+    /// Returns the version of the extension's wire format
+    fn version(&self) -> u8 {
+        1
+    }
+
+    /// Returns bytes to send after a failure reply, if any
+    ///
+    /// The trailer must be self-delimiting: trailers of several negotiated
+    /// extensions are sent back to back in registration order.
+    ///
+    /// # Arguments
+    /// * `reply_code` - The reply code the client gets
+    /// * `reason` - Why the request failed, e.g. `allow-list: target is not allowed`
+    fn failure_trailer(&self, _reply_code: u8, _reason: &str) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Appends the reason for a failure reply, as one length byte and up to
+/// 255 bytes of UTF-8 text
+///
+/// Clients ask for it by offering [`auth::DENIAL_REASONS`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DenialReasons;
+
+impl WireExtension for DenialReasons {
+    fn name(&self) -> &'static str {
+        "denial-reasons"
+    }
+
+    fn method(&self) -> u8 {
+        auth::DENIAL_REASONS
+    }
+
+    fn failure_trailer(&self, _reply_code: u8, reason: &str) -> Option<Vec<u8>> {
+        Some(encode_denial_reason(reason))
+    }
+}
+
+/// Encodes a denial reason as one length byte and up to 255 bytes of UTF-8
+///
+/// Longer reasons are cut at the last character boundary that fits.
+pub fn encode_denial_reason(reason: &str) -> Vec<u8> {
+    let mut len = reason.len().min(255);
+    while !reason.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut trailer = Vec::with_capacity(1 + len);
+    trailer.push(len as u8);
+    trailer.extend_from_slice(&reason.as_bytes()[..len]);
+    trailer
+}
+
+/// The wire extensions a server supports
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionRegistry {
+    /// The supported extensions, in registration order
+    extensions: Vec<Arc<dyn WireExtension>>,
+}
+
+impl ExtensionRegistry {
+    /// Creates a registry without extensions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an extension, replacing a registered one of the same name
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the extension was registered
+    /// * `Err(Socks5Error)` - If its method is not private or claimed by
+    ///   another extension
+    pub fn register(&mut self, extension: Arc<dyn WireExtension>) -> Socks5Result<()> {
+        let method = extension.method();
+        if !PRIVATE_METHODS.contains(&method) {
+            return Err(Socks5Error::ConfigError(format!(
+                "Extension {} uses method {:#04x} outside the private range", extension.name(), method
+            )));
+        }
+        if let Some(other) = self.extensions.iter().find(|other| other.method() == method && other.name() != extension.name()) {
+            return Err(Socks5Error::ConfigError(format!(
+                "Extension {} uses method {:#04x}, already claimed by {}", extension.name(), method, other.name()
+            )));
+        }
+        match self.extensions.iter_mut().find(|other| other.name() == extension.name()) {
+            Some(slot) => *slot = extension,
+            None => self.extensions.push(extension),
+        }
+        Ok(())
+    }
+
+    /// Removes the extension with the given name
+    ///
+    /// # Returns
+    /// * Whether an extension was removed
+    pub fn unregister(&mut self, name: &str) -> bool {
+        let before = self.extensions.len();
+        self.extensions.retain(|extension| extension.name() != name);
+        self.extensions.len() < before
+    }
+
+    /// Returns the extension with the given name, if registered
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn WireExtension>> {
+        self.extensions.iter().find(|extension| extension.name() == name)
+    }
+
+    /// Returns the names of the registered extensions, in registration order
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.extensions.iter().map(|extension| extension.name())
+    }
+
+    /// Returns whether no extension is registered
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Picks the extensions a client asked for by offering their methods
+    ///
+    /// # Arguments
+    /// * `offered_methods` - The methods from the client's greeting
+    pub fn negotiate(&self, offered_methods: &[u8]) -> Negotiated {
+        Negotiated {
+            extensions: self
+                .extensions
+                .iter()
+                .filter(|extension| offered_methods.contains(&extension.method()))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// The wire extensions in effect for one connection
+#[derive(Debug, Clone, Default)]
+pub struct Negotiated {
+    /// The negotiated extensions, in registration order
+    extensions: Vec<Arc<dyn WireExtension>>,
+}
+
+impl Negotiated {
+    /// Returns whether the named extension is in effect
+    pub fn contains(&self, name: &str) -> bool {
+        self.extensions.iter().any(|extension| extension.name() == name)
+    }
+
+    /// Returns the negotiated extensions with their versions, e.g. `denial-reasons/1`
+    pub fn names(&self) -> Vec<String> {
+        self.extensions
+            .iter()
+            .map(|extension| format!("{}/{}", extension.name(), extension.version()))
+            .collect()
+    }
+
+    /// Returns whether no extension is in effect
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Collects the bytes the negotiated extensions send after a failure reply
+    pub fn failure_trailer(&self, reply_code: u8, reason: &str) -> Vec<u8> {
+        self.extensions
+            .iter()
+            .filter_map(|extension| extension.failure_trailer(reply_code, reason))
+            .flatten()
+            .collect()
+    }
+}
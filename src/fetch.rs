@@ -0,0 +1,142 @@
+//! A minimal HTTP client that goes through a SOCKS5 proxy.
+//!
+//! [`fetch`] sends one HTTP/1.1 GET through a proxy with the client side of
+//! the protocol and reads the whole response, which makes a dependency-free
+//! end-to-end check of whether web traffic works through a given proxy and
+//! its policies. Like curl's `socks5://` and `socks5h://` proxies, the host
+//! is either resolved locally or handed to the proxy to resolve.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{self, TcpStream};
+
+use crate::client::{self, Credentials};
+use crate::error::{Socks5Error, Socks5Result};
+use crate::http::{parse_authority, MAX_HEAD_SIZE};
+use crate::protocol::TargetAddr;
+
+/// An `http://` URL split into what the request needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    /// The host and port to connect to; port 80 unless given
+    pub target: TargetAddr,
+    /// The `host[:port]` sent in the `Host` header
+    pub authority: String,
+    /// The path and query, at least `/`
+    pub path: String,
+}
+
+impl HttpUrl {
+    /// Parses an absolute `http://` URL; a fragment is dropped
+    pub fn parse(url: &str) -> Socks5Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| Socks5Error::ConfigError(format!("Only http:// URLs can be fetched: {}", url)))?;
+        let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        Ok(Self {
+            target: parse_authority(authority)?,
+            authority: authority.to_string(),
+            path: if path.starts_with('?') { format!("/{}", path) } else { path.to_string() },
+        })
+    }
+}
+
+/// A response read by [`fetch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The status code, e.g. 200
+    pub status: u16,
+    /// The status line, e.g. `HTTP/1.1 200 OK`
+    pub status_line: String,
+    /// The header lines, without the status line
+    pub headers: Vec<String>,
+    /// The body, with chunked transfer coding removed
+    pub body: Vec<u8>,
+}
+
+/// Sends a GET for `url` through the SOCKS5 proxy at `proxy` and reads the response
+///
+/// # Arguments
+/// * `proxy` - The proxy's `host:port`
+/// * `url` - The `http://` URL to fetch
+/// * `remote_dns` - Whether the proxy resolves the host (`socks5h`) instead of this side
+/// * `credentials` - Credentials for the proxy, if it requires them
+///
+/// # Returns
+/// * `Ok(Response)` - The response, whatever its status
+/// * `Err(Socks5Error)` - If the URL is invalid, the proxy refuses or the
+///   response is malformed
+pub async fn fetch(proxy: &str, url: &str, remote_dns: bool, credentials: Option<&Credentials>) -> Socks5Result<Response> {
+    let url = HttpUrl::parse(url)?;
+    let target = match &url.target {
+        TargetAddr::Domain(host, port) if !remote_dns => {
+            let resolved = net::lookup_host((host.as_str(), *port)).await?.next().ok_or_else(|| {
+                Socks5Error::AddressError(format!("Cannot resolve {}", host))
+            })?;
+            TargetAddr::from(resolved)
+        }
+        target => target.clone(),
+    };
+
+    let mut stream = TcpStream::connect(proxy)
+        .await
+        .map_err(|e| Socks5Error::ConnectionError(format!("Cannot reach proxy {}: {}", proxy, e)))?;
+    client::connect(&mut stream, &target, credentials).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rsocks5/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        url.path,
+        url.authority,
+        env!("CARGO_PKG_VERSION")
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+    parse_response(&raw)
+}
+
+/// Parses a complete HTTP/1.x response
+pub fn parse_response(raw: &[u8]) -> Socks5Result<Response> {
+    let malformed = |what: &str| Socks5Error::RelayError(format!("Malformed HTTP response: {}", what));
+    let end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .filter(|end| *end <= MAX_HEAD_SIZE)
+        .ok_or_else(|| malformed("no end of head"))?;
+    let head = std::str::from_utf8(&raw[..end]).map_err(|_| malformed("head is not UTF-8"))?;
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default().to_string();
+    let status = status_line
+        .strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| malformed("bad status line"))?;
+    let headers: Vec<String> = lines.map(str::to_string).collect();
+    let chunked = headers.iter().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding") && value.to_ascii_lowercase().contains("chunked")
+        })
+    });
+    let body = &raw[end + 4..];
+    let body = if chunked { dechunk(body).ok_or_else(|| malformed("bad chunk"))? } else { body.to_vec() };
+    Ok(Response { status, status_line, headers, body })
+}
+
+/// Removes chunked transfer coding from a body
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(body.len());
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
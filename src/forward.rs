@@ -0,0 +1,116 @@
+//! Local port forwarding through a SOCKS5 proxy.
+//!
+//! [`Forwarder`] listens locally and tunnels every accepted connection
+//! through a SOCKS5 proxy to one fixed target, much like `ssh -L`: tools
+//! that cannot speak SOCKS connect to the local port and reach the target
+//! as if it were local. The proxy is dialed anew for each connection, using
+//! the client side of the protocol in [`crate::client`].
+
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::client::{self, Credentials};
+use crate::error::{Socks5Error, Socks5Result};
+use crate::protocol::TargetAddr;
+use crate::relay::{Relay, RelayOptions};
+
+/// Tunnels local connections through a SOCKS5 proxy to a fixed target
+#[derive(Debug, Clone)]
+pub struct Forwarder {
+    /// The proxy's `host:port`
+    proxy: String,
+    /// Where the proxy is asked to connect to
+    target: TargetAddr,
+    /// Credentials for the proxy, if it requires them
+    credentials: Option<Credentials>,
+    /// How data is relayed once a tunnel is up
+    relay_options: RelayOptions,
+}
+
+impl Forwarder {
+    /// Creates a forwarder to `target` through the proxy at `proxy`
+    ///
+    /// # Arguments
+    /// * `proxy` - The proxy's `host:port`; host names are resolved per connection
+    /// * `target` - Where the proxy is asked to connect to
+    pub fn new(proxy: impl Into<String>, target: TargetAddr) -> Self {
+        Self {
+            proxy: proxy.into(),
+            target,
+            credentials: None,
+            relay_options: RelayOptions::default(),
+        }
+    }
+
+    /// Authenticates to the proxy with username/password credentials
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Sets how data is relayed once a tunnel is up
+    pub fn with_relay_options(mut self, relay_options: RelayOptions) -> Self {
+        self.relay_options = relay_options;
+        self
+    }
+
+    /// Returns the proxy's `host:port`
+    pub fn proxy(&self) -> &str {
+        &self.proxy
+    }
+
+    /// Returns where the proxy is asked to connect to
+    pub fn target(&self) -> &TargetAddr {
+        &self.target
+    }
+
+    /// Returns the credentials for the proxy, if any
+    pub fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    /// Accepts connections and tunnels each one in its own task
+    ///
+    /// Only returns if accepting fails; failures of single tunnels are
+    /// logged and close that connection only.
+    ///
+    /// # Arguments
+    /// * `listener` - The local listener to accept connections from
+    pub async fn serve(&self, listener: TcpListener) -> Socks5Result<()> {
+        if let Ok(addr) = listener.local_addr() {
+            log::info!("Forwarding {} to {} via {}", addr, self.target, self.proxy);
+        }
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            let forwarder = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = forwarder.tunnel(stream, peer_addr).await {
+                    log::warn!("Forwarding for {:?} to {} failed: {}", peer_addr, forwarder.target, e);
+                }
+            });
+        }
+    }
+
+    /// Tunnels one accepted connection through the proxy
+    ///
+    /// # Arguments
+    /// * `stream` - The accepted local connection
+    /// * `peer_addr` - The local client's address, for logging
+    ///
+    /// # Returns
+    /// * `Ok(())` - Once the tunnel closed
+    /// * `Err(Socks5Error)` - If the proxy cannot be reached, refuses the
+    ///   request or the relay fails
+    pub async fn tunnel(&self, stream: TcpStream, peer_addr: SocketAddr) -> Socks5Result<()> {
+        let mut proxy_stream = TcpStream::connect(&self.proxy)
+            .await
+            .map_err(|e| Socks5Error::ConnectionError(format!("Cannot reach proxy {}: {}", self.proxy, e)))?;
+        client::connect(&mut proxy_stream, &self.target, self.credentials.as_ref()).await?;
+        log::debug!("Tunnel for {:?} to {} via {} is up", peer_addr, self.target, self.proxy);
+        Relay::new(peer_addr, self.target.to_string())
+            .with_options(self.relay_options.clone())
+            .start_relay(stream, proxy_stream)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+//! Client origin filtering for the SOCKS5 proxy.
+//!
+//! A small IP database maps client addresses to a country and an autonomous
+//! system. Origin rules then reject or rate-limit clients by country or ASN
+//! right after `accept()`, before any handshake work is spawned for them.
+//!
+//! The database is a CSV file with one `network,country,asn` line per
+//! prefix, e.g. `203.0.113.0/24,NL,64500`; either the country or the ASN
+//! may be left empty. Overlapping prefixes resolve to the most specific one.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::bandwidth::{RateLimit, TokenBucket};
+use crate::clock::Clock;
+use crate::error::{Socks5Error, Socks5Result};
+
+/// Where a client address is located
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 country code, upper case
+    pub country: Option<String>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+}
+
+/// Country and ASN data per IP prefix
+///
+/// IPv4 prefixes are stored as IPv4-mapped IPv6 prefixes, so one table per
+/// prefix length covers both families.
+#[derive(Debug, Clone, Default)]
+pub struct GeoDatabase {
+    /// Networks per prefix length, most specific length first
+    prefixes: Vec<(u32, HashMap<u128, GeoInfo>)>,
+    /// Total number of networks
+    len: usize,
+}
+
+impl GeoDatabase {
+    /// Creates an empty database
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a database from a CSV file
+    ///
+    /// # Returns
+    /// * `Ok(GeoDatabase)` - The database
+    /// * `Err(Socks5Error)` - If the file cannot be read or a line is invalid
+    pub fn load(path: impl AsRef<Path>) -> Socks5Result<Self> {
+        let path = path.as_ref();
+        let document = std::fs::read_to_string(path).map_err(|e| {
+            Socks5Error::ConfigError(format!("Cannot read GeoIP database {}: {}", path.display(), e))
+        })?;
+        Self::from_csv(&document)
+    }
+
+    /// Parses a database from `network,country,asn` lines
+    ///
+    /// Blank lines, `#` comments and a leading `network,...` header are skipped.
+    pub fn from_csv(document: &str) -> Socks5Result<Self> {
+        let mut database = Self::new();
+        for (index, line) in document.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (index == 0 && line.starts_with("network")) {
+                continue;
+            }
+            let invalid = |what: &str| {
+                Socks5Error::ConfigError(format!("Invalid GeoIP line {}: {}: {}", index + 1, what, line))
+            };
+            let mut fields = line.split(',').map(str::trim);
+            let network = fields.next().unwrap_or_default();
+            let country = fields.next().unwrap_or_default();
+            let asn = fields.next().unwrap_or_default();
+            let (ip, prefix_len) = parse_network(network).ok_or_else(|| invalid("bad network"))?;
+            let info = GeoInfo {
+                country: match country {
+                    "" => None,
+                    code if is_country_code(code) => Some(code.to_ascii_uppercase()),
+                    _ => return Err(invalid("bad country code")),
+                },
+                asn: match asn.trim_start_matches(['A', 'S', 'a', 's']) {
+                    "" => None,
+                    number => Some(number.parse().map_err(|_| invalid("bad ASN"))?),
+                },
+            };
+            database.insert(ip, prefix_len, info);
+        }
+        Ok(database)
+    }
+
+    /// Adds a network, replacing any entry for the same prefix
+    ///
+    /// # Arguments
+    /// * `ip` - Any address inside the network
+    /// * `prefix_len` - The network's prefix length
+    /// * `info` - Where the network is located
+    pub fn insert(&mut self, ip: IpAddr, prefix_len: u8, info: GeoInfo) {
+        let (key, bits) = match ip {
+            IpAddr::V4(v4) => (v4.to_ipv6_mapped().to_bits(), u32::from(prefix_len.min(32)) + 96),
+            IpAddr::V6(v6) => (v6.to_bits(), u32::from(prefix_len.min(128))),
+        };
+        let position = self.prefixes.partition_point(|(len, _)| *len > bits);
+        if self.prefixes.get(position).is_none_or(|(len, _)| *len != bits) {
+            self.prefixes.insert(position, (bits, HashMap::new()));
+        }
+        if self.prefixes[position].1.insert(key & mask(bits), info).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /// Returns the most specific entry covering `ip`
+    pub fn lookup(&self, ip: IpAddr) -> Option<&GeoInfo> {
+        let key = match ip {
+            IpAddr::V4(v4) => v4.to_ipv6_mapped().to_bits(),
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(v6.to_bits(), |v4| v4.to_ipv6_mapped().to_bits()),
+        };
+        self.prefixes
+            .iter()
+            .find_map(|(bits, networks)| networks.get(&(key & mask(*bits))))
+    }
+
+    /// Returns the number of networks
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the database has no networks
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Returns the netmask of a prefix length
+fn mask(bits: u32) -> u128 {
+    u128::MAX.checked_shl(128 - bits).unwrap_or(0)
+}
+
+/// Parses `ADDRESS/LEN` or a bare address
+fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (ip, len) = match network.split_once('/') {
+        Some((ip, len)) => (ip.parse::<IpAddr>().ok()?, len.parse::<u8>().ok()?),
+        None => {
+            let ip = network.parse::<IpAddr>().ok()?;
+            (ip, if ip.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    (len <= max).then_some((ip, len))
+}
+
+/// Returns whether `code` looks like a two-letter country code
+fn is_country_code(code: &str) -> bool {
+    code.len() == 2 && code.bytes().all(|b| b.is_ascii_alphabetic())
+}
+
+/// The clients an origin rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// Clients located in a country, by ISO 3166-1 alpha-2 code
+    Country(String),
+    /// Clients announced by an autonomous system
+    Asn(u32),
+}
+
+impl Origin {
+    /// Returns whether a located client belongs to this origin
+    pub fn matches(&self, info: &GeoInfo) -> bool {
+        match self {
+            Origin::Country(code) => info.country.as_deref() == Some(code.as_str()),
+            Origin::Asn(asn) => info.asn == Some(*asn),
+        }
+    }
+}
+
+impl FromStr for Origin {
+    type Err = Socks5Error;
+
+    /// Parses a country code such as `NL` or an ASN such as `AS64500`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(number) = s.strip_prefix("AS").or_else(|| s.strip_prefix("as")) {
+            if let Ok(asn) = number.parse() {
+                return Ok(Origin::Asn(asn));
+            }
+        }
+        if is_country_code(s) {
+            return Ok(Origin::Country(s.to_ascii_uppercase()));
+        }
+        Err(Socks5Error::ConfigError(format!("Invalid origin (expected a country code or ASnnn): {}", s)))
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Country(code) => f.write_str(code),
+            Origin::Asn(asn) => write!(f, "AS{}", asn),
+        }
+    }
+}
+
+/// What happens to clients of a matching origin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginAction {
+    /// Drop every connection
+    Deny,
+    /// Accept connections up to a rate per second, shared by the whole origin
+    RateLimit(RateLimit),
+}
+
+/// Rejects or rate-limits the clients of one origin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginRule {
+    /// The clients the rule applies to
+    pub origin: Origin,
+    /// What happens to them
+    pub action: OriginAction,
+}
+
+impl FromStr for OriginRule {
+    type Err = Socks5Error;
+
+    /// Parses `ORIGIN=deny` or `ORIGIN=RATE[/BURST]` with the rate in
+    /// connections per second, e.g. `AS64500=5/20`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (origin, action) = s.split_once('=').ok_or_else(|| {
+            Socks5Error::ConfigError(format!("Invalid origin rule (expected ORIGIN=deny or ORIGIN=RATE): {}", s))
+        })?;
+        let action = if action.eq_ignore_ascii_case("deny") {
+            OriginAction::Deny
+        } else {
+            OriginAction::RateLimit(action.parse()?)
+        };
+        Ok(OriginRule { origin: origin.trim().parse()?, action })
+    }
+}
+
+impl fmt::Display for OriginRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.action {
+            OriginAction::Deny => write!(f, "{}=deny", self.origin),
+            OriginAction::RateLimit(limit) => write!(f, "{}={}", self.origin, limit),
+        }
+    }
+}
+
+/// The outcome of checking a client against the origin rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginVerdict {
+    /// No rule stops the client
+    Allow,
+    /// A deny rule matched
+    Denied,
+    /// A rate-limit rule matched and its budget is spent
+    RateLimited,
+}
+
+/// Origin rules applied to clients as they are accepted
+#[derive(Debug)]
+pub struct OriginFilter {
+    /// Where client addresses are located
+    database: Arc<GeoDatabase>,
+    /// The rules in order, each with the bucket of a rate-limit action
+    rules: Vec<(OriginRule, Option<TokenBucket>)>,
+    /// The time source for rate limits
+    clock: Arc<dyn Clock>,
+}
+
+impl OriginFilter {
+    /// Creates a filter without rules, which allows everyone
+    ///
+    /// # Arguments
+    /// * `database` - Where client addresses are located
+    /// * `clock` - The time source for rate limits
+    pub fn new(database: Arc<GeoDatabase>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            database,
+            rules: Vec::new(),
+            clock,
+        }
+    }
+
+    /// Adds a rule; the first rule matching a client decides
+    pub fn with_rule(mut self, rule: OriginRule) -> Self {
+        let bucket = match rule.action {
+            OriginAction::Deny => None,
+            OriginAction::RateLimit(limit) => Some(TokenBucket::new(limit, Arc::clone(&self.clock))),
+        };
+        self.rules.push((rule, bucket));
+        self
+    }
+
+    /// Returns the database client addresses are located in
+    pub fn database(&self) -> &Arc<GeoDatabase> {
+        &self.database
+    }
+
+    /// Returns the rules in order
+    pub fn rules(&self) -> impl Iterator<Item = &OriginRule> {
+        self.rules.iter().map(|(rule, _)| rule)
+    }
+
+    /// Decides whether a newly accepted client may be served
+    ///
+    /// A rate-limited client that is allowed uses up one connection of its
+    /// origin's budget.
+    pub fn check(&self, ip: IpAddr) -> OriginVerdict {
+        let Some(info) = self.database.lookup(ip) else {
+            return OriginVerdict::Allow;
+        };
+        let Some((_, bucket)) = self.rules.iter().find(|(rule, _)| rule.origin.matches(info)) else {
+            return OriginVerdict::Allow;
+        };
+        match bucket {
+            None => OriginVerdict::Denied,
+            Some(bucket) if bucket.try_take(1) => OriginVerdict::Allow,
+            Some(_) => OriginVerdict::RateLimited,
+        }
+    }
+}
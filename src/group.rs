@@ -0,0 +1,103 @@
+//! Running several SOCKS5 servers in one process.
+//!
+//! Every [`Server`] keeps its own state (metrics, knock table, shutdown
+//! signal), so listeners with different ports and policies can share a
+//! process. A [`ServerGroup`] binds them all before any starts accepting,
+//! runs them together and shuts them all down when one fails or when asked.
+
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::server::Server;
+
+/// A set of servers that run and stop together
+#[derive(Default)]
+pub struct ServerGroup {
+    /// The servers, in the order they were added
+    servers: Vec<Arc<Server>>,
+}
+
+impl ServerGroup {
+    /// Creates an empty group
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a server to the group
+    pub fn with_server(mut self, server: Server) -> Self {
+        self.servers.push(Arc::new(server));
+        self
+    }
+
+    /// Returns the servers in the group
+    pub fn servers(&self) -> &[Arc<Server>] {
+        &self.servers
+    }
+
+    /// Binds every server's listener, in order
+    ///
+    /// # Returns
+    /// * `Ok(Vec<TcpListener>)` - One bound listener per server
+    /// * `Err(Socks5Error)` - If any server fails to bind
+    pub async fn bind(&self) -> Socks5Result<Vec<TcpListener>> {
+        let mut listeners = Vec::with_capacity(self.servers.len());
+        for server in &self.servers {
+            listeners.push(server.bind().await?);
+        }
+        Ok(listeners)
+    }
+
+    /// Serves every server on the listener bound for it by [`ServerGroup::bind`]
+    ///
+    /// If one server fails, the others are shut down and the first error is
+    /// returned once all have stopped.
+    ///
+    /// # Returns
+    /// * `Ok(())` - Once every server has been shut down
+    /// * `Err(Socks5Error)` - The first error a server stopped with
+    pub async fn serve(&self, listeners: Vec<TcpListener>) -> Socks5Result<()> {
+        let mut tasks = JoinSet::new();
+        for (server, listener) in self.servers.iter().zip(listeners) {
+            let server = Arc::clone(server);
+            tasks.spawn(async move { server.serve_tcp(listener).await });
+        }
+
+        let mut first_error = None;
+        while let Some(result) = tasks.join_next().await {
+            let result = result.unwrap_or_else(|e| {
+                Err(Socks5Error::ConnectionError(format!("Server task failed: {}", e)))
+            });
+            if let Err(e) = result {
+                log::error!("Server stopped with an error, shutting down the group: {}", e);
+                self.shutdown();
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Binds and serves every server
+    ///
+    /// No server accepts connections unless all of them could bind.
+    pub async fn run(&self) -> Socks5Result<()> {
+        let listeners = self.bind().await?;
+        self.serve(listeners).await
+    }
+
+    /// Stops every server in the group from accepting new connections
+    pub fn shutdown(&self) {
+        for server in &self.servers {
+            server.shutdown();
+        }
+    }
+}
+
+impl FromIterator<Server> for ServerGroup {
+    fn from_iter<I: IntoIterator<Item = Server>>(servers: I) -> Self {
+        Self {
+            servers: servers.into_iter().map(Arc::new).collect(),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Session lifecycle hooks for the SOCKS5 proxy.
+//!
+//! Embedders that want to follow client sessions implement
+//! [`SessionHooks`] and register it with
+//! [`Server::with_session_hooks`](crate::server::Server::with_session_hooks).
+//! The server calls it at each milestone of a session: when the client is
+//! accepted, when it has authenticated, when it asks for a target, when the
+//! connect to that target succeeded or failed, and when the session closes
+//! with a [`SessionRecord`] summarizing it.
+//!
+//! Hooks observe; they cannot refuse a session. The connection task waits
+//! for each callback, bounded by the [`Watchdog`](crate::watchdog::Watchdog)
+//! budget of [`Hook::SessionHooks`](crate::watchdog::Hook::SessionHooks), so
+//! slow work belongs in a task of its own.
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::error::Socks5Error;
+use crate::protocol::{HandshakeInfo, TargetAddr};
+use crate::recent::SessionRecord;
+
+/// The future returned by the callbacks of [`SessionHooks`]
+pub type HookFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Callbacks at the milestones of every client session
+///
+/// Every callback does nothing by default, so implementations only override
+/// the milestones they care about. Sessions are told apart by their
+/// connection ID, the one shown as hex in log lines.
+pub trait SessionHooks: Send + Sync + fmt::Debug {
+    /// Called when a client connected to a listener
+    ///
+    /// # Arguments
+    /// * `conn_id` - The session's connection ID
+    /// * `client` - The client's address
+    /// * `listener` - The address of the listener that accepted the client
+    fn on_accept<'a>(&'a self, conn_id: u32, client: SocketAddr, listener: &'a str) -> HookFuture<'a> {
+        let _ = (conn_id, client, listener);
+        Box::pin(async {})
+    }
+
+    /// Called when a client completed the SOCKS5 handshake
+    ///
+    /// # Arguments
+    /// * `conn_id` - The session's connection ID
+    /// * `username` - The authenticated username, if any
+    /// * `method` - The authentication method selected in the handshake
+    fn on_auth<'a>(&'a self, conn_id: u32, username: Option<&'a str>, method: u8) -> HookFuture<'a> {
+        let _ = (conn_id, username, method);
+        Box::pin(async {})
+    }
+
+    /// Called when a client asked for a target, before policies are applied
+    ///
+    /// # Arguments
+    /// * `conn_id` - The session's connection ID
+    /// * `target` - The requested target
+    /// * `handshake` - The SOCKS5 handshake, with the methods the client
+    ///   offered; `None` for SOCKS4 and HTTP clients, which have none
+    fn on_request<'a>(
+        &'a self,
+        conn_id: u32,
+        target: &'a TargetAddr,
+        handshake: Option<&'a HandshakeInfo>,
+    ) -> HookFuture<'a> {
+        let _ = (conn_id, target, handshake);
+        Box::pin(async {})
+    }
+
+    /// Called when connecting to an allowed target succeeded or failed
+    ///
+    /// # Arguments
+    /// * `conn_id` - The session's connection ID
+    /// * `target` - The requested target
+    /// * `result` - How long the connect took, or why it failed
+    fn on_connect_result<'a>(
+        &'a self,
+        conn_id: u32,
+        target: &'a TargetAddr,
+        result: Result<Duration, &'a Socks5Error>,
+    ) -> HookFuture<'a> {
+        let _ = (conn_id, target, result);
+        Box::pin(async {})
+    }
+
+    /// Called when a session ended, however far it got
+    ///
+    /// # Arguments
+    /// * `summary` - What happened in the session
+    fn on_close<'a>(&'a self, summary: &'a SessionRecord) -> HookFuture<'a> {
+        let _ = summary;
+        Box::pin(async {})
+    }
+}
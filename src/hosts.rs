@@ -0,0 +1,138 @@
+//! Static host name overrides for the SOCKS5 proxy.
+//!
+//! A hosts table is consulted before DNS, so lab and test setups can point
+//! domains at test targets without touching the system resolver. Entries
+//! come from code or configuration, or from an `/etc/hosts`-style file
+//! that is reloaded when it changes.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::canonical::canonicalize;
+use crate::error::{Socks5Error, Socks5Result};
+
+/// How often a hosts file is checked for changes
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Addresses per normalized host name
+type Table = HashMap<String, Vec<IpAddr>>;
+
+/// Host name overrides consulted before the resolver
+///
+/// Fixed entries take precedence over the file's, and survive reloads.
+#[derive(Debug, Default)]
+pub struct Hosts {
+    /// Entries added in code or configuration
+    fixed: Table,
+    /// The hosts file, if any
+    path: Option<PathBuf>,
+    /// Entries read from the hosts file
+    loaded: RwLock<Table>,
+    /// Modification time and size of the file when it was last read
+    modified: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl Hosts {
+    /// Creates an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads overrides from an `/etc/hosts`-style file
+    ///
+    /// # Returns
+    /// * `Ok(Hosts)` - The table, watching `path` for changes
+    /// * `Err(Socks5Error)` - If the file cannot be read or a line is invalid
+    pub fn load(path: impl Into<PathBuf>) -> Socks5Result<Self> {
+        let hosts = Self {
+            path: Some(path.into()),
+            ..Self::default()
+        };
+        hosts.reload()?;
+        Ok(hosts)
+    }
+
+    /// Points `name` at `ip`, in addition to any addresses it already has
+    pub fn with_entry(mut self, name: &str, ip: IpAddr) -> Self {
+        self.fixed.entry(canonicalize(name).into_owned()).or_default().push(ip);
+        self
+    }
+
+    /// Returns the hosts file, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the number of host names with overrides
+    pub fn len(&self) -> usize {
+        let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+        self.fixed.len() + loaded.keys().filter(|name| !self.fixed.contains_key(*name)).count()
+    }
+
+    /// Returns whether there are no overrides
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the addresses overriding `name`, if any
+    ///
+    /// Names are matched in canonical form: case-insensitively, without a
+    /// trailing dot and with internationalized labels in punycode.
+    pub fn lookup(&self, name: &str) -> Option<Vec<IpAddr>> {
+        let name = canonicalize(name);
+        if let Some(addrs) = self.fixed.get(name.as_ref()) {
+            return Some(addrs.clone());
+        }
+        let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+        loaded.get(name.as_ref()).cloned()
+    }
+
+    /// Re-reads the hosts file if it changed since it was last read
+    ///
+    /// On failure the previous entries stay in effect.
+    ///
+    /// # Returns
+    /// * `Ok(true)` - If the file was read
+    /// * `Ok(false)` - If there is no file or it is unchanged
+    /// * `Err(Socks5Error)` - If the file cannot be read or a line is invalid
+    pub fn reload(&self) -> Socks5Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let unreadable = |e: std::io::Error| {
+            Socks5Error::ConfigError(format!("Cannot read hosts file {}: {}", path.display(), e))
+        };
+        let meta = std::fs::metadata(path).map_err(unreadable)?;
+        let modified = (meta.modified().map_err(unreadable)?, meta.len());
+        let mut last = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+        if *last == Some(modified) {
+            return Ok(false);
+        }
+        let table = parse(&std::fs::read_to_string(path).map_err(unreadable)?)?;
+        *self.loaded.write().unwrap_or_else(|e| e.into_inner()) = table;
+        *last = Some(modified);
+        Ok(true)
+    }
+}
+
+/// Parses `/etc/hosts` lines: an address followed by host names
+fn parse(document: &str) -> Socks5Result<Table> {
+    let mut table = Table::new();
+    for (index, line) in document.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(address) = fields.next() else {
+            continue;
+        };
+        let ip: IpAddr = address.parse().map_err(|_| {
+            Socks5Error::ConfigError(format!("Invalid address on hosts line {}: {}", index + 1, address))
+        })?;
+        for name in fields {
+            table.entry(canonicalize(name).into_owned()).or_default().push(ip);
+        }
+    }
+    Ok(table)
+}
@@ -0,0 +1,180 @@
+//! Minimal HTTP forward proxying for the SOCKS5 proxy.
+//!
+//! Some ancient clients do not speak SOCKS at all and send plain HTTP
+//! requests with an absolute URI (`GET http://host/path HTTP/1.0`) to their
+//! proxy. With HTTP forwarding enabled, such requests are recognized by
+//! their first byte, rewritten to origin form and sent to the target over
+//! the same connect and relay machinery SOCKS requests use. Nothing is
+//! cached, and each connection carries one request: the forwarded request
+//! asks the target to close the connection after its response.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::protocol::{close_gracefully, TargetAddr};
+
+/// The largest request head (request line and headers) that is accepted
+pub const MAX_HEAD_SIZE: usize = 8192;
+
+/// Headers that only concern the hop to the proxy and are not forwarded
+const HOP_BY_HOP: [&str; 6] = [
+    "connection",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "upgrade",
+];
+
+/// A forwardable HTTP request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardRequest {
+    /// The request method, e.g. `GET`
+    pub method: String,
+    /// The host and port from the absolute URI
+    pub target: TargetAddr,
+    /// The rewritten request head to send to the target, ending with an empty line
+    pub head: Vec<u8>,
+}
+
+/// Returns whether a connection opening with `first` may be an HTTP request
+///
+/// HTTP methods are uppercase ASCII tokens, so the first byte can never be
+/// confused with a SOCKS version byte.
+pub fn is_http_start(first: u8) -> bool {
+    first.is_ascii_uppercase()
+}
+
+/// Reads an HTTP request head and rewrites it for forwarding
+///
+/// Bytes of the request body that arrived with the head are appended to the
+/// returned head, so they are forwarded as well.
+///
+/// # Returns
+/// * `Ok(ForwardRequest)` - The request to forward
+/// * `Err(Socks5Error)` - If the head is too large, truncated or not forwardable
+pub async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Socks5Result<ForwardRequest> {
+    let mut buf = Vec::with_capacity(1024);
+    let end = loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buf.len() >= MAX_HEAD_SIZE {
+            return Err(Socks5Error::HandshakeError("HTTP request head is too large".to_string()));
+        }
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(Socks5Error::HandshakeError("HTTP request head is truncated".to_string()));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let mut request = parse_request(&buf[..end])?;
+    request.head.extend_from_slice(&buf[end..]);
+    Ok(request)
+}
+
+/// Parses a request head and rewrites it for forwarding
+///
+/// The absolute URI is reduced to its path and query, hop-by-hop headers are
+/// dropped, a `Host` header is added if missing and `Connection: close` is
+/// appended. Only `http://` URIs are accepted; `CONNECT` and origin-form
+/// requests are refused.
+///
+/// # Arguments
+/// * `head` - The request line and headers, ending with an empty line
+///
+/// # Returns
+/// * `Ok(ForwardRequest)` - The request to forward
+/// * `Err(Socks5Error)` - If the request is malformed or cannot be forwarded
+pub fn parse_request(head: &[u8]) -> Socks5Result<ForwardRequest> {
+    let head = std::str::from_utf8(head)
+        .map_err(|_| Socks5Error::HandshakeError("HTTP request head is not valid UTF-8".to_string()))?;
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(uri), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(Socks5Error::HandshakeError(format!("Malformed HTTP request line: {:?}", request_line)));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Socks5Error::HandshakeError(format!("Unsupported HTTP version: {}", version)));
+    }
+    if method == "CONNECT" {
+        return Err(Socks5Error::HandshakeError("HTTP CONNECT is not supported".to_string()));
+    }
+    let Some(rest) = uri.strip_prefix("http://") else {
+        return Err(Socks5Error::HandshakeError(format!("Not an absolute http:// URI: {}", uri)));
+    };
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let target = parse_authority(authority)?;
+
+    let connection_tokens: Vec<String> = head
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(',').map(|token| token.trim().to_ascii_lowercase()))
+        .collect();
+
+    let mut rewritten = format!("{} {}{} {}\r\n", method, if path.starts_with('?') { "/" } else { "" }, path, version);
+    let mut has_host = false;
+    for line in lines.filter(|line| !line.is_empty()) {
+        let Some((name, _)) = line.split_once(':') else {
+            return Err(Socks5Error::HandshakeError(format!("Malformed HTTP header: {:?}", line)));
+        };
+        let name = name.trim().to_ascii_lowercase();
+        if HOP_BY_HOP.contains(&name.as_str()) || connection_tokens.contains(&name) {
+            continue;
+        }
+        has_host |= name == "host";
+        rewritten.push_str(line);
+        rewritten.push_str("\r\n");
+    }
+    if !has_host {
+        rewritten.push_str(&format!("Host: {}\r\n", authority));
+    }
+    rewritten.push_str("Connection: close\r\n\r\n");
+
+    Ok(ForwardRequest {
+        method: method.to_string(),
+        target,
+        head: rewritten.into_bytes(),
+    })
+}
+
+/// Parses the `host[:port]` authority of a URI, defaulting to port 80
+pub(crate) fn parse_authority(authority: &str) -> Socks5Result<TargetAddr> {
+    let invalid = || Socks5Error::AddressError(format!("Invalid HTTP authority: {:?}", authority));
+    if authority.is_empty() {
+        return Err(invalid());
+    }
+    let with_port = if authority.ends_with(']') || !authority.contains(':') {
+        format!("{}:80", authority)
+    } else {
+        authority.to_string()
+    };
+    with_port.parse().map_err(|_| invalid())
+}
+
+/// Sends an error response and closes the connection
+///
+/// # Arguments
+/// * `stream` - The stream connected to the client
+/// * `status` - The status code, e.g. 502
+/// * `reason` - The reason phrase, e.g. `Bad Gateway`
+pub async fn send_error<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, status: u16, reason: &str) -> Socks5Result<()> {
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        reason,
+        reason.len() + 1,
+        reason
+    );
+    stream.write_all(response.as_bytes()).await?;
+    close_gracefully(stream).await;
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! Port knocking gate for the SOCKS5 proxy.
+//!
+//! When enabled, a source IP must first connect to a secret sequence of TCP
+//! ports before the SOCKS port accepts its connections for a limited window.
+//! Everyone else is dropped without a single byte, hiding the proxy from
+//! scanners.
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::lru::LruMap;
+
+/// Dead knock states are pruned each time the table grows by this many sources
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// The least recently seen sources are evicted beyond this many
+const MAX_SOURCES: usize = 16384;
+
+/// Port knocking settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnockConfig {
+    /// The ports that must be hit, in order
+    pub ports: Vec<u16>,
+    /// Maximum delay between two consecutive knocks
+    pub step_timeout: Duration,
+    /// How long the SOCKS port stays open after a complete sequence
+    pub open_window: Duration,
+}
+
+impl KnockConfig {
+    /// Creates a knock configuration with a 10 second step timeout
+    ///
+    /// # Arguments
+    /// * `ports` - The ports that must be hit, in order
+    /// * `open_window` - How long the SOCKS port stays open afterwards
+    pub fn new(ports: Vec<u16>, open_window: Duration) -> Self {
+        Self {
+            ports,
+            step_timeout: Duration::from_secs(10),
+            open_window,
+        }
+    }
+}
+
+/// Knock progress of one source IP
+#[derive(Debug, Default)]
+struct KnockState {
+    /// How many ports of the sequence have been hit
+    progress: usize,
+    /// When the last correct knock happened
+    last_knock: Option<Instant>,
+    /// Until when the SOCKS port is open for this source
+    open_until: Option<Instant>,
+}
+
+/// Tracks knock sequences and decides which sources may connect
+#[derive(Debug)]
+pub struct KnockGate {
+    /// The knock settings
+    config: KnockConfig,
+    /// Time source for step timeouts and open windows
+    clock: Arc<dyn Clock>,
+    /// Knock progress per source IP, bounded against spoofed floods
+    states: Mutex<LruMap<IpAddr, KnockState>>,
+}
+
+impl KnockGate {
+    /// Creates a new gate
+    ///
+    /// # Arguments
+    /// * `config` - The knock settings
+    /// * `clock` - Time source for step timeouts and open windows
+    pub fn new(config: KnockConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            clock,
+            states: Mutex::new(LruMap::new(MAX_SOURCES)),
+        }
+    }
+
+    /// Returns the knock settings
+    pub fn config(&self) -> &KnockConfig {
+        &self.config
+    }
+
+    /// Returns how many sources were evicted because the table was full
+    pub fn evictions(&self) -> u64 {
+        self.states.lock().unwrap_or_else(|e| e.into_inner()).evictions()
+    }
+
+    /// Records a knock from `ip` on `port`
+    ///
+    /// A knock on the expected port advances the sequence; anything else
+    /// restarts it. Completing the sequence opens the gate for the window.
+    pub fn record_knock(&self, ip: IpAddr, port: u16) {
+        let now = self.clock.now();
+        let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        // Once full, eviction rather than pruning keeps the table bounded
+        let len = states.len();
+        if (PRUNE_THRESHOLD..MAX_SOURCES).contains(&len) && len.is_multiple_of(PRUNE_THRESHOLD) {
+            states.retain(|_, state| is_live(state, now, &self.config));
+        }
+        
+        let state = states.get_or_insert_with(ip, KnockState::default);
+        let in_time = state
+            .last_knock
+            .is_none_or(|last| now.duration_since(last) <= self.config.step_timeout);
+        
+        if in_time && self.config.ports.get(state.progress) == Some(&port) {
+            state.progress += 1;
+        } else if self.config.ports.first() == Some(&port) {
+            state.progress = 1;
+        } else {
+            state.progress = 0;
+        }
+        state.last_knock = Some(now);
+        
+        if state.progress == self.config.ports.len() {
+            log::info!("Knock sequence completed by {}", ip);
+            state.progress = 0;
+            state.open_until = Some(now + self.config.open_window);
+        }
+    }
+
+    /// Returns whether `ip` has completed the sequence within the window
+    ///
+    /// Checking marks the source as recently seen, so clients that keep
+    /// connecting are the last to be evicted.
+    pub fn is_open(&self, ip: IpAddr) -> bool {
+        let now = self.clock.now();
+        let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        states
+            .get_mut(&ip)
+            .and_then(|state| state.open_until)
+            .is_some_and(|until| now < until)
+    }
+
+    /// Binds the knock ports and records every connection made to them
+    ///
+    /// Knock connections are closed immediately without sending any data.
+    ///
+    /// # Arguments
+    /// * `bind_addr` - The IP address to bind the knock ports on
+    ///
+    /// # Returns
+    /// * `Ok(Vec<JoinHandle>)` - The listener tasks, once all are running
+    /// * `Err(Socks5Error)` - If a knock port cannot be bound
+    pub async fn spawn_listeners(self: &Arc<Self>, bind_addr: &str) -> Socks5Result<Vec<JoinHandle<()>>> {
+        let mut tasks = Vec::with_capacity(self.config.ports.len());
+        for &port in &self.config.ports {
+            let listener = TcpListener::bind((bind_addr, port)).await.map_err(|e| {
+                Socks5Error::ConnectionError(format!("Failed to bind knock port {}: {}", port, e))
+            })?;
+            let gate = Arc::clone(self);
+            tasks.push(tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((_, peer_addr)) => gate.record_knock(peer_addr.ip(), port),
+                        Err(e) => log::debug!("Error accepting knock on port {}: {}", port, e),
+                    }
+                }
+            }));
+        }
+        Ok(tasks)
+    }
+}
+
+/// Returns whether a knock state still matters at `now`
+fn is_live(state: &KnockState, now: Instant, config: &KnockConfig) -> bool {
+    let open = state.open_until.is_some_and(|until| now < until);
+    let knocking = state.progress > 0
+        && state.last_knock.is_some_and(|last| now.duration_since(last) <= config.step_timeout);
+    open || knocking
+}
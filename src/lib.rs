@@ -8,20 +8,86 @@
 //! 
 //! ## Features
 //! 
-//! - SOCKS5 protocol implementation
+//! - SOCKS5 protocol implementation, with SOCKS4 and SOCKS4a on the same port
 //! - Support for CONNECT command
 //! - IPv4 and domain name address types
 //! - Authentication methods:
 //!   - No authentication
 //!   - Username/password authentication
 //! - Asynchronous I/O using Tokio
+//!
+//! The wire format itself (constants, [`TargetAddr`](protocol::TargetAddr),
+//! message codecs and an I/O-free client handshake) lives in the `no_std`
+//! `rsocks5-core` crate, re-exported as [`wire`], so embedded clients and
+//! other projects can reuse it without Tokio.
 
+pub use rsocks5_core as wire;
+
+pub mod accounting;
+pub mod acl;
+pub mod active;
+pub mod admin;
+pub mod audit;
+pub mod bandwidth;
+pub mod blocklist;
+pub mod canonical;
+pub mod capabilities;
+pub mod client;
+pub mod cluster;
+pub mod clock;
+pub mod compat;
+pub mod config;
 pub mod constants;
+pub mod dnscache;
+pub mod egress;
 pub mod error;
+pub mod events;
+pub mod extensions;
+pub mod fetch;
+pub mod forward;
+pub mod geoip;
+pub mod group;
+pub mod hooks;
+pub mod hosts;
+pub mod http;
+pub mod knock;
+pub mod listener;
+pub mod logformat;
+pub mod loglevel;
+pub mod lru;
+pub mod metrics;
+pub mod mirror;
+pub mod nat64;
+#[cfg(feature = "natpmp")]
+pub mod natpmp;
+pub mod obfuscation;
+pub mod policy;
+pub mod portowner;
 pub mod protocol;
+pub mod random;
+pub mod ratelimit;
+pub mod recent;
+pub mod reputation;
 pub mod connection;
 pub mod relay;
+pub mod remote;
+pub mod resolver;
+pub mod routing;
+pub mod selftest;
 pub mod server;
+pub mod slo;
+pub mod socks4;
+pub mod srv;
+pub mod syslog;
+pub mod testing;
+pub mod timeouts;
+pub mod tls;
+pub mod upstream;
+pub mod url;
+pub mod users;
+pub mod warm;
+pub mod warnings;
+pub mod watchdog;
 
 // Re-export main components for easier access
 pub use server::Server;
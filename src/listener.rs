@@ -0,0 +1,225 @@
+//! Pluggable accept sources for the SOCKS5 proxy.
+//!
+//! A [`Server`](crate::server::Server) serves any [`Listener`]: a bound
+//! `TcpListener`, a Unix socket, or the in-memory
+//! [`MemoryListener`](crate::testing::MemoryListener) that tests and
+//! embedders can connect to without touching the network. TLS or
+//! socket-activated listeners plug in the same way.
+//!
+//! Connections only need to be readable, writable and peekable; streams
+//! that cannot peek natively are wrapped in [`Peekable`].
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How many bytes [`Peekable`] can hold back
+const PEEK_CAPACITY: usize = 16;
+
+/// A client connection the proxy can serve
+pub trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    /// Waits for data and copies it into `buf` without consuming it
+    fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<usize>>;
+
+    /// Returns the client's IP address and port, if the transport has one
+    fn client_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl ClientStream for TcpStream {
+    fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<usize>> {
+        TcpStream::poll_peek(self, cx, buf)
+    }
+
+    fn client_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+}
+
+/// Returns the next bytes from `stream` without consuming them
+///
+/// # Returns
+/// * `Ok(n)` - The number of bytes copied into `buf`; 0 at end of stream
+/// * `Err(io::Error)` - If the stream fails
+pub async fn peek<S: ClientStream + ?Sized>(stream: &mut S, buf: &mut [u8]) -> io::Result<usize> {
+    std::future::poll_fn(|cx| stream.poll_peek(cx, &mut ReadBuf::new(buf))).await
+}
+
+/// Adds peeking to a stream that cannot peek natively
+///
+/// Peeked bytes are held in a small inline buffer and returned by the next
+/// read, so the wrapper costs no allocation.
+#[derive(Debug)]
+pub struct Peekable<S> {
+    /// The wrapped stream
+    inner: S,
+    /// Bytes read from `inner` but not yet consumed
+    buf: [u8; PEEK_CAPACITY],
+    /// Start of the unconsumed bytes in `buf`
+    start: usize,
+    /// End of the unconsumed bytes in `buf`
+    end: usize,
+    /// The client's address, if known
+    client_addr: Option<SocketAddr>,
+}
+
+impl<S> Peekable<S> {
+    /// Wraps `inner`
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            buf: [0; PEEK_CAPACITY],
+            start: 0,
+            end: 0,
+            client_addr: None,
+        }
+    }
+
+    /// Sets the client address reported by [`ClientStream::client_addr`]
+    pub fn with_client_addr(mut self, addr: SocketAddr) -> Self {
+        self.client_addr = Some(addr);
+        self
+    }
+
+    /// Returns the wrapped stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> ClientStream for Peekable<S> {
+    fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<usize>> {
+        if self.start == self.end {
+            let mut held = ReadBuf::new(&mut self.buf);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut held))?;
+            self.start = 0;
+            self.end = held.filled().len();
+        }
+        let n = buf.remaining().min(self.end - self.start);
+        buf.put_slice(&self.buf[self.start..self.start + n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn client_addr(&self) -> Option<SocketAddr> {
+        self.client_addr
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Peekable<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.start < self.end {
+            let n = buf.remaining().min(self.end - self.start);
+            buf.put_slice(&self.buf[self.start..self.start + n]);
+            self.start += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Peekable<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// How full a listener's accept queue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backlog {
+    /// Connections completed by the kernel but not yet accepted
+    pub queued: u32,
+    /// Most connections the kernel queues before dropping new ones
+    pub limit: u32,
+}
+
+/// A source of client connections
+pub trait Listener: Send + 'static {
+    /// The connections this listener yields
+    type Stream: ClientStream;
+
+    /// Waits for the next client connection
+    ///
+    /// # Returns
+    /// * `Ok((stream, addr))` - The connection and the client's address;
+    ///   transports without IP addresses report an unspecified one
+    /// * `Err(io::Error)` - If accepting failed; the server backs off and retries
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Stream, SocketAddr)>> + Send;
+
+    /// Returns the address the listener accepts on, if it has one
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Returns how full the kernel's accept queue is, if the platform says
+    fn backlog(&self) -> Option<Backlog> {
+        None
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send {
+        TcpListener::accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn backlog(&self) -> Option<Backlog> {
+        tcp_backlog(self)
+    }
+}
+
+/// Reads the accept queue of a listening TCP socket from `TCP_INFO`
+///
+/// For listening sockets Linux reports the queued connections in
+/// `tcpi_unacked` and the queue's size in `tcpi_sacked`.
+#[cfg(target_os = "linux")]
+fn tcp_backlog(listener: &TcpListener) -> Option<Backlog> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: `tcp_info` is plain data and the kernel writes at most `len` bytes
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            listener.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            (&mut info as *mut libc::tcp_info).cast(),
+            &mut len,
+        )
+    };
+    (result == 0).then_some(Backlog {
+        queued: info.tcpi_unacked,
+        limit: info.tcpi_sacked,
+    })
+}
+
+#[cfg(unix)]
+impl Listener for tokio::net::UnixListener {
+    type Stream = Peekable<tokio::net::UnixStream>;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, SocketAddr)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((Peekable::new(stream), SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 0))))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Unix listeners have no IP address"))
+    }
+}
@@ -0,0 +1,127 @@
+//! Runtime log level changes for the SOCKS5 proxy.
+//!
+//! The effective level is the global maximum of the `log` crate, so it can
+//! be raised during an incident and lowered again without restarting and
+//! dropping sessions. On Unix, `SIGUSR2` cycles it one step more verbose,
+//! wrapping back to the configured level after `trace`. The signal handler
+//! only bumps an atomic counter; the level is changed from a regular task.
+
+use log::LevelFilter;
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::task::JoinHandle;
+
+/// How often pending `SIGUSR2` signals are picked up
+pub const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The configured log level and changes to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogLevelControl {
+    /// The level the proxy was started with
+    base: LevelFilter,
+}
+
+impl LogLevelControl {
+    /// Creates a control and sets the effective level to `base`
+    pub fn new(base: LevelFilter) -> Self {
+        log::set_max_level(base);
+        Self { base }
+    }
+
+    /// Returns the level the proxy was started with
+    pub fn base(&self) -> LevelFilter {
+        self.base
+    }
+
+    /// Returns the effective level
+    pub fn current(&self) -> LevelFilter {
+        log::max_level()
+    }
+
+    /// Changes the effective level
+    pub fn set(&self, level: LevelFilter) {
+        let previous = log::max_level();
+        log::set_max_level(level);
+        if level != previous {
+            // Logged at the more verbose level so the change is always visible
+            log::log!(
+                previous.max(level).to_level().unwrap_or(log::Level::Error),
+                "Log level changed from {} to {}",
+                previous,
+                level
+            );
+        }
+    }
+
+    /// Makes the effective level one step more verbose, wrapping from
+    /// `trace` back to the base level
+    ///
+    /// # Returns
+    /// * The new effective level
+    pub fn cycle(&self) -> LevelFilter {
+        let current = self.current();
+        let next = LevelFilter::iter()
+            .find(|level| *level > current)
+            .filter(|_| current >= self.base)
+            .unwrap_or(self.base);
+        self.set(next);
+        next
+    }
+
+    /// Cycles the level on every `SIGUSR2` until aborted
+    ///
+    /// # Returns
+    /// * `Ok(JoinHandle)` - The task applying received signals
+    /// * `Err(io::Error)` - If the signal handler cannot be installed
+    #[cfg(unix)]
+    pub fn spawn_signal_cycling(self) -> std::io::Result<JoinHandle<()>> {
+        signal::install()?;
+        Ok(tokio::spawn(async move {
+            let mut seen = signal::received();
+            loop {
+                tokio::time::sleep(SIGNAL_POLL_INTERVAL).await;
+                let received = signal::received();
+                for _ in seen..received {
+                    self.cycle();
+                }
+                seen = received;
+            }
+        }))
+    }
+}
+
+/// A minimal async-signal-safe `SIGUSR2` counter
+#[cfg(unix)]
+mod signal {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Signals received since the handler was installed
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Only touches an atomic, which is async-signal-safe
+    extern "C" fn on_signal(_: libc::c_int) {
+        RECEIVED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Installs the `SIGUSR2` handler
+    pub(super) fn install() -> std::io::Result<()> {
+        // SAFETY: the action is fully initialized and the handler is
+        // async-signal-safe; SA_RESTART keeps interrupted syscalls going.
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGUSR2, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns how many signals were received so far
+    pub(super) fn received() -> usize {
+        RECEIVED.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,162 @@
+//! Bounded, DoS-resistant maps for per-client state.
+//!
+//! Tables keyed by something a client chooses (its source IP, the hosts it
+//! asks for) must neither degrade under crafted keys nor grow without limit.
+//! [`LruMap`] hashes with randomly keyed SipHash and holds at most a fixed
+//! number of entries, evicting the least recently used one when full and
+//! counting every eviction so operators can see a table under pressure.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A value with the time it was last used
+#[derive(Debug)]
+struct Slot<V> {
+    /// The stored value
+    value: V,
+    /// The use counter value of the last access
+    used: u64,
+}
+
+/// A hash map with a fixed capacity and least-recently-used eviction
+///
+/// Keys are hashed with a [`RandomState`], so every map gets its own
+/// SipHash keys and attackers cannot precompute colliding keys.
+#[derive(Debug)]
+pub struct LruMap<K, V> {
+    /// The entries, keyed with per-map random hash keys
+    entries: HashMap<K, Slot<V>, RandomState>,
+    /// Keys ordered by last use, oldest first
+    order: BTreeMap<u64, K>,
+    /// Monotonic use counter
+    clock: u64,
+    /// Maximum number of entries
+    capacity: usize,
+    /// Entries evicted to make room so far
+    evictions: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruMap<K, V> {
+    /// Creates an empty map holding at most `capacity` entries (at least one)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::with_hasher(RandomState::new()),
+            order: BTreeMap::new(),
+            clock: 0,
+            capacity: capacity.max(1),
+            evictions: 0,
+        }
+    }
+
+    /// Returns the maximum number of entries
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns how many entries have been evicted to make room
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Returns the value for `key` without marking it as used
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|slot| &slot.value)
+    }
+
+    /// Returns the value for `key` and marks it as most recently used
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let used = self.tick();
+        let slot = self.entries.get_mut(key)?;
+        let key = self.order.remove(&slot.used)?;
+        slot.used = used;
+        self.order.insert(used, key);
+        Some(&mut slot.value)
+    }
+
+    /// Returns the value for `key`, inserting `default()` if it is missing
+    ///
+    /// The entry becomes the most recently used. Inserting into a full map
+    /// evicts the least recently used entry first.
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        if self.entries.contains_key(&key) {
+            return self.get_mut(&key).expect("entry is present");
+        }
+        self.make_room();
+        let used = self.tick();
+        self.order.insert(used, key.clone());
+        &mut self.entries.entry(key).or_insert(Slot { value: default(), used }).value
+    }
+
+    /// Inserts `value` for `key` as the most recently used entry
+    ///
+    /// Returns the previous value, if any. Inserting a new key into a full
+    /// map evicts the least recently used entry first.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(slot) = self.get_mut(&key) {
+            return Some(std::mem::replace(slot, value));
+        }
+        self.get_or_insert_with(key, || value);
+        None
+    }
+
+    /// Removes the entry for `key`, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        self.order.remove(&slot.used);
+        Some(slot.value)
+    }
+
+    /// Keeps only the entries for which `keep` returns true
+    ///
+    /// Removed entries are not counted as evictions.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        let order = &mut self.order;
+        self.entries.retain(|key, slot| {
+            let kept = keep(key, &mut slot.value);
+            if !kept {
+                order.remove(&slot.used);
+            }
+            kept
+        });
+    }
+
+    /// Returns an iterator over the values, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|slot| &slot.value)
+    }
+
+    /// Returns an iterator over the entries, in no particular order
+    ///
+    /// Iterating does not count as using the entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, slot)| (key, &slot.value))
+    }
+
+    /// Advances the use counter
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Evicts least recently used entries until there is room for one more
+    fn make_room(&mut self) {
+        while self.entries.len() >= self.capacity {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+            self.evictions += 1;
+        }
+    }
+}
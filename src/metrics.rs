@@ -0,0 +1,674 @@
+//! Runtime counters for the SOCKS5 proxy.
+//!
+//! The server records connection lifecycle events here so operators and
+//! embedders can observe how connections end without parsing log output.
+//! Every listener has its own counters; [`ListenerStats`] snapshots them
+//! together with the listener's label.
+//!
+//! The accept loop also samples how long connections spend between being
+//! accepted and getting a task, how long they wait for a free session when
+//! connections are queued, and how full the kernel's accept queue is, so
+//! capacity planning can tell a slow accept loop from a slow policy path or
+//! saturated relays.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::acl::PortClass;
+use crate::bandwidth::Priority;
+use crate::compat::Quirk;
+use crate::listener::Backlog;
+use crate::watchdog::Hook;
+
+/// Every how many accepted connections the accept loop is timed by default
+pub const DEFAULT_ACCEPT_SAMPLE_EVERY: u32 = 16;
+
+/// Why a client connection was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CloseReason {
+    /// The session ran to completion
+    Completed,
+    /// The session ended because of an error
+    Error,
+    /// The client sent nothing before the first-byte deadline
+    FirstByteTimeout,
+    /// The client had not completed the port knock sequence
+    KnockRequired,
+    /// The client's country or ASN is rejected or over its rate limit
+    OriginFiltered,
+    /// The client did not open with the expected preamble
+    ProbeRejected,
+    /// The requested target was refused by policy
+    Denied,
+    /// SUCCEEDED was sent optimistically but the target connect then failed
+    OptimisticConnectFailed,
+    /// The connection task panicked
+    Panic,
+    /// The connection task was dropped before it finished, e.g. at shutdown
+    Aborted,
+    /// The server was at its connection limit
+    Overloaded,
+    /// The client started more handshakes than its address may
+    RateLimited,
+    /// The client's reputation required authentication it could not give
+    LowReputation,
+    /// The user was at their connection limit or out of quota
+    UserLimit,
+    /// An operator terminated the session
+    Terminated,
+    /// The target closed or reset the connection right after accepting it,
+    /// without sending anything
+    TargetClosedEarly,
+    /// The client had not sent its request by the handshake deadline
+    HandshakeTimeout,
+}
+
+impl CloseReason {
+    /// All close reasons, in counter order
+    pub const ALL: [CloseReason; 17] = [
+        CloseReason::Completed,
+        CloseReason::Error,
+        CloseReason::FirstByteTimeout,
+        CloseReason::KnockRequired,
+        CloseReason::OriginFiltered,
+        CloseReason::ProbeRejected,
+        CloseReason::Denied,
+        CloseReason::OptimisticConnectFailed,
+        CloseReason::Panic,
+        CloseReason::Aborted,
+        CloseReason::Overloaded,
+        CloseReason::RateLimited,
+        CloseReason::LowReputation,
+        CloseReason::UserLimit,
+        CloseReason::Terminated,
+        CloseReason::TargetClosedEarly,
+        CloseReason::HandshakeTimeout,
+    ];
+
+    /// Returns a short, stable name for the reason
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseReason::Completed => "completed",
+            CloseReason::Error => "error",
+            CloseReason::FirstByteTimeout => "first_byte_timeout",
+            CloseReason::KnockRequired => "knock_required",
+            CloseReason::OriginFiltered => "origin_filtered",
+            CloseReason::ProbeRejected => "probe_rejected",
+            CloseReason::Denied => "denied",
+            CloseReason::OptimisticConnectFailed => "optimistic_connect_failed",
+            CloseReason::Panic => "panic",
+            CloseReason::Aborted => "aborted",
+            CloseReason::Overloaded => "overloaded",
+            CloseReason::RateLimited => "rate_limited",
+            CloseReason::LowReputation => "low_reputation",
+            CloseReason::UserLimit => "user_limit",
+            CloseReason::Terminated => "terminated",
+            CloseReason::TargetClosedEarly => "target_closed_early",
+            CloseReason::HandshakeTimeout => "handshake_timeout",
+        }
+    }
+
+    /// Returns whether the reason is a drop by admission control, i.e. the
+    /// connection was closed by the accept loop before it got a task
+    pub fn is_admission(&self) -> bool {
+        matches!(
+            self,
+            CloseReason::KnockRequired | CloseReason::OriginFiltered | CloseReason::RateLimited | CloseReason::Overloaded
+        )
+    }
+
+    /// Returns the counter slot for the reason
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Connection counters shared between the server and its connection tasks
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Total number of accepted client connections
+    connections_accepted: AtomicU64,
+    /// Number of client connections currently being handled
+    active_connections: AtomicU64,
+    /// Number of closed connections, per close reason
+    closed: [AtomicU64; CloseReason::ALL.len()],
+    /// Number of denials computed in shadow mode but not enforced
+    shadow_denials: AtomicU64,
+    /// Number of connections that relied on each compatibility quirk
+    quirks: [AtomicU64; Quirk::ALL.len()],
+    /// Number of connection tasks that panicked
+    panics: AtomicU64,
+    /// Number of connections relayed in each class of service
+    priorities: [AtomicU64; Priority::ALL.len()],
+    /// Number of requests refused for their target port, per port class
+    port_denials: [AtomicU64; PortClass::ALL.len()],
+    /// Number of alerts raised for breached connect latency objectives
+    slo_alerts: AtomicU64,
+    /// Number of hooks abandoned for overrunning their budget, per hook
+    hook_overruns: [AtomicU64; Hook::ALL.len()],
+    /// Sampled timings of the accept loop
+    accept: AcceptTimings,
+    /// Number of TLS handshakes that failed
+    tls_handshake_failures: AtomicU64,
+    /// Number of TLS handshakes that did not finish within their deadline
+    tls_handshake_timeouts: AtomicU64,
+}
+
+/// Sampled accept loop timings, in microseconds
+#[derive(Debug, Default)]
+struct AcceptTimings {
+    /// Number of connections timed from accept to task spawn
+    samples: AtomicU64,
+    /// Sum of the sampled accept-to-spawn delays
+    delay_total: AtomicU64,
+    /// Longest sampled accept-to-spawn delay
+    delay_max: AtomicU64,
+    /// Number of sampled accepts that first waited for a free session
+    queued: AtomicU64,
+    /// Sum of the sampled waits for a free session
+    queue_wait_total: AtomicU64,
+    /// Longest sampled wait for a free session
+    queue_wait_max: AtomicU64,
+    /// Connections in the accept queue at the last sample, plus one
+    backlog: AtomicU32,
+    /// Most connections seen in the accept queue, plus one
+    backlog_peak: AtomicU32,
+    /// Size of the accept queue at the last sample, plus one
+    backlog_limit: AtomicU32,
+}
+
+impl Metrics {
+    /// Creates a new set of zeroed counters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a newly accepted client connection
+    pub fn record_accept(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a closed client connection
+    ///
+    /// # Arguments
+    /// * `reason` - Why the connection was closed
+    pub fn record_close(&self, reason: CloseReason) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        self.closed[reason.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a newly accepted connection and returns a guard that records
+    /// its close
+    ///
+    /// If the guard is dropped without [`ConnectionGuard::close`], e.g.
+    /// because the connection task panicked, the close is still recorded so
+    /// the active connection count never leaks.
+    pub fn track(self: &Arc<Self>) -> ConnectionGuard {
+        self.record_accept();
+        ConnectionGuard {
+            metrics: Arc::clone(self),
+            closed: false,
+        }
+    }
+
+    /// Records a panic in a connection task
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a connection relayed in the given class of service
+    pub fn record_priority(&self, priority: Priority) {
+        self.priorities[priority.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a request refused for its target port
+    pub fn record_port_denial(&self, class: PortClass) {
+        self.port_denials[class.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a denial that shadow mode did not enforce
+    pub fn record_shadow_denial(&self) {
+        self.shadow_denials.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an alert for a breached connect latency objective
+    pub fn record_slo_alert(&self) {
+        self.slo_alerts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a TLS handshake that failed before the client spoke SOCKS
+    pub fn record_tls_handshake_failure(&self) {
+        self.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a TLS handshake that did not finish within its deadline
+    pub fn record_tls_handshake_timeout(&self) {
+        self.tls_handshake_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a hook abandoned for overrunning its time budget
+    pub fn record_hook_overrun(&self, hook: Hook) {
+        self.hook_overruns[hook.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a sampled accept
+    ///
+    /// # Arguments
+    /// * `delay` - How long the connection took from accept to task spawn
+    /// * `queue_wait` - How long the accept loop first waited for a free
+    ///   session, if connections beyond the limit are queued
+    /// * `backlog` - How full the listener's accept queue was, if known
+    pub fn record_accept_sample(&self, delay: Duration, queue_wait: Option<Duration>, backlog: Option<Backlog>) {
+        let timings = &self.accept;
+        let delay = delay.as_micros() as u64;
+        timings.samples.fetch_add(1, Ordering::Relaxed);
+        timings.delay_total.fetch_add(delay, Ordering::Relaxed);
+        timings.delay_max.fetch_max(delay, Ordering::Relaxed);
+        if let Some(wait) = queue_wait {
+            let wait = wait.as_micros() as u64;
+            timings.queued.fetch_add(1, Ordering::Relaxed);
+            timings.queue_wait_total.fetch_add(wait, Ordering::Relaxed);
+            timings.queue_wait_max.fetch_max(wait, Ordering::Relaxed);
+        }
+        if let Some(backlog) = backlog {
+            // Zero means never sampled, so the values are stored plus one
+            let queued = backlog.queued.saturating_add(1);
+            timings.backlog.store(queued, Ordering::Relaxed);
+            timings.backlog_peak.fetch_max(queued, Ordering::Relaxed);
+            timings.backlog_limit.store(backlog.limit.saturating_add(1), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the sampled accept loop timings and admission drops
+    pub fn accept_stats(&self) -> AcceptStats {
+        let timings = &self.accept;
+        let samples = timings.samples.load(Ordering::Relaxed);
+        let queued = timings.queued.load(Ordering::Relaxed);
+        let sampled = |value: &AtomicU32| value.load(Ordering::Relaxed).checked_sub(1);
+        AcceptStats {
+            samples,
+            delay_avg_us: timings.delay_total.load(Ordering::Relaxed).checked_div(samples).unwrap_or(0),
+            delay_max_us: timings.delay_max.load(Ordering::Relaxed),
+            queued,
+            queue_wait_avg_us: timings.queue_wait_total.load(Ordering::Relaxed).checked_div(queued).unwrap_or(0),
+            queue_wait_max_us: timings.queue_wait_max.load(Ordering::Relaxed),
+            admission_drops: CloseReason::ALL
+                .into_iter()
+                .filter(CloseReason::is_admission)
+                .map(|reason| self.closed(reason))
+                .sum(),
+            backlog: sampled(&timings.backlog),
+            backlog_peak: sampled(&timings.backlog_peak),
+            backlog_limit: sampled(&timings.backlog_limit),
+        }
+    }
+
+    /// Records a connection that relied on a compatibility quirk
+    pub fn record_quirk(&self, quirk: Quirk) {
+        self.quirks[quirk.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the total number of accepted connections
+    pub fn connections_accepted(&self) -> u64 {
+        self.connections_accepted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connections currently being handled
+    pub fn active_connections(&self) -> u64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many connections were closed for the given reason
+    pub fn closed(&self, reason: CloseReason) -> u64 {
+        self.closed[reason.index()].load(Ordering::Relaxed)
+    }
+
+    /// Returns how many denials were logged in shadow mode instead of enforced
+    pub fn shadow_denials(&self) -> u64 {
+        self.shadow_denials.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many connection tasks panicked
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many connections were relayed in the given class of service
+    pub fn priority_connections(&self, priority: Priority) -> u64 {
+        self.priorities[priority.index()].load(Ordering::Relaxed)
+    }
+
+    /// Returns how many requests were refused for a port of the given class
+    pub fn port_denials(&self, class: PortClass) -> u64 {
+        self.port_denials[class.index()].load(Ordering::Relaxed)
+    }
+
+    /// Returns how many alerts were raised for breached latency objectives
+    pub fn slo_alerts(&self) -> u64 {
+        self.slo_alerts.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many TLS handshakes failed
+    pub fn tls_handshake_failures(&self) -> u64 {
+        self.tls_handshake_failures.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many TLS handshakes did not finish within their deadline
+    pub fn tls_handshake_timeouts(&self) -> u64 {
+        self.tls_handshake_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many times the given hook overran its time budget
+    pub fn hook_overruns(&self, hook: Hook) -> u64 {
+        self.hook_overruns[hook.index()].load(Ordering::Relaxed)
+    }
+
+    /// Returns how many connections relied on the given quirk
+    pub fn quirk_hits(&self, quirk: Quirk) -> u64 {
+        self.quirks[quirk.index()].load(Ordering::Relaxed)
+    }
+
+    /// Takes a snapshot of the counters, labelled with their listener
+    ///
+    /// Close reasons and quirks that never occurred are left out. The
+    /// listener is reported as accepting; the server overrides that from
+    /// its accept loop's health and adds the process's resource usage.
+    pub fn snapshot(&self, listener: ListenerLabel) -> ListenerStats {
+        ListenerStats {
+            listener,
+            accepting: true,
+            connections_accepted: self.connections_accepted(),
+            active_connections: self.active_connections(),
+            closed: CloseReason::ALL
+                .into_iter()
+                .map(|reason| (reason.as_str(), self.closed(reason)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            shadow_denials: self.shadow_denials(),
+            panics: self.panics(),
+            quirks: Quirk::ALL
+                .into_iter()
+                .map(|quirk| (quirk.as_str(), self.quirk_hits(quirk)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            priorities: Priority::ALL
+                .into_iter()
+                .map(|priority| (priority.as_str(), self.priority_connections(priority)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            port_denials: PortClass::ALL
+                .into_iter()
+                .map(|class| (class.as_str(), self.port_denials(class)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            slo_alerts: self.slo_alerts(),
+            hook_overruns: Hook::ALL
+                .into_iter()
+                .map(|hook| (hook.as_str(), self.hook_overruns(hook)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            accept: self.accept_stats(),
+            tls_handshake_failures: self.tls_handshake_failures(),
+            tls_handshake_timeouts: self.tls_handshake_timeouts(),
+            // Filled in by the owner of the tables and the process
+            evictions: BTreeMap::new(),
+            process: None,
+        }
+    }
+}
+
+/// Records the close of one tracked connection exactly once
+///
+/// Created by [`Metrics::track`]. The counters are atomics, so a panicking
+/// task cannot leave them locked or poisoned for other connections.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    /// The counters the connection is tracked in
+    metrics: Arc<Metrics>,
+    /// Whether the close was already recorded
+    closed: bool,
+}
+
+impl ConnectionGuard {
+    /// Records that the connection closed for `reason`
+    pub fn close(mut self, reason: CloseReason) {
+        self.closed = true;
+        self.metrics.record_close(reason);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if std::thread::panicking() {
+            log::error!("Connection task panicked, closing connection");
+            self.metrics.record_panic();
+            self.metrics.record_close(CloseReason::Panic);
+        } else {
+            log::debug!("Connection task dropped before it finished");
+            self.metrics.record_close(CloseReason::Aborted);
+        }
+    }
+}
+
+/// Identifies the front-end a set of counters belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerLabel {
+    /// The address the listener accepts connections on
+    pub address: String,
+    /// The protocol spoken to clients, e.g. `socks5`
+    pub protocol: String,
+    /// Whether client connections are wrapped in TLS
+    pub tls: bool,
+}
+
+impl fmt::Display for ListenerLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} tls={}", self.protocol, self.address, if self.tls { "yes" } else { "no" })
+    }
+}
+
+/// A point-in-time copy of one listener's counters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerStats {
+    /// The listener the counters belong to
+    pub listener: ListenerLabel,
+    /// Whether the listener's last accept succeeded
+    pub accepting: bool,
+    /// Total number of accepted client connections
+    pub connections_accepted: u64,
+    /// Number of client connections currently being handled
+    pub active_connections: u64,
+    /// Closed connections per close reason
+    pub closed: BTreeMap<&'static str, u64>,
+    /// Denials logged in shadow mode instead of enforced
+    pub shadow_denials: u64,
+    /// Connection tasks that panicked
+    pub panics: u64,
+    /// Connections per compatibility quirk relied on
+    pub quirks: BTreeMap<&'static str, u64>,
+    /// Connections per class of service, when bandwidth limits are configured
+    pub priorities: BTreeMap<&'static str, u64>,
+    /// Requests refused for their target port, per port class
+    pub port_denials: BTreeMap<&'static str, u64>,
+    /// Alerts raised for breached connect latency objectives
+    pub slo_alerts: u64,
+    /// Hooks abandoned for overrunning their time budget, per hook
+    pub hook_overruns: BTreeMap<&'static str, u64>,
+    /// Sampled accept loop timings and admission drops
+    pub accept: AcceptStats,
+    /// TLS handshakes that failed, on listeners that terminate TLS
+    pub tls_handshake_failures: u64,
+    /// TLS handshakes that did not finish within their deadline
+    pub tls_handshake_timeouts: u64,
+    /// Entries evicted from full per-client tables, per table
+    pub evictions: BTreeMap<&'static str, u64>,
+    /// Resource usage of the process serving the listener
+    pub process: Option<ProcessStats>,
+}
+
+/// Where accepted connections spend their time before they are served
+///
+/// Timings come from every Nth accepted connection. A high delay points at
+/// the accept loop's admission checks, a long queue wait at the session
+/// limit, and an accept queue filling towards its limit at an accept loop
+/// that cannot keep up; if all three stay low, the relays are the
+/// bottleneck.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AcceptStats {
+    /// Number of connections timed from accept to task spawn
+    pub samples: u64,
+    /// Mean sampled delay from accept to task spawn, in microseconds
+    pub delay_avg_us: u64,
+    /// Longest sampled delay from accept to task spawn, in microseconds
+    pub delay_max_us: u64,
+    /// Number of sampled accepts that first waited for a free session
+    pub queued: u64,
+    /// Mean sampled wait for a free session, in microseconds
+    pub queue_wait_avg_us: u64,
+    /// Longest sampled wait for a free session, in microseconds
+    pub queue_wait_max_us: u64,
+    /// Connections dropped by admission control before getting a task
+    pub admission_drops: u64,
+    /// Connections waiting in the kernel's accept queue at the last sample
+    pub backlog: Option<u32>,
+    /// Most connections seen waiting in the kernel's accept queue
+    pub backlog_peak: Option<u32>,
+    /// Size of the kernel's accept queue
+    pub backlog_limit: Option<u32>,
+}
+
+/// Resource usage of the proxy process
+///
+/// Values the platform cannot report are `None`: memory and descriptors
+/// are read from `/proc`, task counts from the current Tokio runtime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProcessStats {
+    /// Seconds since the server was created
+    pub uptime_secs: u64,
+    /// Resident set size in bytes
+    pub rss_bytes: Option<u64>,
+    /// Number of open file descriptors
+    pub open_fds: Option<u64>,
+    /// Number of tasks alive on the Tokio runtime
+    pub alive_tasks: Option<usize>,
+}
+
+impl ProcessStats {
+    /// Reads the current resource usage of the process
+    ///
+    /// # Arguments
+    /// * `uptime` - How long the server has been running
+    pub fn collect(uptime: Duration) -> Self {
+        Self {
+            uptime_secs: uptime.as_secs(),
+            rss_bytes: resident_bytes(),
+            open_fds: std::fs::read_dir("/proc/self/fd").ok().map(|fds| fds.count() as u64),
+            alive_tasks: tokio::runtime::Handle::try_current()
+                .ok()
+                .map(|handle| handle.metrics().num_alive_tasks()),
+        }
+    }
+}
+
+/// Reads the resident set size from `/proc/self/status`
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+impl fmt::Display for ListenerStats {
+    /// Formats the stats as one `key=value` line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "listener=\"{}\" accepted={} active={}",
+            self.listener, self.connections_accepted, self.active_connections
+        )?;
+        if !self.accepting {
+            write!(f, " accepting=no")?;
+        }
+        for (reason, count) in &self.closed {
+            write!(f, " closed.{}={}", reason, count)?;
+        }
+        if self.shadow_denials > 0 {
+            write!(f, " shadow_denials={}", self.shadow_denials)?;
+        }
+        if self.panics > 0 {
+            write!(f, " panics={}", self.panics)?;
+        }
+        for (quirk, count) in &self.quirks {
+            write!(f, " quirk.{}={}", quirk, count)?;
+        }
+        for (priority, count) in &self.priorities {
+            write!(f, " priority.{}={}", priority, count)?;
+        }
+        for (class, count) in &self.port_denials {
+            write!(f, " port_denied.{}={}", class, count)?;
+        }
+        if self.slo_alerts > 0 {
+            write!(f, " slo_alerts={}", self.slo_alerts)?;
+        }
+        for (hook, count) in &self.hook_overruns {
+            write!(f, " hook_overrun.{}={}", hook, count)?;
+        }
+        let accept = &self.accept;
+        if accept.samples > 0 {
+            write!(
+                f,
+                " accept.samples={} accept.delay_avg={}us accept.delay_max={}us",
+                accept.samples, accept.delay_avg_us, accept.delay_max_us
+            )?;
+        }
+        if accept.queued > 0 {
+            write!(
+                f,
+                " accept.queued={} accept.queue_wait_avg={}us accept.queue_wait_max={}us",
+                accept.queued, accept.queue_wait_avg_us, accept.queue_wait_max_us
+            )?;
+        }
+        if accept.admission_drops > 0 {
+            write!(f, " accept.dropped={}", accept.admission_drops)?;
+        }
+        if let (Some(backlog), Some(peak), Some(limit)) = (accept.backlog, accept.backlog_peak, accept.backlog_limit) {
+            write!(f, " backlog={}/{} backlog_peak={}", backlog, limit, peak)?;
+        }
+        if self.tls_handshake_failures > 0 {
+            write!(f, " tls.handshake_failed={}", self.tls_handshake_failures)?;
+        }
+        if self.tls_handshake_timeouts > 0 {
+            write!(f, " tls.handshake_timeout={}", self.tls_handshake_timeouts)?;
+        }
+        for (table, count) in &self.evictions {
+            write!(f, " evicted.{}={}", table, count)?;
+        }
+        if let Some(process) = &self.process {
+            write!(f, " uptime={}s", process.uptime_secs)?;
+            if let Some(rss) = process.rss_bytes {
+                write!(f, " rss={}", rss)?;
+            }
+            if let Some(fds) = process.open_fds {
+                write!(f, " fds={}", fds)?;
+            }
+            if let Some(tasks) = process.alive_tasks {
+                write!(f, " tasks={}", tasks)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Real-time request mirroring for the SOCKS5 proxy.
+//!
+//! Every accepted request can be published as a JSON datagram to an external
+//! sink (UDP or a Unix datagram socket) so security tooling can evaluate it
+//! as it happens, independently of any log or audit file.
+
+use serde::Serialize;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{Socks5Error, Socks5Result};
+
+/// Metadata of one client request, as published to the mirror sink
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RequestEvent {
+    /// The connection ID used in log lines, as hex
+    pub conn_id: String,
+    /// Wall-clock time of the request in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// The client's address
+    pub client: SocketAddr,
+    /// The protocol of the request: `socks5`, `socks4` or `http`
+    pub protocol: &'static str,
+    /// The requested target as `host:port`
+    pub target: String,
+    /// Where the proxy connects instead, if a route rewrote the target
+    pub rewritten_target: Option<String>,
+    /// The authenticated username, if any
+    pub username: Option<String>,
+    /// The authentication method selected in the handshake
+    pub method: u8,
+    /// The authentication methods offered by the client
+    pub offered_methods: Vec<u8>,
+    /// The policy rule chain that decided the request, if decisions are traced
+    pub decision: Option<String>,
+}
+
+/// Where mirrored requests are sent
+///
+/// Plain non-blocking std sockets are used so a send is a single syscall
+/// that either succeeds or fails right away.
+#[derive(Debug)]
+enum Sink {
+    /// A connected UDP socket
+    Udp(UdpSocket),
+    /// A connected Unix datagram socket
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+/// Publishes request events to an external datagram sink
+///
+/// Publishing never waits: if the sink cannot take a datagram right away,
+/// the event is dropped and counted, so a slow consumer cannot stall clients.
+#[derive(Debug)]
+pub struct RequestMirror {
+    /// The connected sink socket
+    sink: Sink,
+    /// A description of the sink for logging
+    destination: String,
+    /// Events that could not be sent
+    dropped: AtomicU64,
+}
+
+impl RequestMirror {
+    /// Creates a mirror sending to a UDP address
+    ///
+    /// # Arguments
+    /// * `addr` - The address of the collector
+    pub fn udp(addr: SocketAddr) -> io::Result<Self> {
+        let local: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::with_sink(Sink::Udp(socket), format!("udp://{}", addr)))
+    }
+
+    /// Creates a mirror sending to a Unix datagram socket
+    ///
+    /// # Arguments
+    /// * `path` - The path of the collector's socket
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path.as_ref())?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::with_sink(Sink::Unix(socket), format!("unix://{}", path.as_ref().display())))
+    }
+
+    /// Creates a mirror from `udp://HOST:PORT` or `unix:///PATH`
+    ///
+    /// # Returns
+    /// * `Ok(RequestMirror)` - The connected mirror
+    /// * `Err(Socks5Error)` - If the URL is invalid or the sink cannot be reached
+    pub async fn connect(url: &str) -> Socks5Result<Self> {
+        if let Some(addr) = url.strip_prefix("udp://") {
+            let addr = tokio::net::lookup_host(addr).await?.next().ok_or_else(|| {
+                Socks5Error::ConfigError(format!("Mirror address resolved to nothing: {}", url))
+            })?;
+            return Ok(Self::udp(addr)?);
+        }
+        #[cfg(unix)]
+        if let Some(path) = url.strip_prefix("unix://") {
+            return Ok(Self::unix(path)?);
+        }
+        Err(Socks5Error::ConfigError(format!("Unsupported mirror URL: {}", url)))
+    }
+
+    fn with_sink(sink: Sink, destination: String) -> Self {
+        Self {
+            sink,
+            destination,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a description of the sink, e.g. `udp://127.0.0.1:9000`
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Returns how many events could not be sent
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends an event as one JSON datagram without waiting
+    pub fn publish(&self, event: &RequestEvent) {
+        let payload = match serde_json::to_vec(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                log::debug!("Cannot encode request event: {}", e);
+                return;
+            }
+        };
+        let sent = match &self.sink {
+            Sink::Udp(socket) => socket.send(&payload),
+            #[cfg(unix)]
+            Sink::Unix(socket) => socket.send(&payload),
+        };
+        if let Err(e) = sent {
+            log::debug!("Dropping request event for {}: {}", self.destination, e);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
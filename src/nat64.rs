@@ -0,0 +1,115 @@
+//! NAT64 address synthesis for IPv6-only hosts.
+//!
+//! On hosts without IPv4 connectivity, IPv4 targets are reached through a
+//! NAT64 gateway by embedding the IPv4 address in a /96 IPv6 prefix
+//! (RFC 6052). The prefix is either configured or discovered via RFC 7050.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::error::Socks5Error;
+
+/// The well-known NAT64 prefix `64:ff9b::/96`
+pub const WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// The name resolved to discover the local NAT64 prefix (RFC 7050)
+const DISCOVERY_NAME: &str = "ipv4only.arpa";
+
+/// The IPv4 addresses `ipv4only.arpa` resolves to
+const DISCOVERY_ADDRS: [Ipv4Addr; 2] = [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+/// A /96 NAT64 prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nat64Prefix(Ipv6Addr);
+
+impl Nat64Prefix {
+    /// Creates a prefix from an address; the low 32 bits are ignored
+    pub fn new(prefix: Ipv6Addr) -> Self {
+        let mut octets = prefix.octets();
+        octets[12..].fill(0);
+        Self(Ipv6Addr::from(octets))
+    }
+
+    /// Returns the prefix address
+    pub fn addr(&self) -> Ipv6Addr {
+        self.0
+    }
+
+    /// Embeds an IPv4 address in the prefix
+    ///
+    /// # Arguments
+    /// * `addr` - The IPv4 address to translate
+    ///
+    /// # Returns
+    /// * The synthesized IPv6 address routed through the NAT64 gateway
+    pub fn synthesize(&self, addr: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.0.octets();
+        octets[12..].copy_from_slice(&addr.octets());
+        Ipv6Addr::from(octets)
+    }
+
+    /// Extracts the prefix from a synthesized address of a known IPv4 address
+    fn from_synthesized(addr: Ipv6Addr, embedded: Ipv4Addr) -> Option<Self> {
+        (addr.octets()[12..] == embedded.octets()).then(|| Self::new(addr))
+    }
+}
+
+impl Default for Nat64Prefix {
+    fn default() -> Self {
+        Self(WELL_KNOWN_PREFIX)
+    }
+}
+
+impl fmt::Display for Nat64Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/96", self.0)
+    }
+}
+
+impl FromStr for Nat64Prefix {
+    type Err = Socks5Error;
+
+    /// Parses `64:ff9b::` or `64:ff9b::/96`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addr = match s.split_once('/') {
+            Some((addr, "96")) => addr,
+            Some((_, len)) => {
+                return Err(Socks5Error::AddressError(format!(
+                    "Unsupported NAT64 prefix length /{}, only /96 is supported", len
+                )));
+            }
+            None => s,
+        };
+        addr.parse::<Ipv6Addr>()
+            .map(Self::new)
+            .map_err(|_| Socks5Error::AddressError(format!("Invalid NAT64 prefix: {}", s)))
+    }
+}
+
+/// Discovers the local NAT64 prefix as described in RFC 7050
+///
+/// Resolves `ipv4only.arpa`, which only has A records; a DNS64 resolver
+/// answers with synthesized AAAA records revealing the prefix in use.
+///
+/// # Returns
+/// * `Some(Nat64Prefix)` - The discovered prefix
+/// * `None` - If the resolver does not perform DNS64 synthesis
+pub async fn discover_prefix() -> Option<Nat64Prefix> {
+    let addrs = match tokio::net::lookup_host((DISCOVERY_NAME, 0)).await {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            log::debug!("NAT64 prefix discovery failed: {}", e);
+            return None;
+        }
+    };
+    
+    addrs
+        .filter_map(|addr| match addr.ip() {
+            std::net::IpAddr::V6(v6) => Some(v6),
+            std::net::IpAddr::V4(_) => None,
+        })
+        .find_map(|v6| {
+            DISCOVERY_ADDRS.iter().find_map(|known| Nat64Prefix::from_synthesized(v6, *known))
+        })
+}
@@ -0,0 +1,244 @@
+//! NAT-PMP port mapping for the SOCKS5 proxy.
+//!
+//! Home servers usually sit behind a router that does not forward the SOCKS
+//! port. With the `natpmp` feature, a server can ask the local gateway for a
+//! TCP port mapping with NAT-PMP (RFC 6886) once it is listening, log the
+//! external address and keep renewing the mapping while it serves, so the
+//! proxy is reachable without manual router configuration. Most routers
+//! speaking UPnP IGD also answer NAT-PMP or its successor PCP, which accepts
+//! NAT-PMP requests.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use crate::error::{Socks5Error, Socks5Result};
+
+/// The port gateways receive NAT-PMP requests on
+pub const NATPMP_PORT: u16 = 5351;
+
+/// How long a mapping is requested for by default
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// How long the first request waits for an answer; doubled on each retry
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How often a request is sent before the gateway is considered silent
+const ATTEMPTS: u32 = 4;
+
+/// How long to wait before retrying a failed renewal
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// NAT-PMP version byte
+const VERSION: u8 = 0;
+
+/// Opcode of external address requests
+const OP_EXTERNAL_ADDRESS: u8 = 0;
+
+/// Opcode of TCP mapping requests
+const OP_MAP_TCP: u8 = 2;
+
+/// Added to an opcode in the gateway's response
+const RESPONSE: u8 = 128;
+
+/// A port mapping granted by the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    /// The gateway's external address, if it reported one
+    pub external_ip: Option<Ipv4Addr>,
+    /// The external port forwarded to the server
+    pub external_port: u16,
+    /// How long the gateway keeps the mapping
+    pub lifetime: Duration,
+}
+
+/// Requests and renews TCP port mappings from a NAT-PMP gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapper {
+    /// The gateway's NAT-PMP address
+    gateway: SocketAddr,
+    /// How long mappings are requested for
+    lifetime: Duration,
+}
+
+impl PortMapper {
+    /// Creates a mapper talking to `gateway` on the NAT-PMP port
+    pub fn new(gateway: Ipv4Addr) -> Self {
+        Self::with_address(SocketAddr::from((gateway, NATPMP_PORT)))
+    }
+
+    /// Creates a mapper talking to a gateway on a specific address and port
+    pub fn with_address(gateway: SocketAddr) -> Self {
+        Self {
+            gateway,
+            lifetime: DEFAULT_LIFETIME,
+        }
+    }
+
+    /// Creates a mapper for the system's default IPv4 gateway
+    ///
+    /// # Returns
+    /// * `Ok(PortMapper)` - A mapper for the default gateway
+    /// * `Err(Socks5Error)` - If no default gateway can be found
+    pub fn for_default_gateway() -> Socks5Result<Self> {
+        default_gateway()
+            .map(Self::new)
+            .ok_or_else(|| Socks5Error::ConfigError("Cannot find the default gateway for NAT-PMP".to_string()))
+    }
+
+    /// Sets how long mappings are requested for
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Returns the gateway's NAT-PMP address
+    pub fn gateway(&self) -> SocketAddr {
+        self.gateway
+    }
+
+    /// Returns how long mappings are requested for
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Asks the gateway for its external address
+    pub async fn external_address(&self) -> Socks5Result<Ipv4Addr> {
+        let response = self.request(&[VERSION, OP_EXTERNAL_ADDRESS], OP_EXTERNAL_ADDRESS, 12).await?;
+        Ok(Ipv4Addr::new(response[8], response[9], response[10], response[11]))
+    }
+
+    /// Maps an external TCP port to `internal_port`, preferring the same port
+    ///
+    /// The external address is looked up as well; gateways that do not
+    /// report it still grant the mapping.
+    pub async fn map_tcp(&self, internal_port: u16) -> Socks5Result<Mapping> {
+        let request = encode_map_request(internal_port, internal_port, self.lifetime);
+        let response = self.request(&request, OP_MAP_TCP, 16).await?;
+        let (external_port, lifetime) = parse_map_response(&response)?;
+        let external_ip = match self.external_address().await {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                log::debug!("NAT-PMP gateway {} did not report its external address: {}", self.gateway, e);
+                None
+            }
+        };
+        Ok(Mapping { external_ip, external_port, lifetime })
+    }
+
+    /// Maps `internal_port` and renews the mapping at half its lifetime until aborted
+    ///
+    /// Failures are logged and retried; they never stop the server.
+    pub fn spawn_renewal(&self, internal_port: u16) -> JoinHandle<()> {
+        let mapper = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let delay = match mapper.map_tcp(internal_port).await {
+                    Ok(mapping) => {
+                        match mapping.external_ip {
+                            Some(ip) => log::info!(
+                                "NAT-PMP gateway {} maps {}:{} to local port {} for {}s",
+                                mapper.gateway, ip, mapping.external_port, internal_port, mapping.lifetime.as_secs()
+                            ),
+                            None => log::info!(
+                                "NAT-PMP gateway {} maps external port {} to local port {} for {}s",
+                                mapper.gateway, mapping.external_port, internal_port, mapping.lifetime.as_secs()
+                            ),
+                        }
+                        (mapping.lifetime / 2).max(Duration::from_secs(1))
+                    }
+                    Err(e) => {
+                        log::warn!("NAT-PMP mapping of port {} via {} failed: {}", internal_port, mapper.gateway, e);
+                        RETRY_DELAY
+                    }
+                };
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+
+    /// Sends a request until a matching response arrives or the attempts run out
+    async fn request(&self, request: &[u8], opcode: u8, len: usize) -> Socks5Result<Vec<u8>> {
+        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+        socket.connect(self.gateway).await?;
+        let mut timeout = INITIAL_TIMEOUT;
+        let mut buf = [0; 16];
+        for _ in 0..ATTEMPTS {
+            socket.send(request).await?;
+            let deadline = tokio::time::Instant::now() + timeout;
+            while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                let n = received?;
+                if n < len || buf[0] != VERSION || buf[1] != RESPONSE + opcode {
+                    continue;
+                }
+                let result = u16::from_be_bytes([buf[2], buf[3]]);
+                if result != 0 {
+                    return Err(Socks5Error::ConnectionError(format!(
+                        "NAT-PMP gateway {} refused the request: {}", self.gateway, result_name(result)
+                    )));
+                }
+                return Ok(buf[..len].to_vec());
+            }
+            timeout *= 2;
+        }
+        Err(Socks5Error::ConnectionError(format!("NAT-PMP gateway {} did not answer", self.gateway)))
+    }
+}
+
+/// Encodes a TCP mapping request
+///
+/// A lifetime of zero asks the gateway to delete the mapping.
+pub fn encode_map_request(internal_port: u16, external_port: u16, lifetime: Duration) -> [u8; 12] {
+    let lifetime = u32::try_from(lifetime.as_secs()).unwrap_or(u32::MAX);
+    let mut request = [0; 12];
+    request[0] = VERSION;
+    request[1] = OP_MAP_TCP;
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime.to_be_bytes());
+    request
+}
+
+/// Parses a TCP mapping response into the external port and lifetime
+pub fn parse_map_response(response: &[u8]) -> Socks5Result<(u16, Duration)> {
+    if response.len() < 16 || response[0] != VERSION || response[1] != RESPONSE + OP_MAP_TCP {
+        return Err(Socks5Error::ConnectionError("Malformed NAT-PMP mapping response".to_string()));
+    }
+    let result = u16::from_be_bytes([response[2], response[3]]);
+    if result != 0 {
+        return Err(Socks5Error::ConnectionError(format!("NAT-PMP mapping refused: {}", result_name(result))));
+    }
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+    Ok((external_port, Duration::from_secs(u64::from(lifetime))))
+}
+
+/// Returns the meaning of a NAT-PMP result code
+fn result_name(result: u16) -> &'static str {
+    match result {
+        1 => "unsupported version",
+        2 => "not authorized",
+        3 => "network failure",
+        4 => "out of resources",
+        5 => "unsupported opcode",
+        _ => "unknown result code",
+    }
+}
+
+/// Returns the system's default IPv4 gateway, if it can be found
+///
+/// Only Linux is supported, where the routing table is read from
+/// `/proc/net/route`.
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        // The kernel prints the address bytes as one native-endian number
+        Some(Ipv4Addr::from(gateway.to_ne_bytes())).filter(|ip| !ip.is_unspecified())
+    })
+}
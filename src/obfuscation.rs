@@ -0,0 +1,86 @@
+//! Active-probing resistance for the SOCKS5 listener.
+//!
+//! In this opt-in mode a client must open with a pre-shared preamble before
+//! speaking SOCKS5. Anything else gets no bytes back at all, so the listener
+//! cannot easily be fingerprinted as a SOCKS server.
+
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long a client has to send the full preamble by default
+pub const DEFAULT_PREAMBLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the server does with a client that does not send the preamble
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeResponse {
+    /// Close the connection immediately
+    Close,
+    /// Keep reading and discarding data for the given time, then close,
+    /// mimicking a service that simply never answers
+    Hold(Duration),
+}
+
+/// Pre-shared preamble settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeResistance {
+    /// The bytes a client must send before the SOCKS5 greeting
+    preamble: Vec<u8>,
+    /// How clients without the preamble are treated
+    on_mismatch: ProbeResponse,
+}
+
+impl ProbeResistance {
+    /// Creates a new probe resistance configuration
+    ///
+    /// # Arguments
+    /// * `preamble` - The bytes a client must send before the SOCKS5 greeting
+    /// * `on_mismatch` - How clients without the preamble are treated
+    pub fn new(preamble: Vec<u8>, on_mismatch: ProbeResponse) -> Self {
+        Self { preamble, on_mismatch }
+    }
+
+    /// Returns the expected preamble
+    pub fn preamble(&self) -> &[u8] {
+        &self.preamble
+    }
+
+    /// Returns how clients without the preamble are treated
+    pub fn on_mismatch(&self) -> ProbeResponse {
+        self.on_mismatch
+    }
+
+    /// Reads and checks the preamble from a freshly accepted client
+    ///
+    /// Never writes to the stream. On a mismatch the configured response is
+    /// carried out before returning.
+    ///
+    /// # Arguments
+    /// * `stream` - The client stream
+    /// * `deadline` - How long to wait for the full preamble
+    ///
+    /// # Returns
+    /// * `true` - If the preamble matched and was consumed
+    /// * `false` - If the client must be disconnected
+    pub async fn check<S>(&self, stream: &mut S, deadline: Duration) -> bool
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut received = vec![0; self.preamble.len()];
+        let matched = matches!(
+            tokio::time::timeout(deadline, stream.read_exact(&mut received)).await,
+            Ok(Ok(_))
+        ) && constant_time_eq(&received, &self.preamble);
+        
+        if !matched {
+            if let ProbeResponse::Hold(duration) = self.on_mismatch {
+                let _ = tokio::time::timeout(duration, tokio::io::copy(stream, &mut tokio::io::sink())).await;
+            }
+        }
+        matched
+    }
+}
+
+/// Compares two byte strings without short-circuiting on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,360 @@
+//! Standalone evaluation of the proxy's target policies.
+//!
+//! [`PolicyEngine`] bundles the checks a server runs on every request (port
+//! policy, IP-literals-only, the ACL rules, imported blocklists, the target
+//! allow-list) together with the
+//! routing table, and evaluates them into a [`Decision`]. The server uses
+//! the same engine, so embedders can pre-check destinations, preview rule
+//! changes or unit test their rule files and get exactly what live traffic
+//! would get.
+
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::acl::{AclRules, PortClass, PortPolicy, TargetAllowList};
+use crate::blocklist::Blocklists;
+use crate::canonical::{canonicalize, ip_literal, CanonicalCache};
+use crate::constants::reply;
+use crate::protocol::TargetAddr;
+use crate::routing::{Route, RoutingTable};
+
+/// Who is asking for a target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PolicyContext<'a> {
+    /// The client's address, if known
+    pub client: Option<SocketAddr>,
+    /// The authenticated username, if any
+    pub username: Option<&'a str>,
+}
+
+/// Why a policy refuses a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    /// Short name of the refusing policy, e.g. `allow-list`
+    pub policy: &'static str,
+    /// The SOCKS5 reply code the client gets
+    pub reply: u8,
+    /// An explanation for logs and clients that ask for one
+    pub reason: Cow<'static, str>,
+    /// The class of the refused port, for port policy denials
+    pub port_class: Option<PortClass>,
+    /// The index of the refusing rule in [`AclRules::rules`], for ACL denials
+    pub rule: Option<usize>,
+    /// The name of the refusing rule, for ACL denials by named rules
+    pub rule_name: Option<String>,
+    /// Whether the denial is only logged and counted (shadow mode)
+    pub shadow: bool,
+}
+
+/// The outcome of evaluating a target against the policies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    /// Names of the policies that were checked, in order
+    pub evaluated: Vec<&'static str>,
+    /// Denials in evaluation order; evaluation stops at the first enforced one
+    pub denials: Vec<Denial>,
+    /// The route matching the target, if any
+    pub route: Option<Route>,
+    /// The address to connect to instead of the target, if its route rewrites it
+    pub rewritten: Option<TargetAddr>,
+}
+
+impl Decision {
+    /// Returns whether the request may proceed
+    pub fn is_allowed(&self) -> bool {
+        self.denial().is_none()
+    }
+
+    /// Returns the enforced denial, if any
+    pub fn denial(&self) -> Option<&Denial> {
+        self.denials.iter().find(|denial| !denial.shadow)
+    }
+
+    /// Returns the denials that shadow mode did not enforce
+    pub fn shadowed(&self) -> impl Iterator<Item = &Denial> {
+        self.denials.iter().filter(|denial| denial.shadow)
+    }
+
+    /// Records that `policy` was checked with `denials`
+    ///
+    /// Returns whether one of them is enforced, which ends the evaluation.
+    fn record(&mut self, policy: &'static str, denials: impl IntoIterator<Item = Denial>) -> bool {
+        self.evaluated.push(policy);
+        let before = self.denials.len();
+        self.denials.extend(denials);
+        self.denials[before..].iter().any(|denial| !denial.shadow)
+    }
+
+    /// Describes how the decision was reached, one step per rule
+    ///
+    /// Steps are separated by spaces, e.g.
+    /// `route=*.internal:80 port-policy=allow allow-list=shadow-deny`. ACL
+    /// denials name their rule, or give its index if it has no name,
+    /// several separated by commas, e.g. `acl=shadow-deny#0,deny#no-smtp`.
+    pub fn trace(&self) -> String {
+        let mut steps = Vec::with_capacity(self.evaluated.len() + 1);
+        if let Some(route) = &self.route {
+            steps.push(format!("route={}", route.pattern));
+        }
+        for policy in &self.evaluated {
+            let outcomes: Vec<String> = self
+                .denials
+                .iter()
+                .filter(|denial| denial.policy == *policy)
+                .map(|denial| {
+                    let outcome = if denial.shadow { "shadow-deny" } else { "deny" };
+                    match (&denial.rule_name, denial.rule) {
+                        (Some(name), _) => format!("{}#{}", outcome, name),
+                        (None, Some(index)) => format!("{}#{}", outcome, index),
+                        (None, None) => outcome.to_string(),
+                    }
+                })
+                .collect();
+            if outcomes.is_empty() {
+                steps.push(format!("{}=allow", policy));
+            } else {
+                steps.push(format!("{}={}", policy, outcomes.join(",")));
+            }
+        }
+        steps.join(" ")
+    }
+}
+
+/// The target policies of a server, evaluable on their own
+#[derive(Debug, Clone, Default)]
+pub struct PolicyEngine {
+    /// Which target ports are refused
+    port_policy: PortPolicy,
+    /// Whether domain name targets are refused
+    ip_literals_only: bool,
+    /// Ordered allow and deny rules for targets
+    acl: AclRules,
+    /// Domains imported from blocklists, if any
+    blocklists: Option<Arc<Blocklists>>,
+    /// The only targets clients may connect to, if restricted
+    allowed_targets: Option<TargetAllowList>,
+    /// Per-destination routes, for rewrites
+    routes: RoutingTable,
+    /// Whether every denial is only logged and counted
+    shadow: bool,
+    /// Matched routes of hot domains, shared with the connector
+    canonical: Option<Arc<CanonicalCache>>,
+}
+
+impl PolicyEngine {
+    /// Creates an engine that only refuses port 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets which target ports are refused
+    pub fn with_port_policy(mut self, port_policy: PortPolicy) -> Self {
+        self.port_policy = port_policy;
+        self
+    }
+
+    /// Refuses domain name targets
+    pub fn with_ip_literals_only(mut self, enabled: bool) -> Self {
+        self.ip_literals_only = enabled;
+        self
+    }
+
+    /// Refuses targets denied by ordered allow and deny rules
+    pub fn with_acl(mut self, acl: AclRules) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    /// Refuses domains on imported blocklists
+    ///
+    /// The lists are read as they are when a target is evaluated, so a
+    /// refresh applies to the engine right away.
+    pub fn with_blocklists(mut self, blocklists: Arc<Blocklists>) -> Self {
+        self.blocklists = Some(blocklists);
+        self
+    }
+
+    /// Restricts targets to an allow-list
+    pub fn with_allowed_targets(mut self, allowed_targets: TargetAllowList) -> Self {
+        self.allowed_targets = Some(allowed_targets);
+        self
+    }
+
+    /// Sets the routes that may rewrite targets
+    pub fn with_routes(mut self, routes: RoutingTable) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Only logs and counts denials instead of enforcing them
+    pub fn with_shadow_mode(mut self, enabled: bool) -> Self {
+        self.shadow = enabled;
+        self
+    }
+
+    /// Looks up the routes of domain targets through a cache
+    ///
+    /// The cache must only be used with this engine's routes, e.g. the one
+    /// of the connector the routes come from.
+    pub fn with_canonical_cache(mut self, cache: Arc<CanonicalCache>) -> Self {
+        self.canonical = Some(cache);
+        self
+    }
+
+    /// Returns the port policy
+    pub fn port_policy(&self) -> &PortPolicy {
+        &self.port_policy
+    }
+
+    /// Returns whether domain name targets are refused
+    pub fn ip_literals_only(&self) -> bool {
+        self.ip_literals_only
+    }
+
+    /// Returns the allow and deny rules for targets
+    pub fn acl(&self) -> &AclRules {
+        &self.acl
+    }
+
+    /// Returns the imported blocklists, if any
+    pub fn blocklists(&self) -> Option<&Arc<Blocklists>> {
+        self.blocklists.as_ref()
+    }
+
+    /// Returns the target allow-list, if any
+    pub fn allowed_targets(&self) -> Option<&TargetAllowList> {
+        self.allowed_targets.as_ref()
+    }
+
+    /// Returns the routes
+    pub fn routes(&self) -> &RoutingTable {
+        &self.routes
+    }
+
+    /// Returns whether denials are only logged and counted
+    pub fn shadow_mode(&self) -> bool {
+        self.shadow
+    }
+
+    /// Evaluates a request for `target` by the client described in `context`
+    ///
+    /// Policies are checked in order: port policy, IP-literals-only, the
+    /// ACL rules, the blocklists, then the allow-list. Denials in shadow
+    /// mode are recorded and evaluation continues; the first enforced denial
+    /// ends it, and the policies after it are not checked. ACL rules scoped
+    /// to a user or client network only apply when `context` matches them.
+    ///
+    /// Domain targets are canonicalized once, and every check sees the
+    /// canonical host. Domains spelling an IP literal, e.g. `10.0.0.1` or
+    /// `[::1]`, are checked as the address they resolve to.
+    pub fn evaluate(&self, context: &PolicyContext<'_>, target: &TargetAddr) -> Decision {
+        let literal = match target {
+            TargetAddr::Domain(domain, port) => ip_literal(domain).map(|ip| TargetAddr::from(SocketAddr::new(ip, *port))),
+            _ => None,
+        };
+        let target = literal.as_ref().unwrap_or(target);
+        let (canonical, route) = match (target, &self.canonical) {
+            (TargetAddr::Domain(domain, port), Some(cache)) => {
+                let canonical = cache.lookup(domain, *port, &self.routes);
+                let route = canonical.route.and_then(|index| self.routes.routes().get(index)).cloned();
+                let canonical = if *canonical.host == **domain {
+                    Cow::Borrowed(target)
+                } else {
+                    Cow::Owned(TargetAddr::Domain(canonical.host.to_string(), *port))
+                };
+                (canonical, route)
+            }
+            (TargetAddr::Domain(domain, port), None) => {
+                let canonical = match canonicalize(domain) {
+                    Cow::Borrowed(_) => Cow::Borrowed(target),
+                    Cow::Owned(host) => Cow::Owned(TargetAddr::Domain(host, *port)),
+                };
+                let route = self.routes.route_for(&canonical).cloned();
+                (canonical, route)
+            }
+            _ => (Cow::Borrowed(target), self.routes.route_for(target).cloned()),
+        };
+        let rewritten = route.as_ref().and_then(|route| route.rewrite(target));
+        let target = canonical.as_ref();
+        let mut decision = Decision {
+            evaluated: Vec::new(),
+            denials: Vec::new(),
+            route,
+            rewritten,
+        };
+
+        let denial = |policy, reply, reason| Denial {
+            policy,
+            reply,
+            reason: Cow::Borrowed(reason),
+            port_class: None,
+            rule: None,
+            rule_name: None,
+            shadow: self.shadow,
+        };
+        let port_denial = self.port_policy.check(target.port()).map(|class| Denial {
+            port_class: Some(class),
+            ..denial(
+                "port-policy",
+                class.reply(),
+                match class {
+                    PortClass::Zero => "port-policy: port 0 is not a valid target",
+                    PortClass::Privileged => "port-policy: privileged ports are refused",
+                    PortClass::Denied => "port-policy: port is denied",
+                    PortClass::Unlisted => "port-policy: port is not allowed",
+                },
+            )
+        });
+        if decision.record("port-policy", port_denial) {
+            return decision;
+        }
+        if self.ip_literals_only {
+            let literal_denial = matches!(target, TargetAddr::Domain(..)).then(|| {
+                denial(
+                    "ip-literals-only",
+                    reply::ADDRESS_TYPE_NOT_SUPPORTED,
+                    "ip-literals-only: domain targets are refused",
+                )
+            });
+            if decision.record("ip-literals-only", literal_denial) {
+                return decision;
+            }
+        }
+        if !self.acl.is_empty() {
+            let acl_denials = self.acl.denials(context, target).into_iter().map(|index| {
+                let rule = &self.acl.rules()[index];
+                let denial = denial("acl", reply::NOT_ALLOWED, "acl: target is denied by a rule");
+                Denial {
+                    reason: match &rule.name {
+                        Some(name) => Cow::Owned(format!("acl: blocked by rule {}", name)),
+                        None => denial.reason,
+                    },
+                    rule: Some(index),
+                    rule_name: rule.name.clone(),
+                    shadow: self.shadow || rule.shadow,
+                    ..denial
+                }
+            });
+            if decision.record("acl", acl_denials) {
+                return decision;
+            }
+        }
+        if let Some(blocklists) = &self.blocklists {
+            let blocked = blocklists
+                .is_blocked(target)
+                .then(|| denial("blocklist", reply::NOT_ALLOWED, "blocklist: target is on a blocklist"));
+            if decision.record("blocklist", blocked) {
+                return decision;
+            }
+        }
+        if let Some(allowed_targets) = &self.allowed_targets {
+            let unlisted = (!allowed_targets.is_allowed(target)).then(|| Denial {
+                shadow: self.shadow || allowed_targets.is_shadow(),
+                ..denial("allow-list", reply::NOT_ALLOWED, "allow-list: target is not allowed")
+            });
+            decision.record("allow-list", unlisted);
+        }
+        decision
+    }
+}
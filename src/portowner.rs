@@ -0,0 +1,155 @@
+//! Diagnostics for ports that are already taken.
+//!
+//! A bare "Address already in use" tells an operator nothing about what to
+//! do next. When binding fails that way, [`addr_in_use`] names the process
+//! listening on the port where the platform allows finding it (procfs on
+//! Linux), and suggests letting the server fall back to the next free port
+//! with `--port-range`.
+//!
+//! Sockets of processes run by other users can only be attributed with
+//! enough privileges; the owning uid is still reported then.
+
+use std::fmt;
+use std::io;
+
+/// A process listening on a port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortOwner {
+    /// The user ID the listening socket belongs to
+    pub uid: u32,
+    /// The listening process, if it could be found
+    pub process: Option<Process>,
+}
+
+/// A process found holding a socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    /// The process ID
+    pub pid: u32,
+    /// The command name, e.g. `nginx`
+    pub name: String,
+}
+
+/// Shows the process, e.g. `nginx (pid 1234)`, or only the uid if the
+/// process could not be found
+impl fmt::Display for PortOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.process {
+            Some(process) => write!(f, "{} (pid {}, uid {})", process.name, process.pid, self.uid),
+            None => write!(f, "a process of uid {}", self.uid),
+        }
+    }
+}
+
+/// A listening socket in a `/proc/net/tcp` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListeningSocket {
+    /// The local port
+    pub port: u16,
+    /// The user ID the socket belongs to
+    pub uid: u32,
+    /// The socket's inode, linking it to the descriptors of its process
+    pub inode: u64,
+}
+
+/// Reads the listening sockets from a `/proc/net/tcp` or `/proc/net/tcp6` table
+///
+/// Lines that are not listening sockets or cannot be parsed are skipped.
+///
+/// # Arguments
+/// * `table` - The contents of the table, header line included
+pub fn parse_listening(table: &str) -> Vec<ListeningSocket> {
+    /// The `st` value of sockets in the LISTEN state
+    const LISTEN: &str = "0A";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(3) != Some(&LISTEN) {
+                return None;
+            }
+            let (_, port) = fields.get(1)?.rsplit_once(':')?;
+            Some(ListeningSocket {
+                port: u16::from_str_radix(port, 16).ok()?,
+                uid: fields.get(7)?.parse().ok()?,
+                inode: fields.get(9)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Finds who listens on a TCP port, if the platform tells
+///
+/// # Returns
+/// * `Some(PortOwner)` - The listening socket's uid and, with enough
+///   privileges, its process
+/// * `None` - If no listener was found or the platform has no procfs
+pub fn find(port: u16) -> Option<PortOwner> {
+    find_in_procfs(port)
+}
+
+#[cfg(target_os = "linux")]
+fn find_in_procfs(port: u16) -> Option<PortOwner> {
+    let socket = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .into_iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| parse_listening(&table))
+        .find(|socket| socket.port == port)?;
+    Some(PortOwner {
+        uid: socket.uid,
+        process: process_with_socket(socket.inode),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn find_in_procfs(_port: u16) -> Option<PortOwner> {
+    None
+}
+
+/// Finds the process with a descriptor for the socket with `inode`
+#[cfg(target_os = "linux")]
+fn process_with_socket(inode: u64) -> Option<Process> {
+    let link = format!("socket:[{}]", inode);
+    std::fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+        // Descriptors of other users' processes are unreadable without privileges
+        let holds = std::fs::read_dir(entry.path().join("fd"))
+            .ok()?
+            .flatten()
+            .any(|fd| std::fs::read_link(fd.path()).is_ok_and(|target| target.as_os_str() == link.as_str()));
+        if !holds {
+            return None;
+        }
+        let name = std::fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+        Some(Process { pid, name: name.trim_end().to_string() })
+    })
+}
+
+/// Explains a bind that failed because the port is taken
+///
+/// # Arguments
+/// * `bind_addr` - The address the server tried to bind
+/// * `first_port` - The configured port
+/// * `last_port` - The last fallback port tried, `first_port` without fallback
+/// * `error` - The bind error
+///
+/// # Returns
+/// * An error of the same kind whose message names the port's owner, if it
+///   could be found, and how to get past the conflict
+pub fn addr_in_use(bind_addr: &str, first_port: u16, last_port: u16, error: io::Error) -> io::Error {
+    let owner = find(last_port).map_or_else(String::new, |owner| format!(" by {}", owner));
+    let message = if first_port < last_port {
+        format!(
+            "Ports {}-{} on {} are all in use (port {} is held{}); widen --port-range or free one of them",
+            first_port, last_port, bind_addr, last_port, owner
+        )
+    } else {
+        format!(
+            "Port {} on {} is already in use{}; stop that process, choose another port, or fall back to \
+             the next free one with --port-range {}-{}",
+            first_port, bind_addr, owner, first_port, first_port.saturating_add(10)
+        )
+    };
+    io::Error::new(error.kind(), format!("{} ({})", message, error))
+}
@@ -3,30 +3,119 @@
 //! This module handles the SOCKS5 protocol operations as defined in RFC 1928,
 //! including handshake, authentication, and command processing.
 
-use std::net::Ipv4Addr;
-use std::string::FromUtf8Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use crate::compat::{read_tolerant, Quirk, Quirks, SHORT_READ_GRACE};
 use crate::constants::{auth, atyp, cmd, reply, RESERVED, SOCKS_VERSION};
 use crate::error::{Socks5Error, Socks5Result};
+use crate::extensions::encode_denial_reason;
+use crate::listener::{peek, ClientStream};
+use crate::users::{AuthDecision, Authenticator, UserTable};
 
-/// Represents a target address in SOCKS5 protocol
-#[derive(Debug, Clone)]
-pub enum TargetAddr {
-    /// IPv4 address and port
-    Ipv4(Ipv4Addr, u16),
-    /// Domain name and port
-    Domain(String, u16),
+pub use rsocks5_core::TargetAddr;
+use rsocks5_core::Decoded;
+
+/// How long a failing connection may take to deliver its last reply and close
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The handshake error message for credentials the authenticator denied
+pub(crate) const AUTHENTICATION_FAILED: &str = "Authentication failed";
+
+/// The authentication methods of a greeting, stored inline
+///
+/// A greeting offers at most 255 methods, so they fit on the stack and
+/// parsing it needs no heap allocation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Methods {
+    /// How many of `bytes` are used
+    len: u8,
+    /// The methods, in the order sent
+    bytes: [u8; 255],
 }
 
-impl TargetAddr {
-    /// Converts the target address to a string representation
-    pub fn to_string(&self) -> String {
-        match self {
-            TargetAddr::Ipv4(addr, port) => format!("{}:{}", addr, port),
-            TargetAddr::Domain(domain, port) => format!("{}:{}", domain, port),
-        }
+impl Methods {
+    /// Creates a method list, keeping at most the first 255 methods
+    pub fn new(methods: &[u8]) -> Self {
+        let len = methods.len().min(255);
+        let mut bytes = [0; 255];
+        bytes[..len].copy_from_slice(&methods[..len]);
+        Self { len: len as u8, bytes }
+    }
+
+    /// Returns the methods as a slice
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+}
+
+impl std::ops::Deref for Methods {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl fmt::Debug for Methods {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl PartialEq<Vec<u8>> for Methods {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl PartialEq<[u8]> for Methods {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+/// A length-prefixed field of at most 255 bytes, read onto the stack
+struct Field {
+    /// How many of `bytes` are used
+    len: usize,
+    /// The field's bytes
+    bytes: [u8; 255],
+}
+
+impl Field {
+    /// Reads a field of `len` bytes
+    async fn read<S: AsyncRead + Unpin>(stream: &mut S, len: u8) -> io::Result<Self> {
+        let mut field = Field { len: usize::from(len), bytes: [0; 255] };
+        stream.read_exact(&mut field.bytes[..field.len]).await?;
+        Ok(field)
+    }
+
+    /// Returns the field as UTF-8 text
+    fn as_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.bytes[..self.len])
+    }
+}
+
+/// What was offered and negotiated during the SOCKS5 handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    /// The authentication methods offered by the client, in the order sent
+    pub offered_methods: Methods,
+    /// The authentication method selected by the server
+    pub method: u8,
+    /// The authenticated username, if username/password authentication was used
+    pub username: Option<String>,
+    /// The compatibility quirks the client relied on
+    pub quirks: Quirks,
+}
+
+impl HandshakeInfo {
+    /// Returns whether the client offered the private denial-reason method
+    pub fn wants_denial_reason(&self) -> bool {
+        self.offered_methods.contains(&auth::DENIAL_REASONS)
     }
 }
 
@@ -38,18 +127,104 @@ impl TargetAddr {
 /// 3. Authentication takes place if required
 ///
 /// # Arguments
-/// * `stream` - The TCP stream connected to the client
+/// * `stream` - The stream connected to the client
 /// * `username` - Optional username for authentication
 /// * `password` - Optional password for authentication
 ///
 /// # Returns
-/// - Ok(()) if handshake is successful
+/// - Ok(HandshakeInfo) with the offered and negotiated details if handshake is successful
 /// - Err(Socks5Error) if handshake fails
-pub async fn handshake(
-    stream: &mut TcpStream,
+pub async fn handshake<S: ClientStream>(
+    stream: &mut S,
     username: Option<&str>,
     password: Option<&str>
-) -> Socks5Result<()> {
+) -> Socks5Result<HandshakeInfo> {
+    handshake_with_compat(stream, username, password, Quirks::new()).await
+}
+
+/// Handles the SOCKS5 handshake, tolerating the enabled client quirks
+///
+/// With [`Quirk::ShortReads`], a greeting that delivers fewer methods than
+/// it announced is accepted with the methods that did arrive.
+///
+/// With [`Quirk::UnsolicitedCredentials`], a sub-negotiation (version byte 0x01,
+/// where a request would start with 0x05) following NO_AUTH is consumed and
+/// answered instead of failing later as a malformed request. The credentials
+/// are checked when the server has them configured; in that case clients
+/// offering only NO_AUTH are let through the method selection so they get a
+/// chance to send them, and are dropped if they do not.
+///
+/// # Arguments
+/// * `stream` - The stream connected to the client
+/// * `username` - Optional username for authentication
+/// * `password` - Optional password for authentication
+/// * `compat` - The quirks to tolerate
+///
+/// # Returns
+/// - Ok(HandshakeInfo) with the offered and negotiated details if handshake is successful
+/// - Err(Socks5Error) if handshake fails
+pub async fn handshake_with_compat<S: ClientStream>(
+    stream: &mut S,
+    username: Option<&str>,
+    password: Option<&str>,
+    compat: Quirks,
+) -> Socks5Result<HandshakeInfo> {
+    let users = match (username, password) {
+        (Some(username), Some(password)) => UserTable::new().with_user(username, password),
+        _ => UserTable::new(),
+    };
+    handshake_with_users(stream, &users, compat).await
+}
+
+/// Handles the SOCKS5 handshake against a table of user accounts
+///
+/// USER_PASS is only selected when `users` has accounts; clients then
+/// authenticate as any of them with an RFC 1929 sub-negotiation. With an
+/// empty table, NO_AUTH is selected. Quirks are tolerated as described for
+/// [`handshake_with_compat`].
+///
+/// # Arguments
+/// * `stream` - The stream connected to the client
+/// * `users` - The accounts clients may authenticate as
+/// * `compat` - The quirks to tolerate
+///
+/// # Returns
+/// - Ok(HandshakeInfo) with the offered and negotiated details if handshake is successful
+/// - Err(Socks5Error) if handshake fails
+pub async fn handshake_with_users<S: ClientStream>(
+    stream: &mut S,
+    users: &UserTable,
+    compat: Quirks,
+) -> Socks5Result<HandshakeInfo> {
+    let authenticator = (!users.is_empty()).then_some(users as &dyn Authenticator);
+    let peer_addr = stream.client_addr().unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+    handshake_with_authenticator(stream, authenticator, peer_addr, compat).await
+}
+
+/// Handles the SOCKS5 handshake, checking credentials with an authenticator
+///
+/// USER_PASS is only selected when there is an authenticator; otherwise
+/// NO_AUTH is. Quirks are tolerated as described for
+/// [`handshake_with_compat`].
+///
+/// # Arguments
+/// * `stream` - The stream connected to the client
+/// * `authenticator` - The check for client credentials, if authentication is required
+/// * `peer_addr` - The client's address, passed on to the authenticator
+/// * `compat` - The quirks to tolerate
+///
+/// # Returns
+/// - Ok(HandshakeInfo) with the offered and negotiated details if handshake is successful
+/// - Err(Socks5Error) if handshake fails
+pub async fn handshake_with_authenticator<S: ClientStream>(
+    stream: &mut S,
+    authenticator: Option<&dyn Authenticator>,
+    peer_addr: SocketAddr,
+    compat: Quirks,
+) -> Socks5Result<HandshakeInfo> {
+    let mut quirks = Quirks::new();
+    let tolerate_unsolicited = compat.contains(Quirk::UnsolicitedCredentials);
+
     // Read the first two bytes: SOCKS version (VER) and number of authentication methods (NMETHODS)
     let mut buf = [0; 2];
     stream.read_exact(&mut buf).await?;
@@ -64,24 +239,55 @@ pub async fn handshake(
         )));
     }
     
-    // Read the authentication methods
-    let mut methods = vec![0; nmethods as usize];
-    stream.read_exact(&mut methods).await?;
+    // Read the authentication methods into a stack buffer
+    let mut buf = [0; 255];
+    let announced = &mut buf[..usize::from(nmethods)];
+    let received = if compat.contains(Quirk::ShortReads) {
+        let received = read_tolerant(stream, announced, SHORT_READ_GRACE).await?;
+        if received < announced.len() {
+            quirks.insert(Quirk::ShortReads);
+        }
+        received
+    } else {
+        stream.read_exact(announced).await?
+    };
+    let methods = Methods::new(&buf[..received]);
     
     // Determine which authentication method to use
-    if username.is_some() && password.is_some() {
-        // If credentials are provided, require username/password authentication
+    if let Some(authenticator) = authenticator {
+        // If an authenticator is configured, require username/password authentication
         if methods.contains(&auth::USER_PASS) {
             // Respond with username/password authentication method
             stream.write_all(&[SOCKS_VERSION, auth::USER_PASS]).await?;
             
             // Perform username/password authentication
-            authenticate_user_pass(stream, username.unwrap(), password.unwrap()).await?;
+            let username = authenticate_user_pass(stream, authenticator, peer_addr).await?;
             
-            Ok(())
+            Ok(HandshakeInfo {
+                offered_methods: methods,
+                method: auth::USER_PASS,
+                username: Some(username),
+                quirks,
+            })
+        } else if tolerate_unsolicited && methods.contains(&auth::NO_AUTH) {
+            // Let the client through only if it follows up with valid credentials
+            stream.write_all(&[SOCKS_VERSION, auth::NO_AUTH]).await?;
+            if !sends_subnegotiation(stream).await? {
+                return Err(Socks5Error::HandshakeError(
+                    "Username/password authentication required but not supported by client".to_string()
+                ));
+            }
+            let username = authenticate_user_pass(stream, authenticator, peer_addr).await?;
+            Ok(HandshakeInfo {
+                offered_methods: methods,
+                method: auth::NO_AUTH,
+                username: Some(username),
+                quirks: quirks.with(Quirk::UnsolicitedCredentials),
+            })
         } else {
             // Client doesn't support username/password authentication
             stream.write_all(&[SOCKS_VERSION, auth::NO_ACCEPTABLE_METHODS]).await?;
+            close_gracefully(stream).await;
             Err(Socks5Error::HandshakeError(
                 "Username/password authentication required but not supported by client".to_string()
             ))
@@ -89,10 +295,23 @@ pub async fn handshake(
     } else if methods.contains(&auth::NO_AUTH) {
         // No credentials provided, use no authentication if client supports it
         stream.write_all(&[SOCKS_VERSION, auth::NO_AUTH]).await?;
-        Ok(())
+        if tolerate_unsolicited && sends_subnegotiation(stream).await? {
+            quirks.insert(Quirk::UnsolicitedCredentials);
+            // Nothing to check them against, so consume and accept them
+            let (username, _) = read_user_pass(stream).await?;
+            stream.write_all(&[0x01, 0x00]).await?;
+            log::debug!("Accepted unsolicited credentials for user {:?} after NO_AUTH", username.as_str());
+        }
+        Ok(HandshakeInfo {
+            offered_methods: methods,
+            method: auth::NO_AUTH,
+            username: None,
+            quirks,
+        })
     } else {
         // No acceptable authentication methods
         stream.write_all(&[SOCKS_VERSION, auth::NO_ACCEPTABLE_METHODS]).await?;
+        close_gracefully(stream).await;
         Err(Socks5Error::HandshakeError(
             "No acceptable authentication methods".to_string()
         ))
@@ -102,24 +321,57 @@ pub async fn handshake(
 /// Performs username/password authentication according to RFC 1929
 ///
 /// # Arguments
-/// * `stream` - The TCP stream connected to the client
-/// * `expected_username` - The username to authenticate against
-/// * `expected_password` - The password to authenticate against
+/// * `stream` - The stream connected to the client
+/// * `authenticator` - The check for the client's credentials
+/// * `peer_addr` - The client's address
 ///
 /// # Returns
-/// - Ok(()) if authentication is successful
+/// - Ok(String) with the authenticated username if authentication is successful
 /// - Err(Socks5Error) if authentication fails
-async fn authenticate_user_pass(
-    stream: &mut TcpStream,
-    expected_username: &str,
-    expected_password: &str
-) -> Socks5Result<()> {
+async fn authenticate_user_pass<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    authenticator: &dyn Authenticator,
+    peer_addr: SocketAddr,
+) -> Socks5Result<String> {
+    let (username, password) = read_user_pass(stream).await?;
+    let username = username.as_str()
+        .map_err(|e| Socks5Error::HandshakeError(format!("Invalid username: {}", e)))?;
+    let password = password.as_str()
+        .map_err(|e| Socks5Error::HandshakeError(format!("Invalid password: {}", e)))?;
+    
+    // Verify credentials
+    if authenticator.authenticate(username, password, peer_addr).await == AuthDecision::Allow {
+        // Authentication successful
+        stream.write_all(&[0x01, 0x00]).await?;
+        Ok(username.to_string())
+    } else {
+        // Authentication failed
+        stream.write_all(&[0x01, 0x01]).await?;
+        close_gracefully(stream).await;
+        Err(Socks5Error::HandshakeError(AUTHENTICATION_FAILED.to_string()))
+    }
+}
+
+/// Returns whether the client's next message is an RFC 1929 sub-negotiation
+/// rather than a SOCKS5 request, without consuming it
+async fn sends_subnegotiation<S: ClientStream>(stream: &mut S) -> Socks5Result<bool> {
+    let mut first = [0; 1];
+    let n = peek(stream, &mut first).await?;
+    Ok(n == 1 && first[0] == 0x01)
+}
+
+/// Reads an RFC 1929 username/password request onto the stack
+///
+/// # Returns
+/// - Ok((username, password)) if the request is well-formed
+/// - Err(Socks5Error) if it is not
+async fn read_user_pass<S: AsyncRead + Unpin>(stream: &mut S) -> Socks5Result<(Field, Field)> {
     // Read the subnegotiation version and username length
     let mut buf = [0; 2];
     stream.read_exact(&mut buf).await?;
     
     let ver = buf[0];
-    let ulen = buf[1] as usize;
+    let ulen = buf[1];
     
     // Check subnegotiation version (should be 1)
     if ver != 0x01 {
@@ -129,32 +381,17 @@ async fn authenticate_user_pass(
     }
     
     // Read username
-    let mut username_bytes = vec![0; ulen];
-    stream.read_exact(&mut username_bytes).await?;
-    let username = String::from_utf8(username_bytes)
-        .map_err(|e| Socks5Error::HandshakeError(format!("Invalid username: {}", e)))?;
+    let username = Field::read(stream, ulen).await?;
     
     // Read password length
     let mut plen_buf = [0; 1];
     stream.read_exact(&mut plen_buf).await?;
-    let plen = plen_buf[0] as usize;
+    let plen = plen_buf[0];
     
     // Read password
-    let mut password_bytes = vec![0; plen];
-    stream.read_exact(&mut password_bytes).await?;
-    let password = String::from_utf8(password_bytes)
-        .map_err(|e| Socks5Error::HandshakeError(format!("Invalid password: {}", e)))?;
+    let password = Field::read(stream, plen).await?;
     
-    // Verify credentials
-    if username == expected_username && password == expected_password {
-        // Authentication successful
-        stream.write_all(&[0x01, 0x00]).await?;
-        Ok(())
-    } else {
-        // Authentication failed
-        stream.write_all(&[0x01, 0x01]).await?;
-        Err(Socks5Error::HandshakeError("Authentication failed".to_string()))
-    }
+    Ok((username, password))
 }
 
 /// Processes the SOCKS5 command request
@@ -162,27 +399,55 @@ async fn authenticate_user_pass(
 /// # Returns
 /// - Ok(TargetAddr) with the target address if command is supported
 /// - Err(Socks5Error) if command is not supported or other error occurs
-pub async fn process_command(stream: &mut TcpStream) -> Socks5Result<TargetAddr> {
+pub async fn process_command<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Socks5Result<TargetAddr> {
+    process_command_with_compat(stream, Quirks::new()).await.map(|(target_addr, _)| target_addr)
+}
+
+/// Processes the SOCKS5 command request, tolerating the enabled client quirks
+///
+/// A non-zero reserved byte is refused with GENERAL_FAILURE unless
+/// [`Quirk::ReservedByte`] is enabled.
+///
+/// # Returns
+/// - Ok((TargetAddr, Quirks)) with the target address and the quirks the client relied on
+/// - Err(Socks5Error) if command is not supported or other error occurs
+pub async fn process_command_with_compat<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    compat: Quirks,
+) -> Socks5Result<(TargetAddr, Quirks)> {
+    let mut quirks = Quirks::new();
+    
     // Read the SOCKS5 request: VER, CMD, RSV, ATYP
     let mut request_header = [0; 4];
     stream.read_exact(&mut request_header).await?;
     
     let ver = request_header[0];
     let command = request_header[1];
-    // let rsv = request_header[2]; // Reserved, should be 0x00
+    let rsv = request_header[2];
     let address_type = request_header[3];
     
     // Verify SOCKS version
     if ver != SOCKS_VERSION {
-        send_reply(stream, reply::GENERAL_FAILURE).await?;
+        send_failure(stream, reply::GENERAL_FAILURE).await?;
         return Err(Socks5Error::CommandError(format!(
             "Unsupported SOCKS version in request: {}", ver
         )));
     }
     
+    // The reserved byte must be zero
+    if rsv != RESERVED {
+        if !compat.contains(Quirk::ReservedByte) {
+            send_failure(stream, reply::GENERAL_FAILURE).await?;
+            return Err(Socks5Error::CommandError(format!(
+                "Non-zero reserved byte in request: {:#04x}", rsv
+            )));
+        }
+        quirks.insert(Quirk::ReservedByte);
+    }
+    
     // Check if command is supported (currently only CONNECT)
     if command != cmd::CONNECT {
-        send_reply(stream, reply::COMMAND_NOT_SUPPORTED).await?;
+        send_failure(stream, reply::COMMAND_NOT_SUPPORTED).await?;
         return Err(Socks5Error::CommandError(format!(
             "Unsupported command: {}", command
         )));
@@ -190,73 +455,49 @@ pub async fn process_command(stream: &mut TcpStream) -> Socks5Result<TargetAddr>
     
     // Parse the target address based on address type
     let target_addr = match address_type {
-        atyp::IPV4 => {
-            // Read 4 bytes for IPv4 address
-            let mut ipv4_bytes = [0; 4];
-            stream.read_exact(&mut ipv4_bytes).await?;
-            let ipv4_addr = Ipv4Addr::new(
-                ipv4_bytes[0], ipv4_bytes[1], ipv4_bytes[2], ipv4_bytes[3]
-            );
-            
-            // Read 2 bytes for port number
-            let mut port_bytes = [0; 2];
-            stream.read_exact(&mut port_bytes).await?;
-            let port = u16::from_be_bytes(port_bytes);
-            
-            TargetAddr::Ipv4(ipv4_addr, port)
-        },
-        atyp::DOMAIN => {
-            // Read domain name length
-            let mut len_buf = [0; 1];
-            stream.read_exact(&mut len_buf).await?;
-            let domain_len = len_buf[0] as usize;
-            
-            // Read domain name
-            let mut domain_bytes = vec![0; domain_len];
-            stream.read_exact(&mut domain_bytes).await?;
-            
-            // Convert bytes to string
-            let domain = String::from_utf8(domain_bytes)
-                .map_err(|e: FromUtf8Error| {
-                    Socks5Error::AddressError(format!("Invalid domain name: {}", e))
-                })?;
-            
-            // Read port number
-            let mut port_bytes = [0; 2];
-            stream.read_exact(&mut port_bytes).await?;
-            let port = u16::from_be_bytes(port_bytes);
-            
-            TargetAddr::Domain(domain, port)
-        },
-        atyp::IPV6 => {
-            // IPv6 not implemented
-            send_reply(stream, reply::ADDRESS_TYPE_NOT_SUPPORTED).await?;
-            return Err(Socks5Error::AddressError(
-                "IPv6 address type not supported".to_string()
-            ));
-        },
+        atyp::IPV4 | atyp::DOMAIN | atyp::IPV6 => read_target_addr(stream, address_type).await?,
         _ => {
             // Unknown address type
-            send_reply(stream, reply::ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            send_failure(stream, reply::ADDRESS_TYPE_NOT_SUPPORTED).await?;
             return Err(Socks5Error::AddressError(format!(
                 "Unknown address type: {}", address_type
             )));
         }
     };
     
-    Ok(target_addr)
+    Ok((target_addr, quirks))
+}
+
+/// Reads the rest of an address whose type byte was already read
+///
+/// The address is decoded on the stack, reading exactly the bytes it takes;
+/// only a domain name is copied to the heap, once it is known to be valid.
+async fn read_target_addr<S: AsyncRead + Unpin>(stream: &mut S, address_type: u8) -> Socks5Result<TargetAddr> {
+    // ATYP, at most a length byte and 255 bytes of name, and the port
+    let mut buf = [0; 259];
+    buf[0] = address_type;
+    let mut len = 1;
+    loop {
+        match TargetAddr::decode(&buf[..len])? {
+            Decoded::Complete(target_addr, _) => return Ok(target_addr),
+            Decoded::Incomplete(more) => {
+                stream.read_exact(&mut buf[len..len + more]).await?;
+                len += more;
+            }
+        }
+    }
 }
 
 /// Sends a SOCKS5 reply to the client
 ///
 /// # Arguments
-/// * `stream` - The TCP stream to write to
+/// * `stream` - The client stream to write to
 /// * `reply_code` - The reply code to send
 ///
 /// # Returns
 /// - Ok(()) if reply is sent successfully
 /// - Err(Socks5Error) if an error occurs
-pub async fn send_reply(stream: &mut TcpStream, reply_code: u8) -> Socks5Result<()> {
+pub async fn send_reply<S: AsyncWrite + Unpin>(stream: &mut S, reply_code: u8) -> Socks5Result<()> {
     // Format: VER, REP, RSV, ATYP, BND.ADDR, BND.PORT
     // Using 0.0.0.0:0 as bind address and port
     let reply = [
@@ -272,14 +513,89 @@ pub async fn send_reply(stream: &mut TcpStream, reply_code: u8) -> Socks5Result<
     Ok(())
 }
 
+/// Sends a failure reply and closes the connection gracefully
+///
+/// # Arguments
+/// * `stream` - The client stream to write to
+/// * `reply_code` - The reply code to send
+///
+/// # Returns
+/// - Ok(()) if the reply is sent successfully
+/// - Err(Socks5Error) if an error occurs
+pub async fn send_failure<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, reply_code: u8) -> Socks5Result<()> {
+    send_denial(stream, reply_code, None).await
+}
+
+/// Sends a failure reply, optionally followed by a denial reason, and
+/// closes the connection gracefully
+///
+/// The reason is a vendor extension for clients that offered
+/// [`auth::DENIAL_REASONS`]: one length byte and up to 255 bytes of UTF-8
+/// text after the standard reply.
+///
+/// # Arguments
+/// * `stream` - The client stream to write to
+/// * `reply_code` - The reply code to send
+/// * `reason` - The reason to append, if the client supports it
+///
+/// # Returns
+/// - Ok(()) if the reply is sent successfully
+/// - Err(Socks5Error) if an error occurs
+pub async fn send_denial<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, reply_code: u8, reason: Option<&str>) -> Socks5Result<()> {
+    let trailer = reason.map(encode_denial_reason).unwrap_or_default();
+    send_failure_with_trailer(stream, reply_code, &trailer).await
+}
+
+/// Sends a failure reply followed by the trailer of negotiated wire
+/// extensions, and closes the connection gracefully
+///
+/// # Arguments
+/// * `stream` - The client stream to write to
+/// * `reply_code` - The reply code to send
+/// * `trailer` - The bytes to send after the reply; empty for standard clients
+///
+/// # Returns
+/// - Ok(()) if the reply is sent successfully
+/// - Err(Socks5Error) if an error occurs
+pub async fn send_failure_with_trailer<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, reply_code: u8, trailer: &[u8]) -> Socks5Result<()> {
+    send_reply(stream, reply_code).await?;
+    if !trailer.is_empty() {
+        stream.write_all(trailer).await?;
+    }
+    close_gracefully(stream).await;
+    Ok(())
+}
+
+/// Makes sure the last reply reaches the client before the stream is dropped
+///
+/// The write side is flushed and shut down, then input the client already
+/// sent is drained until it closes too: dropping a socket with unread input
+/// makes the kernel send a RST, which can discard the reply at the client.
+/// All of this is bounded by [`CLOSE_TIMEOUT`].
+///
+/// # Arguments
+/// * `stream` - The client stream about to be dropped
+pub async fn close_gracefully<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) {
+    let closing = async {
+        stream.flush().await?;
+        stream.shutdown().await?;
+        let mut sink = [0; 512];
+        while stream.read(&mut sink).await? > 0 {}
+        io::Result::Ok(())
+    };
+    if let Ok(Err(e)) = tokio::time::timeout(CLOSE_TIMEOUT, closing).await {
+        log::debug!("Error closing client connection: {}", e);
+    }
+}
+
 /// Sends a success reply to the client
 ///
 /// # Arguments
-/// * `stream` - The TCP stream to write to
+/// * `stream` - The client stream to write to
 ///
 /// # Returns
 /// - Ok(()) if reply is sent successfully
 /// - Err(Socks5Error) if an error occurs
-pub async fn send_success_reply(stream: &mut TcpStream) -> Socks5Result<()> {
+pub async fn send_success_reply<S: AsyncWrite + Unpin>(stream: &mut S) -> Socks5Result<()> {
     send_reply(stream, reply::SUCCEEDED).await
 }
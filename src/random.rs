@@ -0,0 +1,91 @@
+//! Random number source abstraction for the SOCKS5 proxy.
+//!
+//! Anything that needs randomness (connection IDs, jittered backoff,
+//! load-balancing choices) draws it from a [`RandomSource`] so tests can
+//! substitute a seeded generator and get reproducible behavior.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A thread-safe source of random numbers
+pub trait RandomSource: Send + Sync + fmt::Debug {
+    /// Returns the next random 64-bit value
+    fn next_u64(&self) -> u64;
+
+    /// Returns a random value in the range `0..upper`
+    ///
+    /// Returns 0 when `upper` is 0.
+    fn below(&self, upper: u64) -> u64 {
+        if upper == 0 {
+            0
+        } else {
+            self.next_u64() % upper
+        }
+    }
+}
+
+/// A SplitMix64 generator with a fixed seed
+///
+/// Produces the same sequence for the same seed, which makes it suitable
+/// for reproducible tests. It is not cryptographically secure.
+#[derive(Debug)]
+pub struct SeededRandom {
+    /// The current generator state
+    state: AtomicU64,
+}
+
+impl SeededRandom {
+    /// Creates a new generator from the given seed
+    ///
+    /// # Arguments
+    /// * `seed` - The initial generator state
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u64(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// The default random source, seeded from the process' random hash keys
+#[derive(Debug)]
+pub struct StdRandom {
+    /// The underlying generator
+    inner: SeededRandom,
+}
+
+impl StdRandom {
+    /// Creates a new randomly seeded generator
+    pub fn new() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Self {
+            inner: SeededRandom::new(hasher.finish()),
+        }
+    }
+}
+
+impl Default for StdRandom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RandomSource for StdRandom {
+    fn next_u64(&self) -> u64 {
+        self.inner.next_u64()
+    }
+}
@@ -0,0 +1,108 @@
+//! Per-source handshake rate limiting for the SOCKS5 proxy.
+//!
+//! Scanners hammer open proxies with connection after connection from one
+//! address. [`SourceRateLimiter`] keeps a token bucket per client IP and
+//! lets each address start only so many handshakes per interval, after an
+//! initial burst; connections beyond that are dropped as they are accepted.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::lru::LruMap;
+
+/// The least recently seen sources are forgotten beyond this many
+const MAX_SOURCES: usize = 65536;
+
+/// How many handshakes one client IP may start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeRateLimit {
+    /// Handshakes allowed per interval, sustained
+    pub count: u32,
+    /// The interval `count` refers to
+    pub interval: Duration,
+    /// Handshakes allowed at once from an address that was quiet
+    pub burst: u32,
+}
+
+impl HandshakeRateLimit {
+    /// Creates a limit of `count` handshakes per `interval` with a `burst` allowance
+    ///
+    /// The count is at least one, the interval at least a millisecond and
+    /// the burst at least the count.
+    pub fn new(count: u32, interval: Duration, burst: u32) -> Self {
+        let count = count.max(1);
+        Self {
+            count,
+            interval: interval.max(Duration::from_millis(1)),
+            burst: burst.max(count),
+        }
+    }
+
+    /// Returns the sustained rate in handshakes per second
+    fn per_second(&self) -> f64 {
+        f64::from(self.count) / self.interval.as_secs_f64()
+    }
+}
+
+impl fmt::Display for HandshakeRateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} per {:?} (burst {})", self.count, self.interval, self.burst)
+    }
+}
+
+/// Token buckets of handshakes per client IP
+#[derive(Debug)]
+pub struct SourceRateLimiter {
+    /// The limit every address is held to
+    limit: HandshakeRateLimit,
+    /// The time source for refills
+    clock: Arc<dyn Clock>,
+    /// Available handshakes per address and when they were computed
+    sources: Mutex<LruMap<IpAddr, (f64, Instant)>>,
+}
+
+impl SourceRateLimiter {
+    /// Creates a limiter with full buckets for every address
+    pub fn new(limit: HandshakeRateLimit, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limit,
+            clock,
+            sources: Mutex::new(LruMap::new(MAX_SOURCES)),
+        }
+    }
+
+    /// Returns the limit every address is held to
+    pub fn limit(&self) -> HandshakeRateLimit {
+        self.limit
+    }
+
+    /// Returns the number of addresses currently tracked
+    pub fn len(&self) -> usize {
+        self.sources.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns whether no address is tracked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decides whether `ip` may start another handshake, using one up if so
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let now = self.clock.now();
+        let burst = f64::from(self.limit.burst);
+        let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+        let (available, updated) = sources.get_or_insert_with(ip, || (burst, now));
+        let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+        *available = (*available + elapsed * self.limit.per_second()).min(burst);
+        *updated = now;
+        if *available < 1.0 {
+            return false;
+        }
+        *available -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,147 @@
+//! Recent session records for the SOCKS5 proxy.
+//!
+//! Permanent audit logging is often off, and after an incident operators
+//! still want to know what the proxy did in the last few minutes. With
+//! [`RecentSessions`], the server keeps a record of each of the last N
+//! sessions in a fixed-size ring buffer in memory: who connected, as which
+//! user, to which target, for how long, how many bytes went each way and
+//! why the session ended. The oldest record is dropped for each new one, so
+//! memory use stays bounded however busy the proxy is.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::protocol::TargetAddr;
+use crate::routing::TargetPattern;
+
+/// What happened in one finished session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    /// The connection ID used in log lines
+    pub conn_id: u32,
+    /// The client's address
+    pub client: SocketAddr,
+    /// The address of the listener that accepted the client
+    pub listener: String,
+    /// The authenticated username, if any
+    pub username: Option<String>,
+    /// The requested target, if the session got as far as asking for one
+    pub target: Option<TargetAddr>,
+    /// Wall-clock time the session was accepted, in milliseconds since the Unix epoch
+    pub started_ms: u64,
+    /// How long the session lasted
+    pub duration: Duration,
+    /// Why the session ended, as counted in the metrics, e.g. `completed`
+    pub reason: &'static str,
+    /// Bytes relayed from the client to the target
+    pub bytes_from_client: u64,
+    /// Bytes relayed from the target to the client
+    pub bytes_from_target: u64,
+}
+
+/// Which records a query returns
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecentQuery {
+    /// Only the newest this many matching records, if limited
+    pub last: Option<usize>,
+    /// Only sessions whose target matches, if given
+    pub target: Option<TargetPattern>,
+    /// Only sessions accepted at or after this wall-clock time, in
+    /// milliseconds since the Unix epoch
+    pub since_ms: Option<u64>,
+}
+
+impl RecentQuery {
+    /// Creates a query returning every record
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns only the newest `last` matching records
+    pub fn with_last(mut self, last: usize) -> Self {
+        self.last = Some(last);
+        self
+    }
+
+    /// Returns only sessions whose target matches `pattern`
+    pub fn with_target(mut self, pattern: TargetPattern) -> Self {
+        self.target = Some(pattern);
+        self
+    }
+
+    /// Returns only sessions accepted at or after `since_ms`
+    pub fn with_since(mut self, since_ms: u64) -> Self {
+        self.since_ms = Some(since_ms);
+        self
+    }
+
+    /// Returns whether a record passes the target and time filters
+    fn matches(&self, record: &SessionRecord) -> bool {
+        let target_matches = match (&self.target, &record.target) {
+            (Some(pattern), Some(target)) => pattern.matches(target),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        target_matches && self.since_ms.is_none_or(|since| record.started_ms >= since)
+    }
+}
+
+/// A ring buffer of the last sessions' records
+#[derive(Debug)]
+pub struct RecentSessions {
+    /// How many records are kept
+    capacity: usize,
+    /// The records, oldest first
+    records: Mutex<VecDeque<SessionRecord>>,
+}
+
+impl RecentSessions {
+    /// Creates a buffer keeping the last `capacity` records
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Returns how many records are kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of records currently kept
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns whether no record is kept
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the record of a finished session, dropping the oldest if full
+    pub fn push(&self, record: SessionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Returns the records matching `query`, newest first
+    pub fn query(&self, query: &RecentQuery) -> Vec<SessionRecord> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .take(query.last.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
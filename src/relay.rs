@@ -3,19 +3,390 @@
 //! This module handles bidirectional data transfer between client and target server
 //! connections, implementing the core proxy functionality.
 
+use std::fmt;
 use std::net::SocketAddr;
-use tokio::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
-use log;
+use tokio::time::Instant;
 
 use crate::error::{Socks5Error, Socks5Result};
 
+/// Default size of the per-direction copy buffer in bytes
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How soon after the relay starts a target that hangs up without sending
+/// anything counts as having closed early
+pub const EARLY_CLOSE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Limits the rate at which relayed bytes are forwarded
+pub trait Throttle: Send + Sync + fmt::Debug {
+    /// Accounts for `bytes` about to be forwarded
+    ///
+    /// # Returns
+    /// * How long to wait before forwarding them (zero to send right away)
+    fn delay_for(&self, bytes: usize) -> Duration;
+}
+
+/// Counts relayed bytes against an allowance as they are read
+pub trait Meter: Send + Sync + fmt::Debug {
+    /// Accounts for `bytes` read from either side
+    ///
+    /// # Returns
+    /// * Whether the allowance leaves room to go on; the relay ends with an
+    ///   error otherwise
+    fn charge(&self, bytes: u64) -> bool;
+}
+
+/// How data is copied between client and target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelayEngine {
+    /// Tokio's `copy_bidirectional`: simple and well-tested, but ignores
+    /// throttles
+    #[default]
+    Lean,
+    /// The custom copy loop with throttles and an idle timeout
+    Full,
+}
+
+/// Engine, buffer size, throttles and idle timeout for the relay
+#[derive(Debug, Clone)]
+pub struct RelayOptions {
+    /// Which copy implementation is used
+    engine: RelayEngine,
+    /// Size of the copy buffer used in each direction
+    buffer_size: usize,
+    /// Throttle for bytes sent from the client to the target
+    upload: Option<Arc<dyn Throttle>>,
+    /// Throttle for bytes sent from the target to the client
+    download: Option<Arc<dyn Throttle>>,
+    /// Ends the relay after this long without traffic in either direction
+    idle_timeout: Option<Duration>,
+    /// Hash the bytes entering and leaving each direction and log them at close
+    checksums: bool,
+    /// Charged with the bytes read from either side, if any
+    meter: Option<Arc<dyn Meter>>,
+}
+
+impl RelayOptions {
+    /// Creates options for the lean engine with the default buffer size
+    pub fn new() -> Self {
+        Self {
+            engine: RelayEngine::Lean,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            upload: None,
+            download: None,
+            idle_timeout: None,
+            checksums: false,
+            meter: None,
+        }
+    }
+
+    /// Selects the copy implementation
+    pub fn with_engine(mut self, engine: RelayEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Sets the size of the copy buffer used in each direction
+    ///
+    /// # Arguments
+    /// * `buffer_size` - The buffer size in bytes; zero is treated as one
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Throttles bytes sent from the client to the target (full engine only)
+    pub fn with_upload_throttle(mut self, throttle: Arc<dyn Throttle>) -> Self {
+        self.upload = Some(throttle);
+        self
+    }
+
+    /// Throttles bytes sent from the target to the client (full engine only)
+    pub fn with_download_throttle(mut self, throttle: Arc<dyn Throttle>) -> Self {
+        self.download = Some(throttle);
+        self
+    }
+
+    /// Ends the relay after `timeout` without traffic in either direction
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Hashes the bytes read and written in each direction and logs both
+    /// hashes when the direction closes (full engine only)
+    ///
+    /// Comparing them with hashes taken at the client and the target shows
+    /// whether data was altered inside the proxy or elsewhere.
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Charges `meter` with the bytes read from either side, ending the
+    /// relay once it runs out
+    pub fn with_meter(mut self, meter: Arc<dyn Meter>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Returns the selected copy implementation
+    pub fn engine(&self) -> RelayEngine {
+        self.engine
+    }
+
+    /// Returns the size of the copy buffer
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Returns the idle timeout, if any
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Returns whether relayed bytes are hashed for debugging
+    pub fn checksums(&self) -> bool {
+        self.checksums
+    }
+}
+
+impl Default for RelayOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Incremental 64-bit FNV-1a hash of a byte stream
+///
+/// The hash of a stream does not depend on how it was split into chunks,
+/// so it can be compared with one computed by an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHash(u64);
+
+impl StreamHash {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    /// Creates the hash of an empty stream
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    /// Extends the hash with the next chunk of the stream
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Returns the hash of the bytes seen so far
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for StreamHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for StreamHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Bytes forwarded in each direction by a relay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayStats {
+    /// Bytes read from the client and written to the target
+    pub client_to_target: u64,
+    /// Bytes read from the target and written to the client
+    pub target_to_client: u64,
+    /// Whether the target closed or reset the connection within
+    /// [`EARLY_CLOSE_WINDOW`] without sending anything, while the client was
+    /// still sending; often a sign of a half-broken backend
+    pub target_closed_early: bool,
+}
+
+/// Copies data in both directions until both sides reach EOF
+///
+/// When one side finishes sending, the other side's writer is shut down so
+/// the half-close is passed on while the opposite direction keeps flowing.
+/// The first error in either direction ends the whole relay, as does the
+/// idle timeout when one is set.
+///
+/// # Arguments
+/// * `client_reader` / `client_writer` - The client side of the relay
+/// * `target_reader` / `target_writer` - The target side of the relay
+/// * `options` - Buffer size and throttles
+///
+/// # Returns
+/// * `Ok(RelayStats)` - The bytes forwarded in each direction
+/// * `Err(io::Error)` - The first read or write error, naming its direction
+pub async fn copy_bidirectional_with_stats<R1, W1, R2, W2>(
+    client_reader: &mut R1,
+    client_writer: &mut W1,
+    target_reader: &mut R2,
+    target_writer: &mut W2,
+    options: &RelayOptions,
+) -> io::Result<RelayStats>
+where
+    R1: AsyncRead + Unpin + ?Sized,
+    W1: AsyncWrite + Unpin + ?Sized,
+    R2: AsyncRead + Unpin + ?Sized,
+    W2: AsyncWrite + Unpin + ?Sized,
+{
+    let activity = Activity::new();
+    let client_to_target = copy_one_way(
+        client_reader,
+        target_writer,
+        options.buffer_size,
+        options.upload.as_deref(),
+        options.checksums,
+        &activity,
+        "client to target",
+    );
+    let target_to_client = copy_one_way(
+        target_reader,
+        client_writer,
+        options.buffer_size,
+        options.download.as_deref(),
+        options.checksums,
+        &activity,
+        "target to client",
+    );
+    let copy = async { tokio::try_join!(client_to_target, target_to_client) };
+    
+    let (client_to_target, target_to_client) = match options.idle_timeout {
+        Some(idle_timeout) => tokio::select! {
+            result = copy => result?,
+            error = activity.expire(idle_timeout) => return Err(error),
+        },
+        None => copy.await?,
+    };
+    Ok(RelayStats {
+        client_to_target,
+        target_to_client,
+        target_closed_early: false,
+    })
+}
+
+/// Tracks when data last moved in either direction
+struct Activity {
+    /// When the relay started
+    start: Instant,
+    /// Milliseconds after `start` of the last read
+    last: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Records that data was read
+    fn touch(&self) {
+        self.last.store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Completes with a timeout error once nothing moved for `idle_timeout`
+    async fn expire(&self, idle_timeout: Duration) -> io::Error {
+        loop {
+            let deadline = self.start + Duration::from_millis(self.last.load(Ordering::Relaxed)) + idle_timeout;
+            if Instant::now() >= deadline {
+                return io::Error::new(io::ErrorKind::TimedOut, format!("Relay idle for {:?}", idle_timeout));
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+/// Copies one direction until EOF, then shuts down the writer
+async fn copy_one_way<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_size: usize,
+    throttle: Option<&dyn Throttle>,
+    checksums: bool,
+    activity: &Activity,
+    direction: &str,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let with_direction = |e: io::Error| io::Error::new(e.kind(), format!("Error copying data from {}: {}", direction, e));
+    let mut buf = vec![0; buffer_size];
+    let mut total = 0;
+    let mut hashes = checksums.then(|| (StreamHash::new(), StreamHash::new()));
+    
+    let result = async {
+        loop {
+            let n = reader.read(&mut buf).await.map_err(with_direction)?;
+            if n == 0 {
+                break;
+            }
+            activity.touch();
+            if let Some((entering, _)) = hashes.as_mut() {
+                entering.update(&buf[..n]);
+            }
+            if let Some(throttle) = throttle {
+                let delay = throttle.delay_for(n);
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            writer.write_all(&buf[..n]).await.map_err(with_direction)?;
+            if let Some((_, exiting)) = hashes.as_mut() {
+                exiting.update(&buf[..n]);
+            }
+            total += n as u64;
+        }
+        writer.shutdown().await.map_err(with_direction)
+    }
+    .await;
+
+    if let Some((entering, exiting)) = hashes {
+        log_checksums(direction, total, &entering, &exiting);
+    }
+    result?;
+    log::info!("{}: {} bytes transferred", direction, total);
+    Ok(total)
+}
+
+/// Logs the hashes of the bytes read and written in one direction
+fn log_checksums(direction: &str, total: u64, entering: &StreamHash, exiting: &StreamHash) {
+    if entering == exiting {
+        log::info!("{}: checksum {} over {} bytes", direction, entering, total);
+    } else {
+        // Bytes read but not yet written when the relay ended also land here
+        log::warn!(
+            "{}: checksum mismatch, read {} but wrote {} ({} bytes written)",
+            direction, entering, exiting, total
+        );
+    }
+}
+
 /// Represents a data relay between client and target server
 pub struct Relay {
     /// Client peer address for logging
     client_addr: SocketAddr,
     /// Target server address string for logging
     target_addr: String,
+    /// Buffer size and throttles for the copy loop
+    options: RelayOptions,
 }
 
 impl Relay {
@@ -31,6 +402,7 @@ impl Relay {
         Self {
             client_addr,
             target_addr,
+            options: RelayOptions::default(),
         }
     }
     
@@ -44,69 +416,238 @@ impl Relay {
         &self.target_addr
     }
 
+    /// Sets the buffer size and throttles used by the relay
+    ///
+    /// # Arguments
+    /// * `options` - The copy options
+    ///
+    /// # Returns
+    /// * The updated Relay instance
+    pub fn with_options(mut self, options: RelayOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the copy options
+    pub fn options(&self) -> &RelayOptions {
+        &self.options
+    }
+
     /// Starts bidirectional data relay between client and target
     ///
     /// This function splits both streams into read and write halves,
     /// then copies data in both directions concurrently.
     ///
     /// # Arguments
-    /// * `client_stream` - The TCP stream connected to the client
+    /// * `client_stream` - The stream connected to the client
     /// * `target_stream` - The TCP stream connected to the target server
     ///
     /// # Returns
-    /// * `Ok(())` - If relay completes successfully
+    /// * `Ok(RelayStats)` - How many bytes were relayed in each direction
     /// * `Err(Socks5Error)` - If an error occurs during relay
-    pub async fn start_relay(
+    pub async fn start_relay<C: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        client_stream: C,
+        target_stream: TcpStream,
+    ) -> Socks5Result<RelayStats> {
+        match self.run(client_stream, target_stream).await {
+            (stats, None) => Ok(stats),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Relays data like [`Relay::start_relay`], keeping the byte counts of
+    /// relays that end with an error
+    ///
+    /// After an error, e.g. an idle timeout or a reset, the counts are the
+    /// bytes read from each side, some of which may not have been written
+    /// to the other.
+    ///
+    /// # Arguments
+    /// * `client_stream` - The stream connected to the client
+    /// * `target_stream` - The TCP stream connected to the target server
+    ///
+    /// # Returns
+    /// * The bytes relayed in each direction, and the error that ended the
+    ///   relay, if any
+    pub async fn run<C: AsyncRead + AsyncWrite + Unpin>(
         &self,
-        client_stream: TcpStream,
+        client_stream: C,
         target_stream: TcpStream,
-    ) -> Socks5Result<()> {
+    ) -> (RelayStats, Option<Socks5Error>) {
         log::info!("Starting data relay for client: {:?} to target: {}", 
                  self.client_addr, self.target_addr);
         
-        // Split the client and target streams into read and write halves.
-        // This allows concurrent reading from one and writing to the other.
-        let (mut client_reader, mut client_writer) = client_stream.into_split();
-        let (mut target_reader, mut target_writer) = target_stream.into_split();
-        
-        // Copy data from client to target
-        let client_to_target = async {
-            match io::copy(&mut client_reader, &mut target_writer).await {
-                Ok(n) => {
-                    log::info!("Client to target: {} bytes transferred", n);
-                    Ok(n)
-                }
-                Err(e) => Err(Socks5Error::RelayError(format!(
-                    "Error copying data from client to target: {}", e
-                ))),
+        let started = Instant::now();
+        let (result, client, target) = match self.options.engine {
+            RelayEngine::Lean => {
+                let activity = Arc::new(Activity::new());
+                let mut client_stream = Watched::new(client_stream)
+                    .with_activity(Arc::clone(&activity))
+                    .with_meter(self.options.meter.clone());
+                let mut target_stream = Watched::new(target_stream)
+                    .with_activity(Arc::clone(&activity))
+                    .with_meter(self.options.meter.clone());
+                let copy = io::copy_bidirectional(&mut client_stream, &mut target_stream);
+                let result = match self.options.idle_timeout {
+                    Some(idle_timeout) => tokio::select! {
+                        result = copy => result,
+                        error = activity.expire(idle_timeout) => Err(error),
+                    },
+                    None => copy.await,
+                };
+                let result = result.map(|(client_to_target, target_to_client)| RelayStats {
+                    client_to_target,
+                    target_to_client,
+                    target_closed_early: false,
+                });
+                (result, client_stream.watch, target_stream.watch)
             }
-        };
-        
-        // Copy data from target to client
-        let target_to_client = async {
-            match io::copy(&mut target_reader, &mut client_writer).await {
-                Ok(n) => {
-                    log::info!("Target to client: {} bytes transferred", n);
-                    Ok(n)
-                }
-                Err(e) => Err(Socks5Error::RelayError(format!(
-                    "Error copying data from target to client: {}", e
-                ))),
+            RelayEngine::Full => {
+                // Split the client and target streams into read and write halves.
+                // This allows concurrent reading from one and writing to the other.
+                let client_stream = Watched::new(client_stream).with_meter(self.options.meter.clone());
+                let (mut client_reader, mut client_writer) = io::split(client_stream);
+                let (target_reader, mut target_writer) = target_stream.into_split();
+                let mut target_reader = Watched::new(target_reader).with_meter(self.options.meter.clone());
+                let result = copy_bidirectional_with_stats(
+                    &mut client_reader,
+                    &mut client_writer,
+                    &mut target_reader,
+                    &mut target_writer,
+                    &self.options,
+                ).await;
+                (result, client_reader.unsplit(client_writer).watch, target_reader.watch)
             }
         };
         
-        // Run both copy operations concurrently
-        match tokio::try_join!(client_to_target, target_to_client) {
-            Ok((from_client, from_target)) => {
+        if target.closed_early(started, &client) {
+            log::warn!("Target {} closed the connection right after accepting it, without sending anything",
+                     self.target_addr);
+            let stats = RelayStats {
+                client_to_target: client.received,
+                target_to_client: 0,
+                target_closed_early: true,
+            };
+            return (stats, None);
+        }
+        match result {
+            Ok(stats) => {
                 log::info!("Data transfer complete: {} bytes from client, {} bytes from target", 
-                         from_client, from_target);
-                Ok(())
+                         stats.client_to_target, stats.target_to_client);
+                (stats, None)
             }
             Err(e) => {
                 log::error!("Error during data transfer: {}", e);
-                Err(e)
+                let stats = RelayStats {
+                    client_to_target: client.received,
+                    target_to_client: target.received,
+                    target_closed_early: false,
+                };
+                (stats, Some(Socks5Error::RelayError(e.to_string())))
+            }
+        }
+    }
+}
+
+/// What one side of a relay sent and when it stopped sending
+#[derive(Debug, Clone, Copy, Default)]
+struct Watch {
+    /// Bytes read from the side
+    received: u64,
+    /// When the side closed or reset the connection
+    ended: Option<Instant>,
+}
+
+impl Watch {
+    /// Returns whether this side, the target, hung up within
+    /// [`EARLY_CLOSE_WINDOW`] of `started` without sending anything, while
+    /// the `client` side was still sending
+    fn closed_early(&self, started: Instant, client: &Watch) -> bool {
+        let Some(ended) = self.ended else {
+            return false;
+        };
+        self.received == 0
+            && ended.saturating_duration_since(started) < EARLY_CLOSE_WINDOW
+            && client.ended.is_none_or(|client_ended| client_ended > ended)
+    }
+}
+
+/// A stream that notes what is read from it into a [`Watch`]
+struct Watched<S> {
+    /// The wrapped stream
+    inner: S,
+    /// What was read so far
+    watch: Watch,
+    /// Touched on every read, for the idle timeout of the lean engine
+    activity: Option<Arc<Activity>>,
+    /// Charged with every read
+    meter: Option<Arc<dyn Meter>>,
+}
+
+impl<S> Watched<S> {
+    fn new(inner: S) -> Self {
+        Self {
+            inner,
+            watch: Watch::default(),
+            activity: None,
+            meter: None,
+        }
+    }
+
+    /// Records every read in `activity` as well
+    fn with_activity(mut self, activity: Arc<Activity>) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// Charges every read to `meter`, if any
+    fn with_meter(mut self, meter: Option<Arc<dyn Meter>>) -> Self {
+        self.meter = meter;
+        self
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Watched<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = ready!(Pin::new(&mut self.inner).poll_read(cx, buf));
+        let read = buf.filled().len() - before;
+        match &result {
+            Ok(()) if read > 0 => {
+                self.watch.received += read as u64;
+                if let Some(activity) = &self.activity {
+                    activity.touch();
+                }
+                if self.meter.as_ref().is_some_and(|meter| !meter.charge(read as u64)) {
+                    return Poll::Ready(Err(io::Error::other("Relay allowance exhausted")));
+                }
+            }
+            // An empty read into a full buffer is not an EOF
+            Ok(()) if buf.remaining() == 0 => {}
+            Ok(()) => {
+                self.watch.ended.get_or_insert_with(Instant::now);
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                self.watch.ended.get_or_insert_with(Instant::now);
             }
+            Err(_) => {}
         }
+        Poll::Ready(result)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Watched<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
     }
 }
 
@@ -115,7 +656,7 @@ impl Relay {
 /// This is a convenience function that creates a Relay instance and starts the relay.
 ///
 /// # Arguments
-/// * `client_stream` - The TCP stream connected to the client
+/// * `client_stream` - The stream connected to the client
 /// * `client_addr` - The client's socket address
 /// * `target_stream` - The TCP stream connected to the target server
 /// * `target_addr` - The target server's address as a string
@@ -123,12 +664,13 @@ impl Relay {
 /// # Returns
 /// * `Ok(())` - If relay completes successfully
 /// * `Err(Socks5Error)` - If an error occurs during relay
-pub async fn relay_data(
-    client_stream: TcpStream,
+pub async fn relay_data<C: AsyncRead + AsyncWrite + Unpin>(
+    client_stream: C,
     client_addr: SocketAddr,
     target_stream: TcpStream,
     target_addr: String,
 ) -> Socks5Result<()> {
     let relay = Relay::new(client_addr, target_addr);
-    relay.start_relay(client_stream, target_stream).await
+    relay.start_relay(client_stream, target_stream).await?;
+    Ok(())
 }
@@ -0,0 +1,378 @@
+//! Configuration pulled from a remote URL for the SOCKS5 proxy.
+//!
+//! A fleet of proxies is easier to run from one place than by pushing files
+//! to every machine. [`RemoteConfig`] polls a configuration document from an
+//! `https://` URL on an interval, sending the last `ETag` so an unchanged
+//! document costs a `304 Not Modified`. When a public key is configured the
+//! document must come with a detached Ed25519 signature, fetched from the
+//! same URL with `.sig` appended and written as hex; a document that does
+//! not verify is never applied. Plain `http://` URLs are only accepted with
+//! a public key, as anyone on the path could rewrite the document otherwise.
+//!
+//! Signed documents must carry a `serial`, and one whose serial is lower
+//! than the document applied last is refused, so an old signed document
+//! cannot be replayed to roll the servers back.
+//!
+//! The document has the format of the configuration file. Its ACL and
+//! allow-list replace the servers' policies, and its accounts replace their
+//! authentication, while they serve; listeners and all other settings stay
+//! as they were started.
+
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::acl::{AclRules, TargetAllowList};
+use crate::config::ServerConfig;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::fetch::{parse_response, HttpUrl, Response};
+use crate::protocol::TargetAddr;
+use crate::users::UserTable;
+
+/// How often the document is polled by default
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long one fetch may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest response accepted, head included
+const MAX_RESPONSE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A URL the document or its signature is fetched from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigUrl {
+    /// The host, port and path to request
+    pub http: HttpUrl,
+    /// Whether the connection is made over TLS (`https://`)
+    pub tls: bool,
+}
+
+impl ConfigUrl {
+    /// Parses an absolute `https://` or `http://` URL
+    ///
+    /// `https://` URLs default to port 443.
+    pub fn parse(url: &str) -> Socks5Result<Self> {
+        let Some(rest) = url.strip_prefix("https://") else {
+            return Ok(Self { http: HttpUrl::parse(url)?, tls: false });
+        };
+        let mut http = HttpUrl::parse(&format!("http://{}", rest))?;
+        let explicit_port = http
+            .authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        if !explicit_port {
+            http.target = match http.target {
+                TargetAddr::Ipv4(ip, _) => TargetAddr::Ipv4(ip, 443),
+                TargetAddr::Ipv6(ip, _) => TargetAddr::Ipv6(ip, 443),
+                TargetAddr::Domain(host, _) => TargetAddr::Domain(host, 443),
+            };
+        }
+        Ok(Self { http, tls: true })
+    }
+
+    /// Returns the URL of the document's detached signature
+    fn signature(&self) -> Self {
+        let mut url = self.clone();
+        url.http.path = match url.http.path.split_once('?') {
+            Some((path, query)) => format!("{}.sig?{}", path, query),
+            None => format!("{}.sig", url.http.path),
+        };
+        url
+    }
+}
+
+/// The settings a remote document replaces while the servers run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteSettings {
+    /// The ACL replacing each server's
+    pub acl: AclRules,
+    /// The allow-list replacing each server's, if the document has one
+    pub allowed_targets: Option<TargetAllowList>,
+    /// The accounts replacing each server's, if the document has any
+    pub users: Arc<UserTable>,
+    /// The revision of the document, if it has one
+    pub serial: Option<u64>,
+}
+
+impl RemoteSettings {
+    /// Takes the replaceable settings from a configuration
+    ///
+    /// A `users_file` names a file on the machine that published the
+    /// document, so it is refused rather than read locally.
+    pub fn from_config(config: &ServerConfig) -> Socks5Result<Self> {
+        if config.users_file.is_some() {
+            return Err(Socks5Error::ConfigError("A remote configuration cannot name a users_file".to_string()));
+        }
+        if config.username.is_some() != config.password.is_some() {
+            return Err(Socks5Error::ConfigError(
+                "Both username and password must be provided if either is provided".to_string(),
+            ));
+        }
+        let mut users = UserTable::new();
+        for (username, password) in &config.users {
+            users.insert(username, password);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            users.insert(username, password);
+        }
+        Ok(Self {
+            acl: config.acl.rules()?,
+            allowed_targets: config.acl.allow_list()?,
+            users: Arc::new(users),
+            serial: config.serial,
+        })
+    }
+}
+
+/// A configuration document polled from a URL
+#[derive(Debug)]
+pub struct RemoteConfig {
+    /// Where the document is fetched from
+    url: ConfigUrl,
+    /// How often the document is polled
+    interval: Duration,
+    /// The Ed25519 key the document must be signed with, if any
+    public_key: Option<[u8; 32]>,
+    /// Trust anchors for `https://` URLs
+    tls: Arc<ClientConfig>,
+    /// The settings of the last document applied
+    current: RwLock<Option<Arc<RemoteSettings>>>,
+    /// The entity tag of the last document applied
+    etag: Mutex<Option<String>>,
+}
+
+impl RemoteConfig {
+    /// Creates a source polling `url` every minute, trusting the web PKI roots
+    pub fn new(url: ConfigUrl) -> Self {
+        Self {
+            url,
+            interval: DEFAULT_INTERVAL,
+            public_key: None,
+            tls: web_pki_client_config(),
+            current: RwLock::new(None),
+            etag: Mutex::new(None),
+        }
+    }
+
+    /// Sets how often the document is polled; at least once a second
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Applies only documents signed with the Ed25519 key `public_key`
+    pub fn with_public_key(mut self, public_key: [u8; 32]) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Returns where the document is fetched from
+    pub fn url(&self) -> &ConfigUrl {
+        &self.url
+    }
+
+    /// Returns how often the document is polled
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the settings of the last document applied, if any
+    pub fn current(&self) -> Option<Arc<RemoteSettings>> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Checks that documents cannot be tampered with on the way
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the URL is `https://` or documents must be signed
+    /// * `Err(Socks5Error)` - If an unsigned document would be fetched over
+    ///   plain `http://`
+    pub fn validate(&self) -> Socks5Result<()> {
+        if self.url.tls || self.public_key.is_some() {
+            return Ok(());
+        }
+        Err(Socks5Error::ConfigError(
+            "A remote configuration fetched over http:// must be signed; use https:// or set a public key".to_string(),
+        ))
+    }
+
+    /// Fetches the document and applies it if it changed
+    ///
+    /// # Returns
+    /// * `Ok(true)` - If a new document was applied
+    /// * `Ok(false)` - If the document did not change
+    /// * `Err(Socks5Error)` - If it could not be fetched, verified or parsed,
+    ///   or its serial is older than the document applied last; the settings
+    ///   applied before stay in force
+    pub async fn poll(&self) -> Socks5Result<bool> {
+        self.validate()?;
+        let etag = self.etag.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let response = self.get(&self.url, etag.as_deref()).await?;
+        match response.status {
+            200 => {}
+            304 => return Ok(false),
+            _ => {
+                return Err(Socks5Error::ConfigError(format!(
+                    "Remote configuration answered {}",
+                    response.status_line
+                )))
+            }
+        }
+        if let Some(public_key) = &self.public_key {
+            self.verify(public_key, &response.body).await?;
+        }
+        let document = std::str::from_utf8(&response.body)
+            .map_err(|_| Socks5Error::ConfigError("Remote configuration is not UTF-8".to_string()))?;
+        let settings = RemoteSettings::from_config(&ServerConfig::from_toml(document)?)?;
+        if self.public_key.is_some() && settings.serial.is_none() {
+            return Err(Socks5Error::ConfigError("Signed remote configuration has no serial".to_string()));
+        }
+
+        let etag = header(&response, "etag").map(str::to_string);
+        let last = self.current().and_then(|current| current.serial);
+        if let (Some(serial), Some(last)) = (settings.serial, last) {
+            if serial < last {
+                return Err(Socks5Error::ConfigError(format!(
+                    "Remote configuration serial {} is older than the applied {}",
+                    serial, last
+                )));
+            }
+            if serial == last {
+                // The same revision served under a new ETag
+                *self.etag.lock().unwrap_or_else(|e| e.into_inner()) = etag;
+                return Ok(false);
+            }
+        }
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(settings));
+        *self.etag.lock().unwrap_or_else(|e| e.into_inner()) = etag;
+        Ok(true)
+    }
+
+    /// Spawns a task polling the document every interval, logging failures
+    pub fn spawn_polling(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let remote = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(remote.interval).await;
+                match remote.poll().await {
+                    Ok(true) => log::info!("Applied new remote configuration from {}", remote.url.http.authority),
+                    Ok(false) => log::debug!("Remote configuration unchanged"),
+                    Err(e) => log::warn!("Keeping the current configuration: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Checks the detached signature of a document
+    async fn verify(&self, public_key: &[u8; 32], document: &[u8]) -> Socks5Result<()> {
+        let response = self.get(&self.url.signature(), None).await?;
+        if response.status != 200 {
+            return Err(Socks5Error::ConfigError(format!(
+                "Remote configuration signature answered {}",
+                response.status_line
+            )));
+        }
+        let signature = std::str::from_utf8(&response.body)
+            .ok()
+            .and_then(|hex| parse_hex(hex.trim()))
+            .ok_or_else(|| Socks5Error::ConfigError("Remote configuration signature is not hex".to_string()))?;
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key)
+            .verify(document, &signature)
+            .map_err(|_| Socks5Error::ConfigError("Remote configuration signature does not verify".to_string()))
+    }
+
+    /// Sends a GET for `url` and reads the whole response
+    async fn get(&self, url: &ConfigUrl, etag: Option<&str>) -> Socks5Result<Response> {
+        get(&self.tls, url, etag, MAX_RESPONSE_SIZE).await
+    }
+}
+
+/// Creates a client configuration trusting the web PKI roots
+pub(crate) fn web_pki_client_config() -> Arc<ClientConfig> {
+    let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// Sends a GET for `url` and reads the whole response
+///
+/// # Arguments
+/// * `tls` - Trust anchors for `https://` URLs
+/// * `url` - The URL to fetch
+/// * `etag` - The entity tag of the copy already held, if any
+/// * `max_size` - The largest response accepted, head included
+pub(crate) async fn get(tls: &Arc<ClientConfig>, url: &ConfigUrl, etag: Option<&str>, max_size: u64) -> Socks5Result<Response> {
+    let exchange = async {
+        let stream = TcpStream::connect(url.http.target.to_string()).await?;
+        if !url.tls {
+            return exchange(stream, &url.http, etag, max_size).await;
+        }
+        let host = match &url.http.target {
+            TargetAddr::Domain(host, _) => host.clone(),
+            TargetAddr::Ipv4(ip, _) => ip.to_string(),
+            TargetAddr::Ipv6(ip, _) => ip.to_string(),
+        };
+        let name = ServerName::try_from(host).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let stream = TlsConnector::from(Arc::clone(tls)).connect(name, stream).await?;
+        exchange(stream, &url.http, etag, max_size).await
+    };
+    let raw = tokio::time::timeout(FETCH_TIMEOUT, exchange)
+        .await
+        .map_err(|_| Socks5Error::ConfigError(format!("{} did not answer within {:?}", url.http.authority, FETCH_TIMEOUT)))?
+        .map_err(|e| Socks5Error::ConfigError(format!("Cannot fetch from {}: {}", url.http.authority, e)))?;
+    parse_response(&raw)
+}
+
+/// Writes a GET request and reads the response until the server closes
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    url: &HttpUrl,
+    etag: Option<&str>,
+    max_size: u64,
+) -> io::Result<Vec<u8>> {
+    let condition = etag.map(|etag| format!("If-None-Match: {}\r\n", etag)).unwrap_or_default();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rsocks5/{}\r\nAccept: */*\r\n{}Connection: close\r\n\r\n",
+        url.path,
+        url.authority,
+        env!("CARGO_PKG_VERSION"),
+        condition
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut raw = Vec::new();
+    match (&mut stream).take(max_size).read_to_end(&mut raw).await {
+        Ok(_) => Ok(raw),
+        // Many servers close without a TLS close_notify once the response is out
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !raw.is_empty() => Ok(raw),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the value of the first header called `name`
+pub(crate) fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Parses a non-empty hex string into bytes
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
@@ -0,0 +1,284 @@
+//! Per-client reputation for the SOCKS5 proxy.
+//!
+//! A fixed handshake rate limit treats a well-behaved client the same as a
+//! scanner guessing passwords. [`Reputation`] keeps a penalty score per
+//! client IP instead: failed authentications, protocol violations, denied
+//! requests and failed connects each add to it, and the score halves every
+//! half-life, so old offenses fade. Sources whose score crosses a threshold
+//! are held to a stricter handshake limit, and past a second threshold they
+//! must authenticate to be served at all.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::lru::LruMap;
+use crate::metrics::CloseReason;
+use crate::protocol::AUTHENTICATION_FAILED;
+use crate::ratelimit::{HandshakeRateLimit, SourceRateLimiter};
+use crate::users::{AuthDecision, AuthFuture, Authenticator};
+
+/// The least recently seen sources are forgotten beyond this many
+const MAX_SOURCES: usize = 65536;
+
+/// How long it takes a score to halve by default
+pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(600);
+
+/// The score from which sources are throttled by default
+pub const DEFAULT_THROTTLE_AT: f64 = 5.0;
+
+/// Something a client did that lowers its reputation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Offense {
+    /// The client's credentials were denied
+    AuthFailure,
+    /// The client broke the protocol, e.g. with a malformed handshake
+    ProtocolViolation,
+    /// The client asked for a target the policy refuses
+    Denied,
+    /// The client's target could not be reached
+    ConnectFailure,
+}
+
+impl Offense {
+    /// All offenses, in counter order
+    pub const ALL: [Offense; 4] = [
+        Offense::AuthFailure,
+        Offense::ProtocolViolation,
+        Offense::Denied,
+        Offense::ConnectFailure,
+    ];
+
+    /// Returns a short, stable name for the offense
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Offense::AuthFailure => "auth_failure",
+            Offense::ProtocolViolation => "protocol_violation",
+            Offense::Denied => "denied",
+            Offense::ConnectFailure => "connect_failure",
+        }
+    }
+
+    /// Returns how much the offense adds to a score by default
+    ///
+    /// Guessing passwords and speaking garbage are what scanners do;
+    /// denied targets and unreachable hosts also happen to honest clients.
+    pub fn default_weight(&self) -> f64 {
+        match self {
+            Offense::AuthFailure => 1.0,
+            Offense::ProtocolViolation => 1.0,
+            Offense::Denied => 0.5,
+            Offense::ConnectFailure => 0.25,
+        }
+    }
+
+    /// Returns the offense a session ending with `result` amounts to, if any
+    pub(crate) fn of_session(result: &Socks5Result<CloseReason>) -> Option<Offense> {
+        match result {
+            Ok(CloseReason::Denied) => Some(Offense::Denied),
+            Ok(CloseReason::ProbeRejected) => Some(Offense::ProtocolViolation),
+            Ok(CloseReason::OptimisticConnectFailed) => Some(Offense::ConnectFailure),
+            Ok(_) => None,
+            Err(Socks5Error::HandshakeError(msg)) if msg == AUTHENTICATION_FAILED => Some(Offense::AuthFailure),
+            Err(Socks5Error::HandshakeError(_) | Socks5Error::CommandError(_) | Socks5Error::AddressError(_)) => {
+                Some(Offense::ProtocolViolation)
+            }
+            Err(Socks5Error::ConnectionError(_)) => Some(Offense::ConnectFailure),
+            Err(_) => None,
+        }
+    }
+
+    /// Returns the offense's slot in the weights
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for Offense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Offense {
+    type Err = Socks5Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Offense::ALL
+            .into_iter()
+            .find(|offense| offense.as_str() == s)
+            .ok_or_else(|| Socks5Error::ConfigError(format!("Unknown offense: {}", s)))
+    }
+}
+
+/// How offenses are scored and what low reputation costs a source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReputationPolicy {
+    /// How much each offense adds to a score, by counter order
+    weights: [f64; Offense::ALL.len()],
+    /// How long it takes a score to halve
+    pub half_life: Duration,
+    /// The score from which a source is held to the throttle limit
+    pub throttle_at: f64,
+    /// The handshake limit for throttled sources
+    pub throttle: HandshakeRateLimit,
+    /// The score from which a source must authenticate, if ever
+    pub require_auth_at: Option<f64>,
+}
+
+impl Default for ReputationPolicy {
+    fn default() -> Self {
+        Self {
+            weights: Offense::ALL.map(|offense| offense.default_weight()),
+            half_life: DEFAULT_HALF_LIFE,
+            throttle_at: DEFAULT_THROTTLE_AT,
+            throttle: HandshakeRateLimit::new(1, Duration::from_secs(10), 1),
+            require_auth_at: None,
+        }
+    }
+}
+
+impl ReputationPolicy {
+    /// Creates a policy throttling sources from a score of five to one
+    /// handshake per ten seconds, with scores halving every ten minutes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how much an offense adds to a score
+    pub fn with_weight(mut self, offense: Offense, weight: f64) -> Self {
+        self.weights[offense.index()] = weight.max(0.0);
+        self
+    }
+
+    /// Sets how long it takes a score to halve; at least a second
+    pub fn with_half_life(mut self, half_life: Duration) -> Self {
+        self.half_life = half_life.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Throttles sources with at least `score`
+    pub fn with_throttle_at(mut self, score: f64) -> Self {
+        self.throttle_at = score;
+        self
+    }
+
+    /// Sets the handshake limit throttled sources are held to
+    pub fn with_throttle_limit(mut self, limit: HandshakeRateLimit) -> Self {
+        self.throttle = limit;
+        self
+    }
+
+    /// Serves sources with at least `score` only once they authenticate
+    pub fn with_require_auth(mut self, score: f64) -> Self {
+        self.require_auth_at = Some(score);
+        self
+    }
+
+    /// Returns how much an offense adds to a score
+    pub fn weight(&self, offense: Offense) -> f64 {
+        self.weights[offense.index()]
+    }
+}
+
+/// Decaying penalty scores per client IP
+#[derive(Debug)]
+pub struct Reputation {
+    /// How offenses are scored and acted on
+    policy: ReputationPolicy,
+    /// The time source for decay
+    clock: Arc<dyn Clock>,
+    /// The score per address and when it was computed
+    scores: Mutex<LruMap<IpAddr, (f64, Instant)>>,
+    /// The handshake buckets of throttled addresses
+    throttle: SourceRateLimiter,
+}
+
+impl Reputation {
+    /// Creates a tracker in which every address starts with a clean score
+    pub fn new(policy: ReputationPolicy, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            throttle: SourceRateLimiter::new(policy.throttle, Arc::clone(&clock)),
+            policy,
+            clock,
+            scores: Mutex::new(LruMap::new(MAX_SOURCES)),
+        }
+    }
+
+    /// Returns how offenses are scored and acted on
+    pub fn policy(&self) -> &ReputationPolicy {
+        &self.policy
+    }
+
+    /// Adds an offense to the score of `ip`
+    ///
+    /// # Returns
+    /// * The address's new score
+    pub fn record(&self, ip: IpAddr, offense: Offense) -> f64 {
+        let now = self.clock.now();
+        let mut scores = self.scores.lock().unwrap_or_else(|e| e.into_inner());
+        let (score, updated) = scores.get_or_insert_with(ip, || (0.0, now));
+        *score = self.decayed(*score, *updated, now) + self.policy.weight(offense);
+        *updated = now;
+        *score
+    }
+
+    /// Returns the current score of `ip`; zero for addresses never seen
+    pub fn score(&self, ip: IpAddr) -> f64 {
+        let scores = self.scores.lock().unwrap_or_else(|e| e.into_inner());
+        scores
+            .peek(&ip)
+            .map_or(0.0, |(score, updated)| self.decayed(*score, *updated, self.clock.now()))
+    }
+
+    /// Returns the addresses with a score of at least `min`, worst first
+    pub fn scores(&self, min: f64) -> Vec<(IpAddr, f64)> {
+        let now = self.clock.now();
+        let scores = self.scores.lock().unwrap_or_else(|e| e.into_inner());
+        let mut scores: Vec<_> = scores
+            .iter()
+            .map(|(ip, (score, updated))| (*ip, self.decayed(*score, *updated, now)))
+            .filter(|(_, score)| *score >= min)
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores
+    }
+
+    /// Decides whether `ip` may start another handshake
+    ///
+    /// Sources below the throttle score always may; throttled ones use up
+    /// a handshake of the throttle limit.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        self.score(ip) < self.policy.throttle_at || self.throttle.check(ip)
+    }
+
+    /// Returns whether `ip` must authenticate to be served
+    pub fn requires_auth(&self, ip: IpAddr) -> bool {
+        self.policy.require_auth_at.is_some_and(|at| self.score(ip) >= at)
+    }
+
+    /// Returns what is left of `score`, computed at `updated`, by `now`
+    fn decayed(&self, score: f64, updated: Instant, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+        score * 0.5f64.powf(elapsed / self.policy.half_life.as_secs_f64())
+    }
+}
+
+/// Asks for credentials that can never be right
+///
+/// Stands in for the authenticator when a server without accounts must
+/// make a low-reputation source authenticate: clients offering only "no
+/// authentication" get no acceptable method, the others are denied.
+#[derive(Debug)]
+pub(crate) struct NoAccounts;
+
+impl Authenticator for NoAccounts {
+    fn authenticate<'a>(&'a self, _username: &'a str, _password: &'a str, _peer_addr: SocketAddr) -> AuthFuture<'a> {
+        Box::pin(std::future::ready(AuthDecision::Deny))
+    }
+}
@@ -0,0 +1,276 @@
+//! Asynchronous host name resolution for the SOCKS5 proxy.
+//!
+//! `lookup_host` hands every name to the blocking `getaddrinfo` on tokio's
+//! blocking thread pool, so a slow or unreachable nameserver ties up a
+//! thread per pending lookup and a burst of requests for fresh domains can
+//! exhaust the pool. [`Resolver`] instead sends A and AAAA queries straight
+//! to its nameservers over UDP, with a timeout per nameserver and a cap on
+//! the lookups in flight, and reports the records' TTL alongside the
+//! addresses. Relative names are expanded with the `search` domains and
+//! `ndots` option of `/etc/resolv.conf` the way `getaddrinfo` does; see
+//! [`SearchList`].
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::random::{RandomSource, StdRandom};
+use crate::srv::{self, DEFAULT_TIMEOUT};
+
+/// The IPv4 address record type
+const TYPE_A: u16 = 1;
+
+/// The IPv6 address record type
+const TYPE_AAAA: u16 = 28;
+
+/// Lookups in flight at once by default
+pub const DEFAULT_CONCURRENCY: usize = 256;
+
+/// The addresses of a host name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The addresses, IPv6 first as `getaddrinfo` usually orders them
+    pub addrs: Vec<IpAddr>,
+    /// How long the addresses may be cached: the lowest TTL of their records
+    pub ttl: Duration,
+}
+
+/// The search domains and `ndots` option of a `resolv.conf`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchList {
+    /// Domains appended to relative names, tried in order
+    pub domains: Vec<String>,
+    /// Names with at least this many dots are tried as given first
+    pub ndots: usize,
+}
+
+impl Default for SearchList {
+    fn default() -> Self {
+        Self { domains: Vec::new(), ndots: 1 }
+    }
+}
+
+impl SearchList {
+    /// Parses the `search`, `domain` and `options ndots:N` lines of a `resolv.conf`
+    ///
+    /// As in glibc the last `search` or `domain` line wins and `ndots` is
+    /// capped at 15.
+    pub fn parse(contents: &str) -> Self {
+        let mut list = Self::default();
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("search") | Some("domain") => {
+                    list.domains = words.map(|domain| domain.trim_end_matches('.').to_string()).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        if let Some(ndots) = option.strip_prefix("ndots:").and_then(|n| n.parse::<usize>().ok()) {
+                            list.ndots = ndots.min(15);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        list
+    }
+
+    /// Reads the search list of `/etc/resolv.conf`; empty if it cannot be read
+    pub fn system() -> Self {
+        Self::parse(&std::fs::read_to_string("/etc/resolv.conf").unwrap_or_default())
+    }
+
+    /// Returns the names to try for `name`, in order
+    ///
+    /// A name with a trailing dot is absolute and tried alone. A name with
+    /// at least `ndots` dots is tried as given before the search domains;
+    /// any other name after them.
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(absolute) = name.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+        let expanded = self.domains.iter().map(|domain| format!("{}.{}", name, domain));
+        if name.matches('.').count() >= self.ndots {
+            std::iter::once(name.to_string()).chain(expanded).collect()
+        } else {
+            expanded.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+/// Resolves host names by querying nameservers directly
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    /// Nameservers asked in order until one answers
+    nameservers: Vec<SocketAddr>,
+    /// How long each nameserver gets to answer
+    timeout: Duration,
+    /// Limits the lookups in flight
+    permits: Arc<Semaphore>,
+    /// Expands relative names
+    search: SearchList,
+    /// Source of query IDs
+    rng: Arc<dyn RandomSource>,
+}
+
+impl Resolver {
+    /// Creates a resolver asking the given nameservers
+    pub fn new(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            timeout: DEFAULT_TIMEOUT,
+            permits: Arc::new(Semaphore::new(DEFAULT_CONCURRENCY)),
+            search: SearchList::default(),
+            rng: Arc::new(StdRandom::new()),
+        }
+    }
+
+    /// Creates a resolver asking the nameservers of `/etc/resolv.conf`
+    /// and expanding names with its search list
+    ///
+    /// Falls back to a resolver on localhost when the file lists none.
+    pub fn system() -> Self {
+        Self::new(srv::system_nameservers()).with_search(SearchList::system())
+    }
+
+    /// Sets the search list relative names are expanded with
+    pub fn with_search(mut self, search: SearchList) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// Sets how long each nameserver gets to answer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Limits how many lookups may be in flight at once; further lookups wait
+    ///
+    /// The limit is at least one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        self
+    }
+
+    /// Sets the random source for query IDs
+    pub fn with_random(mut self, rng: Arc<dyn RandomSource>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Returns the nameservers asked, in order
+    pub fn nameservers(&self) -> &[SocketAddr] {
+        &self.nameservers
+    }
+
+    /// Returns how long each nameserver gets to answer
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Looks up the IPv4 and IPv6 addresses of `host`
+    ///
+    /// IP literals and `localhost` are answered without a query. Otherwise
+    /// the names of [`SearchList::candidates`] are tried in order until one
+    /// has addresses. The A and AAAA queries are sent concurrently; a name
+    /// has addresses if either finds some.
+    ///
+    /// # Returns
+    /// * `Ok(Resolution)` - The addresses and how long they may be cached
+    /// * `Err(io::Error)` - If no name has addresses or no nameserver answered
+    pub async fn lookup(&self, host: &str) -> io::Result<Resolution> {
+        let bare = host.trim_end_matches('.');
+        if let Ok(ip) = bare.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Ok(Resolution { addrs: vec![ip], ttl: Duration::MAX });
+        }
+        if bare.eq_ignore_ascii_case("localhost") {
+            return Ok(Resolution {
+                addrs: vec![IpAddr::V6(Ipv6Addr::LOCALHOST), IpAddr::V4(Ipv4Addr::LOCALHOST)],
+                ttl: Duration::MAX,
+            });
+        }
+
+        let _permit = self.permits.acquire().await.map_err(io::Error::other)?;
+        let mut last_error = None;
+        for name in self.search.candidates(host) {
+            match self.lookup_name(&name).await {
+                Ok(resolution) => return Ok(resolution),
+                Err(e) => {
+                    log::debug!("No addresses for {}: {}", name, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", bare))
+        }))
+    }
+
+    /// Looks up the addresses of exactly `host`, without the search list
+    async fn lookup_name(&self, host: &str) -> io::Result<Resolution> {
+        let (v6, v4) = tokio::join!(self.query(host, TYPE_AAAA), self.query(host, TYPE_A));
+        let mut resolution = Resolution { addrs: Vec::new(), ttl: Duration::MAX };
+        let mut last_error = None;
+        for result in [v6, v4] {
+            match result {
+                Ok((addrs, ttl)) => {
+                    if !addrs.is_empty() {
+                        resolution.ttl = resolution.ttl.min(ttl);
+                    }
+                    resolution.addrs.extend(addrs);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        if !resolution.addrs.is_empty() {
+            return Ok(resolution);
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", host))
+        }))
+    }
+
+    /// Asks the nameservers in turn for the `record_type` addresses of `host`
+    async fn query(&self, host: &str, record_type: u16) -> io::Result<(Vec<IpAddr>, Duration)> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no nameservers configured");
+        for nameserver in &self.nameservers {
+            let id = self.rng.next_u64() as u16;
+            let query = srv::encode_query(id, host, record_type)?;
+            match tokio::time::timeout(self.timeout, srv::exchange(*nameserver, &query)).await {
+                Ok(Ok(response)) => return parse_addresses(&query, &response),
+                Ok(Err(e)) => last_error = e,
+                Err(_) => {
+                    last_error = io::Error::new(io::ErrorKind::TimedOut, format!("{} did not answer", nameserver));
+                }
+            }
+            log::debug!("Lookup of {} via {} failed: {}", host, nameserver, last_error);
+        }
+        Err(last_error)
+    }
+}
+
+/// Extracts the A and AAAA addresses and their lowest TTL from a response to `query`
+fn parse_addresses(query: &[u8], packet: &[u8]) -> io::Result<(Vec<IpAddr>, Duration)> {
+    let mut addrs = Vec::new();
+    let mut ttl = Duration::MAX;
+    // CNAME records in between are skipped; the recursive resolver has
+    // already followed them
+    for answer in srv::answers(query, packet)? {
+        let data = &packet[answer.data];
+        let ip = match (answer.record_type, data.len()) {
+            (TYPE_A, 4) => IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = data.try_into().map_err(io::Error::other)?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+        addrs.push(ip);
+        ttl = ttl.min(answer.ttl);
+    }
+    Ok((addrs, ttl))
+}
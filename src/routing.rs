@@ -0,0 +1,276 @@
+//! Destination-based routing rules for the SOCKS5 proxy.
+//!
+//! Routes match the requested target and adjust how the outbound connection
+//! is made, e.g. tagging bulk traffic with a firewall mark or DSCP value so
+//! the host's policy routing and QoS can treat it differently, or sending it
+//! to a different host or port than the client asked for.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::canonical::canonicalize;
+use crate::error::Socks5Error;
+use crate::protocol::TargetAddr;
+
+/// The host part of a target pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    /// Matches every host
+    Any,
+    /// Matches exactly this host name (in canonical form)
+    Exact(String),
+    /// Matches this domain and all of its subdomains (`*.example.com`)
+    Suffix(String),
+    /// Matches IP literal targets inside this network
+    Cidr(IpAddr, u8),
+}
+
+impl HostPattern {
+    /// Returns whether the pattern matches the target's host
+    ///
+    /// Domain targets are never resolved, so they do not match CIDR patterns.
+    pub fn matches(&self, target: &TargetAddr) -> bool {
+        match (self, target) {
+            (HostPattern::Any, _) => true,
+            (HostPattern::Exact(host), TargetAddr::Domain(domain, _)) => {
+                canonicalize(domain) == host.as_str()
+            }
+            (HostPattern::Exact(host), TargetAddr::Ipv4(ip, _)) => ip.to_string() == *host,
+            (HostPattern::Exact(host), TargetAddr::Ipv6(ip, _)) => ip.to_string() == *host,
+            (HostPattern::Suffix(suffix), TargetAddr::Domain(domain, _)) => {
+                let domain = canonicalize(domain);
+                domain == suffix.as_str()
+                    || domain.strip_suffix(suffix.as_str()).is_some_and(|rest| rest.ends_with('.'))
+            }
+            (HostPattern::Suffix(_), TargetAddr::Ipv4(..) | TargetAddr::Ipv6(..)) => false,
+            (HostPattern::Cidr(network, len), TargetAddr::Ipv4(ip, _)) => {
+                cidr_contains(*network, *len, IpAddr::V4(*ip))
+            }
+            (HostPattern::Cidr(network, len), TargetAddr::Ipv6(ip, _)) => {
+                cidr_contains(*network, *len, IpAddr::V6(*ip))
+            }
+            (HostPattern::Cidr(..), TargetAddr::Domain(..)) => false,
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = Socks5Error;
+
+    /// Parses `*`, `*.example.com`, `10.0.0.0/8` or a plain host
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(HostPattern::Any);
+        }
+        if let Some(suffix) = s.strip_prefix("*.") {
+            return Ok(HostPattern::Suffix(canonicalize(suffix).into_owned()));
+        }
+        if let Some((network, len)) = s.split_once('/') {
+            let network: IpAddr = network.parse().map_err(|_| {
+                Socks5Error::AddressError(format!("Invalid network in pattern: {}", s))
+            })?;
+            let max_len = if network.is_ipv4() { 32 } else { 128 };
+            let len = len.parse::<u8>().ok().filter(|len| *len <= max_len).ok_or_else(|| {
+                Socks5Error::AddressError(format!("Invalid prefix length in pattern: {}", s))
+            })?;
+            return Ok(HostPattern::Cidr(network, len));
+        }
+        if s.is_empty() {
+            return Err(Socks5Error::AddressError("Empty host pattern".to_string()));
+        }
+        Ok(HostPattern::Exact(canonicalize(s).into_owned()))
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostPattern::Any => f.write_str("*"),
+            HostPattern::Exact(host) => f.write_str(host),
+            HostPattern::Suffix(suffix) => write!(f, "*.{}", suffix),
+            HostPattern::Cidr(network, len) => write!(f, "{}/{}", network, len),
+        }
+    }
+}
+
+/// Matches targets by host pattern and optional port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetPattern {
+    /// The host part of the pattern
+    pub host: HostPattern,
+    /// The port to match, or any port when `None`
+    pub port: Option<u16>,
+}
+
+impl TargetPattern {
+    /// Returns whether the pattern matches the target
+    pub fn matches(&self, target: &TargetAddr) -> bool {
+        self.port.is_none_or(|port| port == target.port()) && self.host.matches(target)
+    }
+}
+
+impl FromStr for TargetPattern {
+    type Err = Socks5Error;
+
+    /// Parses `HOST` or `HOST:PORT`, where HOST is any [`HostPattern`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A trailing `:digits` is a port unless it is part of an IPv6 network
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => {
+                let port = port.parse::<u16>().map_err(|_| {
+                    Socks5Error::AddressError(format!("Invalid port in pattern: {}", s))
+                })?;
+                (host, Some(port))
+            }
+            _ => (s, None),
+        };
+        Ok(Self {
+            host: host.parse()?,
+            port,
+        })
+    }
+}
+
+impl fmt::Display for TargetPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => write!(f, "{}", self.host),
+        }
+    }
+}
+
+/// A routing rule: outbound connection settings for matching targets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// Which targets the route applies to
+    pub pattern: TargetPattern,
+    /// DSCP code point (0-63) to mark outbound packets with
+    pub dscp: Option<u8>,
+    /// Firewall mark (`SO_MARK`) for policy routing
+    pub mark: Option<u32>,
+    /// Host to connect to instead of the requested one
+    pub rewrite_host: Option<String>,
+    /// Port to connect to instead of the requested one
+    pub rewrite_port: Option<u16>,
+    /// SRV service prefix such as `_postgres._tcp`; domain targets are
+    /// resolved through the SRV records of `<srv>.<domain>`, whose hosts
+    /// and ports replace the requested port
+    pub srv: Option<String>,
+}
+
+impl Route {
+    /// Returns the address to connect to instead of `target`, if the route
+    /// rewrites its host or port
+    pub fn rewrite(&self, target: &TargetAddr) -> Option<TargetAddr> {
+        if self.rewrite_host.is_none() && self.rewrite_port.is_none() {
+            return None;
+        }
+        let port = self.rewrite_port.unwrap_or(target.port());
+        match &self.rewrite_host {
+            Some(host) => Some(match host.parse::<IpAddr>() {
+                Ok(ip) => TargetAddr::from(SocketAddr::new(ip, port)),
+                Err(_) => TargetAddr::Domain(host.clone(), port),
+            }),
+            None => Some(match target {
+                TargetAddr::Ipv4(ip, _) => TargetAddr::Ipv4(*ip, port),
+                TargetAddr::Ipv6(ip, _) => TargetAddr::Ipv6(*ip, port),
+                TargetAddr::Domain(domain, _) => TargetAddr::Domain(domain.clone(), port),
+            }),
+        }
+    }
+}
+
+impl FromStr for Route {
+    type Err = Socks5Error;
+
+    /// Parses `PATTERN=key:value,...` with keys `dscp`, `mark`, `host`,
+    /// `port` and `srv`, e.g. `*.backup.internal=dscp:8,mark:0x20`,
+    /// `*.internal:80=port:8080` or `*.svc.internal=srv:_http._tcp`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, settings) = s.split_once('=').ok_or_else(|| {
+            Socks5Error::AddressError(format!("Route must be PATTERN=SETTINGS: {}", s))
+        })?;
+        let mut route = Route {
+            pattern: pattern.parse()?,
+            dscp: None,
+            mark: None,
+            rewrite_host: None,
+            rewrite_port: None,
+            srv: None,
+        };
+
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let invalid = || Socks5Error::AddressError(format!("Invalid route setting: {}", setting));
+            let (key, value) = setting.split_once(':').ok_or_else(invalid)?;
+            match key {
+                "dscp" => route.dscp = Some(parse_number(value).filter(|dscp| *dscp < 64).ok_or_else(invalid)? as u8),
+                "mark" => route.mark = Some(parse_number(value).ok_or_else(invalid)?),
+                "host" if !value.is_empty() => route.rewrite_host = Some(value.to_string()),
+                "port" => route.rewrite_port = Some(value.parse().map_err(|_| invalid())?),
+                "srv" if !value.is_empty() => route.srv = Some(value.trim_end_matches('.').to_string()),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(route)
+    }
+}
+
+/// An ordered list of routes; the first matching route wins
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingTable {
+    /// The routes, in priority order
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    /// Creates a routing table from routes in priority order
+    pub fn new(routes: Vec<Route>) -> Self {
+        Self { routes }
+    }
+
+    /// Returns the routes
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Returns the first route matching the target, if any
+    pub fn route_for(&self, target: &TargetAddr) -> Option<&Route> {
+        self.routes.iter().find(|route| route.pattern.matches(target))
+    }
+
+    /// Returns the index of the first route matching the target, if any
+    pub fn route_index(&self, target: &TargetAddr) -> Option<usize> {
+        self.routes.iter().position(|route| route.pattern.matches(target))
+    }
+
+    /// Returns the address to connect to instead of `target`, if its route
+    /// rewrites it
+    pub fn rewrite(&self, target: &TargetAddr) -> Option<TargetAddr> {
+        self.route_for(target).and_then(|route| route.rewrite(target))
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number
+fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Returns whether `ip` lies inside `network/len`
+pub(crate) fn cidr_contains(network: IpAddr, len: u8, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
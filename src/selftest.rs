@@ -0,0 +1,104 @@
+//! Startup self-test for the SOCKS5 proxy.
+//!
+//! A configuration can break the request pipeline in ways that only show
+//! once a client connects: a mistyped upstream, a route rewriting every
+//! target away, an authenticator that refuses everyone. [`self_test`] runs
+//! one CONNECT through a freshly bound listener to a built-in echo target on
+//! loopback and checks that bytes make the round trip, so such problems are
+//! caught at startup instead of by the first user.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::client::{self, Credentials};
+use crate::constants::reply;
+use crate::error::{Socks5Error, Socks5Result};
+use crate::protocol::TargetAddr;
+use crate::Server;
+
+/// How long the whole self-test may take
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The bytes sent through the proxy and expected back
+const PROBE: &[u8] = b"rsocks5 self-test";
+
+/// Connects through a serving server to a built-in echo target and checks a
+/// byte round trip
+///
+/// The server must be bound and serving. The first configured account is
+/// used when authentication is required; servers whose credentials are only
+/// known to a custom authenticator, and servers behind a port knock, cannot
+/// be tested.
+///
+/// # Returns
+/// * `Ok(Duration)` - How long the round trip took
+/// * `Err(Socks5Error)` - What broke, or why the server cannot be tested
+pub async fn self_test(server: &Server) -> Socks5Result<Duration> {
+    let proxy = server.local_addr().ok_or_else(|| {
+        Socks5Error::ConfigError("Self-test needs a bound server".to_string())
+    })?;
+    if server.knock().is_some() {
+        return Err(Socks5Error::ConfigError(
+            "Self-test is not possible behind a port knock".to_string(),
+        ));
+    }
+    let credentials = match server.users().usernames().next() {
+        Some(username) => Some(Credentials::new(username, server.users().password(username).unwrap_or_default())),
+        None if server.authenticator().is_some() => {
+            return Err(Socks5Error::ConfigError(
+                "Self-test needs an account; credentials are checked by a custom authenticator".to_string(),
+            ));
+        }
+        None => None,
+    };
+
+    let started = Instant::now();
+    tokio::time::timeout(TIMEOUT, round_trip(server, proxy, credentials.as_ref()))
+        .await
+        .map_err(|_| Socks5Error::ConnectionError(format!("Self-test through {} timed out after {:?}", proxy, TIMEOUT)))??;
+    Ok(started.elapsed())
+}
+
+/// Sends the probe through the proxy to a one-shot echo target
+async fn round_trip(server: &Server, proxy: SocketAddr, credentials: Option<&Credentials>) -> Socks5Result<()> {
+    let echo = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let target = TargetAddr::from(echo.local_addr()?);
+    tokio::spawn(async move {
+        if let Ok((mut socket, _)) = echo.accept().await {
+            let (mut reader, mut writer) = socket.split();
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        }
+    });
+
+    let mut stream = TcpStream::connect(reachable(proxy)).await?;
+    if let Some(probe_resistance) = server.probe_resistance() {
+        stream.write_all(probe_resistance.preamble()).await?;
+    }
+    client::connect(&mut stream, &target, credentials).await.map_err(|e| match e {
+        Socks5Error::ReplyError(reply::NOT_ALLOWED) => Socks5Error::ConnectionError(format!(
+            "Self-test target {} is refused by policy", target
+        )),
+        e => Socks5Error::ConnectionError(format!("Self-test CONNECT through {} failed: {}", proxy, e)),
+    })?;
+
+    stream.write_all(PROBE).await?;
+    let mut echoed = [0; PROBE.len()];
+    stream.read_exact(&mut echoed).await?;
+    if echoed != PROBE {
+        return Err(Socks5Error::RelayError(format!(
+            "Self-test through {} echoed {:02x?} instead of the probe", proxy, echoed
+        )));
+    }
+    Ok(())
+}
+
+/// Returns an address clients can connect to for a listener's address
+fn reachable(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) if v4.ip().is_unspecified() => SocketAddr::from((Ipv4Addr::LOCALHOST, v4.port())),
+        SocketAddr::V6(v6) if v6.ip().is_unspecified() => SocketAddr::from((Ipv6Addr::LOCALHOST, v6.port())),
+        addr => addr,
+    }
+}
@@ -3,15 +3,118 @@
 //! This module provides the main server functionality for the SOCKS5 proxy,
 //! including server initialization and client connection handling.
 
-use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
 use log;
 
-use crate::constants::DEFAULT_PORT;
+use crate::audit::AuditWriter;
+use crate::acl::{AclRules, PortPolicy, TargetAllowList};
+use crate::accounting::TrafficAccounting;
+use crate::active::{ActiveSession, ActiveSessions, Counted, SessionSnapshot};
+use crate::bandwidth::BandwidthPolicy;
+use crate::blocklist::Blocklists;
+use crate::capabilities::Capabilities;
+use crate::cluster::{Cluster, UserLease};
+use crate::clock::{Clock, TokioClock};
+use crate::compat::{peek_version, Quirk, Quirks};
+use crate::constants::{auth, reply, DEFAULT_PORT, SOCKS_VERSION};
 use crate::error::{Socks5Error, Socks5Result};
-use crate::protocol::{handshake, process_command};
-use crate::connection::connect_to_target;
-use crate::relay::relay_data;
+use crate::events::{unix_millis, EventWriter, SessionEvent};
+use crate::hooks::SessionHooks;
+use crate::extensions::{DenialReasons, ExtensionRegistry, WireExtension};
+use crate::geoip::{OriginFilter, OriginVerdict};
+use crate::http;
+use crate::knock::{KnockConfig, KnockGate};
+use crate::listener::{peek, ClientStream, Listener};
+use crate::metrics::{CloseReason, ListenerLabel, ListenerStats, Metrics, ProcessStats, DEFAULT_ACCEPT_SAMPLE_EVERY};
+use crate::mirror::{RequestEvent, RequestMirror};
+use crate::socks4::{read_socks4_request, send_socks4_reply, SOCKS4_VERSION};
+use crate::syslog::SyslogSink;
+use crate::tls::{AlpnHandler, AlpnHandlers, TlsListener, DEFAULT_HANDSHAKE_TIMEOUT};
+#[cfg(feature = "natpmp")]
+use crate::natpmp::PortMapper;
+use crate::obfuscation::{ProbeResistance, DEFAULT_PREAMBLE_TIMEOUT};
+use crate::policy::{Decision, Denial, PolicyContext, PolicyEngine};
+use crate::protocol::{handshake_with_authenticator, process_command_with_compat, send_failure, send_failure_with_trailer, HandshakeInfo, TargetAddr};
+use crate::random::{RandomSource, StdRandom};
+use crate::ratelimit::{HandshakeRateLimit, SourceRateLimiter};
+use crate::connection::{connect_via_upstreams, Connector, ReplyMode};
+use crate::portowner;
+use crate::relay::{Meter, Relay, RelayEngine, RelayOptions, RelayStats};
+use crate::timeouts::Timeouts;
+use crate::remote::{RemoteConfig, RemoteSettings};
+use crate::recent::{RecentSessions, SessionRecord};
+use crate::reputation::{NoAccounts, Offense, Reputation};
+use crate::slo::SloMonitor;
+use crate::upstream::Upstreams;
+use crate::users::{Authenticator, UserTable};
+use crate::warnings::{WarningAggregator, DEFAULT_WINDOW};
+use crate::watchdog::{Watchdog, WatchedAuthenticator, WatchedSessionHooks};
+use tokio_rustls::rustls;
+
+/// Connections over the limit answered at once; further ones are closed unanswered
+pub const MAX_PENDING_REJECTS: usize = 64;
+
+/// How long a connection over the limit gets to send its greeting
+pub const REJECT_GREETING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Exponential backoff for the accept loop
+///
+/// Persistent accept errors (e.g. running out of file descriptors or
+/// memory) would otherwise spin the loop at full CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptBackoff {
+    /// Pause after the first failed accept
+    pub initial: Duration,
+    /// Upper bound on the pause
+    pub max: Duration,
+}
+
+impl AcceptBackoff {
+    /// Creates a backoff doubling from `initial` up to `max`
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max: max.max(initial) }
+    }
+
+    /// Returns the pause after `failures` consecutive failed accepts
+    pub fn delay(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.initial.saturating_mul(1 << doublings).min(self.max)
+    }
+}
+
+impl Default for AcceptBackoff {
+    /// Starts at 10 ms and caps at one second
+    fn default() -> Self {
+        Self::new(Duration::from_millis(10), Duration::from_secs(1))
+    }
+}
+
+/// What happens to connections beyond the connection limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowMode {
+    /// Answer the greeting with NO ACCEPTABLE METHODS and close, or close
+    /// unanswered while [`MAX_PENDING_REJECTS`] others are being answered
+    #[default]
+    Reject,
+    /// Stop accepting until a session ends; new connections wait in the
+    /// listen backlog
+    Queue,
+}
 
 /// SOCKS5 proxy server
 pub struct Server {
@@ -19,40 +122,1522 @@ pub struct Server {
     bind_addr: String,
     /// The port the server is listening on
     port: u16,
-    /// Optional username for authentication
-    username: Option<String>,
-    /// Optional password for authentication
-    password: Option<String>,
+    /// Accounts clients must authenticate as; empty if no authentication is required
+    users: UserTable,
+    /// Check for client credentials replacing the accounts, if any
+    authenticator: Option<Arc<dyn Authenticator>>,
+    /// Time source used by time-dependent features
+    clock: Arc<dyn Clock>,
+    /// Random source used for connection IDs and randomized choices
+    rng: Arc<dyn RandomSource>,
+    /// Deadlines of the session phases
+    timeouts: Timeouts,
+    /// Connection counters
+    metrics: Arc<Metrics>,
+    /// Upstream proxies to reach targets through, if any
+    upstreams: Option<Upstreams>,
+    /// Settings for direct connections to targets
+    connector: Connector,
+    /// Port knock sequence clients must complete before connecting, if any
+    knock: Option<KnockConfig>,
+    /// Preamble clients must send before SOCKS5, if any
+    probe_resistance: Option<ProbeResistance>,
+    /// The only targets clients may connect to, if restricted
+    allowed_targets: Option<TargetAllowList>,
+    /// Whether domain name targets are refused so the proxy never resolves names
+    ip_literals_only: bool,
+    /// Copy engine, buffer size, throttles and idle timeout for relaying
+    relay_options: RelayOptions,
+    /// Whether policy denials are only logged and counted, not enforced
+    shadow: bool,
+    /// Sink that every request is mirrored to, if any
+    mirror: Option<Arc<RequestMirror>>,
+    /// Wire extensions clients may negotiate
+    extensions: ExtensionRegistry,
+    /// Known client misbehaviours that are tolerated
+    compat: Quirks,
+    /// The address the listener was bound to, once bound
+    local_addr: OnceLock<SocketAddr>,
+    /// How long to pause the accept loop after consecutive accept errors
+    accept_backoff: AcceptBackoff,
+    /// Whether the accept loop is currently accepting without errors
+    accepting: AtomicBool,
+    /// Every how many accepted connections the accept loop is timed
+    accept_sample_every: u32,
+    /// Bandwidth limits applied to relayed traffic, if any
+    bandwidth: Option<Arc<BandwidthPolicy>>,
+    /// The running knock gate, once the server has started
+    knock_gate: OnceLock<Arc<KnockGate>>,
+    /// Set once the server has been asked to stop accepting
+    shutdown: watch::Sender<bool>,
+    /// A listener handed over by the caller, used instead of binding
+    prebound: Mutex<Option<TcpListener>>,
+    /// Syslog collector receiving an audit record of every request, if any
+    audit: Option<Arc<SyslogSink>>,
+    /// Rotating file receiving an audit record of every request, if any
+    audit_file: Option<Arc<AuditWriter>>,
+    /// When the server was created, for its uptime
+    started_at: Instant,
+    /// Origin rules checked right after accept
+    origin_filter: Option<Arc<OriginFilter>>,
+    /// Which target ports are refused
+    port_policy: PortPolicy,
+    /// Whether the policy rule chain of every request is logged and audited
+    decision_tracing: bool,
+    /// How long repeated failures from one client are collapsed into a summary
+    warning_window: Duration,
+    /// Whether plain HTTP requests with an absolute URI are forwarded
+    http_forward: bool,
+    /// Whether SOCKS4 and SOCKS4a requests are served
+    socks4: bool,
+    /// Last port tried when the configured one is taken
+    last_port: u16,
+    /// Gateway asked to forward an external port to the listener, if any
+    #[cfg(feature = "natpmp")]
+    port_mapper: Option<PortMapper>,
+    /// Most client sessions handled at once, if limited
+    max_connections: Option<usize>,
+    /// What happens to connections beyond the limit
+    overflow: OverflowMode,
+    /// How many handshakes one client IP may start, if limited
+    handshake_rate: Option<HandshakeRateLimit>,
+    /// Ordered allow and deny rules for targets
+    acl: AclRules,
+    /// Domains imported from blocklists, if any
+    blocklists: Option<Arc<Blocklists>>,
+    /// Where session events are written, if anywhere
+    events: Option<Arc<EventWriter>>,
+    /// Connect latency objectives to watch
+    slo: Option<Arc<SloMonitor>>,
+    /// Records of the last sessions, kept for inspection
+    recent: Option<Arc<RecentSessions>>,
+    /// The sessions being handled, if listed for inspection
+    active: Option<Arc<ActiveSessions>>,
+    /// Traffic counters by username, if accounted
+    accounting: Option<Arc<TrafficAccounting>>,
+    /// Callbacks at session milestones, if registered
+    hooks: Option<Arc<dyn SessionHooks>>,
+    /// Time budgets for user-provided hooks
+    watchdog: Watchdog,
+    /// Penalty scores of client IPs, if reputation is tracked
+    reputation: Option<Arc<Reputation>>,
+    /// Configuration polled from a URL, replacing policies and accounts
+    remote: Option<Arc<RemoteConfig>>,
+    /// TLS configuration clients connect with, if the listener terminates TLS
+    tls: Option<Arc<rustls::ServerConfig>>,
+    /// How long a client may take to complete the TLS handshake
+    tls_handshake_timeout: Duration,
+    /// Handlers of the ALPN protocols served next to SOCKS on the TLS listener
+    alpn: AlpnHandlers,
+    /// Per-user limits shared with the rest of the fleet, if any
+    cluster: Option<Arc<Cluster>>,
 }
 
-impl Server {
-    /// Creates a new SOCKS5 server instance
-    ///
-    /// # Arguments
-    /// * `bind_addr` - The address to bind the server to (e.g., "0.0.0.0")
-    /// * `port` - The port to listen on (default: 1080)
-    /// * `username` - Optional username for authentication
-    /// * `password` - Optional password for authentication
+/// Per-server state shared with every connection task
+struct ClientContext {
+    /// Check for client credentials; `None` if no authentication is required
+    authenticator: Option<Arc<dyn Authenticator>>,
+    /// Deadlines for the first byte and the handshake; the connector and
+    /// relay options carry the others
+    timeouts: Timeouts,
+    /// Upstream proxies to reach targets through, if any
+    upstreams: Option<Upstreams>,
+    /// Settings for direct connections to targets
+    connector: Connector,
+    /// Preamble clients must send before SOCKS5, if any
+    probe_resistance: Option<ProbeResistance>,
+    /// Target policies every request is evaluated against
+    policy: Arc<PolicyEngine>,
+    /// Time source for event timestamps
+    clock: Arc<dyn Clock>,
+    /// Counters for shadow-mode denials
+    metrics: Arc<Metrics>,
+    /// Copy engine, buffer size, throttles and idle timeout for relaying
+    relay_options: RelayOptions,
+    /// Sink that every request is mirrored to, if any
+    mirror: Option<Arc<RequestMirror>>,
+    /// Wire extensions clients may negotiate
+    extensions: ExtensionRegistry,
+    /// Known client misbehaviours that are tolerated
+    compat: Quirks,
+    /// Bandwidth limits applied to relayed traffic, if any
+    bandwidth: Option<Arc<BandwidthPolicy>>,
+    /// Syslog collector receiving an audit record of every request, if any
+    audit: Option<Arc<SyslogSink>>,
+    /// Rotating file receiving an audit record of every request, if any
+    audit_file: Option<Arc<AuditWriter>>,
+    /// Whether the policy rule chain of every request is logged and audited
+    decision_tracing: bool,
+    /// Collapses repeated handshake failures per client IP
+    handshake_failures: Arc<WarningAggregator<IpAddr>>,
+    /// Whether plain HTTP requests with an absolute URI are forwarded
+    http_forward: bool,
+    /// Whether SOCKS4 and SOCKS4a requests are served
+    socks4: bool,
+    /// Where session events are written, if anywhere
+    events: Option<Arc<EventWriter>>,
+    /// Connect latency objectives to watch
+    slo: Option<Arc<SloMonitor>>,
+    /// Records of the last sessions, kept for inspection
+    recent: Option<Arc<RecentSessions>>,
+    /// The sessions being handled, if listed for inspection
+    active: Option<Arc<ActiveSessions>>,
+    /// Traffic counters by username, if accounted
+    accounting: Option<Arc<TrafficAccounting>>,
+    /// Callbacks at session milestones, bounded by the watchdog, if registered
+    hooks: Option<Arc<dyn SessionHooks>>,
+    /// Penalty scores of client IPs, if reputation is tracked
+    reputation: Option<Arc<Reputation>>,
+    /// Configuration polled from a URL, replacing policies and accounts
+    remote: Option<Arc<RemoteConfig>>,
+    /// The policies with the remote settings last applied, once there are any
+    remote_policy: Mutex<Option<(Arc<RemoteSettings>, Arc<PolicyEngine>)>>,
+    /// Per-user limits shared with the rest of the fleet, if any
+    cluster: Option<Arc<Cluster>>,
+}
+
+impl ClientContext {
+    /// Returns the target policies to evaluate requests against
+    ///
+    /// Remote settings replace the ACL, and the allow-list if they have one;
+    /// the engine is rebuilt once per remote document.
+    fn policy(&self) -> Arc<PolicyEngine> {
+        let Some(settings) = self.remote.as_ref().and_then(|remote| remote.current()) else {
+            return Arc::clone(&self.policy);
+        };
+        let mut cached = self.remote_policy.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((applied, policy)) = &*cached {
+            if Arc::ptr_eq(applied, &settings) {
+                return Arc::clone(policy);
+            }
+        }
+        let mut policy = (*self.policy).clone().with_acl(settings.acl.clone());
+        if let Some(allowed_targets) = &settings.allowed_targets {
+            policy = policy.with_allowed_targets(allowed_targets.clone());
+        }
+        let policy = Arc::new(policy);
+        *cached = Some((settings, Arc::clone(&policy)));
+        policy
+    }
+
+    /// Returns the check for client credentials, if authentication is required
+    ///
+    /// Accounts in the remote settings replace the server's own check.
+    fn authenticator(&self) -> Option<Arc<dyn Authenticator>> {
+        match self.remote.as_ref().and_then(|remote| remote.current()) {
+            Some(settings) if !settings.users.is_empty() => Some(Arc::clone(&settings.users) as Arc<dyn Authenticator>),
+            _ => self.authenticator.clone(),
+        }
+    }
+}
+
+impl Server {
+    /// Creates a new SOCKS5 server instance
+    ///
+    /// # Arguments
+    /// * `bind_addr` - The address to bind the server to (e.g., "0.0.0.0")
+    /// * `port` - The port to listen on (default: 1080)
+    /// * `username` - Optional username for authentication
+    /// * `password` - Optional password for authentication
+    ///
+    /// # Returns
+    /// * A new Server instance
+    pub fn new(bind_addr: String, port: Option<u16>, username: Option<String>, password: Option<String>) -> Self {
+        let users = match (&username, &password) {
+            (Some(username), Some(password)) => UserTable::new().with_user(username, password),
+            _ => UserTable::new(),
+        };
+        Self {
+            bind_addr,
+            port: port.unwrap_or(DEFAULT_PORT),
+            users,
+            authenticator: None,
+            clock: Arc::new(TokioClock),
+            rng: Arc::new(StdRandom::new()),
+            timeouts: Timeouts::default(),
+            metrics: Arc::new(Metrics::new()),
+            upstreams: None,
+            connector: Connector::new(),
+            knock: None,
+            probe_resistance: None,
+            allowed_targets: None,
+            ip_literals_only: false,
+            relay_options: RelayOptions::default(),
+            shadow: false,
+            mirror: None,
+            extensions: ExtensionRegistry::new(),
+            compat: Quirks::new(),
+            local_addr: OnceLock::new(),
+            accept_backoff: AcceptBackoff::default(),
+            accepting: AtomicBool::new(true),
+            accept_sample_every: DEFAULT_ACCEPT_SAMPLE_EVERY,
+            bandwidth: None,
+            knock_gate: OnceLock::new(),
+            shutdown: watch::Sender::new(false),
+            prebound: Mutex::new(None),
+            audit: None,
+            audit_file: None,
+            started_at: Instant::now(),
+            origin_filter: None,
+            port_policy: PortPolicy::new(),
+            decision_tracing: false,
+            warning_window: DEFAULT_WINDOW,
+            http_forward: false,
+            socks4: true,
+            last_port: port.unwrap_or(DEFAULT_PORT),
+            #[cfg(feature = "natpmp")]
+            port_mapper: None,
+            max_connections: None,
+            overflow: OverflowMode::Reject,
+            handshake_rate: None,
+            acl: AclRules::new(),
+            blocklists: None,
+            events: None,
+            slo: None,
+            recent: None,
+            active: None,
+            accounting: None,
+            hooks: None,
+            watchdog: Watchdog::new(),
+            reputation: None,
+            remote: None,
+            tls: None,
+            tls_handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            alpn: Vec::new(),
+            cluster: None,
+        }
+    }
+
+    /// Creates a server that accepts on an already bound listener
+    ///
+    /// The listener is used by [`Server::bind`] and [`Server::run`] instead
+    /// of binding the configured address, e.g. for sockets inherited through
+    /// socket activation. Credentials can be set with
+    /// [`Server::with_credentials`].
+    ///
+    /// # Arguments
+    /// * `listener` - The bound listener to accept connections on
+    ///
+    /// # Returns
+    /// * `Ok(Server)` - A server reporting the listener's address
+    /// * `Err(Socks5Error)` - If the listener's address cannot be read
+    pub fn from_listener(listener: TcpListener) -> Socks5Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let server = Self::new(local_addr.ip().to_string(), Some(local_addr.port()), None, None);
+        *server.prebound.lock().unwrap_or_else(|e| e.into_inner()) = Some(listener);
+        Ok(server)
+    }
+
+    /// Requires clients to authenticate with a username and password
+    ///
+    /// The account is added to any configured before, so clients may
+    /// authenticate as either.
+    ///
+    /// # Arguments
+    /// * `username` - The expected username
+    /// * `password` - The expected password
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_credentials(mut self, username: String, password: String) -> Self {
+        self.users.insert(&username, &password);
+        self
+    }
+
+    /// Requires clients to authenticate as one of several accounts
+    ///
+    /// The accounts are added to any configured before.
+    ///
+    /// # Arguments
+    /// * `users` - The accounts clients may authenticate as
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_users(mut self, users: UserTable) -> Self {
+        self.users.extend(&users);
+        self
+    }
+
+    /// Returns the accounts clients must authenticate as
+    pub fn users(&self) -> &UserTable {
+        &self.users
+    }
+
+    /// Checks client credentials with a custom authenticator
+    ///
+    /// The authenticator replaces the configured accounts, e.g. to look
+    /// clients up in an existing user database.
+    ///
+    /// # Arguments
+    /// * `authenticator` - The check for username/password credentials
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Bounds user-provided hooks, such as a custom authenticator, in time
+    ///
+    /// A hook that overruns its budget is abandoned, counted in the
+    /// metrics, and the request is allowed or refused as its fail mode
+    /// says. Without this every hook gets ten seconds and fails closed.
+    ///
+    /// # Arguments
+    /// * `watchdog` - The time budget of each hook
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    /// Returns the time budgets of user-provided hooks
+    pub fn watchdog(&self) -> &Watchdog {
+        &self.watchdog
+    }
+
+    /// Returns the check for client credentials, if authentication is required
+    ///
+    /// This is the custom authenticator if one is set, otherwise the
+    /// accounts if there are any.
+    pub fn authenticator(&self) -> Option<Arc<dyn Authenticator>> {
+        match &self.authenticator {
+            Some(authenticator) => Some(Arc::clone(authenticator)),
+            None if !self.users.is_empty() => Some(Arc::new(self.users.clone())),
+            None => None,
+        }
+    }
+
+    /// Returns the check for client credentials as connections use it
+    ///
+    /// A custom authenticator runs within the watchdog's budget; the
+    /// configured accounts are checked in memory and need none.
+    fn watched_authenticator(&self) -> Option<Arc<dyn Authenticator>> {
+        match &self.authenticator {
+            Some(authenticator) => Some(Arc::new(WatchedAuthenticator::new(
+                Arc::clone(authenticator),
+                self.watchdog,
+                Arc::clone(&self.metrics),
+            ))),
+            None => self.authenticator(),
+        }
+    }
+
+    /// Returns the connector as connections use it, bounding connect attempts
+    /// by the server's deadline unless the connector has its own
+    fn connector_with_timeout(&self) -> Connector {
+        match self.timeouts.connect {
+            Some(timeout) if self.connector.connect_timeout().is_none() => self.connector.clone().with_connect_timeout(timeout),
+            _ => self.connector.clone(),
+        }
+    }
+
+    /// Returns the upstreams as connections use them, bounding each chain
+    /// by the server's connect deadline unless the upstreams have their own
+    fn upstreams_with_timeout(&self) -> Option<Upstreams> {
+        let upstreams = self.upstreams.clone()?;
+        match self.timeouts.connect {
+            Some(timeout) if upstreams.connect_timeout().is_none() => Some(upstreams.with_connect_timeout(timeout)),
+            _ => Some(upstreams),
+        }
+    }
+
+    /// Returns the relay options as connections use them, bounding idle
+    /// relays by the server's deadline unless the options have their own
+    fn relay_options_with_timeout(&self) -> RelayOptions {
+        match self.timeouts.idle {
+            Some(timeout) if self.relay_options.idle_timeout().is_none() => self.relay_options.clone().with_idle_timeout(timeout),
+            _ => self.relay_options.clone(),
+        }
+    }
+
+    /// Returns the session hooks as connections use them, within the watchdog's budget
+    fn watched_session_hooks(&self) -> Option<Arc<dyn SessionHooks>> {
+        self.hooks.as_ref().map(|hooks| {
+            Arc::new(WatchedSessionHooks::new(Arc::clone(hooks), self.watchdog, Arc::clone(&self.metrics))) as Arc<dyn SessionHooks>
+        })
+    }
+
+    /// Replaces the time source used by the server
+    ///
+    /// # Arguments
+    /// * `clock` - The clock to use for timeouts, rate limits and expiry
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Replaces the random source used by the server
+    ///
+    /// # Arguments
+    /// * `rng` - The random source to use for connection IDs and randomized choices
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_rng(mut self, rng: Arc<dyn RandomSource>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Sets the deadline for the client's first byte
+    ///
+    /// Clients that connect but send nothing within this window are dropped
+    /// before any handshake work is done, which protects against
+    /// slowloris-style connection hoarding.
+    ///
+    /// # Arguments
+    /// * `timeout` - The maximum time to wait for the first byte
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_first_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.first_byte = Some(timeout);
+        self
+    }
+
+    /// Sets the deadline of every session phase at once
+    ///
+    /// Without this the handshake, each target connect attempt and idle
+    /// relays are bounded by the [`timeouts`](crate::timeouts) defaults. A
+    /// connect timeout or idle timeout set on the connector or the relay
+    /// options takes precedence.
+    ///
+    /// # Arguments
+    /// * `timeouts` - The deadlines; a phase set to `None` has none
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Routes all CONNECT requests through upstream SOCKS5 proxies
+    ///
+    /// # Arguments
+    /// * `upstreams` - The upstream proxies and how to choose between them
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_upstreams(mut self, upstreams: Upstreams) -> Self {
+        self.upstreams = Some(upstreams);
+        self
+    }
+
+    /// Replaces the settings used for direct connections to targets
+    ///
+    /// # Arguments
+    /// * `connector` - The outbound connection settings (e.g. NAT64)
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_connector(mut self, connector: Connector) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    /// Requires clients to complete a port knock sequence before connecting
+    ///
+    /// # Arguments
+    /// * `knock` - The knock ports and timing
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_knock(mut self, knock: KnockConfig) -> Self {
+        self.knock = Some(knock);
+        self
+    }
+
+    /// Rejects or rate-limits clients by country or ASN as they are accepted
+    ///
+    /// Filtered clients are closed without a byte, before any task is
+    /// spawned for them.
+    ///
+    /// # Arguments
+    /// * `filter` - The GeoIP database and origin rules
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_origin_filter(mut self, filter: OriginFilter) -> Self {
+        self.origin_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Requires clients to open with a pre-shared preamble
+    ///
+    /// Clients that do not send it never receive a single byte, which makes
+    /// the listener hard to identify as a SOCKS server by active probing.
+    ///
+    /// # Arguments
+    /// * `probe_resistance` - The preamble and how to treat mismatches
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_probe_resistance(mut self, probe_resistance: ProbeResistance) -> Self {
+        self.probe_resistance = Some(probe_resistance);
+        self
+    }
+
+    /// Restricts clients to an explicit list of `host:port` targets
+    ///
+    /// Any other target is refused with a NOT_ALLOWED reply.
+    ///
+    /// # Arguments
+    /// * `allowed_targets` - The only targets clients may connect to
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_allowed_targets(mut self, allowed_targets: TargetAllowList) -> Self {
+        self.allowed_targets = Some(allowed_targets);
+        self
+    }
+
+    /// Refuses targets denied by ordered allow and deny rules
+    ///
+    /// The first rule matching a target decides; targets matching no rule
+    /// are allowed. Refused requests get a NOT_ALLOWED reply.
+    ///
+    /// # Arguments
+    /// * `acl` - The rules, in evaluation order
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_acl(mut self, acl: AclRules) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    /// Refuses domains on imported hosts-file or adblock-style blocklists
+    ///
+    /// The lists are checked after the ACL rules, so an `allow` rule can
+    /// exempt a listed domain. Refused requests get a NOT_ALLOWED reply.
+    /// Refreshing the lists, e.g. with [`Blocklists::spawn_refresh`], applies
+    /// to the running server.
+    ///
+    /// # Arguments
+    /// * `blocklists` - The deny group of imported lists
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_blocklists(mut self, blocklists: Arc<Blocklists>) -> Self {
+        self.blocklists = Some(blocklists);
+        self
+    }
+
+    /// Refuses domain name targets so the proxy never performs DNS lookups
+    ///
+    /// Requests with ATYP=DOMAIN get an ADDRESS_TYPE_NOT_SUPPORTED reply,
+    /// forcing clients to resolve names themselves.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether only IP literal targets are accepted
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_ip_literals_only(mut self, enabled: bool) -> Self {
+        self.ip_literals_only = enabled;
+        self
+    }
+
+    /// Sets which target ports are refused
+    ///
+    /// Port 0 is always refused; the policy can refuse privileged ports too.
+    /// Refused requests get the port class's reply code and are counted.
+    ///
+    /// # Arguments
+    /// * `port_policy` - The ports to refuse
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_port_policy(mut self, port_policy: PortPolicy) -> Self {
+        self.port_policy = port_policy;
+        self
+    }
+
+    /// Sets how data is relayed between clients and targets
+    ///
+    /// # Arguments
+    /// * `relay_options` - The relay engine and its settings
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_relay_options(mut self, relay_options: RelayOptions) -> Self {
+        self.relay_options = relay_options;
+        self
+    }
+
+    /// Runs every policy in shadow mode
+    ///
+    /// Denials are still computed, logged and counted in
+    /// [`Metrics::shadow_denials`], but the request proceeds, so new policies
+    /// can be validated against live traffic before they are enforced. The
+    /// handshake rate limit, the restrictions of low reputation and the
+    /// per-user limits of a cluster are only reported as well.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether denials are only logged instead of enforced
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_shadow_mode(mut self, enabled: bool) -> Self {
+        self.shadow = enabled;
+        self
+    }
+
+    /// Records which policy rules each request passed or failed
+    ///
+    /// The rule chain, as given by [`Decision::trace`], is logged with the
+    /// connection ID and added to mirrored and audited request records, so
+    /// a denial can be explained from the logs alone.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether decisions are traced
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_decision_tracing(mut self, enabled: bool) -> Self {
+        self.decision_tracing = enabled;
+        self
+    }
+
+    /// Sets how long repeated handshake failures from one client are collapsed
+    ///
+    /// The first failure from a client IP is logged as usual; further ones
+    /// within the window are only counted and logged as a single summary,
+    /// so one broken or hostile client cannot flood the log.
+    ///
+    /// # Arguments
+    /// * `window` - The collapsing window; zero logs every failure
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_warning_window(mut self, window: Duration) -> Self {
+        self.warning_window = window;
+        self
+    }
+
+    /// Publishes every request to an external sink for security tooling
+    ///
+    /// # Arguments
+    /// * `mirror` - The sink to send request events to
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_request_mirror(mut self, mirror: RequestMirror) -> Self {
+        self.mirror = Some(Arc::new(mirror));
+        self
+    }
+
+    /// Sends an RFC 5424 audit record of every request to a syslog collector
+    ///
+    /// # Arguments
+    /// * `audit` - The collector to send audit records to
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_audit_log(mut self, audit: SyslogSink) -> Self {
+        self.audit = Some(Arc::new(audit));
+        self
+    }
+
+    /// Appends an audit record of every request to a rotating file
+    ///
+    /// # Arguments
+    /// * `audit_file` - The writer of the audit file
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_audit_file(mut self, audit_file: AuditWriter) -> Self {
+        self.audit_file = Some(Arc::new(audit_file));
+        self
+    }
+
+    /// Writes the milestones of every session as JSON lines
+    ///
+    /// Each session reports when it is accepted, authenticated, connected
+    /// to its target and closed, the last with its duration and byte counts.
+    ///
+    /// # Arguments
+    /// * `events` - The writer of the event stream
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_events(mut self, events: EventWriter) -> Self {
+        self.events = Some(Arc::new(events));
+        self
+    }
+
+    /// Watches how long connecting to targets takes against latency objectives
+    ///
+    /// Objectives breached repeatedly raise an alert that is logged, counted
+    /// in the stats and written to the event stream, if any.
+    ///
+    /// # Arguments
+    /// * `monitor` - The objectives and how many breaches within which window alert
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_slo_monitor(mut self, monitor: SloMonitor) -> Self {
+        self.slo = Some(Arc::new(monitor));
+        self
+    }
+
+    /// Keeps a record of each of the last `capacity` sessions in memory
+    ///
+    /// Records hold the client, username, target, duration, byte counts and
+    /// close reason, and are queried through [`Server::recent_sessions`].
+    ///
+    /// # Arguments
+    /// * `capacity` - How many records are kept; older ones are dropped
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_recent_sessions(mut self, capacity: usize) -> Self {
+        self.recent = Some(Arc::new(RecentSessions::new(capacity)));
+        self
+    }
+
+    /// Lists the sessions being handled so they can be inspected and
+    /// terminated while they run
+    ///
+    /// Each session shows its client, username, target, duration and the
+    /// bytes relayed so far, through [`Server::active_sessions`]. Terminated
+    /// sessions count as `terminated` closes.
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_active_sessions(mut self) -> Self {
+        self.active = Some(Arc::new(ActiveSessions::new()));
+        self
+    }
+
+    /// Counts the sessions and relayed bytes of authenticated users
+    ///
+    /// The registry may be shared with other servers; read it through
+    /// [`Server::traffic_accounting`] or the handle passed in.
+    ///
+    /// # Arguments
+    /// * `accounting` - The registry to count into
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_traffic_accounting(mut self, accounting: Arc<TrafficAccounting>) -> Self {
+        self.accounting = Some(accounting);
+        self
+    }
+
+    /// Registers callbacks at the milestones of every session
+    ///
+    /// Each callback runs within the watchdog's `session_hooks` budget; the
+    /// connection task waits for it.
+    ///
+    /// # Arguments
+    /// * `hooks` - The callbacks to call
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_session_hooks(mut self, hooks: Arc<dyn SessionHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Appends a short reason to denial replies for clients that offered the
+    /// private [`auth::DENIAL_REASONS`](crate::constants::auth::DENIAL_REASONS)
+    /// method, e.g. `allow-list: target is not allowed`
+    ///
+    /// Standard clients never see the extra bytes.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether reasons are sent to clients that ask for them
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_denial_reasons(mut self, enabled: bool) -> Self {
+        if !enabled {
+            self.extensions.unregister(DenialReasons.name());
+        } else if let Err(e) = self.extensions.register(Arc::new(DenialReasons)) {
+            log::warn!("Denial reasons stay disabled: {}", e);
+        }
+        self
+    }
+
+    /// Sets the wire extensions clients may negotiate
+    ///
+    /// Replaces the extensions registered so far, including denial reasons.
+    /// Clients ask for an extension by offering its private method in their
+    /// greeting; the extensions they asked for are in effect for the rest of
+    /// the connection.
+    ///
+    /// # Arguments
+    /// * `extensions` - The supported extensions
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_extensions(mut self, extensions: ExtensionRegistry) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Serves SOCKS4 and SOCKS4a CONNECT requests alongside SOCKS5
+    ///
+    /// Enabled by default. The protocol is told apart by the client's first
+    /// byte; SOCKS4 clients are refused while credentials are required.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether SOCKS4 requests are served
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_socks4(mut self, enabled: bool) -> Self {
+        self.socks4 = enabled;
+        self
+    }
+
+    /// Forwards plain HTTP requests with an absolute URI
+    ///
+    /// Clients that open with an HTTP request line such as
+    /// `GET http://example.com/ HTTP/1.0` instead of a SOCKS greeting get
+    /// the request rewritten and forwarded to the target, one request per
+    /// connection. The same target policies apply; there is no
+    /// authentication, so the mode is refused while credentials are required.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether HTTP requests are forwarded
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_http_forward(mut self, enabled: bool) -> Self {
+        self.http_forward = enabled;
+        self
+    }
+
+    /// Tolerates clients that send RFC 1929 credentials after NO_AUTH was
+    /// selected, instead of failing on a malformed request
+    ///
+    /// When credentials are configured, clients offering only NO_AUTH are
+    /// then allowed in if their unsolicited credentials are valid.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether unsolicited credentials are accepted
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_unsolicited_credentials(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.compat.insert(Quirk::UnsolicitedCredentials);
+        } else {
+            self.compat.remove(Quirk::UnsolicitedCredentials);
+        }
+        self
+    }
+
+    /// Sets the known client misbehaviours to tolerate
+    ///
+    /// The default is the empty set, i.e. the strict protocol path. Each
+    /// time a client relies on a quirk it is counted in the metrics.
+    ///
+    /// # Arguments
+    /// * `compat` - The quirks to tolerate
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_compat(mut self, compat: Quirks) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Falls back to the following ports when the configured one is taken
+    ///
+    /// [`Server::bind`] tries each port from the configured one up to
+    /// `last_port` until binding succeeds; [`Server::local_addr`] reports
+    /// the selected one. Useful on desktops and embedded devices where the
+    /// default port may already be in use.
+    ///
+    /// # Arguments
+    /// * `last_port` - The last port to try; ports below the configured one disable the fallback
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_port_fallback(mut self, last_port: u16) -> Self {
+        self.last_port = last_port;
+        self
+    }
+
+    /// Asks a NAT-PMP gateway to forward an external port to the listener
+    ///
+    /// The mapping is requested once the server is listening and renewed
+    /// while it serves; the external address and port are logged. Mapping
+    /// failures are logged and retried without stopping the server.
+    ///
+    /// # Arguments
+    /// * `mapper` - The gateway to request the mapping from
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    #[cfg(feature = "natpmp")]
+    pub fn with_port_mapping(mut self, mapper: PortMapper) -> Self {
+        self.port_mapper = Some(mapper);
+        self
+    }
+
+    /// Caps the number of client sessions handled at once
+    ///
+    /// Without a cap, a burst of connections spawns unbounded tasks and can
+    /// exhaust file descriptors. Connections beyond the cap are rejected
+    /// with NO ACCEPTABLE METHODS or left waiting in the listen backlog, and
+    /// count as `overloaded` closes when rejected.
+    ///
+    /// # Arguments
+    /// * `max` - The most sessions handled at once
+    /// * `overflow` - Whether further connections are rejected or queued
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_max_connections(mut self, max: usize, overflow: OverflowMode) -> Self {
+        self.max_connections = Some(max);
+        self.overflow = overflow;
+        self
+    }
+
+    /// Limits how many handshakes one client IP may start
+    ///
+    /// Connections from an address over its budget are dropped as they are
+    /// accepted and count as `rate_limited` closes, which keeps scanners
+    /// hammering the listener from tying up connection tasks.
+    ///
+    /// # Arguments
+    /// * `limit` - The handshakes allowed per interval and the burst
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_handshake_rate_limit(mut self, limit: HandshakeRateLimit) -> Self {
+        self.handshake_rate = Some(limit);
+        self
+    }
+
+    /// Scores client IPs by their offenses and restricts the worst ones
+    ///
+    /// Failed authentications, protocol violations, denied requests and
+    /// failed connects raise a source's score. Throttled sources over their
+    /// limit are dropped as `rate_limited` closes; sources that must
+    /// authenticate on a server without accounts are refused as
+    /// `low_reputation` closes.
+    ///
+    /// # Arguments
+    /// * `reputation` - The scores and what low reputation costs
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_reputation(mut self, reputation: Reputation) -> Self {
+        self.reputation = Some(Arc::new(reputation));
+        self
+    }
+
+    /// Replaces the ACL, allow-list and accounts with remotely polled ones
+    ///
+    /// Requests use the settings of the last document the source applied,
+    /// and the server's own until there is one. Polling is up to the
+    /// caller, so one source can serve several servers.
+    ///
+    /// # Arguments
+    /// * `remote` - The source of the configuration document
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_remote_config(mut self, remote: Arc<RemoteConfig>) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// Limits users' open sessions and relayed bytes, fleet-wide if the
+    /// cluster gossips with peers
+    ///
+    /// Applies to authenticated sessions; over-limit requests are refused
+    /// with NOT_ALLOWED and closed as `user_limit`.
+    ///
+    /// # Arguments
+    /// * `cluster` - The limits and the counters they are checked against
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_cluster(mut self, cluster: Arc<Cluster>) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Terminates TLS on accepted connections before serving SOCKS
+    ///
+    /// Applies when the server serves its TCP listener, through
+    /// [`Server::run`] or [`Server::serve_tcp`]; see
+    /// [`tls::load_server_config`](crate::tls::load_server_config).
+    ///
+    /// # Arguments
+    /// * `config` - The certificate and TLS settings clients connect with
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_tls(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Sets how long a client may take to complete the TLS handshake
+    ///
+    /// The first-byte timeout only starts once the handshake is done, so a
+    /// stalled TLS client and a stalled SOCKS client are timed and counted
+    /// separately: the former as `tls_handshake_timeouts`, the latter as
+    /// `first_byte_timeout` closes.
+    ///
+    /// # Arguments
+    /// * `timeout` - The deadline for the TLS handshake
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_tls_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.tls_handshake_timeout = timeout;
+        self
+    }
+
+    /// Serves another protocol on the TLS port, chosen by the client through ALPN
+    ///
+    /// Clients negotiating `protocol` are handed to `handler`; those that
+    /// negotiate `socks5` or nothing are proxied as before. Only applies
+    /// with [`Server::with_tls`]; see
+    /// [`TlsListener::with_alpn_handler`](crate::tls::TlsListener::with_alpn_handler).
+    ///
+    /// # Arguments
+    /// * `protocol` - The ALPN protocol ID, e.g. `http/1.1`
+    /// * `handler` - Serves the connections negotiating it
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_alpn_handler(mut self, protocol: impl Into<Vec<u8>>, handler: Arc<dyn AlpnHandler>) -> Self {
+        self.alpn.push((protocol.into(), handler));
+        self
+    }
+
+    /// Sets the backoff applied while `accept()` keeps failing
+    ///
+    /// # Arguments
+    /// * `backoff` - The initial and maximum pause
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_accept_backoff(mut self, backoff: AcceptBackoff) -> Self {
+        self.accept_backoff = backoff;
+        self
+    }
+
+    /// Sets every how many accepted connections the accept loop is timed
+    ///
+    /// Sampled connections record how long they took from accept to task
+    /// spawn, how long they waited for a free session, and how full the
+    /// accept queue was, reported in [`ListenerStats::accept`].
+    ///
+    /// # Arguments
+    /// * `every` - Time one in this many connections; 1 times all of them
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_accept_sampling(mut self, every: u32) -> Self {
+        self.accept_sample_every = every.max(1);
+        self
+    }
+
+    /// Limits the bandwidth of relayed traffic
+    ///
+    /// Connections that get a throttle are relayed by the full engine, as
+    /// the lean engine cannot apply one.
+    ///
+    /// # Arguments
+    /// * `bandwidth` - The global, per-user and per-rule limits
+    ///
+    /// # Returns
+    /// * The updated Server instance
+    pub fn with_bandwidth(mut self, bandwidth: BandwidthPolicy) -> Self {
+        self.bandwidth = Some(Arc::new(bandwidth));
+        self
+    }
+
+    /// Returns the server's bind address
+    pub fn bind_addr(&self) -> &str {
+        &self.bind_addr
+    }
+
+    /// Returns the server's port
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the address the server is listening on, once bound
+    ///
+    /// Unlike [`Server::addr`], this reports the port actually selected,
+    /// e.g. an OS-assigned one or a fallback port.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr.get().copied()
+    }
+
+    /// Returns the most client sessions handled at once, if limited
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Returns what happens to connections beyond the limit
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.overflow
+    }
+
+    /// Returns how many handshakes one client IP may start, if limited
+    pub fn handshake_rate_limit(&self) -> Option<HandshakeRateLimit> {
+        self.handshake_rate
+    }
+
+    /// Returns the penalty scores of client IPs, if reputation is tracked
+    pub fn reputation(&self) -> Option<&Reputation> {
+        self.reputation.as_deref()
+    }
+
+    /// Returns the per-user limits shared with the fleet, if any
+    pub fn cluster(&self) -> Option<&Arc<Cluster>> {
+        self.cluster.as_ref()
+    }
+
+    /// Returns the TLS configuration clients connect with, if TLS is terminated
+    pub fn tls(&self) -> Option<&Arc<rustls::ServerConfig>> {
+        self.tls.as_ref()
+    }
+
+    /// Returns how long a client may take to complete the TLS handshake
+    pub fn tls_handshake_timeout(&self) -> Duration {
+        self.tls_handshake_timeout
+    }
+
+    /// Returns the source of remotely polled settings, if any
+    pub fn remote_config(&self) -> Option<&Arc<RemoteConfig>> {
+        self.remote.as_ref()
+    }
+
+    /// Returns the last port tried when the configured one is taken
+    pub fn last_port(&self) -> u16 {
+        self.last_port.max(self.port)
+    }
+
+    /// Returns the gateway asked to forward an external port, if any
+    #[cfg(feature = "natpmp")]
+    pub fn port_mapper(&self) -> Option<&PortMapper> {
+        self.port_mapper.as_ref()
+    }
+
+    /// Returns the time source used by the server
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Returns the random source used by the server
+    pub fn rng(&self) -> &Arc<dyn RandomSource> {
+        &self.rng
+    }
+
+    /// Returns the first-byte deadline, if any
+    pub fn first_byte_timeout(&self) -> Option<Duration> {
+        self.timeouts.first_byte
+    }
+
+    /// Returns the deadlines of the session phases
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// Returns the upstream proxies, if any
+    pub fn upstreams(&self) -> Option<&Upstreams> {
+        self.upstreams.as_ref()
+    }
+
+    /// Returns the settings used for direct connections to targets
+    pub fn connector(&self) -> &Connector {
+        &self.connector
+    }
+
+    /// Returns the port knock settings, if any
+    pub fn knock(&self) -> Option<&KnockConfig> {
+        self.knock.as_ref()
+    }
+
+    /// Returns the client origin filter, if any
+    pub fn origin_filter(&self) -> Option<&Arc<OriginFilter>> {
+        self.origin_filter.as_ref()
+    }
+
+    /// Returns the probe resistance settings, if any
+    pub fn probe_resistance(&self) -> Option<&ProbeResistance> {
+        self.probe_resistance.as_ref()
+    }
+
+    /// Returns the target allow-list, if any
+    pub fn allowed_targets(&self) -> Option<&TargetAllowList> {
+        self.allowed_targets.as_ref()
+    }
+
+    /// Returns whether only IP literal targets are accepted
+    pub fn ip_literals_only(&self) -> bool {
+        self.ip_literals_only
+    }
+
+    /// Returns the allow and deny rules for targets
+    pub fn acl(&self) -> &AclRules {
+        &self.acl
+    }
+
+    /// Returns which target ports are refused
+    pub fn port_policy(&self) -> &PortPolicy {
+        &self.port_policy
+    }
+
+    /// Returns whether every policy runs in shadow mode
+    pub fn shadow_mode(&self) -> bool {
+        self.shadow
+    }
+
+    /// Returns whether the policy rule chain of every request is recorded
+    pub fn decision_tracing(&self) -> bool {
+        self.decision_tracing
+    }
+
+    /// Returns how long repeated handshake failures from one client are collapsed
+    pub fn warning_window(&self) -> Duration {
+        self.warning_window
+    }
+
+    /// Returns the syslog audit collector, if any
+    pub fn audit_log(&self) -> Option<&Arc<SyslogSink>> {
+        self.audit.as_ref()
+    }
+
+    /// Returns the audit file writer, if any
+    pub fn audit_file(&self) -> Option<&Arc<AuditWriter>> {
+        self.audit_file.as_ref()
+    }
+
+    /// Returns the request mirror, if any
+    pub fn request_mirror(&self) -> Option<&Arc<RequestMirror>> {
+        self.mirror.as_ref()
+    }
+
+    /// Returns the writer of the session event stream, if any
+    pub fn events(&self) -> Option<&Arc<EventWriter>> {
+        self.events.as_ref()
+    }
+
+    /// Returns the records of the last sessions, if they are kept
+    pub fn recent_sessions(&self) -> Option<&RecentSessions> {
+        self.recent.as_deref()
+    }
+
+    /// Returns the sessions being handled, oldest first, if they are listed
+    pub fn active_sessions(&self) -> Option<Vec<SessionSnapshot>> {
+        self.active.as_ref().map(|active| active.list(self.clock.now()))
+    }
+
+    /// Returns the traffic counters by username, if they are kept
+    pub fn traffic_accounting(&self) -> Option<&Arc<TrafficAccounting>> {
+        self.accounting.as_ref()
+    }
+
+    /// Terminates the session with the given connection ID
+    ///
+    /// # Returns
+    /// * `true` if the session was active and is being closed; `false` if
+    ///   it is unknown or sessions are not listed
+    pub fn terminate_session(&self, conn_id: u32) -> bool {
+        self.active.as_ref().is_some_and(|active| active.terminate(conn_id))
+    }
+
+    /// Returns the connect latency objectives being watched, if any
+    pub fn slo_monitor(&self) -> Option<&SloMonitor> {
+        self.slo.as_deref()
+    }
+
+    /// Returns whether denial reasons are sent to clients that ask for them
+    pub fn denial_reasons(&self) -> bool {
+        self.extensions.get(DenialReasons.name()).is_some()
+    }
+
+    /// Returns the wire extensions clients may negotiate
+    pub fn extensions(&self) -> &ExtensionRegistry {
+        &self.extensions
+    }
+
+    /// Returns whether SOCKS4 and SOCKS4a requests are served
+    pub fn socks4(&self) -> bool {
+        self.socks4
+    }
+
+    /// Returns whether plain HTTP requests are forwarded
+    pub fn http_forward(&self) -> bool {
+        self.http_forward
+    }
+
+    /// Returns whether unsolicited credentials after NO_AUTH are tolerated
+    pub fn unsolicited_credentials(&self) -> bool {
+        self.compat.contains(Quirk::UnsolicitedCredentials)
+    }
+
+    /// Returns the tolerated client quirks
+    pub fn compat(&self) -> Quirks {
+        self.compat
+    }
+
+    /// Returns the accept loop's error backoff
+    pub fn accept_backoff(&self) -> AcceptBackoff {
+        self.accept_backoff
+    }
+
+    /// Returns every how many accepted connections the accept loop is timed
+    pub fn accept_sampling(&self) -> u32 {
+        self.accept_sample_every
+    }
+
+    /// Returns whether the listener is healthy, i.e. the last accept succeeded
+    ///
+    /// Flips to `false` while accept errors persist, so readiness checks can
+    /// take the listener out of rotation until it recovers.
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Relaxed)
+    }
+
+    /// Stops the server from accepting new connections
+    ///
+    /// [`Server::serve`] returns once it notices, stopping the knock and warm
+    /// refresh tasks it started. Connections already being handled run to
+    /// completion. A server that has been shut down stays shut down.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Returns whether [`Server::shutdown`] has been called
+    pub fn is_shut_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Returns the bandwidth limits, if any
+    pub fn bandwidth(&self) -> Option<&BandwidthPolicy> {
+        self.bandwidth.as_deref()
+    }
+
+    /// Returns the relay settings
+    pub fn relay_options(&self) -> &RelayOptions {
+        &self.relay_options
+    }
+
+    /// Returns the server's connection counters
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Returns the server's target policies as a standalone engine
+    ///
+    /// The engine evaluates targets exactly as the server does for live
+    /// requests, so it can pre-check destinations or test rule changes.
+    pub fn policy_engine(&self) -> PolicyEngine {
+        let mut engine = PolicyEngine::new()
+            .with_port_policy(self.port_policy.clone())
+            .with_ip_literals_only(self.ip_literals_only)
+            .with_acl(self.acl.clone())
+            .with_routes(self.connector.routes().clone())
+            .with_shadow_mode(self.shadow);
+        if let Some(allowed_targets) = &self.allowed_targets {
+            engine = engine.with_allowed_targets(allowed_targets.clone());
+        }
+        if let Some(blocklists) = &self.blocklists {
+            engine = engine.with_blocklists(Arc::clone(blocklists));
+        }
+        if let Some(cache) = self.connector.canonical_cache() {
+            engine = engine.with_canonical_cache(Arc::clone(cache));
+        }
+        engine
+    }
+
+    /// Returns what the server accepts with its current settings
+    pub fn capabilities(&self) -> Capabilities {
+        let mut versions = vec![5];
+        if self.socks4 || self.compat.contains(Quirk::Socks4Greeting) {
+            versions.push(4);
+        }
+        let mut address_types = vec!["ipv4"];
+        if !self.ip_literals_only {
+            address_types.push("domain");
+        }
+        address_types.push("ipv6");
+        let auth_methods = if self.authenticator.is_some() || !self.users.is_empty() {
+            vec!["username-password"]
+        } else {
+            vec!["no-auth"]
+        };
+        let mut extensions: Vec<&'static str> = self.extensions.names().collect();
+        if self.http_forward {
+            extensions.push("http-forward");
+        }
+        if self.connector.reply_mode() == ReplyMode::Optimistic {
+            extensions.push("optimistic-reply");
+        }
+        if self.probe_resistance.is_some() {
+            extensions.push("preamble");
+        }
+        if self.knock.is_some() {
+            extensions.push("port-knock");
+        }
+        if self.upstreams.is_some() {
+            extensions.push("upstream-chaining");
+        }
+        Capabilities {
+            versions,
+            commands: vec!["connect"],
+            address_types,
+            auth_methods,
+            extensions,
+            quirks: self.compat.iter().map(|quirk| quirk.as_str()).collect(),
+        }
+    }
+
+    /// Returns the label identifying this listener in stats and logs
     ///
-    /// # Returns
-    /// * A new Server instance
-    pub fn new(bind_addr: String, port: Option<u16>, username: Option<String>, password: Option<String>) -> Self {
-        Self {
-            bind_addr,
-            port: port.unwrap_or(DEFAULT_PORT),
-            username,
-            password,
+    /// The address is the bound one once [`Server::bind`] succeeded, so
+    /// port 0 is reported as the port actually chosen.
+    pub fn listener_label(&self) -> ListenerLabel {
+        ListenerLabel {
+            address: self.local_addr.get().map_or_else(|| self.addr(), SocketAddr::to_string),
+            protocol: "socks5".to_string(),
+            tls: self.tls.is_some(),
         }
     }
 
-    /// Returns the server's bind address
-    pub fn bind_addr(&self) -> &str {
-        &self.bind_addr
+    /// Returns a labelled snapshot of this listener's counters
+    pub fn stats(&self) -> ListenerStats {
+        let mut evictions = BTreeMap::new();
+        if let Some(gate) = self.knock_gate.get() {
+            evictions.insert("knock", gate.evictions());
+        }
+        if let Some(pool) = self.connector.egress() {
+            evictions.insert("egress", pool.evictions());
+        }
+        evictions.retain(|_, count| *count > 0);
+        ListenerStats {
+            accepting: self.is_accepting(),
+            evictions,
+            process: Some(ProcessStats::collect(self.uptime())),
+            ..self.metrics.snapshot(self.listener_label())
+        }
     }
 
-    /// Returns the server's port
-    pub fn port(&self) -> u16 {
-        self.port
+    /// Returns how long ago the server was created
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
     }
 
     /// Returns the server's bind address as a string
@@ -69,90 +1654,1201 @@ impl Server {
     /// * `Ok(())` - If the server starts and runs successfully
     /// * `Err(Socks5Error)` - If an error occurs during server operation
     pub async fn run(&self) -> Socks5Result<()> {
-        // Bind the TCP listener to the specified address and port
-        let listener = TcpListener::bind(self.addr()).await
-            .map_err(|e| Socks5Error::IoError(e))?;
+        let listener = self.bind().await?;
+        self.serve_tcp(listener).await
+    }
+
+    /// Serves clients on a bound TCP listener, over TLS if configured
+    ///
+    /// # Arguments
+    /// * `listener` - The listener returned by [`Server::bind`]
+    ///
+    /// # Returns
+    /// * `Ok(())` - Once the server has been shut down
+    /// * `Err(Socks5Error)` - If an error occurs during server operation
+    pub async fn serve_tcp(&self, listener: TcpListener) -> Socks5Result<()> {
+        match &self.tls {
+            Some(config) => {
+                let listener = self.alpn.iter().fold(
+                    TlsListener::new(listener, Arc::clone(config))
+                        .with_handshake_timeout(self.tls_handshake_timeout)
+                        .with_metrics(Arc::clone(&self.metrics)),
+                    |listener, (protocol, handler)| listener.with_alpn_handler(protocol.clone(), Arc::clone(handler)),
+                );
+                self.serve(listener).await
+            }
+            None => self.serve(listener).await,
+        }
+    }
+
+    /// Binds the TCP listener to the configured address and port
+    ///
+    /// Splitting binding from [`Server::serve`] lets callers learn the bound
+    /// address (e.g. to signal readiness) before connections are accepted.
+    /// A server created with [`Server::from_listener`] returns that listener
+    /// the first time instead. Taken ports are skipped up to the
+    /// [fallback](Server::with_port_fallback) port.
+    ///
+    /// # Returns
+    /// * `Ok(TcpListener)` - The bound listener
+    /// * `Err(Socks5Error)` - If binding fails
+    pub async fn bind(&self) -> Socks5Result<TcpListener> {
+        let prebound = self.prebound.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let listener = match prebound {
+            Some(listener) => listener,
+            None => self.bind_first_free().await?,
+        };
+        
+        let local_addr = listener.local_addr()?;
+        let _ = self.local_addr.set(local_addr);
+        log::info!("SOCKS5 proxy listening on {}", local_addr);
+        log::debug!("Capabilities on {}: {}", local_addr, self.capabilities());
+        Ok(listener)
+    }
+
+    /// Binds the first port from the configured one up to the fallback port
+    /// that is not taken
+    async fn bind_first_free(&self) -> Socks5Result<TcpListener> {
+        let mut port = self.port;
+        loop {
+            match TcpListener::bind(format!("{}:{}", self.bind_addr, port)).await {
+                Ok(listener) => return Ok(listener),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse && port != 0 && port < self.last_port => {
+                    log::warn!("Port {} on {} is taken, trying {}", port, self.bind_addr, port + 1);
+                    port += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    return Err(Socks5Error::IoError(portowner::addr_in_use(&self.bind_addr, self.port, port, e)));
+                }
+                Err(e) => return Err(Socks5Error::IoError(e)),
+            }
+        }
+    }
+
+    /// Accepts and handles client connections on an already bound listener
+    ///
+    /// Any [`Listener`] can be served: a `TcpListener` from [`Server::bind`],
+    /// a Unix socket or an in-memory listener. Runs until
+    /// [`Server::shutdown`] is called.
+    ///
+    /// # Arguments
+    /// * `listener` - The listener to accept connections from
+    ///
+    /// # Returns
+    /// * `Ok(())` - Once the server has been shut down
+    /// * `Err(Socks5Error)` - If an error occurs during server operation
+    pub async fn serve<L: Listener>(&self, mut listener: L) -> Socks5Result<()> {
+        if let Ok(local_addr) = listener.local_addr() {
+            let _ = self.local_addr.set(local_addr);
+        }
+        let context = Arc::new(ClientContext {
+            authenticator: self.watched_authenticator(),
+            timeouts: self.timeouts,
+            upstreams: self.upstreams_with_timeout(),
+            connector: self.connector_with_timeout(),
+            probe_resistance: self.probe_resistance.clone(),
+            policy: Arc::new(self.policy_engine()),
+            remote_policy: Mutex::new(None),
+            clock: Arc::clone(&self.clock),
+            metrics: Arc::clone(&self.metrics),
+            relay_options: self.relay_options_with_timeout(),
+            mirror: self.mirror.clone(),
+            extensions: self.extensions.clone(),
+            compat: self.compat,
+            bandwidth: self.bandwidth.clone(),
+            audit: self.audit.clone(),
+            audit_file: self.audit_file.clone(),
+            decision_tracing: self.decision_tracing,
+            handshake_failures: Arc::new(WarningAggregator::new(
+                "handshake failures",
+                self.warning_window,
+                Arc::clone(&self.clock),
+            )),
+            http_forward: self.http_forward,
+            socks4: self.socks4,
+            events: self.events.clone(),
+            slo: self.slo.clone(),
+            recent: self.recent.clone(),
+            active: self.active.clone(),
+            accounting: self.accounting.clone(),
+            hooks: self.watched_session_hooks(),
+            reputation: self.reputation.clone(),
+            remote: self.remote.clone(),
+            cluster: self.cluster.clone(),
+        });
+        let label = self.listener_label();
+        
+        let mut stop = self.shutdown.subscribe();
+        
+        // Background tasks live exactly as long as this call
+        let mut background = BackgroundTasks::default();
+        
+        // Keep hot destinations resolved and pre-connected
+        background.0.extend(self.connector.spawn_warm_refresh());
+        
+        // Pick up edits to the hosts file
+        background.0.extend(self.connector.spawn_hosts_reload());
         
-        log::info!("SOCKS5 proxy listening on {}", self.addr());
+        // Summarize collapsed handshake failures and rejections
+        background.0.extend(context.handshake_failures.spawn_flush());
+        let limit = self.max_connections.map(|max| Arc::new(Semaphore::new(max)));
+        let rejects = Arc::new(Semaphore::new(MAX_PENDING_REJECTS));
+        let overloaded = Arc::new(WarningAggregator::new(
+            "connections over the limit",
+            self.warning_window,
+            Arc::clone(&self.clock),
+        ));
+        background.0.extend(overloaded.spawn_flush());
+        let handshake_limiter = self.handshake_rate.map(|limit| SourceRateLimiter::new(limit, Arc::clone(&self.clock)));
+        let rate_limited = Arc::new(WarningAggregator::new(
+            "connections over the handshake rate",
+            self.warning_window,
+            Arc::clone(&self.clock),
+        ));
+        background.0.extend(rate_limited.spawn_flush());
         
-        // Loop indefinitely to accept incoming client connections
+        // Keep the listener reachable through the gateway
+        #[cfg(feature = "natpmp")]
+        if let (Some(mapper), Some(local_addr)) = (&self.port_mapper, self.local_addr.get()) {
+            background.0.push(mapper.spawn_renewal(local_addr.port()));
+        }
+        
+        // Start the knock listeners before accepting SOCKS connections
+        let knock_gate = match &self.knock {
+            Some(config) => {
+                let gate = Arc::new(KnockGate::new(config.clone(), Arc::clone(&self.clock)));
+                background.0.extend(gate.spawn_listeners(&self.bind_addr).await?);
+                let _ = self.knock_gate.set(Arc::clone(&gate));
+                Some(gate)
+            }
+            None => None,
+        };
+        
+        // Accept incoming client connections until shut down
+        let mut failures = 0u32;
+        let mut unsampled = 0u32;
         loop {
             // Accept a new client connection
-            let (client_stream, peer_addr) = match listener.accept().await {
-                Ok((stream, addr)) => (stream, addr),
+            let accepted = tokio::select! {
+                _ = stop.wait_for(|stopped| *stopped) => {
+                    log::info!("Stopped accepting on {}", label);
+                    return Ok(());
+                }
+                accepted = accept_within_limit(&mut listener, limit.as_ref(), self.overflow, self.clock.as_ref()) => accepted,
+            };
+            let accepted_at = self.clock.now();
+            let (accepted, queued, queue_wait) = accepted;
+            let (client_stream, peer_addr) = match accepted {
+                Ok((stream, addr)) => {
+                    if failures > 0 {
+                        log::info!("Accepting on {} again after {} failed attempts", label, failures);
+                        failures = 0;
+                        self.accepting.store(true, Ordering::Relaxed);
+                    }
+                    (stream, addr)
+                }
                 Err(e) => {
-                    log::error!("Error accepting connection: {}", e);
+                    failures = failures.saturating_add(1);
+                    self.accepting.store(false, Ordering::Relaxed);
+                    let delay = self.accept_backoff.delay(failures);
+                    log::error!("Error accepting connection on {}: {} (retrying in {:?})", label, e, delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+            
+            // Silently drop sources that have not knocked
+            if let Some(gate) = &knock_gate {
+                if !gate.is_open(peer_addr.ip()) {
+                    log::debug!("Dropping connection from {:?}: knock required", peer_addr);
+                    self.metrics.record_accept();
+                    self.metrics.record_close(CloseReason::KnockRequired);
+                    continue;
+                }
+            }
+            
+            // Drop clients from filtered countries and networks
+            if let Some(filter) = &self.origin_filter {
+                let verdict = filter.check(peer_addr.ip());
+                if verdict != OriginVerdict::Allow {
+                    log::debug!("Dropping connection from {:?}: origin {:?}", peer_addr, verdict);
+                    self.metrics.record_accept();
+                    self.metrics.record_close(CloseReason::OriginFiltered);
                     continue;
                 }
+            }
+            
+            // Drop sources starting handshakes faster than allowed
+            if let Some(limiter) = &handshake_limiter {
+                if !limiter.check(peer_addr.ip()) {
+                    if self.shadow {
+                        if rate_limited.record(peer_addr.ip()) {
+                            log::warn!("Shadow mode: handshake rate exceeded by {:?}, accepting anyway", peer_addr);
+                        }
+                        self.metrics.record_shadow_denial();
+                    } else {
+                        if rate_limited.record(peer_addr.ip()) {
+                            log::warn!("Dropping connection from {:?}: handshake rate exceeded", peer_addr);
+                        }
+                        self.metrics.record_accept();
+                        self.metrics.record_close(CloseReason::RateLimited);
+                        continue;
+                    }
+                }
+            }
+            if let Some(reputation) = &self.reputation {
+                if !reputation.check(peer_addr.ip()) {
+                    if self.shadow {
+                        if rate_limited.record(peer_addr.ip()) {
+                            log::warn!("Shadow mode: {:?} is throttled for its low reputation, accepting anyway", peer_addr);
+                        }
+                        self.metrics.record_shadow_denial();
+                    } else {
+                        if rate_limited.record(peer_addr.ip()) {
+                            log::warn!("Dropping connection from {:?}: throttled for its low reputation", peer_addr);
+                        }
+                        self.metrics.record_accept();
+                        self.metrics.record_close(CloseReason::RateLimited);
+                        continue;
+                    }
+                }
+            }
+            
+            // Turn away connections beyond the limit
+            let permit = match (&limit, queued) {
+                (Some(limit), None) => match Arc::clone(limit).try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        if overloaded.record(peer_addr.ip()) {
+                            log::warn!("Rejecting connection from {:?}: {} sessions active", peer_addr, self.max_connections.unwrap_or_default());
+                        }
+                        self.metrics.record_accept();
+                        self.metrics.record_close(CloseReason::Overloaded);
+                        // A flood of rejections must not cost a task each
+                        if let Ok(permit) = Arc::clone(&rejects).try_acquire_owned() {
+                            tokio::spawn(async move {
+                                let _permit = permit;
+                                let mut client_stream = client_stream;
+                                let _ = tokio::time::timeout(REJECT_GREETING_TIMEOUT, reject_greeting(&mut client_stream)).await;
+                            });
+                        }
+                        continue;
+                    }
+                },
+                (_, queued) => queued,
             };
             
-            log::info!("New client connected from: {:?}", peer_addr);
+            // Tag the connection with a random ID so its log lines can be correlated
+            let conn_id = self.rng.next_u64() as u32;
+            log::info!(
+                event = "accepted", conn_id:% = ConnId(conn_id), client:% = peer_addr, listener = label.address.as_str();
+                "New client connected from: {:?} (conn {:08x}) on {}", peer_addr, conn_id, label
+            );
+            if let Some(events) = &context.events {
+                events.publish(&SessionEvent::Accepted {
+                    conn_id: format!("{:08x}", conn_id),
+                    timestamp_ms: unix_millis(self.clock.wall_time()),
+                    client: peer_addr,
+                    listener: label.address.clone(),
+                });
+            }
+            // Records the close even if the task panics or is dropped
+            let guard = self.metrics.track();
+            unsampled += 1;
+            if unsampled >= self.accept_sample_every {
+                unsampled = 0;
+                let delay = self.clock.now().saturating_duration_since(accepted_at);
+                self.metrics.record_accept_sample(delay, queue_wait, listener.backlog());
+            }
             
-            // Clone username and password to avoid lifetime issues
-            let username_clone = self.username.clone();
-            let password_clone = self.password.clone();
+            let context = Arc::clone(&context);
+            let label = label.clone();
             
             // Spawn a new task to handle the client
             tokio::spawn(async move {
-                // Convert Option<String> to Option<&str>
-                let username_ref = username_clone.as_deref();
-                let password_ref = password_clone.as_deref();
-                
-                if let Err(e) = handle_client(client_stream, peer_addr, username_ref, password_ref).await {
-                    log::error!("Error handling client {}: {}", peer_addr, e);
+                let started = context.clock.now();
+                let started_ms = unix_millis(context.clock.wall_time());
+                let mut session = SessionState {
+                    accounting: context.accounting.clone(),
+                    ..SessionState::default()
+                };
+                let registered = context
+                    .active
+                    .as_ref()
+                    .map(|active| active.register(conn_id, peer_addr, label.address.clone(), started, started_ms));
+                // A panic in the handler or a hook still closes the session below
+                let outcome = CatchUnwind(Box::pin(async {
+                    if let Some(hooks) = &context.hooks {
+                        hooks.on_accept(conn_id, peer_addr, &label.address).await;
+                    }
+                    match &registered {
+                        Some(handle) => {
+                            let live = Arc::clone(handle.session());
+                            session.live = Some(Arc::clone(&live));
+                            let client_stream = Counted::new(client_stream, Arc::clone(&live));
+                            let result = tokio::select! {
+                                result = handle_client(client_stream, peer_addr, conn_id, &context, &mut session) => result,
+                                _ = live.terminated() => {
+                                    log::info!("Terminated client {} (conn {:08x}) on {} on request", peer_addr, conn_id, label);
+                                    Ok(CloseReason::Terminated)
+                                }
+                            };
+                            if matches!(result, Ok(CloseReason::Terminated)) {
+                                session.relayed = RelayStats {
+                                    client_to_target: live.bytes_from_client(),
+                                    target_to_client: live.bytes_from_target(),
+                                    ..RelayStats::default()
+                                };
+                            }
+                            result
+                        }
+                        None => handle_client(client_stream, peer_addr, conn_id, &context, &mut session).await,
+                    }
+                }))
+                .await;
+                let result = match outcome {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        log::error!(
+                            event = "panic", conn_id:% = ConnId(conn_id), client:% = peer_addr;
+                            "Connection task for {} (conn {:08x}) on {} panicked: {}", peer_addr, conn_id, label, panic_message(&*panic)
+                        );
+                        context.metrics.record_panic();
+                        Ok(CloseReason::Panic)
+                    }
+                };
+                if let (Some(reputation), Some(offense)) = (&context.reputation, Offense::of_session(&result)) {
+                    let score = reputation.record(peer_addr.ip(), offense);
+                    log::debug!("Client {} scored {:.2} for {} (conn {:08x})", peer_addr.ip(), score, offense, conn_id);
+                }
+                let reason = match result {
+                    Ok(reason) => reason,
+                    Err(e @ Socks5Error::HandshakeError(_)) => {
+                        if context.handshake_failures.record(peer_addr.ip()) {
+                            log::error!(
+                                event = "error", conn_id:% = ConnId(conn_id), client:% = peer_addr, error:% = e;
+                                "Error handling client {} (conn {:08x}) on {}: {}", peer_addr, conn_id, label, e
+                            );
+                        }
+                        CloseReason::Error
+                    }
+                    Err(e) => {
+                        log::error!(
+                            event = "error", conn_id:% = ConnId(conn_id), client:% = peer_addr, error:% = e;
+                            "Error handling client {} (conn {:08x}) on {}: {}", peer_addr, conn_id, label, e
+                        );
+                        CloseReason::Error
+                    }
+                };
+                let duration = context.clock.now().saturating_duration_since(started);
+                session.account();
+                log::info!(
+                    event = "closed",
+                    conn_id:% = ConnId(conn_id),
+                    client:% = peer_addr,
+                    reason = reason.as_str(),
+                    duration_ms = duration.as_millis() as u64,
+                    bytes_from_client = session.relayed.client_to_target,
+                    bytes_from_target = session.relayed.target_to_client;
+                    "Session with {} (conn {:08x}) ended ({}) after {:?}: {} bytes from client, {} bytes from target",
+                    peer_addr, conn_id, reason.as_str(), duration, session.relayed.client_to_target, session.relayed.target_to_client
+                );
+                if let Some(events) = &context.events {
+                    events.publish(&SessionEvent::Closed {
+                        conn_id: format!("{:08x}", conn_id),
+                        timestamp_ms: unix_millis(context.clock.wall_time()),
+                        reason: reason.as_str(),
+                        duration_ms: duration.as_millis() as u64,
+                        bytes_from_client: session.relayed.client_to_target,
+                        bytes_from_target: session.relayed.target_to_client,
+                    });
                 }
+                if context.recent.is_some() || context.hooks.is_some() {
+                    let record = SessionRecord {
+                        conn_id,
+                        client: peer_addr,
+                        listener: label.address.clone(),
+                        username: session.username,
+                        target: session.target,
+                        started_ms,
+                        duration,
+                        reason: reason.as_str(),
+                        bytes_from_client: session.relayed.client_to_target,
+                        bytes_from_target: session.relayed.target_to_client,
+                    };
+                    if let Some(hooks) = &context.hooks {
+                        hooks.on_close(&record).await;
+                    }
+                    if let Some(recent) = &context.recent {
+                        recent.push(record);
+                    }
+                }
+                drop(registered);
+                guard.close(reason);
+                drop(permit);
             });
         }
     }
 }
 
+/// A connection ID as the hex shown in log lines, for structured log fields
+struct ConnId(u32);
+
+impl fmt::Display for ConnId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+/// Resolves to the panic instead of unwinding when the wrapped future panics
+///
+/// Lets the connection task report a session that panicked the same way as
+/// any other close, instead of only counting it in the metrics.
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = self.0.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+/// Returns the message a panic was raised with, when it has one
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&'static str>() {
+        Some(message) => message,
+        None => panic.downcast_ref::<String>().map_or("(no message)", String::as_str),
+    }
+}
+
+/// What a connection task learns about its session, reported when it closes
+#[derive(Debug, Default)]
+struct SessionState {
+    /// The authenticated username, once the handshake is done
+    username: Option<String>,
+    /// The requested target, once the request is read
+    target: Option<TargetAddr>,
+    /// The bytes relayed in each direction
+    relayed: RelayStats,
+    /// The session's entry in the active session registry, if one is kept
+    live: Option<Arc<ActiveSession>>,
+    /// The registry the session's traffic is counted in, once authenticated
+    accounting: Option<Arc<TrafficAccounting>>,
+}
+
+impl SessionState {
+    /// Records the authenticated username
+    fn set_username(&mut self, username: Option<String>) {
+        if let Some(live) = &self.live {
+            live.set_username(username.clone());
+        }
+        if let (Some(accounting), Some(username)) = (&self.accounting, &username) {
+            accounting.open_session(username);
+        }
+        self.username = username;
+    }
+
+    /// Adds the session's relayed bytes to its user's traffic, if accounted
+    fn account(&self) {
+        if let (Some(accounting), Some(username)) = (&self.accounting, &self.username) {
+            accounting.close_session(username, &self.relayed);
+        }
+    }
+
+    /// Records the requested target
+    fn set_target(&mut self, target: TargetAddr) {
+        if let Some(live) = &self.live {
+            live.set_target(target.clone());
+        }
+        self.target = Some(target);
+    }
+}
+
+/// Accepts the next connection, first waiting for a free session when
+/// connections beyond the limit are queued
+///
+/// # Returns
+/// * The accept result and, when queueing, the session permit it waited for
+///   and how long that took
+async fn accept_within_limit<L: Listener>(
+    listener: &mut L,
+    limit: Option<&Arc<Semaphore>>,
+    overflow: OverflowMode,
+    clock: &dyn Clock,
+) -> (io::Result<(L::Stream, SocketAddr)>, Option<OwnedSemaphorePermit>, Option<Duration>) {
+    let (permit, waited) = match (limit, overflow) {
+        (Some(limit), OverflowMode::Queue) => {
+            let started = clock.now();
+            let permit = Arc::clone(limit).acquire_owned().await.ok();
+            (permit, Some(clock.now().saturating_duration_since(started)))
+        }
+        _ => (None, None),
+    };
+    (listener.accept().await, permit, waited)
+}
+
+/// Tasks started alongside a listener, aborted when it stops serving
+#[derive(Default)]
+struct BackgroundTasks(Vec<JoinHandle<()>>);
+
+impl Drop for BackgroundTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
 /// Handles a single client connection
 ///
 /// This function implements the SOCKS5 protocol flow:
-/// 1. Perform handshake
-/// 2. Process command request
-/// 3. Connect to target
-/// 4. Relay data between client and target
+/// 1. Wait for the client's first byte
+/// 2. Perform handshake
+/// 3. Process command request
+/// 4. Connect to target
+/// 5. Relay data between client and target
 ///
 /// # Arguments
-/// * `client_stream` - The TCP stream connected to the client
+/// * `client_stream` - The stream connected to the client
 /// * `peer_addr` - The client's socket address
-/// * `username` - Optional username for authentication
-/// * `password` - Optional password for authentication
+/// * `conn_id` - The random ID tagging the connection's log lines
+/// * `context` - Server settings shared with the connection task
+/// * `session` - Filled in with what the session learns, for reporting its close
 ///
 /// # Returns
-/// * `Ok(())` - If client handling completes successfully
+/// * `Ok(CloseReason)` - Why the connection was closed
 /// * `Err(Socks5Error)` - If an error occurs during client handling
-async fn handle_client(
-    mut client_stream: TcpStream, 
+async fn handle_client<S: ClientStream>(
+    mut client_stream: S,
     peer_addr: SocketAddr,
-    username: Option<&str>,
-    password: Option<&str>
-) -> Socks5Result<()> {
-    // Step 1: Perform SOCKS5 handshake
-    handshake(&mut client_stream, username, password).await?;
-    
-    if username.is_some() {
-        log::info!("SOCKS5 handshake with authentication successful with {:?}", peer_addr);
-    } else {
-        log::info!("SOCKS5 handshake successful with {:?}", peer_addr);
+    conn_id: u32,
+    context: &ClientContext,
+    session: &mut SessionState,
+) -> Socks5Result<CloseReason> {
+    // Clients get a fixed time to send their request, however they trickle it
+    let deadline = context.timeouts.handshake.map(|timeout| tokio::time::Instant::now() + timeout);
+    
+    // Step 1: Drop clients that connect but never send anything
+    if let Some(deadline) = context.timeouts.first_byte {
+        let mut first_byte = [0; 1];
+        if tokio::time::timeout(deadline, peek(&mut client_stream, &mut first_byte)).await.is_err() {
+            log::warn!("No data from {:?} within {:?}, closing connection", peer_addr, deadline);
+            return Ok(CloseReason::FirstByteTimeout);
+        }
+    }
+    
+    // Stay silent towards clients that do not know the preamble
+    if let Some(probe_resistance) = &context.probe_resistance {
+        let deadline = context.timeouts.first_byte.unwrap_or(DEFAULT_PREAMBLE_TIMEOUT);
+        if !probe_resistance.check(&mut client_stream, deadline).await {
+            log::debug!("Client {:?} did not send the expected preamble", peer_addr);
+            return Ok(CloseReason::ProbeRejected);
+        }
+    }
+    
+    // Make sources with low reputation authenticate, which an open proxy
+    // has no accounts for
+    let authenticator = context.authenticator();
+    if authenticator.is_none() && context.reputation.as_ref().is_some_and(|r| r.requires_auth(peer_addr.ip())) {
+        if context.policy().shadow_mode() {
+            log::warn!("Shadow mode: {:?} must authenticate for its low reputation, serving anyway", peer_addr);
+            context.metrics.record_shadow_denial();
+        } else {
+            log::debug!("Requiring authentication from {:?} for its reputation", peer_addr);
+            let _ = handshake_with_authenticator(&mut client_stream, Some(&NoAccounts), peer_addr, context.compat).await;
+            return Ok(CloseReason::LowReputation);
+        }
+    }
+    
+    // Legacy clients speaking SOCKS4 are served on their own path
+    let socks4_quirk = context.compat.contains(Quirk::Socks4Greeting);
+    if context.socks4 || socks4_quirk {
+        let Some(version) = within_handshake(deadline, peer_addr, peek_version(&mut client_stream)).await else {
+            return Ok(CloseReason::HandshakeTimeout);
+        };
+        if version? == Some(SOCKS4_VERSION) {
+            if socks4_quirk {
+                record_quirks(context, Quirks::new().with(Quirk::Socks4Greeting), peer_addr);
+            }
+            return handle_socks4_client(client_stream, peer_addr, conn_id, context, session, deadline).await;
+        }
+    }
+    
+    // So are clients sending plain HTTP requests to a forward proxy
+    if context.http_forward {
+        let mut first = [0; 1];
+        let Some(peeked) = within_handshake(deadline, peer_addr, peek(&mut client_stream, &mut first)).await else {
+            return Ok(CloseReason::HandshakeTimeout);
+        };
+        if peeked? == 1 && http::is_http_start(first[0]) {
+            return handle_http_client(client_stream, peer_addr, conn_id, context, session, deadline).await;
+        }
+    }
+    
+    // Step 2: Perform SOCKS5 handshake
+    let handshake = handshake_with_authenticator(&mut client_stream, authenticator.as_deref(), peer_addr, context.compat);
+    let Some(handshake_info) = within_handshake(deadline, peer_addr, handshake).await else {
+        return Ok(CloseReason::HandshakeTimeout);
+    };
+    let handshake_info = handshake_info?;
+    record_quirks(context, handshake_info.quirks, peer_addr);
+    session.set_username(handshake_info.username.clone());
+    
+    match &handshake_info.username {
+        Some(username) => log::info!(
+            event = "authenticated", conn_id:% = ConnId(conn_id), method = handshake_info.method, username = username.as_str();
+            "SOCKS5 handshake with authentication successful with {:?} as {}", peer_addr, username
+        ),
+        None => log::info!(
+            event = "authenticated", conn_id:% = ConnId(conn_id), method = handshake_info.method;
+            "SOCKS5 handshake successful with {:?}", peer_addr
+        ),
+    }
+    log::debug!("Client {:?} offered methods {:02x?}, selected {:#04x}",
+              peer_addr, handshake_info.offered_methods, handshake_info.method);
+    if let Some(events) = &context.events {
+        events.publish(&SessionEvent::Authenticated {
+            conn_id: format!("{:08x}", conn_id),
+            timestamp_ms: unix_millis(context.clock.wall_time()),
+            username: handshake_info.username.clone(),
+            method: handshake_info.method,
+        });
+    }
+    if let Some(hooks) = &context.hooks {
+        hooks.on_auth(conn_id, handshake_info.username.as_deref(), handshake_info.method).await;
+    }
+    let extensions = context.extensions.negotiate(&handshake_info.offered_methods);
+    if !extensions.is_empty() {
+        log::debug!("Client {:?} negotiated extensions {:?}", peer_addr, extensions.names());
+    }
+    
+    // Step 3: Process command request
+    let Some(request) = within_handshake(deadline, peer_addr, process_command_with_compat(&mut client_stream, context.compat)).await else {
+        return Ok(CloseReason::HandshakeTimeout);
+    };
+    let (target_addr, quirks) = request?;
+    record_quirks(context, quirks, peer_addr);
+    log::info!("Received request to connect to: {}", target_addr);
+    session.set_target(target_addr.clone());
+    if let Some(hooks) = &context.hooks {
+        hooks.on_request(conn_id, &target_addr, Some(&handshake_info)).await;
+    }
+    
+    let policy_context = PolicyContext {
+        client: Some(peer_addr),
+        username: handshake_info.username.as_deref(),
+    };
+    let decision = context.policy().evaluate(&policy_context, &target_addr);
+    let rewritten = &decision.rewritten;
+    publish_request(context, conn_id, peer_addr, "socks5", &target_addr, &decision, Some(&handshake_info));
+    
+    // Refuse targets the port policy, IP-literals-only mode, ACL rules or allow-list deny
+    if let Some(denial) = enforce_decision(context, &decision, &target_addr, peer_addr) {
+        let trailer = extensions.failure_trailer(denial.reply, &denial.reason);
+        send_failure_with_trailer(&mut client_stream, denial.reply, &trailer).await?;
+        return Ok(CloseReason::Denied);
+    }
+    
+    // Refuse users at their connection limit or out of quota
+    let lease = match admit_user(context, handshake_info.username.as_deref(), peer_addr) {
+        Ok(lease) => lease,
+        Err(reason) => {
+            send_failure(&mut client_stream, reply::NOT_ALLOWED).await?;
+            return Ok(reason);
+        }
+    };
+    
+    // Step 4: Connect to target server
+    let connect_started = context.clock.now();
+    let opened = match &context.upstreams {
+        Some(upstreams) => {
+            let upstream_target = rewritten.as_ref().unwrap_or(&target_addr);
+            connect_via_upstreams(&mut client_stream, upstream_target, upstreams).await
+        }
+        None => context.connector.connect(&mut client_stream, &target_addr).await,
+    };
+    report_connect_result(context, conn_id, &target_addr, connect_started, &opened).await;
+    let target_stream = match opened {
+        Ok(stream) => stream,
+        // The client was already told SUCCEEDED; all that is left is to hang up
+        Err(e) if context.upstreams.is_none() && context.connector.reply_mode() == ReplyMode::Optimistic => {
+            log::warn!("Optimistic connect for client {:?} failed: {}", peer_addr, e);
+            return Ok(CloseReason::OptimisticConnectFailed);
+        }
+        Err(e) => return Err(e),
+    };
+    
+    record_connect_latency(context, conn_id, &target_addr, connect_started);
+    
+    // Step 5: Relay data between client and target
+    publish_connected(context, conn_id, &target_addr);
+    let options = relay_options_for(context, handshake_info.username.as_deref(), &target_addr);
+    let (relayed, error) = Relay::new(peer_addr, target_addr.to_string())
+        .with_options(metered(context, options, lease.as_ref(), peer_addr))
+        .run(client_stream, target_stream)
+        .await;
+    session.relayed = relayed;
+    if let Some(e) = error {
+        return quota_close_reason(context, lease.as_ref(), peer_addr).ok_or(e);
+    }
+    
+    log::info!("Connection closed for client: {:?}", peer_addr);
+    Ok(relay_close_reason(&session.relayed))
+}
+
+/// Serves a SOCKS4 or SOCKS4a CONNECT request
+///
+/// The same target policies apply as for SOCKS5 requests; routes and
+/// upstreams are honoured, but there is no authentication and failures get
+/// the single SOCKS4 rejection code.
+async fn handle_socks4_client<S: ClientStream>(
+    mut client_stream: S,
+    peer_addr: SocketAddr,
+    conn_id: u32,
+    context: &ClientContext,
+    session: &mut SessionState,
+    deadline: Option<tokio::time::Instant>,
+) -> Socks5Result<CloseReason> {
+    let Some(request) = within_handshake(deadline, peer_addr, read_socks4_request(&mut client_stream)).await else {
+        return Ok(CloseReason::HandshakeTimeout);
+    };
+    let target_addr = request?;
+    session.set_target(target_addr.clone());
+    log::info!("Received SOCKS4 request from {:?} to connect to: {}", peer_addr, target_addr);
+    if let Some(hooks) = &context.hooks {
+        hooks.on_request(conn_id, &target_addr, None).await;
+    }
+    
+    if context.authenticator().is_some() {
+        log::warn!("SOCKS4 client {:?} refused: authentication is required", peer_addr);
+        send_socks4_reply(&mut client_stream, false).await?;
+        return Ok(CloseReason::Denied);
+    }
+    let policy_context = PolicyContext { client: Some(peer_addr), username: None };
+    let decision = context.policy().evaluate(&policy_context, &target_addr);
+    publish_request(context, conn_id, peer_addr, "socks4", &target_addr, &decision, None);
+    if enforce_decision(context, &decision, &target_addr, peer_addr).is_some() {
+        send_socks4_reply(&mut client_stream, false).await?;
+        return Ok(CloseReason::Denied);
+    }
+    let lease = match admit_user(context, None, peer_addr) {
+        Ok(lease) => lease,
+        Err(reason) => {
+            send_socks4_reply(&mut client_stream, false).await?;
+            return Ok(reason);
+        }
+    };
+    
+    let connect_started = context.clock.now();
+    let opened = match &context.upstreams {
+        Some(upstreams) => upstreams.connect(decision.rewritten.as_ref().unwrap_or(&target_addr)).await,
+        None => context.connector.open_for(Some(peer_addr.ip()), &target_addr).await.map_err(Socks5Error::from),
+    };
+    report_connect_result(context, conn_id, &target_addr, connect_started, &opened).await;
+    let target_stream = match opened {
+        Ok(stream) => stream,
+        Err(e) => {
+            send_socks4_reply(&mut client_stream, false).await?;
+            return Err(Socks5Error::ConnectionError(format!(
+                "Failed to connect to target {}: {}", target_addr, e
+            )));
+        }
+    };
+    record_connect_latency(context, conn_id, &target_addr, connect_started);
+    send_socks4_reply(&mut client_stream, true).await?;
+    
+    publish_connected(context, conn_id, &target_addr);
+    let options = relay_options_for(context, None, &target_addr);
+    let (relayed, error) = Relay::new(peer_addr, target_addr.to_string())
+        .with_options(metered(context, options, lease.as_ref(), peer_addr))
+        .run(client_stream, target_stream)
+        .await;
+    session.relayed = relayed;
+    if let Some(e) = error {
+        return quota_close_reason(context, lease.as_ref(), peer_addr).ok_or(e);
+    }
+    
+    log::info!("Connection closed for client: {:?}", peer_addr);
+    Ok(relay_close_reason(&session.relayed))
+}
+
+/// Forwards a plain HTTP request with an absolute URI to its target
+///
+/// The same target policies apply as for SOCKS5 requests; routes and
+/// upstreams are honoured. Failures are answered with an HTTP error status.
+async fn handle_http_client<S: ClientStream>(
+    mut client_stream: S,
+    peer_addr: SocketAddr,
+    conn_id: u32,
+    context: &ClientContext,
+    session: &mut SessionState,
+    deadline: Option<tokio::time::Instant>,
+) -> Socks5Result<CloseReason> {
+    let Some(request) = within_handshake(deadline, peer_addr, http::read_request(&mut client_stream)).await else {
+        return Ok(CloseReason::HandshakeTimeout);
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            http::send_error(&mut client_stream, 400, "Bad Request").await?;
+            return Err(e);
+        }
+    };
+    let target_addr = request.target.clone();
+    session.set_target(target_addr.clone());
+    log::info!("Received HTTP {} request from {:?} for: {}", request.method, peer_addr, target_addr);
+    if let Some(hooks) = &context.hooks {
+        hooks.on_request(conn_id, &target_addr, None).await;
     }
     
-    // Step 2: Process command request
-    let target_addr = process_command(&mut client_stream).await?;
-    log::info!("Received request to connect to: {}", target_addr.to_string());
+    if context.authenticator().is_some() {
+        log::warn!("HTTP client {:?} refused: authentication is required", peer_addr);
+        http::send_error(&mut client_stream, 403, "Forbidden").await?;
+        return Ok(CloseReason::Denied);
+    }
+    let policy_context = PolicyContext { client: Some(peer_addr), username: None };
+    let decision = context.policy().evaluate(&policy_context, &target_addr);
+    publish_request(context, conn_id, peer_addr, "http", &target_addr, &decision, None);
+    if enforce_decision(context, &decision, &target_addr, peer_addr).is_some() {
+        http::send_error(&mut client_stream, 403, "Forbidden").await?;
+        return Ok(CloseReason::Denied);
+    }
+    let lease = match admit_user(context, None, peer_addr) {
+        Ok(lease) => lease,
+        Err(reason) => {
+            http::send_error(&mut client_stream, 429, "Too Many Requests").await?;
+            return Ok(reason);
+        }
+    };
     
-    // Step 3: Connect to target server
-    let target_stream = connect_to_target(&mut client_stream, &target_addr).await?;
+    let connect_started = context.clock.now();
+    let opened = match &context.upstreams {
+        Some(upstreams) => upstreams.connect(decision.rewritten.as_ref().unwrap_or(&target_addr)).await,
+        None => context.connector.open_for(Some(peer_addr.ip()), &target_addr).await.map_err(Socks5Error::from),
+    };
+    report_connect_result(context, conn_id, &target_addr, connect_started, &opened).await;
+    let mut target_stream = match opened {
+        Ok(stream) => stream,
+        Err(e) => {
+            http::send_error(&mut client_stream, 502, "Bad Gateway").await?;
+            return Err(Socks5Error::ConnectionError(format!(
+                "Failed to connect to target {}: {}", target_addr, e
+            )));
+        }
+    };
+    record_connect_latency(context, conn_id, &target_addr, connect_started);
+    target_stream.write_all(&request.head).await?;
+    if let Some(lease) = &lease {
+        lease.add_bytes(request.head.len() as u64);
+    }
     
-    // Step 4: Relay data between client and target
-    relay_data(
-        client_stream,
-        peer_addr,
-        target_stream,
-        target_addr.to_string(),
-    ).await?;
+    publish_connected(context, conn_id, &target_addr);
+    let options = relay_options_for(context, None, &target_addr);
+    let (relayed, error) = Relay::new(peer_addr, target_addr.to_string())
+        .with_options(metered(context, options, lease.as_ref(), peer_addr))
+        .run(client_stream, target_stream)
+        .await;
+    session.relayed = relayed;
+    if let Some(e) = error {
+        return quota_close_reason(context, lease.as_ref(), peer_addr).ok_or(e);
+    }
     
     log::info!("Connection closed for client: {:?}", peer_addr);
-    Ok(())
-}
\ No newline at end of file
+    Ok(relay_close_reason(&session.relayed))
+}
+
+/// Runs a step of the client's handshake unless its deadline passes first
+///
+/// # Arguments
+/// * `deadline` - When the client must have sent its request, if ever
+/// * `peer_addr` - The client's address, for the log line
+/// * `step` - The read or write to run
+///
+/// # Returns
+/// * `Some(T)` - What the step returned
+/// * `None` - If the deadline passed first; the step is dropped
+async fn within_handshake<F: Future>(deadline: Option<tokio::time::Instant>, peer_addr: SocketAddr, step: F) -> Option<F::Output> {
+    let Some(deadline) = deadline else {
+        return Some(step.await);
+    };
+    let output = tokio::time::timeout_at(deadline, step).await.ok();
+    if output.is_none() {
+        log::warn!("Client {:?} did not send its request in time, closing connection", peer_addr);
+    }
+    output
+}
+
+/// Returns why a session that got as far as relaying ended
+fn relay_close_reason(relayed: &RelayStats) -> CloseReason {
+    if relayed.target_closed_early {
+        CloseReason::TargetClosedEarly
+    } else {
+        CloseReason::Completed
+    }
+}
+
+/// Logs that a session is connected to its target and reports it, if events are written
+fn publish_connected(context: &ClientContext, conn_id: u32, target_addr: &TargetAddr) {
+    log::info!(
+        event = "connected", conn_id:% = ConnId(conn_id), target:% = target_addr;
+        "Relaying conn {:08x} to {}", conn_id, target_addr
+    );
+    if let Some(events) = &context.events {
+        events.publish(&SessionEvent::Connected {
+            conn_id: format!("{:08x}", conn_id),
+            timestamp_ms: unix_millis(context.clock.wall_time()),
+            target: target_addr.to_string(),
+        });
+    }
+}
+
+/// Tells the session hooks, if any, whether connecting to a target succeeded
+async fn report_connect_result<T>(
+    context: &ClientContext,
+    conn_id: u32,
+    target_addr: &TargetAddr,
+    started: tokio::time::Instant,
+    opened: &Socks5Result<T>,
+) {
+    if let Some(hooks) = &context.hooks {
+        let latency = context.clock.now().saturating_duration_since(started);
+        hooks.on_connect_result(conn_id, target_addr, opened.as_ref().map(|_| latency)).await;
+    }
+}
+
+/// Checks how long connecting to a target took against its latency objective
+///
+/// An alert is logged, counted and reported as an event.
+fn record_connect_latency(context: &ClientContext, conn_id: u32, target_addr: &TargetAddr, started: tokio::time::Instant) {
+    let Some(slo) = &context.slo else {
+        return;
+    };
+    let latency = context.clock.now().saturating_duration_since(started);
+    let Some(alert) = slo.record(target_addr, latency) else {
+        return;
+    };
+    log::warn!("Latency objective alert for {}: {}", target_addr, alert);
+    context.metrics.record_slo_alert();
+    if let Some(events) = &context.events {
+        events.publish(&SessionEvent::SloBreached {
+            conn_id: format!("{:08x}", conn_id),
+            timestamp_ms: unix_millis(context.clock.wall_time()),
+            slo: alert.slo.to_string(),
+            target: target_addr.to_string(),
+            latency_ms: alert.latency.as_millis() as u64,
+            breaches: alert.breaches,
+            window_ms: alert.window.as_millis() as u64,
+        });
+    }
+}
+
+/// Returns the relay options for a connection, with its bandwidth throttles
+fn relay_options_for(context: &ClientContext, username: Option<&str>, target_addr: &TargetAddr) -> RelayOptions {
+    let mut options = context.relay_options.clone();
+    let Some(bandwidth) = &context.bandwidth else {
+        return options;
+    };
+    let throttles = bandwidth.throttles_for(username, target_addr);
+    log::debug!("Relaying {} in class {}", target_addr, throttles.priority);
+    context.metrics.record_priority(throttles.priority);
+    if let Some(upload) = throttles.upload {
+        options = options.with_engine(RelayEngine::Full).with_upload_throttle(upload);
+    }
+    if let Some(download) = throttles.download {
+        options = options.with_engine(RelayEngine::Full).with_download_throttle(download);
+    }
+    options
+}
+
+/// Counts and logs the compatibility quirks a client relied on
+fn record_quirks(context: &ClientContext, quirks: Quirks, peer_addr: SocketAddr) {
+    for quirk in quirks.iter() {
+        log::info!("Client {:?} relied on compatibility quirk {}", peer_addr, quirk);
+        context.metrics.record_quirk(quirk);
+    }
+}
+
+/// Logs the traced policy decision on a request and publishes the request
+/// to the mirror and audit sinks, whatever protocol it came in
+///
+/// # Arguments
+/// * `protocol` - The request's protocol: `socks5`, `socks4` or `http`
+/// * `handshake` - The SOCKS5 handshake, for SOCKS5 requests
+fn publish_request(
+    context: &ClientContext,
+    conn_id: u32,
+    peer_addr: SocketAddr,
+    protocol: &'static str,
+    target_addr: &TargetAddr,
+    decision: &Decision,
+    handshake: Option<&HandshakeInfo>,
+) {
+    if let Some(rewritten) = &decision.rewritten {
+        log::info!("Route rewrites target {} to {}", target_addr, rewritten);
+    }
+    let trace = context.decision_tracing.then(|| decision.trace());
+    if let Some(trace) = &trace {
+        log::info!("Policy decision for {} from {} client {:?} (conn {:08x}): {}", target_addr, protocol, peer_addr, conn_id, trace);
+    }
+    if context.mirror.is_none() && context.audit.is_none() && context.audit_file.is_none() {
+        return;
+    }
+    let event = RequestEvent {
+        conn_id: format!("{:08x}", conn_id),
+        timestamp_ms: unix_millis(context.clock.wall_time()),
+        client: peer_addr,
+        protocol,
+        target: target_addr.to_string(),
+        rewritten_target: decision.rewritten.as_ref().map(TargetAddr::to_string),
+        username: handshake.and_then(|handshake| handshake.username.clone()),
+        method: handshake.map_or(auth::NO_AUTH, |handshake| handshake.method),
+        offered_methods: handshake.map(|handshake| handshake.offered_methods.to_vec()).unwrap_or_default(),
+        decision: trace,
+    };
+    if let Some(mirror) = &context.mirror {
+        mirror.publish(&event);
+    }
+    if let Some(audit) = &context.audit {
+        audit.publish(&event);
+    }
+    if let Some(audit_file) = &context.audit_file {
+        audit_file.publish(&event);
+    }
+}
+
+/// Counts a session against its user's cluster-wide limits
+///
+/// In shadow mode users over their limits are logged, counted and let through.
+///
+/// # Returns
+/// * `Ok(Some(lease))` - The user's session, counted until the lease is dropped
+/// * `Ok(None)` - If there is no cluster or no user to count
+/// * `Err(CloseReason::UserLimit)` - If the user is over their limits and must be refused
+fn admit_user(context: &ClientContext, username: Option<&str>, peer_addr: SocketAddr) -> Result<Option<Arc<UserLease>>, CloseReason> {
+    let (Some(cluster), Some(username)) = (&context.cluster, username) else {
+        return Ok(None);
+    };
+    if let Some(lease) = cluster.admit(username) {
+        return Ok(Some(Arc::new(lease)));
+    }
+    if context.policy().shadow_mode() {
+        log::warn!("Shadow mode: user {} is over their limits ({:?}), client {:?} proceeds", username, cluster.usage(username), peer_addr);
+        context.metrics.record_shadow_denial();
+        return Ok(Some(Arc::new(cluster.open(username))));
+    }
+    log::warn!("User {} is over their limits ({:?}), refusing client {:?}", username, cluster.usage(username), peer_addr);
+    Err(CloseReason::UserLimit)
+}
+
+/// Charges the bytes of a session to its user's quota as they are read
+///
+/// The relay ends once the quota runs out, unless in shadow mode.
+fn metered(context: &ClientContext, options: RelayOptions, lease: Option<&Arc<UserLease>>, peer_addr: SocketAddr) -> RelayOptions {
+    let Some(lease) = lease else {
+        return options;
+    };
+    let meter: Arc<dyn Meter> = if context.policy().shadow_mode() {
+        Arc::new(ShadowMeter {
+            lease: Arc::clone(lease),
+            metrics: Arc::clone(&context.metrics),
+            peer_addr,
+            exhausted: AtomicBool::new(false),
+        })
+    } else {
+        Arc::clone(lease) as Arc<dyn Meter>
+    };
+    options.with_meter(meter)
+}
+
+/// Returns [`CloseReason::UserLimit`] if a failed relay was ended by its user running out of quota
+fn quota_close_reason(context: &ClientContext, lease: Option<&Arc<UserLease>>, peer_addr: SocketAddr) -> Option<CloseReason> {
+    let lease = lease?;
+    if context.policy().shadow_mode() || lease.has_bytes_left() {
+        return None;
+    }
+    log::warn!("User {} ran out of byte quota, closing client {:?}", lease.username(), peer_addr);
+    Some(CloseReason::UserLimit)
+}
+
+/// Charges a session's bytes to its user in shadow mode, never ending the relay
+///
+/// Running out of quota is logged and counted as a shadow denial once.
+#[derive(Debug)]
+struct ShadowMeter {
+    /// The user's session
+    lease: Arc<UserLease>,
+    /// Where the shadow denial is counted
+    metrics: Arc<Metrics>,
+    /// The client, for the log line
+    peer_addr: SocketAddr,
+    /// Whether the quota has run out already
+    exhausted: AtomicBool,
+}
+
+impl Meter for ShadowMeter {
+    fn charge(&self, bytes: u64) -> bool {
+        if !self.lease.charge(bytes) && !self.exhausted.swap(true, Ordering::Relaxed) {
+            log::warn!("Shadow mode: client {:?} ran out of byte quota, relay continues", self.peer_addr);
+            self.metrics.record_shadow_denial();
+        }
+        true
+    }
+}
+
+/// Turns away a SOCKS5 client before its session starts
+///
+/// The client's greeting is read and answered with NO ACCEPTABLE METHODS,
+/// the only refusal the protocol allows at that point.
+async fn reject_greeting<S: ClientStream>(stream: &mut S) -> std::io::Result<()> {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        return Ok(());
+    }
+    let mut methods = vec![0; usize::from(header[1])];
+    stream.read_exact(&mut methods).await?;
+    stream.write_all(&[SOCKS_VERSION, auth::NO_ACCEPTABLE_METHODS]).await?;
+    stream.shutdown().await
+}
+
+/// Logs and counts a policy decision, returning the denial to enforce
+///
+/// Denials in shadow mode are logged and counted instead, and the request
+/// goes ahead unless a later policy enforces its own.
+fn enforce_decision<'a>(
+    context: &ClientContext,
+    decision: &'a Decision,
+    target_addr: &TargetAddr,
+    peer_addr: SocketAddr,
+) -> Option<&'a Denial> {
+    let policy = context.policy();
+    // Names the ACL rule behind a denial, e.g. ` (rule #2: deny *.internal)`
+    let rule = |denial: &Denial| {
+        denial
+            .rule
+            .and_then(|index| Some((index, policy.acl().rules().get(index)?)))
+            .map(|(index, rule)| format!(" (rule #{}: {})", index, rule))
+            .unwrap_or_default()
+    };
+    for denial in decision.shadowed() {
+        log::warn!(
+            "Shadow mode: {}{} would deny {} for client {:?}",
+            denial.policy,
+            rule(denial),
+            target_addr,
+            peer_addr
+        );
+        context.metrics.record_shadow_denial();
+    }
+    let denial = decision.denial()?;
+    log::warn!("Target {} refused for client {:?}: {}{}", target_addr, peer_addr, denial.reason, rule(denial));
+    if let Some(class) = denial.port_class {
+        context.metrics.record_port_denial(class);
+    }
+    Some(denial)
+}
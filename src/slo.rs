@@ -0,0 +1,190 @@
+//! Connect latency objectives for the SOCKS5 proxy.
+//!
+//! Every CONNECT measures how long the target took to accept the
+//! connection, which makes the proxy a passive health monitor for the
+//! services behind it. A [`LatencySlo`] such as
+//! `*.payments.internal>300ms` names a destination and the connect latency
+//! it should stay under; [`SloMonitor`] counts the breaches of each
+//! objective and raises an [`SloAlert`] once enough of them fall into a
+//! sliding window, so a single slow handshake does not page anyone.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::error::Socks5Error;
+use crate::protocol::TargetAddr;
+use crate::routing::TargetPattern;
+
+/// How many breaches within the window raise an alert by default
+pub const DEFAULT_BREACHES: u32 = 3;
+
+/// How far back breaches are counted by default
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The connect latency destinations matching a pattern should stay under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencySlo {
+    /// The destinations the objective applies to
+    pub pattern: TargetPattern,
+    /// Connects taking longer than this are breaches
+    pub threshold: Duration,
+}
+
+impl FromStr for LatencySlo {
+    type Err = Socks5Error;
+
+    /// Parses `PATTERN>LATENCY`, e.g. `*.payments.internal>300ms`
+    ///
+    /// The latency is in milliseconds unless it ends in `s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Socks5Error::AddressError(format!("Invalid latency objective (expected PATTERN>LATENCY): {}", s));
+        let (pattern, latency) = s.rsplit_once('>').ok_or_else(invalid)?;
+        let latency = latency.trim();
+        let threshold = match latency.strip_suffix("ms") {
+            Some(ms) => ms.parse().map(Duration::from_millis),
+            None => match latency.strip_suffix('s') {
+                Some(secs) => secs.parse().map(Duration::from_secs),
+                None => latency.parse().map(Duration::from_millis),
+            },
+        }
+        .map_err(|_| invalid())?;
+        Ok(Self {
+            pattern: pattern.trim().parse()?,
+            threshold,
+        })
+    }
+}
+
+impl fmt::Display for LatencySlo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}>{}ms", self.pattern, self.threshold.as_millis())
+    }
+}
+
+/// An objective breached often enough to report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SloAlert {
+    /// The breached objective
+    pub slo: LatencySlo,
+    /// The latency of the connect that raised the alert
+    pub latency: Duration,
+    /// How many breaches fell into the window
+    pub breaches: u32,
+    /// How far back breaches were counted
+    pub window: Duration,
+}
+
+impl fmt::Display for SloAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} breached {} times within {:?}, last at {}ms",
+            self.slo, self.breaches, self.window, self.latency.as_millis()
+        )
+    }
+}
+
+/// Counts breaches of connect latency objectives
+#[derive(Debug)]
+pub struct SloMonitor {
+    /// The objectives; the first one matching a target applies
+    slos: Vec<LatencySlo>,
+    /// How many breaches within the window raise an alert
+    breaches: u32,
+    /// How far back breaches are counted
+    window: Duration,
+    /// Time source for the window
+    clock: Arc<dyn Clock>,
+    /// When the recent breaches of each objective happened
+    recent: Mutex<Vec<VecDeque<Instant>>>,
+    /// Alerts raised so far
+    alerts: AtomicU64,
+}
+
+impl SloMonitor {
+    /// Creates a monitor alerting on three breaches within a minute
+    pub fn new(slos: Vec<LatencySlo>, clock: Arc<dyn Clock>) -> Self {
+        let recent = Mutex::new(vec![VecDeque::new(); slos.len()]);
+        Self {
+            slos,
+            breaches: DEFAULT_BREACHES,
+            window: DEFAULT_WINDOW,
+            clock,
+            recent,
+            alerts: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets how many breaches within the window raise an alert; at least one
+    pub fn with_breaches(mut self, breaches: u32) -> Self {
+        self.breaches = breaches.max(1);
+        self
+    }
+
+    /// Sets how far back breaches are counted
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Returns the objectives
+    pub fn slos(&self) -> &[LatencySlo] {
+        &self.slos
+    }
+
+    /// Returns how many breaches within the window raise an alert
+    pub fn breaches(&self) -> u32 {
+        self.breaches
+    }
+
+    /// Returns how far back breaches are counted
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns how many alerts were raised
+    pub fn alerts(&self) -> u64 {
+        self.alerts.load(Ordering::Relaxed)
+    }
+
+    /// Records how long connecting to `target` took
+    ///
+    /// Once an alert is raised the objective's breaches are forgotten, so a
+    /// destination that stays slow raises one alert per full set of
+    /// breaches rather than one per connect.
+    ///
+    /// # Returns
+    /// * `Some(SloAlert)` - If this connect completed a set of breaches
+    /// * `None` - If the target has no objective or is within it
+    pub fn record(&self, target: &TargetAddr, latency: Duration) -> Option<SloAlert> {
+        let index = self.slos.iter().position(|slo| slo.pattern.matches(target))?;
+        let slo = &self.slos[index];
+        if latency <= slo.threshold {
+            return None;
+        }
+        let now = self.clock.now();
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let breaches = &mut recent[index];
+        while breaches.front().is_some_and(|at| now.saturating_duration_since(*at) >= self.window) {
+            breaches.pop_front();
+        }
+        breaches.push_back(now);
+        if breaches.len() < self.breaches as usize {
+            return None;
+        }
+        breaches.clear();
+        self.alerts.fetch_add(1, Ordering::Relaxed);
+        Some(SloAlert {
+            slo: slo.clone(),
+            latency,
+            breaches: self.breaches,
+            window: self.window,
+        })
+    }
+}
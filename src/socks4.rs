@@ -0,0 +1,82 @@
+//! SOCKS4 and SOCKS4a support for the SOCKS5 proxy.
+//!
+//! Legacy tooling (older curl builds, some game launchers) only speaks
+//! SOCKS4 or its SOCKS4a extension for host names. The server tells the
+//! protocols apart by the first byte of a connection and serves SOCKS4
+//! CONNECT requests alongside SOCKS5 ones, under the same target policies.
+//! SOCKS4 has no authentication, so it is refused while credentials are
+//! required.
+
+use std::net::Ipv4Addr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::protocol::{close_gracefully, TargetAddr};
+
+/// SOCKS4 protocol version byte
+pub const SOCKS4_VERSION: u8 = 0x04;
+
+/// SOCKS4 reply: request granted
+const SOCKS4_GRANTED: u8 = 0x5A;
+/// SOCKS4 reply: request rejected or failed
+const SOCKS4_REJECTED: u8 = 0x5B;
+
+/// Reads a SOCKS4 or SOCKS4a CONNECT request
+///
+/// The user ID is read and discarded. A destination IP of `0.0.0.x` with a
+/// non-zero `x` marks a SOCKS4a request whose host name follows the user ID.
+///
+/// # Returns
+/// * `Ok(TargetAddr)` - The requested destination
+/// * `Err(Socks5Error)` - If the request is malformed or not a CONNECT
+pub async fn read_socks4_request<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Socks5Result<TargetAddr> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header).await?;
+    if header[0] != SOCKS4_VERSION {
+        return Err(Socks5Error::HandshakeError(format!("Unsupported SOCKS version: {}", header[0])));
+    }
+    if header[1] != crate::constants::cmd::CONNECT {
+        send_socks4_reply(stream, false).await?;
+        return Err(Socks5Error::CommandError(format!("Unsupported SOCKS4 command: {}", header[1])));
+    }
+    let port = u16::from_be_bytes([header[2], header[3]]);
+    let ip = Ipv4Addr::new(header[4], header[5], header[6], header[7]);
+
+    read_null_terminated(stream, "user ID").await?;
+    let octets = ip.octets();
+    if octets[..3] == [0, 0, 0] && octets[3] != 0 {
+        let host = read_null_terminated(stream, "host name").await?;
+        let host = String::from_utf8(host)
+            .map_err(|e| Socks5Error::AddressError(format!("Invalid domain name: {}", e)))?;
+        Ok(TargetAddr::Domain(host, port))
+    } else {
+        Ok(TargetAddr::Ipv4(ip, port))
+    }
+}
+
+/// Sends a SOCKS4 reply granting or rejecting the request
+///
+/// After a rejection the connection is closed gracefully.
+pub async fn send_socks4_reply<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, granted: bool) -> Socks5Result<()> {
+    let status = if granted { SOCKS4_GRANTED } else { SOCKS4_REJECTED };
+    stream.write_all(&[0x00, status, 0, 0, 0, 0, 0, 0]).await?;
+    if !granted {
+        close_gracefully(stream).await;
+    }
+    Ok(())
+}
+
+/// Reads a NUL-terminated field of at most 255 bytes
+async fn read_null_terminated<S: AsyncRead + Unpin>(stream: &mut S, what: &str) -> Socks5Result<Vec<u8>> {
+    let mut field = Vec::new();
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == 0 {
+            return Ok(field);
+        }
+        if field.len() == 255 {
+            return Err(Socks5Error::HandshakeError(format!("SOCKS4 {} too long", what)));
+        }
+        field.push(byte);
+    }
+}
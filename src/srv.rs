@@ -0,0 +1,348 @@
+//! DNS SRV lookups for the SOCKS5 proxy.
+//!
+//! Routes can ask for a domain target to be resolved through its SRV
+//! records (RFC 2782), so internal services are reached on the host and
+//! port their records announce instead of the port the client asked for.
+//! The system resolver behind `lookup_host` only returns addresses, so SRV
+//! queries go straight to the nameservers of `/etc/resolv.conf` over UDP,
+//! and again over TCP when the answer did not fit.
+//!
+//! Responses must repeat the question asked, and only records owned by the
+//! name asked or an alias its CNAME records lead to are taken, so a
+//! spoofed or confused answer cannot slip in records for other names.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::random::{RandomSource, StdRandom};
+
+/// The SRV record type
+const TYPE_SRV: u16 = 33;
+
+/// The CNAME record type
+const TYPE_CNAME: u16 = 5;
+
+/// The Internet class
+const CLASS_IN: u16 = 1;
+
+/// Largest response read over UDP, as advertised by EDNS-less resolvers
+const MAX_RESPONSE: usize = 4096;
+
+/// How long each nameserver gets to answer by default
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One service location from an SRV record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    /// Lower values are tried first
+    pub priority: u16,
+    /// Relative share among records of the same priority
+    pub weight: u16,
+    /// The port the service listens on
+    pub port: u16,
+    /// The host providing the service, without trailing dot
+    pub target: String,
+}
+
+/// Sends SRV queries to a list of nameservers
+#[derive(Debug, Clone)]
+pub struct SrvResolver {
+    /// Nameservers asked in order until one answers
+    nameservers: Vec<SocketAddr>,
+    /// How long each nameserver gets to answer
+    timeout: Duration,
+    /// Source of query IDs and weighted ordering
+    rng: Arc<dyn RandomSource>,
+}
+
+impl SrvResolver {
+    /// Creates a resolver asking the given nameservers
+    pub fn new(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            timeout: DEFAULT_TIMEOUT,
+            rng: Arc::new(StdRandom::new()),
+        }
+    }
+
+    /// Creates a resolver asking the nameservers of `/etc/resolv.conf`
+    ///
+    /// Falls back to a resolver on localhost when the file lists none.
+    pub fn system() -> Self {
+        Self::new(system_nameservers())
+    }
+
+    /// Sets how long each nameserver gets to answer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the random source for query IDs and weighted ordering
+    pub fn with_random(mut self, rng: Arc<dyn RandomSource>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Returns the nameservers asked, in order
+    pub fn nameservers(&self) -> &[SocketAddr] {
+        &self.nameservers
+    }
+
+    /// Looks up the SRV records of `name`, e.g. `_postgres._tcp.db.internal`
+    ///
+    /// Records come back in the order to try them: by priority, and within
+    /// a priority in a random order weighted by their weights.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<SrvRecord>)` - The records; empty if the name has none
+    /// * `Err(io::Error)` - If no nameserver answered
+    pub async fn lookup(&self, name: &str) -> io::Result<Vec<SrvRecord>> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no nameservers configured");
+        for nameserver in &self.nameservers {
+            let id = self.rng.next_u64() as u16;
+            let query = encode_query(id, name, TYPE_SRV)?;
+            match tokio::time::timeout(self.timeout, exchange(*nameserver, &query)).await {
+                Ok(Ok(response)) => return Ok(self.order(parse_response(&query, &response)?)),
+                Ok(Err(e)) => last_error = e,
+                Err(_) => {
+                    last_error = io::Error::new(io::ErrorKind::TimedOut, format!("{} did not answer", nameserver));
+                }
+            }
+            log::debug!("SRV lookup of {} via {} failed: {}", name, nameserver, last_error);
+        }
+        Err(last_error)
+    }
+
+    /// Orders records by priority, then by weighted random selection
+    fn order(&self, mut records: Vec<SrvRecord>) -> Vec<SrvRecord> {
+        records.sort_by_key(|record| record.priority);
+        let mut ordered = Vec::with_capacity(records.len());
+        while !records.is_empty() {
+            let priority = records[0].priority;
+            let end = records.iter().take_while(|record| record.priority == priority).count();
+            let mut group: Vec<SrvRecord> = records.drain(..end).collect();
+            while !group.is_empty() {
+                let total: u64 = group.iter().map(|record| u64::from(record.weight) + 1).sum();
+                let mut pick = self.rng.below(total);
+                let index = group
+                    .iter()
+                    .position(|record| {
+                        let share = u64::from(record.weight) + 1;
+                        if pick < share {
+                            return true;
+                        }
+                        pick -= share;
+                        false
+                    })
+                    .unwrap_or(0);
+                ordered.push(group.remove(index));
+            }
+        }
+        ordered
+    }
+}
+
+/// Returns the nameservers of `/etc/resolv.conf`, or localhost if it lists none
+pub(crate) fn system_nameservers() -> Vec<SocketAddr> {
+    let mut nameservers: Vec<SocketAddr> = std::fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+    if nameservers.is_empty() {
+        nameservers.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53));
+    }
+    nameservers
+}
+
+/// Sends one query and waits for the matching response
+///
+/// A response with the truncation (TC) bit set is thrown away and the
+/// query is sent again over TCP.
+pub(crate) async fn exchange(nameserver: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let local: SocketAddr = if nameserver.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(nameserver).await?;
+    socket.send(query).await?;
+    let mut response = vec![0; MAX_RESPONSE];
+    loop {
+        let len = socket.recv(&mut response).await?;
+        // Ignore stray datagrams that do not answer this query
+        if len >= 3 && response[..2] == query[..2] {
+            if response[2] & 0x02 != 0 {
+                log::debug!("Truncated answer from {}, asking again over TCP", nameserver);
+                return exchange_tcp(nameserver, query).await;
+            }
+            response.truncate(len);
+            return Ok(response);
+        }
+    }
+}
+
+/// Sends one query over TCP, where answers are never truncated
+async fn exchange_tcp(nameserver: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(nameserver).await?;
+    // Messages over TCP are preceded by their length
+    let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+    let mut length = [0; 2];
+    stream.read_exact(&mut length).await?;
+    let mut response = vec![0; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// Encodes a recursive query for the `record_type` records of `name`
+pub(crate) fn encode_query(id: u16, name: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid DNS name: {}", name)));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Extracts the SRV records from a response to `query`
+fn parse_response(query: &[u8], packet: &[u8]) -> io::Result<Vec<SrvRecord>> {
+    let mut records = Vec::new();
+    for answer in answers(query, packet)? {
+        let data = answer.data;
+        // Skip CNAMEs and anything else that is not SRV
+        if answer.record_type != TYPE_SRV || data.len() < 7 {
+            continue;
+        }
+        let field = |at: usize| u16::from_be_bytes([packet[data.start + at], packet[data.start + at + 1]]);
+        records.push(SrvRecord {
+            priority: field(0),
+            weight: field(2),
+            port: field(4),
+            target: read_name(packet, data.start + 6)?.0,
+        });
+    }
+    // A target of "." means the service is decidedly not available
+    records.retain(|record| !record.target.is_empty());
+    Ok(records)
+}
+
+/// One resource record in the answer section of a response
+pub(crate) struct Answer {
+    /// The record type, e.g. 1 for A
+    pub(crate) record_type: u16,
+    /// How long the record may be cached
+    pub(crate) ttl: Duration,
+    /// Where the record data lies in the packet
+    pub(crate) data: Range<usize>,
+}
+
+/// Extracts the answer records from a response to `query`
+///
+/// The response must repeat the query's question. Answers owned by names
+/// other than the one asked and the aliases its CNAME records lead to are
+/// dropped. A response saying the name does not exist has no answers.
+pub(crate) fn answers(query: &[u8], packet: &[u8]) -> io::Result<Vec<Answer>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Malformed DNS response");
+    let header = packet.get(..12).ok_or_else(malformed)?;
+    if header[..2] != query[..2] || header[2] & 0x80 == 0 {
+        return Err(malformed());
+    }
+    let rcode = header[3] & 0x0F;
+    if rcode != 0 && rcode != 3 {
+        return Err(io::Error::other(format!("Nameserver failed the query (rcode {})", rcode)));
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+
+    // The question, compared case-insensitively as resolvers may change case
+    let (asked, asked_end) = read_name(query, 12)?;
+    let (question, mut offset) = read_name(packet, 12)?;
+    if questions != 1
+        || !question.eq_ignore_ascii_case(&asked)
+        || packet.get(offset..offset + 4) != query.get(asked_end..asked_end + 4)
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS response does not answer the question asked"));
+    }
+    offset += 4;
+    // NXDOMAIN: the name has no records at all
+    if rcode == 3 {
+        return Ok(Vec::new());
+    }
+
+    let mut owners = vec![asked];
+    let mut records = Vec::with_capacity(usize::from(answers));
+    for _ in 0..answers {
+        let (owner, end) = read_name(packet, offset)?;
+        offset = end;
+        let fixed = packet.get(offset..offset + 10).ok_or_else(malformed)?;
+        let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let length = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+        let data = offset + 10;
+        offset = data + length;
+        if offset > packet.len() {
+            return Err(malformed());
+        }
+        if !owners.iter().any(|name| name.eq_ignore_ascii_case(&owner)) {
+            log::debug!("Dropped a DNS answer for {}, which was not asked about", owner);
+            continue;
+        }
+        if record_type == TYPE_CNAME {
+            owners.push(read_name(packet, data)?.0);
+        }
+        records.push(Answer {
+            record_type,
+            ttl: Duration::from_secs(u64::from(ttl)),
+            data: data..offset,
+        });
+    }
+    Ok(records)
+}
+
+/// Reads a possibly compressed name at `offset`
+///
+/// # Returns
+/// * The name without trailing dot, and the offset just past it
+fn read_name(packet: &[u8], mut offset: usize) -> io::Result<(String, usize)> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "Malformed name in DNS response");
+    let mut name = String::new();
+    let mut end = None;
+    // Bounds pointer chains so a looping packet cannot hang the lookup
+    for _ in 0..128 {
+        let len = *packet.get(offset).ok_or_else(malformed)?;
+        match len {
+            0 => return Ok((name, end.unwrap_or(offset + 1))),
+            len if len & 0xC0 == 0xC0 => {
+                let low = *packet.get(offset + 1).ok_or_else(malformed)?;
+                end.get_or_insert(offset + 2);
+                offset = usize::from(len & 0x3F) << 8 | usize::from(low);
+            }
+            len if len < 64 => {
+                let label = packet.get(offset + 1..offset + 1 + usize::from(len)).ok_or_else(malformed)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&String::from_utf8_lossy(label));
+                offset += 1 + usize::from(len);
+            }
+            _ => return Err(malformed()),
+        }
+    }
+    Err(malformed())
+}
@@ -0,0 +1,283 @@
+//! Audit log delivery to syslog for the SOCKS5 proxy.
+//!
+//! Every accepted request can be shipped as an RFC 5424 syslog message over
+//! UDP, TCP or a Unix datagram socket (e.g. `/dev/log`), so session records
+//! reach a SIEM without a file tailing agent. The request's metadata travels
+//! as structured data next to a human-readable message.
+
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::mirror::RequestEvent;
+
+/// The `local0` facility, used unless configured otherwise
+pub const DEFAULT_FACILITY: u8 = 16;
+
+/// Severity of audit records: informational
+const SEVERITY_INFO: u8 = 6;
+
+/// Structured data ID of audit records; 32473 is the documentation
+/// enterprise number from RFC 5612
+const SD_ID: &str = "socks@32473";
+
+/// How many records may wait for a TCP collector before new ones are dropped
+const TCP_QUEUE: usize = 1024;
+
+/// Where audit records are sent
+#[derive(Debug)]
+enum Transport {
+    /// A connected UDP socket
+    Udp(UdpSocket),
+    /// A Unix datagram socket such as `/dev/log`
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+    /// A queue drained by a task writing to a TCP collector
+    Tcp(mpsc::Sender<Vec<u8>>),
+}
+
+/// Sends audit records to a syslog collector
+///
+/// Like the request mirror, sending never waits: records the transport
+/// cannot take right away are dropped and counted.
+#[derive(Debug)]
+pub struct SyslogSink {
+    /// The transport records are sent over
+    transport: Transport,
+    /// A description of the collector for logging
+    destination: String,
+    /// The syslog facility of the records
+    facility: u8,
+    /// The HOSTNAME field of the records
+    hostname: String,
+    /// Records that could not be sent
+    dropped: Arc<AtomicU64>,
+}
+
+impl SyslogSink {
+    /// Creates a sink sending datagrams to a UDP collector
+    ///
+    /// # Arguments
+    /// * `addr` - The address of the collector
+    pub fn udp(addr: SocketAddr) -> io::Result<Self> {
+        let local: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::with_transport(Transport::Udp(socket), format!("udp://{}", addr)))
+    }
+
+    /// Creates a sink sending datagrams to a Unix socket such as `/dev/log`
+    ///
+    /// # Arguments
+    /// * `path` - The path of the collector's socket
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path.as_ref())?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::with_transport(Transport::Unix(socket), format!("unix://{}", path.as_ref().display())))
+    }
+
+    /// Creates a sink streaming to a TCP collector
+    ///
+    /// Records are framed with octet counting (RFC 6587). A background task
+    /// connects lazily and reconnects after errors; records that fail to
+    /// send are dropped. Must be called from within a Tokio runtime.
+    ///
+    /// # Arguments
+    /// * `addr` - The address of the collector
+    pub fn tcp(addr: SocketAddr) -> Self {
+        let (queue, records) = mpsc::channel(TCP_QUEUE);
+        let sink = Self::with_transport(Transport::Tcp(queue), format!("tcp://{}", addr));
+        tokio::spawn(write_tcp(addr, records, Arc::clone(&sink.dropped)));
+        sink
+    }
+
+    /// Creates a sink from `udp://HOST:PORT`, `tcp://HOST:PORT` or `unix:///PATH`
+    ///
+    /// # Returns
+    /// * `Ok(SyslogSink)` - The sink
+    /// * `Err(Socks5Error)` - If the URL is invalid or the collector cannot be reached
+    pub async fn connect(url: &str) -> Socks5Result<Self> {
+        for (scheme, tcp) in [("udp://", false), ("tcp://", true)] {
+            if let Some(addr) = url.strip_prefix(scheme) {
+                let addr = tokio::net::lookup_host(addr).await?.next().ok_or_else(|| {
+                    Socks5Error::ConfigError(format!("Syslog address resolved to nothing: {}", url))
+                })?;
+                return Ok(if tcp { Self::tcp(addr) } else { Self::udp(addr)? });
+            }
+        }
+        #[cfg(unix)]
+        if let Some(path) = url.strip_prefix("unix://") {
+            return Ok(Self::unix(path)?);
+        }
+        Err(Socks5Error::ConfigError(format!("Unsupported syslog URL: {}", url)))
+    }
+
+    fn with_transport(transport: Transport, destination: String) -> Self {
+        Self {
+            transport,
+            destination,
+            facility: DEFAULT_FACILITY,
+            hostname: local_hostname(),
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Sets the syslog facility code (0-23) of the records
+    pub fn with_facility(mut self, facility: u8) -> Self {
+        self.facility = facility.min(23);
+        self
+    }
+
+    /// Sets the HOSTNAME field of the records
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Returns a description of the collector, e.g. `tcp://10.0.0.5:6514`
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Returns the syslog facility code of the records
+    pub fn facility(&self) -> u8 {
+        self.facility
+    }
+
+    /// Returns how many records could not be sent
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends the audit record of a request without waiting
+    pub fn publish(&self, event: &RequestEvent) {
+        let record = format_record(event, self.facility, &self.hostname);
+        let sent = match &self.transport {
+            Transport::Udp(socket) => socket.send(record.as_bytes()).map(drop),
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(record.as_bytes()).map(drop),
+            Transport::Tcp(queue) => queue
+                .try_send(format!("{} {}", record.len(), record).into_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::WouldBlock, e.to_string())),
+        };
+        if let Err(e) = sent {
+            log::debug!("Dropping audit record for {}: {}", self.destination, e);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Formats a request as an RFC 5424 message
+///
+/// # Arguments
+/// * `event` - The request to record
+/// * `facility` - The syslog facility code
+/// * `hostname` - The HOSTNAME field
+pub fn format_record(event: &RequestEvent, facility: u8, hostname: &str) -> String {
+    let mut record = format!(
+        "<{}>1 {} {} rsocks5 {} request [{}",
+        u16::from(facility) * 8 + u16::from(SEVERITY_INFO),
+        rfc3339_millis(event.timestamp_ms),
+        if hostname.is_empty() { "-" } else { hostname },
+        std::process::id(),
+        SD_ID,
+    );
+    let mut param = |name: &str, value: &str| {
+        let _ = write!(record, " {}=\"{}\"", name, escape_param(value));
+    };
+    param("conn", &event.conn_id);
+    param("client", &event.client.to_string());
+    param("protocol", event.protocol);
+    param("target", &event.target);
+    if let Some(rewritten) = &event.rewritten_target {
+        param("rewritten", rewritten);
+    }
+    if let Some(username) = &event.username {
+        param("user", username);
+    }
+    param("method", &format!("{:#04x}", event.method));
+    if let Some(decision) = &event.decision {
+        param("decision", decision);
+    }
+    let _ = write!(record, "] {} requested {}", event.client, event.target);
+    record
+}
+
+/// Escapes `"`, `\` and `]` in a structured data parameter value
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formats milliseconds since the Unix epoch as an RFC 3339 UTC timestamp
+fn rfc3339_millis(timestamp_ms: u64) -> String {
+    let secs = timestamp_ms / 1000;
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, hour, minute, second, timestamp_ms % 1000
+    )
+}
+
+/// Returns the host name for the HOSTNAME field, or `-` if it is unknown
+fn local_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Writes queued records to a TCP collector, reconnecting after errors
+async fn write_tcp(addr: SocketAddr, mut records: mpsc::Receiver<Vec<u8>>, dropped: Arc<AtomicU64>) {
+    let mut stream: Option<TcpStream> = None;
+    while let Some(record) = records.recv().await {
+        if stream.is_none() {
+            match TcpStream::connect(addr).await {
+                Ok(connected) => stream = Some(connected),
+                Err(e) => {
+                    log::debug!("Cannot reach syslog collector {}: {}", addr, e);
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+        }
+        if let Some(connected) = &mut stream {
+            if let Err(e) = connected.write_all(&record).await {
+                log::debug!("Lost syslog collector {}: {}", addr, e);
+                dropped.fetch_add(1, Ordering::Relaxed);
+                stream = None;
+            }
+        }
+    }
+}
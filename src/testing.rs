@@ -0,0 +1,133 @@
+//! Helpers for hermetic tests of the SOCKS5 proxy.
+//!
+//! [`MemoryListener`] and [`MemoryConnector`] connect clients to a server
+//! over in-memory duplex streams, and [`TestServer`] runs a [`Server`] on
+//! them, so the whole pipeline from greeting to relay can be exercised
+//! without binding a client-facing port.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::listener::{Listener, Peekable};
+use crate::server::Server;
+
+/// Buffer size of each in-memory connection, per direction
+const MEMORY_BUFFER: usize = 64 * 1024;
+
+/// A connection queued for a [`MemoryListener`]
+type MemoryConnection = (Peekable<DuplexStream>, SocketAddr);
+
+/// An in-memory listener, for tests and in-process clients
+///
+/// Connections made through its [`MemoryConnector`] appear to come from
+/// `127.0.0.1`, each with its own port.
+#[derive(Debug)]
+pub struct MemoryListener {
+    /// Connections waiting to be accepted
+    incoming: mpsc::UnboundedReceiver<MemoryConnection>,
+}
+
+impl MemoryListener {
+    /// Creates a listener and the connector that reaches it
+    pub fn new() -> (Self, MemoryConnector) {
+        let (outgoing, incoming) = mpsc::unbounded_channel();
+        let connector = MemoryConnector {
+            outgoing,
+            next_port: Arc::new(AtomicU16::new(1)),
+        };
+        (Self { incoming }, connector)
+    }
+}
+
+impl Listener for MemoryListener {
+    type Stream = Peekable<DuplexStream>;
+
+    async fn accept(&mut self) -> io::Result<MemoryConnection> {
+        match self.incoming.recv().await {
+            Some(connection) => Ok(connection),
+            // No connector is left, so nothing will ever arrive
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "memory listeners have no IP address"))
+    }
+}
+
+/// Opens connections to a [`MemoryListener`]
+#[derive(Debug, Clone)]
+pub struct MemoryConnector {
+    /// Hands connections to the listener
+    outgoing: mpsc::UnboundedSender<MemoryConnection>,
+    /// The client port of the next connection
+    next_port: Arc<AtomicU16>,
+}
+
+impl MemoryConnector {
+    /// Opens a connection and returns the client's end of it
+    ///
+    /// # Returns
+    /// * `Ok(DuplexStream)` - The client end of the connection
+    /// * `Err(io::Error)` - If the listener has been dropped
+    pub fn connect(&self) -> io::Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(MEMORY_BUFFER);
+        let port = self.next_port.fetch_add(1, Ordering::Relaxed);
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        self.outgoing
+            .send((Peekable::new(server).with_client_addr(addr), addr))
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "memory listener is gone"))?;
+        Ok(client)
+    }
+}
+
+/// A server running on a [`MemoryListener`] in a background task
+///
+/// Must be started from within a Tokio runtime.
+pub struct TestServer {
+    /// The running server
+    server: Arc<Server>,
+    /// Opens client connections to the server
+    connector: MemoryConnector,
+    /// The task serving the listener
+    task: JoinHandle<Socks5Result<()>>,
+}
+
+impl TestServer {
+    /// Starts serving `server` on a fresh in-memory listener
+    pub fn start(server: Server) -> Self {
+        let server = Arc::new(server);
+        let (listener, connector) = MemoryListener::new();
+        let serving = Arc::clone(&server);
+        let task = tokio::spawn(async move { serving.serve(listener).await });
+        Self { server, connector, task }
+    }
+
+    /// Returns the running server, e.g. to inspect its metrics
+    pub fn server(&self) -> &Arc<Server> {
+        &self.server
+    }
+
+    /// Opens a client connection to the server
+    pub fn connect(&self) -> io::Result<DuplexStream> {
+        self.connector.connect()
+    }
+
+    /// Shuts the server down and waits for it to stop accepting
+    ///
+    /// # Returns
+    /// * `Ok(())` - Once the server has stopped
+    /// * `Err(Socks5Error)` - If the server failed or its task panicked
+    pub async fn stop(self) -> Socks5Result<()> {
+        self.server.shutdown();
+        self.task.await.unwrap_or_else(|e| {
+            Err(Socks5Error::ConnectionError(format!("Server task failed: {}", e)))
+        })
+    }
+}
@@ -0,0 +1,64 @@
+//! Deadlines for each phase of a SOCKS5 session.
+//!
+//! Without deadlines, a client that sends two bytes and stalls holds its
+//! connection task forever, and so does a relay to a target that went away
+//! without closing. [`Timeouts`] bounds every phase:
+//!
+//! * `first_byte` - from accepting the client until it sends anything
+//! * `handshake` - from accepting the client until its request is read,
+//!   covering the greeting, authentication and the request itself
+//! * `connect` - each attempt to connect to one of the target's addresses
+//! * `idle` - a relay without traffic in either direction
+//!
+//! The defaults bound everything but the first byte, which the handshake
+//! deadline covers too; a phase set to `None` has no deadline.
+
+use std::time::Duration;
+
+/// How long a client may take from connecting until its request is read by default
+pub const DEFAULT_HANDSHAKE: Duration = Duration::from_secs(30);
+
+/// How long each connect attempt to a target may take by default
+pub const DEFAULT_CONNECT: Duration = Duration::from_secs(30);
+
+/// How long a relay may go without traffic by default
+pub const DEFAULT_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// The deadline of each session phase
+///
+/// Override single phases with struct update syntax, e.g.
+/// `Timeouts { connect: Some(Duration::from_secs(5)), ..Timeouts::default() }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long a client may stay silent after connecting
+    pub first_byte: Option<Duration>,
+    /// How long a client may take from connecting until its request is read
+    pub handshake: Option<Duration>,
+    /// How long each connect attempt to a target address may take
+    pub connect: Option<Duration>,
+    /// How long a relay may go without traffic in either direction
+    pub idle: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Creates deadlines for no phase at all
+    pub fn none() -> Self {
+        Self {
+            first_byte: None,
+            handshake: None,
+            connect: None,
+            idle: None,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            first_byte: None,
+            handshake: Some(DEFAULT_HANDSHAKE),
+            connect: Some(DEFAULT_CONNECT),
+            idle: Some(DEFAULT_IDLE),
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! TLS-wrapped listeners for the SOCKS5 proxy.
+//!
+//! SOCKS5 itself is plaintext, RFC 1929 passwords included, which is fine
+//! on a trusted network and not elsewhere. A [`TlsListener`] terminates TLS
+//! on accepted connections before the proxy sees them, so clients that
+//! speak SOCKS over TLS (e.g. through `stunnel` or a TLS-capable client
+//! library) are served exactly like plain ones.
+//!
+//! Handshakes run concurrently with accepting, each within a deadline, so
+//! a client that stalls its handshake ties up neither the accept loop nor
+//! a session slot. That deadline is separate from the server's first-byte
+//! timeout, which only starts once the handshake is done, and failed and
+//! timed-out handshakes are counted on their own so broken TLS clients can
+//! be told apart from broken SOCKS clients.
+//!
+//! One TLS port can serve more than SOCKS: protocols registered with
+//! [`TlsListener::with_alpn_handler`] are offered through ALPN next to
+//! [`ALPN_SOCKS5`], and connections negotiating one of them are handed to
+//! its [`AlpnHandler`] instead of the proxy. Clients that negotiate
+//! `socks5` or nothing at all are served as SOCKS clients.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::listener::{Backlog, Listener, Peekable};
+use crate::metrics::Metrics;
+
+/// How long a client may take to complete the TLS handshake by default
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshakes in progress at once; further connections wait in the backlog
+const MAX_PENDING_HANDSHAKES: usize = 1024;
+
+/// The ALPN protocol of SOCKS over TLS, served by the proxy itself
+pub const ALPN_SOCKS5: &[u8] = b"socks5";
+
+/// The ALPN protocol of HTTP/1.1, e.g. for the admin API
+pub const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// The future returned by [`AlpnHandler::serve`]
+pub type AlpnFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Serves TLS connections that negotiated an ALPN protocol other than SOCKS
+///
+/// Each connection is served in a task of its own, outside the proxy's
+/// session limits, so handlers bound their own work.
+pub trait AlpnHandler: Send + Sync + fmt::Debug {
+    /// Serves one connection after its TLS handshake
+    ///
+    /// # Arguments
+    /// * `stream` - The connection, with the handshake done
+    /// * `client` - The client's address
+    fn serve(self: Arc<Self>, stream: TlsStream<TcpStream>, client: SocketAddr) -> AlpnFuture;
+}
+
+/// The handlers of the ALPN protocols served next to SOCKS, by protocol
+pub type AlpnHandlers = Vec<(Vec<u8>, Arc<dyn AlpnHandler>)>;
+
+/// Loads a certificate chain and private key from PEM files
+///
+/// # Arguments
+/// * `cert_path` - The certificate chain, leaf first
+/// * `key_path` - The leaf's private key (PKCS#8, PKCS#1 or SEC1)
+///
+/// # Returns
+/// * `Ok(Arc<ServerConfig>)` - A server configuration offering TLS 1.2 and 1.3
+/// * `Err(Socks5Error)` - If a file cannot be read or holds no usable key
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Socks5Result<Arc<ServerConfig>> {
+    let invalid = |what: &Path, e: &dyn std::fmt::Display| {
+        Socks5Error::ConfigError(format!("Cannot load {}: {}", what.display(), e))
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert_path, &e))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_path, &"no certificates"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid(key_path, &e))?;
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Socks5Error::ConfigError(e.to_string()))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(cert_path, &e))?;
+    Ok(Arc::new(config))
+}
+
+/// The outcome of a handshake, with the client's address either way
+type Handshake = Result<(TlsStream<TcpStream>, SocketAddr), (io::Error, SocketAddr)>;
+
+/// A TCP listener yielding connections after their TLS handshake
+pub struct TlsListener {
+    /// The listener accepting raw connections
+    inner: TcpListener,
+    /// The TLS configuration, before the ALPN protocols are added
+    config: Arc<ServerConfig>,
+    /// Accepts connections with `config` and the ALPN protocols
+    acceptor: TlsAcceptor,
+    /// Where connections negotiating other protocols than SOCKS go
+    handlers: AlpnHandlers,
+    /// How long a client may take to complete the handshake
+    handshake_timeout: Duration,
+    /// Handshakes in progress
+    pending: JoinSet<Handshake>,
+    /// Where failed and timed-out handshakes are counted, if anywhere
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl TlsListener {
+    /// Wraps a bound listener so its connections are served over TLS
+    pub fn new(inner: TcpListener, config: Arc<ServerConfig>) -> Self {
+        Self {
+            inner,
+            acceptor: TlsAcceptor::from(Arc::clone(&config)),
+            config,
+            handlers: Vec::new(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            pending: JoinSet::new(),
+            metrics: None,
+        }
+    }
+
+    /// Sets how long a client may take to complete the TLS handshake
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Counts failed and timed-out handshakes in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Hands connections negotiating the ALPN protocol `protocol` to `handler`
+    ///
+    /// Once a handler is registered, the listener offers [`ALPN_SOCKS5`]
+    /// first, then the registered protocols in order. Registering
+    /// `socks5` itself has no effect.
+    pub fn with_alpn_handler(mut self, protocol: impl Into<Vec<u8>>, handler: Arc<dyn AlpnHandler>) -> Self {
+        let protocol = protocol.into();
+        if protocol == ALPN_SOCKS5 {
+            return self;
+        }
+        self.handlers.retain(|(registered, _)| *registered != protocol);
+        self.handlers.push((protocol, handler));
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = std::iter::once(ALPN_SOCKS5.to_vec())
+            .chain(self.handlers.iter().map(|(protocol, _)| protocol.clone()))
+            .collect();
+        self.acceptor = TlsAcceptor::from(Arc::new(config));
+        self
+    }
+
+    /// Returns how long a client may take to complete the TLS handshake
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    /// Starts the TLS handshake of a newly accepted connection
+    fn start_handshake(&mut self, stream: TcpStream, addr: SocketAddr) {
+        let acceptor = self.acceptor.clone();
+        let timeout = self.handshake_timeout;
+        self.pending.spawn(async move {
+            match tokio::time::timeout(timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => Ok((stream, addr)),
+                Ok(Err(e)) => Err((e, addr)),
+                Err(_) => Err((io::Error::new(io::ErrorKind::TimedOut, format!("no handshake within {:?}", timeout)), addr)),
+            }
+        });
+    }
+}
+
+impl fmt::Debug for TlsListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsListener")
+            .field("inner", &self.inner)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("alpn", &self.handlers.iter().map(|(protocol, _)| String::from_utf8_lossy(protocol)).collect::<Vec<_>>())
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Listener for TlsListener {
+    type Stream = Peekable<TlsStream<TcpStream>>;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, SocketAddr)> {
+        loop {
+            tokio::select! {
+                accepted = self.inner.accept(), if self.pending.len() < MAX_PENDING_HANDSHAKES => {
+                    let (stream, addr) = accepted?;
+                    self.start_handshake(stream, addr);
+                }
+                Some(finished) = self.pending.join_next() => match finished {
+                    Ok(Ok((stream, addr))) => {
+                        let negotiated = stream.get_ref().1.alpn_protocol();
+                        match self.handlers.iter().find(|(protocol, _)| Some(protocol.as_slice()) == negotiated) {
+                            Some((protocol, handler)) => {
+                                log::debug!("Serving {:?} over ALPN {}", addr, String::from_utf8_lossy(protocol));
+                                tokio::spawn(Arc::clone(handler).serve(stream, addr));
+                            }
+                            None => return Ok((Peekable::new(stream).with_client_addr(addr), addr)),
+                        }
+                    }
+                    Ok(Err((e, addr))) => {
+                        log::debug!("TLS handshake with {:?} failed: {}", addr, e);
+                        if let Some(metrics) = &self.metrics {
+                            // The deadline in `start_handshake` reports `TimedOut`
+                            if e.kind() == io::ErrorKind::TimedOut {
+                                metrics.record_tls_handshake_timeout();
+                            } else {
+                                metrics.record_tls_handshake_failure();
+                            }
+                        }
+                    }
+                    Err(e) => log::warn!("TLS handshake task failed: {}", e),
+                },
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn backlog(&self) -> Option<Backlog> {
+        self.inner.backlog()
+    }
+}
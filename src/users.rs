@@ -0,0 +1,168 @@
+//! User accounts for RFC 1929 username/password authentication.
+//!
+//! A [`UserTable`] holds every account clients may authenticate as. An
+//! empty table means no authentication is required. Accounts come from code,
+//! configuration or a file of `username:password` lines.
+//!
+//! Embedders with their own user database implement [`Authenticator`]
+//! instead; the table is the default implementation.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::{ready, Future};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+
+use crate::error::{Socks5Error, Socks5Result};
+
+/// The outcome of checking a client's credentials
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthDecision {
+    /// The client is authenticated as the username it sent
+    Allow,
+    /// The credentials are refused and the client is disconnected
+    Deny,
+}
+
+/// The future returned by [`Authenticator::authenticate`]
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = AuthDecision> + Send + 'a>>;
+
+/// A check of RFC 1929 credentials
+///
+/// Clients offering username/password authentication are checked with
+/// [`Authenticator::authenticate`] during the handshake; the connection
+/// waits for the decision, so lookups in a remote database are fine.
+pub trait Authenticator: Send + Sync + fmt::Debug {
+    /// Decides whether `username` and `password` are valid credentials
+    ///
+    /// # Arguments
+    /// * `username` - The username the client sent
+    /// * `password` - The password the client sent
+    /// * `peer_addr` - The client's address; unspecified if the transport has none
+    fn authenticate<'a>(&'a self, username: &'a str, password: &'a str, peer_addr: SocketAddr) -> AuthFuture<'a>;
+}
+
+/// Accounts clients may authenticate as
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct UserTable {
+    /// Password per username
+    users: BTreeMap<String, String>,
+}
+
+impl UserTable {
+    /// Creates an empty table, which requires no authentication
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads accounts from a file of `username:password` lines
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    ///
+    /// # Returns
+    /// * `Ok(UserTable)` - The accounts in the file
+    /// * `Err(Socks5Error)` - If the file cannot be read or a line is invalid
+    pub fn load(path: impl AsRef<Path>) -> Socks5Result<Self> {
+        let path = path.as_ref();
+        let document = std::fs::read_to_string(path).map_err(|e| {
+            Socks5Error::ConfigError(format!("Cannot read users file {}: {}", path.display(), e))
+        })?;
+        let mut users = Self::new();
+        for (index, line) in document.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (username, password) = parse_account(line).map_err(|_| {
+                Socks5Error::ConfigError(format!("Invalid account on users line {}", index + 1))
+            })?;
+            users.insert(username, password);
+        }
+        Ok(users)
+    }
+
+    /// Adds an account, replacing the password of an existing one
+    pub fn with_user(mut self, username: &str, password: &str) -> Self {
+        self.insert(username, password);
+        self
+    }
+
+    /// Adds an account, replacing the password of an existing one
+    pub fn insert(&mut self, username: &str, password: &str) {
+        self.users.insert(username.to_string(), password.to_string());
+    }
+
+    /// Adds the accounts of `other`, whose passwords win on conflicts
+    pub fn extend(&mut self, other: &UserTable) {
+        self.users.extend(other.users.iter().map(|(username, password)| (username.clone(), password.clone())));
+    }
+
+    /// Returns the number of accounts
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Returns whether there are no accounts, so no authentication is required
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Returns whether an account with this username exists
+    pub fn contains(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    /// Returns the usernames, in sorted order
+    pub fn usernames(&self) -> impl Iterator<Item = &str> {
+        self.users.keys().map(String::as_str)
+    }
+
+    /// Returns the password of `username`, if the account exists
+    pub(crate) fn password(&self, username: &str) -> Option<&str> {
+        self.users.get(username).map(String::as_str)
+    }
+
+    /// Returns whether `password` is the password of `username`
+    ///
+    /// Passwords are compared in time independent of where they differ.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        self.users.get(username).is_some_and(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+    }
+}
+
+impl Authenticator for UserTable {
+    fn authenticate<'a>(&'a self, username: &'a str, password: &'a str, _peer_addr: SocketAddr) -> AuthFuture<'a> {
+        let decision = if self.verify(username, password) { AuthDecision::Allow } else { AuthDecision::Deny };
+        Box::pin(ready(decision))
+    }
+}
+
+impl fmt::Debug for UserTable {
+    /// Lists the usernames only, so passwords never end up in logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.usernames()).finish()
+    }
+}
+
+/// Parses a `username:password` account, as given on the command line
+///
+/// The password may contain colons; the username may not.
+pub fn parse_account(s: &str) -> Socks5Result<(&str, &str)> {
+    match s.split_once(':') {
+        Some((username, password)) if !username.is_empty() && username.len() <= 255 && password.len() <= 255 => {
+            Ok((username, password))
+        }
+        _ => Err(Socks5Error::ConfigError("Account must be USERNAME:PASSWORD (at most 255 bytes each)".to_string())),
+    }
+}
+
+/// Compares two byte strings without returning early on the first difference
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let longest = a.len().max(b.len());
+    let mut diff = a.len() ^ b.len();
+    for i in 0..longest {
+        diff |= usize::from(a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0));
+    }
+    diff == 0
+}
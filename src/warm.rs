@@ -0,0 +1,151 @@
+//! Warm destinations for the SOCKS5 proxy.
+//!
+//! Operators can list their most important targets so the proxy keeps their
+//! addresses resolved and, optionally, a few idle connections open to them,
+//! cutting time-to-first-byte for those endpoints.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::protocol::TargetAddr;
+
+/// Which destinations to keep warm and how
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmConfig {
+    /// The destinations to keep warm
+    pub targets: Vec<TargetAddr>,
+    /// How often addresses are re-resolved and idle connections topped up
+    pub refresh_interval: Duration,
+    /// Idle connections kept open per destination; zero only pre-resolves
+    pub idle_connections: usize,
+    /// Idle connections older than this are discarded instead of used
+    pub max_idle: Duration,
+}
+
+impl WarmConfig {
+    /// Creates a configuration that pre-resolves the targets every 30 seconds
+    ///
+    /// # Arguments
+    /// * `targets` - The destinations to keep warm
+    pub fn new(targets: Vec<TargetAddr>) -> Self {
+        Self {
+            targets,
+            refresh_interval: Duration::from_secs(30),
+            idle_connections: 0,
+            max_idle: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Cached state of one warm destination
+#[derive(Debug, Default)]
+struct WarmEntry {
+    /// The resolved addresses and when they were resolved
+    addrs: Option<(Vec<SocketAddr>, Instant)>,
+    /// Pre-connected sockets and when they were opened
+    idle: Vec<(TcpStream, Instant)>,
+}
+
+/// Resolved addresses and idle connections for the warm destinations
+///
+/// The store is filled by [`Connector::refresh_warm`](crate::connection::Connector::refresh_warm)
+/// and consulted by the connector before resolving or connecting.
+#[derive(Debug)]
+pub struct WarmPool {
+    /// The warm settings
+    config: WarmConfig,
+    /// Time source for address freshness and idle connection age
+    clock: Arc<dyn Clock>,
+    /// State per destination, keyed by its normalized `host:port`
+    entries: Mutex<HashMap<String, WarmEntry>>,
+}
+
+impl WarmPool {
+    /// Creates an empty pool
+    ///
+    /// # Arguments
+    /// * `config` - The warm settings
+    /// * `clock` - Time source for address freshness and idle connection age
+    pub fn new(config: WarmConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            clock,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the warm settings
+    pub fn config(&self) -> &WarmConfig {
+        &self.config
+    }
+
+    /// Returns the cached addresses of a warm destination
+    ///
+    /// Addresses are served for two refresh intervals, so one failed refresh
+    /// does not fall back to resolving on the request path.
+    pub fn cached_addrs(&self, target: &TargetAddr) -> Option<Vec<SocketAddr>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (addrs, resolved_at) = entries.get(&key(target))?.addrs.as_ref()?;
+        let fresh = self.clock.now().duration_since(*resolved_at) < self.config.refresh_interval * 2;
+        fresh.then(|| addrs.clone())
+    }
+
+    /// Stores freshly resolved addresses for a destination
+    pub fn store_addrs(&self, target: &TargetAddr, addrs: Vec<SocketAddr>) {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.entry(key(target)).or_default().addrs = Some((addrs, now));
+    }
+
+    /// Takes an idle connection to the destination, if a usable one exists
+    ///
+    /// Connections past the maximum idle age or already closed by the
+    /// target are discarded.
+    pub fn take_connection(&self, target: &TargetAddr) -> Option<TcpStream> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.get_mut(&key(target))?;
+        while let Some((stream, opened_at)) = entry.idle.pop() {
+            if now.duration_since(opened_at) < self.config.max_idle && is_open(&stream) {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    /// Adds a pre-connected socket for a destination
+    pub fn store_connection(&self, target: &TargetAddr, stream: TcpStream) {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.entry(key(target)).or_default().idle.push((stream, now));
+    }
+
+    /// Drops stale idle connections and returns how many usable ones remain
+    pub fn idle_connections(&self, target: &TargetAddr) -> usize {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entry) = entries.get_mut(&key(target)) else {
+            return 0;
+        };
+        entry.idle.retain(|(stream, opened_at)| {
+            now.duration_since(*opened_at) < self.config.max_idle && is_open(stream)
+        });
+        entry.idle.len()
+    }
+}
+
+/// Returns the normalized `host:port` a destination is stored under
+fn key(target: &TargetAddr) -> String {
+    target.to_string().to_ascii_lowercase()
+}
+
+/// Returns whether an idle connection is still open and has no unread data
+fn is_open(stream: &TcpStream) -> bool {
+    let mut probe = [0; 1];
+    matches!(stream.try_read(&mut probe), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+}
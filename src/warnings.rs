@@ -0,0 +1,140 @@
+//! Aggregation of repeated warnings for the SOCKS5 proxy.
+//!
+//! A single misbehaving client can fail thousands of handshakes a minute,
+//! and logging each one buries everything else. [`WarningAggregator`] lets
+//! the first occurrence per key through, counts the rest, and logs one
+//! summary per key and window, e.g. `1532 handshake failures from 10.2.3.4
+//! in last 60s`.
+
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::clock::Clock;
+use crate::lru::LruMap;
+
+/// How long repeated warnings are collapsed by default
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The least recently warned about keys are evicted beyond this many
+const MAX_KEYS: usize = 4096;
+
+/// Occurrences of one key within the current window
+#[derive(Debug)]
+struct Window {
+    /// When the first occurrence was logged
+    started: Instant,
+    /// Occurrences in the window, including the logged one
+    count: u64,
+}
+
+/// Collapses repeated warnings per key into periodic summaries
+#[derive(Debug)]
+pub struct WarningAggregator<K> {
+    /// What is being counted, in plural, e.g. `handshake failures`
+    what: &'static str,
+    /// How long occurrences are collapsed after one is logged
+    window: Duration,
+    /// Time source for windows
+    clock: Arc<dyn Clock>,
+    /// Open windows per key, bounded against spoofed floods
+    windows: Mutex<LruMap<K, Window>>,
+    /// Occurrences that were counted instead of logged
+    suppressed: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone + fmt::Display> WarningAggregator<K> {
+    /// Creates an aggregator
+    ///
+    /// # Arguments
+    /// * `what` - What is being counted, in plural, for summaries
+    /// * `window` - How long occurrences are collapsed; zero disables collapsing
+    /// * `clock` - Time source for windows
+    pub fn new(what: &'static str, window: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            what,
+            window,
+            clock,
+            windows: Mutex::new(LruMap::new(MAX_KEYS)),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns how long occurrences are collapsed
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns how many occurrences were counted instead of logged so far
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+
+    /// Records an occurrence for `key`
+    ///
+    /// The caller logs the occurrence itself when this returns `true`;
+    /// otherwise it is counted towards the key's next summary. A summary of
+    /// an elapsed window is logged before a new window opens.
+    pub fn record(&self, key: K) -> bool {
+        if self.window.is_zero() {
+            return true;
+        }
+        let now = self.clock.now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        let window = windows.get_or_insert_with(key.clone(), || Window { started: now, count: 0 });
+        if window.count > 0 && now.duration_since(window.started) >= self.window {
+            self.summarize(&key, window);
+            *window = Window { started: now, count: 0 };
+        }
+        window.count += 1;
+        if window.count > 1 {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Logs summaries of elapsed windows and forgets them
+    pub fn flush(&self) {
+        let now = self.clock.now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows.retain(|key, window| {
+            if now.duration_since(window.started) < self.window {
+                return true;
+            }
+            self.summarize(key, window);
+            false
+        });
+    }
+
+    /// Flushes elapsed windows once per window until aborted
+    ///
+    /// # Returns
+    /// * `None` - If collapsing is disabled
+    pub fn spawn_flush(self: &Arc<Self>) -> Option<JoinHandle<()>>
+    where
+        K: Send + 'static,
+    {
+        if self.window.is_zero() {
+            return None;
+        }
+        let aggregator = Arc::clone(self);
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(aggregator.window).await;
+                aggregator.flush();
+            }
+        }))
+    }
+
+    /// Logs a window's summary if occurrences were suppressed
+    fn summarize(&self, key: &K, window: &Window) {
+        if window.count > 1 {
+            log::warn!("{} {} from {} in last {}s", window.count, self.what, key, self.window.as_secs());
+        }
+    }
+}
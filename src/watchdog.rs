@@ -0,0 +1,253 @@
+//! Time budgets for user-provided hooks in the SOCKS5 proxy.
+//!
+//! Embedders plug their own code into the connection path, e.g. an
+//! [`Authenticator`] asking an external service. If that code hangs, the
+//! connection task hangs with it and holds its session slot forever. The
+//! [`Watchdog`] gives each extensibility point a time budget; a hook that
+//! overruns it is abandoned with a [`Socks5Error::HookTimeoutError`],
+//! counted in the metrics, and the request is allowed or refused as the
+//! hook's [`FailMode`] says. [`SessionHooks`] only observe, so an overrun
+//! callback is abandoned and the session carries on whatever the fail mode.
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{Socks5Error, Socks5Result};
+use crate::hooks::{HookFuture, SessionHooks};
+use crate::metrics::Metrics;
+use crate::protocol::{HandshakeInfo, TargetAddr};
+use crate::recent::SessionRecord;
+use crate::users::{AuthDecision, AuthFuture, Authenticator};
+
+/// How long hooks may run by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An extensibility point whose code the watchdog bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    /// A custom [`Authenticator`] checking client credentials
+    Authenticator,
+    /// Registered [`SessionHooks`] following session milestones
+    SessionHooks,
+}
+
+impl Hook {
+    /// All hooks, in counter order
+    pub const ALL: [Hook; 2] = [Hook::Authenticator, Hook::SessionHooks];
+
+    /// Returns a short, stable name for the hook
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hook::Authenticator => "authenticator",
+            Hook::SessionHooks => "session_hooks",
+        }
+    }
+
+    /// Returns the hook's slot in counters and budgets
+    pub(crate) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Hook {
+    type Err = Socks5Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Hook::ALL
+            .into_iter()
+            .find(|hook| hook.as_str() == s)
+            .ok_or_else(|| Socks5Error::ConfigError(format!("Unknown hook: {}", s)))
+    }
+}
+
+/// What happens to a request when its hook overruns the budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailMode {
+    /// The request proceeds as if the hook had allowed it
+    Open,
+    /// The request is refused
+    #[default]
+    Closed,
+}
+
+/// How long a hook may run and what an overrun means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookBudget {
+    /// How long the hook may run
+    pub timeout: Duration,
+    /// What happens to the request when the hook overruns
+    pub fail: FailMode,
+}
+
+impl Default for HookBudget {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            fail: FailMode::Closed,
+        }
+    }
+}
+
+impl FromStr for HookBudget {
+    type Err = Socks5Error;
+
+    /// Parses `MS` or `MS:open` / `MS:closed`, failing closed by default
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Socks5Error::ConfigError(format!("Invalid hook budget (expected MS[:open|closed]): {}", s));
+        let (ms, fail) = s.split_once(':').unwrap_or((s, "closed"));
+        let fail = match fail {
+            "open" => FailMode::Open,
+            "closed" => FailMode::Closed,
+            _ => return Err(invalid()),
+        };
+        let ms = ms.parse::<u64>().map_err(|_| invalid())?;
+        Ok(Self { timeout: Duration::from_millis(ms), fail })
+    }
+}
+
+/// The time budgets of all hooks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Watchdog {
+    /// The budget of each hook, by counter order
+    budgets: [HookBudget; Hook::ALL.len()],
+}
+
+impl Watchdog {
+    /// Creates a watchdog giving every hook the default budget, failing closed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the budget of one hook
+    pub fn with_budget(mut self, hook: Hook, budget: HookBudget) -> Self {
+        self.budgets[hook.index()] = budget;
+        self
+    }
+
+    /// Returns the budget of a hook
+    pub fn budget(&self, hook: Hook) -> HookBudget {
+        self.budgets[hook.index()]
+    }
+
+    /// Runs a hook's future within its budget
+    ///
+    /// # Returns
+    /// * `Ok(T)` - The hook's result, if it finished in time
+    /// * `Err(Socks5Error::HookTimeoutError)` - If it overran; the future is dropped
+    pub async fn run<F: Future>(&self, hook: Hook, future: F) -> Socks5Result<F::Output> {
+        let timeout = self.budget(hook).timeout;
+        tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| Socks5Error::HookTimeoutError(hook.as_str().to_string(), timeout))
+    }
+}
+
+/// An authenticator bounded by the watchdog's budget
+///
+/// Overruns are logged and counted, then decided by the budget's fail mode.
+#[derive(Debug)]
+pub struct WatchedAuthenticator {
+    /// The authenticator being bounded
+    inner: Arc<dyn Authenticator>,
+    /// The budget it runs within
+    watchdog: Watchdog,
+    /// Where overruns are counted
+    metrics: Arc<Metrics>,
+}
+
+impl WatchedAuthenticator {
+    /// Wraps `inner` so it runs within the watchdog's authenticator budget
+    pub fn new(inner: Arc<dyn Authenticator>, watchdog: Watchdog, metrics: Arc<Metrics>) -> Self {
+        Self { inner, watchdog, metrics }
+    }
+}
+
+impl Authenticator for WatchedAuthenticator {
+    fn authenticate<'a>(&'a self, username: &'a str, password: &'a str, peer_addr: SocketAddr) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let checked = self.inner.authenticate(username, password, peer_addr);
+            match self.watchdog.run(Hook::Authenticator, checked).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    self.metrics.record_hook_overrun(Hook::Authenticator);
+                    let fail = self.watchdog.budget(Hook::Authenticator).fail;
+                    log::warn!("{} for {} from {}; failing {:?}", e, username, peer_addr, fail);
+                    match fail {
+                        FailMode::Open => AuthDecision::Allow,
+                        FailMode::Closed => AuthDecision::Deny,
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Session hooks bounded by the watchdog's budget
+///
+/// Overruns are logged and counted; the session carries on.
+#[derive(Debug)]
+pub struct WatchedSessionHooks {
+    /// The hooks being bounded
+    inner: Arc<dyn SessionHooks>,
+    /// The budget each callback runs within
+    watchdog: Watchdog,
+    /// Where overruns are counted
+    metrics: Arc<Metrics>,
+}
+
+impl WatchedSessionHooks {
+    /// Wraps `inner` so each callback runs within the watchdog's session hooks budget
+    pub fn new(inner: Arc<dyn SessionHooks>, watchdog: Watchdog, metrics: Arc<Metrics>) -> Self {
+        Self { inner, watchdog, metrics }
+    }
+
+    /// Runs one callback within the budget
+    async fn run(&self, callback: &str, conn_id: u32, future: HookFuture<'_>) {
+        if let Err(e) = self.watchdog.run(Hook::SessionHooks, future).await {
+            self.metrics.record_hook_overrun(Hook::SessionHooks);
+            log::warn!("{} in {} (conn {:08x})", e, callback, conn_id);
+        }
+    }
+}
+
+impl SessionHooks for WatchedSessionHooks {
+    fn on_accept<'a>(&'a self, conn_id: u32, client: SocketAddr, listener: &'a str) -> HookFuture<'a> {
+        Box::pin(self.run("on_accept", conn_id, self.inner.on_accept(conn_id, client, listener)))
+    }
+
+    fn on_auth<'a>(&'a self, conn_id: u32, username: Option<&'a str>, method: u8) -> HookFuture<'a> {
+        Box::pin(self.run("on_auth", conn_id, self.inner.on_auth(conn_id, username, method)))
+    }
+
+    fn on_request<'a>(
+        &'a self,
+        conn_id: u32,
+        target: &'a TargetAddr,
+        handshake: Option<&'a HandshakeInfo>,
+    ) -> HookFuture<'a> {
+        Box::pin(self.run("on_request", conn_id, self.inner.on_request(conn_id, target, handshake)))
+    }
+
+    fn on_connect_result<'a>(
+        &'a self,
+        conn_id: u32,
+        target: &'a TargetAddr,
+        result: Result<Duration, &'a Socks5Error>,
+    ) -> HookFuture<'a> {
+        Box::pin(self.run("on_connect_result", conn_id, self.inner.on_connect_result(conn_id, target, result)))
+    }
+
+    fn on_close<'a>(&'a self, summary: &'a SessionRecord) -> HookFuture<'a> {
+        Box::pin(self.run("on_close", summary.conn_id, self.inner.on_close(summary)))
+    }
+}
@@ -0,0 +1,172 @@
+use rsocks5::accounting::{TrafficAccounting, UserTraffic};
+use rsocks5::client::{self, Credentials};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::relay::RelayStats;
+use rsocks5::Server;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn relayed(client_to_target: u64, target_to_client: u64) -> RelayStats {
+    RelayStats {
+        client_to_target,
+        target_to_client,
+        ..RelayStats::default()
+    }
+}
+
+#[test]
+fn test_accounting_counts_sessions_and_bytes_per_user() {
+    let accounting = TrafficAccounting::new();
+    assert!(accounting.is_empty());
+    accounting.open_session("alice");
+    accounting.open_session("alice");
+    accounting.open_session("bob");
+    accounting.close_session("alice", &relayed(100, 2000));
+
+    let alice = accounting.user("alice").unwrap();
+    assert_eq!(
+        alice,
+        UserTraffic {
+            sessions: 2,
+            active_sessions: 1,
+            bytes_from_client: 100,
+            bytes_from_target: 2000,
+        }
+    );
+    assert_eq!(alice.total_bytes(), 2100);
+    assert_eq!(accounting.users().keys().collect::<Vec<_>>(), ["alice", "bob"]);
+    assert_eq!(accounting.user("carol"), None);
+}
+
+#[test]
+fn test_take_resets_counters_but_keeps_active_sessions() {
+    let accounting = TrafficAccounting::new();
+    accounting.open_session("alice");
+    accounting.open_session("bob");
+    accounting.close_session("bob", &relayed(10, 20));
+
+    let taken = accounting.take();
+    assert_eq!(taken["bob"].total_bytes(), 30);
+    assert_eq!(taken["alice"].active_sessions, 1);
+    assert_eq!(accounting.len(), 1);
+
+    // The session still running is billed in the next period
+    accounting.close_session("alice", &relayed(5, 5));
+    assert_eq!(
+        accounting.user("alice").unwrap(),
+        UserTraffic {
+            sessions: 0,
+            active_sessions: 0,
+            bytes_from_client: 5,
+            bytes_from_target: 5,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_server_accounts_authenticated_sessions() {
+    // A target that answers every request with twice its bytes
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = target.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0; 4];
+                stream.read_exact(&mut request).await.unwrap();
+                stream.write_all(&[request, request].concat()).await.unwrap();
+            });
+        }
+    });
+
+    let accounting = Arc::new(TrafficAccounting::new());
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()))
+            .with_traffic_accounting(Arc::clone(&accounting)),
+    );
+    assert!(server.traffic_accounting().is_some());
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let credentials = Credentials::new("alice", "secret");
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        client::connect(&mut stream, &target_addr, Some(&credentials)).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"pingping");
+    }
+
+    for _ in 0..50 {
+        if accounting.user("alice").is_some_and(|alice| alice.active_sessions == 0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(
+        accounting.user("alice").unwrap(),
+        UserTraffic {
+            sessions: 2,
+            active_sessions: 0,
+            bytes_from_client: 8,
+            bytes_from_target: 16,
+        }
+    );
+    server.shutdown();
+}
+
+#[tokio::test]
+async fn test_sessions_ended_by_idle_timeout_are_accounted() {
+    use rsocks5::timeouts::Timeouts;
+
+    // A target that echoes one request, then stays silent without closing
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut stream, _) = target.accept().await.unwrap();
+        let mut request = [0; 4];
+        stream.read_exact(&mut request).await.unwrap();
+        stream.write_all(&request).await.unwrap();
+        let _ = stream.read_to_end(&mut Vec::new()).await;
+    });
+
+    let accounting = Arc::new(TrafficAccounting::new());
+    let timeouts = Timeouts {
+        idle: Some(Duration::from_millis(200)),
+        ..Timeouts::default()
+    };
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()))
+            .with_traffic_accounting(Arc::clone(&accounting))
+            .with_timeouts(timeouts),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::connect(&mut stream, &target_addr, Some(&Credentials::new("alice", "secret"))).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut response = Vec::new();
+    // The relay goes idle after the echo and is closed by the proxy
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response, b"ping");
+
+    for _ in 0..50 {
+        if accounting.user("alice").is_some_and(|alice| alice.active_sessions == 0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let alice = accounting.user("alice").unwrap();
+    assert_eq!((alice.active_sessions, alice.bytes_from_client, alice.bytes_from_target), (0, 4, 4));
+    server.shutdown();
+}
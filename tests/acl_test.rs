@@ -0,0 +1,247 @@
+use rsocks5::acl::{AclAction, AclRules, TargetAllowList};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::policy::PolicyContext;
+use std::net::Ipv4Addr;
+
+#[test]
+fn test_allow_list_matches_exact_pairs() {
+    let mut list = TargetAllowList::new();
+    list.allow_str("DB.Internal.:5432").unwrap();
+    list.allow_str("10.0.0.5:443").unwrap();
+    assert_eq!(list.len(), 2);
+
+    assert!(list.is_allowed(&TargetAddr::Domain("db.internal".to_string(), 5432)));
+    assert!(list.is_allowed(&TargetAddr::Ipv4(Ipv4Addr::new(10, 0, 0, 5), 443)));
+
+    // Other ports and hosts are refused
+    assert!(!list.is_allowed(&TargetAddr::Domain("db.internal".to_string(), 5433)));
+    assert!(!list.is_allowed(&TargetAddr::Domain("evil.example".to_string(), 5432)));
+    assert!(!list.is_allowed(&TargetAddr::Ipv4(Ipv4Addr::new(10, 0, 0, 6), 443)));
+}
+
+#[test]
+fn test_allow_list_rejects_invalid_entries() {
+    let mut list = TargetAllowList::new();
+    assert!(list.allow_str("missing-port").is_err());
+    assert!(list.is_empty());
+}
+
+#[tokio::test]
+async fn test_server_refuses_unlisted_target() {
+    use rsocks5::error::Socks5Error;
+    use rsocks5::{client, Server};
+    use std::sync::Arc;
+
+    let mut list = TargetAllowList::new();
+    list.allow("allowed.example", 443);
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_allowed_targets(list));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let result = client::connect(&mut stream, &TargetAddr::Domain("other.example".to_string(), 443), None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))));
+}
+
+#[test]
+fn test_allow_list_shadow_flag() {
+    let list = TargetAllowList::new();
+    assert!(!list.is_shadow());
+    assert!(list.with_shadow(true).is_shadow());
+}
+
+#[test]
+fn test_port_policy_classes() {
+    use rsocks5::acl::{PortClass, PortPolicy};
+
+    let default = PortPolicy::new();
+    assert_eq!(default.check(0), Some(PortClass::Zero));
+    assert_eq!(default.check(22), None);
+
+    let strict = PortPolicy::new().with_privileged_denied(true).with_exception(443);
+    assert_eq!(strict.check(22), Some(PortClass::Privileged));
+    assert_eq!(strict.check(443), None);
+    assert_eq!(strict.check(1024), None);
+    assert_eq!(strict.exceptions().collect::<Vec<_>>(), vec![443]);
+}
+
+#[test]
+fn test_port_allow_and_deny_lists() {
+    use rsocks5::acl::{PortClass, PortPolicy, PortRange};
+
+    assert_eq!("8000-8100".parse::<PortRange>().unwrap(), PortRange::new(8000, 8100));
+    assert_eq!("443".parse::<PortRange>().unwrap().to_string(), "443");
+    assert_eq!(PortRange::new(90, 80).to_string(), "80-90");
+    assert!("80-".parse::<PortRange>().is_err());
+    assert!("http".parse::<PortRange>().is_err());
+
+    let denied = PortPolicy::new().with_denied(PortRange::from(25)).with_denied("465-587".parse().unwrap());
+    assert_eq!(denied.check(25), Some(PortClass::Denied));
+    assert_eq!(denied.check(500), Some(PortClass::Denied));
+    assert_eq!(denied.check(443), None);
+
+    let listed = PortPolicy::new()
+        .with_privileged_denied(true)
+        .with_allowed(PortRange::from(443))
+        .with_allowed("8000-8100".parse().unwrap())
+        .with_denied(PortRange::from(8080));
+    assert_eq!(listed.check(443), None);
+    assert_eq!(listed.check(8000), None);
+    assert_eq!(listed.check(22), Some(PortClass::Unlisted));
+    assert_eq!(listed.check(9000), Some(PortClass::Unlisted));
+    // The deny list wins over the allow list, and port 0 over both
+    assert_eq!(listed.check(8080), Some(PortClass::Denied));
+    assert_eq!(listed.check(0), Some(PortClass::Zero));
+    assert_eq!(listed.allowed().len(), 2);
+}
+
+#[tokio::test]
+async fn test_server_refuses_listed_ports() {
+    use rsocks5::acl::{PortClass, PortPolicy, PortRange};
+    use rsocks5::error::Socks5Error;
+    use rsocks5::testing::TestServer;
+    use rsocks5::{client, Server};
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_port_policy(PortPolicy::new().with_allowed(PortRange::from(443)).with_denied(PortRange::from(25)));
+    let test_server = TestServer::start(server);
+    for port in [25, 8080] {
+        let mut stream = test_server.connect().unwrap();
+        let result = client::connect(&mut stream, &TargetAddr::Domain("mail.example".to_string(), port), None).await;
+        assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))));
+    }
+    let metrics = test_server.server().metrics();
+    assert_eq!(metrics.port_denials(PortClass::Denied), 1);
+    assert_eq!(metrics.port_denials(PortClass::Unlisted), 1);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_refuses_degenerate_ports() {
+    use rsocks5::acl::{PortClass, PortPolicy};
+    use rsocks5::error::Socks5Error;
+    use rsocks5::testing::TestServer;
+    use rsocks5::{client, Server};
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_port_policy(PortPolicy::new().with_privileged_denied(true));
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, 0), None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x08))));
+
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &TargetAddr::Domain("mail.example".to_string(), 25), None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))));
+
+    let metrics = test_server.server().metrics();
+    assert_eq!(metrics.port_denials(PortClass::Zero), 1);
+    assert_eq!(metrics.port_denials(PortClass::Privileged), 1);
+    assert!(test_server.server().stats().to_string().contains(" port_denied.privileged=1 port_denied.zero=1"));
+
+    test_server.stop().await.unwrap();
+}
+
+#[test]
+fn test_acl_rules_first_match_wins() {
+    let anyone = PolicyContext::default();
+    let mut acl = AclRules::new();
+    acl.push_str("deny 10.0.0.0/8").unwrap();
+    acl.push_str("allow *.internal:443").unwrap();
+    acl.push_str("DENY *.internal").unwrap();
+    acl.push_str("deny *:25").unwrap();
+    assert_eq!(acl.len(), 4);
+    assert_eq!(acl.rules()[2].to_string(), "deny *.internal");
+
+    assert!(!acl.is_allowed(&anyone, &TargetAddr::Ipv4(Ipv4Addr::new(10, 1, 2, 3), 443)));
+    assert!(acl.is_allowed(&anyone, &TargetAddr::Ipv4(Ipv4Addr::new(192, 0, 2, 1), 443)));
+    assert!(acl.is_allowed(&anyone, &TargetAddr::Domain("db.internal".to_string(), 443)));
+    assert!(!acl.is_allowed(&anyone, &TargetAddr::Domain("db.internal".to_string(), 5432)));
+    assert!(!acl.is_allowed(&anyone, &TargetAddr::Domain("mail.example".to_string(), 25)));
+    // Unmatched targets are allowed
+    assert!(acl.is_allowed(&anyone, &TargetAddr::Domain("example.com".to_string(), 80)));
+    assert_eq!(
+        acl.rule_for(&anyone, &TargetAddr::Domain("db.internal".to_string(), 443))
+            .map(|(index, rule)| (index, rule.action)),
+        Some((1, AclAction::Allow))
+    );
+
+    assert!(acl.push_str("permit *").is_err());
+    assert!(acl.push_str("deny").is_err());
+    assert!(acl.push_str("deny 10.0.0.0/33").is_err());
+    assert!(AclRules::new().is_allowed(&anyone, &TargetAddr::Domain("example.com".to_string(), 80)));
+}
+
+#[test]
+fn test_acl_rules_scoped_to_users_and_client_networks() {
+    let mut acl = AclRules::new();
+    acl.push_str("allow *:22 user=admin from=10.0.0.0/8").unwrap();
+    acl.push_str("deny *:22").unwrap();
+    acl.push_str("deny * from=192.0.2.7").unwrap();
+    assert_eq!(acl.rules()[0].to_string(), "allow *:22 user=admin from=10.0.0.0/8");
+    assert_eq!(acl.rules()[2].to_string(), "deny * from=192.0.2.7/32");
+
+    let ssh = TargetAddr::Domain("bastion.example".to_string(), 22);
+    let admin = |client: &str| PolicyContext {
+        client: Some(client.parse().unwrap()),
+        username: Some("admin"),
+    };
+    assert!(acl.is_allowed(&admin("10.1.2.3:40000"), &ssh));
+    // IPv4 clients of a dual-stack listener arrive as mapped addresses
+    assert!(acl.is_allowed(&admin("[::ffff:10.1.2.3]:40000"), &ssh));
+    assert!(!acl.is_allowed(&admin("192.0.2.1:40000"), &ssh));
+    let guest = PolicyContext { client: Some("10.1.2.3:40000".parse().unwrap()), username: Some("guest") };
+    assert!(!acl.is_allowed(&guest, &ssh));
+    assert!(!acl.is_allowed(&PolicyContext::default(), &ssh));
+
+    let web = TargetAddr::Domain("example.com".to_string(), 443);
+    assert!(!acl.is_allowed(&admin("192.0.2.7:40000"), &web));
+    assert!(acl.is_allowed(&admin("192.0.2.8:40000"), &web));
+
+    assert!(acl.push_str("deny * user=").is_err());
+    assert!(acl.push_str("deny * from=example.com").is_err());
+    assert!(acl.push_str("deny * from=10.0.0.0/40").is_err());
+    assert!(acl.push_str("deny * to=10.0.0.0/8").is_err());
+}
+
+#[test]
+fn test_shadow_acl_rules_only_report() {
+    let mut acl = AclRules::new();
+    acl.push_str("deny *.internal shadow").unwrap();
+    acl.push_str("deny *:25").unwrap();
+    acl.push_str("deny db.internal").unwrap();
+    assert!(acl.rules()[0].shadow);
+    assert_eq!(acl.rules()[0].to_string(), "deny *.internal shadow");
+    assert!(acl.push_str("allow * shadow").is_err());
+
+    let anyone = PolicyContext::default();
+    let wiki = TargetAddr::Domain("wiki.internal".to_string(), 80);
+    assert!(acl.is_allowed(&anyone, &wiki));
+    assert_eq!(acl.denials(&anyone, &wiki), vec![0]);
+    // The rules after a shadow rule still decide
+    let mail = TargetAddr::Domain("mail.internal".to_string(), 25);
+    assert!(!acl.is_allowed(&anyone, &mail));
+    assert_eq!(acl.denials(&anyone, &mail), vec![0, 1]);
+    assert_eq!(acl.rule_for(&anyone, &mail).map(|(index, _)| index), Some(1));
+    assert!(acl.denials(&anyone, &TargetAddr::Domain("example.com".to_string(), 80)).is_empty());
+}
+
+#[tokio::test]
+async fn test_server_refuses_targets_denied_by_acl() {
+    use rsocks5::error::Socks5Error;
+    use rsocks5::{client, Server};
+    use std::sync::Arc;
+
+    let acl = AclRules::new().with_rule("deny 127.0.0.0/8".parse().unwrap());
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_acl(acl));
+    assert_eq!(server.policy_engine().acl().len(), 1);
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let result = client::connect(&mut stream, &TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, 9), None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))));
+}
@@ -0,0 +1,58 @@
+use rsocks5::active::{ActiveSessions, Counted};
+use rsocks5::protocol::TargetAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
+
+fn client() -> std::net::SocketAddr {
+    "192.0.2.7:40000".parse().unwrap()
+}
+
+#[test]
+fn test_sessions_are_listed_until_their_handle_drops() {
+    let active = Arc::new(ActiveSessions::new());
+    let started = Instant::now();
+    let first = active.register(0x0a, client(), "127.0.0.1:1080".to_string(), started, 1_000);
+    let second = active.register(0x0b, client(), "127.0.0.1:1080".to_string(), started + Duration::from_secs(1), 2_000);
+    first.session().set_username(Some("alice".to_string()));
+    first.session().set_target(TargetAddr::Domain("example.com".to_string(), 443));
+
+    let sessions = active.list(started + Duration::from_secs(3));
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].conn_id, "0000000a");
+    assert_eq!(sessions[0].username.as_deref(), Some("alice"));
+    assert_eq!(sessions[0].target.as_deref(), Some("example.com:443"));
+    assert_eq!(sessions[0].duration_ms, 3_000);
+    assert_eq!(sessions[1].duration_ms, 2_000);
+
+    drop(second);
+    assert_eq!(active.len(), 1);
+    drop(first);
+    assert!(active.is_empty());
+}
+
+#[tokio::test]
+async fn test_terminate_wakes_the_session() {
+    let active = Arc::new(ActiveSessions::new());
+    let handle = active.register(7, client(), "127.0.0.1:1080".to_string(), Instant::now(), 0);
+    assert!(!active.terminate(8));
+    assert!(active.terminate(7));
+    tokio::time::timeout(Duration::from_secs(1), handle.session().terminated()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_counted_stream_counts_both_directions() {
+    let active = Arc::new(ActiveSessions::new());
+    let handle = active.register(1, client(), "127.0.0.1:1080".to_string(), Instant::now(), 0);
+    let (mut peer, stream) = tokio::io::duplex(64);
+    let mut stream = Counted::new(stream, Arc::clone(handle.session()));
+
+    peer.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    stream.write_all(b"hi").await.unwrap();
+
+    assert_eq!(handle.session().bytes_from_client(), 5);
+    assert_eq!(handle.session().bytes_from_target(), 2);
+}
@@ -0,0 +1,178 @@
+use rsocks5::admin::AdminApi;
+use rsocks5::client;
+use rsocks5::clock::TokioClock;
+use rsocks5::loglevel::LogLevelControl;
+use rsocks5::metrics::CloseReason;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::reputation::{Offense, Reputation, ReputationPolicy};
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn server() -> Server {
+    Server::new("127.0.0.1".to_string(), Some(0), None, None)
+}
+
+/// Starts a target that echoes everything back
+async fn echo_target() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (mut reader, mut writer) = stream.split();
+        let _ = tokio::io::copy(&mut reader, &mut writer).await;
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_status_and_routing() {
+    let test_server = TestServer::start(server());
+    let api = AdminApi::new(vec![Arc::clone(test_server.server())]);
+
+    let status = api.handle("GET", "/status", None);
+    assert_eq!(status.status, 200);
+    assert_eq!(status.body["listeners"].as_array().unwrap().len(), 1);
+    assert_eq!(status.body["listeners"][0]["connections_accepted"], 0);
+
+    // Sessions are not listed unless the server keeps them
+    assert_eq!(api.handle("GET", "/sessions", None).body, serde_json::json!([]));
+    assert_eq!(api.handle("POST", "/status", None).status, 405);
+    assert_eq!(api.handle("GET", "/sessions/0000000a", None).status, 405);
+    assert_eq!(api.handle("DELETE", "/sessions/zz", None).status, 400);
+    assert_eq!(api.handle("DELETE", "/sessions/0000000a", None).status, 404);
+    assert_eq!(api.handle("GET", "/nope", None).status, 404);
+
+    test_server.stop().await.unwrap();
+}
+
+#[test]
+fn test_token_is_required_when_set() {
+    let api = AdminApi::new(Vec::new()).with_token("s3cret");
+    assert_eq!(api.handle("GET", "/status", None).status, 401);
+    assert_eq!(api.handle("GET", "/status", Some("Bearer guess")).status, 401);
+    assert_eq!(api.handle("GET", "/status", Some("Bearer s3cret")).status, 200);
+}
+
+#[tokio::test]
+async fn test_list_and_terminate_a_session() {
+    let target = echo_target().await;
+    let test_server = TestServer::start(server().with_active_sessions());
+    let api = AdminApi::new(vec![Arc::clone(test_server.server())]);
+
+    let mut stream = test_server.connect().unwrap();
+    client::connect(&mut stream, &TargetAddr::from(target), None).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    let sessions = api.handle("GET", "/sessions", None).body;
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+    assert_eq!(sessions[0]["target"], target.to_string());
+    let conn_id = sessions[0]["conn_id"].as_str().unwrap().to_string();
+
+    let terminated = api.handle("DELETE", &format!("/sessions/{}", conn_id), None);
+    assert_eq!(terminated.status, 200);
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(n, 0);
+
+    let metrics = test_server.server().metrics();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while metrics.closed(CloseReason::Terminated) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(test_server.server().active_sessions().unwrap().len(), 0);
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_serves_json_over_http() {
+    let api = Arc::new(AdminApi::new(Vec::new()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(api.serve(listener));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /sessions HTTP/1.1\r\nHost: admin\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: application/json\r\n"));
+    assert!(response.ends_with("\r\n\r\n[]\n"));
+}
+
+#[tokio::test]
+async fn test_recent_sessions_and_reputation() {
+    let target = echo_target().await;
+    let reputation = Reputation::new(ReputationPolicy::new(), Arc::new(TokioClock));
+    let test_server = TestServer::start(server().with_recent_sessions(16).with_reputation(reputation));
+    let api = AdminApi::new(vec![Arc::clone(test_server.server())]);
+
+    let mut stream = test_server.connect().unwrap();
+    client::connect(&mut stream, &TargetAddr::from(target), None).await.unwrap();
+    drop(stream);
+    let recent = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let recent = api.handle("GET", "/recent?last=10", None).body;
+            if !recent.as_array().unwrap().is_empty() {
+                return recent;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(recent[0]["target"], target.to_string());
+    assert_eq!(recent[0]["reason"], "completed");
+    let matching = api.handle("GET", &format!("/recent?target=127.0.0.1:{}", target.port()), None).body;
+    assert_eq!(matching.as_array().unwrap().len(), 1);
+    assert_eq!(api.handle("GET", "/recent?target=*.example.com", None).body, serde_json::json!([]));
+    assert_eq!(api.handle("GET", "/recent?since=99999999999999", None).body, serde_json::json!([]));
+    assert_eq!(api.handle("GET", "/recent?last=many", None).status, 400);
+
+    let ip = "192.0.2.7".parse().unwrap();
+    test_server.server().reputation().unwrap().record(ip, Offense::ProtocolViolation);
+    let scores = api.handle("GET", "/reputation", None).body;
+    assert_eq!(scores[0]["ip"], "192.0.2.7");
+    assert!(scores[0]["score"].as_f64().unwrap() > 0.0);
+    assert_eq!(api.handle("GET", "/reputation?min=1000", None).body, serde_json::json!([]));
+    assert_eq!(api.handle("DELETE", "/reputation", None).status, 405);
+
+    test_server.stop().await.unwrap();
+}
+
+#[test]
+fn test_log_level_is_read_and_changed() {
+    assert_eq!(AdminApi::new(Vec::new()).handle("GET", "/loglevel", None).status, 404);
+
+    let api = AdminApi::new(Vec::new()).with_log_level(LogLevelControl::new(log::LevelFilter::Info));
+    let level = api.handle("GET", "/loglevel", None).body;
+    assert_eq!((level["level"].as_str(), level["base"].as_str()), (Some("info"), Some("info")));
+    assert_eq!(api.handle("PUT", "/loglevel?level=debug", None).body["level"], "debug");
+    assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    assert_eq!(api.handle("PUT", "/loglevel?level=loud", None).status, 400);
+    assert_eq!(api.handle("PUT", "/loglevel", None).status, 400);
+    assert_eq!(api.handle("PUT", "/loglevel?level=info", None).body["level"], "info");
+}
+
+#[tokio::test]
+async fn test_silent_connections_do_not_hold_up_others() {
+    let api = Arc::new(AdminApi::new(Vec::new()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(api.serve(listener));
+
+    let _silent = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /status HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
@@ -0,0 +1,52 @@
+use rsocks5::protocol::{handshake, process_command, TargetAddr};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::Ipv4Addr;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Counts allocations made on the current thread while counting is enabled
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.with(Cell::get) {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_handshake_and_request_do_not_allocate() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    // Greeting offering NO_AUTH, then CONNECT 10.0.0.1:80
+    client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    client.write_all(&[0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0, 80]).await.unwrap();
+    server.readable().await.unwrap();
+
+    COUNTING.with(|counting| counting.set(true));
+    let info = handshake(&mut server, None, None).await.unwrap();
+    let target = process_command(&mut server).await.unwrap();
+    COUNTING.with(|counting| counting.set(false));
+
+    assert_eq!(info.offered_methods, vec![0x00]);
+    assert!(matches!(target, TargetAddr::Ipv4(ip, 80) if ip == Ipv4Addr::new(10, 0, 0, 1)));
+    assert_eq!(ALLOCATIONS.with(Cell::get), 0);
+}
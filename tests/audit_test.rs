@@ -0,0 +1,95 @@
+use flate2::read::GzDecoder;
+use rsocks5::audit::{parse_size, AuditFile, AuditWriter, RotationPolicy};
+use rsocks5::clock::{ManualClock, TokioClock};
+use rsocks5::mirror::RequestEvent;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Returns a fresh, empty directory for one test
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rsocks5-audit-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_size_rotation_keeps_newest_files() {
+    let dir = scratch_dir("size");
+    let policy = RotationPolicy::new().with_max_bytes(10).with_keep(2);
+    let mut file = AuditFile::open(dir.join("audit.log"), policy, Arc::new(ManualClock::new())).unwrap();
+
+    for record in ["first-rec\n", "second-re\n", "third-rec\n", "fourth-re\n"] {
+        file.write_record(record.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("audit.log"), "fourth-re\n");
+    assert_eq!(read("audit.log.1"), "third-rec\n");
+    assert_eq!(read("audit.log.2"), "second-re\n");
+    assert!(!dir.join("audit.log.3").exists());
+}
+
+#[test]
+fn test_age_rotation_compresses_rotated_files() {
+    let dir = scratch_dir("age");
+    let clock = Arc::new(ManualClock::new());
+    let policy = RotationPolicy::new()
+        .with_max_age(Duration::from_secs(3600))
+        .with_compression(true);
+    let mut file = AuditFile::open(dir.join("audit.log"), policy, clock.clone()).unwrap();
+
+    file.write_record(b"old\n").unwrap();
+    clock.advance(Duration::from_secs(1800));
+    file.write_record(b"still current\n").unwrap();
+    clock.advance(Duration::from_secs(1800));
+    file.write_record(b"new\n").unwrap();
+    file.flush().unwrap();
+
+    assert_eq!(file.rotated_path(1), dir.join("audit.log.1.gz"));
+    let mut rotated = String::new();
+    GzDecoder::new(std::fs::File::open(dir.join("audit.log.1.gz")).unwrap())
+        .read_to_string(&mut rotated)
+        .unwrap();
+    assert_eq!(rotated, "old\nstill current\n");
+    assert!(!dir.join("audit.log.1").exists());
+    assert_eq!(std::fs::read_to_string(dir.join("audit.log")).unwrap(), "new\n");
+}
+
+#[test]
+fn test_writer_appends_json_lines() {
+    let dir = scratch_dir("writer");
+    let path = dir.join("audit.log");
+    let writer = AuditWriter::open(&path, RotationPolicy::new(), Arc::new(TokioClock)).unwrap();
+    for conn_id in ["00000001", "00000002"] {
+        writer.publish(&RequestEvent {
+            conn_id: conn_id.to_string(),
+            timestamp_ms: 1_700_000_000_000,
+            client: "127.0.0.1:40000".parse().unwrap(),
+            protocol: "socks5",
+            target: "example.com:443".to_string(),
+            rewritten_target: None,
+            username: None,
+            method: 0x00,
+            offered_methods: vec![0x00],
+            decision: None,
+        });
+    }
+    assert_eq!(writer.dropped(), 0);
+    writer.close();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["conn_id"], "00000002");
+}
+
+#[test]
+fn test_parse_size_suffixes() {
+    assert_eq!(parse_size("512").unwrap(), 512);
+    assert_eq!(parse_size("100M").unwrap(), 100 << 20);
+    assert!(parse_size("lots").is_err());
+}
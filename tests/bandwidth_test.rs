@@ -0,0 +1,179 @@
+use rsocks5::bandwidth::{BandwidthPolicy, RateLimit, TokenBucket};
+use rsocks5::clock::ManualClock;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::relay::Throttle;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn domain(host: &str, port: u16) -> TargetAddr {
+    TargetAddr::Domain(host.to_string(), port)
+}
+
+#[test]
+fn test_rate_limit_parsing() {
+    assert_eq!("1M/4M".parse::<RateLimit>().unwrap(), RateLimit { rate: 1 << 20, burst: 4 << 20 });
+    assert_eq!("512".parse::<RateLimit>().unwrap(), RateLimit { rate: 512, burst: 512 });
+    assert_eq!("2k".parse::<RateLimit>().unwrap().rate, 2048);
+    // The burst is never below one second's worth of the rate
+    assert_eq!("1M/1K".parse::<RateLimit>().unwrap().burst, 1 << 20);
+
+    assert!("fast".parse::<RateLimit>().is_err());
+    assert!("1M/".parse::<RateLimit>().is_err());
+    assert!("99999999999G".parse::<RateLimit>().is_err());
+}
+
+#[test]
+fn test_idle_time_accrues_burst_credit_up_to_the_cap() {
+    let clock = Arc::new(ManualClock::new());
+    let bucket = TokenBucket::new(RateLimit::new(1000, 4000), clock.clone());
+
+    // A full bucket lets the burst through at once
+    assert_eq!(bucket.delay_for(4000), Duration::ZERO);
+    // Beyond it, bytes wait for the sustained rate
+    assert_eq!(bucket.delay_for(500), Duration::from_millis(500));
+
+    // After a long idle period only the burst is available again
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(bucket.available(), 4000.0);
+    assert_eq!(bucket.delay_for(2000), Duration::ZERO);
+    assert_eq!(bucket.available(), 2000.0);
+}
+
+#[test]
+fn test_user_limits_are_shared_and_rule_limits_are_per_connection() {
+    let clock = Arc::new(ManualClock::new());
+    let policy = BandwidthPolicy::new(clock)
+        .with_user("alice", RateLimit::new(1000, 1000))
+        .with_rule("*.backup.internal".parse().unwrap(), RateLimit::new(1000, 1000));
+
+    // Two connections of the same user draw from one bucket
+    let first = policy.throttles_for(Some("alice"), &domain("example.com", 443));
+    let second = policy.throttles_for(Some("alice"), &domain("example.com", 443));
+    assert_eq!(first.upload.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+    assert_eq!(second.upload.as_ref().unwrap().delay_for(1000), Duration::from_secs(1));
+    // Directions are limited separately
+    assert_eq!(second.download.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+
+    // Each connection matching a rule gets its own bucket
+    let first = policy.throttles_for(None, &domain("nas.backup.internal", 22));
+    let second = policy.throttles_for(None, &domain("nas.backup.internal", 22));
+    assert_eq!(first.upload.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+    assert_eq!(second.upload.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+
+    // Unlimited connections are not throttled at all
+    let unlimited = policy.throttles_for(Some("bob"), &domain("example.com", 443));
+    assert!(unlimited.upload.is_none() && unlimited.download.is_none());
+}
+
+#[test]
+fn test_slowest_class_decides() {
+    let clock = Arc::new(ManualClock::new());
+    let policy = BandwidthPolicy::new(clock)
+        .with_global(RateLimit::new(10_000, 10_000))
+        .with_user("alice", RateLimit::new(1000, 1000));
+    assert!(!policy.is_empty());
+
+    let throttles = policy.throttles_for(Some("alice"), &domain("example.com", 443));
+    let upload = throttles.upload.unwrap();
+    assert_eq!(upload.delay_for(1000), Duration::ZERO);
+    assert_eq!(upload.delay_for(2000), Duration::from_secs(2));
+}
+
+#[test]
+fn test_bulk_flow_does_not_starve_newcomers() {
+    use rsocks5::bandwidth::FairQueue;
+
+    let clock = Arc::new(ManualClock::new());
+    let queue = Arc::new(FairQueue::new(RateLimit::new(1000, 1000), clock.clone()));
+
+    // A lone bulk transfer gets the whole rate and runs into debt
+    let bulk = queue.flow(1);
+    assert_eq!(bulk.delay_for(1000), Duration::ZERO);
+    assert_eq!(bulk.delay_for(5000), Duration::from_secs(5));
+
+    // A newcomer is not queued behind that debt: it gets its own half
+    let interactive = queue.flow(1);
+    assert_eq!(queue.flows(), 2);
+    assert_eq!(interactive.delay_for(500), Duration::ZERO);
+    assert_eq!(interactive.delay_for(500), Duration::from_secs(1));
+
+    // While both are active, the bulk transfer only gets half the rate
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(bulk.delay_for(250), Duration::from_secs(10));
+
+    drop(interactive);
+    assert_eq!(queue.flows(), 1);
+}
+
+#[test]
+fn test_idle_flows_do_not_reduce_shares() {
+    use rsocks5::bandwidth::{FairQueue, ACTIVE_WINDOW};
+
+    let clock = Arc::new(ManualClock::new());
+    let queue = Arc::new(FairQueue::new(RateLimit::new(1000, 1000), clock.clone()));
+    let idle = queue.flow(1);
+    let busy = queue.flow(1);
+
+    assert_eq!(idle.delay_for(1), Duration::ZERO);
+    clock.advance(ACTIVE_WINDOW);
+    // Only the busy flow sent recently, so its share is the whole rate
+    assert_eq!(busy.delay_for(1500), Duration::from_millis(500));
+}
+
+#[test]
+fn test_priority_classes_weight_the_global_cap() {
+    use rsocks5::bandwidth::Priority;
+
+    let clock = Arc::new(ManualClock::new());
+    let policy = BandwidthPolicy::new(clock)
+        .with_global(RateLimit::new(2100, 2100))
+        .with_user_priority("backup", Priority::Bulk)
+        .with_rule_priority("*.voip.example".parse().unwrap(), Priority::High);
+
+    assert_eq!(policy.priority_for(Some("backup"), &domain("example.com", 443)), Priority::Bulk);
+    // Rules take precedence over the user's class
+    assert_eq!(policy.priority_for(Some("backup"), &domain("sip.voip.example", 5060)), Priority::High);
+    assert_eq!(policy.priority_for(None, &domain("example.com", 443)), Priority::Normal);
+
+    let bulk = policy.throttles_for(Some("backup"), &domain("example.com", 443));
+    let high = policy.throttles_for(None, &domain("sip.voip.example", 5060));
+    let normal = policy.throttles_for(None, &domain("example.com", 443));
+    assert_eq!(high.priority, Priority::High);
+    let (bulk, high, normal) = (bulk.upload.unwrap(), high.upload.unwrap(), normal.upload.unwrap());
+
+    // Once all three are active, each one's credit is capped at its share
+    // of the burst: 1600 bytes for high, 400 for normal and 100 for bulk
+    for _ in 0..2 {
+        for throttle in [&bulk, &high, &normal] {
+            throttle.delay_for(0);
+        }
+    }
+    assert_eq!(high.delay_for(2000).as_millis(), 250);
+    assert_eq!(normal.delay_for(2000).as_millis(), 4000);
+    assert_eq!(bulk.delay_for(2000).as_millis(), 19_000);
+
+    assert_eq!("bulk".parse::<Priority>().unwrap(), Priority::Bulk);
+    assert!("urgent".parse::<Priority>().is_err());
+}
+
+#[test]
+fn test_connection_limit_is_per_connection_and_overridable_per_user() {
+    let clock = Arc::new(ManualClock::new());
+    let policy = BandwidthPolicy::new(clock)
+        .with_connection_limit(RateLimit::new(1000, 1000))
+        .with_user_connection_limit("alice", RateLimit::new(4000, 4000));
+    assert!(!policy.is_empty());
+
+    // Each connection gets its own bucket
+    let first = policy.throttles_for(None, &domain("example.com", 443));
+    let second = policy.throttles_for(Some("bob"), &domain("example.com", 443));
+    assert_eq!(first.upload.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+    assert_eq!(first.upload.as_ref().unwrap().delay_for(1000), Duration::from_secs(1));
+    assert_eq!(second.upload.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+    assert_eq!(first.download.as_ref().unwrap().delay_for(1000), Duration::ZERO);
+
+    // Alice's override replaces the default cap
+    let alice = policy.throttles_for(Some("alice"), &domain("example.com", 443));
+    assert_eq!(alice.upload.as_ref().unwrap().delay_for(4000), Duration::ZERO);
+    assert_eq!(alice.upload.as_ref().unwrap().delay_for(1000), Duration::from_millis(250));
+}
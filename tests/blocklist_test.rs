@@ -0,0 +1,119 @@
+use rsocks5::blocklist::{Blocklist, BlocklistFormat, BlocklistLocation, BlocklistSource, Blocklists};
+use rsocks5::constants::reply;
+use rsocks5::policy::{PolicyContext, PolicyEngine};
+use rsocks5::protocol::TargetAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn domain(host: &str) -> TargetAddr {
+    TargetAddr::Domain(host.to_string(), 443)
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rsocks5-blocklist-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn test_hosts_file_blocks_exact_hosts() {
+    let text = "\
+# Ad servers
+127.0.0.1 localhost
+::1 localhost ip6-localhost
+0.0.0.0 ads.example.com tracker.example.net # two hosts
+0.0.0.0 0.0.0.0
+bare.example.org
+not a host line
+";
+    let list = Blocklist::parse(text, BlocklistFormat::Hosts);
+    assert_eq!(list.len(), 3);
+    assert_eq!(list.skipped(), 1);
+    assert!(list.is_blocked(&domain("ads.example.com")));
+    assert!(list.is_blocked(&domain("Tracker.Example.NET.")));
+    assert!(list.is_blocked(&domain("bare.example.org")));
+    // Hosts entries do not cover subdomains
+    assert!(!list.is_blocked(&domain("cdn.ads.example.com")));
+    assert!(!list.is_blocked(&domain("localhost")));
+    assert!(!list.is_blocked(&TargetAddr::Ipv4("0.0.0.0".parse().unwrap(), 443)));
+}
+
+#[test]
+fn test_adblock_rules_block_domains_and_subdomains() {
+    let text = "\
+[Adblock Plus 2.0]
+! Title: trackers
+||tracker.example.com^
+||ads.example.net^$important
+@@||allowed.example.com^
+example.com##.banner
+||example.org/ads/*
+||thirdparty.example.com^$third-party
+";
+    let list = Blocklist::parse(text, BlocklistFormat::Adblock);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list.skipped(), 4);
+    assert!(list.is_blocked(&domain("tracker.example.com")));
+    assert!(list.is_blocked(&domain("pixel.eu.tracker.example.com")));
+    assert!(list.is_blocked(&domain("ads.example.net")));
+    assert!(!list.is_blocked(&domain("example.com")));
+    assert!(!list.is_blocked(&domain("nottracker.example.com")));
+    assert!(!list.is_blocked(&domain("thirdparty.example.com")));
+}
+
+#[test]
+fn test_sources_parse_format_and_location() {
+    let source: BlocklistSource = "adblock:https://example.com/filters.txt".parse().unwrap();
+    assert_eq!(source.format, BlocklistFormat::Adblock);
+    assert!(matches!(source.location, BlocklistLocation::Url(_)));
+    assert_eq!(source.to_string(), "adblock:https://example.com/filters.txt");
+
+    let source: BlocklistSource = "HOSTS:/etc/blocked.hosts".parse().unwrap();
+    assert_eq!(source.format, BlocklistFormat::Hosts);
+    assert_eq!(source.location, BlocklistLocation::File(PathBuf::from("/etc/blocked.hosts")));
+
+    assert!("dnsmasq:/etc/blocked.conf".parse::<BlocklistSource>().is_err());
+    assert!("/etc/blocked.hosts".parse::<BlocklistSource>().is_err());
+}
+
+#[tokio::test]
+async fn test_refresh_merges_lists_and_keeps_them_on_failure() {
+    let hosts = temp_path("hosts");
+    let adblock = temp_path("adblock");
+    std::fs::write(&hosts, "0.0.0.0 ads.example.com\n").unwrap();
+    std::fs::write(&adblock, "||tracker.example.net^\n").unwrap();
+    let sources = vec![
+        format!("hosts:{}", hosts.display()).parse().unwrap(),
+        format!("adblock:{}", adblock.display()).parse().unwrap(),
+    ];
+    let blocklists = Blocklists::new(sources);
+    assert!(!blocklists.is_blocked(&domain("ads.example.com")));
+
+    assert!(blocklists.refresh().await.is_empty());
+    assert_eq!(blocklists.current().len(), 2);
+    assert!(blocklists.is_blocked(&domain("ads.example.com")));
+    assert!(blocklists.is_blocked(&domain("cdn.tracker.example.net")));
+
+    // A list that disappears keeps its last entries
+    std::fs::remove_file(&adblock).unwrap();
+    std::fs::write(&hosts, "0.0.0.0 other.example.com\n").unwrap();
+    let failures = blocklists.refresh().await;
+    assert_eq!(failures.len(), 1);
+    assert!(!blocklists.is_blocked(&domain("ads.example.com")));
+    assert!(blocklists.is_blocked(&domain("other.example.com")));
+    assert!(blocklists.is_blocked(&domain("tracker.example.net")));
+    std::fs::remove_file(&hosts).unwrap();
+}
+
+#[tokio::test]
+async fn test_policy_engine_denies_blocklisted_targets() {
+    let path = temp_path("policy");
+    std::fs::write(&path, "||ads.example.com^\n").unwrap();
+    let blocklists = Arc::new(Blocklists::new(vec![format!("adblock:{}", path.display()).parse().unwrap()]));
+    blocklists.refresh().await;
+    std::fs::remove_file(&path).unwrap();
+
+    let engine = PolicyEngine::new().with_blocklists(blocklists);
+    let context = PolicyContext::default();
+    let denial = engine.evaluate(&context, &domain("cdn.ads.example.com")).denial().unwrap().clone();
+    assert_eq!((denial.policy, denial.reply), ("blocklist", reply::NOT_ALLOWED));
+    assert!(engine.evaluate(&context, &domain("example.com")).is_allowed());
+}
@@ -0,0 +1,76 @@
+use rsocks5::canonical::{canonicalize, ip_literal, CanonicalCache};
+use rsocks5::clock::ManualClock;
+use rsocks5::connection::Connector;
+use rsocks5::policy::{PolicyContext, PolicyEngine};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::routing::RoutingTable;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_canonicalize_lowercases_and_encodes_punycode() {
+    assert_eq!(canonicalize("WWW.Example.COM."), "www.example.com");
+    assert_eq!(canonicalize("Bücher.example"), "xn--bcher-kva.example");
+    assert_eq!(canonicalize("münchen.de"), "xn--mnchen-3ya.de");
+    assert_eq!(canonicalize("例え.テスト"), "xn--r8jz45g.xn--zckzah");
+    // Names already in canonical form are not copied
+    assert!(matches!(canonicalize("www.example.com"), Cow::Borrowed(_)));
+}
+
+#[test]
+fn test_ip_literals_are_recognized_in_every_spelling() {
+    let ip = |s: &str| Some(s.parse::<std::net::IpAddr>().unwrap());
+    assert_eq!(ip_literal("10.0.0.1"), ip("10.0.0.1"));
+    assert_eq!(ip_literal("10.0.0.1."), ip("10.0.0.1"));
+    assert_eq!(ip_literal("[::1]"), ip("::1"));
+    assert_eq!(ip_literal("::ffff:10.0.0.1"), ip("::ffff:10.0.0.1"));
+    // The shorthand forms getaddrinfo accepts
+    assert_eq!(ip_literal("10.1"), ip("10.0.0.1"));
+    assert_eq!(ip_literal("10.1.258"), ip("10.1.1.2"));
+    assert_eq!(ip_literal("0x0a000001"), ip("10.0.0.1"));
+    assert_eq!(ip_literal("012.0.0.1"), ip("10.0.0.1"));
+    assert_eq!(ip_literal("167772161"), ip("10.0.0.1"));
+
+    assert_eq!(ip_literal("example.com"), None);
+    assert_eq!(ip_literal("10.0.0.256"), None);
+    assert_eq!(ip_literal("256.1"), None);
+    assert_eq!(ip_literal("1.2.3.4.5"), None);
+    assert_eq!(ip_literal("08.0.0.1"), None);
+}
+
+#[test]
+fn test_cache_remembers_matched_routes_until_expiry() {
+    let clock = Arc::new(ManualClock::new());
+    let cache = CanonicalCache::new(16, Duration::from_secs(60), clock.clone());
+    let routes = RoutingTable::new(vec![
+        "*.internal:80=port:8080".parse().unwrap(),
+        "*.internal=dscp:8".parse().unwrap(),
+    ]);
+
+    let canonical = cache.lookup("Wiki.Internal.", 443, &routes);
+    assert_eq!(&*canonical.host, "wiki.internal");
+    assert_eq!(canonical.route, Some(1));
+    assert_eq!(cache.lookup("Wiki.Internal.", 443, &routes), canonical);
+    assert_eq!(cache.lookup("Wiki.Internal.", 80, &routes).route, Some(0));
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 2, 2));
+
+    clock.advance(Duration::from_secs(60));
+    cache.lookup("Wiki.Internal.", 443, &routes);
+    assert_eq!(cache.misses(), 3);
+}
+
+#[test]
+fn test_engine_and_connector_share_the_cache() {
+    let routes = RoutingTable::new(vec!["db.internal=port:6432".parse().unwrap()]);
+    let cache = CanonicalCache::new(16, Duration::from_secs(60), Arc::new(ManualClock::new()));
+    let connector = Connector::new().with_routes(routes.clone()).with_canonical_cache(cache);
+    let cache = Arc::clone(connector.canonical_cache().unwrap());
+    let engine = PolicyEngine::new().with_routes(routes).with_canonical_cache(Arc::clone(&cache));
+
+    let target = TargetAddr::Domain("DB.internal".to_string(), 5432);
+    let decision = engine.evaluate(&PolicyContext::default(), &target);
+    assert_eq!(decision.rewritten, Some(TargetAddr::Domain("DB.internal".to_string(), 6432)));
+    connector.options_for(&target);
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+}
@@ -0,0 +1,41 @@
+use rsocks5::compat::{Quirk, Quirks};
+use rsocks5::Server;
+
+fn server() -> Server {
+    Server::new("127.0.0.1".to_string(), Some(0), None, None)
+}
+
+#[test]
+fn test_default_capabilities() {
+    let capabilities = server().capabilities();
+    assert_eq!(capabilities.versions, vec![5, 4]);
+    assert!(capabilities.supports_command("connect"));
+    assert!(!capabilities.supports_command("bind"));
+    assert!(capabilities.supports_address_type("domain"));
+    assert_eq!(capabilities.auth_methods, vec!["no-auth"]);
+    assert!(capabilities.extensions.is_empty());
+    assert_eq!(
+        capabilities.to_string(),
+        "versions=5,4 commands=connect address_types=ipv4,domain,ipv6 auth=no-auth"
+    );
+}
+
+#[test]
+fn test_capabilities_follow_settings() {
+    let capabilities = server()
+        .with_credentials("alice".to_string(), "secret".to_string())
+        .with_ip_literals_only(true)
+        .with_denial_reasons(true)
+        .with_socks4(false)
+        .with_compat(Quirks::new().with(Quirk::Socks4Greeting))
+        .capabilities();
+    assert_eq!(capabilities.versions, vec![5, 4]);
+    assert!(!capabilities.supports_address_type("domain"));
+    assert_eq!(capabilities.auth_methods, vec!["username-password"]);
+    assert!(capabilities.has_extension("denial-reasons"));
+    assert_eq!(capabilities.quirks, vec!["socks4-greeting"]);
+    assert_eq!(
+        capabilities.to_json(),
+        r#"{"versions":[5,4],"commands":["connect"],"address_types":["ipv4","ipv6"],"auth_methods":["username-password"],"extensions":["denial-reasons"],"quirks":["socks4-greeting"]}"#
+    );
+}
@@ -0,0 +1,98 @@
+use rsocks5::client::Socks5Stream;
+use rsocks5::error::Socks5Error;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Starts a target echoing back what it receives
+async fn echo_target() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Serves `server` on a local port and returns its address
+async fn serve(server: Server) -> SocketAddr {
+    let server = Arc::new(server);
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+    addr
+}
+
+#[test]
+fn test_target_conversions() {
+    assert_eq!(TargetAddr::from(("example.com", 443)), TargetAddr::Domain("example.com".to_string(), 443));
+    assert_eq!(TargetAddr::from(("10.0.0.1", 80)), "10.0.0.1:80".parse().unwrap());
+    assert_eq!(TargetAddr::from(("::1".to_string(), 80)), "[::1]:80".parse().unwrap());
+}
+
+#[tokio::test]
+async fn test_stream_reaches_target() {
+    let proxy = serve(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+    let target = echo_target().await;
+
+    let mut stream = Socks5Stream::connect(proxy, target).await.unwrap();
+    assert!(matches!(stream.bound_addr(), TargetAddr::Ipv4(..)), "{:?}", stream.bound_addr());
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // Targets can be given as host and port
+    let mut stream = Socks5Stream::connect(proxy.to_string(), ("127.0.0.1", target.port())).await.unwrap();
+    stream.write_all(b"again").await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"again");
+}
+
+#[tokio::test]
+async fn test_stream_authenticates() {
+    let proxy = serve(Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()))).await;
+    let target = echo_target().await;
+
+    let result = Socks5Stream::connect(proxy, target).await;
+    assert!(matches!(result, Err(Socks5Error::HandshakeError(_))), "{:?}", result);
+    let result = Socks5Stream::connect_with_password(proxy, target, "alice", "wrong").await;
+    assert!(matches!(result, Err(Socks5Error::HandshakeError(_))), "{:?}", result);
+
+    let mut stream = Socks5Stream::connect_with_password(proxy, target, "alice", "secret").await.unwrap();
+    stream.write_all(b"hi").await.unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hi");
+}
+
+#[tokio::test]
+async fn test_stream_over_existing_connection() {
+    let test_server = TestServer::start(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let target = echo_target().await;
+
+    let connection = test_server.connect().unwrap();
+    let mut stream = Socks5Stream::connect_with_stream(connection, target, None).await.unwrap();
+    stream.write_all(b"duplex").await.unwrap();
+    let mut buf = [0; 6];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"duplex");
+    drop(stream);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unreachable_proxy() {
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let result = Socks5Stream::connect(closed, ("example.com", 80)).await;
+    assert!(matches!(result, Err(Socks5Error::ConnectionError(_))), "{:?}", result);
+}
@@ -0,0 +1,26 @@
+use rsocks5::clock::{Clock, ManualClock, TokioClock};
+use std::time::Duration;
+
+#[test]
+fn test_manual_clock_advance() {
+    // A manual clock only moves when advanced
+    let clock = ManualClock::new();
+    let start = clock.now();
+    let wall_start = clock.wall_time();
+
+    clock.advance(Duration::from_secs(30));
+
+    assert_eq!(clock.now() - start, Duration::from_secs(30));
+    assert_eq!(clock.wall_time().duration_since(wall_start).unwrap(), Duration::from_secs(30));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_tokio_clock_follows_paused_time() {
+    // The Tokio clock honours paused time in tests
+    let clock = TokioClock;
+    let start = clock.now();
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+
+    assert_eq!(clock.now() - start, Duration::from_secs(5));
+}
@@ -0,0 +1,218 @@
+use rsocks5::client::{self, Credentials};
+use rsocks5::clock::{ManualClock, TokioClock};
+use rsocks5::cluster::{Cluster, Usage, UserQuota};
+use rsocks5::error::Socks5Error;
+use rsocks5::metrics::CloseReason;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Polls until `done` holds, for up to a second
+async fn eventually(mut done: impl FnMut() -> bool) -> bool {
+    for _ in 0..100 {
+        if done() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    done()
+}
+
+#[test]
+fn test_local_limits() {
+    let clock = Arc::new(ManualClock::new());
+    let cluster = Arc::new(
+        Cluster::new(clock.clone())
+            .with_default_quota(UserQuota::new().with_max_connections(2))
+            .with_user_quota("bob", UserQuota::new().with_max_bytes(100))
+            .with_period(Duration::from_secs(3600)),
+    );
+
+    let first = cluster.admit("alice").unwrap();
+    let _second = cluster.admit("alice").unwrap();
+    assert!(cluster.admit("alice").is_none());
+    drop(first);
+    assert_eq!(cluster.usage("alice"), Usage { connections: 1, bytes: 0 });
+    assert!(cluster.admit("alice").is_some());
+
+    // Bob has no connection limit but a byte quota
+    let lease = cluster.admit("bob").unwrap();
+    lease.add_bytes(100);
+    assert!(cluster.admit("bob").is_none());
+    drop(lease);
+    assert_eq!(cluster.local_usage("bob"), Usage { connections: 0, bytes: 100 });
+
+    // The quota starts over with the next period
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(cluster.usage("bob"), Usage::default());
+    assert!(cluster.admit("bob").is_some());
+}
+
+#[tokio::test]
+async fn test_gossip_shares_counters() {
+    let sockets = [
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+    ];
+    let addrs: Vec<_> = sockets.iter().map(|socket| socket.local_addr().unwrap()).collect();
+    let node = |key: &[u8]| {
+        Arc::new(
+            Cluster::new(Arc::new(TokioClock))
+                .with_default_quota(UserQuota::new().with_max_connections(1))
+                .with_peers(addrs.clone())
+                .with_key(key)
+                .with_interval(Duration::from_millis(10)),
+        )
+    };
+    let (a, b, outsider) = (node(b"fleet"), node(b"fleet"), node(b"other"));
+    let [socket_a, socket_b, socket_outsider] = sockets;
+    a.spawn_gossip(socket_a);
+    b.spawn_gossip(socket_b);
+    outsider.spawn_gossip(socket_outsider);
+
+    let lease = a.admit("alice").unwrap();
+    assert!(eventually(|| b.usage("alice").connections == 1).await);
+    assert!(b.admit("alice").is_none());
+    // Counters signed with another key are dropped
+    assert_eq!(outsider.usage("alice"), Usage::default());
+
+    drop(lease);
+    assert!(eventually(|| b.usage("alice").connections == 0).await);
+    assert!(b.admit("alice").is_some());
+}
+
+#[tokio::test]
+async fn test_replayed_gossip_is_dropped() {
+    let socket_a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket_b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let eavesdropper = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr_b = socket_b.local_addr().unwrap();
+    let node = |peers| {
+        Arc::new(
+            Cluster::new(Arc::new(TokioClock))
+                .with_peers(peers)
+                .with_key(b"fleet")
+                .with_interval(Duration::from_millis(10)),
+        )
+    };
+    let a = node(vec![addr_b, eavesdropper.local_addr().unwrap()]);
+    let b = node(Vec::new());
+    a.spawn_gossip(socket_a);
+    b.spawn_gossip(socket_b);
+
+    // Capture a datagram reporting alice's open session
+    let lease = a.admit("alice").unwrap();
+    let mut buf = vec![0; 65536];
+    let captured = loop {
+        let len = eavesdropper.recv(&mut buf).await.unwrap();
+        if buf[..len].windows(5).any(|window| window == b"alice") {
+            break buf[..len].to_vec();
+        }
+    };
+    drop(lease);
+    assert!(eventually(|| b.usage("alice").connections == 0).await);
+
+    // Replaying it does not bring the session back
+    eavesdropper.send_to(&captured, addr_b).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(b.usage("alice").connections, 0);
+}
+
+#[tokio::test]
+async fn test_server_ends_sessions_that_run_out_of_byte_quota() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut stream, _) = target.accept().await.unwrap();
+        let _ = stream.read_to_end(&mut Vec::new()).await;
+    });
+    let cluster = Arc::new(Cluster::new(Arc::new(TokioClock)).with_default_quota(UserQuota::new().with_max_bytes(8)));
+    let server = Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()))
+        .with_cluster(Arc::clone(&cluster));
+    let test_server = TestServer::start(server);
+    let credentials = Credentials::new("alice", "secret");
+
+    // The session is still open when the quota runs out
+    let mut stream = test_server.connect().unwrap();
+    client::connect(&mut stream, &target_addr, Some(&credentials)).await.unwrap();
+    stream.write_all(&[0; 16]).await.unwrap();
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut [0; 1])).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+    assert!(eventually(|| test_server.server().metrics().closed(CloseReason::UserLimit) == 1).await);
+    assert_eq!(cluster.local_usage("alice").bytes, 16);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_refuses_users_over_their_limit() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = target.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    let cluster = Arc::new(Cluster::new(Arc::new(TokioClock)).with_default_quota(UserQuota::new().with_max_connections(1)));
+    let server = Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()))
+        .with_cluster(Arc::clone(&cluster));
+    let test_server = TestServer::start(server);
+    let credentials = Credentials::new("alice", "secret");
+
+    let mut open = test_server.connect().unwrap();
+    client::connect(&mut open, &target_addr, Some(&credentials)).await.unwrap();
+    let mut refused = test_server.connect().unwrap();
+    let result = client::connect(&mut refused, &target_addr, Some(&credentials)).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))), "{:?}", result);
+    assert!(eventually(|| test_server.server().metrics().closed(CloseReason::UserLimit) == 1).await);
+
+    // Closing the open session frees the slot
+    drop(open);
+    assert!(eventually(|| cluster.usage("alice").connections == 0).await);
+    let mut stream = test_server.connect().unwrap();
+    client::connect(&mut stream, &target_addr, Some(&credentials)).await.unwrap();
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shadow_mode_only_reports_user_limits() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = target.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    let quota = UserQuota::new().with_max_connections(1).with_max_bytes(8);
+    let cluster = Arc::new(Cluster::new(Arc::new(TokioClock)).with_default_quota(quota));
+    let server = Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()))
+        .with_cluster(Arc::clone(&cluster))
+        .with_shadow_mode(true);
+    let test_server = TestServer::start(server);
+    let credentials = Credentials::new("alice", "secret");
+
+    // Running out of quota does not end the session
+    let mut open = test_server.connect().unwrap();
+    client::connect(&mut open, &target_addr, Some(&credentials)).await.unwrap();
+    open.write_all(&[0; 16]).await.unwrap();
+    open.read_exact(&mut [0; 16]).await.unwrap();
+    assert!(eventually(|| test_server.server().metrics().shadow_denials() == 1).await);
+
+    // Nor does the connection limit refuse a second one, which is still counted
+    let mut second = test_server.connect().unwrap();
+    client::connect(&mut second, &target_addr, Some(&credentials)).await.unwrap();
+    assert!(eventually(|| cluster.usage("alice").connections == 2).await);
+    assert!(eventually(|| test_server.server().metrics().shadow_denials() == 2).await);
+    assert_eq!(test_server.server().metrics().closed(CloseReason::UserLimit), 0);
+    test_server.stop().await.unwrap();
+}
@@ -0,0 +1,143 @@
+use rsocks5::compat::{Quirk, Quirks};
+use rsocks5::metrics::CloseReason;
+use rsocks5::protocol::process_command_with_compat;
+use rsocks5::Server;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Accepts one connection, running `client` against it on the other end
+async fn accept_with<F, Fut>(client: F) -> TcpStream
+where
+    F: FnOnce(TcpStream) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { client(TcpStream::connect(addr).await.unwrap()).await });
+    listener.accept().await.unwrap().0
+}
+
+#[test]
+fn test_quirk_names_round_trip() {
+    for quirk in Quirk::ALL {
+        assert_eq!(quirk.as_str().parse::<Quirk>().unwrap(), quirk);
+    }
+    assert!("lenient".parse::<Quirk>().is_err());
+
+    let quirks: Quirks = [Quirk::ShortReads, Quirk::ReservedByte].into_iter().collect();
+    assert!(quirks.contains(Quirk::ShortReads));
+    assert!(!quirks.contains(Quirk::Socks4Greeting));
+    assert_eq!(quirks.iter().collect::<Vec<_>>(), vec![Quirk::ReservedByte, Quirk::ShortReads]);
+    assert!(Quirks::default().is_empty());
+}
+
+#[tokio::test]
+async fn test_reserved_byte_is_strict_by_default() {
+    let mut stream = accept_with(|mut client| async move {
+        client.write_all(&[0x05, 0x01, 0x07, 0x01, 10, 0, 0, 1, 0x00, 0x50]).await.unwrap();
+        let mut reply = [0; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], 0x01);
+    })
+    .await;
+    assert!(process_command_with_compat(&mut stream, Quirks::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_reserved_byte_quirk_accepts_request() {
+    let mut stream = accept_with(|mut client| async move {
+        client.write_all(&[0x05, 0x01, 0x07, 0x01, 10, 0, 0, 1, 0x00, 0x50]).await.unwrap();
+    })
+    .await;
+    let compat = Quirks::new().with(Quirk::ReservedByte);
+    let (target, used) = process_command_with_compat(&mut stream, compat).await.unwrap();
+    assert_eq!(target.to_string(), "10.0.0.1:80");
+    assert_eq!(used, compat);
+}
+
+#[tokio::test]
+async fn test_short_read_greeting_is_accepted_and_counted() {
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_compat(Quirks::new().with(Quirk::ShortReads)),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    // Announces three methods but only sends one
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(&[0x05, 0x03, 0x00]).await.unwrap();
+    let mut choice = [0; 2];
+    client.read_exact(&mut choice).await.unwrap();
+    assert_eq!(choice, [0x05, 0x00]);
+    for _ in 0..50 {
+        if server.metrics().quirk_hits(Quirk::ShortReads) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.metrics().quirk_hits(Quirk::ShortReads), 1);
+    assert_eq!(server.metrics().quirk_hits(Quirk::ReservedByte), 0);
+}
+
+#[tokio::test]
+async fn test_socks4a_greeting_is_downgraded() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = target.accept().await.unwrap();
+        stream.write_all(b"hi").await.unwrap();
+    });
+
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_socks4(false)
+            .with_compat(Quirks::new().with(Quirk::Socks4Greeting)),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    // SOCKS4a: IP 0.0.0.1 means a host name follows the user ID
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&target_port.to_be_bytes());
+    request.extend_from_slice(&[0, 0, 0, 1]);
+    request.extend_from_slice(b"legacy\0localhost\0");
+    client.write_all(&request).await.unwrap();
+
+    let mut reply = [0; 8];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x00, 0x5A]);
+    let mut greeting = [0; 2];
+    client.read_exact(&mut greeting).await.unwrap();
+    assert_eq!(&greeting, b"hi");
+    assert_eq!(server.metrics().quirk_hits(Quirk::Socks4Greeting), 1);
+}
+
+#[tokio::test]
+async fn test_socks4_greeting_is_refused_when_disabled() {
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_socks4(false));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(&[0x04, 0x01, 0x00, 0x50, 10, 0, 0, 1, 0x00]).await.unwrap();
+    let mut buf = [0; 8];
+    let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await.unwrap().unwrap_or(0);
+    assert_eq!(n, 0);
+    for _ in 0..50 {
+        if server.metrics().closed(CloseReason::Error) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.metrics().closed(CloseReason::Error), 1);
+}
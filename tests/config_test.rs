@@ -0,0 +1,152 @@
+use rsocks5::config::{AclConfig, ListenerConfig, RouteConfig, ServerConfig, Timeouts};
+use rsocks5::constants::DEFAULT_PORT;
+use rsocks5::error::Socks5Error;
+use rsocks5::relay::RelayEngine;
+use std::time::Duration;
+
+#[test]
+fn test_default_config_has_one_listener() {
+    let config = ServerConfig::default();
+    assert_eq!(config.listeners, vec![ListenerConfig::default()]);
+    assert_eq!(config.listeners[0].ip, "0.0.0.0");
+    assert_eq!(config.listeners[0].port, DEFAULT_PORT);
+    assert_eq!(config.timeouts, Timeouts::default());
+}
+
+#[test]
+fn test_config_from_toml() {
+    let config = ServerConfig::from_toml(
+        r#"
+        username = "admin"
+        password = "secret"
+
+        [[listeners]]
+        ip = "127.0.0.1"
+        port = 1081
+        ip_literals_only = true
+
+        [acl]
+        allow_targets = ["db.internal:5432"]
+
+        [[routes]]
+        pattern = "*.backup.internal"
+        dscp = 8
+
+        [timeouts]
+        idle = 300
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.username.as_deref(), Some("admin"));
+    assert_eq!(config.listeners.len(), 1);
+    assert!(config.listeners[0].ip_literals_only);
+    assert_eq!(config.acl, AclConfig { rules: Vec::new(), allow_targets: vec!["db.internal:5432".to_string()], shadow: false });
+    assert_eq!(config.routes[0].dscp, Some(8));
+    assert_eq!(config.timeouts.idle, Some(300));
+    assert_eq!(config.timeouts.first_byte, None);
+}
+
+#[test]
+fn test_config_rejects_unknown_fields() {
+    let result = ServerConfig::from_toml("bogus = 1");
+    assert!(matches!(result, Err(Socks5Error::ConfigError(_))));
+}
+
+#[test]
+fn test_config_toml_round_trip() {
+    let config = ServerConfig {
+        routes: vec![RouteConfig {
+            pattern: "10.0.0.0/8".to_string(),
+            dscp: None,
+            mark: Some(0x20),
+            rewrite_host: None,
+            rewrite_port: Some(8080),
+            srv: None,
+        }],
+        timeouts: Timeouts { first_byte: Some(5), handshake: Some(20), connect: None, idle: None, tls_handshake: None },
+        ..ServerConfig::default()
+    };
+    let document = config.to_toml().unwrap();
+    assert_eq!(ServerConfig::from_toml(&document).unwrap(), config);
+}
+
+#[test]
+fn test_build_servers_applies_settings() {
+    let config = ServerConfig {
+        listeners: vec![
+            ListenerConfig { ip: "127.0.0.1".to_string(), port: 0, last_port: None, ip_literals_only: false, tls_cert: None, tls_key: None },
+            ListenerConfig { ip: "127.0.0.1".to_string(), port: 0, last_port: None, ip_literals_only: true, tls_cert: None, tls_key: None },
+        ],
+        acl: AclConfig { rules: vec!["deny *:25".to_string()], allow_targets: vec!["example.com:443".to_string()], shadow: true },
+        timeouts: Timeouts { first_byte: Some(5), handshake: Some(20), connect: Some(0), idle: Some(60), tls_handshake: Some(3) },
+        ..ServerConfig::default()
+    };
+    let servers = config.build_servers().unwrap();
+
+    assert_eq!(servers.len(), 2);
+    assert!(!servers[0].ip_literals_only());
+    assert!(servers[1].ip_literals_only());
+    assert_eq!(servers[0].first_byte_timeout(), Some(Duration::from_secs(5)));
+    assert_eq!(servers[0].timeouts().handshake, Some(Duration::from_secs(20)));
+    assert_eq!(servers[0].timeouts().connect, None);
+    assert_eq!(servers[0].tls_handshake_timeout(), Duration::from_secs(3));
+    assert_eq!(servers[0].allowed_targets().map(|list| list.len()), Some(1));
+    assert!(servers[0].allowed_targets().unwrap().is_shadow());
+    assert_eq!(servers[1].acl().len(), 1);
+    assert_eq!(servers[0].relay_options().engine(), RelayEngine::Full);
+    assert_eq!(servers[0].relay_options().idle_timeout(), Some(Duration::from_secs(60)));
+}
+
+#[test]
+fn test_build_servers_rejects_invalid_settings() {
+    let config = ServerConfig { username: Some("admin".to_string()), ..ServerConfig::default() };
+    assert!(matches!(config.build_servers(), Err(Socks5Error::ConfigError(_))));
+
+    let config = ServerConfig {
+        routes: vec![RouteConfig {
+            pattern: "*".to_string(),
+            dscp: Some(64),
+            mark: None,
+            rewrite_host: None,
+            rewrite_port: None,
+            srv: None,
+        }],
+        ..ServerConfig::default()
+    };
+    assert!(matches!(config.build_servers(), Err(Socks5Error::ConfigError(_))));
+}
+
+#[test]
+fn test_json_schema_describes_config() {
+    let schema: serde_json::Value = serde_json::from_str(&ServerConfig::json_schema()).unwrap();
+    assert_eq!(schema["title"], "ServerConfig");
+    let properties = schema["properties"].as_object().unwrap();
+    for key in ["listeners", "username", "password", "upstreams", "acl", "routes", "timeouts"] {
+        assert!(properties.contains_key(key), "missing {}", key);
+    }
+}
+
+#[tokio::test]
+async fn test_config_hosts_overrides() {
+    use rsocks5::protocol::TargetAddr;
+
+    let config = ServerConfig::from_toml(
+        r#"
+        [[listeners]]
+        ip = "127.0.0.1"
+        port = 0
+
+        [hosts]
+        "DB.test" = ["127.0.0.2"]
+        "#,
+    )
+    .unwrap();
+    let servers = config.build_servers().unwrap();
+    let hosts = servers[0].connector().hosts().unwrap();
+    assert_eq!(hosts.lookup("db.test"), Some(vec!["127.0.0.2".parse().unwrap()]));
+
+    let target = TargetAddr::Domain("db.test".to_string(), 80);
+    let addrs = servers[0].connector().resolve(&target).await.unwrap();
+    assert_eq!(addrs, vec!["127.0.0.2:80".parse().unwrap()]);
+}
@@ -18,3 +18,106 @@ fn test_target_addr_domain_to_string() {
     // Verify the to_string method returns the expected string
     assert_eq!(addr.to_string(), "example.com:443");
 }
+
+#[tokio::test]
+async fn test_connector_applies_bind_address() {
+    use rsocks5::connection::{Connector, SocketOptions};
+    use std::net::IpAddr;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connector = Connector::new().with_socket_options(SocketOptions {
+        bind_addr: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        recv_buffer_size: Some(64 * 1024),
+        ..SocketOptions::default()
+    });
+
+    let stream = connector.open(&TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, port)).await.unwrap();
+    let (_, peer) = listener.accept().await.unwrap();
+    assert_eq!(peer, stream.local_addr().unwrap());
+    assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+}
+
+#[tokio::test]
+async fn test_verified_reply_detects_immediate_close() {
+    use rsocks5::connection::{Connector, ReplyMode};
+    use rsocks5::error::Socks5Error;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    // A target that accepts and immediately closes
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = target.accept().await {
+            drop(stream);
+        }
+    });
+
+    // A local client connection to send replies over
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(front.local_addr().unwrap()).await.unwrap();
+    let (mut server_side, _) = front.accept().await.unwrap();
+
+    let connector = Connector::new().with_reply_mode(ReplyMode::Verified { settle: Duration::from_millis(500) });
+    let result = connector
+        .connect(&mut server_side, &TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, target_port))
+        .await;
+    assert!(matches!(result, Err(Socks5Error::ConnectionError(_))));
+
+    // The client got a failure reply rather than SUCCEEDED
+    let mut reply = [0; 10];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_ne!(reply[1], 0x00);
+}
+
+#[tokio::test]
+async fn test_verified_reply_retries_another_address_after_immediate_close() {
+    use rsocks5::connection::{Connector, ReplyMode};
+    use rsocks5::hosts::Hosts;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // The first address accepts and immediately closes, the second one works
+    let broken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = broken.local_addr().unwrap().port();
+    let working_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    let working = TcpListener::bind(SocketAddr::new(working_ip, port)).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = broken.accept().await {
+            drop(stream);
+        }
+    });
+    tokio::spawn(async move {
+        let (mut stream, _) = working.accept().await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+    });
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(front.local_addr().unwrap()).await.unwrap();
+    let (mut server_side, _) = front.accept().await.unwrap();
+
+    let hosts = Hosts::new()
+        .with_entry("flaky.test", IpAddr::V4(Ipv4Addr::LOCALHOST))
+        .with_entry("flaky.test", working_ip);
+    let connector = Connector::new()
+        .with_hosts(hosts)
+        .with_reply_mode(ReplyMode::Verified { settle: Duration::from_millis(200) })
+        .with_early_close_retry(true);
+    let mut stream = connector
+        .connect(&mut server_side, &TargetAddr::Domain("flaky.test".to_string(), port))
+        .await
+        .unwrap();
+    assert_eq!(stream.peer_addr().unwrap().ip(), working_ip);
+    let mut greeting = [0; 5];
+    stream.read_exact(&mut greeting).await.unwrap();
+    assert_eq!(&greeting, b"hello");
+
+    let mut reply = [0; 10];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+}
@@ -0,0 +1,81 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::connection::Connector;
+use rsocks5::dnscache::{DnsCache, DEFAULT_TTL};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::resolver::Resolver;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+#[test]
+fn test_entries_expire_after_their_ttl() {
+    let clock = Arc::new(ManualClock::new());
+    let cache = DnsCache::new(16, clock.clone());
+    let addrs: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap()];
+
+    assert_eq!(cache.get("example.test"), None);
+    cache.insert("Example.Test.", addrs.clone(), Some(Duration::from_secs(10)));
+    cache.insert("system.test", addrs.clone(), None);
+    cache.insert("empty.test", Vec::new(), Some(Duration::from_secs(10)));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get("example.test"), Some(addrs.clone()));
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.get("example.test"), None);
+    assert_eq!(cache.get("system.test"), Some(addrs.clone()));
+    clock.advance(DEFAULT_TTL);
+    assert_eq!(cache.get("system.test"), None);
+    assert_eq!((cache.hits(), cache.misses()), (2, 3));
+}
+
+#[test]
+fn test_ttl_override_is_capped() {
+    let clock = Arc::new(ManualClock::new());
+    let cache = DnsCache::new(16, clock.clone())
+        .with_ttl_override(Duration::from_secs(600))
+        .with_max_ttl(Duration::from_secs(120));
+    let addrs: Vec<IpAddr> = vec!["2001:db8::1".parse().unwrap()];
+
+    cache.insert("short.test", addrs.clone(), Some(Duration::from_secs(1)));
+    clock.advance(Duration::from_secs(119));
+    assert_eq!(cache.get("short.test"), Some(addrs));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(cache.get("short.test"), None);
+}
+
+#[tokio::test]
+async fn test_connector_resolves_each_name_once() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let nameserver = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counted = queries.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+            counted.fetch_add(1, Ordering::SeqCst);
+            let query = &buf[..len];
+            let is_a = query[len - 3] == 1;
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, u8::from(is_a), 0, 0, 0, 0]);
+            response.extend_from_slice(&query[12..]);
+            if is_a {
+                response.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 9]);
+            }
+            socket.send_to(&response, client).await.unwrap();
+        }
+    });
+    let connector = Connector::new()
+        .with_resolver(Resolver::new(vec![nameserver]))
+        .with_dns_cache(DnsCache::new(16, Arc::new(ManualClock::new())));
+
+    for port in [80, 443] {
+        let addrs = connector.resolve(&TargetAddr::Domain("www.example.test".to_string(), port)).await.unwrap();
+        assert_eq!(addrs, vec![SocketAddr::from(([192, 0, 2, 9], port))]);
+    }
+    // One A and one AAAA query for the first connection only
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+    assert_eq!(connector.dns_cache().unwrap().hits(), 1);
+}
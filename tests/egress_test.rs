@@ -0,0 +1,81 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::egress::EgressPool;
+use rsocks5::protocol::TargetAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn egress(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+}
+
+fn client(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(198, 51, 100, last))
+}
+
+fn site(host: &str) -> (TargetAddr, SocketAddr) {
+    (TargetAddr::Domain(host.to_string(), 443), "203.0.113.1:443".parse().unwrap())
+}
+
+#[test]
+fn test_round_robin_without_stickiness() {
+    let pool = EgressPool::new(vec![egress(1), egress(2)], Arc::new(ManualClock::new()));
+    let (target, dest) = site("example.com");
+    let first = pool.select(client(1), &target, &dest).unwrap();
+    let second = pool.select(client(1), &target, &dest).unwrap();
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_sticky_sessions_keep_their_address() {
+    let clock = Arc::new(ManualClock::new());
+    let pool = EgressPool::new(vec![egress(1), egress(2), egress(3)], clock.clone())
+        .with_stickiness(Duration::from_secs(60));
+    let (target, dest) = site("login.example.com");
+
+    let pinned = pool.select(client(1), &target, &dest).unwrap();
+    for _ in 0..10 {
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(pool.select(client(1), &target, &dest), Some(pinned));
+    }
+}
+
+#[test]
+fn test_sticky_sessions_spread_over_least_used() {
+    let pool = EgressPool::new(vec![egress(1), egress(2)], Arc::new(ManualClock::new()))
+        .with_stickiness(Duration::from_secs(60));
+    let (target, dest) = site("example.com");
+
+    let first = pool.select(client(1), &target, &dest).unwrap();
+    let second = pool.select(client(2), &target, &dest).unwrap();
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_idle_sessions_are_rebalanced() {
+    let clock = Arc::new(ManualClock::new());
+    let pool = EgressPool::new(vec![egress(1), egress(2)], clock.clone())
+        .with_stickiness(Duration::from_secs(60));
+    let (target, dest) = site("a.example.com");
+    let (other, other_dest) = site("b.example.com");
+
+    pool.select(client(1), &target, &dest).unwrap();
+    clock.advance(Duration::from_secs(61));
+
+    // Another live session holds the other address's share of load
+    let busy = pool.select(client(2), &other, &other_dest).unwrap();
+    let rebalanced = pool.select(client(1), &target, &dest).unwrap();
+    assert_ne!(rebalanced, busy);
+}
+
+#[test]
+fn test_select_matches_address_family() {
+    let v6: IpAddr = "2001:db8::1".parse().unwrap();
+    let pool = EgressPool::new(vec![egress(1), v6], Arc::new(ManualClock::new()));
+    let target = TargetAddr::Domain("example.com".to_string(), 443);
+    let v6_dest: SocketAddr = "[2001:db8::2]:443".parse().unwrap();
+    assert_eq!(pool.select(client(1), &target, &v6_dest), Some(v6));
+
+    let v4_only = EgressPool::new(vec![egress(1)], Arc::new(ManualClock::new()));
+    assert_eq!(v4_only.select(client(1), &target, &v6_dest), None);
+}
@@ -38,6 +38,18 @@ fn test_error_display() {
     let relay_err = Socks5Error::RelayError("relay failed".to_string());
     assert_eq!(format!("{}", relay_err), "SOCKS5 relay error: relay failed");
 
+    let reply_err = Socks5Error::ReplyError(0x05);
+    assert_eq!(format!("{}", reply_err), "SOCKS5 server replied with error code: 0x05");
+
+    let denied_err = Socks5Error::DeniedError(0x02, "blocked by rule corp-no-smtp".to_string());
+    assert_eq!(format!("{}", denied_err), "SOCKS5 server denied the request (0x02): blocked by rule corp-no-smtp");
+
+    let config_err = Socks5Error::ConfigError("bad port".to_string());
+    assert_eq!(format!("{}", config_err), "SOCKS5 configuration error: bad port");
+
+    let hook_err = Socks5Error::HookTimeoutError("authenticator".to_string(), std::time::Duration::from_secs(2));
+    assert_eq!(format!("{}", hook_err), "SOCKS5 hook timeout: authenticator did not finish within 2s");
+
     let io_err = Socks5Error::IoError(IoError::new(ErrorKind::ConnectionRefused, "connection refused"));
     assert!(format!("{}", io_err).contains("IO error: connection refused"));
 }
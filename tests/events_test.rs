@@ -0,0 +1,95 @@
+use rsocks5::events::{EventWriter, SessionEvent};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::{client, Server};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// An output that collects everything written to it
+#[derive(Clone, Default)]
+struct Collected(Arc<Mutex<Vec<u8>>>);
+
+impl Collected {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        let bytes = self.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+}
+
+impl Write for Collected {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_events_are_tagged_json_lines() {
+    let output = Collected::default();
+    let events = EventWriter::new(output.clone(), "memory").unwrap();
+    events.publish(&SessionEvent::Connected {
+        conn_id: "0badc0de".to_string(),
+        timestamp_ms: 1,
+        target: "example.com:443".to_string(),
+    });
+    events.close();
+
+    let lines = output.lines();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["event"], "connected");
+    assert_eq!(lines[0]["conn_id"], "0badc0de");
+    assert_eq!(lines[0]["target"], "example.com:443");
+}
+
+#[tokio::test]
+async fn test_server_reports_each_session_milestone() {
+    let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = TargetAddr::from(echo.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut socket, _) = echo.accept().await.unwrap();
+        let (mut reader, mut writer) = socket.split();
+        let _ = tokio::io::copy(&mut reader, &mut writer).await;
+    });
+
+    let output = Collected::default();
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_events(EventWriter::new(output.clone(), "memory").unwrap()),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::connect(&mut stream, &target, None).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut echoed = [0; 4];
+    stream.read_exact(&mut echoed).await.unwrap();
+    drop(stream);
+
+    let mut lines = Vec::new();
+    for _ in 0..50 {
+        lines = output.lines();
+        if lines.len() == 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let kinds: Vec<_> = lines.iter().map(|line| line["event"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["accepted", "authenticated", "connected", "closed"]);
+    assert!(lines.iter().all(|line| line["conn_id"] == lines[0]["conn_id"]));
+    assert_eq!(lines[0]["listener"], addr.to_string());
+    assert_eq!(lines[1]["method"], 0);
+    assert_eq!(lines[2]["target"], target.to_string());
+    assert_eq!(lines[3]["reason"], "completed");
+    assert_eq!(lines[3]["bytes_from_client"], 4);
+    assert_eq!(lines[3]["bytes_from_target"], 4);
+    server.shutdown();
+}
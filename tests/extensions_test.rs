@@ -0,0 +1,102 @@
+use rsocks5::acl::TargetAllowList;
+use rsocks5::extensions::{encode_denial_reason, DenialReasons, ExtensionRegistry, WireExtension};
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Sends the reply code back after failures, as version 2 of its format
+#[derive(Debug)]
+struct EchoCode;
+
+impl WireExtension for EchoCode {
+    fn name(&self) -> &'static str {
+        "echo-code"
+    }
+
+    fn method(&self) -> u8 {
+        0xE6
+    }
+
+    fn version(&self) -> u8 {
+        2
+    }
+
+    fn failure_trailer(&self, reply_code: u8, _reason: &str) -> Option<Vec<u8>> {
+        Some(vec![0xEC, reply_code])
+    }
+}
+
+/// Claims a method that is not private
+#[derive(Debug)]
+struct Public;
+
+impl WireExtension for Public {
+    fn name(&self) -> &'static str {
+        "public"
+    }
+
+    fn method(&self) -> u8 {
+        0x02
+    }
+}
+
+#[test]
+fn test_registry_validates_and_negotiates_offered_methods() {
+    let mut registry = ExtensionRegistry::new();
+    assert!(registry.register(Arc::new(Public)).is_err());
+    registry.register(Arc::new(DenialReasons)).unwrap();
+    registry.register(Arc::new(EchoCode)).unwrap();
+    // Registering again replaces rather than duplicates
+    registry.register(Arc::new(DenialReasons)).unwrap();
+    assert_eq!(registry.names().collect::<Vec<_>>(), ["denial-reasons", "echo-code"]);
+
+    let negotiated = registry.negotiate(&[0x00, 0xE6]);
+    assert!(negotiated.contains("echo-code"));
+    assert!(!negotiated.contains("denial-reasons"));
+    assert_eq!(negotiated.names(), ["echo-code/2"]);
+    assert_eq!(negotiated.failure_trailer(0x02, "denied"), [0xEC, 0x02]);
+    assert!(registry.negotiate(&[0x00]).is_empty());
+
+    let negotiated = registry.negotiate(&[0xE6, 0x00, 0xE5]);
+    let mut trailer = encode_denial_reason("denied");
+    trailer.extend_from_slice(&[0xEC, 0x02]);
+    assert_eq!(negotiated.failure_trailer(0x02, "denied"), trailer);
+
+    assert!(registry.unregister("echo-code"));
+    assert!(!registry.unregister("echo-code"));
+}
+
+#[test]
+fn test_denial_reason_is_cut_at_a_character_boundary() {
+    let reason = "é".repeat(200);
+    let encoded = encode_denial_reason(&reason);
+    assert_eq!(encoded[0], 254);
+    assert_eq!(encoded.len(), 255);
+}
+
+#[tokio::test]
+async fn test_negotiated_extension_appends_its_trailer() {
+    let mut registry = ExtensionRegistry::new();
+    registry.register(Arc::new(EchoCode)).unwrap();
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_allowed_targets(TargetAllowList::new())
+        .with_extensions(registry);
+    assert!(server.capabilities().has_extension("echo-code"));
+    let test_server = TestServer::start(server);
+    let request = [0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0, 80];
+
+    for (greeting, expected) in [(&[0x05, 0x02, 0x00, 0xE6][..], &[0xEC, 0x02][..]), (&[0x05, 0x01, 0x00][..], &[][..])] {
+        let mut stream = test_server.connect().unwrap();
+        stream.write_all(greeting).await.unwrap();
+        let mut selected = [0; 2];
+        stream.read_exact(&mut selected).await.unwrap();
+        assert_eq!(selected, [0x05, 0x00]);
+        stream.write_all(&request).await.unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply[..2], [0x05, 0x02]);
+        assert_eq!(&reply[10..], expected);
+    }
+    test_server.stop().await.unwrap();
+}
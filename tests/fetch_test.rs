@@ -0,0 +1,70 @@
+use rsocks5::fetch::{fetch, parse_response, HttpUrl};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::Server;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[test]
+fn test_url_parsing() {
+    let url = HttpUrl::parse("http://example.com:8080/a/b?q=1#top").unwrap();
+    assert_eq!(url.target, TargetAddr::Domain("example.com".to_string(), 8080));
+    assert_eq!(url.authority, "example.com:8080");
+    assert_eq!(url.path, "/a/b?q=1");
+
+    let url = HttpUrl::parse("http://10.0.0.1?x").unwrap();
+    assert_eq!(url.target, TargetAddr::Ipv4(Ipv4Addr::new(10, 0, 0, 1), 80));
+    assert_eq!(url.path, "/?x");
+
+    assert!(HttpUrl::parse("https://example.com/").is_err());
+    assert!(HttpUrl::parse("http:///path").is_err());
+}
+
+#[test]
+fn test_response_parsing_removes_chunked_coding() {
+    let response = parse_response(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n",
+    )
+    .unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.status_line, "HTTP/1.1 200 OK");
+    assert_eq!(response.headers, ["Transfer-Encoding: chunked"]);
+    assert_eq!(response.body, b"hello, world");
+
+    let response = parse_response(b"HTTP/1.0 404 Not Found\r\n\r\nmissing").unwrap();
+    assert_eq!(response.status, 404);
+    assert_eq!(response.body, b"missing");
+
+    assert!(parse_response(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+    assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5").is_err());
+}
+
+#[tokio::test]
+async fn test_fetch_through_the_proxy() {
+    let origin = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = origin.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = origin.accept().await {
+            let mut request = vec![0; 1024];
+            let n = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).to_string();
+            assert!(request.starts_with("GET /status HTTP/1.1\r\n"), "{}", request);
+            assert!(request.contains("Connection: close\r\n"));
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+        }
+    });
+
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let listener = server.bind().await.unwrap();
+    let proxy = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    // Resolved here, and by the proxy with socks5h
+    for remote_dns in [false, true] {
+        let url = format!("http://localhost:{}/status", port);
+        let response = fetch(&proxy, &url, remote_dns, None).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"ok");
+    }
+}
@@ -0,0 +1,78 @@
+use rsocks5::client::Credentials;
+use rsocks5::forward::Forwarder;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Serves a proxy on loopback and returns its address
+async fn proxy(server: Server) -> SocketAddr {
+    let server = Arc::new(server);
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+    addr
+}
+
+/// Serves an echo target on loopback and returns its address
+async fn echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = socket.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Serves a forwarder on loopback and returns its address
+async fn forward(forwarder: Forwarder) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { forwarder.serve(listener).await });
+    addr
+}
+
+#[tokio::test]
+async fn test_forwarded_connections_reach_the_target_through_the_proxy() {
+    let proxy = proxy(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_credentials("alice".to_string(), "secret".to_string()),
+    )
+    .await;
+    let forwarder = Forwarder::new(proxy.to_string(), TargetAddr::from(echo().await))
+        .with_credentials(Credentials::new("alice", "secret"));
+    assert_eq!(forwarder.proxy(), proxy.to_string());
+    let local = forward(forwarder).await;
+
+    // Every connection gets its own tunnel
+    for message in [&b"first"[..], &b"second"[..]] {
+        let mut stream = TcpStream::connect(local).await.unwrap();
+        stream.write_all(message).await.unwrap();
+        let mut echoed = vec![0; message.len()];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(echoed, message);
+    }
+}
+
+#[tokio::test]
+async fn test_refused_tunnels_close_the_local_connection() {
+    let proxy = proxy(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_credentials("alice".to_string(), "secret".to_string()),
+    )
+    .await;
+    let forwarder = Forwarder::new(proxy.to_string(), TargetAddr::from(echo().await))
+        .with_credentials(Credentials::new("alice", "wrong"));
+    let local = forward(forwarder).await;
+
+    let mut stream = TcpStream::connect(local).await.unwrap();
+    let mut buf = [0; 1];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+}
@@ -0,0 +1,90 @@
+use rsocks5::bandwidth::RateLimit;
+use rsocks5::clock::ManualClock;
+use rsocks5::geoip::{GeoDatabase, GeoInfo, Origin, OriginAction, OriginFilter, OriginRule, OriginVerdict};
+use rsocks5::metrics::CloseReason;
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+const DATABASE: &str = "\
+network,country,asn
+# documentation ranges
+192.0.2.0/24,nl,64500
+192.0.2.128/25,DE,
+198.51.100.0/24,,AS64501
+2001:db8::/32,US,64502
+";
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_database_prefers_most_specific_network() {
+    let database = GeoDatabase::from_csv(DATABASE).unwrap();
+    assert_eq!(database.len(), 4);
+
+    let nl = database.lookup(ip("192.0.2.10")).unwrap();
+    assert_eq!(nl, &GeoInfo { country: Some("NL".to_string()), asn: Some(64500) });
+    assert_eq!(database.lookup(ip("192.0.2.200")).unwrap().country.as_deref(), Some("DE"));
+    assert_eq!(database.lookup(ip("198.51.100.7")).unwrap().asn, Some(64501));
+    assert_eq!(database.lookup(ip("2001:db8::1")).unwrap().country.as_deref(), Some("US"));
+    assert_eq!(database.lookup(ip("::ffff:192.0.2.10")), Some(nl));
+    assert_eq!(database.lookup(ip("203.0.113.1")), None);
+
+    let error = GeoDatabase::from_csv("192.0.2.0/33,NL,1").unwrap_err();
+    assert!(error.to_string().contains("line 1"));
+    assert!(GeoDatabase::from_csv("192.0.2.0/24,Netherlands,1").is_err());
+}
+
+#[test]
+fn test_origin_rule_parsing() {
+    let rule: OriginRule = "as64500=deny".parse().unwrap();
+    assert_eq!(rule, OriginRule { origin: Origin::Asn(64500), action: OriginAction::Deny });
+    assert_eq!(rule.to_string(), "AS64500=deny");
+
+    let rule: OriginRule = "nl=5/20".parse().unwrap();
+    assert_eq!(rule.origin, Origin::Country("NL".to_string()));
+    assert_eq!(rule.action, OriginAction::RateLimit(RateLimit::new(5, 20)));
+
+    assert!("NL".parse::<OriginRule>().is_err());
+    assert!("Netherlands=deny".parse::<OriginRule>().is_err());
+}
+
+#[test]
+fn test_filter_denies_and_rate_limits_origins() {
+    let clock = Arc::new(ManualClock::new());
+    let database = Arc::new(GeoDatabase::from_csv(DATABASE).unwrap());
+    let filter = OriginFilter::new(database, clock.clone())
+        .with_rule("AS64501=deny".parse().unwrap())
+        .with_rule("NL=1/2".parse().unwrap());
+
+    assert_eq!(filter.check(ip("198.51.100.7")), OriginVerdict::Denied);
+    assert_eq!(filter.check(ip("192.0.2.200")), OriginVerdict::Allow);
+    assert_eq!(filter.check(ip("203.0.113.1")), OriginVerdict::Allow);
+
+    // The budget is shared by every client of the origin
+    assert_eq!(filter.check(ip("192.0.2.1")), OriginVerdict::Allow);
+    assert_eq!(filter.check(ip("192.0.2.2")), OriginVerdict::Allow);
+    assert_eq!(filter.check(ip("192.0.2.3")), OriginVerdict::RateLimited);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(filter.check(ip("192.0.2.3")), OriginVerdict::Allow);
+}
+
+#[tokio::test]
+async fn test_server_drops_filtered_clients() {
+    let database = Arc::new(GeoDatabase::from_csv("127.0.0.0/8,ZZ,").unwrap());
+    let filter = OriginFilter::new(database, Arc::new(ManualClock::new())).with_rule("ZZ=deny".parse().unwrap());
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_origin_filter(filter);
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    let mut buf = [0u8; 1];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(test_server.server().metrics().closed(CloseReason::OriginFiltered), 1);
+
+    test_server.stop().await.unwrap();
+}
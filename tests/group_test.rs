@@ -0,0 +1,66 @@
+use rsocks5::group::ServerGroup;
+use rsocks5::Server;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn local_server(username: Option<&str>) -> Server {
+    Server::new(
+        "127.0.0.1".to_string(),
+        Some(0),
+        username.map(str::to_string),
+        username.map(|_| "secret".to_string()),
+    )
+}
+
+async fn selected_method(addr: std::net::SocketAddr) -> u8 {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&[0x05, 0x02, 0x00, 0x02]).await.unwrap();
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await.unwrap();
+    choice[1]
+}
+
+#[test]
+fn test_server_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Server>();
+    assert_send_sync::<ServerGroup>();
+}
+
+#[tokio::test]
+async fn test_group_runs_servers_with_their_own_policies_and_stops_together() {
+    let group = Arc::new(
+        ServerGroup::new()
+            .with_server(local_server(None))
+            .with_server(local_server(Some("alice"))),
+    );
+    let listeners = group.bind().await.unwrap();
+    let addrs: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    let running = tokio::spawn({
+        let group = Arc::clone(&group);
+        async move { group.serve(listeners).await }
+    });
+
+    assert_eq!(selected_method(addrs[0]).await, 0x00);
+    assert_eq!(selected_method(addrs[1]).await, 0x02);
+    assert_eq!(group.servers()[0].stats().connections_accepted, 1);
+    assert_eq!(group.servers()[1].stats().connections_accepted, 1);
+
+    group.shutdown();
+    let result = tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap();
+    assert!(result.is_ok());
+    assert!(group.servers().iter().all(|server| server.is_shut_down()));
+}
+
+#[tokio::test]
+async fn test_group_does_not_serve_unless_all_servers_bind() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let group = ServerGroup::new()
+        .with_server(local_server(None))
+        .with_server(Server::new("127.0.0.1".to_string(), Some(port), None, None));
+
+    assert!(group.run().await.is_err());
+}
@@ -0,0 +1,228 @@
+use rsocks5::client;
+use rsocks5::error::Socks5Error;
+use rsocks5::hooks::{HookFuture, SessionHooks};
+use rsocks5::protocol::{HandshakeInfo, TargetAddr};
+use rsocks5::recent::{RecentQuery, SessionRecord};
+use rsocks5::testing::TestServer;
+use rsocks5::watchdog::{FailMode, Hook, HookBudget, Watchdog};
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Writes down every milestone it is told about
+#[derive(Debug, Default)]
+struct Recording {
+    milestones: Mutex<Vec<String>>,
+    closed: Mutex<Option<SessionRecord>>,
+}
+
+impl Recording {
+    fn record(&self, milestone: String) {
+        self.milestones.lock().unwrap().push(milestone);
+    }
+
+    /// Waits for the session to close and returns its milestones and summary
+    async fn wait_closed(&self) -> (Vec<String>, SessionRecord) {
+        for _ in 0..200 {
+            if let Some(summary) = self.closed.lock().unwrap().clone() {
+                return (self.milestones.lock().unwrap().clone(), summary);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("session did not close: {:?}", self.milestones.lock().unwrap());
+    }
+}
+
+impl SessionHooks for Recording {
+    fn on_accept<'a>(&'a self, _conn_id: u32, _client: SocketAddr, listener: &'a str) -> HookFuture<'a> {
+        Box::pin(async move { self.record(format!("accept on {}", listener)) })
+    }
+
+    fn on_auth<'a>(&'a self, _conn_id: u32, username: Option<&'a str>, method: u8) -> HookFuture<'a> {
+        Box::pin(async move { self.record(format!("auth {:?} with {}", username, method)) })
+    }
+
+    fn on_request<'a>(
+        &'a self,
+        _conn_id: u32,
+        target: &'a TargetAddr,
+        handshake: Option<&'a HandshakeInfo>,
+    ) -> HookFuture<'a> {
+        let offered = handshake.map(|handshake| handshake.offered_methods.to_vec());
+        Box::pin(async move { self.record(format!("request {} offering {:?}", target, offered)) })
+    }
+
+    fn on_connect_result<'a>(
+        &'a self,
+        _conn_id: u32,
+        target: &'a TargetAddr,
+        result: Result<Duration, &'a Socks5Error>,
+    ) -> HookFuture<'a> {
+        Box::pin(async move { self.record(format!("connect {} ok={}", target, result.is_ok())) })
+    }
+
+    fn on_close<'a>(&'a self, summary: &'a SessionRecord) -> HookFuture<'a> {
+        Box::pin(async move {
+            self.record(format!("close {}", summary.reason));
+            *self.closed.lock().unwrap() = Some(summary.clone());
+        })
+    }
+}
+
+/// Takes its time when a client connects, and only then
+#[derive(Debug)]
+struct SlowAccept;
+
+impl SessionHooks for SlowAccept {
+    fn on_accept<'a>(&'a self, _conn_id: u32, _client: SocketAddr, _listener: &'a str) -> HookFuture<'a> {
+        Box::pin(std::future::pending())
+    }
+}
+
+/// Panics when a client asks for a target, and records the close
+#[derive(Debug, Default)]
+struct PanicOnRequest(Recording);
+
+impl SessionHooks for PanicOnRequest {
+    fn on_request<'a>(
+        &'a self,
+        _conn_id: u32,
+        _target: &'a TargetAddr,
+        _handshake: Option<&'a HandshakeInfo>,
+    ) -> HookFuture<'a> {
+        Box::pin(async { panic!("hook failed") })
+    }
+
+    fn on_close<'a>(&'a self, summary: &'a SessionRecord) -> HookFuture<'a> {
+        self.0.on_close(summary)
+    }
+}
+
+#[tokio::test]
+async fn test_hooks_follow_a_session_from_accept_to_close() {
+    // A target that echoes four bytes and waits for the client to hang up
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut stream, _) = target.accept().await.unwrap();
+        let mut request = [0; 4];
+        stream.read_exact(&mut request).await.unwrap();
+        stream.write_all(&request).await.unwrap();
+        let _ = stream.read_to_end(&mut Vec::new()).await;
+    });
+
+    let hooks = Arc::new(Recording::default());
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_session_hooks(hooks.clone());
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    client::connect(&mut stream, &target_addr, None).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await.unwrap();
+    drop(stream);
+
+    let (milestones, summary) = hooks.wait_closed().await;
+    let listener = summary.listener.clone();
+    assert_eq!(
+        milestones,
+        [
+            format!("accept on {}", listener),
+            "auth None with 0".to_string(),
+            format!("request {} offering {:?}", target_addr, Some(vec![0x00])),
+            format!("connect {} ok=true", target_addr),
+            "close completed".to_string(),
+        ]
+    );
+    assert_eq!(summary.target, Some(target_addr));
+    assert_eq!((summary.bytes_from_client, summary.bytes_from_target), (4, 4));
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_hooks_see_failed_connects() {
+    // Nothing listens on the port once the listener is dropped
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(closed.local_addr().unwrap());
+    drop(closed);
+
+    let hooks = Arc::new(Recording::default());
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_session_hooks(hooks.clone());
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    assert!(client::connect(&mut stream, &target_addr, None).await.is_err());
+
+    let (milestones, summary) = hooks.wait_closed().await;
+    assert!(milestones.contains(&format!("connect {} ok=false", target_addr)), "{:?}", milestones);
+    assert_eq!(summary.reason, "error");
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_socks4_requests_have_no_handshake() {
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let hooks = Arc::new(Recording::default());
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_session_hooks(hooks.clone());
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&[127, 0, 0, 1, 0]);
+    stream.write_all(&request).await.unwrap();
+
+    let (milestones, _) = hooks.wait_closed().await;
+    let target = TargetAddr::Ipv4("127.0.0.1".parse().unwrap(), port);
+    assert!(milestones.contains(&format!("request {} offering None", target)), "{:?}", milestones);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_overrunning_hooks_do_not_hold_up_sessions() {
+    let budget = HookBudget { timeout: Duration::from_millis(50), fail: FailMode::Closed };
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_session_hooks(Arc::new(SlowAccept))
+        .with_watchdog(Watchdog::new().with_budget(Hook::SessionHooks, budget));
+    let test_server = TestServer::start(server);
+
+    // The session gets as far as a reply despite the hanging callback
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = TargetAddr::from(closed.local_addr().unwrap());
+    drop(closed);
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(!matches!(result, Err(Socks5Error::HandshakeError(_))), "{:?}", result);
+    assert_eq!(test_server.server().metrics().hook_overruns(Hook::SessionHooks), 1);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sessions_that_panic_are_still_closed() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+
+    let hooks = Arc::new(PanicOnRequest::default());
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_session_hooks(hooks.clone())
+        .with_recent_sessions(4);
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    assert!(client::connect(&mut stream, &target_addr, None).await.is_err());
+
+    let (_, summary) = hooks.0.wait_closed().await;
+    assert_eq!(summary.reason, "panic");
+    assert_eq!(summary.target, Some(target_addr));
+    let server = test_server.server();
+    assert_eq!(server.metrics().panics(), 1);
+    let recent = server.recent_sessions().unwrap().query(&RecentQuery::new());
+    assert_eq!(recent.iter().map(|record| record.reason).collect::<Vec<_>>(), ["panic"]);
+    test_server.stop().await.unwrap();
+}
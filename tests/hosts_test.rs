@@ -0,0 +1,48 @@
+use rsocks5::connection::Connector;
+use rsocks5::hosts::Hosts;
+use rsocks5::protocol::TargetAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+/// Returns a path for one test's hosts file
+fn scratch_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rsocks5-hosts-{}-{}", name, std::process::id()))
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_hosts_file_is_parsed_and_reloaded() {
+    let path = scratch_file("reload");
+    std::fs::write(&path, "# lab targets\n10.0.0.5 db.test database.test\n::1 db.test # also v6\n\n").unwrap();
+
+    let hosts = Hosts::load(&path).unwrap().with_entry("cache.test", ip("10.0.0.9"));
+    assert_eq!(hosts.len(), 3);
+    assert_eq!(hosts.lookup("DB.test."), Some(vec![ip("10.0.0.5"), ip("::1")]));
+    assert_eq!(hosts.lookup("cache.test"), Some(vec![ip("10.0.0.9")]));
+    assert_eq!(hosts.lookup("other.test"), None);
+    assert!(!hosts.reload().unwrap());
+
+    std::fs::write(&path, "10.0.0.6 db.test cache.test\n").unwrap();
+    assert!(hosts.reload().unwrap());
+    assert_eq!(hosts.lookup("db.test"), Some(vec![ip("10.0.0.6")]));
+    assert_eq!(hosts.lookup("database.test"), None);
+    // Fixed entries win over the file and survive reloads
+    assert_eq!(hosts.lookup("cache.test"), Some(vec![ip("10.0.0.9")]));
+
+    // A broken file keeps the previous entries
+    std::fs::write(&path, "not-an-address db.test\n").unwrap();
+    assert!(hosts.reload().is_err());
+    assert_eq!(hosts.lookup("db.test"), Some(vec![ip("10.0.0.6")]));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_connector_resolves_overrides_before_dns() {
+    let connector = Connector::new().with_hosts(Hosts::new().with_entry("db.test", ip("127.0.0.1")));
+    let addrs = connector.resolve(&TargetAddr::Domain("db.test".to_string(), 5432)).await.unwrap();
+    assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 5432))]);
+}
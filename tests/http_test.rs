@@ -0,0 +1,100 @@
+use rsocks5::http::{is_http_start, parse_request};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn test_parse_rewrites_absolute_uri_requests() {
+    let request = parse_request(
+        b"GET http://Example.com:8080/index.html?q=1 HTTP/1.0\r\n\
+          Proxy-Connection: keep-alive\r\n\
+          Connection: X-Trace\r\n\
+          X-Trace: 1\r\n\
+          Accept: */*\r\n\r\n",
+    )
+    .unwrap();
+    assert_eq!(request.method, "GET");
+    assert_eq!(request.target, TargetAddr::Domain("Example.com".to_string(), 8080));
+    assert_eq!(
+        String::from_utf8(request.head).unwrap(),
+        "GET /index.html?q=1 HTTP/1.0\r\nAccept: */*\r\nHost: Example.com:8080\r\nConnection: close\r\n\r\n"
+    );
+
+    let request = parse_request(b"HEAD http://[::1] HTTP/1.1\r\nHost: [::1]\r\n\r\n").unwrap();
+    assert_eq!(request.target, TargetAddr::Ipv6("::1".parse().unwrap(), 80));
+    assert_eq!(String::from_utf8(request.head).unwrap(), "HEAD / HTTP/1.1\r\nHost: [::1]\r\nConnection: close\r\n\r\n");
+
+    assert!(parse_request(b"GET /index.html HTTP/1.1\r\n\r\n").is_err());
+    assert!(parse_request(b"CONNECT example.com:443 HTTP/1.1\r\n\r\n").is_err());
+    assert!(parse_request(b"GET https://example.com/ HTTP/1.1\r\n\r\n").is_err());
+    assert!(is_http_start(b'G') && !is_http_start(0x05) && !is_http_start(0x04));
+}
+
+#[tokio::test]
+async fn test_server_forwards_http_requests() {
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut socket, _) = target.accept().await.unwrap();
+        let mut request = Vec::new();
+        socket.read_to_end(&mut request).await.unwrap();
+        let response = format!("HTTP/1.0 200 OK\r\n\r\n{}", String::from_utf8(request).unwrap());
+        socket.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_http_forward(true);
+    assert!(server.capabilities().extensions.contains(&"http-forward"));
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    let request = format!("POST http://127.0.0.1:{}/submit HTTP/1.0\r\nContent-Length: 4\r\n\r\nbody", target_port);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert_eq!(
+        response,
+        format!(
+            "HTTP/1.0 200 OK\r\n\r\nPOST /submit HTTP/1.0\r\nContent-Length: 4\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\n\r\nbody",
+            target_port
+        )
+    );
+
+    // Refused targets get an HTTP error instead of a SOCKS reply
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(b"GET http://127.0.0.1:0/ HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_http_requests_are_mirrored() {
+    use rsocks5::mirror::RequestMirror;
+    use tokio::net::UdpSocket;
+
+    let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mirror = RequestMirror::connect(&format!("udp://{}", collector.local_addr().unwrap())).await.unwrap();
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_http_forward(true)
+        .with_request_mirror(mirror);
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(b"GET http://example.com:0/ HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let mut buf = [0; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&buf[..n]).unwrap();
+    assert_eq!(event["protocol"], "http");
+    assert_eq!(event["target"], "example.com:0");
+    assert_eq!(event["offered_methods"], serde_json::json!([]));
+
+    test_server.stop().await.unwrap();
+}
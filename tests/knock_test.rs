@@ -0,0 +1,79 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::knock::{KnockConfig, KnockGate};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+fn gate(clock: Arc<ManualClock>) -> KnockGate {
+    KnockGate::new(KnockConfig::new(vec![7000, 8000, 9000], Duration::from_secs(30)), clock)
+}
+
+#[test]
+fn test_correct_sequence_opens_gate() {
+    let gate = gate(Arc::new(ManualClock::new()));
+    assert!(!gate.is_open(CLIENT));
+
+    for port in [7000, 8000, 9000] {
+        gate.record_knock(CLIENT, port);
+    }
+    assert!(gate.is_open(CLIENT));
+    assert!(!gate.is_open(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+}
+
+#[test]
+fn test_wrong_order_keeps_gate_closed() {
+    let gate = gate(Arc::new(ManualClock::new()));
+    for port in [7000, 9000, 8000] {
+        gate.record_knock(CLIENT, port);
+    }
+    assert!(!gate.is_open(CLIENT));
+
+    // Restarting the sequence still works
+    for port in [7000, 8000, 9000] {
+        gate.record_knock(CLIENT, port);
+    }
+    assert!(gate.is_open(CLIENT));
+}
+
+#[test]
+fn test_slow_knocks_and_window_expiry() {
+    let clock = Arc::new(ManualClock::new());
+    let gate = gate(Arc::clone(&clock));
+
+    // Too long between knocks restarts the sequence
+    gate.record_knock(CLIENT, 7000);
+    clock.advance(Duration::from_secs(11));
+    gate.record_knock(CLIENT, 8000);
+    gate.record_knock(CLIENT, 9000);
+    assert!(!gate.is_open(CLIENT));
+
+    // A completed sequence expires after the window
+    for port in [7000, 8000, 9000] {
+        gate.record_knock(CLIENT, port);
+    }
+    clock.advance(Duration::from_secs(29));
+    assert!(gate.is_open(CLIENT));
+    clock.advance(Duration::from_secs(2));
+    assert!(!gate.is_open(CLIENT));
+}
+
+#[test]
+fn test_spoofed_flood_is_bounded_and_spares_active_clients() {
+    let gate = gate(Arc::new(ManualClock::new()));
+    for port in [7000, 8000, 9000] {
+        gate.record_knock(CLIENT, port);
+    }
+
+    // Half-finished sequences from spoofed sources stay live, so only
+    // eviction keeps the table bounded
+    for i in 0..20_000u32 {
+        gate.record_knock(IpAddr::V6(u128::from(i).into()), 7000);
+        if i % 1000 == 0 {
+            assert!(gate.is_open(CLIENT));
+        }
+    }
+    assert!(gate.is_open(CLIENT));
+    assert!(gate.evictions() > 0);
+}
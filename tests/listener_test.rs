@@ -0,0 +1,151 @@
+use rsocks5::listener::{peek, Peekable};
+use rsocks5::testing::MemoryListener;
+use rsocks5::Server;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Starts a target that echoes one message back
+async fn echo_target() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_peekable_returns_peeked_bytes_on_read() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut stream = Peekable::new(server);
+    client.write_all(b"hello").await.unwrap();
+
+    let mut first = [0; 1];
+    assert_eq!(peek(&mut stream, &mut first).await.unwrap(), 1);
+    assert_eq!(&first, b"h");
+
+    let mut all = [0; 5];
+    stream.read_exact(&mut all).await.unwrap();
+    assert_eq!(&all, b"hello");
+}
+
+#[tokio::test]
+async fn test_server_serves_in_memory_connections() {
+    let target = echo_target().await;
+    let (listener, connector) = MemoryListener::new();
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let running = tokio::spawn({
+        let server = Arc::clone(&server);
+        async move { server.serve(listener).await }
+    });
+
+    let mut client = connector.connect().unwrap();
+    client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut choice = [0; 2];
+    client.read_exact(&mut choice).await.unwrap();
+    assert_eq!(choice, [0x05, 0x00]);
+
+    let ip = match target.ip() {
+        std::net::IpAddr::V4(ip) => ip.octets(),
+        std::net::IpAddr::V6(_) => unreachable!(),
+    };
+    let port = target.port().to_be_bytes();
+    client.write_all(&[0x05, 0x01, 0x00, 0x01, ip[0], ip[1], ip[2], ip[3], port[0], port[1]]).await.unwrap();
+    let mut reply = [0; 10];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00);
+
+    client.write_all(b"hello").await.unwrap();
+    let mut echoed = [0; 5];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+
+    server.shutdown();
+    running.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_from_listener_accepts_on_the_given_listener() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Arc::new(
+        Server::from_listener(listener)
+            .unwrap()
+            .with_credentials("alice".to_string(), "secret".to_string()),
+    );
+    assert_eq!(server.port(), addr.port());
+    tokio::spawn({
+        let server = Arc::clone(&server);
+        async move { server.run().await }
+    });
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut choice = [0; 2];
+    client.read_exact(&mut choice).await.unwrap();
+    assert_eq!(choice, [0x05, 0x02]);
+    assert_eq!(server.listener_label().address, addr.to_string());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_server_serves_unix_socket_connections() {
+    let path = std::env::temp_dir().join(format!("rsocks5-listener-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    tokio::spawn({
+        let server = Arc::clone(&server);
+        async move { server.serve(listener).await }
+    });
+
+    let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+    client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut choice = [0; 2];
+    client.read_exact(&mut choice).await.unwrap();
+    assert_eq!(choice, [0x05, 0x00]);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_tcp_listener_reports_its_backlog() {
+    use rsocks5::listener::Listener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let backlog = Listener::backlog(&listener).unwrap();
+    assert!(backlog.limit > 0);
+    assert_eq!(backlog.queued, 1);
+}
+
+#[tokio::test]
+async fn test_server_samples_accepts() {
+    let (listener, connector) = MemoryListener::new();
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_accept_sampling(2));
+    let running = tokio::spawn({
+        let server = Arc::clone(&server);
+        async move { server.serve(listener).await }
+    });
+
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        let mut client = connector.connect().unwrap();
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut choice = [0; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        clients.push(client);
+    }
+
+    let stats = server.stats();
+    assert_eq!(stats.connections_accepted, 4);
+    assert_eq!(stats.accept.samples, 2);
+    assert_eq!(stats.accept.admission_drops, 0);
+
+    server.shutdown();
+    running.await.unwrap().unwrap();
+}
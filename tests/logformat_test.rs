@@ -0,0 +1,51 @@
+use log::{Level, Record};
+use rsocks5::logformat::{json_line, LogFormat};
+use serde_json::{json, Value};
+
+#[test]
+fn test_log_format_parses_names() {
+    assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert_eq!("TEXT".parse::<LogFormat>().unwrap(), LogFormat::Text);
+    assert_eq!(LogFormat::default(), LogFormat::Text);
+    assert_eq!(LogFormat::Json.to_string(), "json");
+    assert!("logfmt".parse::<LogFormat>().is_err());
+}
+
+#[test]
+fn test_json_line_carries_message_and_fields() {
+    let fields: &[(&str, &str)] = &[("event", "closed"), ("conn_id", "0badc0de")];
+    let line = json_line(
+        &Record::builder()
+            .level(Level::Info)
+            .target("rsocks5::server")
+            .args(format_args!("Session with {} ended", "192.0.2.1:5000"))
+            .key_values(&fields)
+            .build(),
+        1_700_000_000_000,
+    );
+    assert!(!line.contains('\n'));
+    let object: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(
+        object,
+        json!({
+            "timestamp_ms": 1_700_000_000_000u64,
+            "level": "info",
+            "target": "rsocks5::server",
+            "message": "Session with 192.0.2.1:5000 ended",
+            "event": "closed",
+            "conn_id": "0badc0de",
+        })
+    );
+}
+
+#[test]
+fn test_json_line_keeps_numbers_typed_and_reserved_fields() {
+    let fields: &[(&str, u64)] = &[("bytes_from_client", 42), ("level", 7)];
+    let line = json_line(
+        &Record::builder().level(Level::Warn).args(format_args!("done")).key_values(&fields).build(),
+        0,
+    );
+    let object: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(object["bytes_from_client"], json!(42));
+    assert_eq!(object["level"], json!("warn"));
+}
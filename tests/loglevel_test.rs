@@ -0,0 +1,19 @@
+use log::LevelFilter;
+use rsocks5::loglevel::LogLevelControl;
+
+#[test]
+fn test_cycle_steps_up_and_wraps_to_base() {
+    let control = LogLevelControl::new(LevelFilter::Info);
+    assert_eq!(control.current(), LevelFilter::Info);
+
+    assert_eq!(control.cycle(), LevelFilter::Debug);
+    assert_eq!(control.cycle(), LevelFilter::Trace);
+    assert_eq!(control.cycle(), LevelFilter::Info);
+    assert_eq!(log::max_level(), LevelFilter::Info);
+
+    // A level set below the base cycles back to the base
+    control.set(LevelFilter::Error);
+    assert_eq!(control.current(), LevelFilter::Error);
+    assert_eq!(control.cycle(), LevelFilter::Info);
+    assert_eq!(control.base(), LevelFilter::Info);
+}
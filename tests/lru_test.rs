@@ -0,0 +1,49 @@
+use rsocks5::lru::LruMap;
+
+#[test]
+fn test_full_map_evicts_least_recently_used() {
+    let mut map = LruMap::new(2);
+    map.insert("a", 1);
+    map.insert("b", 2);
+
+    // Touching "a" leaves "b" as the oldest entry
+    assert_eq!(map.get_mut(&"a"), Some(&mut 1));
+    map.insert("c", 3);
+
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.peek(&"a"), Some(&1));
+    assert_eq!(map.peek(&"b"), None);
+    assert_eq!(map.peek(&"c"), Some(&3));
+    assert_eq!(map.evictions(), 1);
+}
+
+#[test]
+fn test_updates_and_removals_do_not_evict() {
+    let mut map = LruMap::new(2);
+    assert_eq!(map.insert("a", 1), None);
+    assert_eq!(map.insert("b", 2), None);
+    assert_eq!(map.insert("a", 10), Some(1));
+    *map.get_or_insert_with("b", || 0) += 1;
+    assert_eq!(map.remove(&"b"), Some(3));
+    map.retain(|_, value| *value > 100);
+
+    assert!(map.is_empty());
+    assert_eq!(map.evictions(), 0);
+
+    // Removed entries no longer count towards the capacity
+    map.insert("c", 3);
+    map.insert("d", 4);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.evictions(), 0);
+}
+
+#[test]
+fn test_peek_does_not_refresh_entries() {
+    let mut map = LruMap::new(2);
+    map.insert(1, "one");
+    map.insert(2, "two");
+    assert_eq!(map.peek(&1), Some(&"one"));
+    map.insert(3, "three");
+    assert_eq!(map.peek(&1), None);
+    assert_eq!(map.capacity(), 2);
+}
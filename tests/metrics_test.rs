@@ -0,0 +1,173 @@
+use rsocks5::metrics::{CloseReason, Metrics};
+use std::time::Duration;
+
+#[test]
+fn test_metrics_accept_and_close() {
+    let metrics = Metrics::new();
+    metrics.record_accept();
+    metrics.record_accept();
+    assert_eq!(metrics.connections_accepted(), 2);
+    assert_eq!(metrics.active_connections(), 2);
+
+    metrics.record_close(CloseReason::FirstByteTimeout);
+    assert_eq!(metrics.active_connections(), 1);
+    assert_eq!(metrics.closed(CloseReason::FirstByteTimeout), 1);
+    assert_eq!(metrics.closed(CloseReason::Completed), 0);
+}
+
+#[test]
+fn test_close_reason_names() {
+    assert_eq!(CloseReason::Completed.to_string(), "completed");
+    assert_eq!(CloseReason::FirstByteTimeout.as_str(), "first_byte_timeout");
+}
+
+#[test]
+fn test_metrics_shadow_denials() {
+    let metrics = Metrics::new();
+    assert_eq!(metrics.shadow_denials(), 0);
+    metrics.record_shadow_denial();
+    assert_eq!(metrics.shadow_denials(), 1);
+}
+
+#[test]
+fn test_snapshot_carries_listener_label() {
+    use rsocks5::compat::Quirk;
+    use rsocks5::metrics::ListenerLabel;
+
+    let metrics = Metrics::new();
+    metrics.record_accept();
+    metrics.record_accept();
+    metrics.record_close(CloseReason::Denied);
+    metrics.record_quirk(Quirk::ReservedByte);
+    let label = ListenerLabel {
+        address: "127.0.0.1:1080".to_string(),
+        protocol: "socks5".to_string(),
+        tls: false,
+    };
+
+    let stats = metrics.snapshot(label.clone());
+    assert_eq!(stats.listener, label);
+    assert_eq!(stats.active_connections, 1);
+    assert_eq!(stats.closed.get("denied"), Some(&1));
+    assert_eq!(stats.closed.get("completed"), None);
+    assert_eq!(
+        stats.to_string(),
+        "listener=\"socks5 127.0.0.1:1080 tls=no\" accepted=2 active=1 closed.denied=1 quirk.reserved-byte=1"
+    );
+}
+
+#[tokio::test]
+async fn test_connection_guard_records_panics() {
+    use std::sync::Arc;
+
+    let metrics = Arc::new(Metrics::new());
+    let guard = metrics.track();
+    assert_eq!(metrics.active_connections(), 1);
+    let task = tokio::spawn(async move {
+        let _guard = guard;
+        panic!("handler bug");
+    });
+    assert!(task.await.unwrap_err().is_panic());
+
+    assert_eq!(metrics.active_connections(), 0);
+    assert_eq!(metrics.closed(CloseReason::Panic), 1);
+    assert_eq!(metrics.panics(), 1);
+
+    // The counters keep working for later connections
+    metrics.track().close(CloseReason::Completed);
+    assert_eq!(metrics.connections_accepted(), 2);
+    assert_eq!(metrics.closed(CloseReason::Completed), 1);
+    assert_eq!(metrics.active_connections(), 0);
+}
+
+#[tokio::test]
+async fn test_connection_guard_records_aborted_tasks() {
+    use std::sync::Arc;
+
+    let metrics = Arc::new(Metrics::new());
+    let guard = metrics.track();
+    let task = tokio::spawn(async move {
+        let _guard = guard;
+        std::future::pending::<()>().await;
+    });
+    task.abort();
+    assert!(task.await.unwrap_err().is_cancelled());
+
+    assert_eq!(metrics.active_connections(), 0);
+    assert_eq!(metrics.closed(CloseReason::Aborted), 1);
+    assert_eq!(metrics.panics(), 0);
+}
+
+#[test]
+fn test_snapshot_counts_priority_classes() {
+    use rsocks5::bandwidth::Priority;
+    use rsocks5::metrics::ListenerLabel;
+
+    let metrics = Metrics::new();
+    metrics.record_priority(Priority::Bulk);
+    metrics.record_priority(Priority::Bulk);
+    assert_eq!(metrics.priority_connections(Priority::Bulk), 2);
+    assert_eq!(metrics.priority_connections(Priority::High), 0);
+
+    let stats = metrics.snapshot(ListenerLabel {
+        address: "127.0.0.1:1080".to_string(),
+        protocol: "socks5".to_string(),
+        tls: false,
+    });
+    assert_eq!(stats.priorities.get("bulk"), Some(&2));
+    assert!(stats.to_string().ends_with(" priority.bulk=2"));
+}
+
+#[tokio::test]
+async fn test_process_stats() {
+    use rsocks5::metrics::{ListenerLabel, ProcessStats};
+
+    let process = ProcessStats::collect(Duration::from_millis(61_500));
+    assert_eq!(process.uptime_secs, 61);
+    assert!(process.alive_tasks.is_some());
+    if cfg!(target_os = "linux") {
+        assert!(process.rss_bytes.is_some_and(|rss| rss > 0));
+        assert!(process.open_fds.is_some_and(|fds| fds > 0));
+    }
+
+    let mut stats = Metrics::new().snapshot(ListenerLabel {
+        address: "127.0.0.1:1080".to_string(),
+        protocol: "socks5".to_string(),
+        tls: false,
+    });
+    assert!(stats.process.is_none());
+    stats.process = Some(ProcessStats {
+        uptime_secs: 61,
+        rss_bytes: Some(4096),
+        open_fds: None,
+        alive_tasks: Some(3),
+    });
+    assert!(stats.to_string().ends_with(" uptime=61s rss=4096 tasks=3"));
+}
+
+#[test]
+fn test_accept_samples_and_admission_drops() {
+    use rsocks5::listener::Backlog;
+
+    let metrics = Metrics::new();
+    assert_eq!(metrics.accept_stats().samples, 0);
+    assert_eq!(metrics.accept_stats().backlog, None);
+
+    metrics.record_accept_sample(Duration::from_micros(100), None, Some(Backlog { queued: 3, limit: 128 }));
+    metrics.record_accept_sample(Duration::from_micros(300), Some(Duration::from_millis(2)), Some(Backlog { queued: 0, limit: 128 }));
+    metrics.record_accept();
+    metrics.record_close(CloseReason::Overloaded);
+    metrics.record_accept();
+    metrics.record_close(CloseReason::Completed);
+
+    let stats = metrics.accept_stats();
+    assert_eq!(stats.samples, 2);
+    assert_eq!(stats.delay_avg_us, 200);
+    assert_eq!(stats.delay_max_us, 300);
+    assert_eq!(stats.queued, 1);
+    assert_eq!(stats.queue_wait_max_us, 2000);
+    assert_eq!(stats.admission_drops, 1);
+    assert_eq!(stats.backlog, Some(0));
+    assert_eq!(stats.backlog_peak, Some(3));
+    assert_eq!(stats.backlog_limit, Some(128));
+}
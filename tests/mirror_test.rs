@@ -0,0 +1,59 @@
+use rsocks5::mirror::{RequestEvent, RequestMirror};
+use tokio::net::UdpSocket;
+
+fn sample_event() -> RequestEvent {
+    RequestEvent {
+        conn_id: "0000beef".to_string(),
+        timestamp_ms: 1_700_000_000_000,
+        client: "127.0.0.1:40000".parse().unwrap(),
+        protocol: "socks5",
+        target: "example.com:443".to_string(),
+        rewritten_target: None,
+        username: Some("alice".to_string()),
+        method: 0x02,
+        offered_methods: vec![0x00, 0x02],
+        decision: None,
+    }
+}
+
+#[tokio::test]
+async fn test_mirror_publishes_json_over_udp() {
+    let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("udp://{}", collector.local_addr().unwrap());
+    let mirror = RequestMirror::connect(&url).await.unwrap();
+    assert_eq!(mirror.destination(), url);
+
+    mirror.publish(&sample_event());
+
+    let mut buf = [0; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&buf[..n]).unwrap();
+    assert_eq!(event["conn_id"], "0000beef");
+    assert_eq!(event["client"], "127.0.0.1:40000");
+    assert_eq!(event["target"], "example.com:443");
+    assert_eq!(event["username"], "alice");
+    assert_eq!(event["offered_methods"], serde_json::json!([0, 2]));
+    assert_eq!(mirror.dropped(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_mirror_publishes_over_unix_socket() {
+    let path = std::env::temp_dir().join(format!("rsocks5-mirror-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let collector = tokio::net::UnixDatagram::bind(&path).unwrap();
+
+    let mirror = RequestMirror::connect(&format!("unix://{}", path.display())).await.unwrap();
+    mirror.publish(&sample_event());
+
+    let mut buf = [0; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&buf[..n]).unwrap();
+    assert_eq!(event["method"], 2);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_mirror_rejects_unknown_scheme() {
+    assert!(RequestMirror::connect("nats://localhost:4222").await.is_err());
+}
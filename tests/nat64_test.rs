@@ -0,0 +1,43 @@
+use rsocks5::connection::Connector;
+use rsocks5::nat64::{Nat64Prefix, WELL_KNOWN_PREFIX};
+use rsocks5::protocol::TargetAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+#[test]
+fn test_synthesize_with_well_known_prefix() {
+    let prefix = Nat64Prefix::default();
+    assert_eq!(prefix.addr(), WELL_KNOWN_PREFIX);
+
+    let synthesized = prefix.synthesize(Ipv4Addr::new(192, 0, 2, 33));
+    assert_eq!(synthesized, "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap());
+}
+
+#[test]
+fn test_parse_prefix() {
+    let prefix: Nat64Prefix = "2001:db8:64::/96".parse().unwrap();
+    assert_eq!(prefix.to_string(), "2001:db8:64::/96");
+
+    // The low 32 bits of the prefix are ignored
+    let prefix: Nat64Prefix = "64:ff9b::1.2.3.4".parse().unwrap();
+    assert_eq!(prefix, Nat64Prefix::default());
+
+    assert!("64:ff9b::/64".parse::<Nat64Prefix>().is_err());
+    assert!("not-an-address".parse::<Nat64Prefix>().is_err());
+}
+
+#[tokio::test]
+async fn test_connector_translates_ipv4_literals() {
+    let connector = Connector::new().with_nat64(Nat64Prefix::default());
+    let target = TargetAddr::Ipv4(Ipv4Addr::new(198, 51, 100, 7), 443);
+
+    let addrs = connector.resolve(&target).await.unwrap();
+    let expected: SocketAddr = "[64:ff9b::c633:6407]:443".parse().unwrap();
+    assert_eq!(addrs, vec![expected]);
+}
+
+#[tokio::test]
+async fn test_connector_without_nat64_keeps_ipv4() {
+    let target = TargetAddr::Ipv4(Ipv4Addr::new(198, 51, 100, 7), 443);
+    let addrs = Connector::new().resolve(&target).await.unwrap();
+    assert_eq!(addrs, vec!["198.51.100.7:443".parse::<SocketAddr>().unwrap()]);
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "natpmp")]
+
+use rsocks5::natpmp::{encode_map_request, parse_map_response, PortMapper};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+#[test]
+fn test_map_request_round_trip() {
+    let request = encode_map_request(1080, 1080, Duration::from_secs(7200));
+    assert_eq!(request, [0, 2, 0, 0, 0x04, 0x38, 0x04, 0x38, 0, 0, 0x1c, 0x20]);
+
+    let response = [0, 130, 0, 0, 0, 0, 0, 1, 0x04, 0x38, 0x27, 0x10, 0, 0, 0x0e, 0x10];
+    assert_eq!(parse_map_response(&response).unwrap(), (10000, Duration::from_secs(3600)));
+    let refused = [0, 130, 0, 2, 0, 0, 0, 1, 0x04, 0x38, 0, 0, 0, 0, 0, 0];
+    assert!(parse_map_response(&refused).is_err());
+}
+
+#[tokio::test]
+async fn test_mapper_requests_mapping_and_external_address() {
+    let gateway = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mapper = PortMapper::with_address(gateway.local_addr().unwrap()).with_lifetime(Duration::from_secs(600));
+    tokio::spawn(async move {
+        let mut buf = [0; 16];
+        loop {
+            let (n, client) = gateway.recv_from(&mut buf).await.unwrap();
+            let response = match &buf[..n] {
+                [0, 0] => vec![0, 128, 0, 0, 0, 0, 0, 1, 203, 0, 113, 7],
+                [0, 2, 0, 0, internal @ .., _, _, _, _, _, _] => {
+                    let mut response = vec![0, 130, 0, 0, 0, 0, 0, 1];
+                    response.extend_from_slice(internal);
+                    response.extend_from_slice(&[0x27, 0x10]);
+                    response.extend_from_slice(&buf[8..12]);
+                    response
+                }
+                _ => continue,
+            };
+            gateway.send_to(&response, client).await.unwrap();
+        }
+    });
+
+    let mapping = mapper.map_tcp(1080).await.unwrap();
+    assert_eq!(mapping.external_ip, Some(Ipv4Addr::new(203, 0, 113, 7)));
+    assert_eq!(mapping.external_port, 10000);
+    assert_eq!(mapping.lifetime, Duration::from_secs(600));
+}
+
+#[tokio::test]
+async fn test_mapper_gives_up_on_silent_gateway() {
+    let gateway = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mapper = PortMapper::with_address(gateway.local_addr().unwrap());
+    tokio::time::pause();
+    assert!(mapper.external_address().await.is_err());
+}
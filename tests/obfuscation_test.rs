@@ -0,0 +1,37 @@
+use rsocks5::obfuscation::{ProbeResistance, ProbeResponse};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn test_matching_preamble_is_consumed() {
+    let probe = ProbeResistance::new(b"knock".to_vec(), ProbeResponse::Close);
+    let (mut client, mut server) = tokio::io::duplex(64);
+
+    client.write_all(b"knock\x05\x01\x00").await.unwrap();
+    assert!(probe.check(&mut server, Duration::from_secs(1)).await);
+
+    // Only the preamble was consumed; the SOCKS5 greeting follows
+    let mut greeting = [0; 3];
+    server.read_exact(&mut greeting).await.unwrap();
+    assert_eq!(greeting, [0x05, 0x01, 0x00]);
+}
+
+#[tokio::test]
+async fn test_socks_greeting_without_preamble_is_rejected() {
+    let probe = ProbeResistance::new(b"knock".to_vec(), ProbeResponse::Close);
+    let (mut client, mut server) = tokio::io::duplex(64);
+
+    client.write_all(b"\x05\x01\x00\x05\x01").await.unwrap();
+    assert!(!probe.check(&mut server, Duration::from_secs(1)).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hold_keeps_connection_silent_until_timeout() {
+    let probe = ProbeResistance::new(b"knock".to_vec(), ProbeResponse::Hold(Duration::from_secs(30)));
+    let (mut client, mut server) = tokio::io::duplex(64);
+
+    client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+    let start = tokio::time::Instant::now();
+    assert!(!probe.check(&mut server, Duration::from_secs(1)).await);
+    assert!(start.elapsed() >= Duration::from_secs(30));
+}
@@ -0,0 +1,202 @@
+use rsocks5::acl::{AclRules, PortClass, PortPolicy, TargetAllowList};
+use rsocks5::canonical::{CanonicalCache, DEFAULT_CAPACITY, DEFAULT_TTL};
+use rsocks5::clock::ManualClock;
+use rsocks5::constants::reply;
+use rsocks5::policy::{PolicyContext, PolicyEngine};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::routing::RoutingTable;
+use rsocks5::Server;
+use std::sync::Arc;
+
+fn domain(host: &str, port: u16) -> TargetAddr {
+    TargetAddr::Domain(host.to_string(), port)
+}
+
+#[test]
+fn test_engine_evaluates_policies_in_order() {
+    let mut allowed = TargetAllowList::new();
+    allowed.allow("db.internal", 5432);
+    let engine = PolicyEngine::new()
+        .with_port_policy(PortPolicy::new().with_privileged_denied(true))
+        .with_allowed_targets(allowed)
+        .with_routes(RoutingTable::new(vec!["db.internal=port:6432".parse().unwrap()]));
+    let context = PolicyContext::default();
+
+    let decision = engine.evaluate(&context, &domain("db.internal", 5432));
+    assert!(decision.is_allowed());
+    assert_eq!(decision.rewritten, Some(domain("db.internal", 6432)));
+    assert!(decision.route.is_some());
+
+    // The port policy is checked first and stops evaluation
+    let decision = engine.evaluate(&context, &domain("smtp.example.com", 25));
+    let denial = decision.denial().unwrap();
+    assert_eq!(denial.policy, "port-policy");
+    assert_eq!(denial.port_class, Some(PortClass::Privileged));
+    assert_eq!(decision.denials.len(), 1);
+
+    let denial = engine.evaluate(&context, &domain("smtp.example.com", 2525)).denial().unwrap().clone();
+    assert_eq!((denial.policy, denial.reply), ("allow-list", reply::NOT_ALLOWED));
+}
+
+#[test]
+fn test_every_check_sees_the_canonical_host() {
+    let acl = AclRules::new().with_rule("deny xn--bcher-kva.example".parse().unwrap());
+    let mut allowed = TargetAllowList::new();
+    allowed.allow("xn--mnchen-3ya.de", 443);
+    let engine = PolicyEngine::new()
+        .with_acl(acl)
+        .with_allowed_targets(allowed)
+        .with_routes(RoutingTable::new(vec!["xn--mnchen-3ya.de=port:8443".parse().unwrap()]));
+    let cache = Arc::new(CanonicalCache::new(DEFAULT_CAPACITY, DEFAULT_TTL, Arc::new(ManualClock::new())));
+    let cached = engine.clone().with_canonical_cache(Arc::clone(&cache));
+    let context = PolicyContext::default();
+
+    for engine in [&engine, &cached, &cached] {
+        let denial = engine.evaluate(&context, &domain("BÜCHER.example.", 443)).denial().unwrap().clone();
+        assert_eq!(denial.policy, "acl");
+        let decision = engine.evaluate(&context, &domain("München.DE.", 443));
+        assert!(decision.is_allowed(), "{}", decision.trace());
+        assert!(decision.route.is_some());
+    }
+    assert_eq!((cache.misses(), cache.hits()), (2, 2));
+}
+
+#[test]
+fn test_shadow_denials_do_not_stop_evaluation() {
+    let allowed = TargetAllowList::new().with_shadow(true);
+    let engine = PolicyEngine::new().with_allowed_targets(allowed.clone()).with_ip_literals_only(true);
+    let context = PolicyContext::default();
+
+    // A shadowed allow-list still lets enforced policies deny
+    let decision = engine.evaluate(&context, &domain("example.com", 443));
+    assert_eq!(decision.denial().unwrap().policy, "ip-literals-only");
+    assert_eq!(decision.shadowed().count(), 0);
+
+    let ip = TargetAddr::Ipv4("192.0.2.1".parse().unwrap(), 443);
+    let decision = engine.evaluate(&context, &ip);
+    assert!(decision.is_allowed());
+    assert_eq!(decision.shadowed().map(|denial| denial.policy).collect::<Vec<_>>(), ["allow-list"]);
+
+    // Global shadow mode turns every denial into a logged one
+    let engine = PolicyEngine::new().with_allowed_targets(allowed).with_ip_literals_only(true).with_shadow_mode(true);
+    let decision = engine.evaluate(&context, &domain("example.com", 0));
+    assert!(decision.is_allowed());
+    assert_eq!(decision.shadowed().count(), 3);
+}
+
+#[test]
+fn test_acl_rules_see_who_is_asking() {
+    let mut acl = AclRules::new();
+    acl.push_str("allow *.internal user=ops").unwrap();
+    acl.push_str("deny *.internal").unwrap();
+    let engine = PolicyEngine::new().with_acl(acl);
+    let target = domain("db.internal", 5432);
+
+    let ops = PolicyContext { client: Some("192.0.2.1:40000".parse().unwrap()), username: Some("ops") };
+    assert!(engine.evaluate(&ops, &target).is_allowed());
+    let decision = engine.evaluate(&PolicyContext::default(), &target);
+    assert_eq!(decision.denial().unwrap().policy, "acl");
+}
+
+#[test]
+fn test_domains_spelling_addresses_are_checked_as_addresses() {
+    let acl = AclRules::new().with_rule("deny 10.0.0.0/8".parse().unwrap()).with_rule("deny ::1/128".parse().unwrap());
+    let engine = PolicyEngine::new().with_acl(acl);
+    let context = PolicyContext::default();
+
+    for host in ["10.0.0.1", "10.0.0.1.", "10.1", "0x0a000001", "[::1]"] {
+        let decision = engine.evaluate(&context, &domain(host, 80));
+        assert_eq!(decision.denial().map(|denial| denial.policy), Some("acl"), "{}", host);
+    }
+    assert!(engine.evaluate(&context, &domain("192.0.2.1", 80)).is_allowed());
+}
+
+#[test]
+fn test_acl_denials_name_their_rule() {
+    let mut acl = AclRules::new();
+    acl.push_str("deny *.internal shadow").unwrap();
+    acl.push_str("allow wiki.internal").unwrap();
+    acl.push_str("deny *.internal").unwrap();
+    let engine = PolicyEngine::new().with_acl(acl);
+    let context = PolicyContext::default();
+
+    let decision = engine.evaluate(&context, &domain("db.internal", 5432));
+    assert_eq!(decision.denial().unwrap().rule, Some(2));
+    assert_eq!(decision.shadowed().map(|denial| denial.rule).collect::<Vec<_>>(), [Some(0)]);
+    assert_eq!(decision.trace(), "port-policy=allow acl=shadow-deny#0,deny#2");
+
+    let decision = engine.evaluate(&context, &domain("wiki.internal", 80));
+    assert!(decision.is_allowed());
+    assert_eq!(decision.trace(), "port-policy=allow acl=shadow-deny#0");
+}
+
+#[test]
+fn test_named_acl_rules_are_reported_by_name() {
+    let mut acl = AclRules::new();
+    acl.push_str("deny *:25 name=no-smtp shadow").unwrap();
+    acl.push_str("deny *.internal name=internal").unwrap();
+    assert_eq!(acl.rules()[0].to_string(), "deny *:25 name=no-smtp shadow");
+    assert!(acl.push_str("deny * name=").is_err());
+    let engine = PolicyEngine::new().with_acl(acl);
+
+    let decision = engine.evaluate(&PolicyContext::default(), &domain("mail.internal", 25));
+    let denial = decision.denial().unwrap();
+    assert_eq!((denial.rule, denial.rule_name.as_deref()), (Some(1), Some("internal")));
+    assert_eq!(denial.reason, "acl: blocked by rule internal");
+    assert_eq!(decision.trace(), "port-policy=allow acl=shadow-deny#no-smtp,deny#internal");
+}
+
+#[test]
+fn test_server_exposes_its_policies() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_ip_literals_only(true)
+        .with_shadow_mode(true);
+    let engine = server.policy_engine();
+    assert!(engine.ip_literals_only());
+    assert!(engine.shadow_mode());
+    assert!(engine.allowed_targets().is_none());
+    assert!(engine.evaluate(&PolicyContext::default(), &domain("example.com", 80)).is_allowed());
+}
+
+#[test]
+fn test_trace_lists_the_rule_chain() {
+    let mut allowed = TargetAllowList::new();
+    allowed.allow("db.internal", 5432);
+    let engine = PolicyEngine::new()
+        .with_allowed_targets(allowed.with_shadow(true))
+        .with_routes(RoutingTable::new(vec!["*.internal=port:6432".parse().unwrap()]));
+    let context = PolicyContext::default();
+
+    let decision = engine.evaluate(&context, &domain("db.internal", 5432));
+    assert_eq!(decision.trace(), "route=*.internal port-policy=allow allow-list=allow");
+    let decision = engine.evaluate(&context, &domain("wiki.internal", 80));
+    assert_eq!(decision.trace(), "route=*.internal port-policy=allow allow-list=shadow-deny");
+    let decision = engine.evaluate(&context, &domain("example.com", 0));
+    assert_eq!(decision.trace(), "port-policy=deny");
+}
+
+#[tokio::test]
+async fn test_server_mirrors_traced_decisions() {
+    use rsocks5::client;
+    use rsocks5::mirror::RequestMirror;
+    use rsocks5::testing::TestServer;
+    use tokio::net::UdpSocket;
+
+    let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mirror = RequestMirror::connect(&format!("udp://{}", collector.local_addr().unwrap())).await.unwrap();
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_allowed_targets(TargetAllowList::new())
+        .with_request_mirror(mirror)
+        .with_decision_tracing(true);
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    assert!(client::connect(&mut stream, &domain("example.com", 443), None).await.is_err());
+
+    let mut buf = [0; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&buf[..n]).unwrap();
+    assert_eq!(event["decision"], "port-policy=allow allow-list=deny");
+
+    test_server.stop().await.unwrap();
+}
@@ -0,0 +1,63 @@
+use rsocks5::error::Socks5Error;
+use rsocks5::portowner::{self, ListeningSocket, PortOwner, Process};
+use rsocks5::Server;
+use std::io;
+use tokio::net::TcpListener;
+
+#[test]
+fn test_parse_listening_keeps_listening_sockets() {
+    let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+   0: 0100007F:0438 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 4242 1 0000000000000000 100 0 0 10 0\n\
+   1: 0100007F:0438 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1000        0 4343 1 0000000000000000 20 4 30 10 -1\n\
+   2: garbage\n";
+    assert_eq!(
+        portowner::parse_listening(table),
+        [ListeningSocket { port: 1080, uid: 1000, inode: 4242 }]
+    );
+}
+
+#[test]
+fn test_owner_display() {
+    let found = PortOwner {
+        uid: 0,
+        process: Some(Process { pid: 1234, name: "nginx".to_string() }),
+    };
+    assert_eq!(found.to_string(), "nginx (pid 1234, uid 0)");
+    assert_eq!(PortOwner { uid: 33, process: None }.to_string(), "a process of uid 33");
+}
+
+#[tokio::test]
+async fn test_bind_conflict_names_owner_and_suggests_port_range() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+
+    let server = Server::new("127.0.0.1".to_string(), Some(port), None, None);
+    let error = match server.bind().await {
+        Err(Socks5Error::IoError(e)) => e,
+        other => panic!("unexpected result: {:?}", other.map(|listener| listener.local_addr())),
+    };
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+    let message = error.to_string();
+    assert!(message.contains(&format!("Port {} on 127.0.0.1 is already in use", port)), "{}", message);
+    assert!(message.contains("--port-range"), "{}", message);
+    if cfg!(target_os = "linux") {
+        // This test process holds the port
+        assert!(message.contains(&format!("pid {}", std::process::id())), "{}", message);
+    }
+}
+
+#[tokio::test]
+async fn test_exhausted_fallback_range_is_reported() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+    // If the next port cannot be bound, someone else holds it, which serves as well
+    let next = TcpListener::bind(("127.0.0.1", port + 1)).await.ok();
+
+    let server = Server::new("127.0.0.1".to_string(), Some(port), None, None).with_port_fallback(port + 1);
+    let message = match server.bind().await {
+        Err(e) => e.to_string(),
+        Ok(listener) => panic!("bound {:?}", listener.local_addr()),
+    };
+    drop(next);
+    assert!(message.contains(&format!("Ports {}-{} on 127.0.0.1 are all in use", port, port + 1)), "{}", message);
+}
@@ -1,5 +1,5 @@
 use rsocks5::protocol::TargetAddr;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 #[test]
 fn test_target_addr_ipv4_to_string() {
@@ -11,4 +11,107 @@ fn test_target_addr_ipv4_to_string() {
 fn test_target_addr_domain_to_string() {
     let addr = TargetAddr::Domain("example.com".to_string(), 443);
     assert_eq!(addr.to_string(), "example.com:443");
-}
\ No newline at end of file
+}
+#[test]
+fn test_target_addr_from_str() {
+    let addr: TargetAddr = "10.0.0.1:1080".parse().unwrap();
+    assert!(matches!(addr, TargetAddr::Ipv4(ip, 1080) if ip == Ipv4Addr::new(10, 0, 0, 1)));
+
+    let addr: TargetAddr = "proxy.internal:1080".parse().unwrap();
+    assert!(matches!(addr, TargetAddr::Domain(ref host, 1080) if host == "proxy.internal"));
+
+    let addr: TargetAddr = "[2001:db8::1]:443".parse().unwrap();
+    assert_eq!(addr, TargetAddr::Ipv6("2001:db8::1".parse::<Ipv6Addr>().unwrap(), 443));
+    assert_eq!(addr.to_string(), "[2001:db8::1]:443");
+    assert!("[2001:db8::zz]:443".parse::<TargetAddr>().is_err());
+
+    assert!("no-port".parse::<TargetAddr>().is_err());
+    assert!(":1080".parse::<TargetAddr>().is_err());
+}
+
+#[tokio::test]
+async fn test_handshake_reports_offered_and_selected_methods() {
+    use rsocks5::protocol::handshake;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[0x05, 0x02, 0x00, 0x02]).await.unwrap();
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await.unwrap();
+        choice
+    });
+
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let info = handshake(&mut stream, None, None).await.unwrap();
+
+    assert_eq!(info.offered_methods, vec![0x00, 0x02]);
+    assert_eq!(info.method, 0x00);
+    assert_eq!(info.username, None);
+    assert_eq!(client.await.unwrap(), [0x05, 0x00]);
+}
+
+#[tokio::test]
+async fn test_handshake_tolerates_unsolicited_credentials() {
+    use rsocks5::compat::{Quirk, Quirks};
+    use rsocks5::protocol::{handshake_with_compat, process_command};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[0x05, 0x02, 0x00, 0x02]).await.unwrap();
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await.unwrap();
+        // Broken client: sends credentials even though NO_AUTH was selected
+        stream.write_all(&[0x01, 0x01, b'u', 0x01, b'p']).await.unwrap();
+        let mut status = [0; 2];
+        stream.read_exact(&mut status).await.unwrap();
+        stream.write_all(&[0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0x00, 0x50]).await.unwrap();
+        (choice, status)
+    });
+
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let compat = Quirks::new().with(Quirk::UnsolicitedCredentials);
+    let info = handshake_with_compat(&mut stream, None, None, compat).await.unwrap();
+    assert_eq!(info.method, 0x00);
+    assert_eq!(info.quirks, compat);
+    assert_eq!(info.username, None);
+
+    let target = process_command(&mut stream).await.unwrap();
+    assert_eq!(target.to_string(), "10.0.0.1:80");
+    assert_eq!(client.await.unwrap(), ([0x05, 0x00], [0x01, 0x00]));
+}
+
+#[tokio::test]
+async fn test_handshake_validates_unsolicited_credentials_when_configured() {
+    use rsocks5::compat::{Quirk, Quirks};
+    use rsocks5::protocol::handshake_with_compat;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // Offers only NO_AUTH, then sends the wrong password anyway
+        stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await.unwrap();
+        stream.write_all(&[0x01, 0x01, b'u', 0x01, b'x']).await.unwrap();
+        let mut status = [0; 2];
+        stream.read_exact(&mut status).await.unwrap();
+        status
+    });
+
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let compat = Quirks::new().with(Quirk::UnsolicitedCredentials);
+    let result = handshake_with_compat(&mut stream, Some("u"), Some("p"), compat).await;
+    assert!(result.is_err());
+    assert_eq!(client.await.unwrap(), [0x01, 0x01]);
+}
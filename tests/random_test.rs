@@ -0,0 +1,28 @@
+use rsocks5::random::{RandomSource, SeededRandom, StdRandom};
+
+#[test]
+fn test_seeded_random_is_reproducible() {
+    // Two generators with the same seed produce the same sequence
+    let a = SeededRandom::new(42);
+    let b = SeededRandom::new(42);
+
+    for _ in 0..16 {
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+}
+
+#[test]
+fn test_seeded_random_differs_by_seed() {
+    let a = SeededRandom::new(1);
+    let b = SeededRandom::new(2);
+    assert_ne!(a.next_u64(), b.next_u64());
+}
+
+#[test]
+fn test_below_stays_in_range() {
+    let rng = StdRandom::new();
+    for _ in 0..100 {
+        assert!(rng.below(7) < 7);
+    }
+    assert_eq!(rng.below(0), 0);
+}
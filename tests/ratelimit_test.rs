@@ -0,0 +1,76 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::metrics::CloseReason;
+use rsocks5::ratelimit::{HandshakeRateLimit, SourceRateLimiter};
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_burst_then_sustained_rate_per_source() {
+    let clock = Arc::new(ManualClock::new());
+    let limiter = SourceRateLimiter::new(HandshakeRateLimit::new(2, Duration::from_secs(1), 3), clock.clone());
+
+    assert!(limiter.check(ip("10.2.3.4")));
+    assert!(limiter.check(ip("10.2.3.4")));
+    assert!(limiter.check(ip("10.2.3.4")));
+    assert!(!limiter.check(ip("10.2.3.4")));
+    // Other sources have their own budget
+    assert!(limiter.check(ip("10.2.3.5")));
+    assert_eq!(limiter.len(), 2);
+
+    // Half a second refills one handshake at two per second
+    clock.advance(Duration::from_millis(500));
+    assert!(limiter.check(ip("10.2.3.4")));
+    assert!(!limiter.check(ip("10.2.3.4")));
+
+    // A quiet source gets its burst back, but no more
+    clock.advance(Duration::from_secs(60));
+    for _ in 0..3 {
+        assert!(limiter.check(ip("10.2.3.4")));
+    }
+    assert!(!limiter.check(ip("10.2.3.4")));
+}
+
+#[test]
+fn test_limit_is_clamped() {
+    let limit = HandshakeRateLimit::new(0, Duration::ZERO, 0);
+    assert_eq!(limit.count, 1);
+    assert_eq!(limit.interval, Duration::from_millis(1));
+    assert_eq!(limit.burst, 1);
+    assert_eq!(HandshakeRateLimit::new(5, Duration::from_secs(10), 2).burst, 5);
+}
+
+#[tokio::test]
+async fn test_server_drops_sources_over_the_handshake_rate() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_handshake_rate_limit(HandshakeRateLimit::new(1, Duration::from_secs(60), 1));
+    let test_server = TestServer::start(server);
+    let _first = test_server.connect().unwrap();
+    let mut second = test_server.connect().unwrap();
+    let mut buf = [0; 1];
+    assert_eq!(second.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(test_server.server().metrics().closed(CloseReason::RateLimited), 1);
+    assert_eq!(test_server.server().metrics().connections_accepted(), 2);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shadow_mode_only_reports_the_handshake_rate() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_handshake_rate_limit(HandshakeRateLimit::new(1, Duration::from_secs(60), 1))
+        .with_shadow_mode(true);
+    let test_server = TestServer::start(server);
+    let _first = test_server.connect().unwrap();
+    let mut second = test_server.connect().unwrap();
+    rsocks5::client::connect(&mut second, &"127.0.0.1:9".parse().unwrap(), None).await.ok();
+    assert_eq!(test_server.server().metrics().closed(CloseReason::RateLimited), 0);
+    assert_eq!(test_server.server().metrics().shadow_denials(), 1);
+    test_server.stop().await.unwrap();
+}
@@ -0,0 +1,125 @@
+use rsocks5::protocol::TargetAddr;
+use rsocks5::recent::{RecentQuery, RecentSessions, SessionRecord};
+use rsocks5::{client, Server};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Returns the record of a completed session to `target` accepted at `started_ms`
+fn record(conn_id: u32, target: &str, started_ms: u64) -> SessionRecord {
+    SessionRecord {
+        conn_id,
+        client: "192.0.2.1:50000".parse().unwrap(),
+        listener: "0.0.0.0:1080".to_string(),
+        username: None,
+        target: Some(TargetAddr::Domain(target.to_string(), 443)),
+        started_ms,
+        duration: Duration::from_secs(1),
+        reason: "completed",
+        bytes_from_client: 100,
+        bytes_from_target: 1000,
+    }
+}
+
+#[test]
+fn test_oldest_records_are_dropped() {
+    let recent = RecentSessions::new(2);
+    for conn_id in 1..=3 {
+        recent.push(record(conn_id, "example.com", u64::from(conn_id)));
+    }
+    assert_eq!(recent.len(), 2);
+    let ids: Vec<u32> = recent.query(&RecentQuery::new()).iter().map(|record| record.conn_id).collect();
+    assert_eq!(ids, [3, 2]);
+
+    let disabled = RecentSessions::new(0);
+    disabled.push(record(1, "example.com", 1));
+    assert!(disabled.is_empty());
+}
+
+#[test]
+fn test_queries_filter_by_target_and_time() {
+    let recent = RecentSessions::new(10);
+    recent.push(record(1, "a.example.com", 1000));
+    recent.push(record(2, "example.org", 2000));
+    recent.push(record(3, "b.example.com", 3000));
+    recent.push(record(4, "c.example.com", 4000));
+    let ids = |query: RecentQuery| -> Vec<u32> { recent.query(&query).iter().map(|record| record.conn_id).collect() };
+
+    let example_com = "*.example.com".parse().unwrap();
+    assert_eq!(ids(RecentQuery::new().with_target(example_com)), [4, 3, 1]);
+    let example_com = "*.example.com".parse().unwrap();
+    assert_eq!(ids(RecentQuery::new().with_target(example_com).with_last(2)), [4, 3]);
+    assert_eq!(ids(RecentQuery::new().with_since(2000)), [4, 3, 2]);
+}
+
+#[tokio::test]
+async fn test_server_records_finished_sessions() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    // The target keeps the connection until the client hangs up; one that
+    // closes right away would end the session as target_closed_early
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = target.accept().await {
+            tokio::spawn(async move { stream.read_to_end(&mut Vec::new()).await });
+        }
+    });
+
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_recent_sessions(8));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::connect(&mut stream, &target_addr, None).await.unwrap();
+    drop(stream);
+
+    let recent = server.recent_sessions().unwrap();
+    for _ in 0..50 {
+        if !recent.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let records = recent.query(&RecentQuery::new());
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].target, Some(target_addr));
+    assert_eq!(records[0].listener, addr.to_string());
+    assert_eq!(records[0].reason, "completed");
+    server.shutdown();
+}
+
+#[tokio::test]
+async fn test_server_classifies_targets_closing_right_after_connect() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move { while target.accept().await.is_ok() {} });
+
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_recent_sessions(8));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    // The client waits for the proxy to pass on the target's close
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::connect(&mut stream, &target_addr, None).await.unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    assert!(response.is_empty());
+    drop(stream);
+
+    let recent = server.recent_sessions().unwrap();
+    for _ in 0..50 {
+        if !recent.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let records = recent.query(&RecentQuery::new());
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].reason, "target_closed_early");
+    assert_eq!(server.stats().closed.get("target_closed_early"), Some(&1));
+    server.shutdown();
+}
@@ -16,3 +16,301 @@ fn test_relay_new() {
     assert_eq!(relay.client_addr(), client_addr);
     assert_eq!(relay.target_addr(), &target_addr);
 }
+
+#[tokio::test]
+async fn test_copy_bidirectional_counts_bytes_until_eof() {
+    use rsocks5::relay::{copy_bidirectional_with_stats, RelayOptions, RelayStats};
+    use tokio::io::{duplex, split, AsyncReadExt, AsyncWriteExt};
+
+    let (client, proxy_client_side) = duplex(64);
+    let (proxy_target_side, target) = duplex(64);
+    let (mut client_reader, mut client_writer) = split(proxy_client_side);
+    let (mut target_reader, mut target_writer) = split(proxy_target_side);
+
+    let peers = async {
+        let (mut client_read, mut client_write) = split(client);
+        let (mut target_read, mut target_write) = split(target);
+        client_write.write_all(b"hello target").await.unwrap();
+        client_write.shutdown().await.unwrap();
+        target_write.write_all(b"hi").await.unwrap();
+        target_write.shutdown().await.unwrap();
+
+        let mut at_target = Vec::new();
+        target_read.read_to_end(&mut at_target).await.unwrap();
+        let mut at_client = Vec::new();
+        client_read.read_to_end(&mut at_client).await.unwrap();
+        (at_target, at_client)
+    };
+    // A tiny buffer forces several reads per direction
+    let options = RelayOptions::new().with_buffer_size(3);
+    let relay = copy_bidirectional_with_stats(
+        &mut client_reader,
+        &mut client_writer,
+        &mut target_reader,
+        &mut target_writer,
+        &options,
+    );
+
+    let (stats, (at_target, at_client)) = tokio::join!(relay, peers);
+    assert_eq!(at_target, b"hello target");
+    assert_eq!(at_client, b"hi");
+    assert_eq!(stats.unwrap(), RelayStats { client_to_target: 12, target_to_client: 2, target_closed_early: false });
+}
+
+#[tokio::test]
+async fn test_copy_bidirectional_passes_half_close_on() {
+    use rsocks5::relay::{copy_bidirectional_with_stats, RelayOptions};
+    use tokio::io::{duplex, split, AsyncReadExt, AsyncWriteExt};
+
+    let (client, proxy_client_side) = duplex(64);
+    let (proxy_target_side, target) = duplex(64);
+    let (mut client_reader, mut client_writer) = split(proxy_client_side);
+    let (mut target_reader, mut target_writer) = split(proxy_target_side);
+    let options = RelayOptions::default();
+    let relay = tokio::spawn(async move {
+        copy_bidirectional_with_stats(
+            &mut client_reader,
+            &mut client_writer,
+            &mut target_reader,
+            &mut target_writer,
+            &options,
+        )
+        .await
+    });
+
+    // The client stops sending; the target still answers after seeing EOF
+    let (mut client_read, mut client_write) = split(client);
+    let (mut target_read, mut target_write) = split(target);
+    client_write.write_all(b"request").await.unwrap();
+    client_write.shutdown().await.unwrap();
+    let mut request = Vec::new();
+    target_read.read_to_end(&mut request).await.unwrap();
+    assert_eq!(request, b"request");
+
+    target_write.write_all(b"response").await.unwrap();
+    target_write.shutdown().await.unwrap();
+    let mut response = Vec::new();
+    client_read.read_to_end(&mut response).await.unwrap();
+    assert_eq!(response, b"response");
+
+    let stats = relay.await.unwrap().unwrap();
+    assert_eq!(stats.client_to_target, 7);
+    assert_eq!(stats.target_to_client, 8);
+}
+
+#[tokio::test]
+async fn test_copy_bidirectional_reports_errors() {
+    use rsocks5::relay::{copy_bidirectional_with_stats, RelayOptions};
+    use std::io;
+    use tokio_test::io::Builder;
+
+    let mut client_reader = Builder::new()
+        .read(b"data")
+        .read_error(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+        .build();
+    let mut client_writer = Builder::new().build();
+    let mut target_reader = Builder::new().wait(std::time::Duration::from_secs(60)).build();
+    let mut target_writer = Builder::new().write(b"data").build();
+
+    let result = copy_bidirectional_with_stats(
+        &mut client_reader,
+        &mut client_writer,
+        &mut target_reader,
+        &mut target_writer,
+        &RelayOptions::new(),
+    )
+    .await;
+    let error = result.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+    assert!(error.to_string().contains("client to target"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_copy_bidirectional_applies_throttle() {
+    use rsocks5::relay::{copy_bidirectional_with_stats, RelayOptions, Throttle};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::empty;
+    use tokio_test::io::Builder;
+
+    // One second per byte, so the delay is easy to observe on a paused clock
+    #[derive(Debug)]
+    struct SecondPerByte;
+    impl Throttle for SecondPerByte {
+        fn delay_for(&self, bytes: usize) -> Duration {
+            Duration::from_secs(bytes as u64)
+        }
+    }
+
+    let mut client_reader = Builder::new().read(b"abc").build();
+    let mut client_writer = tokio::io::sink();
+    let mut target_reader = empty();
+    let mut target_writer = Builder::new().write(b"abc").build();
+    let options = RelayOptions::new().with_upload_throttle(Arc::new(SecondPerByte));
+
+    let start = tokio::time::Instant::now();
+    let stats = copy_bidirectional_with_stats(
+        &mut client_reader,
+        &mut client_writer,
+        &mut target_reader,
+        &mut target_writer,
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(stats.client_to_target, 3);
+    assert!(start.elapsed() >= Duration::from_secs(3));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_copy_bidirectional_idle_timeout() {
+    use rsocks5::relay::{copy_bidirectional_with_stats, RelayEngine, RelayOptions};
+    use std::io;
+    use std::time::Duration;
+    use tokio::io::duplex;
+
+    // Neither peer ever sends anything
+    let (_client, proxy_client_side) = duplex(64);
+    let (proxy_target_side, _target) = duplex(64);
+    let (mut client_reader, mut client_writer) = tokio::io::split(proxy_client_side);
+    let (mut target_reader, mut target_writer) = tokio::io::split(proxy_target_side);
+    let options = RelayOptions::new()
+        .with_engine(RelayEngine::Full)
+        .with_idle_timeout(Duration::from_secs(30));
+
+    let result = copy_bidirectional_with_stats(
+        &mut client_reader,
+        &mut client_writer,
+        &mut target_reader,
+        &mut target_writer,
+        &options,
+    )
+    .await;
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn test_relay_options_default_to_lean_engine() {
+    use rsocks5::relay::{RelayEngine, RelayOptions, DEFAULT_BUFFER_SIZE};
+
+    let options = RelayOptions::default();
+    assert_eq!(options.engine(), RelayEngine::Lean);
+    assert_eq!(options.buffer_size(), DEFAULT_BUFFER_SIZE);
+    assert_eq!(options.idle_timeout(), None);
+    assert!(!options.checksums());
+}
+
+#[test]
+fn test_stream_hash_ignores_chunking() {
+    use rsocks5::relay::StreamHash;
+
+    let mut whole = StreamHash::new();
+    whole.update(b"hello world");
+    let mut chunked = StreamHash::new();
+    chunked.update(b"hel");
+    chunked.update(b"");
+    chunked.update(b"lo world");
+    assert_eq!(whole, chunked);
+    // Known FNV-1a 64 test vector
+    let mut vector = StreamHash::new();
+    vector.update(b"a");
+    assert_eq!(vector.to_string(), "af63dc4c8601ec8c");
+    assert_ne!(whole, vector);
+}
+
+#[tokio::test]
+async fn test_copy_bidirectional_with_checksums_relays_unchanged() {
+    use rsocks5::relay::{copy_bidirectional_with_stats, RelayEngine, RelayOptions};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    let (mut client, proxy_client_side) = duplex(64);
+    let (proxy_target_side, mut target) = duplex(64);
+    let (mut client_reader, mut client_writer) = tokio::io::split(proxy_client_side);
+    let (mut target_reader, mut target_writer) = tokio::io::split(proxy_target_side);
+    let options = RelayOptions::new().with_engine(RelayEngine::Full).with_checksums(true);
+
+    let relay = tokio::spawn(async move {
+        copy_bidirectional_with_stats(
+            &mut client_reader,
+            &mut client_writer,
+            &mut target_reader,
+            &mut target_writer,
+            &options,
+        )
+        .await
+    });
+
+    client.write_all(b"payload").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut received = Vec::new();
+    target.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"payload");
+    target.shutdown().await.unwrap();
+
+    let stats = relay.await.unwrap().unwrap();
+    assert_eq!(stats.client_to_target, 7);
+    assert_eq!(stats.target_to_client, 0);
+}
+
+#[tokio::test]
+async fn test_relay_classifies_targets_closing_right_after_connect() {
+    use rsocks5::relay::{RelayEngine, RelayOptions};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // A target that accepts and immediately closes
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = target.accept().await {
+            drop(stream);
+        }
+    });
+
+    for engine in [RelayEngine::Lean, RelayEngine::Full] {
+        let target_stream = TcpStream::connect(target_addr).await.unwrap();
+        let (mut client, proxy_client_side) = duplex(64);
+        let relay = Relay::new("192.0.2.1:5000".parse().unwrap(), target_addr.to_string())
+            .with_options(RelayOptions::new().with_engine(engine));
+        let relay = tokio::spawn(async move { relay.start_relay(proxy_client_side, target_stream).await });
+
+        // The client sends its request and hangs up once the proxy does
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.is_empty());
+        drop(client);
+
+        let stats = relay.await.unwrap().unwrap();
+        assert!(stats.target_closed_early, "{:?}", engine);
+        assert_eq!(stats.target_to_client, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_relay_does_not_blame_targets_for_clients_hanging_up() {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // A target that closes once the client is done, without answering
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = target.accept().await.unwrap();
+        let mut request = Vec::new();
+        let _ = stream.read_to_end(&mut request).await;
+    });
+
+    let target_stream = TcpStream::connect(target_addr).await.unwrap();
+    let (mut client, proxy_client_side) = duplex(64);
+    let relay = Relay::new("192.0.2.1:5000".parse().unwrap(), target_addr.to_string());
+    let relay = tokio::spawn(async move { relay.start_relay(proxy_client_side, target_stream).await });
+
+    client.shutdown().await.unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+
+    let stats = relay.await.unwrap().unwrap();
+    assert!(!stats.target_closed_early);
+    assert_eq!(stats.target_to_client, 0);
+}
@@ -0,0 +1,173 @@
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsocks5::client;
+use rsocks5::config::ServerConfig;
+use rsocks5::error::Socks5Error;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::remote::{ConfigUrl, RemoteConfig, RemoteSettings};
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const DOCUMENT: &str = "serial = 2\n[acl]\nrules = [\"deny *.blocked.test\"]\n";
+
+/// A document and its signature, as currently published
+type Published = Arc<Mutex<(String, String)>>;
+
+/// Serves `document` with an `ETag`, and `signature` at the `.sig` URL
+async fn serve(document: &str, signature: String) -> String {
+    serve_published(Arc::new(Mutex::new((document.to_string(), signature)))).await
+}
+
+/// Serves whatever is published, tagged by its length
+async fn serve_published(published: Published) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                head.extend_from_slice(&buf[..n]);
+            }
+            let head = String::from_utf8(head).unwrap();
+            let (document, signature) = published.lock().unwrap().clone();
+            let etag = format!("\"v{}\"", document.len());
+            let response = if head.starts_with("GET /proxy.toml.sig ") {
+                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", signature.len(), signature)
+            } else if head.contains(&format!("If-None-Match: {}\r\n", etag)) {
+                "HTTP/1.1 304 Not Modified\r\n\r\n".to_string()
+            } else {
+                format!("HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n\r\n{}", etag, document.len(), document)
+            };
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    format!("http://{}/proxy.toml", addr)
+}
+
+/// Returns a fresh key pair and the hex signature of `document`
+fn sign(document: &str) -> (Ed25519KeyPair, String) {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let signature = sign_with(&key_pair, document);
+    (key_pair, signature)
+}
+
+/// Returns the hex signature of `document` by `key_pair`
+fn sign_with(key_pair: &Ed25519KeyPair, document: &str) -> String {
+    key_pair.sign(document.as_bytes()).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the raw public key of `key_pair`
+fn public_key(key_pair: &Ed25519KeyPair) -> [u8; 32] {
+    key_pair.public_key().as_ref().try_into().unwrap()
+}
+
+#[test]
+fn test_url_parsing() {
+    let url = ConfigUrl::parse("https://config.example.com/fleet/proxy.toml").unwrap();
+    assert!(url.tls);
+    assert_eq!(url.http.target, TargetAddr::Domain("config.example.com".to_string(), 443));
+    assert_eq!(url.http.path, "/fleet/proxy.toml");
+
+    let url = ConfigUrl::parse("https://[::1]:8443/proxy.toml").unwrap();
+    assert_eq!(url.http.target, "[::1]:8443".parse().unwrap());
+    assert!(!ConfigUrl::parse("http://10.0.0.1/proxy.toml").unwrap().tls);
+    assert!(ConfigUrl::parse("ftp://10.0.0.1/proxy.toml").is_err());
+}
+
+#[test]
+fn test_settings_refuse_local_files() {
+    let config = ServerConfig::from_toml("users_file = \"/etc/rsocks5/users\"").unwrap();
+    assert!(matches!(RemoteSettings::from_config(&config), Err(Socks5Error::ConfigError(_))));
+
+    let config = ServerConfig::from_toml("username = \"alice\"\npassword = \"one\"\n[users]\nbob = \"two\"").unwrap();
+    let settings = RemoteSettings::from_config(&config).unwrap();
+    assert_eq!(settings.users.len(), 2);
+    assert!(settings.allowed_targets.is_none());
+}
+
+#[tokio::test]
+async fn test_poll_applies_signed_documents_once() {
+    let (key_pair, signature) = sign(DOCUMENT);
+    let url = serve(DOCUMENT, signature).await;
+    let remote = RemoteConfig::new(ConfigUrl::parse(&url).unwrap()).with_public_key(public_key(&key_pair));
+    assert!(remote.current().is_none());
+    assert!(remote.poll().await.unwrap());
+    let applied = remote.current().unwrap();
+    assert!(!applied.acl.is_empty());
+    // The ETag makes the next poll a no-op
+    assert!(!remote.poll().await.unwrap());
+    assert!(Arc::ptr_eq(&applied, &remote.current().unwrap()));
+
+    // A signature by another key is rejected
+    let (other, _) = sign(DOCUMENT);
+    let remote = RemoteConfig::new(ConfigUrl::parse(&url).unwrap()).with_public_key(public_key(&other));
+    let result = remote.poll().await;
+    assert!(matches!(result, Err(Socks5Error::ConfigError(_))), "{:?}", result);
+    assert!(remote.current().is_none());
+}
+
+#[tokio::test]
+async fn test_server_enforces_the_remote_acl() {
+    let (key_pair, signature) = sign(DOCUMENT);
+    let url = serve(DOCUMENT, signature).await;
+    let remote = Arc::new(RemoteConfig::new(ConfigUrl::parse(&url).unwrap()).with_public_key(public_key(&key_pair)));
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_remote_config(Arc::clone(&remote));
+    let test_server = TestServer::start(server);
+    let target = TargetAddr::Domain("www.blocked.test".to_string(), 80);
+
+    // The server's own, open policy applies until a document arrives
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(!matches!(result, Err(Socks5Error::ReplyError(0x02))), "{:?}", result);
+
+    remote.poll().await.unwrap();
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))), "{:?}", result);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unsigned_documents_need_https() {
+    let url = serve(DOCUMENT, String::new()).await;
+    let remote = RemoteConfig::new(ConfigUrl::parse(&url).unwrap());
+    assert!(matches!(remote.validate(), Err(Socks5Error::ConfigError(_))));
+    let result = remote.poll().await;
+    assert!(matches!(result, Err(Socks5Error::ConfigError(_))), "{:?}", result);
+    assert!(remote.current().is_none());
+
+    let https = RemoteConfig::new(ConfigUrl::parse("https://config.example.com/proxy.toml").unwrap());
+    assert!(https.validate().is_ok());
+}
+
+#[tokio::test]
+async fn test_signed_documents_cannot_be_rolled_back() {
+    let (key_pair, _) = sign(DOCUMENT);
+    let sign_published = |document: &str| (document.to_string(), sign_with(&key_pair, document));
+    let published = Arc::new(Mutex::new(sign_published(DOCUMENT)));
+    let url = serve_published(Arc::clone(&published)).await;
+    let remote = RemoteConfig::new(ConfigUrl::parse(&url).unwrap()).with_public_key(public_key(&key_pair));
+    assert!(remote.poll().await.unwrap());
+    assert_eq!(remote.current().unwrap().serial, Some(2));
+
+    // An older signed document is refused and the newer one stays in force
+    *published.lock().unwrap() = sign_published("serial = 1\n");
+    let result = remote.poll().await;
+    assert!(matches!(result, Err(Socks5Error::ConfigError(_))), "{:?}", result);
+    assert!(!remote.current().unwrap().acl.is_empty());
+
+    // So is a signed document without a serial
+    *published.lock().unwrap() = sign_published("[acl]\n");
+    assert!(remote.poll().await.is_err());
+
+    *published.lock().unwrap() = sign_published("serial = 3\n");
+    assert!(remote.poll().await.unwrap());
+    assert_eq!(remote.current().unwrap().serial, Some(3));
+}
@@ -0,0 +1,146 @@
+use rsocks5::client;
+use rsocks5::clock::ManualClock;
+use rsocks5::error::Socks5Error;
+use rsocks5::metrics::CloseReason;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::ratelimit::HandshakeRateLimit;
+use rsocks5::reputation::{Offense, Reputation, ReputationPolicy};
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_scores_add_up_and_decay() {
+    let clock = Arc::new(ManualClock::new());
+    let policy = ReputationPolicy::new()
+        .with_half_life(Duration::from_secs(60))
+        .with_weight(Offense::ConnectFailure, 2.0);
+    let reputation = Reputation::new(policy, clock.clone());
+
+    assert_eq!(reputation.score(ip("10.0.0.1")), 0.0);
+    assert_eq!(reputation.record(ip("10.0.0.1"), Offense::AuthFailure), 1.0);
+    assert_eq!(reputation.record(ip("10.0.0.1"), Offense::Denied), 1.5);
+    assert_eq!(reputation.record(ip("10.0.0.2"), Offense::ConnectFailure), 2.0);
+
+    // Every half-life halves the score
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(reputation.score(ip("10.0.0.1")), 0.75);
+    assert_eq!(reputation.record(ip("10.0.0.1"), Offense::ProtocolViolation), 1.75);
+    assert_eq!(reputation.scores(0.0), [(ip("10.0.0.1"), 1.75), (ip("10.0.0.2"), 1.0)]);
+    assert_eq!(reputation.scores(1.5), [(ip("10.0.0.1"), 1.75)]);
+
+    assert_eq!("auth_failure".parse::<Offense>().unwrap(), Offense::AuthFailure);
+    assert!(matches!("spam".parse::<Offense>(), Err(Socks5Error::ConfigError(_))));
+}
+
+#[test]
+fn test_low_reputation_tightens_the_handshake_limit() {
+    let clock = Arc::new(ManualClock::new());
+    let policy = ReputationPolicy::new()
+        .with_throttle_at(2.0)
+        .with_throttle_limit(HandshakeRateLimit::new(1, Duration::from_secs(10), 1))
+        .with_require_auth(4.0);
+    let reputation = Reputation::new(policy, clock.clone());
+
+    reputation.record(ip("10.0.0.1"), Offense::AuthFailure);
+    for _ in 0..5 {
+        assert!(reputation.check(ip("10.0.0.1")));
+    }
+    reputation.record(ip("10.0.0.1"), Offense::AuthFailure);
+    assert!(reputation.check(ip("10.0.0.1")));
+    assert!(!reputation.check(ip("10.0.0.1")));
+    assert!(!reputation.requires_auth(ip("10.0.0.1")));
+    clock.advance(Duration::from_secs(10));
+    assert!(reputation.check(ip("10.0.0.1")));
+
+    // The score has decayed a little since
+    for _ in 0..3 {
+        reputation.record(ip("10.0.0.1"), Offense::AuthFailure);
+    }
+    assert!(reputation.requires_auth(ip("10.0.0.1")));
+    assert!(!reputation.requires_auth(ip("10.0.0.2")));
+}
+
+#[tokio::test]
+async fn test_server_makes_offending_sources_authenticate() {
+    let policy = ReputationPolicy::new().with_throttle_at(100.0).with_require_auth(2.0);
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_reputation(Reputation::new(policy, Arc::new(ManualClock::new())));
+    let test_server = TestServer::start(server);
+
+    // Two garbage greetings are protocol violations
+    for _ in 0..2 {
+        let mut stream = test_server.connect().unwrap();
+        stream.write_all(&[0x07, 0x01, 0x00]).await.unwrap();
+        let mut buf = Vec::new();
+        let _ = stream.read_to_end(&mut buf).await;
+    }
+    let reputation = test_server.server().reputation().unwrap();
+    for _ in 0..100 {
+        if reputation.scores(2.0).len() == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(reputation.scores(2.0).len(), 1);
+
+    // Without accounts, the source is offered no acceptable method
+    let target = TargetAddr::Domain("example.com".to_string(), 80);
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(result.is_err(), "{:?}", result);
+    for _ in 0..100 {
+        if test_server.server().metrics().closed(CloseReason::LowReputation) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(test_server.server().metrics().closed(CloseReason::LowReputation), 1);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shadow_mode_only_reports_low_reputation() {
+    let echo = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = TargetAddr::from(echo.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let policy = ReputationPolicy::new()
+        .with_throttle_at(1.0)
+        .with_throttle_limit(HandshakeRateLimit::new(1, Duration::from_secs(60), 1))
+        .with_require_auth(1.0);
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_reputation(Reputation::new(policy, Arc::new(ManualClock::new())))
+        .with_shadow_mode(true);
+    let test_server = TestServer::start(server);
+    test_server.server().reputation().unwrap().record(ip("127.0.0.1"), Offense::AuthFailure);
+
+    // Throttled and made to authenticate, yet every connect goes through
+    for _ in 0..3 {
+        let mut stream = test_server.connect().unwrap();
+        client::connect(&mut stream, &target, None).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+    }
+    let metrics = test_server.server().metrics();
+    assert_eq!(metrics.closed(CloseReason::RateLimited), 0);
+    assert_eq!(metrics.closed(CloseReason::LowReputation), 0);
+    // Two throttled accepts, and three sessions that skipped authentication
+    assert_eq!(metrics.shadow_denials(), 5);
+    test_server.stop().await.unwrap();
+}
@@ -0,0 +1,238 @@
+use rsocks5::connection::Connector;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::resolver::{Resolver, SearchList};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Starts a nameserver answering A and AAAA queries with the given addresses
+///
+/// Every record comes with a CNAME in front of it, as recursive resolvers
+/// return them, and the given TTL.
+async fn nameserver(addrs: Vec<IpAddr>, ttl: u32) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+            let query = &buf[..len];
+            let record_type = u16::from_be_bytes([query[len - 4], query[len - 3]]);
+            let matching: Vec<Vec<u8>> = addrs
+                .iter()
+                .filter_map(|ip| match (ip, record_type) {
+                    (IpAddr::V4(v4), 1) => Some(v4.octets().to_vec()),
+                    (IpAddr::V6(v6), 28) => Some(v6.octets().to_vec()),
+                    _ => None,
+                })
+                .collect();
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1]);
+            response.extend_from_slice(&(matching.len() as u16 + 1).to_be_bytes());
+            response.extend_from_slice(&[0, 0, 0, 0]);
+            response.extend_from_slice(&query[12..]);
+            // CNAME to the question's name itself, which the resolver skips
+            response.extend_from_slice(&[0xC0, 0x0C, 0, 5, 0, 1, 0, 0, 0x0E, 0x10, 0, 2, 0xC0, 0x0C]);
+            for data in matching {
+                response.extend_from_slice(&[0xC0, 0x0C]);
+                response.extend_from_slice(&record_type.to_be_bytes());
+                response.extend_from_slice(&[0, 1]);
+                response.extend_from_slice(&ttl.to_be_bytes());
+                response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                response.extend_from_slice(&data);
+            }
+            socket.send_to(&response, client).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_lookup_returns_both_families_and_lowest_ttl() {
+    let addrs = vec!["192.0.2.7".parse().unwrap(), "2001:db8::7".parse().unwrap()];
+    let resolver = Resolver::new(vec![nameserver(addrs, 300).await]);
+
+    let resolution = resolver.lookup("www.example.test.").await.unwrap();
+    assert_eq!(resolution.addrs, vec!["2001:db8::7".parse::<IpAddr>().unwrap(), "192.0.2.7".parse().unwrap()]);
+    assert_eq!(resolution.ttl, Duration::from_secs(300));
+
+    // Literals and localhost are answered without asking
+    let literal = resolver.lookup("10.1.2.3").await.unwrap();
+    assert_eq!(literal.addrs, vec!["10.1.2.3".parse::<IpAddr>().unwrap()]);
+    assert!(resolver.lookup("LocalHost").await.unwrap().addrs.iter().all(IpAddr::is_loopback));
+    assert!(resolver.lookup("bad..name").await.is_err());
+}
+
+#[tokio::test]
+async fn test_silent_nameserver_is_skipped_after_timeout() {
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let answering = nameserver(vec!["192.0.2.8".parse().unwrap()], 60).await;
+    let resolver = Resolver::new(vec![silent.local_addr().unwrap(), answering])
+        .with_timeout(Duration::from_millis(50))
+        .with_concurrency(1);
+
+    let resolution = resolver.lookup("host.example.test").await.unwrap();
+    assert_eq!(resolution.addrs, vec!["192.0.2.8".parse::<IpAddr>().unwrap()]);
+
+    let unanswered = Resolver::new(vec![silent.local_addr().unwrap()]).with_timeout(Duration::from_millis(50));
+    let error = unanswered.lookup("host.example.test").await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn test_connector_resolves_through_the_resolver() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = target.local_addr().unwrap().port();
+    let resolver = Resolver::new(vec![nameserver(vec!["127.0.0.1".parse().unwrap()], 60).await]);
+    let connector = Connector::new().with_resolver(resolver);
+
+    let domain = TargetAddr::Domain("service.example.test".to_string(), port);
+    assert_eq!(connector.resolve(&domain).await.unwrap(), vec![target.local_addr().unwrap()]);
+    connector.open(&domain).await.unwrap();
+}
+
+/// Encodes a host name as DNS labels
+fn labels(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// Decodes the question name of an uncompressed query
+fn question(query: &[u8]) -> String {
+    let mut offset = 12;
+    let mut name = Vec::new();
+    while query[offset] != 0 {
+        let len = usize::from(query[offset]);
+        name.push(String::from_utf8_lossy(&query[offset + 1..offset + 1 + len]).into_owned());
+        offset += len + 1;
+    }
+    name.join(".")
+}
+
+/// Builds a response to `query` with the given question name and A records
+fn response(query: &[u8], flags: [u8; 2], asked: &str, records: &[(&str, [u8; 4])]) -> Vec<u8> {
+    let mut response = query[..2].to_vec();
+    response.extend_from_slice(&flags);
+    response.extend_from_slice(&[0, 1]);
+    response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(&labels(asked));
+    response.extend_from_slice(&query[query.len() - 4..]);
+    for (owner, ip) in records {
+        response.extend_from_slice(&labels(owner));
+        response.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        response.extend_from_slice(ip);
+    }
+    response
+}
+
+/// Starts a nameserver answering UDP and TCP queries on the same port
+///
+/// A queries are answered by `udp` and `tcp`, AAAA queries with no records.
+async fn scripted(udp: fn(&[u8], &str) -> Vec<u8>, tcp: fn(&[u8], &str) -> Vec<u8>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let answer = |query: &[u8], script: fn(&[u8], &str) -> Vec<u8>| {
+        let name = question(query);
+        if query[query.len() - 3] == 28 {
+            response(query, [0x81, 0x80], &name, &[])
+        } else {
+            script(query, &name)
+        }
+    };
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&answer(&buf[..len], udp), client).await.unwrap();
+        }
+    });
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut query = vec![0; usize::from(u16::from_be_bytes(length))];
+            stream.read_exact(&mut query).await.unwrap();
+            let reply = answer(&query, tcp);
+            stream.write_all(&(reply.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&reply).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_responses_must_answer_the_question_asked() {
+    let resolver = Resolver::new(vec![
+        scripted(
+            |query, name| match name {
+                // Repeats some other question
+                "spoofed.example.test" => {
+                    response(query, [0x81, 0x80], "other.example.test", &[("other.example.test", [192, 0, 2, 66])])
+                }
+                // Slips in a record for a name nobody asked about
+                _ => response(query, [0x81, 0x80], name, &[("evil.test", [192, 0, 2, 66]), (name, [192, 0, 2, 10])]),
+            },
+            |_, _| unreachable!(),
+        )
+        .await,
+    ]);
+
+    let error = resolver.lookup("spoofed.example.test").await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    let resolution = resolver.lookup("Mixed.Example.Test").await.unwrap();
+    assert_eq!(resolution.addrs, vec!["192.0.2.10".parse::<IpAddr>().unwrap()]);
+}
+
+#[tokio::test]
+async fn test_truncated_answer_is_retried_over_tcp() {
+    let resolver = Resolver::new(vec![
+        scripted(
+            |query, name| response(query, [0x83, 0x80], name, &[]),
+            |query, name| response(query, [0x81, 0x80], name, &[(name, [192, 0, 2, 11])]),
+        )
+        .await,
+    ]);
+
+    let resolution = resolver.lookup("big.example.test").await.unwrap();
+    assert_eq!(resolution.addrs, vec!["192.0.2.11".parse::<IpAddr>().unwrap()]);
+}
+
+#[tokio::test]
+async fn test_search_list_expands_relative_names() {
+    let search = SearchList::parse("domain old.test\nsearch corp.test lab.test.\noptions rotate ndots:2\n");
+    assert_eq!(search.domains, vec!["corp.test", "lab.test"]);
+    assert_eq!(search.ndots, 2);
+    assert_eq!(search.candidates("intranet"), vec!["intranet.corp.test", "intranet.lab.test", "intranet"]);
+    assert_eq!(search.candidates("a.b.c"), vec!["a.b.c", "a.b.c.corp.test", "a.b.c.lab.test"]);
+    assert_eq!(search.candidates("intranet."), vec!["intranet"]);
+    assert_eq!(SearchList::parse("options ndots:99").ndots, 15);
+
+    // Only names under lab.test exist
+    let resolver = Resolver::new(vec![
+        scripted(
+            |query, name| {
+                if name.ends_with(".lab.test") {
+                    response(query, [0x81, 0x80], name, &[(name, [192, 0, 2, 12])])
+                } else {
+                    response(query, [0x81, 0x83], name, &[])
+                }
+            },
+            |_, _| unreachable!(),
+        )
+        .await,
+    ])
+    .with_search(search);
+
+    let resolution = resolver.lookup("intranet").await.unwrap();
+    assert_eq!(resolution.addrs, vec!["192.0.2.12".parse::<IpAddr>().unwrap()]);
+    assert!(resolver.lookup("intranet.").await.is_err());
+}
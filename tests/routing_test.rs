@@ -0,0 +1,90 @@
+use rsocks5::connection::{Connector, SocketOptions};
+use rsocks5::protocol::TargetAddr;
+use rsocks5::routing::{HostPattern, Route, RoutingTable, TargetPattern};
+use std::net::Ipv4Addr;
+
+fn domain(host: &str, port: u16) -> TargetAddr {
+    TargetAddr::Domain(host.to_string(), port)
+}
+
+#[test]
+fn test_suffix_pattern() {
+    let pattern: TargetPattern = "*.Example.com".parse().unwrap();
+    assert_eq!(pattern.host, HostPattern::Suffix("example.com".to_string()));
+    assert!(pattern.matches(&domain("api.example.com", 443)));
+    assert!(pattern.matches(&domain("example.com.", 80)));
+    assert!(!pattern.matches(&domain("badexample.com", 443)));
+}
+
+#[test]
+fn test_cidr_and_port_pattern() {
+    let pattern: TargetPattern = "10.0.0.0/8:25".parse().unwrap();
+    assert!(pattern.matches(&TargetAddr::Ipv4(Ipv4Addr::new(10, 1, 2, 3), 25)));
+    assert!(!pattern.matches(&TargetAddr::Ipv4(Ipv4Addr::new(10, 1, 2, 3), 587)));
+    assert!(!pattern.matches(&TargetAddr::Ipv4(Ipv4Addr::new(11, 0, 0, 1), 25)));
+    assert_eq!(pattern.to_string(), "10.0.0.0/8:25");
+
+    assert!("10.0.0.0/33".parse::<TargetPattern>().is_err());
+    assert!("*:notaport".parse::<TargetPattern>().is_err());
+}
+
+#[test]
+fn test_route_parsing() {
+    let route: Route = "*.backup.internal=dscp:8,mark:0x20".parse().unwrap();
+    assert_eq!(route.dscp, Some(8));
+    assert_eq!(route.mark, Some(0x20));
+
+    assert!("*.x=dscp:64".parse::<Route>().is_err());
+    assert!("*.x=color:red".parse::<Route>().is_err());
+    assert!("*.x".parse::<Route>().is_err());
+}
+
+#[test]
+fn test_connector_applies_first_matching_route() {
+    let table = RoutingTable::new(vec![
+        "*.backup.internal=dscp:8".parse().unwrap(),
+        "*=mark:7".parse().unwrap(),
+    ]);
+    let connector = Connector::new()
+        .with_socket_options(SocketOptions { tos: Some(0x10), ..SocketOptions::default() })
+        .with_routes(table);
+
+    let bulk = connector.options_for(&domain("nas.backup.internal", 22));
+    assert_eq!(bulk.tos, Some(8 << 2));
+    assert_eq!(bulk.mark, None);
+
+    let other = connector.options_for(&domain("example.com", 443));
+    assert_eq!(other.tos, Some(0x10));
+    assert_eq!(other.mark, Some(7));
+}
+
+#[test]
+fn test_route_rewrites_host_and_port() {
+    let table = RoutingTable::new(vec![
+        "*.internal:80=port:8080".parse().unwrap(),
+        "legacy.example.com=host:10.0.0.5,port:8443".parse().unwrap(),
+    ]);
+
+    let rewritten = table.rewrite(&domain("wiki.internal", 80)).unwrap();
+    assert_eq!(rewritten.to_string(), "wiki.internal:8080");
+    assert!(table.rewrite(&domain("wiki.internal", 443)).is_none());
+
+    let rewritten = table.rewrite(&domain("legacy.example.com", 443)).unwrap();
+    assert_eq!(rewritten.to_string(), "10.0.0.5:8443");
+
+    assert!("*=port:notaport".parse::<Route>().is_err());
+}
+
+#[tokio::test]
+async fn test_connector_connects_to_rewritten_port() {
+    use tokio::net::TcpListener;
+
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let real_port = target.local_addr().unwrap().port();
+    let route: Route = format!("127.0.0.1:1=port:{}", real_port).parse().unwrap();
+    let connector = Connector::new().with_routes(RoutingTable::new(vec![route]));
+
+    let requested = rsocks5::protocol::TargetAddr::Ipv4(std::net::Ipv4Addr::LOCALHOST, 1);
+    let stream = connector.open(&requested).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap().port(), real_port);
+}
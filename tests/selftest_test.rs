@@ -0,0 +1,36 @@
+use rsocks5::acl::TargetAllowList;
+use rsocks5::selftest::self_test;
+use rsocks5::Server;
+use std::sync::Arc;
+
+/// Binds a server on loopback and serves it in the background
+async fn serving(server: Server) -> Arc<Server> {
+    let server = Arc::new(server);
+    let listener = server.bind().await.unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+    server
+}
+
+#[tokio::test]
+async fn test_self_test_round_trips_through_the_listener() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_credentials("alice".to_string(), "secret".to_string());
+    assert!(self_test(&server).await.is_err());
+
+    let server = serving(server).await;
+    self_test(&server).await.unwrap();
+    assert_eq!(server.metrics().connections_accepted(), 1);
+    server.shutdown();
+}
+
+#[tokio::test]
+async fn test_self_test_reports_policies_refusing_the_echo_target() {
+    let server = serving(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None).with_allowed_targets(TargetAllowList::new()),
+    )
+    .await;
+    let error = self_test(&server).await.unwrap_err();
+    assert!(error.to_string().contains("refused by policy"), "{}", error);
+    server.shutdown();
+}
@@ -41,3 +41,460 @@ fn test_server_new_with_auth() {
     assert_eq!(server.port(), 8888);
     assert_eq!(server.addr(), "127.0.0.1:8888");
 }
+
+#[tokio::test]
+async fn test_server_bind_reports_local_addr() {
+    // Binding to port 0 yields an OS-assigned port that can be reported
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None);
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    assert!(addr.ip().is_loopback());
+    assert_ne!(addr.port(), 0);
+}
+
+#[tokio::test]
+async fn test_first_byte_timeout_closes_silent_client() {
+    use rsocks5::metrics::CloseReason;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    // A client that never sends anything is dropped after the deadline
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_first_byte_timeout(Duration::from_millis(100)),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0; 1];
+    let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(n, 0);
+
+    // The close is recorded right after the socket is dropped
+    for _ in 0..50 {
+        if server.metrics().closed(CloseReason::FirstByteTimeout) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.metrics().closed(CloseReason::FirstByteTimeout), 1);
+}
+
+#[tokio::test]
+async fn test_ip_literals_only_refuses_domains() {
+    use rsocks5::client;
+    use rsocks5::error::Socks5Error;
+    use rsocks5::protocol::TargetAddr;
+    use std::sync::Arc;
+
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_ip_literals_only(true));
+    assert!(server.ip_literals_only());
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let target = TargetAddr::Domain("example.com".to_string(), 80);
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x08))));
+}
+
+#[tokio::test]
+async fn test_optimistic_reply_counts_failed_connects() {
+    use rsocks5::client;
+    use rsocks5::connection::{Connector, ReplyMode};
+    use rsocks5::metrics::CloseReason;
+    use rsocks5::protocol::TargetAddr;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    // A port with nothing listening on it
+    let closed_port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_connector(Connector::new().with_reply_mode(ReplyMode::Optimistic)),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    // The client is told SUCCEEDED, then the connection just closes
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let target = TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, closed_port);
+    client::connect(&mut stream, &target, None).await.unwrap();
+    let mut buf = [0; 1];
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    for _ in 0..50 {
+        if server.metrics().closed(CloseReason::OptimisticConnectFailed) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.metrics().closed(CloseReason::OptimisticConnectFailed), 1);
+}
+
+#[tokio::test]
+async fn test_shadow_mode_logs_denials_without_enforcing() {
+    use rsocks5::client;
+    use rsocks5::protocol::TargetAddr;
+    use std::sync::Arc;
+
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move { while target.accept().await.is_ok() {} });
+
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_ip_literals_only(true)
+            .with_shadow_mode(true),
+    );
+    assert!(server.shadow_mode());
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    // The domain target would be refused, but shadow mode lets it through
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let target = TargetAddr::Domain("localhost".to_string(), target_port);
+    client::connect(&mut stream, &target, None).await.unwrap();
+    assert_eq!(server.metrics().shadow_denials(), 1);
+}
+
+#[tokio::test]
+async fn test_denial_reason_sent_to_clients_that_ask() {
+    use rsocks5::acl::TargetAllowList;
+    use rsocks5::client;
+    use rsocks5::error::Socks5Error;
+    use rsocks5::protocol::TargetAddr;
+    use std::sync::Arc;
+
+    let mut allowed = TargetAllowList::new();
+    allowed.allow("db.internal", 5432);
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_allowed_targets(allowed)
+            .with_denial_reasons(true),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+    let target = TargetAddr::Domain("smtp.example.com".to_string(), 25);
+
+    // Clients offering the private method get the reason
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    match client::connect_with_denial_reason(&mut stream, &target, None).await {
+        Err(Socks5Error::DeniedError(0x02, reason)) => assert!(reason.starts_with("allow-list")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Standard clients only see the reply code
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))));
+}
+
+#[tokio::test]
+async fn test_denial_reason_names_the_refusing_rule() {
+    use rsocks5::acl::AclRules;
+    use rsocks5::client;
+    use rsocks5::error::Socks5Error;
+    use rsocks5::protocol::TargetAddr;
+    use std::sync::Arc;
+
+    let mut acl = AclRules::new();
+    acl.push_str("deny *:25 name=no-smtp").unwrap();
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_acl(acl)
+            .with_denial_reasons(true),
+    );
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let target = TargetAddr::Domain("smtp.example.com".to_string(), 25);
+    match client::connect_with_denial_reason(&mut stream, &target, None).await {
+        Err(Socks5Error::DeniedError(0x02, reason)) => assert_eq!(reason, "acl: blocked by rule no-smtp"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_listeners_keep_separate_labelled_stats() {
+    use rsocks5::metrics::CloseReason;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    let first = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let second = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    assert_eq!(first.listener_label().address, "127.0.0.1:0");
+
+    let first_listener = first.bind().await.unwrap();
+    let first_addr = first_listener.local_addr().unwrap();
+    let second_listener = second.bind().await.unwrap();
+    let serving = Arc::clone(&first);
+    tokio::spawn(async move { serving.serve(first_listener).await });
+    let serving = Arc::clone(&second);
+    tokio::spawn(async move { serving.serve(second_listener).await });
+
+    // A bogus greeting on the first listener only
+    let mut client = tokio::net::TcpStream::connect(first_addr).await.unwrap();
+    client.write_all(&[0x09, 0x01, 0x00]).await.unwrap();
+    for _ in 0..50 {
+        if first.metrics().closed(CloseReason::Error) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let stats = first.stats();
+    assert_eq!(stats.listener.address, first_addr.to_string());
+    assert_eq!(stats.listener.protocol, "socks5");
+    assert!(!stats.listener.tls);
+    assert_eq!(stats.closed.get("error"), Some(&1));
+    assert_eq!(second.stats().connections_accepted, 0);
+
+    let process = stats.process.as_ref().expect("server stats include process usage");
+    assert!(process.alive_tasks.is_some_and(|tasks| tasks > 0));
+    assert!(stats.to_string().contains(" uptime="));
+}
+
+/// Sends a pipelined greeting, request and early payload, then reads the
+/// whole response once the server had time to give up on the connection
+async fn pipelined_response(addr: std::net::SocketAddr, request: &[u8]) -> Vec<u8> {
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut pipelined = vec![0x05, 0x01, 0x00];
+    pipelined.extend_from_slice(request);
+    pipelined.extend_from_slice(&[0xAB; 4096]);
+    stream.write_all(&pipelined).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Unread payload must not turn the close into a reset that eats the reply
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_denial_reply_survives_unread_client_data() {
+    use std::sync::Arc;
+
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_ip_literals_only(true));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, 11];
+    request.extend_from_slice(b"example.com");
+    request.extend_from_slice(&80u16.to_be_bytes());
+    let response = pipelined_response(addr, &request).await;
+    assert_eq!(response, [0x05, 0x00, 0x05, 0x08, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn test_command_error_reply_survives_unread_client_data() {
+    use std::sync::Arc;
+
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    // BIND is not supported
+    let response = pipelined_response(addr, &[0x05, 0x02, 0x00, 0x01, 127, 0, 0, 1, 0, 80]).await;
+    assert_eq!(response, [0x05, 0x00, 0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn test_connect_failure_reply_survives_unread_client_data() {
+    use std::sync::Arc;
+
+    let closed_port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    request.extend_from_slice(&closed_port.to_be_bytes());
+    let response = pipelined_response(addr, &request).await;
+    assert_eq!(response, [0x05, 0x00, 0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_accept_backoff_doubles_up_to_cap() {
+    use rsocks5::server::AcceptBackoff;
+    use std::time::Duration;
+
+    let backoff = AcceptBackoff::new(Duration::from_millis(10), Duration::from_millis(100));
+    assert_eq!(backoff.delay(1), Duration::from_millis(10));
+    assert_eq!(backoff.delay(2), Duration::from_millis(20));
+    assert_eq!(backoff.delay(4), Duration::from_millis(80));
+    assert_eq!(backoff.delay(5), Duration::from_millis(100));
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(100));
+
+    // A cap below the initial pause is raised to it
+    let backoff = AcceptBackoff::new(Duration::from_millis(50), Duration::from_millis(1));
+    assert_eq!(backoff.delay(3), Duration::from_millis(50));
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_accept_backoff(AcceptBackoff::new(Duration::from_millis(1), Duration::from_secs(5)));
+    assert_eq!(server.accept_backoff().max, Duration::from_secs(5));
+    assert!(server.is_accepting());
+    assert!(server.stats().accepting);
+}
+
+#[tokio::test]
+async fn test_connects_to_ipv6_targets() {
+    use rsocks5::client;
+    use rsocks5::protocol::TargetAddr;
+    use rsocks5::testing::TestServer;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Hosts without IPv6 loopback cannot run this test
+    let Ok(target) = tokio::net::TcpListener::bind("[::1]:0").await else {
+        return;
+    };
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut socket, _) = target.accept().await.unwrap();
+        socket.write_all(b"v6").await.unwrap();
+    });
+
+    let test_server = TestServer::start(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let mut stream = test_server.connect().unwrap();
+    client::connect(&mut stream, &target_addr, None).await.unwrap();
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"v6");
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bind_falls_back_to_next_free_port() {
+    let taken = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let Some(last_port) = port.checked_add(20) else {
+        return;
+    };
+
+    let strict = Server::new("127.0.0.1".to_string(), Some(port), None, None);
+    assert_eq!(strict.last_port(), port);
+    assert!(strict.bind().await.is_err());
+
+    let server = Server::new("127.0.0.1".to_string(), Some(port), None, None).with_port_fallback(last_port);
+    let listener = server.bind().await.unwrap();
+    let selected = listener.local_addr().unwrap().port();
+    assert!(selected > port && selected <= last_port);
+    assert_eq!(server.local_addr().unwrap().port(), selected);
+}
+
+#[tokio::test]
+async fn test_connection_limit_rejects_or_queues_overflow() {
+    use rsocks5::metrics::CloseReason;
+    use rsocks5::server::OverflowMode;
+    use rsocks5::testing::TestServer;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_max_connections(1, OverflowMode::Reject);
+    assert_eq!(server.max_connections(), Some(1));
+    let test_server = TestServer::start(server);
+    let first = test_server.connect().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Rejected clients get an answer to their greeting, then the door
+    let mut second = test_server.connect().unwrap();
+    second.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut reply = [0; 2];
+    second.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, [0x05, 0xFF]);
+    assert_eq!(second.read(&mut [0; 1]).await.unwrap(), 0);
+    assert_eq!(test_server.server().metrics().closed(CloseReason::Overloaded), 1);
+
+    // A finished session frees its slot
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut third = test_server.connect().unwrap();
+    third.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut selected = [0; 2];
+    third.read_exact(&mut selected).await.unwrap();
+    assert_eq!(selected, [0x05, 0x00]);
+    test_server.stop().await.unwrap();
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_max_connections(1, OverflowMode::Queue);
+    let test_server = TestServer::start(server);
+    let first = test_server.connect().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut second = test_server.connect().unwrap();
+    second.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut selected = [0; 2];
+    assert!(tokio::time::timeout(Duration::from_millis(100), second.read_exact(&mut selected)).await.is_err());
+    drop(first);
+    second.read_exact(&mut selected).await.unwrap();
+    assert_eq!(selected, [0x05, 0x00]);
+    assert_eq!(test_server.server().metrics().closed(CloseReason::Overloaded), 0);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rejections_beyond_the_pool_are_closed_unanswered() {
+    use rsocks5::metrics::CloseReason;
+    use rsocks5::server::{OverflowMode, MAX_PENDING_REJECTS};
+    use rsocks5::testing::TestServer;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_max_connections(1, OverflowMode::Reject);
+    let test_server = TestServer::start(server);
+    let _first = test_server.connect().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Silent clients hold every reject slot until their greeting times out
+    let mut silent = Vec::new();
+    for _ in 0..MAX_PENDING_REJECTS {
+        silent.push(test_server.connect().unwrap());
+    }
+    let metrics = test_server.server().metrics();
+    while metrics.closed(CloseReason::Overloaded) < MAX_PENDING_REJECTS as u64 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut late = test_server.connect().unwrap();
+    let _ = late.write_all(&[0x05, 0x01, 0x00]).await;
+    let mut reply = Vec::new();
+    let _ = late.read_to_end(&mut reply).await;
+    assert!(reply.is_empty(), "{:?}", reply);
+    test_server.stop().await.unwrap();
+}
@@ -0,0 +1,78 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::slo::{LatencySlo, SloMonitor};
+use rsocks5::{client, Server};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+#[test]
+fn test_slo_parsing() {
+    let slo: LatencySlo = "*.payments.internal>300ms".parse().unwrap();
+    assert_eq!(slo.threshold, Duration::from_millis(300));
+    assert!(slo.pattern.matches(&TargetAddr::Domain("api.payments.internal".to_string(), 443)));
+    assert_eq!(slo.to_string(), "*.payments.internal>300ms");
+
+    let slo: LatencySlo = "db.internal:5432 > 2s".parse().unwrap();
+    assert_eq!(slo.threshold, Duration::from_secs(2));
+    assert_eq!("10.0.0.0/8>150".parse::<LatencySlo>().unwrap().threshold, Duration::from_millis(150));
+
+    assert!("*.payments.internal".parse::<LatencySlo>().is_err());
+    assert!("*.payments.internal>fast".parse::<LatencySlo>().is_err());
+}
+
+#[test]
+fn test_repeated_breaches_within_the_window_alert_once() {
+    let clock = Arc::new(ManualClock::new());
+    let monitor = SloMonitor::new(vec!["*.payments.internal>300ms".parse().unwrap()], clock.clone())
+        .with_breaches(2)
+        .with_window(Duration::from_secs(60));
+    let target = TargetAddr::Domain("api.payments.internal".to_string(), 443);
+    let slow = Duration::from_millis(301);
+
+    // Fast connects and other destinations are not breaches
+    assert_eq!(monitor.record(&target, Duration::from_millis(300)), None);
+    assert_eq!(monitor.record(&TargetAddr::Domain("example.com".to_string(), 443), slow), None);
+
+    // Breaches a window apart never add up
+    assert_eq!(monitor.record(&target, slow), None);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(monitor.record(&target, slow), None);
+
+    clock.advance(Duration::from_secs(30));
+    let alert = monitor.record(&target, slow).unwrap();
+    assert_eq!(alert.slo, monitor.slos()[0]);
+    assert_eq!((alert.latency, alert.breaches), (slow, 2));
+    // The alert starts a new set of breaches
+    assert_eq!(monitor.record(&target, slow), None);
+    assert_eq!(monitor.alerts(), 1);
+}
+
+#[tokio::test]
+async fn test_server_counts_alerts() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = TargetAddr::from(target.local_addr().unwrap());
+    tokio::spawn(async move { while target.accept().await.is_ok() {} });
+
+    // Any connect takes longer than zero milliseconds
+    let monitor = SloMonitor::new(vec!["127.0.0.1>0ms".parse().unwrap()], Arc::new(ManualClock::new())).with_breaches(1);
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None).with_slo_monitor(monitor));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    client::connect(&mut stream, &target_addr, None).await.unwrap();
+    // The reply may reach the client before the alert is counted
+    for _ in 0..50 {
+        if server.metrics().slo_alerts() > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.metrics().slo_alerts(), 1);
+    assert_eq!(server.slo_monitor().unwrap().alerts(), 1);
+    assert!(server.stats().to_string().contains(" slo_alerts=1"));
+    server.shutdown();
+}
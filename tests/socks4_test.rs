@@ -0,0 +1,100 @@
+use rsocks5::protocol::TargetAddr;
+use rsocks5::socks4::read_socks4_request;
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Builds a SOCKS4 CONNECT request; a host name makes it SOCKS4a
+fn request(ip: [u8; 4], port: u16, host: Option<&str>) -> Vec<u8> {
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&ip);
+    request.extend_from_slice(b"legacy\0");
+    if let Some(host) = host {
+        request.extend_from_slice(host.as_bytes());
+        request.push(0);
+    }
+    request
+}
+
+#[tokio::test]
+async fn test_reads_socks4_and_socks4a_requests() {
+    let (mut client, mut server) = tokio::io::duplex(1024);
+    client.write_all(&request([192, 0, 2, 1], 80, None)).await.unwrap();
+    client.write_all(&request([0, 0, 0, 1], 443, Some("example.com"))).await.unwrap();
+    assert_eq!(read_socks4_request(&mut server).await.unwrap(), TargetAddr::Ipv4("192.0.2.1".parse().unwrap(), 80));
+    assert_eq!(read_socks4_request(&mut server).await.unwrap(), TargetAddr::Domain("example.com".to_string(), 443));
+}
+
+#[tokio::test]
+async fn test_server_serves_socks4_by_default() {
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut socket, _) = target.accept().await.unwrap();
+        socket.write_all(b"hi").await.unwrap();
+    });
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None);
+    assert!(server.socks4());
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(&request([127, 0, 0, 1], target_port, None)).await.unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x00, 0x5A]);
+    assert_eq!(&reply[8..], b"hi");
+
+    // Policies apply to SOCKS4 requests as well
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(&request([0, 0, 0, 1], 0, Some("localhost"))).await.unwrap();
+    let mut reply = [0; 8];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x00, 0x5B]);
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_refuses_socks4_when_credentials_are_required() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_credentials("alice".to_string(), "secret".to_string());
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(&request([192, 0, 2, 1], 80, None)).await.unwrap();
+    let mut reply = [0; 8];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x00, 0x5B]);
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_socks4_requests_are_mirrored() {
+    use rsocks5::mirror::RequestMirror;
+    use tokio::net::UdpSocket;
+
+    let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mirror = RequestMirror::connect(&format!("udp://{}", collector.local_addr().unwrap())).await.unwrap();
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_request_mirror(mirror)
+        .with_decision_tracing(true);
+    let test_server = TestServer::start(server);
+
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(&request([0, 0, 0, 1], 0, Some("example.com"))).await.unwrap();
+    let mut reply = [0; 8];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x00, 0x5B]);
+
+    let mut buf = [0; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&buf[..n]).unwrap();
+    assert_eq!(event["protocol"], "socks4");
+    assert_eq!(event["target"], "example.com:0");
+    assert_eq!(event["decision"], "port-policy=deny");
+
+    test_server.stop().await.unwrap();
+}
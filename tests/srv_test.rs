@@ -0,0 +1,85 @@
+use rsocks5::connection::Connector;
+use rsocks5::hosts::Hosts;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::random::SeededRandom;
+use rsocks5::routing::{Route, RoutingTable};
+use rsocks5::srv::{SrvRecord, SrvResolver};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Encodes a host name as DNS labels
+fn labels(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// Starts a nameserver answering every query with the given SRV records
+async fn nameserver(records: Vec<(u16, u16, u16, &'static str)>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+            let query = &buf[..len];
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1]);
+            response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+            response.extend_from_slice(&[0, 0, 0, 0]);
+            response.extend_from_slice(&query[12..]);
+            for (priority, weight, port, target) in &records {
+                let target = labels(target);
+                // Name compressed to the question, type SRV, class IN, TTL 60
+                response.extend_from_slice(&[0xC0, 0x0C, 0, 33, 0, 1, 0, 0, 0, 60]);
+                response.extend_from_slice(&(6 + target.len() as u16).to_be_bytes());
+                for field in [priority, weight, port] {
+                    response.extend_from_slice(&field.to_be_bytes());
+                }
+                response.extend_from_slice(&target);
+            }
+            socket.send_to(&response, client).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_srv_records_are_ordered_by_priority() {
+    let addr = nameserver(vec![(20, 0, 5432, "backup.test."), (10, 5, 6432, "primary.test.")]).await;
+    let resolver = SrvResolver::new(vec![addr]).with_random(Arc::new(SeededRandom::new(7)));
+
+    let records = resolver.lookup("_postgres._tcp.db.test").await.unwrap();
+    assert_eq!(
+        records,
+        vec![
+            SrvRecord { priority: 10, weight: 5, port: 6432, target: "primary.test".to_string() },
+            SrvRecord { priority: 20, weight: 0, port: 5432, target: "backup.test".to_string() },
+        ]
+    );
+    assert!(resolver.lookup("bad..name").await.is_err());
+}
+
+#[tokio::test]
+async fn test_route_resolves_target_through_srv() {
+    let service = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = service.local_addr().unwrap().port();
+    let records = vec![(10, 0, port, "node1.svc.test.")];
+    let resolver = SrvResolver::new(vec![nameserver(records).await]);
+
+    let route: Route = "*.svc.test=srv:_http._tcp".parse().unwrap();
+    assert_eq!(route.srv.as_deref(), Some("_http._tcp"));
+    let connector = Connector::new()
+        .with_routes(RoutingTable::new(vec![route]))
+        .with_hosts(Hosts::new().with_entry("node1.svc.test", "127.0.0.1".parse().unwrap()))
+        .with_srv_resolver(resolver);
+
+    // The requested port is replaced by the one from the SRV record
+    let stream = connector.open(&TargetAddr::Domain("api.svc.test".to_string(), 1)).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap().port(), port);
+}
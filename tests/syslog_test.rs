@@ -0,0 +1,69 @@
+use rsocks5::mirror::RequestEvent;
+use rsocks5::syslog::{format_record, SyslogSink};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, UdpSocket};
+
+fn sample_event() -> RequestEvent {
+    RequestEvent {
+        conn_id: "0000beef".to_string(),
+        timestamp_ms: 1_700_000_000_123,
+        client: "127.0.0.1:40000".parse().unwrap(),
+        protocol: "socks5",
+        target: "example.com:443".to_string(),
+        rewritten_target: None,
+        username: Some("al\"ice]".to_string()),
+        method: 0x02,
+        offered_methods: vec![0x00, 0x02],
+        decision: None,
+    }
+}
+
+#[test]
+fn test_record_follows_rfc_5424() {
+    let record = format_record(&sample_event(), 16, "proxy1");
+    let expected = format!(
+        "<134>1 2023-11-14T22:13:20.123Z proxy1 rsocks5 {} request [socks@32473 conn=\"0000beef\" \
+         client=\"127.0.0.1:40000\" protocol=\"socks5\" target=\"example.com:443\" user=\"al\\\"ice\\]\" method=\"0x02\"] \
+         127.0.0.1:40000 requested example.com:443",
+        std::process::id()
+    );
+    assert_eq!(record, expected);
+}
+
+#[tokio::test]
+async fn test_records_sent_over_udp() {
+    let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("udp://{}", collector.local_addr().unwrap());
+    let sink = SyslogSink::connect(&url).await.unwrap().with_facility(4).with_hostname("proxy1");
+    assert_eq!(sink.destination(), url);
+
+    sink.publish(&sample_event());
+
+    let mut buf = [0; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let record = std::str::from_utf8(&buf[..n]).unwrap();
+    assert!(record.starts_with("<38>1 2023-11-14T22:13:20.123Z proxy1 rsocks5 "));
+    assert_eq!(sink.dropped(), 0);
+}
+
+#[tokio::test]
+async fn test_records_octet_counted_over_tcp() {
+    let collector = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("tcp://{}", collector.local_addr().unwrap());
+    let sink = SyslogSink::connect(&url).await.unwrap().with_hostname("proxy1");
+
+    sink.publish(&sample_event());
+    sink.publish(&sample_event());
+
+    let (mut stream, _) = collector.accept().await.unwrap();
+    let expected = format_record(&sample_event(), 16, "proxy1");
+    let framed = format!("{} {}", expected.len(), expected);
+    let mut buf = vec![0; framed.len() * 2];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, format!("{}{}", framed, framed).into_bytes());
+}
+
+#[tokio::test]
+async fn test_unsupported_url_is_rejected() {
+    assert!(SyslogSink::connect("http://127.0.0.1:514").await.is_err());
+}
@@ -0,0 +1,57 @@
+use rsocks5::acl::TargetAllowList;
+use rsocks5::client::{self, Credentials};
+use rsocks5::error::Socks5Error;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::testing::TestServer;
+use rsocks5::Server;
+
+fn server() -> Server {
+    Server::new("127.0.0.1".to_string(), Some(0), None, None)
+}
+
+#[tokio::test]
+async fn test_policies_apply_to_in_memory_clients() {
+    let test_server = TestServer::start(server().with_ip_literals_only(true));
+
+    let mut stream = test_server.connect().unwrap();
+    let target = TargetAddr::Domain("example.com".to_string(), 80);
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x08))));
+    assert_eq!(test_server.server().stats().connections_accepted, 1);
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_authentication_over_in_memory_transport() {
+    let mut allowed = TargetAllowList::new();
+    allowed.allow("db.internal", 5432);
+    let test_server = TestServer::start(
+        server()
+            .with_credentials("alice".to_string(), "secret".to_string())
+            .with_allowed_targets(allowed),
+    );
+    let target = TargetAddr::Domain("smtp.example.com".to_string(), 25);
+
+    // Wrong credentials fail the handshake
+    let mut stream = test_server.connect().unwrap();
+    let wrong = Credentials::new("alice", "guess");
+    let result = client::connect(&mut stream, &target, Some(&wrong)).await;
+    assert!(matches!(result, Err(Socks5Error::HandshakeError(_))));
+
+    // Right credentials get as far as the allow list
+    let mut stream = test_server.connect().unwrap();
+    let right = Credentials::new("alice", "secret");
+    let result = client::connect(&mut stream, &target, Some(&right)).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x02))));
+}
+
+#[tokio::test]
+async fn test_stop_shuts_the_server_down() {
+    let test_server = TestServer::start(server());
+    let server = std::sync::Arc::clone(test_server.server());
+    assert!(!server.is_shut_down());
+
+    test_server.stop().await.unwrap();
+    assert!(server.is_shut_down());
+}
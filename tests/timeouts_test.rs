@@ -0,0 +1,92 @@
+use rsocks5::connection::Connector;
+use rsocks5::metrics::CloseReason;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::relay::{Relay, RelayEngine, RelayOptions};
+use rsocks5::testing::TestServer;
+use rsocks5::timeouts::{Timeouts, DEFAULT_CONNECT, DEFAULT_HANDSHAKE, DEFAULT_IDLE};
+use rsocks5::Server;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+#[test]
+fn test_defaults_bound_every_phase_but_the_first_byte() {
+    let timeouts = Timeouts::default();
+    assert_eq!(timeouts.first_byte, None);
+    assert_eq!(timeouts.handshake, Some(DEFAULT_HANDSHAKE));
+    assert_eq!(timeouts.connect, Some(DEFAULT_CONNECT));
+    assert_eq!(timeouts.idle, Some(DEFAULT_IDLE));
+    assert_eq!(Server::new("127.0.0.1".to_string(), Some(0), None, None).timeouts(), timeouts);
+
+    let none = Timeouts::none();
+    assert_eq!((none.first_byte, none.handshake, none.connect, none.idle), (None, None, None, None));
+}
+
+#[tokio::test]
+async fn test_handshake_timeout_closes_stalled_client() {
+    let timeouts = Timeouts {
+        handshake: Some(Duration::from_millis(100)),
+        ..Timeouts::default()
+    };
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_timeouts(timeouts);
+    let test_server = TestServer::start(server);
+
+    // A greeting announcing one method that never follows
+    let mut stream = test_server.connect().unwrap();
+    stream.write_all(&[0x05, 0x01]).await.unwrap();
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut [0; 1]))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+
+    let metrics = test_server.server().metrics();
+    for _ in 0..100 {
+        if metrics.closed(CloseReason::HandshakeTimeout) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(metrics.closed(CloseReason::HandshakeTimeout), 1);
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_connect_timeout_gives_up_on_unresponsive_target() {
+    // A listener whose accept queue is full drops further SYNs
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(0).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut queued = Vec::new();
+    for _ in 0..2 {
+        if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(200), TcpStream::connect(addr)).await {
+            queued.push(stream);
+        }
+    }
+
+    let connector = Connector::new().with_connect_timeout(Duration::from_millis(200));
+    assert_eq!(connector.connect_timeout(), Some(Duration::from_millis(200)));
+    let result = tokio::time::timeout(Duration::from_secs(5), connector.open(&TargetAddr::from(addr)))
+        .await
+        .expect("the connect timeout did not fire");
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn test_lean_relay_honours_idle_timeout() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_stream = TcpStream::connect(target.local_addr().unwrap()).await.unwrap();
+    let (_held, _) = target.accept().await.unwrap();
+    let (_client, proxy_side) = tokio::io::duplex(64);
+
+    let options = RelayOptions::new()
+        .with_engine(RelayEngine::Lean)
+        .with_idle_timeout(Duration::from_millis(100));
+    let relay = Relay::new("127.0.0.1:1".parse().unwrap(), "idle target".to_string()).with_options(options);
+    let result = tokio::time::timeout(Duration::from_secs(5), relay.start_relay(proxy_side, target_stream))
+        .await
+        .expect("the idle timeout did not fire");
+    assert!(result.is_err(), "{:?}", result);
+}
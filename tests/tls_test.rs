@@ -0,0 +1,200 @@
+use rsocks5::admin::AdminApi;
+use rsocks5::client::{self, Credentials};
+use rsocks5::config::ServerConfig;
+use rsocks5::error::Socks5Error;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::tls::{self, AlpnFuture, AlpnHandler};
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsConnector;
+
+const CERT: &str = "tests/data/localhost.crt";
+const KEY: &str = "tests/data/localhost.key";
+
+/// Serves `server` over TLS and returns the address it listens on
+async fn serve(server: Server) -> SocketAddr {
+    let server = Arc::new(server.with_tls(tls::load_server_config(Path::new(CERT), Path::new(KEY)).unwrap()));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve_tcp(listener).await });
+    addr
+}
+
+/// Opens a TLS connection to `addr`, trusting only the test certificate
+async fn connect_tls(addr: SocketAddr) -> tokio_rustls::client::TlsStream<TcpStream> {
+    connect_tls_alpn(addr, &[]).await
+}
+
+/// Opens a TLS connection to `addr` offering the ALPN protocols `alpn`
+async fn connect_tls_alpn(addr: SocketAddr, alpn: &[&[u8]]) -> tokio_rustls::client::TlsStream<TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(CERT).unwrap()).unwrap();
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let name = ServerName::try_from("localhost").unwrap();
+    TlsConnector::from(Arc::new(config)).connect(name, stream).await.unwrap()
+}
+
+/// Starts a target echoing back what it receives
+async fn echo_target() -> TargetAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    TargetAddr::from(addr)
+}
+
+/// Greets every connection handed to it and hangs up
+#[derive(Debug)]
+struct Greeter;
+
+impl AlpnHandler for Greeter {
+    fn serve(self: Arc<Self>, mut stream: TlsStream<TcpStream>, _client: SocketAddr) -> AlpnFuture {
+        Box::pin(async move {
+            let _ = stream.write_all(b"hello").await;
+            let _ = stream.shutdown().await;
+        })
+    }
+}
+
+#[test]
+fn test_load_server_config_errors() {
+    let result = tls::load_server_config(Path::new("tests/data/missing.crt"), Path::new(KEY));
+    assert!(matches!(result, Err(Socks5Error::ConfigError(_))), "{:?}", result);
+    // The certificate is no key
+    let result = tls::load_server_config(Path::new(CERT), Path::new(CERT));
+    assert!(matches!(result, Err(Socks5Error::ConfigError(_))), "{:?}", result);
+
+    let config = ServerConfig::from_toml(&format!("[[listeners]]\nport = 0\ntls_cert = \"{}\"", CERT)).unwrap();
+    assert!(matches!(config.build_servers(), Err(Socks5Error::ConfigError(_))));
+    let config = ServerConfig::from_toml(&format!("[[listeners]]\nport = 0\ntls_cert = \"{}\"\ntls_key = \"{}\"", CERT, KEY)).unwrap();
+    assert!(config.build_servers().unwrap()[0].tls().is_some());
+}
+
+#[tokio::test]
+async fn test_relays_over_tls() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), Some("alice".to_string()), Some("secret".to_string()));
+    let addr = serve(server).await;
+    let target = echo_target().await;
+
+    let mut stream = connect_tls(addr).await;
+    client::connect(&mut stream, &target, Some(&Credentials::new("alice", "secret"))).await.unwrap();
+    stream.write_all(b"over tls").await.unwrap();
+    let mut buf = [0; 8];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"over tls");
+
+    // Plaintext SOCKS is not understood by a TLS listener
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let result = client::connect(&mut stream, &target, None).await;
+    assert!(result.is_err(), "{:?}", result);
+}
+
+#[tokio::test]
+async fn test_stalled_handshake_does_not_block_others() {
+    let addr = serve(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+    let target = echo_target().await;
+
+    // Connects but never starts the TLS handshake
+    let _stalled = TcpStream::connect(addr).await.unwrap();
+    let mut stream = tokio::time::timeout(std::time::Duration::from_secs(5), connect_tls(addr)).await.unwrap();
+    client::connect(&mut stream, &target, None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handshake_failures_and_timeouts_are_counted_apart() {
+    let server = Arc::new(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_tls(tls::load_server_config(Path::new(CERT), Path::new(KEY)).unwrap())
+            .with_tls_handshake_timeout(std::time::Duration::from_millis(100))
+            .with_first_byte_timeout(std::time::Duration::from_secs(30)),
+    );
+    assert_eq!(server.tls_handshake_timeout(), std::time::Duration::from_millis(100));
+    assert!(server.listener_label().tls);
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn({
+        let server = Arc::clone(&server);
+        async move { server.serve_tcp(listener).await }
+    });
+
+    // Stalls the handshake past its deadline, well within the first-byte timeout
+    let _stalled = TcpStream::connect(addr).await.unwrap();
+    // Speaks plaintext SOCKS to the TLS listener
+    let mut plaintext = TcpStream::connect(addr).await.unwrap();
+    plaintext.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+
+    let metrics = server.metrics();
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while metrics.tls_handshake_timeouts() == 0 || metrics.tls_handshake_failures() == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(metrics.tls_handshake_timeouts(), 1);
+    assert_eq!(metrics.tls_handshake_failures(), 1);
+    assert_eq!(metrics.connections_accepted(), 0);
+    let stats = server.stats().to_string();
+    assert!(stats.contains(" tls.handshake_failed=1 tls.handshake_timeout=1"), "{}", stats);
+}
+
+#[tokio::test]
+async fn test_alpn_dispatches_other_protocols_to_their_handler() {
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_alpn_handler("greeting", Arc::new(Greeter));
+    let addr = serve(server).await;
+    let target = echo_target().await;
+
+    let mut stream = connect_tls_alpn(addr, &[b"greeting"]).await;
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"greeting"[..]));
+    let mut greeting = Vec::new();
+    stream.read_to_end(&mut greeting).await.unwrap();
+    assert_eq!(greeting, b"hello");
+
+    // Clients asking for SOCKS, or for nothing, are proxied
+    for alpn in [&[tls::ALPN_SOCKS5][..], &[]] {
+        let mut stream = connect_tls_alpn(addr, alpn).await;
+        client::connect(&mut stream, &target, None).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+    }
+}
+
+#[tokio::test]
+async fn test_admin_api_shares_the_tls_port() {
+    let server = Arc::new(Server::new("127.0.0.1".to_string(), Some(0), None, None));
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = tls::load_server_config(Path::new(CERT), Path::new(KEY)).unwrap();
+    let api = Arc::new(AdminApi::new(vec![Arc::clone(&server)]));
+    let listener = tls::TlsListener::new(listener, config).with_alpn_handler(tls::ALPN_HTTP1, api);
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut stream = connect_tls_alpn(addr, &[tls::ALPN_HTTP1]).await;
+    stream.write_all(b"GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+}
@@ -0,0 +1,171 @@
+use rsocks5::client;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::upstream::{UpstreamMode, UpstreamProxy, Upstreams};
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Starts a TCP echo server and returns its address
+async fn spawn_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Starts a SOCKS5 server and returns its address
+async fn spawn_server(server: Server) -> SocketAddr {
+    let server = Arc::new(server);
+    let listener = server.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve(listener).await });
+    addr
+}
+
+/// Starts a listener that accepts connections but never answers
+async fn spawn_black_hole() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            held.push(stream);
+        }
+    });
+    addr
+}
+
+fn target(addr: SocketAddr) -> TargetAddr {
+    match addr {
+        SocketAddr::V4(v4) => TargetAddr::Ipv4(*v4.ip(), v4.port()),
+        SocketAddr::V6(_) => unreachable!("tests bind IPv4 loopback"),
+    }
+}
+
+#[tokio::test]
+async fn test_race_prefers_fast_upstream() {
+    // The first upstream never answers, so the staggered second one wins
+    let echo = spawn_echo().await;
+    let slow = spawn_black_hole().await;
+    let fast = spawn_server(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+
+    let upstreams = Upstreams::new(
+        vec![UpstreamProxy::new(slow.to_string()), UpstreamProxy::new(fast.to_string())],
+        UpstreamMode::Race { stagger: Duration::from_millis(50) },
+    );
+
+    let mut stream = tokio::time::timeout(Duration::from_secs(5), upstreams.connect(&target(echo)))
+        .await
+        .unwrap()
+        .unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn test_server_forwards_through_upstream() {
+    // A server configured with an upstream tunnels client traffic through it
+    let echo = spawn_echo().await;
+    let upstream = spawn_server(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+    let edge = spawn_server(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None).with_upstreams(Upstreams::new(
+            vec![UpstreamProxy::new(upstream.to_string())],
+            UpstreamMode::Failover,
+        )),
+    )
+    .await;
+
+    let mut stream = TcpStream::connect(edge).await.unwrap();
+    client::connect(&mut stream, &target(echo), None).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn test_failover_reports_upstream_failure() {
+    // Every upstream refusing yields an error
+    let closed = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    };
+    let upstreams = Upstreams::new(vec![UpstreamProxy::new(closed.to_string())], UpstreamMode::Failover);
+    assert!(upstreams.connect(&TargetAddr::Domain("example.com".to_string(), 80)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_chain_traverses_every_hop() {
+    // Two chained proxies: the first is asked to CONNECT to the second
+    use rsocks5::upstream::ProxyChain;
+
+    let echo = spawn_echo().await;
+    let hop_a = spawn_server(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+    let hop_b = spawn_server(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+
+    let chain = ProxyChain::new(vec![
+        UpstreamProxy::new(hop_a.to_string()),
+        UpstreamProxy::new(hop_b.to_string()),
+    ]);
+    assert_eq!(chain.describe(), format!("{} -> {}", hop_a, hop_b));
+
+    let mut stream = chain.connect(&target(echo)).await.unwrap();
+    stream.write_all(b"chain").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"chain");
+}
+
+#[tokio::test]
+async fn test_black_holed_upstream_fails_over_in_time() {
+    let echo = spawn_echo().await;
+    let dead = spawn_black_hole().await;
+    let alive = spawn_server(Server::new("127.0.0.1".to_string(), Some(0), None, None)).await;
+
+    let upstreams = Upstreams::new(
+        vec![UpstreamProxy::new(dead.to_string()), UpstreamProxy::new(alive.to_string())],
+        UpstreamMode::Failover,
+    )
+    .with_connect_timeout(Duration::from_millis(100));
+    assert_eq!(upstreams.connect_timeout(), Some(Duration::from_millis(100)));
+
+    let mut stream = tokio::time::timeout(Duration::from_secs(5), upstreams.connect(&target(echo)))
+        .await
+        .unwrap()
+        .unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn test_server_bounds_upstreams_by_its_connect_deadline() {
+    use rsocks5::timeouts::Timeouts;
+
+    let dead = spawn_black_hole().await;
+    let edge = spawn_server(
+        Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_upstreams(Upstreams::new(vec![UpstreamProxy::new(dead.to_string())], UpstreamMode::Failover))
+            .with_timeouts(Timeouts { connect: Some(Duration::from_millis(100)), ..Timeouts::default() }),
+    )
+    .await;
+
+    let mut stream = TcpStream::connect(edge).await.unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(5), client::connect(&mut stream, &target(edge), None))
+        .await
+        .unwrap();
+    assert!(result.is_err());
+}
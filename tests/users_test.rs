@@ -0,0 +1,111 @@
+use rsocks5::client::{self, Credentials};
+use rsocks5::error::Socks5Error;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::testing::TestServer;
+use rsocks5::users::{parse_account, AuthDecision, AuthFuture, Authenticator, UserTable};
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_table_loads_and_verifies_accounts() {
+    let path = std::env::temp_dir().join(format!("rsocks5-users-{}.txt", std::process::id()));
+    std::fs::write(&path, "# accounts\nalice:wonder:land\n\nbob:builder\n").unwrap();
+    let users = UserTable::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(users.len(), 2);
+    assert_eq!(users.usernames().collect::<Vec<_>>(), ["alice", "bob"]);
+    assert!(users.verify("alice", "wonder:land"));
+    assert!(!users.verify("alice", "wonder"));
+    assert!(!users.verify("carol", ""));
+    assert_eq!(format!("{:?}", users), r#"{"alice", "bob"}"#);
+
+    assert_eq!(parse_account("carol:").unwrap(), ("carol", ""));
+    assert!(parse_account(":secret").is_err());
+    assert!(parse_account("carol").is_err());
+}
+
+#[tokio::test]
+async fn test_server_accepts_every_account() {
+    let users = UserTable::new().with_user("alice", "one").with_user("bob", "two");
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None).with_users(users);
+    assert_eq!(server.capabilities().auth_methods, ["username-password"]);
+    let test_server = TestServer::start(server);
+
+    // The target is refused by the port policy, which is only checked after
+    // authentication succeeded
+    let target = TargetAddr::Domain("example.com".to_string(), 0);
+    for (username, password) in [("alice", "one"), ("bob", "two")] {
+        let mut stream = test_server.connect().unwrap();
+        let credentials = Credentials::new(username, password);
+        let result = client::connect(&mut stream, &target, Some(&credentials)).await;
+        assert!(matches!(result, Err(Socks5Error::ReplyError(0x08))), "{:?}", result);
+    }
+
+    let mut stream = test_server.connect().unwrap();
+    let credentials = Credentials::new("alice", "two");
+    let result = client::connect(&mut stream, &target, Some(&credentials)).await;
+    assert!(matches!(result, Err(Socks5Error::HandshakeError(_))), "{:?}", result);
+
+    test_server.stop().await.unwrap();
+}
+
+/// Accepts users whose password is their name reversed, remembering who asked
+#[derive(Debug, Default)]
+struct ReversedNames {
+    peers: Mutex<Vec<SocketAddr>>,
+}
+
+impl Authenticator for ReversedNames {
+    fn authenticate<'a>(&'a self, username: &'a str, password: &'a str, peer_addr: SocketAddr) -> AuthFuture<'a> {
+        Box::pin(async move {
+            self.peers.lock().unwrap().push(peer_addr);
+            tokio::task::yield_now().await;
+            if username.chars().rev().eq(password.chars()) {
+                AuthDecision::Allow
+            } else {
+                AuthDecision::Deny
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_server_checks_credentials_with_custom_authenticator() {
+    let authenticator = Arc::new(ReversedNames::default());
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+        .with_credentials("alice".to_string(), "one".to_string())
+        .with_authenticator(authenticator.clone());
+    assert_eq!(server.capabilities().auth_methods, ["username-password"]);
+    let test_server = TestServer::start(server);
+
+    let target = TargetAddr::Domain("example.com".to_string(), 0);
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &target, Some(&Credentials::new("alice", "ecila"))).await;
+    assert!(matches!(result, Err(Socks5Error::ReplyError(0x08))), "{:?}", result);
+
+    // The authenticator replaces the configured accounts
+    let mut stream = test_server.connect().unwrap();
+    let result = client::connect(&mut stream, &target, Some(&Credentials::new("alice", "one"))).await;
+    assert!(matches!(result, Err(Socks5Error::HandshakeError(_))), "{:?}", result);
+
+    let peers = authenticator.peers.lock().unwrap().clone();
+    assert_eq!(peers.len(), 2);
+    assert!(peers.iter().all(|peer| peer.ip().is_loopback() && peer.port() != 0));
+
+    test_server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_table_is_the_default_authenticator() {
+    let peer: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+    let users = UserTable::new().with_user("alice", "one");
+    assert_eq!(users.authenticate("alice", "one", peer).await, AuthDecision::Allow);
+    assert_eq!(users.authenticate("alice", "two", peer).await, AuthDecision::Deny);
+
+    let server = Server::new("127.0.0.1".to_string(), Some(0), None, None);
+    assert!(server.authenticator().is_none());
+    let authenticator = server.with_users(users).authenticator().unwrap();
+    assert_eq!(authenticator.authenticate("alice", "one", peer).await, AuthDecision::Allow);
+}
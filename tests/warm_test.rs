@@ -0,0 +1,68 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::connection::Connector;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::warm::{WarmConfig, WarmPool};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+#[test]
+fn test_cached_addrs_expire_after_two_refresh_intervals() {
+    let clock = Arc::new(ManualClock::new());
+    let target = TargetAddr::Domain("Api.Example.com".to_string(), 443);
+    let pool = WarmPool::new(WarmConfig::new(vec![target.clone()]), clock.clone());
+    assert!(pool.cached_addrs(&target).is_none());
+
+    let addrs: Vec<SocketAddr> = vec!["192.0.2.1:443".parse().unwrap()];
+    pool.store_addrs(&target, addrs.clone());
+    let lowercase = TargetAddr::Domain("api.example.com".to_string(), 443);
+    assert_eq!(pool.cached_addrs(&lowercase), Some(addrs));
+
+    clock.advance(Duration::from_secs(61));
+    assert!(pool.cached_addrs(&target).is_none());
+}
+
+#[tokio::test]
+async fn test_refresh_keeps_idle_connections_ready() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, listener.local_addr().unwrap().port());
+    tokio::spawn(async move {
+        let mut accepted = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            accepted.push(stream);
+        }
+    });
+
+    let mut config = WarmConfig::new(vec![target.clone()]);
+    config.idle_connections = 2;
+    let connector = Connector::new().with_warm(WarmPool::new(config, Arc::new(ManualClock::new())));
+
+    connector.refresh_warm().await;
+    let warm = connector.warm().unwrap();
+    assert_eq!(warm.idle_connections(&target), 2);
+    assert!(warm.cached_addrs(&target).is_some());
+
+    // Opening the target hands out a pre-connected socket
+    let stream = connector.open(&target).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap().port(), target.port());
+    assert_eq!(warm.idle_connections(&target), 1);
+}
+
+#[tokio::test]
+async fn test_closed_idle_connections_are_discarded() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = TargetAddr::Ipv4(Ipv4Addr::LOCALHOST, listener.local_addr().unwrap().port());
+
+    let mut config = WarmConfig::new(vec![target.clone()]);
+    config.idle_connections = 1;
+    let connector = Connector::new().with_warm(WarmPool::new(config, Arc::new(ManualClock::new())));
+    connector.refresh_warm().await;
+
+    // The target hangs up on the idle connection
+    let (accepted, _) = listener.accept().await.unwrap();
+    drop(accepted);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert!(connector.warm().unwrap().take_connection(&target).is_none());
+}
@@ -0,0 +1,40 @@
+use rsocks5::clock::ManualClock;
+use rsocks5::warnings::WarningAggregator;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_repeats_are_collapsed_per_key_and_window() {
+    let clock = Arc::new(ManualClock::new());
+    let warnings = WarningAggregator::new("handshake failures", Duration::from_secs(60), clock.clone());
+
+    assert!(warnings.record(ip("10.2.3.4")));
+    assert!(!warnings.record(ip("10.2.3.4")));
+    assert!(!warnings.record(ip("10.2.3.4")));
+    assert!(warnings.record(ip("10.2.3.5")));
+    assert_eq!(warnings.suppressed(), 2);
+
+    // A new window lets the next occurrence through again
+    clock.advance(Duration::from_secs(60));
+    assert!(warnings.record(ip("10.2.3.4")));
+    assert!(!warnings.record(ip("10.2.3.4")));
+
+    // Flushed windows are forgotten
+    clock.advance(Duration::from_secs(60));
+    warnings.flush();
+    assert!(warnings.record(ip("10.2.3.5")));
+    assert_eq!(warnings.suppressed(), 3);
+}
+
+#[test]
+fn test_zero_window_logs_everything() {
+    let warnings = WarningAggregator::new("handshake failures", Duration::ZERO, Arc::new(ManualClock::new()));
+    assert!(warnings.record(ip("10.2.3.4")));
+    assert!(warnings.record(ip("10.2.3.4")));
+    assert_eq!(warnings.suppressed(), 0);
+}
@@ -0,0 +1,75 @@
+use rsocks5::client::{self, Credentials};
+use rsocks5::error::Socks5Error;
+use rsocks5::protocol::TargetAddr;
+use rsocks5::testing::TestServer;
+use rsocks5::users::{AuthFuture, Authenticator};
+use rsocks5::watchdog::{FailMode, Hook, HookBudget, Watchdog, DEFAULT_TIMEOUT};
+use rsocks5::Server;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Never decides, like an authorizer whose backend stopped answering
+#[derive(Debug)]
+struct Hanging;
+
+impl Authenticator for Hanging {
+    fn authenticate<'a>(&'a self, _username: &'a str, _password: &'a str, _peer_addr: SocketAddr) -> AuthFuture<'a> {
+        Box::pin(std::future::pending())
+    }
+}
+
+#[test]
+fn test_budget_parsing() {
+    assert_eq!(
+        "250".parse::<HookBudget>().unwrap(),
+        HookBudget { timeout: Duration::from_millis(250), fail: FailMode::Closed }
+    );
+    assert_eq!("1500:open".parse::<HookBudget>().unwrap().fail, FailMode::Open);
+    assert_eq!("1500:closed".parse::<HookBudget>().unwrap().fail, FailMode::Closed);
+    assert!(matches!("1500:maybe".parse::<HookBudget>(), Err(Socks5Error::ConfigError(_))));
+    assert!(matches!("soon".parse::<HookBudget>(), Err(Socks5Error::ConfigError(_))));
+
+    assert_eq!("authenticator".parse::<Hook>().unwrap(), Hook::Authenticator);
+    assert!(matches!("resolver".parse::<Hook>(), Err(Socks5Error::ConfigError(_))));
+    assert_eq!(Watchdog::new().budget(Hook::Authenticator).timeout, DEFAULT_TIMEOUT);
+}
+
+#[tokio::test]
+async fn test_run_abandons_overrunning_hooks() {
+    let budget = HookBudget { timeout: Duration::from_millis(20), fail: FailMode::Closed };
+    let watchdog = Watchdog::new().with_budget(Hook::Authenticator, budget);
+
+    assert_eq!(watchdog.run(Hook::Authenticator, async { 7 }).await.unwrap(), 7);
+    let result = watchdog.run(Hook::Authenticator, std::future::pending::<()>()).await;
+    match result {
+        Err(e @ Socks5Error::HookTimeoutError(..)) => {
+            assert_eq!(e.to_string(), "SOCKS5 hook timeout: authenticator did not finish within 20ms");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_hanging_authenticator_fails_per_budget() {
+    let target = TargetAddr::Domain("example.com".to_string(), 0);
+    let credentials = Credentials::new("alice", "one");
+
+    for (fail, refused) in [(FailMode::Closed, true), (FailMode::Open, false)] {
+        let budget = HookBudget { timeout: Duration::from_millis(50), fail };
+        let server = Server::new("127.0.0.1".to_string(), Some(0), None, None)
+            .with_authenticator(Arc::new(Hanging))
+            .with_watchdog(Watchdog::new().with_budget(Hook::Authenticator, budget));
+        let test_server = TestServer::start(server);
+
+        let mut stream = test_server.connect().unwrap();
+        let result = client::connect(&mut stream, &target, Some(&credentials)).await;
+        // Refused clients fail the handshake; admitted ones get as far as a reply
+        assert_eq!(matches!(result, Err(Socks5Error::HandshakeError(_))), refused, "{:?}", result);
+
+        let stats = test_server.server().stats().to_string();
+        assert_eq!(test_server.server().metrics().hook_overruns(Hook::Authenticator), 1);
+        assert!(stats.contains(" hook_overrun.authenticator=1"), "{}", stats);
+        test_server.stop().await.unwrap();
+    }
+}